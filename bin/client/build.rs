@@ -0,0 +1,56 @@
+//! Records what the binary was built from, see `build_info`, and compiles the
+//! gRPC service.
+use std::{fs, path::Path, process::Command};
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&root)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=LIGHT_CLIENT_GIT_COMMIT={}", commit);
+
+    // The circuits are locked separately, the prover is whatever they pin
+    let lock = root.join("nearx/Cargo.lock");
+    let plonky2x = fs::read_to_string(&lock)
+        .ok()
+        .and_then(|lock| locked_source(&lock, "plonky2x"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=LIGHT_CLIENT_PLONKY2X={}", plonky2x);
+
+    println!(
+        "cargo:rerun-if-changed={}",
+        root.join(".git/HEAD").display()
+    );
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    // The gRPC service, see `grpc`
+    let proto = "proto/prover.proto";
+    tonic_build::compile_protos(proto).expect("Failed to compile the protos");
+    println!("cargo:rerun-if-changed={}", proto);
+}
+
+/// The version and source of a locked package.
+fn locked_source(lock: &str, name: &str) -> Option<String> {
+    let package = lock.split("[[package]]").find(|p| {
+        p.lines()
+            .any(|l| l.trim() == format!("name = \"{}\"", name))
+    })?;
+    let field = |key: &str| {
+        package.lines().find_map(|l| {
+            l.trim()
+                .strip_prefix(&format!("{} = ", key))
+                .map(|v| v.trim_matches('"').to_string())
+        })
+    };
+    Some(match field("source") {
+        Some(source) => format!("{} {}", field("version")?, source),
+        None => field("version")?,
+    })
+}
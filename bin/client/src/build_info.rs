@@ -0,0 +1,71 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use crate::prelude::*;
+
+/// The committed manifest of circuit digests, see `nearx::repro`.
+const MANIFEST: &str = include_str!("../../../nearx/verifier-keys.json");
+
+/// What this binary was built from, so a proof that fails to verify long
+/// after it was produced can be traced back to its build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Versions of our crates, by name.
+    pub crates: BTreeMap<String, String>,
+    pub git_commit: String,
+    /// The locked version and source of plonky2x the circuits are built with.
+    pub plonky2x: String,
+    /// Verifier key digests by circuit, unset until recorded in the manifest.
+    pub circuits: BTreeMap<String, Option<String>>,
+    /// The semantic version of the circuits' outputs, see
+    /// `nearx::repro::OUTPUT_VERSION`.
+    pub output_version: String,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    output_version: String,
+    digests: BTreeMap<String, Option<String>>,
+}
+
+impl BuildInfo {
+    pub fn get() -> &'static BuildInfo {
+        static INFO: OnceLock<BuildInfo> = OnceLock::new();
+        INFO.get_or_init(|| {
+            let (circuits, output_version) = serde_json::from_str::<Manifest>(MANIFEST)
+                .map(|m| (m.digests, m.output_version))
+                .unwrap_or_else(|e| {
+                    log::warn!("Failed to read the circuit manifest: {:?}", e);
+                    Default::default()
+                });
+            BuildInfo {
+                crates: [
+                    (env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+                    ("near-light-client-protocol", protocol::VERSION),
+                    ("near-light-client-rpc", rpc::VERSION),
+                ]
+                .into_iter()
+                .map(|(name, version)| (name.to_string(), version.to_string()))
+                .collect(),
+                git_commit: env!("LIGHT_CLIENT_GIT_COMMIT").to_string(),
+                plonky2x: env!("LIGHT_CLIENT_PLONKY2X").to_string(),
+                circuits,
+                output_version,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = BuildInfo::get();
+        assert_eq!(info.crates.len(), 3);
+        assert!(info.circuits.contains_key("verify"));
+        assert!(semver::Version::parse(&info.output_version).is_ok());
+        assert!(!info.plonky2x.is_empty());
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use near_crypto::{PublicKey, SecretKey, Signature};
+use tokio::sync::Mutex;
+
+use super::store::Relay;
+use crate::{config::AuditConfig, prelude::*};
+
+/// An externally visible action taken by the operator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// A batch of verify proofs was delivered to its requesters.
+    ProofDelivered { root: CryptoHash, slots: usize },
+    /// A sync was relayed to the destination chain.
+    ProofRelayed {
+        root: CryptoHash,
+        head: CryptoHash,
+        relay: Relay,
+    },
+    /// A shadow build's output was compared against the active build.
+    ShadowCompared { epoch_id: CryptoHash, matches: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    /// The hash of the previous entry, the default hash for the first.
+    pub prev: CryptoHash,
+    pub action: Action,
+    pub hash: CryptoHash,
+}
+
+impl Entry {
+    fn digest(seq: u64, timestamp_ms: u64, prev: &CryptoHash, action: &Action) -> CryptoHash {
+        let bytes = serde_json::to_vec(&(seq, timestamp_ms, prev, action))
+            .expect("actions always serialize");
+        CryptoHash::hash_bytes(&bytes)
+    }
+}
+
+/// A signature over the hash of the entry at `seq`, committing to every entry
+/// before it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub seq: u64,
+    pub hash: CryptoHash,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl Checkpoint {
+    pub fn verify(&self) -> bool {
+        self.signature.verify(&self.hash.0, &self.public_key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Entry(Entry),
+    Checkpoint(Checkpoint),
+}
+
+/// The latest entry, and the latest checkpoint if any.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditHead {
+    pub seq: Option<u64>,
+    pub hash: CryptoHash,
+    pub checkpoint: Option<Checkpoint>,
+}
+
+struct State {
+    file: File,
+    head: AuditHead,
+    since_checkpoint: u64,
+}
+
+/// An append only log of every externally visible action, where each entry
+/// commits to the one before it. Periodic checkpoints are signed with the
+/// operator's key so the log can be shown to partners.
+pub struct AuditLog {
+    state: Mutex<State>,
+    signer: Option<SecretKey>,
+    checkpoint_interval: u64,
+}
+
+impl AuditLog {
+    /// Open the log, verifying what's there and continuing the chain from it.
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let signer = config
+            .signer_key
+            .as_deref()
+            .map(SecretKey::from_str)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid audit signer key: {}", e))?;
+        if signer.is_none() {
+            log::warn!("No audit signer key configured, checkpoints will not be written");
+        }
+
+        let head = if config.path.exists() {
+            verify(&config.path)?
+        } else {
+            Default::default()
+        };
+        let since_checkpoint = match (head.seq, &head.checkpoint) {
+            (Some(seq), Some(checkpoint)) => seq - checkpoint.seq,
+            (Some(seq), None) => seq + 1,
+            (None, _) => 0,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+
+        Ok(Self {
+            state: Mutex::new(State {
+                file,
+                head,
+                since_checkpoint,
+            }),
+            signer,
+            checkpoint_interval: config.checkpoint_interval,
+        })
+    }
+
+    pub async fn record(&self, action: Action) -> Result<Entry> {
+        let mut state = self.state.lock().await;
+
+        let seq = state.head.seq.map_or(0, |s| s + 1);
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let prev = state.head.hash;
+        let hash = Entry::digest(seq, timestamp_ms, &prev, &action);
+        let entry = Entry {
+            seq,
+            timestamp_ms,
+            prev,
+            action,
+            hash,
+        };
+        append(&mut state.file, &Record::Entry(entry.clone()))?;
+        state.head.seq = Some(seq);
+        state.head.hash = hash;
+        state.since_checkpoint += 1;
+
+        if let Some(signer) = &self.signer {
+            if state.since_checkpoint >= self.checkpoint_interval {
+                let checkpoint = Checkpoint {
+                    seq,
+                    hash,
+                    public_key: signer.public_key(),
+                    signature: signer.sign(&hash.0),
+                };
+                append(&mut state.file, &Record::Checkpoint(checkpoint.clone()))?;
+                state.head.checkpoint = Some(checkpoint);
+                state.since_checkpoint = 0;
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Record an action, logging rather than failing if the log can't be
+    /// written.
+    pub async fn try_record(&self, action: Action) {
+        if let Err(e) = self.record(action).await {
+            log::error!("Failed to write audit log: {:?}", e);
+        }
+    }
+
+    pub async fn head(&self) -> AuditHead {
+        self.state.lock().await.head.clone()
+    }
+}
+
+fn append(file: &mut File, record: &Record) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Check the chain of entries and every checkpoint's signature, returning the
+/// head.
+pub fn verify(path: impl AsRef<Path>) -> Result<AuditHead> {
+    let mut head = AuditHead::default();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let record: Record = serde_json::from_str(&line?)
+            .map_err(|e| anyhow!("Line {} is not a record: {}", i + 1, e))?;
+        match record {
+            Record::Entry(entry) => {
+                let expected_seq = head.seq.map_or(0, |s| s + 1);
+                if entry.seq != expected_seq || entry.prev != head.hash {
+                    anyhow::bail!("Entry {} does not follow entry {:?}", entry.seq, head.seq);
+                }
+                let hash = Entry::digest(entry.seq, entry.timestamp_ms, &entry.prev, &entry.action);
+                if entry.hash != hash {
+                    anyhow::bail!("Entry {} has been modified", entry.seq);
+                }
+                head.seq = Some(entry.seq);
+                head.hash = entry.hash;
+            }
+            Record::Checkpoint(checkpoint) => {
+                if Some(checkpoint.seq) != head.seq || checkpoint.hash != head.hash {
+                    anyhow::bail!(
+                        "Checkpoint {} is not for the entry before it",
+                        checkpoint.seq
+                    );
+                }
+                if !checkpoint.verify() {
+                    anyhow::bail!("Checkpoint {} has an invalid signature", checkpoint.seq);
+                }
+                head.checkpoint = Some(checkpoint);
+            }
+        }
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use near_crypto::KeyType;
+
+    use super::*;
+
+    fn config(interval: u64) -> AuditConfig {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("audit-{}.log", rand::random::<u64>()));
+        AuditConfig {
+            path,
+            checkpoint_interval: interval,
+            signer_key: Some(SecretKey::from_seed(KeyType::ED25519, "audit").to_string()),
+        }
+    }
+
+    fn action(i: u8) -> Action {
+        Action::ShadowCompared {
+            epoch_id: CryptoHash::hash_bytes(&[i]),
+            matches: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_and_checkpoints() {
+        let config = config(2);
+        let log = AuditLog::open(&config).unwrap();
+        for i in 0..5 {
+            log.record(action(i)).await.unwrap();
+        }
+
+        let head = verify(&config.path).unwrap();
+        assert_eq!(head, log.head().await);
+        assert_eq!(head.seq, Some(4));
+        assert_eq!(head.checkpoint.unwrap().seq, 3);
+
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resumes_chain() {
+        let config = config(100);
+        let first = AuditLog::open(&config).unwrap();
+        first.record(action(0)).await.unwrap();
+        drop(first);
+
+        let second = AuditLog::open(&config).unwrap();
+        let entry = second.record(action(1)).await.unwrap();
+        assert_eq!(entry.seq, 1);
+        assert_eq!(verify(&config.path).unwrap().hash, entry.hash);
+
+        std::fs::remove_file(&config.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tampering_is_detected() {
+        let config = config(100);
+        let log = AuditLog::open(&config).unwrap();
+        for i in 0..3 {
+            log.record(action(i)).await.unwrap();
+        }
+
+        let original = std::fs::read_to_string(&config.path).unwrap();
+        let tampered = original.replacen("true", "false", 1);
+        std::fs::write(&config.path, tampered).unwrap();
+        assert!(verify(&config.path).is_err());
+
+        // Dropping an entry breaks the chain
+        let dropped = original.lines().skip(1).join("\n");
+        std::fs::write(&config.path, dropped).unwrap();
+        assert!(verify(&config.path).is_err());
+
+        std::fs::remove_file(&config.path).unwrap();
+    }
+}
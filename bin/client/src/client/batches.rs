@@ -0,0 +1,416 @@
+//! Following a batch of requests as one, rather than waiting on each.
+//!
+//! A batch's requests are queued like any others, so they can be proven
+//! across several batch proofs. Subscribers get an event as each request is
+//! delivered and a final one with the proofs they were in, pointing each
+//! request at its proof and its slot in it. Those that would rather poll can
+//! ask for a batch's status instead.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{stream::FuturesUnordered, Stream, StreamExt};
+use near_primitives::types::TransactionOrReceiptId;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    oneshot, RwLock,
+};
+
+use super::{
+    failure::{Failure, FailureReason},
+    queue::{now_ns, AnchoredProof, Delivery},
+    tenant::Tenant,
+};
+use crate::prelude::*;
+
+/// Subscribers further behind than this start missing events.
+const CAPACITY: usize = 128;
+
+/// How long a completed batch can still be subscribed to.
+const RETENTION: Duration = Duration::from_secs(600);
+
+/// A queued batch, subscribe to it by its id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnqueuedBatch {
+    pub batch_id: CryptoHash,
+    pub len: usize,
+}
+
+/// How one request of a batch ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct Item {
+    /// Where the request was in the batch.
+    pub index: usize,
+    pub id: TransactionOrReceiptId,
+    /// Which of the proofs it is in, unset if it failed.
+    pub proof: Option<usize>,
+    /// Where it is in that proof.
+    pub slot: usize,
+    pub cost: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Failure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BatchEvent {
+    /// A request of the batch was delivered.
+    Item {
+        batch_id: CryptoHash,
+        index: usize,
+        id: TransactionOrReceiptId,
+        delivery: Delivery,
+    },
+    /// Every request of the batch was delivered, each proof only once.
+    Completed {
+        batch_id: CryptoHash,
+        proofs: Vec<AnchoredProof>,
+        items: Vec<Item>,
+    },
+}
+
+impl BatchEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Item { .. } => "item",
+            Self::Completed { .. } => "completed",
+        }
+    }
+
+    /// Gather the deliveries of a batch, in the order they were requested.
+    fn completed(
+        batch_id: CryptoHash,
+        deliveries: Vec<(TransactionOrReceiptId, Delivery)>,
+    ) -> Self {
+        let mut proofs = vec![];
+        let mut indices = HashMap::new();
+        let items = deliveries
+            .into_iter()
+            .enumerate()
+            .map(|(index, (id, delivery))| {
+                let (proof, error) = match delivery.result {
+                    Ok(proof) => {
+                        let i = *indices
+                            .entry(CryptoHash::hash_borsh(&proof.proof))
+                            .or_insert_with(|| {
+                                proofs.push(proof);
+                                proofs.len() - 1
+                            });
+                        (Some(i), None)
+                    }
+                    Err(e) => (None, Some(e)),
+                };
+                Item {
+                    index,
+                    id,
+                    proof,
+                    slot: delivery.slot,
+                    cost: delivery.cost,
+                    error,
+                }
+            })
+            .collect();
+        Self::Completed {
+            batch_id,
+            proofs,
+            items,
+        }
+    }
+}
+
+/// Where a batch is, for polling it rather than following it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BatchStatus {
+    Pending {
+        delivered: usize,
+        len: usize,
+    },
+    Completed {
+        proofs: Vec<AnchoredProof>,
+        items: Vec<Item>,
+    },
+}
+
+/// The events of a batch so far and those to come.
+pub struct Subscription {
+    history: Vec<BatchEvent>,
+    rx: broadcast::Receiver<BatchEvent>,
+}
+
+impl Subscription {
+    /// Every event of the batch, ending with `BatchEvent::Completed`.
+    pub fn into_stream(self) -> impl Stream<Item = BatchEvent> {
+        let state = (VecDeque::from(self.history), self.rx, false);
+        futures::stream::unfold(state, |(mut history, mut rx, done)| async move {
+            if done {
+                return None;
+            }
+            let event = match history.pop_front() {
+                Some(event) => event,
+                None => loop {
+                    match rx.recv().await {
+                        Ok(event) => break event,
+                        Err(RecvError::Lagged(n)) => log::warn!("Batch stream lagged by {}", n),
+                        Err(RecvError::Closed) => return None,
+                    }
+                },
+            };
+            let done = matches!(event, BatchEvent::Completed { .. });
+            Some((event, (history, rx, done)))
+        })
+    }
+}
+
+struct Tracked {
+    tenant: Tenant,
+    len: usize,
+    history: Vec<BatchEvent>,
+    tx: broadcast::Sender<BatchEvent>,
+}
+
+/// The batches being followed, and those completed in the last `RETENTION`.
+#[derive(Default)]
+pub struct Batches(RwLock<HashMap<CryptoHash, Tracked>>);
+
+impl Batches {
+    /// Follow the deliveries of a tenant's batch of requests until they are
+    /// all in.
+    pub async fn track(
+        self: &Arc<Self>,
+        tenant: Tenant,
+        requests: Vec<(TransactionOrReceiptId, oneshot::Receiver<Delivery>)>,
+    ) -> EnqueuedBatch {
+        let ids = requests.iter().map(|(id, _)| id).collect_vec();
+        let batch_id = CryptoHash::hash_bytes(
+            &serde_json::to_vec(&(&tenant, ids, now_ns())).unwrap_or_default(),
+        );
+        let len = requests.len();
+        self.0.write().await.insert(
+            batch_id,
+            Tracked {
+                tenant,
+                len,
+                history: vec![],
+                tx: broadcast::channel(CAPACITY).0,
+            },
+        );
+
+        let batches = self.clone();
+        tokio::spawn(async move {
+            batches.follow(batch_id, requests).await;
+            tokio::time::sleep(RETENTION).await;
+            batches.0.write().await.remove(&batch_id);
+        });
+        EnqueuedBatch { batch_id, len }
+    }
+
+    async fn follow(
+        &self,
+        batch_id: CryptoHash,
+        requests: Vec<(TransactionOrReceiptId, oneshot::Receiver<Delivery>)>,
+    ) {
+        let (ids, receivers): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+        let mut pending = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(index, rx)| async move { (index, rx.await) })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut deliveries = vec![None; ids.len()];
+        while let Some((index, delivery)) = pending.next().await {
+            // The queue only drops a request when the client shuts down
+            let delivery = delivery.unwrap_or_else(|_| Delivery {
+                result: Err(Failure::new(
+                    FailureReason::Internal,
+                    "The request was dropped",
+                )),
+                slot: 0,
+                cost: 0,
+            });
+            self.publish(
+                &batch_id,
+                BatchEvent::Item {
+                    batch_id,
+                    index,
+                    id: ids[index].clone(),
+                    delivery: delivery.clone(),
+                },
+            )
+            .await;
+            deliveries[index] = Some(delivery);
+        }
+
+        let deliveries = ids.into_iter().zip(deliveries.into_iter().flatten());
+        self.publish(
+            &batch_id,
+            BatchEvent::completed(batch_id, deliveries.collect()),
+        )
+        .await;
+    }
+
+    async fn publish(&self, batch_id: &CryptoHash, event: BatchEvent) {
+        if let Some(tracked) = self.0.write().await.get_mut(batch_id) {
+            tracked.history.push(event.clone());
+            // It's fine if there are no subscribers
+            let _ = tracked.tx.send(event);
+        }
+    }
+
+    /// Subscribe to a batch, only the tenant that queued it can.
+    pub async fn subscribe(&self, batch_id: &CryptoHash, tenant: &str) -> Option<Subscription> {
+        let batches = self.0.read().await;
+        let tracked = batches.get(batch_id).filter(|t| t.tenant == tenant)?;
+        Some(Subscription {
+            history: tracked.history.clone(),
+            rx: tracked.tx.subscribe(),
+        })
+    }
+
+    /// Where a batch is, only the tenant that queued it can ask.
+    pub async fn status(&self, batch_id: &CryptoHash, tenant: &str) -> Option<BatchStatus> {
+        let batches = self.0.read().await;
+        let tracked = batches.get(batch_id).filter(|t| t.tenant == tenant)?;
+        Some(match tracked.history.last() {
+            Some(BatchEvent::Completed { proofs, items, .. }) => BatchStatus::Completed {
+                proofs: proofs.clone(),
+                items: items.clone(),
+            },
+            _ => BatchStatus::Pending {
+                delivered: tracked.history.len(),
+                len: tracked.len,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_info::BuildInfo, client::tenant::DEFAULT_TENANT};
+
+    fn ids(n: u8) -> Vec<TransactionOrReceiptId> {
+        (0..n)
+            .map(|i| TransactionOrReceiptId::Receipt {
+                receipt_id: CryptoHash::hash_bytes(&[i]),
+                receiver_id: "test.near".parse().unwrap(),
+            })
+            .collect()
+    }
+
+    fn proof(root: u8) -> AnchoredProof {
+        AnchoredProof {
+            proof: ExperimentalProof::new(CryptoHash::hash_bytes(&[root]), vec![]),
+            anchor: None,
+            build: BuildInfo::get(),
+            attestations: vec![],
+            packed_results: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_events() {
+        let batches = Arc::new(Batches::default());
+        let ids = ids(3);
+        let (txs, rxs): (Vec<_>, Vec<_>) = ids.iter().map(|_| oneshot::channel()).unzip();
+        let batch = batches
+            .track(
+                DEFAULT_TENANT.to_string(),
+                ids.iter().cloned().zip(rxs).collect(),
+            )
+            .await;
+        assert_eq!(batch.len, 3);
+        assert!(batches.subscribe(&batch.batch_id, "other").await.is_none());
+        assert!(matches!(
+            batches.status(&batch.batch_id, DEFAULT_TENANT).await,
+            Some(BatchStatus::Pending {
+                delivered: 0,
+                len: 3
+            })
+        ));
+
+        let mut txs = txs.into_iter().map(Some).collect_vec();
+        let mut deliver = |i: usize, result, slot| {
+            let tx = txs[i].take().unwrap();
+            tx.send(Delivery {
+                result,
+                slot,
+                cost: 1,
+            })
+            .unwrap();
+        };
+        // Out of order and across two proofs
+        deliver(2, Ok(proof(1)), 0);
+        tokio::task::yield_now().await;
+        // Subscribing late still sees what was delivered before
+        let subscription = batches
+            .subscribe(&batch.batch_id, DEFAULT_TENANT)
+            .await
+            .unwrap();
+        deliver(0, Ok(proof(0)), 1);
+        let failure = Failure::new(FailureReason::Timeout, "boom");
+        deliver(1, Err(failure.clone()), 0);
+
+        let events = subscription.into_stream().collect::<Vec<_>>().await;
+        let indices = events
+            .iter()
+            .filter_map(|e| match e {
+                BatchEvent::Item { index, .. } => Some(*index),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(indices, vec![2, 0, 1]);
+        assert!(matches!(
+            batches.status(&batch.batch_id, DEFAULT_TENANT).await,
+            Some(BatchStatus::Completed { .. })
+        ));
+
+        let Some(BatchEvent::Completed { proofs, items, .. }) = events.last() else {
+            panic!("The stream should end with the completed event");
+        };
+        assert_eq!(proofs.len(), 2);
+        assert_eq!(
+            items
+                .iter()
+                .map(|i| (i.index, i.proof, i.slot))
+                .collect_vec(),
+            vec![(0, Some(1), 1), (1, None, 0), (2, Some(0), 0)]
+        );
+        assert_eq!(items[0].id, ids[0]);
+        assert_eq!(items[1].error, Some(failure));
+        assert_eq!(
+            proofs[items[2].proof.unwrap()].proof.head_block_root,
+            CryptoHash::hash_bytes(&[1])
+        );
+    }
+
+    #[test]
+    fn test_completed_shares_proofs() {
+        let batch_id = CryptoHash::default();
+        let ids = ids(2);
+        let deliveries = ids
+            .into_iter()
+            .enumerate()
+            .map(|(slot, id)| {
+                let delivery = Delivery {
+                    result: Ok(proof(0)),
+                    slot,
+                    cost: 1,
+                };
+                (id, delivery)
+            })
+            .collect();
+        let BatchEvent::Completed { proofs, items, .. } =
+            BatchEvent::completed(batch_id, deliveries)
+        else {
+            unreachable!()
+        };
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(
+            items.iter().map(|i| (i.proof, i.slot)).collect_vec(),
+            vec![(Some(0), 0), (Some(0), 1)]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use near_primitives::{
+    merkle::MerklePath,
+    types::{BlockHeight, BlockId, BlockReference},
+};
+use protocol::{
+    block_merkle::{self, NodeKey},
+    compute_root_from_path,
+};
+use rpc::NearRpcClient;
+
+use super::store::{self, Store};
+use crate::{config::BlockTreeConfig, prelude::*};
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct TreeBounds {
+    /// The first leaf we hold, blocks before it can't be proven.
+    pub start: u64,
+    /// The number of leaves.
+    pub size: u64,
+    /// The height of the last block appended.
+    pub height: BlockHeight,
+}
+
+/// NEAR's block merkle tree, rebuilt from the blocks we follow so block proofs
+/// can be generated without the RPC. Rebuilding from genesis isn't practical,
+/// so the tree is seeded from the first block proof we fetch, see
+/// [`block_merkle`].
+pub struct BlockTree {
+    config: BlockTreeConfig,
+    client: NearRpcClient,
+    store: Arc<Store<store::sled::Store>>,
+}
+
+impl BlockTree {
+    pub fn new(
+        config: BlockTreeConfig,
+        client: NearRpcClient,
+        store: Arc<Store<store::sled::Store>>,
+    ) -> Self {
+        Self {
+            config,
+            client,
+            store,
+        }
+    }
+
+    pub async fn start(self: Arc<Self>) {
+        let interval = Duration::from_millis(self.config.poll_interval_ms);
+        loop {
+            if let Err(e) = self.follow().await {
+                log::error!("Error following blocks: {:?}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Seed the tree from a block proof fetched from the RPC, unless it has
+    /// been seeded already.
+    pub async fn seed(&self, proof: &BasicProof) -> Result<()> {
+        if self.store.block_tree_bounds().await?.is_some() {
+            return Ok(());
+        }
+        let block = proof.block_header_lite.hash();
+        let view = self
+            .client
+            .fetch_block(BlockReference::BlockId(BlockId::Hash(block)))
+            .await?;
+        let leaf = view
+            .header
+            .block_ordinal
+            .ok_or_else(|| anyhow!("Block {} has no ordinal", block))?
+            - 1;
+
+        let mut nodes = block_merkle::frontier_from_path(leaf, &proof.block_proof)?;
+        let known: HashMap<NodeKey, CryptoHash> = nodes.iter().map(|n| (n.key(), n.hash)).collect();
+        if block_merkle::root(&known, leaf)? != proof.block_header_lite.inner_lite.block_merkle_root
+        {
+            anyhow::bail!("Block proof for {} does not give its merkle root", block);
+        }
+        nodes.extend(block_merkle::append(&known, leaf, block)?);
+
+        log::info!("Seeded block tree at {}", view.header.height);
+        self.store
+            .write_block_tree(
+                &nodes,
+                TreeBounds {
+                    start: leaf,
+                    size: leaf + 1,
+                    height: view.header.height,
+                },
+            )
+            .await
+    }
+
+    /// Append every block since the last, up to the latest final block.
+    async fn follow(&self) -> Result<()> {
+        let Some(mut bounds) = self.store.block_tree_bounds().await? else {
+            return Ok(());
+        };
+        let final_block = self.client.fetch_final_block().await?;
+
+        for height in bounds.height + 1..=final_block.header.height {
+            let block = if height == final_block.header.height {
+                final_block.clone()
+            } else {
+                match self
+                    .client
+                    .fetch_block(BlockReference::BlockId(BlockId::Height(height)))
+                    .await
+                {
+                    Ok(block) => block,
+                    // Heights can be skipped, a missed block is caught by the
+                    // ordinal check
+                    Err(_) => continue,
+                }
+            };
+            let header = &block.header;
+            if header.block_ordinal != Some(bounds.size + 1) {
+                anyhow::bail!(
+                    "Expected ordinal {} at {}, got {:?}",
+                    bounds.size + 1,
+                    height,
+                    header.block_ordinal
+                );
+            }
+
+            let nodes = {
+                let store = self.store.0.read().await;
+                if block_merkle::root(&*store, bounds.size)? != header.block_merkle_root {
+                    anyhow::bail!("Block tree diverged from the chain at {}", height);
+                }
+                block_merkle::append(&*store, bounds.size, header.hash)?
+            };
+            bounds = TreeBounds {
+                size: bounds.size + 1,
+                height,
+                ..bounds
+            };
+            self.store.write_block_tree(&nodes, bounds.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether blocks can be proven against the head.
+    pub async fn contains(&self, head: &CryptoHash) -> bool {
+        matches!(self.store.block_tree_leaf(head).await, Ok(Some(_)))
+    }
+
+    /// The proof of a block against the `block_merkle_root` of a head, if both
+    /// are in the tree.
+    pub async fn prove(
+        &self,
+        block: &CryptoHash,
+        head: &CryptoHash,
+        root: &CryptoHash,
+    ) -> Result<Option<MerklePath>> {
+        let store = self.store.0.read().await;
+        // The root of a head is over every block before it
+        let (Some(leaf), Some(size)) =
+            (store.block_tree_leaf(block)?, store.block_tree_leaf(head)?)
+        else {
+            return Ok(None);
+        };
+        if leaf >= size {
+            return Ok(None);
+        }
+        let path = block_merkle::path(&*store, leaf, size)?;
+        if compute_root_from_path(path.iter(), *block) != *root {
+            anyhow::bail!("Block proof for {} does not give root {}", block, root);
+        }
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use tokio::sync::RwLock;
+
+use crate::{config::CanaryConfig, prelude::*};
+
+/// The outcome of comparing a shadow build's output for an epoch against the
+/// active build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub epoch_id: CryptoHash,
+    pub active: CryptoHash,
+    pub shadow: CryptoHash,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.active == self.shadow
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanaryStatus {
+    pub function_id: CryptoHash,
+    pub clean_epochs: u64,
+    pub required_epochs: u64,
+    pub mismatches: Vec<Comparison>,
+    /// Whether proofs from the shadow build can be relayed.
+    pub eligible: bool,
+}
+
+/// Runs a new circuit build in shadow, comparing its sync outputs epoch by
+/// epoch against what the active build produced. Any mismatch disqualifies the
+/// build.
+pub struct Canary {
+    config: CanaryConfig,
+    comparisons: RwLock<BTreeMap<CryptoHash, Comparison>>,
+}
+
+impl Canary {
+    pub fn new(config: CanaryConfig) -> Self {
+        Self {
+            config,
+            comparisons: Default::default(),
+        }
+    }
+
+    pub fn function_id(&self) -> &CryptoHash {
+        &self.config.function_id
+    }
+
+    /// Record a comparison, a later output for the same epoch replaces the
+    /// earlier one.
+    pub async fn record(&self, comparison: Comparison) {
+        if !comparison.matches() {
+            log::warn!("Canary mismatch: {:?}", comparison);
+        }
+        self.comparisons
+            .write()
+            .await
+            .insert(comparison.epoch_id, comparison);
+    }
+
+    pub async fn status(&self) -> CanaryStatus {
+        let comparisons = self.comparisons.read().await;
+        let (clean, mismatches): (Vec<_>, Vec<_>) =
+            comparisons.values().cloned().partition(Comparison::matches);
+        let clean_epochs = clean.len() as u64;
+        CanaryStatus {
+            function_id: self.config.function_id,
+            clean_epochs,
+            required_epochs: self.config.shadow_epochs,
+            eligible: mismatches.is_empty() && clean_epochs >= self.config.shadow_epochs,
+            mismatches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comparison(epoch: u8, shadow: u8) -> Comparison {
+        Comparison {
+            epoch_id: CryptoHash::hash_bytes(&[epoch]),
+            active: CryptoHash::hash_bytes(&[epoch]),
+            shadow: CryptoHash::hash_bytes(&[shadow]),
+        }
+    }
+
+    fn canary() -> Canary {
+        Canary::new(CanaryConfig {
+            function_id: CryptoHash::default(),
+            shadow_epochs: 2,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_eligible_after_clean_period() {
+        let canary = canary();
+        canary.record(comparison(0, 0)).await;
+        assert!(!canary.status().await.eligible);
+
+        // Replaying an epoch doesn't count twice
+        canary.record(comparison(0, 0)).await;
+        assert!(!canary.status().await.eligible);
+
+        canary.record(comparison(1, 1)).await;
+        let status = canary.status().await;
+        assert_eq!(status.clean_epochs, 2);
+        assert!(status.eligible);
+    }
+
+    #[tokio::test]
+    async fn test_mismatch_disqualifies() {
+        let canary = canary();
+        for epoch in 0..3 {
+            canary.record(comparison(epoch, epoch)).await;
+        }
+        canary.record(comparison(3, 42)).await;
+
+        let status = canary.status().await;
+        assert_eq!(status.clean_epochs, 3);
+        assert_eq!(status.mismatches, vec![comparison(3, 42)]);
+        assert!(!status.eligible);
+    }
+}
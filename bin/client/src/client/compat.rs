@@ -0,0 +1,454 @@
+//! The handshake with the deployed verifier contract at startup.
+//!
+//! The contract only accepts proofs from the circuits registered as its
+//! function ids, and only decodes the outputs it was written for. An operator
+//! redeployed with other circuits would otherwise keep relaying syncs that
+//! revert, so we read what the contract expects first and refuse to run, or
+//! only serve proofs to verify off chain, on a mismatch.
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Context;
+use semver::Version;
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    build_info::BuildInfo,
+    config::{Config, HookConfig, OnMismatch, VerifierConfig},
+    prelude::*,
+};
+
+/// The contract's getters for its function ids, by circuit in the manifest.
+pub const FUNCTION_IDS: [(&str, &str); 2] = [
+    ("sync", "syncFunctionId()"),
+    ("verify", "verifyFunctionId()"),
+];
+
+/// Returns the major, minor and patch of the output version the contract
+/// decodes.
+pub const OUTPUT_VERSION: &str = "outputVersion()";
+
+/// Whether a function id is registered, one it accepts proofs from.
+pub const REGISTERED: &str = "registeredFunctionIds(bytes32)";
+
+/// What the contract expects of the proofs it accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expected {
+    pub function_ids: BTreeMap<String, CryptoHash>,
+    pub output_version: Version,
+}
+
+impl Expected {
+    pub async fn read(contract: &dyn VerifierContract) -> Result<Self> {
+        let mut function_ids = BTreeMap::new();
+        for (circuit, getter) in FUNCTION_IDS {
+            let output = contract.call(getter, &[]).await?;
+            function_ids.insert(circuit.to_string(), CryptoHash(word(&output, 0)?));
+        }
+        let output = contract.call(OUTPUT_VERSION, &[]).await?;
+        let output_version = Version::new(uint(&output, 0)?, uint(&output, 1)?, uint(&output, 2)?);
+        Ok(Self {
+            function_ids,
+            output_version,
+        })
+    }
+}
+
+/// Whether the contract accepts proofs from `function_id`, a revoked id is
+/// no longer registered.
+pub async fn is_registered(
+    contract: &dyn VerifierContract,
+    function_id: &CryptoHash,
+) -> Result<bool> {
+    let output = contract.call(REGISTERED, &[function_id.0]).await?;
+    Ok(uint(&output, 0)? != 0)
+}
+
+/// Calls the contract's getters, by their signature and with any static
+/// arguments as words.
+#[async_trait]
+pub trait VerifierContract: Send + Sync {
+    async fn call(&self, signature: &str, args: &[[u8; 32]]) -> Result<Vec<u8>>;
+}
+
+/// Reads the contract with `eth_call` at the latest block.
+pub struct EthCall {
+    url: String,
+    address: String,
+    client: reqwest::Client,
+}
+
+impl EthCall {
+    pub fn new(config: &VerifierConfig) -> Result<Self> {
+        Ok(Self {
+            url: config.rpc_url.clone(),
+            address: config.address.clone(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(config.timeout_ms))
+                .build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl VerifierContract for EthCall {
+    async fn call(&self, signature: &str, args: &[[u8; 32]]) -> Result<Vec<u8>> {
+        let mut data = selector(signature).to_vec();
+        data.extend(args.concat());
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [
+                { "to": self.address, "data": format!("0x{}", hex::encode(data)) },
+                "latest"
+            ],
+        });
+        let response: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("Calling {} failed: {}", signature, error));
+        }
+        let result = response
+            .get("result")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| anyhow!("Calling {} returned no result", signature))?;
+        Ok(hex::decode(result.trim_start_matches("0x"))?)
+    }
+}
+
+/// The first four bytes of the keccak of the signature, what a call starts
+/// with.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = Keccak256::digest(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// The `i`th 32 byte word of an ABI encoded output.
+fn word(output: &[u8], i: usize) -> Result<[u8; 32]> {
+    output
+        .get(i * 32..(i + 1) * 32)
+        .and_then(|w| w.try_into().ok())
+        .ok_or_else(|| anyhow!("Expected {} words, got {} bytes", i + 1, output.len()))
+}
+
+fn uint(output: &[u8], i: usize) -> Result<u64> {
+    let word = word(output, i)?;
+    let (high, low) = word.split_at(24);
+    if high.iter().any(|b| *b != 0) {
+        return Err(anyhow!("Word {} overflows a u64", i));
+    }
+    Ok(u64::from_be_bytes(low.try_into()?))
+}
+
+/// Outputs of version `ours` decode as `theirs` if they've only been
+/// appended to since, the same major version and at least the minor.
+pub fn compatible(ours: &Version, theirs: &Version) -> bool {
+    ours.major == theirs.major && ours.minor >= theirs.minor
+}
+
+/// The circuits the contract registers that the manifest has no digest for
+/// yet, these can't be checked.
+pub fn unrecorded<'a>(build: &BuildInfo, expected: &'a Expected) -> Vec<&'a str> {
+    expected
+        .function_ids
+        .keys()
+        .filter(|circuit| !matches!(build.circuits.get(*circuit), Some(Some(_))))
+        .map(String::as_str)
+        .collect()
+}
+
+/// Why the contract would reject what this build proves, empty if it
+/// wouldn't.
+pub fn mismatches(build: &BuildInfo, expected: &Expected) -> Vec<String> {
+    let mut mismatches = vec![];
+    for (circuit, function_id) in &expected.function_ids {
+        let theirs = format!("0x{}", hex::encode(function_id.0));
+        match build.circuits.get(circuit).and_then(Option::as_deref) {
+            Some(ours) if !ours.eq_ignore_ascii_case(&theirs) => mismatches.push(format!(
+                "{} is {} here, the contract expects {}",
+                circuit, ours, theirs
+            )),
+            _ => {}
+        }
+    }
+    match Version::parse(&build.output_version) {
+        Ok(ours) if compatible(&ours, &expected.output_version) => {}
+        Ok(ours) => mismatches.push(format!(
+            "output version {} doesn't decode as {}",
+            ours, expected.output_version
+        )),
+        Err(e) => mismatches.push(format!(
+            "output version {:?} is invalid: {}",
+            build.output_version, e
+        )),
+    }
+    mismatches
+}
+
+/// How the operator runs after the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Full,
+    /// Nothing is relayed, proofs are only served to verify off chain.
+    VerifyOnly,
+}
+
+impl Mode {
+    pub fn apply(self, config: &mut Config) {
+        if self == Mode::VerifyOnly {
+            config
+                .hooks
+                .batch
+                .retain(|hook| !matches!(hook, HookConfig::Relay(..)));
+        }
+    }
+}
+
+/// Check the contract would accept what this build proves.
+pub async fn handshake(config: &VerifierConfig, build: &BuildInfo) -> Result<Mode> {
+    let contract = EthCall::new(config)?;
+    decide(&contract, build, config.on_mismatch).await
+}
+
+async fn decide(
+    contract: &dyn VerifierContract,
+    build: &BuildInfo,
+    on_mismatch: OnMismatch,
+) -> Result<Mode> {
+    // Not knowing what the contract expects is as bad as a mismatch
+    let mismatches = match Expected::read(contract)
+        .await
+        .context("Failed to read the verifier contract")
+    {
+        Ok(expected) => {
+            log::info!(
+                "The verifier contract expects {:?} at output version {}",
+                expected.function_ids,
+                expected.output_version
+            );
+            let unrecorded = unrecorded(build, &expected);
+            if !unrecorded.is_empty() {
+                log::warn!(
+                    "No digest is recorded for {:?}, run `make repro` to check them against the \
+                     contract",
+                    unrecorded
+                );
+            }
+            mismatches(build, &expected)
+        }
+        Err(e) => vec![format!("{:#}", e)],
+    };
+    if mismatches.is_empty() {
+        return Ok(Mode::Full);
+    }
+
+    let mismatches = mismatches.join(", ");
+    match on_mismatch {
+        OnMismatch::Refuse => Err(anyhow!(
+            "The verifier contract would reject our proofs: {}",
+            mismatches
+        )),
+        OnMismatch::VerifyOnly => {
+            log::warn!(
+                "The verifier contract would reject our proofs, only serving them to verify: {}",
+                mismatches
+            );
+            Ok(Mode::VerifyOnly)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Outputs by signature, followed by the hex of any arguments.
+    struct Fixed(HashMap<String, Vec<u8>>);
+
+    #[async_trait]
+    impl VerifierContract for Fixed {
+        async fn call(&self, signature: &str, args: &[[u8; 32]]) -> Result<Vec<u8>> {
+            self.0
+                .get(&format!("{}{}", signature, hex::encode(args.concat())))
+                .cloned()
+                .ok_or_else(|| anyhow!("execution reverted"))
+        }
+    }
+
+    fn uint_word(n: u64) -> Vec<u8> {
+        let mut word = vec![0; 24];
+        word.extend(n.to_be_bytes());
+        word
+    }
+
+    fn contract(sync: CryptoHash, version: [u64; 3]) -> Fixed {
+        Fixed(HashMap::from([
+            ("syncFunctionId()".to_string(), sync.0.to_vec()),
+            (
+                "verifyFunctionId()".to_string(),
+                CryptoHash::hash_bytes(b"verify").0.to_vec(),
+            ),
+            (
+                OUTPUT_VERSION.to_string(),
+                version.into_iter().flat_map(uint_word).collect(),
+            ),
+            (
+                format!("{}{}", REGISTERED, hex::encode(sync.0)),
+                uint_word(1),
+            ),
+        ]))
+    }
+
+    fn build(sync: CryptoHash, output_version: &str) -> BuildInfo {
+        let digest = |hash: CryptoHash| Some(format!("0x{}", hex::encode(hash.0)));
+        BuildInfo {
+            circuits: BTreeMap::from([
+                ("sync".to_string(), digest(sync)),
+                (
+                    "verify".to_string(),
+                    digest(CryptoHash::hash_bytes(b"verify")),
+                ),
+            ]),
+            output_version: output_version.to_string(),
+            ..BuildInfo::get().clone()
+        }
+    }
+
+    #[test]
+    fn test_selector() {
+        assert_eq!(
+            hex::encode(selector("transfer(address,uint256)")),
+            "a9059cbb"
+        );
+    }
+
+    #[test]
+    fn test_compatible() {
+        let v = |s| Version::parse(s).unwrap();
+        assert!(compatible(&v("1.2.0"), &v("1.2.3")));
+        assert!(compatible(&v("1.3.0"), &v("1.2.0")));
+        assert!(!compatible(&v("1.1.0"), &v("1.2.0")));
+        assert!(!compatible(&v("2.0.0"), &v("1.2.0")));
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let sync = CryptoHash::hash_bytes(b"sync");
+        let deployed = contract(sync, [1, 0, 0]);
+
+        let mode = decide(&deployed, &build(sync, "1.1.0"), OnMismatch::Refuse).await;
+        assert_eq!(mode.unwrap(), Mode::Full);
+
+        // Redeployed with another sync circuit
+        let other = build(CryptoHash::default(), "1.0.0");
+        let refused = decide(&deployed, &other, OnMismatch::Refuse).await;
+        assert!(refused.unwrap_err().to_string().contains("sync is 0x"));
+        let mode = decide(&deployed, &other, OnMismatch::VerifyOnly).await;
+        assert_eq!(mode.unwrap(), Mode::VerifyOnly);
+
+        // Until `make repro` records a digest only the output version is
+        // checked
+        let mut unrecorded = build(sync, "1.0.0");
+        unrecorded.circuits.insert("sync".to_string(), None);
+        unrecorded.circuits.remove("verify");
+        let mode = decide(&deployed, &unrecorded, OnMismatch::Refuse).await;
+        assert_eq!(mode.unwrap(), Mode::Full);
+        let expected = Expected::read(&deployed).await.unwrap();
+        assert_eq!(
+            super::unrecorded(&unrecorded, &expected),
+            ["sync", "verify"]
+        );
+
+        let breaking = build(sync, "2.0.0");
+        let refused = decide(&deployed, &breaking, OnMismatch::Refuse).await;
+        assert!(refused.unwrap_err().to_string().contains("output version"));
+
+        // A contract without the getters is refused too
+        let old = Fixed(HashMap::new());
+        assert!(decide(&old, &build(sync, "1.0.0"), OnMismatch::Refuse)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_is_registered() {
+        let sync = CryptoHash::hash_bytes(b"sync");
+        let deployed = contract(sync, [1, 0, 0]);
+        assert!(is_registered(&deployed, &sync).await.unwrap());
+
+        let mut revoked = contract(sync, [1, 0, 0]);
+        revoked.0.insert(
+            format!("{}{}", REGISTERED, hex::encode(sync.0)),
+            uint_word(0),
+        );
+        assert!(!is_registered(&revoked, &sync).await.unwrap());
+        // A contract without the registry is an error, not unregistered
+        assert!(is_registered(&Fixed(HashMap::new()), &sync).await.is_err());
+    }
+
+    /// The public state variables of the contract, their getters, by name.
+    fn getters(source: &str) -> HashMap<&str, &str> {
+        source
+            .lines()
+            .filter_map(|line| {
+                let (ty, name) = line.trim().strip_suffix(';')?.split_once(" public ")?;
+                Some((name, ty))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contract_getters() {
+        let contract = include_str!("../../../../nearx/contract/src/NearX.sol");
+        let interface = include_str!("../../../../nearx/contract/src/interfaces/INearX.sol");
+        let getters = getters(contract);
+
+        for (_, getter) in FUNCTION_IDS {
+            let name = getter.trim_end_matches("()");
+            assert_eq!(getters.get(name), Some(&"bytes32"), "{}", getter);
+        }
+        assert_eq!(
+            getters.get(REGISTERED.split_once('(').unwrap().0),
+            Some(&"mapping(bytes32 => bool)")
+        );
+        // A public struct's getter returns its members, three words here
+        let ty = getters[OUTPUT_VERSION.trim_end_matches("()")];
+        let (_, members) = interface
+            .split_once(&format!("struct {} {{", ty))
+            .and_then(|(_, rest)| rest.split_once('}'))
+            .unwrap();
+        let members = members
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with("//"))
+            .collect::<Vec<_>>();
+        assert_eq!(members, ["uint64 major;", "uint64 minor;", "uint64 patch;"]);
+    }
+
+    #[test]
+    fn test_verify_only_drops_relays() {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "catchup": false,
+            "hooks": { "batch": [
+                { "kind": "attest", "signer_key": "ed25519:..." },
+                { "kind": "relay", "url": "https://relayer.example" },
+            ]},
+        }))
+        .unwrap();
+        Mode::Full.apply(&mut config);
+        assert_eq!(config.hooks.batch.len(), 2);
+
+        Mode::VerifyOnly.apply(&mut config);
+        assert_eq!(config.hooks.batch.len(), 1);
+        assert!(matches!(config.hooks.batch[0], HookConfig::Attest { .. }));
+    }
+}
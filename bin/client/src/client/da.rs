@@ -0,0 +1,465 @@
+//! Posting proven heads and proof commitments to an external data
+//! availability layer, so consumers can fetch them while our API is down.
+//!
+//! Posting is off the proving path. Artifacts are posted one at a time in the
+//! background, and a DA layer that is down is retried, logged and counted but
+//! never holds up a sync or a delivery. Artifacts that pile up while it is
+//! down are dropped, as the head feed drops them for slow subscribers.
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use near_primitives::types::BlockHeight;
+use near_primitives_core::serialize::to_base64;
+use rpc::retry::RetryPolicy;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{
+    heads::HeadEvent,
+    hooks::{Attestation, PostProcessor},
+    queue::AnchoredProof,
+};
+use crate::{
+    config::{CelestiaConfig, DaConfig, EigenDaConfig},
+    prelude::*,
+};
+
+/// Proofs queued further behind than this are dropped.
+const CAPACITY: usize = 128;
+
+/// Posts are retried briefly, the next artifact is waiting.
+const RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay_ms: 1_000,
+    max_delay_ms: 10_000,
+};
+
+/// What is posted, as json.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Artifact {
+    /// A head we synced to, proofs against its root can be checked with it.
+    Head {
+        id: CryptoHash,
+        height: BlockHeight,
+        epoch_id: CryptoHash,
+        block_merkle_root: CryptoHash,
+        next_bp_hash: CryptoHash,
+    },
+    /// A proven batch, committed to by the hash of the borsh encoded proof.
+    Proof {
+        digest: CryptoHash,
+        head_block_root: CryptoHash,
+        /// The head the proof needs to be relayed after.
+        anchor: Option<CryptoHash>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        attestations: Vec<Attestation>,
+    },
+}
+
+impl Artifact {
+    /// Only proven heads are posted, relays are already on chain.
+    pub fn head(event: &HeadEvent) -> Option<Self> {
+        match event {
+            HeadEvent::Proven {
+                id,
+                height,
+                epoch_id,
+                block_merkle_root,
+                next_bp_hash,
+                ..
+            } => Some(Self::Head {
+                id: *id,
+                height: *height,
+                epoch_id: *epoch_id,
+                block_merkle_root: *block_merkle_root,
+                next_bp_hash: *next_bp_hash,
+            }),
+            HeadEvent::Relayed { .. } => None,
+        }
+    }
+
+    pub fn proof(proof: &AnchoredProof) -> Self {
+        Self::Proof {
+            digest: Attestation::digest(proof),
+            head_block_root: proof.proof.head_block_root,
+            anchor: proof.anchor.as_ref().map(|a| a.head),
+            attestations: proof.attestations.clone(),
+        }
+    }
+}
+
+/// A DA layer that takes blobs.
+#[async_trait]
+pub trait DaLayer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Post a blob, returning where it can be fetched from.
+    async fn post(&self, blob: &[u8]) -> Result<String>;
+}
+
+/// A Celestia light or bridge node, blobs are submitted through its RPC.
+pub struct Celestia {
+    url: String,
+    auth_token: Option<String>,
+    namespace: [u8; NAMESPACE_SIZE],
+    gas_price: Option<f64>,
+    client: reqwest::Client,
+}
+
+const NAMESPACE_SIZE: usize = 29;
+
+/// The most of a version 0 namespace that is ours to choose.
+const NAMESPACE_ID_SIZE: usize = 10;
+
+impl Celestia {
+    pub fn new(config: &CelestiaConfig) -> Result<Self> {
+        Ok(Self {
+            url: config.url.clone(),
+            auth_token: config.auth_token.clone(),
+            namespace: namespace(&config.namespace)?,
+            gas_price: config.gas_price,
+            client: client(config.timeout_ms)?,
+        })
+    }
+}
+
+/// A version 0 namespace from the hex of its id, which is zero padded on the
+/// left to the namespace size.
+fn namespace(id: &str) -> Result<[u8; NAMESPACE_SIZE]> {
+    let id = hex::decode(id.trim_start_matches("0x"))
+        .map_err(|e| anyhow!("Invalid Celestia namespace {}: {}", id, e))?;
+    anyhow::ensure!(
+        !id.is_empty() && id.len() <= NAMESPACE_ID_SIZE,
+        "A Celestia namespace is 1 to {} bytes, got {}",
+        NAMESPACE_ID_SIZE,
+        id.len()
+    );
+    let mut namespace = [0; NAMESPACE_SIZE];
+    namespace[NAMESPACE_SIZE - id.len()..].copy_from_slice(&id);
+    Ok(namespace)
+}
+
+#[async_trait]
+impl DaLayer for Celestia {
+    fn name(&self) -> &'static str {
+        "celestia"
+    }
+
+    async fn post(&self, blob: &[u8]) -> Result<String> {
+        let options = match self.gas_price {
+            Some(gas_price) => serde_json::json!({ "gas_price": gas_price }),
+            None => serde_json::json!({}),
+        };
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "blob.Submit",
+            "params": [
+                [{
+                    "namespace": to_base64(&self.namespace),
+                    "data": to_base64(blob),
+                    "share_version": 0,
+                }],
+                options,
+            ],
+        });
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token);
+        }
+        let response: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("The node rejected the blob: {}", error);
+        }
+        let height = response["result"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("No height in {}", response))?;
+        Ok(format!(
+            "celestia:{}:{}",
+            height,
+            hex::encode(self.namespace)
+        ))
+    }
+}
+
+/// EigenDA through an eigenda-proxy, which disperses blobs and returns their
+/// certificate.
+pub struct EigenDa {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl EigenDa {
+    pub fn new(config: &EigenDaConfig) -> Result<Self> {
+        Ok(Self {
+            url: config.url.trim_end_matches('/').to_string(),
+            client: client(config.timeout_ms)?,
+        })
+    }
+}
+
+#[async_trait]
+impl DaLayer for EigenDa {
+    fn name(&self) -> &'static str {
+        "eigenda"
+    }
+
+    async fn post(&self, blob: &[u8]) -> Result<String> {
+        let commitment = self
+            .client
+            .post(format!("{}/put?commitment_mode=standard", self.url))
+            .body(blob.to_vec())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(format!("eigenda:0x{}", hex::encode(commitment)))
+    }
+}
+
+fn client(timeout_ms: u64) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_millis(timeout_ms))
+        .build()?)
+}
+
+/// Posts proven heads and proofs to the configured DA layer.
+pub struct Publisher {
+    layer: Box<dyn DaLayer>,
+    proofs: broadcast::Sender<Artifact>,
+    posted: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Publisher {
+    pub fn new(config: &DaConfig) -> Result<Self> {
+        let layer: Box<dyn DaLayer> = match config {
+            DaConfig::Celestia(config) => Box::new(Celestia::new(config)?),
+            DaConfig::EigenDa(config) => Box::new(EigenDa::new(config)?),
+        };
+        Ok(Self::with_layer(layer))
+    }
+
+    fn with_layer(layer: Box<dyn DaLayer>) -> Self {
+        Self {
+            layer,
+            proofs: broadcast::channel(CAPACITY).0,
+            posted: Default::default(),
+            failed: Default::default(),
+            dropped: Default::default(),
+        }
+    }
+
+    /// Post each head proven on `heads` and each proof queued with
+    /// `Publish`, until the head feed closes.
+    pub async fn start(self: Arc<Self>, mut heads: broadcast::Receiver<HeadEvent>) {
+        let mut proofs = self.proofs.subscribe();
+        loop {
+            let received = tokio::select! {
+                event = heads.recv() => event.map(|e| Artifact::head(&e)),
+                artifact = proofs.recv() => artifact.map(Some),
+            };
+            match received {
+                Ok(Some(artifact)) => self.post(&artifact).await,
+                Ok(None) => {}
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!(
+                        "Dropped {} artifacts while {} was slow",
+                        n,
+                        self.layer.name()
+                    );
+                    self.dropped.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    async fn post(&self, artifact: &Artifact) {
+        let blob = match serde_json::to_vec(artifact) {
+            Ok(blob) => blob,
+            Err(e) => {
+                log::error!("Failed to encode {:?}: {:?}", artifact, e);
+                return;
+            }
+        };
+        let what = format!("Posting to {}", self.layer.name());
+        match RETRY
+            .retry(&what, || self.layer.post(&blob), |_| true)
+            .await
+        {
+            Ok(reference) => {
+                log::info!("Posted {:?} at {}", artifact, reference);
+                self.posted.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                log::error!("Failed to post {:?}: {:?}", artifact, e);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP light_client_da_posts_total Artifacts posted to the DA layer\n");
+        out.push_str("# TYPE light_client_da_posts_total counter\n");
+        for (status, count) in [
+            ("posted", &self.posted),
+            ("failed", &self.failed),
+            ("dropped", &self.dropped),
+        ] {
+            out.push_str(&format!(
+                "light_client_da_posts_total{{layer=\"{}\",status=\"{}\"}} {}\n",
+                self.layer.name(),
+                status,
+                count.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+/// Queue each proven batch to be posted, run last so the attestations are
+/// posted with it.
+pub struct Publish(pub Arc<Publisher>);
+
+#[async_trait]
+impl PostProcessor for Publish {
+    fn name(&self) -> &'static str {
+        "da"
+    }
+
+    async fn process(&self, proof: &mut AnchoredProof) -> Result<()> {
+        // Nothing is listening until the publisher starts
+        let _ = self.0.proofs.send(Artifact::proof(proof));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use protocol::experimental::Proof as ExperimentalProof;
+
+    use super::*;
+    use crate::{build_info::BuildInfo, client::heads};
+
+    /// Keeps what was posted, failing the first `fail` posts.
+    struct Record(Arc<Mutex<Vec<serde_json::Value>>>, AtomicU64);
+
+    #[async_trait]
+    impl DaLayer for Record {
+        fn name(&self) -> &'static str {
+            "record"
+        }
+
+        async fn post(&self, blob: &[u8]) -> Result<String> {
+            if self.1.load(Ordering::Relaxed) > 0 {
+                self.1.fetch_sub(1, Ordering::Relaxed);
+                anyhow::bail!("unavailable");
+            }
+            let mut posted = self.0.lock().unwrap();
+            posted.push(serde_json::from_slice(blob)?);
+            Ok(format!("record:{}", posted.len()))
+        }
+    }
+
+    #[test]
+    fn test_namespace() {
+        let ns = namespace("0x6e6561722d6c63").unwrap();
+        assert_eq!(&ns[..22], &[0; 22]);
+        assert_eq!(&ns[22..], b"near-lc");
+        assert!(namespace("").is_err());
+        assert!(namespace(&"ab".repeat(11)).is_err());
+        assert!(namespace("not hex").is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config: DaConfig = serde_json::from_value(serde_json::json!({
+            "kind": "celestia",
+            "url": "http://localhost:26658",
+            "namespace": "6e656172",
+        }))
+        .unwrap();
+        assert!(Publisher::new(&config).is_ok());
+
+        let config: DaConfig = serde_json::from_value(serde_json::json!({
+            "kind": "eigenda",
+            "url": "http://localhost:3100/",
+        }))
+        .unwrap();
+        assert_eq!(Publisher::new(&config).unwrap().layer.name(), "eigenda");
+    }
+
+    #[tokio::test]
+    async fn test_publisher_posts_heads_and_proofs() {
+        let posted = Arc::new(Mutex::new(vec![]));
+        let publisher = Arc::new(Publisher::with_layer(Box::new(Record(
+            posted.clone(),
+            AtomicU64::new(1),
+        ))));
+        let feed = heads::feed();
+        let task = tokio::spawn(publisher.clone().start(feed.subscribe()));
+        // Let it subscribe to proofs
+        tokio::task::yield_now().await;
+
+        let root = CryptoHash::hash_bytes(b"root");
+        heads::publish(
+            &feed,
+            HeadEvent::Proven {
+                id: CryptoHash::hash_bytes(b"head"),
+                height: 10,
+                epoch_id: CryptoHash::default(),
+                block_merkle_root: root,
+                next_bp_hash: CryptoHash::default(),
+                approvals: None,
+                marginal: false,
+                rejected_keys: None,
+            },
+        );
+        let mut proof = AnchoredProof {
+            proof: ExperimentalProof::new(root, vec![]),
+            anchor: None,
+            build: BuildInfo::get(),
+            attestations: vec![],
+            packed_results: None,
+        };
+        Publish(publisher.clone())
+            .process(&mut proof)
+            .await
+            .unwrap();
+
+        // The first post is retried
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while posted.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let kinds = posted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|a| a["kind"].as_str().unwrap().to_string())
+            .sorted()
+            .collect_vec();
+        assert_eq!(kinds, vec!["head", "proof"]);
+        assert!(publisher
+            .render()
+            .contains("light_client_da_posts_total{layer=\"record\",status=\"posted\"} 2\n"));
+
+        drop(feed);
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use nearx_error::Layer;
+use protocol::error::Error as ProtocolError;
+
+use crate::prelude::*;
+
+/// Why a job failed. These are stable, so they can be alerted on, new reasons
+/// should be added to the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FailureReason {
+    /// The RPC could not serve the data needed for the proof.
+    RpcUnavailable,
+    /// A merkle path was deeper than the circuit supports.
+    ProofTooDeep,
+    SignatureInvalid,
+    StakeBelowThreshold,
+    /// Any other protocol check failed, these would be unsatisfiable in the
+    /// circuit.
+    ConstraintUnsatisfied,
+    Timeout,
+    RelayReverted,
+    /// The proof's root was never synced by this client.
+    UnknownRoot,
+    Internal,
+    /// Another relay of the same head was already accepted.
+    AlreadyRelayed,
+    /// The build's circuits differ from those an epoch was proven with.
+    CircuitMismatch,
+}
+
+impl FailureReason {
+    pub const ALL: [FailureReason; 11] = [
+        Self::RpcUnavailable,
+        Self::ProofTooDeep,
+        Self::SignatureInvalid,
+        Self::StakeBelowThreshold,
+        Self::ConstraintUnsatisfied,
+        Self::Timeout,
+        Self::RelayReverted,
+        Self::UnknownRoot,
+        Self::Internal,
+        Self::AlreadyRelayed,
+        Self::CircuitMismatch,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RpcUnavailable => "rpc_unavailable",
+            Self::ProofTooDeep => "proof_too_deep",
+            Self::SignatureInvalid => "signature_invalid",
+            Self::StakeBelowThreshold => "stake_below_threshold",
+            Self::ConstraintUnsatisfied => "constraint_unsatisfied",
+            Self::Timeout => "timeout",
+            Self::RelayReverted => "relay_reverted",
+            Self::UnknownRoot => "unknown_root",
+            Self::Internal => "internal",
+            Self::AlreadyRelayed => "already_relayed",
+            Self::CircuitMismatch => "circuit_mismatch",
+        }
+    }
+
+    /// Classify an error that wasn't raised as a `Failure`, by the protocol
+    /// error or the layer it was raised in.
+    fn of(e: &anyhow::Error) -> Self {
+        let layered = e
+            .chain()
+            .find_map(|e| e.downcast_ref::<nearx_error::Error>());
+        let protocol = e
+            .downcast_ref::<ProtocolError>()
+            .or_else(|| layered.and_then(|e| e.downcast_ref::<ProtocolError>()));
+        if let Some(e) = protocol {
+            return match e {
+                ProtocolError::SignatureInvalid
+                | ProtocolError::ValidatorNotSigned
+                | ProtocolError::InvalidPublicKey(_) => Self::SignatureInvalid,
+                ProtocolError::NotEnoughApprovedStake => Self::StakeBelowThreshold,
+                _ => Self::ConstraintUnsatisfied,
+            };
+        }
+        if e.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+            return Self::Timeout;
+        }
+        match layered.map(nearx_error::Error::layer) {
+            Some(Layer::Rpc) => Self::RpcUnavailable,
+            _ => Self::Internal,
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A classified failure, raised where we know the reason and returned to
+/// requesters in place of free text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[error("{reason}: {message}")]
+pub struct Failure {
+    pub reason: FailureReason,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(reason: FailureReason, message: impl ToString) -> Self {
+        Self {
+            reason,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<&anyhow::Error> for Failure {
+    fn from(e: &anyhow::Error) -> Self {
+        e.chain()
+            .find_map(|e| e.downcast_ref::<Failure>())
+            .cloned()
+            .unwrap_or_else(|| Failure::new(FailureReason::of(e), format!("{:#}", e)))
+    }
+}
+
+pub const DEFAULT_RECENT_ERRORS: usize = 100;
+
+/// A failure as kept in the recent errors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentError {
+    pub timestamp_ms: u64,
+    /// What failed, the id being proven, the root being relayed or `sync`.
+    pub job: Option<String>,
+    #[serde(flatten)]
+    pub failure: Failure,
+}
+
+/// Failure counts per reason, and the last few failures for triage without
+/// log access.
+#[derive(Debug)]
+pub struct FailureCounters {
+    counts: [AtomicU64; FailureReason::ALL.len()],
+    recent: Mutex<VecDeque<RecentError>>,
+    capacity: usize,
+}
+
+impl Default for FailureCounters {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECENT_ERRORS)
+    }
+}
+
+impl FailureCounters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            counts: Default::default(),
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, reason: FailureReason) {
+        self.counts[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the failure and keep it in the recent errors, dropping the oldest
+    /// if full.
+    pub fn record_failure(&self, job: Option<String>, failure: &Failure) {
+        self.record(failure.reason);
+        if self.capacity == 0 {
+            return;
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut recent = self.recent.lock().expect("poisoned");
+        if recent.len() == self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            timestamp_ms,
+            job,
+            failure: failure.clone(),
+        });
+    }
+
+    /// The recent errors, newest first.
+    pub fn recent(&self) -> Vec<RecentError> {
+        let recent = self.recent.lock().expect("poisoned");
+        recent.iter().rev().cloned().collect()
+    }
+
+    pub fn get(&self, reason: FailureReason) -> u64 {
+        self.counts[reason as usize].load(Ordering::Relaxed)
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::from(
+            "# HELP light_client_failures_total Failed jobs by reason\n# TYPE \
+             light_client_failures_total counter\n",
+        );
+        for reason in FailureReason::ALL {
+            out.push_str(&format!(
+                "light_client_failures_total{{reason=\"{}\"}} {}\n",
+                reason,
+                self.get(reason)
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nearx_error::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_all_is_in_discriminant_order() {
+        for (i, reason) in FailureReason::ALL.iter().enumerate() {
+            assert_eq!(*reason as usize, i);
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let e = anyhow::Error::from(ProtocolError::NotEnoughApprovedStake);
+        assert_eq!(Failure::from(&e).reason, FailureReason::StakeBelowThreshold);
+
+        let e = anyhow::Error::from(ProtocolError::BlockAlreadyVerified).context("syncing");
+        assert_eq!(
+            Failure::from(&e).reason,
+            FailureReason::ConstraintUnsatisfied
+        );
+
+        let e = anyhow::Error::from(Failure::new(FailureReason::RpcUnavailable, "down"))
+            .context("fetching");
+        assert_eq!(
+            Failure::from(&e),
+            Failure::new(FailureReason::RpcUnavailable, "down")
+        );
+
+        let e = anyhow::anyhow!("something else");
+        assert_eq!(Failure::from(&e).reason, FailureReason::Internal);
+    }
+
+    #[test]
+    fn test_classify_layered() {
+        let e = Err::<(), _>(ProtocolError::SignatureInvalid)
+            .context("syncing to 7")
+            .map_err(anyhow::Error::from)
+            .unwrap_err()
+            .context("sync");
+        let failure = Failure::from(&e);
+        assert_eq!(failure.reason, FailureReason::SignatureInvalid);
+        assert_eq!(failure.message, "sync: syncing to 7: Signature invalid");
+
+        let e = anyhow::Error::from(nearx_error::err!(Rpc, "no block at 7"));
+        assert_eq!(Failure::from(&e).reason, FailureReason::RpcUnavailable);
+    }
+
+    #[test]
+    fn test_render() {
+        let counters = FailureCounters::default();
+        counters.record(FailureReason::Timeout);
+        counters.record(FailureReason::Timeout);
+
+        let out = counters.render();
+        assert!(out.contains("light_client_failures_total{reason=\"timeout\"} 2\n"));
+        assert!(out.contains("light_client_failures_total{reason=\"internal\"} 0\n"));
+    }
+
+    #[test]
+    fn test_recent_errors_ring() {
+        let counters = FailureCounters::new(2);
+        for i in 0..3 {
+            counters.record_failure(
+                Some(i.to_string()),
+                &Failure::new(FailureReason::RpcUnavailable, "down"),
+            );
+        }
+
+        let recent = counters.recent();
+        assert_eq!(
+            recent.iter().map(|e| e.job.as_deref()).collect_vec(),
+            vec![Some("2"), Some("1")]
+        );
+        // Evicted errors are still counted
+        assert_eq!(counters.get(FailureReason::RpcUnavailable), 3);
+    }
+}
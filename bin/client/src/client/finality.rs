@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use protocol::{balance::format_near, signature::KeyPolicy, StakeInfo};
+
+use crate::{config::FinalityConfig, prelude::*};
+
+/// Flags synced heads whose approved stake only just passed the 2/3
+/// threshold, consumers may want to wait for more confirmations of these.
+#[derive(Debug)]
+pub struct Finality {
+    config: FinalityConfig,
+    last_margin_bps: AtomicU64,
+    marginal: AtomicU64,
+}
+
+impl Finality {
+    pub fn new(config: FinalityConfig) -> Self {
+        Self {
+            config,
+            last_margin_bps: Default::default(),
+            marginal: Default::default(),
+        }
+    }
+
+    /// What a sync does with an approval by a seat with an invalid key.
+    pub fn key_policy(&self) -> KeyPolicy {
+        self.config.key_policy
+    }
+
+    /// Whether the head's approvals were marginal, these are logged and
+    /// counted.
+    pub fn check(&self, head: &Header, stake: &StakeInfo) -> bool {
+        let margin_bps = stake.margin_bps();
+        self.last_margin_bps
+            .store(margin_bps as u64, Ordering::Relaxed);
+
+        let marginal = margin_bps < self.config.marginal_bps;
+        if marginal {
+            self.marginal.fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "Head {} at {} was approved by {} of {} NEAR, {}bps past the threshold",
+                head.hash(),
+                head.inner_lite.height,
+                format_near(stake.approved),
+                format_near(stake.total),
+                margin_bps
+            );
+        }
+        marginal
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP light_client_approval_margin_bps How far the last synced head's approved \
+             stake was past the threshold, in basis points of the total\n",
+        );
+        out.push_str("# TYPE light_client_approval_margin_bps gauge\n");
+        out.push_str(&format!(
+            "light_client_approval_margin_bps {}\n",
+            self.last_margin_bps.load(Ordering::Relaxed)
+        ));
+        out.push_str(
+            "# HELP light_client_marginal_heads_total Synced heads approved by stake close to \
+             the threshold\n",
+        );
+        out.push_str("# TYPE light_client_marginal_heads_total counter\n");
+        out.push_str(&format!(
+            "light_client_marginal_heads_total {}\n",
+            self.marginal.load(Ordering::Relaxed)
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::{test_first, to_header};
+
+    use super::*;
+
+    #[test]
+    fn test_marginal_heads_are_flagged() {
+        let finality = Finality::new(FinalityConfig {
+            marginal_bps: 500,
+            ..Default::default()
+        });
+        let head = to_header(test_first().body);
+
+        // 10% past the threshold
+        let comfortable = StakeInfo::from((300, 230));
+        assert!(!finality.check(&head, &comfortable));
+        assert!(finality
+            .render()
+            .contains("light_client_approval_margin_bps 1000\n"));
+
+        let marginal = StakeInfo::from((300, 201));
+        assert!(finality.check(&head, &marginal));
+        let metrics = finality.render();
+        assert!(metrics.contains("light_client_approval_margin_bps 33\n"));
+        assert!(metrics.contains("light_client_marginal_heads_total 1\n"));
+    }
+}
@@ -0,0 +1,360 @@
+//! Checkpoints of what the client needs to resume: the trusted head, the block
+//! producers it syncs with and how far proving got.
+//!
+//! A client whose store is lost resumes from the latest checkpoint rather than
+//! trusting a `starting_head` again. Checkpoints are written off the sync path,
+//! on a new epoch and every `interval` heads between, and once more on
+//! shutdown.
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use near_primitives::types::{validator_stake::ValidatorStake, BlockHeight};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::{
+    heads::HeadEvent,
+    s3::Bucket,
+    store::{head_key, Collection, Entity, LightClientStore, Pipeline, Store},
+};
+use crate::{
+    config::{HeadStoreBackend, HeadStoreConfig, S3Config},
+    prelude::*,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct Checkpoint {
+    pub head: Header,
+    /// The block producers of the head's epoch and of the next, those we
+    /// have, keyed by epoch id.
+    pub bps: Vec<(CryptoHash, Vec<ValidatorStake>)>,
+    /// The highest head proofs were built against.
+    pub proven: Option<BlockHeight>,
+}
+
+impl Checkpoint {
+    /// Where the store is now.
+    pub async fn read<S: LightClientStore>(store: &Store<S>) -> Result<Self> {
+        let head = store.head().await?;
+        let mut bps = vec![];
+        for epoch_id in [head.inner_lite.epoch_id, head.inner_lite.next_epoch_id] {
+            if let Ok(stake) = store
+                .get(&Collection::BlockProducers, &epoch_id)
+                .await
+                .and_then(|e| e.bps())
+            {
+                bps.push((epoch_id, stake));
+            }
+        }
+        Ok(Self {
+            head,
+            bps,
+            proven: store.progress().await.proven,
+        })
+    }
+
+    /// The inserts restoring an empty store to the checkpoint.
+    pub fn inserts(self) -> Vec<(CryptoHash, Entity)> {
+        let mut inserts = self
+            .bps
+            .into_iter()
+            .map(|(epoch_id, stake)| (epoch_id, stake.into()))
+            .collect_vec();
+        inserts.push(Pipeline::Fetched.mark(self.head.inner_lite.height));
+        if let Some(proven) = self.proven {
+            inserts.push(Pipeline::Proven.mark(proven));
+        }
+        inserts.push((head_key(), self.head.into()));
+        inserts
+    }
+}
+
+/// Somewhere to keep the latest checkpoint.
+#[async_trait]
+pub trait HeadStore: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The latest checkpoint, if one was written.
+    async fn load(&self) -> Result<Option<Checkpoint>>;
+
+    /// Replace the latest checkpoint.
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()>;
+}
+
+/// A file, written beside and renamed over so a crash can't leave half a
+/// checkpoint.
+pub struct Fs {
+    path: PathBuf,
+}
+
+impl Fs {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl HeadStore for Fs {
+    fn name(&self) -> &'static str {
+        "fs"
+    }
+
+    async fn load(&self) -> Result<Option<Checkpoint>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => Ok(Some(Checkpoint::try_from_slice(&bytes)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, borsh::to_vec(checkpoint)?).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// A sled database of its own.
+pub struct Sled(::sled::Db);
+
+const CHECKPOINT_KEY: &[u8] = b"checkpoint";
+
+impl Sled {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self(::sled::open(path)?))
+    }
+}
+
+#[async_trait]
+impl HeadStore for Sled {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
+
+    async fn load(&self) -> Result<Option<Checkpoint>> {
+        Ok(self
+            .0
+            .get(CHECKPOINT_KEY)?
+            .map(|bytes| Checkpoint::try_from_slice(&bytes))
+            .transpose()?)
+    }
+
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        self.0.insert(CHECKPOINT_KEY, borsh::to_vec(checkpoint)?)?;
+        self.0.flush_async().await?;
+        Ok(())
+    }
+}
+
+/// An object in S3, or a store speaking its API.
+pub struct S3 {
+    bucket: Bucket,
+    key: String,
+}
+
+impl S3 {
+    pub fn new(config: &S3Config) -> Result<Self> {
+        Ok(Self {
+            bucket: Bucket::new(config, false)?,
+            key: config.key.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl HeadStore for S3 {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    async fn load(&self) -> Result<Option<Checkpoint>> {
+        self.bucket
+            .get(&self.key)
+            .await?
+            .map(|bytes| Ok(Checkpoint::try_from_slice(&bytes)?))
+            .transpose()
+    }
+
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        self.bucket.put(&self.key, borsh::to_vec(checkpoint)?).await
+    }
+}
+
+/// Writes checkpoints to the configured head store as heads are synced.
+pub struct Checkpointer {
+    store: Box<dyn HeadStore>,
+    interval: u64,
+}
+
+impl Checkpointer {
+    pub fn new(config: &HeadStoreConfig) -> Result<Self> {
+        let store: Box<dyn HeadStore> = match &config.backend {
+            HeadStoreBackend::Fs { path } => Box::new(Fs::new(path)),
+            HeadStoreBackend::Sled { path } => Box::new(Sled::open(path)?),
+            HeadStoreBackend::S3(config) => Box::new(S3::new(config)?),
+        };
+        Ok(Self::with_store(store, config.interval))
+    }
+
+    fn with_store(store: Box<dyn HeadStore>, interval: u64) -> Self {
+        Self { store, interval }
+    }
+
+    /// The checkpoint to resume from, if one was written.
+    pub async fn restore(&self) -> Result<Option<Checkpoint>> {
+        self.store.load().await
+    }
+
+    pub async fn save<S: LightClientStore>(&self, store: &Store<S>) -> Result<Checkpoint> {
+        let checkpoint = Checkpoint::read(store).await?;
+        self.store.save(&checkpoint).await?;
+        log::info!(
+            "Checkpointed head {} to {}",
+            checkpoint.head.inner_lite.height,
+            self.store.name()
+        );
+        Ok(checkpoint)
+    }
+
+    /// Checkpoint the heads proven on `heads` as they become due, until the
+    /// head feed closes.
+    pub async fn start<S: LightClientStore + Send + Sync>(
+        self: Arc<Self>,
+        store: Arc<Store<S>>,
+        mut heads: broadcast::Receiver<HeadEvent>,
+    ) {
+        let mut last = None;
+        loop {
+            match heads.recv().await {
+                Ok(HeadEvent::Proven {
+                    height, epoch_id, ..
+                }) => {
+                    if !self.due(last, height, epoch_id) {
+                        continue;
+                    }
+                    match self.save(&store).await {
+                        Ok(checkpoint) => {
+                            let inner = &checkpoint.head.inner_lite;
+                            last = Some((inner.height, inner.epoch_id));
+                        }
+                        Err(e) => {
+                            log::error!("Failed to checkpoint to {}: {:?}", self.store.name(), e)
+                        }
+                    }
+                }
+                Ok(HeadEvent::Relayed { .. }) => {}
+                // The next head checkpoints whatever the store is at
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    fn due(
+        &self,
+        last: Option<(BlockHeight, CryptoHash)>,
+        height: BlockHeight,
+        epoch_id: CryptoHash,
+    ) -> bool {
+        match last {
+            None => true,
+            Some((saved, saved_epoch)) => {
+                saved_epoch != epoch_id || height >= saved + self.interval
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::Mutex};
+
+    use super::*;
+    use crate::client::{bootstrap_inserts, store::sled::temporary};
+
+    /// Keeps checkpoints in memory.
+    #[derive(Default)]
+    struct Memory(Arc<Mutex<Option<Checkpoint>>>);
+
+    #[async_trait]
+    impl HeadStore for Memory {
+        fn name(&self) -> &'static str {
+            "memory"
+        }
+
+        async fn load(&self) -> Result<Option<Checkpoint>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+            *self.0.lock().unwrap() = Some(checkpoint.clone());
+            Ok(())
+        }
+    }
+
+    async fn bootstrapped() -> Store<crate::client::store::sled::Store> {
+        let store = Store(temporary().unwrap().into());
+        let first = test_utils::test_first().body;
+        store
+            .insert(&bootstrap_inserts(first).unwrap())
+            .await
+            .unwrap();
+        store.insert(&[Pipeline::Proven.mark(10)]).await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_restores() {
+        let store = bootstrapped().await;
+        let checkpoint = Checkpoint::read(&store).await.unwrap();
+        assert_eq!(checkpoint.bps.len(), 1);
+        assert_eq!(checkpoint.proven, Some(10));
+
+        let restored = Store(temporary().unwrap().into());
+        restored
+            .insert(&checkpoint.clone().inserts())
+            .await
+            .unwrap();
+        assert_eq!(Checkpoint::read(&restored).await.unwrap(), checkpoint);
+        assert_eq!(restored.progress().await, store.progress().await);
+    }
+
+    #[tokio::test]
+    async fn test_backends_round_trip() {
+        let checkpoint = Checkpoint::read(&bootstrapped().await).await.unwrap();
+        let dir = env::temp_dir().join(format!("head-store-{}", rand::random::<u64>()));
+        let backends: [Box<dyn HeadStore>; 2] = [
+            Box::new(Fs::new(&dir.join("checkpoint"))),
+            Box::new(Sled::open(&dir.join("sled")).unwrap()),
+        ];
+        for backend in backends {
+            assert_eq!(backend.load().await.unwrap(), None);
+            backend.save(&checkpoint).await.unwrap();
+            assert_eq!(backend.load().await.unwrap().as_ref(), Some(&checkpoint));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_when_due() {
+        let checkpointer = Checkpointer::with_store(Box::<Memory>::default(), 100);
+        let (epoch, next_epoch) = (CryptoHash::default(), CryptoHash::hash_bytes(b"next"));
+        assert!(checkpointer.due(None, 1, epoch));
+        assert!(!checkpointer.due(Some((1, epoch)), 100, epoch));
+        assert!(checkpointer.due(Some((1, epoch)), 101, epoch));
+        assert!(checkpointer.due(Some((1, epoch)), 2, next_epoch));
+
+        let store = bootstrapped().await;
+        assert_eq!(checkpointer.restore().await.unwrap(), None);
+        let saved = checkpointer.save(&store).await.unwrap();
+        assert_eq!(checkpointer.restore().await.unwrap(), Some(saved));
+    }
+}
@@ -0,0 +1,74 @@
+use near_primitives::types::BlockHeight;
+use protocol::approval::ApprovalBitmap;
+use tokio::sync::broadcast;
+
+use super::store::Anchor;
+use crate::prelude::*;
+
+/// Subscribers further behind than this start missing events.
+const CAPACITY: usize = 128;
+
+/// Published whenever a head is proven or relayed, for downstream indexers to
+/// react to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HeadEvent {
+    /// A head was synced, proofs can now be built against its root.
+    Proven {
+        id: CryptoHash,
+        height: BlockHeight,
+        epoch_id: CryptoHash,
+        block_merkle_root: CryptoHash,
+        next_bp_hash: CryptoHash,
+        /// Which seats approved the head, for liveness dashboards.
+        approvals: Option<ApprovalBitmap>,
+        /// The approved stake was close to the threshold, consumers may want
+        /// more confirmations.
+        marginal: bool,
+        /// Seats whose signatures were skipped for an invalid key.
+        rejected_keys: Option<u32>,
+    },
+    /// The sync to a head was accepted on the destination chain.
+    Relayed { id: CryptoHash, anchor: Anchor },
+}
+
+impl HeadEvent {
+    /// The head as it was anchored.
+    pub fn proven(head: &Header, anchor: &Anchor) -> Self {
+        Self::Proven {
+            id: head.hash(),
+            height: head.inner_lite.height,
+            epoch_id: head.inner_lite.epoch_id,
+            block_merkle_root: head.inner_lite.block_merkle_root,
+            next_bp_hash: head.inner_lite.next_bp_hash,
+            approvals: anchor.approvals.clone(),
+            marginal: anchor.marginal,
+            rejected_keys: anchor.rejected_keys,
+        }
+    }
+
+    pub fn relayed(anchor: Anchor) -> Self {
+        Self::Relayed {
+            id: anchor.head,
+            anchor,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Proven { .. } => "proven",
+            Self::Relayed { .. } => "relayed",
+        }
+    }
+}
+
+pub type HeadFeed = broadcast::Sender<HeadEvent>;
+
+pub fn feed() -> HeadFeed {
+    broadcast::channel(CAPACITY).0
+}
+
+/// Publish to any subscribers, it's fine if there are none.
+pub fn publish(feed: &HeadFeed, event: HeadEvent) {
+    let _ = feed.send(event);
+}
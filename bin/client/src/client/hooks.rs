@@ -0,0 +1,214 @@
+use std::{str::FromStr, time::Duration};
+
+use near_crypto::{PublicKey, SecretKey, Signature};
+
+use super::queue::AnchoredProof;
+use crate::{
+    config::{HookConfig, HooksConfig},
+    prelude::*,
+};
+
+/// A step between proving and delivery, e.g signing or forwarding the proof.
+#[async_trait]
+pub trait PostProcessor: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn process(&self, proof: &mut AnchoredProof) -> Result<()>;
+}
+
+/// An operator's signature over a proof, so consumers can tell who built it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Attestation {
+    /// The hash of the borsh encoded proof.
+    pub digest: CryptoHash,
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+impl Attestation {
+    pub fn digest(proof: &AnchoredProof) -> CryptoHash {
+        CryptoHash::hash_borsh(&proof.proof)
+    }
+
+    pub fn verify(&self, proof: &AnchoredProof) -> bool {
+        self.digest == Self::digest(proof)
+            && self.signature.verify(&self.digest.0, &self.public_key)
+    }
+}
+
+/// Sign the proof with the operator's key.
+pub struct Attest(SecretKey);
+
+#[async_trait]
+impl PostProcessor for Attest {
+    fn name(&self) -> &'static str {
+        "attest"
+    }
+
+    async fn process(&self, proof: &mut AnchoredProof) -> Result<()> {
+        let digest = Attestation::digest(proof);
+        proof.attestations.push(Attestation {
+            digest,
+            public_key: self.0.public_key(),
+            signature: self.0.sign(&digest.0),
+        });
+        Ok(())
+    }
+}
+
+/// Post the proof to a relayer, which submits it to the destination chain.
+pub struct Relay {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl PostProcessor for Relay {
+    fn name(&self) -> &'static str {
+        "relay"
+    }
+
+    async fn process(&self, proof: &mut AnchoredProof) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .json(proof)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// The post processors each kind of proof goes through, in order.
+#[derive(Default)]
+pub struct Hooks {
+    batch: Vec<Box<dyn PostProcessor>>,
+}
+
+impl Hooks {
+    pub fn new(config: &HooksConfig) -> Result<Self> {
+        Ok(Self {
+            batch: config.batch.iter().map(build).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Run `hook` on batches after those that are configured.
+    pub fn then(mut self, hook: Box<dyn PostProcessor>) -> Self {
+        self.batch.push(hook);
+        self
+    }
+
+    /// Run a proven batch through its hooks, the first failure fails the
+    /// batch.
+    pub async fn batch(&self, proof: &mut AnchoredProof) -> Result<()> {
+        run(&self.batch, proof).await
+    }
+}
+
+fn build(config: &HookConfig) -> Result<Box<dyn PostProcessor>> {
+    Ok(match config {
+        HookConfig::Attest { signer_key } => Box::new(Attest(
+            SecretKey::from_str(signer_key)
+                .map_err(|e| anyhow!("Invalid attestation signer key: {}", e))?,
+        )),
+        HookConfig::Relay(relay) => Box::new(Relay {
+            url: relay.url.clone(),
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(relay.timeout_ms))
+                .build()?,
+        }),
+    })
+}
+
+async fn run(hooks: &[Box<dyn PostProcessor>], proof: &mut AnchoredProof) -> Result<()> {
+    for hook in hooks {
+        log::debug!("Running {} hook", hook.name());
+        hook.process(proof)
+            .await
+            .map_err(|e| anyhow!("The {} hook failed: {:?}", hook.name(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use near_crypto::KeyType;
+    use protocol::experimental::Proof as ExperimentalProof;
+    use test_utils::fixture;
+
+    use super::*;
+    use crate::build_info::BuildInfo;
+
+    fn proof() -> AnchoredProof {
+        let proof: BasicProof = fixture("old.json");
+        AnchoredProof {
+            proof: ExperimentalProof::new(CryptoHash::default(), vec![proof]),
+            anchor: None,
+            build: BuildInfo::get(),
+            attestations: vec![],
+            packed_results: None,
+        }
+    }
+
+    fn attest(seed: &str) -> Box<dyn PostProcessor> {
+        build(&HookConfig::Attest {
+            signer_key: SecretKey::from_seed(KeyType::ED25519, seed).to_string(),
+        })
+        .unwrap()
+    }
+
+    /// Records the order hooks ran in, failing if asked to.
+    struct Record(&'static str, Arc<Mutex<Vec<&'static str>>>, bool);
+
+    #[async_trait]
+    impl PostProcessor for Record {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn process(&self, _proof: &mut AnchoredProof) -> Result<()> {
+            self.1.lock().unwrap().push(self.0);
+            anyhow::ensure!(!self.2, "asked to fail");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_attestations_verify() {
+        let mut proof = proof();
+        run(&[attest("a"), attest("b")], &mut proof).await.unwrap();
+
+        assert_eq!(proof.attestations.len(), 2);
+        assert!(proof.attestations.iter().all(|a| a.verify(&proof)));
+
+        let mut other = proof.clone();
+        other.proof.head_block_root = CryptoHash::hash_bytes(b"other");
+        assert!(!proof.attestations[0].verify(&other));
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_order_until_one_fails() {
+        let ran = Arc::new(Mutex::new(vec![]));
+        let hooks: Vec<Box<dyn PostProcessor>> = vec![
+            Box::new(Record("first", ran.clone(), false)),
+            Box::new(Record("second", ran.clone(), true)),
+            Box::new(Record("third", ran.clone(), false)),
+        ];
+
+        let e = run(&hooks, &mut proof()).await.unwrap_err();
+        assert!(e.to_string().contains("second"));
+        assert_eq!(*ran.lock().unwrap(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_invalid_signer_key() {
+        let config = HooksConfig {
+            batch: vec![HookConfig::Attest {
+                signer_key: "nope".to_string(),
+            }],
+        };
+        assert!(Hooks::new(&config).is_err());
+    }
+}
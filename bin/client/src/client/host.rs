@@ -0,0 +1,301 @@
+//! What the host can do, checked at startup so a slow or mismatched machine
+//! shows up in the logs rather than as timed out batches.
+//!
+//! The hashing and curve arithmetic we do natively pick their code paths at
+//! runtime. `sha2` uses the SHA instructions of x86 or ARMv8 when the CPU has
+//! them, `curve25519-dalek` its AVX2 backend on x86, both falling back to
+//! portable code otherwise, such as for curve arithmetic on ARM. We detect
+//! the same features to report which paths are taken, and benchmark them to
+//! estimate what the host can prove.
+use std::{
+    env::consts::ARCH,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use near_crypto::{KeyType, SecretKey};
+use protocol::config::{NetworkParams, NUM_BLOCK_PRODUCER_SEATS};
+
+use crate::{config::Config, prelude::*};
+
+/// How long each benchmark runs for.
+const BENCHMARK_BUDGET: Duration = Duration::from_millis(100);
+
+/// Roughly how often NEAR produces a block, the time we have to check a head
+/// before the next.
+const BLOCK_TIME: Duration = Duration::from_secs(1);
+
+/// The CPU features our dependencies look for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Features {
+    /// SHA-256 instructions, SHA-NI on x86 or the ARMv8 crypto extension.
+    pub sha: bool,
+    /// 256 bit vectors on x86.
+    pub avx2: bool,
+    /// 128 bit vectors on ARM, always there on aarch64.
+    pub neon: bool,
+}
+
+impl Features {
+    #[cfg(target_arch = "x86_64")]
+    pub fn detect() -> Self {
+        Self {
+            sha: std::arch::is_x86_feature_detected!("sha")
+                && std::arch::is_x86_feature_detected!("sse4.1"),
+            avx2: std::arch::is_x86_feature_detected!("avx2"),
+            neon: false,
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn detect() -> Self {
+        Self {
+            sha: std::arch::is_aarch64_feature_detected!("sha2"),
+            avx2: false,
+            neon: std::arch::is_aarch64_feature_detected!("neon"),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn detect() -> Self {
+        Self::default()
+    }
+}
+
+/// How SHA-256 is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashPath {
+    ShaNi,
+    ArmSha2,
+    Portable,
+}
+
+/// How signatures are verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurvePath {
+    Avx2,
+    /// 64 bit limbs, what ARM gets.
+    Serial,
+}
+
+/// The code paths our dependencies take on a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Paths {
+    pub hash: HashPath,
+    pub curve: CurvePath,
+}
+
+impl Paths {
+    pub fn of(arch: &str, features: &Features) -> Self {
+        let hash = match arch {
+            "x86_64" if features.sha => HashPath::ShaNi,
+            "aarch64" if features.sha => HashPath::ArmSha2,
+            _ => HashPath::Portable,
+        };
+        let curve = match arch {
+            "x86_64" if features.avx2 => CurvePath::Avx2,
+            _ => CurvePath::Serial,
+        };
+        Self { hash, curve }
+    }
+}
+
+/// How fast the host does the work that slots and heads cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Benchmark {
+    pub hashes_per_sec: f64,
+    pub verifies_per_sec: f64,
+}
+
+impl Benchmark {
+    /// Run each benchmark for about `budget`.
+    pub fn run(budget: Duration) -> Self {
+        // A merkle node, two hashes
+        let hashes_per_sec = rate(budget, |i| {
+            black_box(CryptoHash::hash_bytes(&[i as u8; 64]));
+        });
+
+        let key = SecretKey::from_seed(KeyType::ED25519, "benchmark");
+        let (public_key, signature) = (key.public_key(), key.sign(b"approval"));
+        let verifies_per_sec = rate(budget, |_| {
+            black_box(signature.verify(black_box(b"approval"), &public_key));
+        });
+
+        Self {
+            hashes_per_sec,
+            verifies_per_sec,
+        }
+    }
+
+    /// A slot hashes its outcome and each node of its merkle paths, at worst
+    /// as deep as the network allows.
+    pub fn slot_time(&self, params: &NetworkParams) -> Duration {
+        let hashes = 1
+            + params.outcome_proof_depth
+            + params.outcome_root_proof_depth
+            + params.block_proof_depth;
+        Duration::from_secs_f64(hashes as f64 / self.hashes_per_sec)
+    }
+
+    /// A head is checked against a signature from each seat.
+    pub fn head_time(&self) -> Duration {
+        Duration::from_secs_f64(NUM_BLOCK_PRODUCER_SEATS as f64 / self.verifies_per_sec)
+    }
+}
+
+/// How many times a second `f` runs, over about `budget`.
+fn rate(budget: Duration, mut f: impl FnMut(u64)) -> f64 {
+    let start = Instant::now();
+    let mut runs = 0;
+    while start.elapsed() < budget {
+        f(runs);
+        runs += 1;
+    }
+    runs as f64 / start.elapsed().as_secs_f64()
+}
+
+/// What the config asks of the host that it likely can't do.
+pub fn warnings(config: &Config, benchmark: &Benchmark) -> Vec<String> {
+    let mut warnings = vec![];
+
+    let batch =
+        benchmark.slot_time(&config.rpc.network.params()) * config.scheduler.batch_size as u32;
+    let timeout = Duration::from_millis(config.scheduler.timeout_ms);
+    // The rest of the timeout is for fetching the proofs and the hooks
+    if batch * 2 > timeout {
+        warnings.push(format!(
+            "Proving a batch of {} takes about {:?} on this host, more than half of \
+             scheduler.timeout_ms, lower scheduler.batch_size",
+            config.scheduler.batch_size, batch
+        ));
+    }
+
+    let head = benchmark.head_time();
+    if head > BLOCK_TIME {
+        warnings.push(format!(
+            "Checking a head takes about {:?} on this host, longer than a block, sync will fall \
+             behind",
+            head
+        ));
+    }
+
+    if config.scheduler.provers > config.runtime.cpu_threads {
+        warnings.push(format!(
+            "Only {} of the {} scheduler.provers can run at once with runtime.cpu_threads",
+            config.runtime.cpu_threads, config.scheduler.provers
+        ));
+    }
+    warnings
+}
+
+/// Log what the host is, what it can prove and warn about what it likely
+/// can't.
+pub fn check(config: &Config) {
+    let features = Features::detect();
+    let paths = Paths::of(ARCH, &features);
+    log::info!(
+        "Host is {} with {:?}, hashing is {:?} and curve arithmetic {:?}",
+        ARCH,
+        features,
+        paths.hash,
+        paths.curve
+    );
+
+    let benchmark = Benchmark::run(BENCHMARK_BUDGET);
+    let provers = config
+        .scheduler
+        .provers
+        .min(config.runtime.cpu_threads)
+        .max(1);
+    let slot = benchmark.slot_time(&config.rpc.network.params());
+    log::info!(
+        "Expecting to prove about {:.0} slots/s with {} provers and check a head in {:?}",
+        provers as f64 / slot.as_secs_f64(),
+        provers,
+        benchmark.head_time()
+    );
+
+    for warning in warnings(config, &benchmark) {
+        log::warn!("{}", warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(batch_size: usize, timeout_ms: u64) -> Config {
+        serde_json::from_value(serde_json::json!({
+            "starting_head": "4zwZQzjQDpimeLK3tX39nzok6UjDU9edS57EFhkAa4Sk",
+            "catchup": false,
+            "scheduler": { "batch_size": batch_size, "timeout_ms": timeout_ms, "provers": 1 },
+            "runtime": { "cpu_threads": 1 },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_paths() {
+        let all = Features {
+            sha: true,
+            avx2: true,
+            neon: true,
+        };
+        assert_eq!(
+            Paths::of("x86_64", &all),
+            Paths {
+                hash: HashPath::ShaNi,
+                curve: CurvePath::Avx2
+            }
+        );
+        // Graviton has the SHA extension, but no vector backend for the curve
+        assert_eq!(
+            Paths::of("aarch64", &all),
+            Paths {
+                hash: HashPath::ArmSha2,
+                curve: CurvePath::Serial
+            }
+        );
+        assert_eq!(
+            Paths::of("x86_64", &Features::default()),
+            Paths {
+                hash: HashPath::Portable,
+                curve: CurvePath::Serial
+            }
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let params = NetworkParams::TESTNET;
+        let hashes = 1
+            + params.outcome_proof_depth
+            + params.outcome_root_proof_depth
+            + params.block_proof_depth;
+        // A slot a millisecond and a head half a block
+        let benchmark = Benchmark {
+            hashes_per_sec: hashes as f64 * 1000.0,
+            verifies_per_sec: NUM_BLOCK_PRODUCER_SEATS as f64 * 2.0,
+        };
+        assert!(warnings(&config(16, 60_000), &benchmark).is_empty());
+
+        let slow = warnings(&config(16, 30), &benchmark);
+        assert_eq!(slow.len(), 1);
+        assert!(slow[0].contains("batch of 16"));
+
+        let slow_heads = Benchmark {
+            verifies_per_sec: 1.0,
+            ..benchmark
+        };
+        assert_eq!(warnings(&config(16, 60_000), &slow_heads).len(), 1);
+    }
+
+    #[test]
+    fn test_benchmark() {
+        let benchmark = Benchmark::run(Duration::from_millis(10));
+        assert!(benchmark.hashes_per_sec > 0.0 && benchmark.verifies_per_sec > 0.0);
+        assert!(benchmark.slot_time(&NetworkParams::MAINNET) > Duration::ZERO);
+    }
+}
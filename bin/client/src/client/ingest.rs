@@ -0,0 +1,343 @@
+//! Follows the chain, enqueueing any receipts matching the rules so they are
+//! proven without a client needing to ask.
+//!
+//! Blocks are read from a NEAR Lake bucket, where the Lake indexer writes
+//! each block as `{height}/block.json` and its shards as
+//! `{height}/shard_{id}.json`, if one is configured. Otherwise we poll the
+//! RPC for each height and its chunks, which needs no credentials but costs
+//! a request per chunk.
+use std::{sync::Arc, time::Duration};
+
+use near_primitives::{
+    types::{BlockHeight, TransactionOrReceiptId},
+    views::{BlockView, ReceiptView},
+};
+use rpc::NearRpcClient;
+
+use super::{
+    queue::{Queue, Requester},
+    rules::Priority,
+    s3::Bucket,
+};
+use crate::{config::IngestConfig, prelude::*};
+
+/// Receipts we enqueue are accounted to this requester of the operator.
+const REQUESTER: &str = "ingest";
+
+/// The most heights a source returns at once.
+const MAX_HEIGHTS: usize = 1000;
+
+/// Where the blocks we ingest come from.
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    /// The heights with a final block from `from` on, in order and skipping
+    /// any height without a block.
+    async fn heights(&self, from: BlockHeight) -> Result<Vec<BlockHeight>>;
+
+    /// Every receipt in the chunks of the block at `height`, `None` if
+    /// there is no block.
+    async fn receipts(&self, height: BlockHeight) -> Result<Option<Vec<ReceiptView>>>;
+}
+
+#[async_trait]
+impl BlockSource for NearRpcClient {
+    async fn heights(&self, from: BlockHeight) -> Result<Vec<BlockHeight>> {
+        let to = self.fetch_final_block().await?.header.height;
+        // Skipped heights are only known once we ask for them
+        Ok((from..=to).take(MAX_HEIGHTS).collect())
+    }
+
+    async fn receipts(&self, height: BlockHeight) -> Result<Option<Vec<ReceiptView>>> {
+        let Some(block) = self.fetch_block_at(height).await? else {
+            log::debug!("No block at {}", height);
+            return Ok(None);
+        };
+        let futs = block
+            .chunks
+            .iter()
+            .map(|c| self.fetch_chunk(&c.chunk_hash))
+            .map(Box::pin)
+            .collect_vec();
+        let chunks = futures::future::try_join_all(futs).await?;
+        Ok(Some(chunks.into_iter().flat_map(|c| c.receipts).collect()))
+    }
+}
+
+/// A NEAR Lake bucket.
+pub struct Lake(Bucket);
+
+#[derive(Deserialize)]
+struct Shard {
+    chunk: Option<Chunk>,
+}
+
+#[derive(Deserialize)]
+struct Chunk {
+    receipts: Vec<ReceiptView>,
+}
+
+/// Lake keys are the height padded to 12 digits, so they list in order.
+fn lake_key(height: BlockHeight) -> String {
+    format!("{:012}", height)
+}
+
+#[async_trait]
+impl BlockSource for Lake {
+    async fn heights(&self, from: BlockHeight) -> Result<Vec<BlockHeight>> {
+        let start_after = from.checked_sub(1).map(lake_key).unwrap_or_default();
+        // Only heights with a block are written, so skipped ones never list
+        self.0
+            .list_prefixes(&start_after, MAX_HEIGHTS)
+            .await?
+            .iter()
+            .map(|prefix| {
+                prefix
+                    .parse()
+                    .map_err(|e| anyhow!("Unexpected Lake prefix {}: {:?}", prefix, e))
+            })
+            .collect()
+    }
+
+    async fn receipts(&self, height: BlockHeight) -> Result<Option<Vec<ReceiptView>>> {
+        let prefix = lake_key(height);
+        let Some(block) = self.0.get(&format!("{}/block.json", prefix)).await? else {
+            log::debug!("No block at {}", height);
+            return Ok(None);
+        };
+        let block: BlockView = serde_json::from_slice(&block)?;
+
+        let futs = block
+            .chunks
+            .iter()
+            .map(|c| async move {
+                let key = format!("{}/shard_{}.json", prefix, c.shard_id);
+                // The block is written last, so its shards should all exist
+                let shard = self
+                    .0
+                    .get(&key)
+                    .await?
+                    .ok_or_else(|| anyhow!("Missing Lake shard {}", key))?;
+                Ok::<_, anyhow::Error>(serde_json::from_slice::<Shard>(&shard)?)
+            })
+            .map(Box::pin)
+            .collect_vec();
+        let shards = futures::future::try_join_all(futs).await?;
+        Ok(Some(
+            shards
+                .into_iter()
+                .filter_map(|s| s.chunk)
+                .flat_map(|c| c.receipts)
+                .collect(),
+        ))
+    }
+}
+
+pub struct Ingester {
+    config: IngestConfig,
+    client: NearRpcClient,
+    source: Box<dyn BlockSource>,
+    queue: Arc<Queue>,
+}
+
+impl Ingester {
+    pub fn new(config: IngestConfig, client: NearRpcClient, queue: Arc<Queue>) -> Result<Self> {
+        let source: Box<dyn BlockSource> = match &config.lake {
+            Some(lake) => Box::new(Lake(Bucket::new(&lake.s3(), true)?)),
+            None => Box::new(client.clone()),
+        };
+        Ok(Self {
+            config,
+            client,
+            source,
+            queue,
+        })
+    }
+
+    pub async fn start(self) {
+        let interval = Duration::from_millis(self.config.poll_interval_ms);
+        let mut next_height = self.config.start_height;
+        loop {
+            if let Err(e) = self.ingest(&mut next_height).await {
+                log::error!("Error ingesting from {:?}: {:?}", next_height, e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Ingest from `next` up to the latest block the source has. `next`
+    /// moves past each height once its receipts are enqueued, so after an
+    /// error the retry starts at the height that failed rather than
+    /// enqueueing the ones before it again.
+    async fn ingest(&self, next: &mut Option<BlockHeight>) -> Result<()> {
+        if next.is_none() {
+            *next = Some(self.client.fetch_final_block().await?.header.height);
+        }
+
+        while let Some(from) = *next {
+            let heights = self.source.heights(from).await?;
+            if heights.is_empty() {
+                break;
+            }
+            for height in heights {
+                let matched = self.matched_receipts(height).await?;
+                if !matched.is_empty() {
+                    log::info!("Ingested {} receipts at {}", matched.len(), height);
+                    self.queue
+                        .extend(matched, &Requester::operator(REQUESTER))
+                        .await;
+                }
+                *next = Some(height + 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate the rules over a historical range without enqueueing
+    /// anything, returning what would have been proven.
+    pub async fn dry_run(
+        &self,
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> Result<Vec<(BlockHeight, Priority, TransactionOrReceiptId)>> {
+        let mut matched = vec![];
+        let mut from = from;
+        while from <= to {
+            let heights = self.source.heights(from).await?;
+            let Some(last) = heights.last().copied() else {
+                break;
+            };
+            for height in heights.into_iter().take_while(|h| *h <= to) {
+                matched.extend(
+                    self.matched_receipts(height)
+                        .await?
+                        .into_iter()
+                        .map(|(priority, id)| (height, priority, id)),
+                );
+            }
+            from = last + 1;
+        }
+        Ok(matched)
+    }
+
+    async fn matched_receipts(
+        &self,
+        height: BlockHeight,
+    ) -> Result<Vec<(Priority, TransactionOrReceiptId)>> {
+        let receipts = self.source.receipts(height).await?.unwrap_or_default();
+        Ok(receipts
+            .into_iter()
+            .filter_map(|r| {
+                let priority = self.config.rules.evaluate(&r)?;
+                Some((
+                    priority,
+                    TransactionOrReceiptId::Receipt {
+                        receipt_id: r.receipt_id,
+                        receiver_id: r.receiver_id,
+                    },
+                ))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use near_primitives::hash::CryptoHash;
+
+    use super::*;
+    use crate::client::rules::{Rule, Rules};
+
+    /// Blocks at the given heights, failing once at `fail`.
+    struct Fake {
+        blocks: BTreeMap<BlockHeight, Vec<ReceiptView>>,
+        fail: std::sync::Mutex<Option<BlockHeight>>,
+    }
+
+    #[async_trait]
+    impl BlockSource for Fake {
+        async fn heights(&self, from: BlockHeight) -> Result<Vec<BlockHeight>> {
+            Ok(self.blocks.range(from..).map(|(h, _)| *h).take(2).collect())
+        }
+
+        async fn receipts(&self, height: BlockHeight) -> Result<Option<Vec<ReceiptView>>> {
+            let mut fail = self.fail.lock().unwrap();
+            if *fail == Some(height) {
+                *fail = None;
+                anyhow::bail!("Unavailable");
+            }
+            Ok(self.blocks.get(&height).cloned())
+        }
+    }
+
+    fn receipt(n: u8) -> ReceiptView {
+        serde_json::from_value(serde_json::json!({
+            "predecessor_id": "alice.near",
+            "receiver_id": "bridge.near",
+            "receipt_id": CryptoHash([n; 32]),
+            "receipt": {
+                "Data": {
+                    "data_id": CryptoHash::default(),
+                    "data": null,
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn ingester(fail: Option<BlockHeight>) -> Ingester {
+        let blocks = [(10, receipt(1)), (12, receipt(2)), (13, receipt(3))]
+            .into_iter()
+            .map(|(h, r)| (h, vec![r]))
+            .collect();
+        Ingester {
+            config: IngestConfig {
+                rules: Rules(vec![Rule {
+                    receiver: "*.near".into(),
+                    predecessor: None,
+                    methods: vec![],
+                    min_deposit: 0,
+                    priority: 1,
+                }]),
+                start_height: Some(10),
+                poll_interval_ms: 0,
+                lake: None,
+            },
+            client: NearRpcClient::new(rpc::Network::Testnet),
+            source: Box::new(Fake {
+                blocks,
+                fail: fail.into(),
+            }),
+            queue: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ingest_resumes_at_failed_height() {
+        let ingester = ingester(Some(12));
+        let mut next = Some(10);
+
+        assert!(ingester.ingest(&mut next).await.is_err());
+        assert_eq!(next, Some(11));
+        assert_eq!(ingester.queue.len().await, 1);
+
+        ingester.ingest(&mut next).await.unwrap();
+        assert_eq!(next, Some(14));
+        assert_eq!(ingester.queue.len().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_stops_at_to() {
+        let matched = ingester(None).dry_run(11, 12).await.unwrap();
+        assert_eq!(
+            matched.iter().map(|(h, p, _)| (*h, *p)).collect_vec(),
+            vec![(12, 1)]
+        );
+    }
+
+    #[test]
+    fn test_lake_key() {
+        assert_eq!(lake_key(9820210), "000009820210");
+    }
+}
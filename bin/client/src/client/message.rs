@@ -1,7 +1,22 @@
+use std::collections::HashMap;
+
 use coerce::actor::message::Message;
-use near_primitives::types::TransactionOrReceiptId;
+use near_primitives::types::{BlockHeight, TransactionOrReceiptId};
 use protocol::{experimental::Proof as ExperimentalProof, Proof};
+use tokio::sync::{broadcast, oneshot};
 
+use super::{
+    audit::AuditHead,
+    batches::{BatchStatus, EnqueuedBatch, Subscription},
+    canary::{CanaryStatus, Comparison},
+    failure::RecentError,
+    heads::HeadEvent,
+    queue::{CancelError, Delivery, EnqueueError, QueuedRequest},
+    rules::Priority,
+    staleness::Freshness,
+    store::{Anchor, CircuitSnapshot, EpochBps, Progress, Relay},
+    tenant::{Tenant, TenantError},
+};
 use crate::prelude::*;
 
 pub struct Shutdown;
@@ -16,6 +31,194 @@ impl Message for Head {
     type Result = Option<Header>;
 }
 
+/// The tenant an API key belongs to.
+pub struct Authenticate {
+    pub api_key: Option<String>,
+}
+
+impl Message for Authenticate {
+    type Result = Result<Tenant, TenantError>;
+}
+
+pub struct Pending {
+    pub tenant: Tenant,
+}
+
+impl Message for Pending {
+    type Result = Vec<TransactionOrReceiptId>;
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Enqueue {
+    pub id: TransactionOrReceiptId,
+    /// Unique within the tenant.
+    pub requester: String,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Set from the API key, never from the request.
+    #[serde(skip)]
+    pub tenant: Tenant,
+}
+
+impl Message for Enqueue {
+    type Result = Result<oneshot::Receiver<Delivery>, EnqueueError>;
+}
+
+/// Withdraw a request that is still pending.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CancelRequest {
+    pub id: TransactionOrReceiptId,
+    pub requester: String,
+    /// Set from the API key, never from the request.
+    #[serde(skip)]
+    pub tenant: Tenant,
+}
+
+impl Message for CancelRequest {
+    type Result = Result<(), CancelError>;
+}
+
+/// The tenant's requests in the queue, with where they are in it.
+pub struct GetRequests {
+    pub tenant: Tenant,
+}
+
+impl Message for GetRequests {
+    type Result = Vec<QueuedRequest>;
+}
+
+/// Queue a batch of requests to follow as one, see `batches`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EnqueueBatch {
+    pub ids: Vec<TransactionOrReceiptId>,
+    /// Unique within the tenant.
+    pub requester: String,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Set from the API key, never from the request.
+    #[serde(skip)]
+    pub tenant: Tenant,
+}
+
+impl Message for EnqueueBatch {
+    type Result = Result<EnqueuedBatch, EnqueueError>;
+}
+
+/// The events of one of a tenant's batches.
+pub struct SubscribeBatch {
+    pub batch_id: CryptoHash,
+    pub tenant: Tenant,
+}
+
+impl Message for SubscribeBatch {
+    type Result = Option<Subscription>;
+}
+
+/// Where one of a tenant's batches is.
+pub struct GetBatch {
+    pub batch_id: CryptoHash,
+    pub tenant: Tenant,
+}
+
+impl Message for GetBatch {
+    type Result = Option<BatchStatus>;
+}
+
+/// What each of a tenant's requesters has been charged.
+pub struct Costs {
+    pub tenant: Tenant,
+}
+
+impl Message for Costs {
+    type Result = HashMap<String, u64>;
+}
+
+/// The sync a proof's root is anchored to.
+pub struct GetAnchor {
+    pub root: CryptoHash,
+}
+
+impl Message for GetAnchor {
+    type Result = Option<Anchor>;
+}
+
+/// Record where the sync for an anchor was accepted on the destination chain.
+pub struct RecordRelay {
+    pub root: CryptoHash,
+    pub relay: Relay,
+}
+
+impl Message for RecordRelay {
+    type Result = Result<Anchor>;
+}
+
+/// The latest relayed anchor at or below a NEAR height.
+pub struct AnchorAt {
+    pub height: BlockHeight,
+}
+
+impl Message for AnchorAt {
+    type Result = Result<Option<Anchor>>;
+}
+
+/// Failure counters in the prometheus text format.
+pub struct Metrics;
+
+impl Message for Metrics {
+    type Result = String;
+}
+
+/// A sync output from the shadow build, compared against the active build's
+/// head for the same epoch.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShadowOutput {
+    pub epoch_id: CryptoHash,
+    pub new_head: CryptoHash,
+}
+
+impl Message for ShadowOutput {
+    type Result = Result<Comparison>;
+}
+
+pub struct GetCanaryStatus;
+
+impl Message for GetCanaryStatus {
+    type Result = Option<CanaryStatus>;
+}
+
+/// How fresh the head is compared to local time.
+pub struct CheckHead;
+
+impl Message for CheckHead {
+    type Result = Result<Freshness>;
+}
+
+/// The most recent failures, newest first.
+pub struct RecentErrors;
+
+impl Message for RecentErrors {
+    type Result = Vec<RecentError>;
+}
+
+pub struct GetAuditHead;
+
+impl Message for GetAuditHead {
+    type Result = AuditHead;
+}
+
+/// How far each pipeline has got, see `store::Pipeline`.
+pub struct GetProgress;
+
+impl Message for GetProgress {
+    type Result = Progress;
+}
+
+pub struct SubscribeHeads;
+
+impl Message for SubscribeHeads {
+    type Result = broadcast::Receiver<HeadEvent>;
+}
+
 pub struct Archive {
     pub epoch: CryptoHash,
 }
@@ -24,6 +227,24 @@ impl Message for Archive {
     type Result = Option<Header>;
 }
 
+/// The pre-image of the BPS hash for an epoch.
+pub struct GetEpochBps {
+    pub epoch_id: CryptoHash,
+}
+
+impl Message for GetEpochBps {
+    type Result = Option<EpochBps>;
+}
+
+/// The circuits an epoch was first proven with.
+pub struct GetCircuitSnapshot {
+    pub epoch_id: CryptoHash,
+}
+
+impl Message for GetCircuitSnapshot {
+    type Result = Option<CircuitSnapshot>;
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetProof(pub TransactionOrReceiptId);
 
@@ -35,7 +256,31 @@ impl Message for GetProof {
 pub struct BatchGetProof(pub Vec<GetProof>);
 
 impl Message for BatchGetProof {
-    type Result = Option<ExperimentalProof>;
+    type Result = Result<ExperimentalProof>;
+}
+
+/// Fetch everything needed to prove a batch against the latest head and
+/// persist it, resolving to the key of the prepared batch. Slots the head
+/// doesn't cover are requeued, there is no batch if none are left.
+pub struct PrepareBatch(pub Vec<TransactionOrReceiptId>);
+
+impl Message for PrepareBatch {
+    type Result = Result<Option<CryptoHash>>;
+}
+
+/// Prove against a past head from the root registry rather than the latest,
+/// for consumers that pin an older anchor.
+pub struct ProveAt<T> {
+    pub request: T,
+    pub head: Option<CryptoHash>,
+}
+
+impl Message for ProveAt<GetProof> {
+    type Result = Result<Option<Proof>>;
+}
+
+impl Message for ProveAt<BatchGetProof> {
+    type Result = Result<ExperimentalProof>;
 }
 
 pub struct VerifyProof {
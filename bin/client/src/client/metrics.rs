@@ -0,0 +1,186 @@
+//! Metrics for alerting on the prover and sync, rendered alongside those the
+//! other parts of the client keep in the prometheus text format.
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use rpc::histogram::{Histogram, Snapshot};
+
+/// Buckets for proving a batch, in milliseconds.
+const PROVING_BUCKETS_MS: &[u64] = &[
+    100, 500, 1_000, 5_000, 10_000, 30_000, 60_000, 120_000, 300_000, 600_000,
+];
+
+/// The batches we proved and how long they took.
+#[derive(Debug)]
+pub struct ProvingMetrics {
+    proofs: AtomicU64,
+    slots: AtomicU64,
+    proving: Histogram,
+}
+
+impl Default for ProvingMetrics {
+    fn default() -> Self {
+        Self {
+            proofs: Default::default(),
+            slots: Default::default(),
+            proving: Histogram::new(PROVING_BUCKETS_MS),
+        }
+    }
+}
+
+impl ProvingMetrics {
+    /// A batch of `slots` was proven in `elapsed`.
+    pub fn record(&self, slots: usize, elapsed: Duration) {
+        self.proofs.fetch_add(1, Ordering::Relaxed);
+        self.slots.fetch_add(slots as u64, Ordering::Relaxed);
+        self.proving.observe(elapsed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP light_client_proofs_total Batch proofs generated\n");
+        out.push_str("# TYPE light_client_proofs_total counter\n");
+        out.push_str(&format!(
+            "light_client_proofs_total {}\n",
+            self.proofs.load(Ordering::Relaxed)
+        ));
+        out.push_str(
+            "# HELP light_client_proven_slots_total Slots in the batch proofs generated\n",
+        );
+        out.push_str("# TYPE light_client_proven_slots_total counter\n");
+        out.push_str(&format!(
+            "light_client_proven_slots_total {}\n",
+            self.slots.load(Ordering::Relaxed)
+        ));
+        out.push_str(&render_histogram(
+            "light_client_proving_seconds",
+            "How long proving a batch took",
+            [(String::new(), self.proving.snapshot())],
+        ));
+        out
+    }
+}
+
+/// Render histograms of the same metric, each with its labels, such as
+/// `endpoint="rpc"`.
+pub fn render_histogram(
+    name: &str,
+    help: &str,
+    series: impl IntoIterator<Item = (String, Snapshot)>,
+) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("# HELP {} {}\n", name, help));
+    out.push_str(&format!("# TYPE {} histogram\n", name));
+    for (labels, snapshot) in series {
+        let prefix = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{},", labels)
+        };
+        let bounds = snapshot
+            .bounds_ms
+            .iter()
+            .map(|ms| format!("{}", *ms as f64 / 1000.0))
+            .chain(["+Inf".to_string()]);
+        let mut cumulative = 0;
+        for (le, count) in bounds.zip(&snapshot.counts) {
+            cumulative += count;
+            out.push_str(&format!(
+                "{}_bucket{{{}le=\"{}\"}} {}\n",
+                name, prefix, le, cumulative
+            ));
+        }
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        out.push_str(&format!(
+            "{}_sum{} {}\n",
+            name,
+            labels,
+            snapshot.sum_us as f64 / 1_000_000.0
+        ));
+        out.push_str(&format!("{}_count{} {}\n", name, labels, cumulative));
+    }
+    out
+}
+
+/// Render where sync is and how much is queued, so a sync that stalled or a
+/// queue that backs up can be alerted on.
+pub fn render_progress(head: Option<(u64, u64)>, pending: usize, in_flight: usize) -> String {
+    let mut out = String::new();
+    if let Some((height, timestamp_ns)) = head {
+        out.push_str("# HELP light_client_head_height Height of the head we synced to\n");
+        out.push_str("# TYPE light_client_head_height gauge\n");
+        out.push_str(&format!("light_client_head_height {}\n", height));
+        out.push_str(
+            "# HELP light_client_head_timestamp_seconds When the head we synced to was produced\n",
+        );
+        out.push_str("# TYPE light_client_head_timestamp_seconds gauge\n");
+        out.push_str(&format!(
+            "light_client_head_timestamp_seconds {}\n",
+            timestamp_ns / 1_000_000_000
+        ));
+    }
+    out.push_str("# HELP light_client_queue_depth Slots queued to be proven\n");
+    out.push_str("# TYPE light_client_queue_depth gauge\n");
+    for (state, count) in [("pending", pending), ("in_flight", in_flight)] {
+        out.push_str(&format!(
+            "light_client_queue_depth{{state=\"{}\"}} {}\n",
+            state, count
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram() {
+        let histogram = Histogram::new(&[100, 1_000]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_millis(2_000));
+        let out = render_histogram(
+            "latency_seconds",
+            "Latency",
+            [("endpoint=\"rpc\"".to_string(), histogram.snapshot())],
+        );
+        assert_eq!(
+            out,
+            "# HELP latency_seconds Latency\n# TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{endpoint=\"rpc\",le=\"0.1\"} 1\n\
+             latency_seconds_bucket{endpoint=\"rpc\",le=\"1\"} 2\n\
+             latency_seconds_bucket{endpoint=\"rpc\",le=\"+Inf\"} 3\n\
+             latency_seconds_sum{endpoint=\"rpc\"} 2.55\n\
+             latency_seconds_count{endpoint=\"rpc\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_proving_metrics() {
+        let metrics = ProvingMetrics::default();
+        metrics.record(3, Duration::from_secs(2));
+        metrics.record(1, Duration::from_secs(20));
+        let out = metrics.render();
+        assert!(out.contains("light_client_proofs_total 2\n"));
+        assert!(out.contains("light_client_proven_slots_total 4\n"));
+        assert!(out.contains("light_client_proving_seconds_bucket{le=\"5\"} 1\n"));
+        assert!(out.contains("light_client_proving_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn test_render_progress() {
+        let out = render_progress(Some((100, 1_700_000_000_500_000_000)), 3, 1);
+        assert!(out.contains("light_client_head_height 100\n"));
+        assert!(out.contains("light_client_head_timestamp_seconds 1700000000\n"));
+        assert!(out.contains("light_client_queue_depth{state=\"in_flight\"} 1\n"));
+        // Before the store has a head there is nothing to report
+        assert!(!render_progress(None, 0, 0).contains("head_height"));
+    }
+}
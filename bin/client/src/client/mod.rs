@@ -1,39 +1,169 @@
-use std::{str::FromStr, sync::Arc};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
 
+use anyhow::Context;
 use coerce::actor::{context::ActorContext, message::Handler, Actor};
-use message::{Archive, GetProof, Head, Shutdown, VerifyProof};
-use near_primitives::views::validator_stake_view::ValidatorStakeView;
-use protocol::{Proof, Protocol};
+use message::{
+    AnchorAt, Archive, Authenticate, CancelRequest, CheckHead, Costs, Enqueue, EnqueueBatch,
+    GetAnchor, GetAuditHead, GetBatch, GetCanaryStatus, GetCircuitSnapshot, GetEpochBps,
+    GetProgress, GetProof, GetRequests, Head, Metrics, Pending, PrepareBatch, ProveAt,
+    RecentErrors, RecordRelay, ShadowOutput, Shutdown, SubscribeBatch, SubscribeHeads, VerifyProof,
+};
+use near_primitives::{
+    types::TransactionOrReceiptId,
+    views::{validator_stake_view::ValidatorStakeView, LightClientBlockView},
+};
+use protocol::{timestamp::Timestamp, Proof, Protocol};
 use rpc::LightClientRpc;
 use tokio::time;
 
-use self::{message::BatchGetProof, store::Store};
+use self::{
+    audit::{Action, AuditLog},
+    batches::Batches,
+    block_tree::BlockTree,
+    canary::{Canary, Comparison},
+    compat::{EthCall, VerifierContract},
+    da::{Publish, Publisher},
+    failure::{Failure, FailureCounters, FailureReason},
+    finality::Finality,
+    head_store::Checkpointer,
+    heads::{HeadEvent, HeadFeed},
+    hooks::Hooks,
+    ingest::Ingester,
+    message::BatchGetProof,
+    metrics::ProvingMetrics,
+    queue::{Queue, Requester},
+    runtime::{CpuPool, RuntimeHealth},
+    scheduler::{Ledger, Scheduler},
+    selection::Selector,
+    staleness::Staleness,
+    store::Store,
+};
 use crate::{
-    client::store::{head_key, Collection, Entity},
+    build_info::BuildInfo,
+    client::store::{
+        head_key, Anchor, CircuitSnapshot, Collection, Entity, Pipeline, PreparedBatch, Relay,
+    },
     config::Config,
     prelude::*,
 };
 
+pub mod audit;
+pub mod batches;
+pub mod block_tree;
+pub mod canary;
+pub mod compat;
+pub mod da;
+pub mod failure;
+pub mod finality;
+pub mod head_store;
+pub mod heads;
+pub mod hooks;
+pub mod host;
+pub mod ingest;
 pub mod message;
-mod store;
+pub mod metrics;
+pub mod queue;
+pub mod rules;
+pub mod runtime;
+pub mod s3;
+mod scheduler;
+pub mod selection;
+pub mod staleness;
+pub mod store;
+pub mod tenant;
 
 pub struct LightClient {
     config: Config,
     client: rpc::NearRpcClient,
     store: Arc<Store<store::sled::Store>>,
+    queue: Arc<Queue>,
+    batches: Arc<Batches>,
+    ledger: Arc<Ledger>,
+    selector: Arc<Selector>,
+    failures: Arc<FailureCounters>,
+    canary: Option<Canary>,
+    /// Relays are checked against its function id registry.
+    verifier: Option<Arc<dyn VerifierContract>>,
+    heads: HeadFeed,
+    staleness: Staleness,
+    finality: Arc<Finality>,
+    audit: Arc<AuditLog>,
+    cpu: CpuPool,
+    runtime: Arc<RuntimeHealth>,
+    proving: Arc<ProvingMetrics>,
+    block_tree: Option<Arc<BlockTree>>,
+    hooks: Arc<Hooks>,
+    da: Option<Arc<Publisher>>,
+    head_store: Option<Arc<Checkpointer>>,
+    ingester: Option<Ingester>,
 }
 
 #[async_trait]
 impl Actor for LightClient {
-    async fn started(&mut self, _ctx: &mut ActorContext) {
+    async fn started(&mut self, ctx: &mut ActorContext) {
         self.bootstrap_store()
             .await
             .expect("Failed to bootstrap store");
+        match self.queue.restore().await {
+            Ok(0) => {}
+            Ok(n) => log::info!("Restored {} queued requests", n),
+            Err(e) => log::error!("Failed to restore the queue: {:?}", e),
+        }
+        match self.ledger.restore().await {
+            Ok(0) => {}
+            Ok(n) => log::info!("Restored charges of {} requesters", n),
+            Err(e) => log::error!("Failed to restore the ledger: {:?}", e),
+        }
         // TODO: anonymous ctx.spawn(id, actor)
         let catchup = self.config.catchup;
         let store = self.store.clone();
         let client = self.client.clone();
-        tokio::task::spawn(async move { Self::start_syncing(catchup, store, client).await });
+        let heads = self.heads.clone();
+        let cpu = self.cpu.clone();
+        let failures = self.failures.clone();
+        let queue = self.queue.clone();
+        let selector = self.selector.clone();
+        let finality = self.finality.clone();
+        tokio::task::spawn(async move {
+            Self::start_syncing(
+                catchup, store, client, heads, cpu, failures, queue, selector, finality,
+            )
+            .await
+        });
+        tokio::task::spawn(self.runtime.clone().start());
+        if let Some(block_tree) = &self.block_tree {
+            tokio::task::spawn(block_tree.clone().start());
+        }
+
+        if let Some(ingester) = self.ingester.take() {
+            tokio::task::spawn(ingester.start());
+        }
+
+        if let Some(da) = &self.da {
+            tokio::task::spawn(da.clone().start(self.heads.subscribe()));
+        }
+
+        if let Some(head_store) = &self.head_store {
+            tokio::task::spawn(
+                head_store
+                    .clone()
+                    .start(self.store.clone(), self.heads.subscribe()),
+            );
+        }
+
+        let scheduler = Scheduler::new(
+            self.config.scheduler.clone(),
+            self.queue.clone(),
+            self.ledger.clone(),
+            self.failures.clone(),
+            self.audit.clone(),
+            self.store.clone(),
+            self.cpu.clone(),
+            self.hooks.clone(),
+            self.proving.clone(),
+            ctx.actor_ref::<Self>(),
+        );
+        tokio::task::spawn(scheduler.start());
     }
 }
 
@@ -48,6 +178,249 @@ impl Handler<Head> for LightClient {
     }
 }
 
+#[async_trait]
+impl Handler<Pending> for LightClient {
+    async fn handle(
+        &mut self,
+        message: Pending,
+        _ctx: &mut ActorContext,
+    ) -> <Pending as coerce::actor::message::Message>::Result {
+        self.queue.pending(&message.tenant).await
+    }
+}
+
+#[async_trait]
+impl Handler<Authenticate> for LightClient {
+    async fn handle(
+        &mut self,
+        message: Authenticate,
+        _ctx: &mut ActorContext,
+    ) -> <Authenticate as coerce::actor::message::Message>::Result {
+        self.config.tenants.authenticate(message.api_key.as_deref())
+    }
+}
+
+#[async_trait]
+impl Handler<Enqueue> for LightClient {
+    async fn handle(
+        &mut self,
+        message: Enqueue,
+        _ctx: &mut ActorContext,
+    ) -> <Enqueue as coerce::actor::message::Message>::Result {
+        let spent = self.ledger.spent(&message.tenant).await;
+        self.config.tenants.check_quota(&message.tenant, spent)?;
+        if self.config.scheduler.prevalidate {
+            self.client.check_provable(message.id.clone()).await?;
+        }
+        let requester = Requester::new(&message.tenant, &message.requester);
+        Ok(self
+            .queue
+            .enqueue(message.priority, message.id, requester)
+            .await)
+    }
+}
+
+#[async_trait]
+impl Handler<EnqueueBatch> for LightClient {
+    async fn handle(
+        &mut self,
+        message: EnqueueBatch,
+        _ctx: &mut ActorContext,
+    ) -> <EnqueueBatch as coerce::actor::message::Message>::Result {
+        let spent = self.ledger.spent(&message.tenant).await;
+        self.config.tenants.check_quota(&message.tenant, spent)?;
+        // Turn the whole batch away before any of it is queued
+        if self.config.scheduler.prevalidate {
+            for id in &message.ids {
+                self.client.check_provable(id.clone()).await?;
+            }
+        }
+        let requester = Requester::new(&message.tenant, &message.requester);
+        let mut requests = vec![];
+        for id in message.ids {
+            let rx = self
+                .queue
+                .enqueue(message.priority, id.clone(), requester.clone())
+                .await;
+            requests.push((id, rx));
+        }
+        Ok(self.batches.track(message.tenant, requests).await)
+    }
+}
+
+#[async_trait]
+impl Handler<GetCircuitSnapshot> for LightClient {
+    async fn handle(
+        &mut self,
+        message: GetCircuitSnapshot,
+        _ctx: &mut ActorContext,
+    ) -> <GetCircuitSnapshot as coerce::actor::message::Message>::Result {
+        self.store.circuit_snapshot(&message.epoch_id).await
+    }
+}
+
+#[async_trait]
+impl Handler<CancelRequest> for LightClient {
+    async fn handle(
+        &mut self,
+        message: CancelRequest,
+        _ctx: &mut ActorContext,
+    ) -> <CancelRequest as coerce::actor::message::Message>::Result {
+        let requester = Requester::new(&message.tenant, &message.requester);
+        self.queue.cancel(&message.id, &requester).await
+    }
+}
+
+#[async_trait]
+impl Handler<GetRequests> for LightClient {
+    async fn handle(
+        &mut self,
+        message: GetRequests,
+        _ctx: &mut ActorContext,
+    ) -> <GetRequests as coerce::actor::message::Message>::Result {
+        self.queue.requests(&message.tenant).await
+    }
+}
+
+#[async_trait]
+impl Handler<SubscribeBatch> for LightClient {
+    async fn handle(
+        &mut self,
+        message: SubscribeBatch,
+        _ctx: &mut ActorContext,
+    ) -> <SubscribeBatch as coerce::actor::message::Message>::Result {
+        self.batches
+            .subscribe(&message.batch_id, &message.tenant)
+            .await
+    }
+}
+
+#[async_trait]
+impl Handler<GetBatch> for LightClient {
+    async fn handle(
+        &mut self,
+        message: GetBatch,
+        _ctx: &mut ActorContext,
+    ) -> <GetBatch as coerce::actor::message::Message>::Result {
+        self.batches
+            .status(&message.batch_id, &message.tenant)
+            .await
+    }
+}
+
+#[async_trait]
+impl Handler<Costs> for LightClient {
+    async fn handle(
+        &mut self,
+        message: Costs,
+        _ctx: &mut ActorContext,
+    ) -> <Costs as coerce::actor::message::Message>::Result {
+        self.ledger.costs(&message.tenant).await
+    }
+}
+
+#[async_trait]
+impl Handler<GetAnchor> for LightClient {
+    async fn handle(
+        &mut self,
+        message: GetAnchor,
+        _ctx: &mut ActorContext,
+    ) -> <GetAnchor as coerce::actor::message::Message>::Result {
+        self.get_anchor(&message.root).await
+    }
+}
+
+#[async_trait]
+impl Handler<RecordRelay> for LightClient {
+    async fn handle(
+        &mut self,
+        message: RecordRelay,
+        _ctx: &mut ActorContext,
+    ) -> <RecordRelay as coerce::actor::message::Message>::Result {
+        self.record_relay(&message.root, message.relay).await
+    }
+}
+
+#[async_trait]
+impl Handler<ShadowOutput> for LightClient {
+    async fn handle(
+        &mut self,
+        message: ShadowOutput,
+        _ctx: &mut ActorContext,
+    ) -> <ShadowOutput as coerce::actor::message::Message>::Result {
+        self.compare_shadow(message).await
+    }
+}
+
+#[async_trait]
+impl Handler<GetCanaryStatus> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: GetCanaryStatus,
+        _ctx: &mut ActorContext,
+    ) -> <GetCanaryStatus as coerce::actor::message::Message>::Result {
+        match &self.canary {
+            Some(canary) => Some(canary.status().await),
+            None => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<RecentErrors> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: RecentErrors,
+        _ctx: &mut ActorContext,
+    ) -> <RecentErrors as coerce::actor::message::Message>::Result {
+        self.failures.recent()
+    }
+}
+
+#[async_trait]
+impl Handler<GetAuditHead> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: GetAuditHead,
+        _ctx: &mut ActorContext,
+    ) -> <GetAuditHead as coerce::actor::message::Message>::Result {
+        self.audit.head().await
+    }
+}
+
+#[async_trait]
+impl Handler<GetProgress> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: GetProgress,
+        _ctx: &mut ActorContext,
+    ) -> <GetProgress as coerce::actor::message::Message>::Result {
+        self.store.progress().await
+    }
+}
+
+#[async_trait]
+impl Handler<SubscribeHeads> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: SubscribeHeads,
+        _ctx: &mut ActorContext,
+    ) -> <SubscribeHeads as coerce::actor::message::Message>::Result {
+        self.heads.subscribe()
+    }
+}
+
+#[async_trait]
+impl Handler<AnchorAt> for LightClient {
+    async fn handle(
+        &mut self,
+        message: AnchorAt,
+        _ctx: &mut ActorContext,
+    ) -> <AnchorAt as coerce::actor::message::Message>::Result {
+        self.store.anchor_at(message.height).await
+    }
+}
+
 #[async_trait]
 impl Handler<Archive> for LightClient {
     async fn handle(
@@ -59,6 +432,17 @@ impl Handler<Archive> for LightClient {
     }
 }
 
+#[async_trait]
+impl Handler<GetEpochBps> for LightClient {
+    async fn handle(
+        &mut self,
+        message: GetEpochBps,
+        _ctx: &mut ActorContext,
+    ) -> <GetEpochBps as coerce::actor::message::Message>::Result {
+        self.store.epoch_bps(&message.epoch_id).await
+    }
+}
+
 #[async_trait]
 impl Handler<Shutdown> for LightClient {
     async fn handle(
@@ -66,6 +450,11 @@ impl Handler<Shutdown> for LightClient {
         _message: Shutdown,
         ctx: &mut ActorContext,
     ) -> <Shutdown as coerce::actor::message::Message>::Result {
+        if let Some(head_store) = &self.head_store {
+            if let Err(e) = head_store.save(&self.store).await {
+                log::error!("Failed to checkpoint on shutdown: {:?}", e);
+            }
+        }
         self.store.shutdown().await;
         ctx.stop(None);
         Ok(())
@@ -79,7 +468,7 @@ impl Handler<GetProof> for LightClient {
         message: GetProof,
         _ctx: &mut ActorContext,
     ) -> <GetProof as coerce::actor::message::Message>::Result {
-        self.get_proofs(BatchGetProof(vec![message]))
+        self.get_proofs(BatchGetProof(vec![message]), None)
             .await
             .ok()
             .and_then(|proofs| proofs.into_iter().next())
@@ -95,6 +484,7 @@ impl Handler<VerifyProof> for LightClient {
     ) -> <VerifyProof as coerce::actor::message::Message>::Result {
         self.verify_proof(message.proof).await.map_err(|e| {
             log::error!("{:?}", e);
+            self.failures.record_failure(None, &Failure::from(&e));
             e
         })
     }
@@ -107,29 +497,171 @@ impl Handler<BatchGetProof> for LightClient {
         message: BatchGetProof,
         _ctx: &mut ActorContext,
     ) -> <BatchGetProof as coerce::actor::message::Message>::Result {
-        self.experimental_get_proofs(message).await.ok()
+        self.experimental_get_proofs(message, None).await
+    }
+}
+
+#[async_trait]
+impl Handler<PrepareBatch> for LightClient {
+    async fn handle(
+        &mut self,
+        message: PrepareBatch,
+        _ctx: &mut ActorContext,
+    ) -> <PrepareBatch as coerce::actor::message::Message>::Result {
+        self.prepare_batch(message.0).await
+    }
+}
+
+#[async_trait]
+impl Handler<ProveAt<GetProof>> for LightClient {
+    async fn handle(
+        &mut self,
+        message: ProveAt<GetProof>,
+        _ctx: &mut ActorContext,
+    ) -> <ProveAt<GetProof> as coerce::actor::message::Message>::Result {
+        self.get_proofs(BatchGetProof(vec![message.request]), message.head)
+            .await
+            .map(|proofs| proofs.into_iter().next())
+    }
+}
+
+#[async_trait]
+impl Handler<ProveAt<BatchGetProof>> for LightClient {
+    async fn handle(
+        &mut self,
+        message: ProveAt<BatchGetProof>,
+        _ctx: &mut ActorContext,
+    ) -> <ProveAt<BatchGetProof> as coerce::actor::message::Message>::Result {
+        self.experimental_get_proofs(message.request, message.head)
+            .await
+    }
+}
+
+#[async_trait]
+impl Handler<Metrics> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: Metrics,
+        _ctx: &mut ActorContext,
+    ) -> <Metrics as coerce::actor::message::Message>::Result {
+        let head = self.store.head().await.ok().map(|head| {
+            (
+                head.inner_lite.height,
+                Timestamp::from(&head.inner_lite).as_nanos(),
+            )
+        });
+        self.failures.render()
+            + &self.proving.render()
+            + &metrics::render_progress(head, self.queue.len().await, self.queue.in_flight().await)
+            + &self.da.as_ref().map(|da| da.render()).unwrap_or_default()
+            + &self.staleness.render()
+            + &self.finality.render()
+            + &self.runtime.render()
+            + &self.selector.render()
+            + &runtime::render_rpc(&self.client.usage())
+            + &tenant::render(
+                &self.ledger.by_tenant().await,
+                &self.queue.pending_by_tenant().await,
+            )
+    }
+}
+
+#[async_trait]
+impl Handler<CheckHead> for LightClient {
+    async fn handle(
+        &mut self,
+        _message: CheckHead,
+        _ctx: &mut ActorContext,
+    ) -> <CheckHead as coerce::actor::message::Message>::Result {
+        let head = self.store.head().await?;
+        Ok(self.staleness.check(&head))
     }
 }
 
 impl LightClient {
     pub fn new(config: &Config) -> Result<Self> {
-        let client = rpc::NearRpcClient::new(config.network);
+        let client = rpc::NearRpcClient::with_limits(config.rpc.network, config.rpc.limits);
 
         // TODO: store selector in config
-        let store = store::sled::init(config)?;
+        let store: Arc<_> = Store(store::sled::init(config)?.into()).into();
+        let block_tree = config
+            .block_tree
+            .clone()
+            .map(|c| BlockTree::new(c, client.clone(), store.clone()).into());
+        let da: Option<Arc<_>> = config
+            .da
+            .as_ref()
+            .map(Publisher::new)
+            .transpose()?
+            .map(Into::into);
+        let head_store = config
+            .head_store
+            .as_ref()
+            .map(Checkpointer::new)
+            .transpose()?
+            .map(Into::into);
+        let verifier = config
+            .verifier
+            .as_ref()
+            .map(EthCall::new)
+            .transpose()?
+            .map(|contract| Arc::new(contract) as Arc<dyn VerifierContract>);
+        let queue: Arc<_> = Queue::durable(store.clone()).into();
+        let ingester = config
+            .ingest
+            .clone()
+            .map(|c| Ingester::new(c, client.clone(), queue.clone()))
+            .transpose()?;
+        let mut hooks = Hooks::new(&config.hooks)?;
+        if let Some(da) = &da {
+            hooks = hooks.then(Box::new(Publish(da.clone())));
+        }
 
         Ok(Self {
             client,
             config: config.clone(),
-            store: Store(store.into()).into(),
+            store: store.clone(),
+            queue,
+            batches: Default::default(),
+            ledger: Ledger::durable(store.clone()).into(),
+            selector: Selector::new(config.selection.clone()).into(),
+            failures: FailureCounters::new(config.api.recent_errors).into(),
+            canary: config.canary.clone().map(Canary::new),
+            verifier,
+            heads: heads::feed(),
+            staleness: Staleness::new(config.staleness.clone()),
+            finality: Finality::new(config.finality.clone()).into(),
+            audit: AuditLog::open(&config.audit)?.into(),
+            cpu: CpuPool::new(config.runtime.cpu_threads),
+            runtime: RuntimeHealth::new(config.runtime.clone()).into(),
+            proving: Default::default(),
+            block_tree,
+            hooks: hooks.into(),
+            da,
+            head_store,
+            ingester,
         })
     }
 
     async fn bootstrap_store(&mut self) -> Result<()> {
         let head = self.store.head().await;
         if head.is_err() {
-            let sync_from =
-                CryptoHash::from_str(&self.config.starting_head).map_err(anyhow::Error::msg)?;
+            // Resuming from a checkpoint trusts nothing new
+            if let Some(head_store) = &self.head_store {
+                if let Some(checkpoint) = head_store.restore().await? {
+                    log::info!(
+                        "resuming from checkpoint: {:?}",
+                        checkpoint.head.inner_lite.height
+                    );
+                    self.store.insert(&checkpoint.inserts()).await?;
+                    return Ok(());
+                }
+            }
+
+            let sync_from = self.config.starting_head.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("We need a starting_head, or a checkpoint to resume from")
+            })?;
+            let sync_from = CryptoHash::from_str(sync_from).map_err(anyhow::Error::msg)?;
 
             let starting_head = self
                 .client
@@ -139,38 +671,26 @@ impl LightClient {
 
             log::info!("starting head: {:?}", starting_head.inner_lite.height);
 
-            let mut inserts: Vec<(CryptoHash, Entity)> = vec![];
-
-            inserts.push((
-                starting_head.inner_lite.epoch_id,
-                starting_head
-                    .next_bps
-                    .ok_or_else(|| anyhow::anyhow!("next_bps should be Some for boostrapped head"))?
-                    .into_iter()
-                    .map(ValidatorStakeView::into_validator_stake)
-                    .collect_vec()
-                    .into(),
-            ));
-
-            let boostrapped_head = Header {
-                prev_block_hash: starting_head.prev_block_hash,
-                inner_rest_hash: starting_head.inner_rest_hash,
-                inner_lite: starting_head.inner_lite,
-            };
-
-            inserts.push((head_key(), boostrapped_head.into()));
-
-            self.store.insert(&inserts).await?;
+            self.store
+                .insert(&bootstrap_inserts(starting_head)?)
+                .await?;
         }
 
         Ok(())
     }
 
     // TODO: dynamically determine if should catchup
+    #[allow(clippy::too_many_arguments)]
     pub async fn start_syncing(
         mut catching_up: bool,
         store: Arc<Store<store::sled::Store>>,
         client: rpc::NearRpcClient,
+        heads: HeadFeed,
+        cpu: CpuPool,
+        failures: Arc<FailureCounters>,
+        queue: Arc<Queue>,
+        selector: Arc<Selector>,
+        finality: Arc<Finality>,
     ) {
         // TODO: make configurable, currently set to ~block time
         let default_duration = time::Duration::from_secs(2);
@@ -182,11 +702,12 @@ impl LightClient {
                 default_duration
             };
             tokio::select! {
-                r = Self::sync(store.clone(), client.clone()) => {
+                r = Self::sync(store.clone(), &client, &heads, &cpu, &queue, &selector, &finality) => {
                     tokio::time::sleep(duration).await;
                     match r {
                         Err(e) => {
                             log::error!("Error syncing: {:?}", e);
+                            failures.record_failure(Some("sync".to_string()), &Failure::from(&e));
                             catching_up = false;
                         },
                         Ok(false) if catching_up => {
@@ -201,7 +722,12 @@ impl LightClient {
     }
     pub async fn sync(
         store: Arc<Store<store::sled::Store>>,
-        client: rpc::NearRpcClient,
+        client: &(impl LightClientRpc + Sync),
+        heads: &HeadFeed,
+        cpu: &CpuPool,
+        queue: &Queue,
+        selector: &Selector,
+        finality: &Finality,
     ) -> Result<bool> {
         let head = store.head().await?;
         log::debug!("Current head: {:#?}", head);
@@ -215,12 +741,26 @@ impl LightClient {
             .ok_or_else(|| anyhow!("Failed to fetch latest header"))?;
         log::trace!("Got new header: {:#?}", next_header.inner_lite);
 
+        let unanchored = queue
+            .unanchored(Timestamp::from(&head.inner_lite).as_nanos())
+            .await;
+        if selector.select(&head, &next_header, unanchored).is_none() {
+            return Ok(false);
+        }
+
         let bps = store
             .get(&Collection::BlockProducers, &head.inner_lite.epoch_id)
             .await
             .and_then(|x| x.bps())?;
 
-        let synced = Protocol::sync(&head, &bps, next_header)?;
+        let height = next_header.inner_lite.height;
+        let policy = finality.key_policy();
+        let synced = {
+            let head = head.clone();
+            cpu.run(move || Protocol::sync(&head, &bps, next_header, policy))
+                .await?
+                .with_context(|| format!("syncing to {}", height))?
+        };
 
         let mut inserts: Vec<(CryptoHash, Entity)> = vec![];
 
@@ -229,10 +769,21 @@ impl LightClient {
             inserts.push((epoch.0, next_bps.into()));
         }
 
+        let anchor = Anchor {
+            approvals: Some(synced.approvals),
+            margin_bps: Some(synced.stake.margin_bps()),
+            marginal: finality.check(&synced.new_head, &synced.stake),
+            rejected_keys: Some(synced.rejected_keys as u32),
+            ..Anchor::from(&synced.new_head)
+        };
+        let proven = HeadEvent::proven(&synced.new_head, &anchor);
+        inserts.extend(anchor_inserts(anchor));
         inserts.push((head.inner_lite.epoch_id, synced.new_head.clone().into()));
+        inserts.push(Pipeline::Fetched.mark(synced.new_head.inner_lite.height));
         inserts.push((head_key(), synced.new_head.into()));
 
         store.insert(&inserts).await?;
+        heads::publish(heads, proven);
         Ok(true)
     }
 
@@ -244,60 +795,698 @@ impl LightClient {
             .ok()
     }
 
+    /// Compare the shadow build's head for an epoch against the head the active
+    /// build synced to from the same epoch.
+    async fn compare_shadow(&self, output: ShadowOutput) -> Result<Comparison> {
+        let canary = self
+            .canary
+            .as_ref()
+            .ok_or_else(|| anyhow!("No canary configured"))?;
+        let active = self
+            .header(output.epoch_id)
+            .await
+            .ok_or_else(|| anyhow!("Epoch {} has not been synced", output.epoch_id))?;
+        let comparison = Comparison {
+            epoch_id: output.epoch_id,
+            active: active.hash(),
+            shadow: output.new_head,
+        };
+        canary.record(comparison.clone()).await;
+        self.audit
+            .try_record(Action::ShadowCompared {
+                epoch_id: comparison.epoch_id,
+                matches: comparison.matches(),
+            })
+            .await;
+        Ok(comparison)
+    }
+
     pub async fn verify_proof(&self, p: Proof) -> Result<bool> {
-        anyhow::ensure!(
-            self.store
-                .contains(&Collection::UsedRoots, p.block_merkle_root())
-                .await?,
-            "Root {:?} is not known",
-            p.block_merkle_root()
-        );
-        Protocol::inclusion_proof_verify(p)
+        if !self
+            .store
+            .contains(&Collection::UsedRoots, p.block_merkle_root())
+            .await?
+        {
+            return Err(Failure::new(
+                FailureReason::UnknownRoot,
+                format!("Root {:?} is not known", p.block_merkle_root()),
+            )
+            .into());
+        }
+        Ok(self
+            .cpu
+            .run(move || Protocol::inclusion_proof_verify(p))
+            .await??)
     }
 
-    pub async fn get_proofs(&self, req: BatchGetProof) -> Result<Vec<Proof>> {
+    pub async fn get_proofs(
+        &self,
+        req: BatchGetProof,
+        head: Option<CryptoHash>,
+    ) -> Result<Vec<Proof>> {
         let req = req.0.into_iter().map(|p| p.0).collect();
-        let head = self.store.head().await?;
-        let proofs = self.client.batch_fetch_proofs(&head.hash(), req).await;
+        let (head, root) = self.proving_head(head).await?;
+        let proofs = self.fetch_proofs(&head, &root, req).await;
         let (oks, errs): (Vec<_>, Vec<_>) = proofs.into_values().partition_result();
 
         if !errs.is_empty() {
-            return Err(anyhow::format_err!("Failed to fetch proofs: {:?}", errs));
+            return Err(Failure::new(
+                FailureReason::RpcUnavailable,
+                format!("Failed to fetch proofs: {:?}", errs),
+            )
+            .into());
         }
 
+        Ok(oks.into_iter().map(|x| (root, x)).map(Into::into).collect())
+    }
+
+    /// Fetch proofs against a head. When the block tree holds the head, the RPC
+    /// proves against its own latest block, which any node can do, and the
+    /// block proofs are rebuilt locally against our head.
+    async fn fetch_proofs(
+        &self,
+        head: &CryptoHash,
+        root: &CryptoHash,
+        mut reqs: Vec<TransactionOrReceiptId>,
+    ) -> HashMap<CryptoHash, nearx_error::Result<BasicProof>> {
+        let Some(tree) = &self.block_tree else {
+            return self.client.batch_fetch_proofs(head, reqs).await;
+        };
+
+        let mut proofs = HashMap::new();
+        if tree.contains(head).await {
+            match self.client.fetch_final_block().await {
+                Ok(latest) => {
+                    let fetched = self
+                        .client
+                        .batch_fetch_proofs(&latest.header.hash, reqs.clone())
+                        .await;
+                    for (id, mut proof) in fetched
+                        .into_iter()
+                        .filter_map(|(id, p)| Some((id, p.ok()?)))
+                    {
+                        let block = proof.block_header_lite.hash();
+                        match tree.prove(&block, head, root).await {
+                            Ok(Some(path)) => {
+                                proof.block_proof = path;
+                                proofs.insert(id, Ok(proof));
+                            }
+                            Ok(None) => (),
+                            Err(e) => log::warn!("Failed to prove block {}: {:?}", block, e),
+                        }
+                    }
+                    reqs.retain(|r| !proofs.contains_key(&request_id(r)));
+                }
+                Err(e) => log::warn!("Failed to fetch the latest block: {:?}", e),
+            }
+        }
+
+        // Anything left is proven by the RPC against our head
+        let fetched = self.client.batch_fetch_proofs(head, reqs).await;
+        if let Some(proof) = fetched.values().find_map(|p| p.as_ref().ok()) {
+            if let Err(e) = tree.seed(proof).await {
+                log::warn!("Failed to seed the block tree: {:?}", e);
+            }
+        }
+        proofs.extend(fetched);
+        proofs
+    }
+
+    /// The head hash and root to prove against, the latest head unless the
+    /// client pinned a past one. Pinned heads must be in the root registry.
+    async fn proving_head(&self, pinned: Option<CryptoHash>) -> Result<(CryptoHash, CryptoHash)> {
+        match pinned {
+            None => {
+                let head = self.latest_proving_head().await?;
+                Ok((head.hash(), head.inner_lite.block_merkle_root))
+            }
+            Some(head) => {
+                let root = self
+                    .store
+                    .get(&Collection::AnchorHeads, &head)
+                    .await
+                    .and_then(|e| e.anchor_head())
+                    .map_err(|_| {
+                        Failure::new(
+                            FailureReason::UnknownRoot,
+                            format!("Head {} is not in the root registry", head),
+                        )
+                    })?;
+                if let Some(anchor) = self.get_anchor(&root).await {
+                    self.ensure_same_circuits(&anchor.epoch_id).await?;
+                }
+                self.store.insert(&[(root, Entity::UsedRoot)]).await?;
+                Ok((head, root))
+            }
+        }
+    }
+
+    /// The latest head, anchored so it can be proven against.
+    async fn latest_proving_head(&self) -> Result<Header> {
+        let head = self.store.head().await?;
+        self.anchor(&head).await?;
+        Ok(head)
+    }
+
+    /// Mark the head's root as used and link it to the head it was synced
+    /// to, keeping any relay tx we already know about. The first head of an
+    /// epoch to be proven against records the circuits it is proven with.
+    ///
+    /// The head is only marked proven once a proof against it is built.
+    async fn anchor(&self, head: &Header) -> Result<()> {
+        let root = head.inner_lite.block_merkle_root;
+        let mut inserts: Vec<(CryptoHash, Entity)> = vec![(root, Entity::UsedRoot)];
+        if !self.store.contains(&Collection::Anchors, &root).await? {
+            inserts.extend(anchor_inserts(Anchor::from(head)));
+        }
+        let epoch_id = head.inner_lite.epoch_id;
+        if !self
+            .store
+            .contains(&Collection::CircuitSnapshots, &epoch_id)
+            .await?
+        {
+            inserts.push((epoch_id, self.circuit_snapshot(epoch_id).into()));
+        }
+        self.store.insert(&inserts).await
+    }
+
+    /// The circuits this build proves with.
+    fn circuit_snapshot(&self, epoch_id: CryptoHash) -> CircuitSnapshot {
+        CircuitSnapshot::new(
+            epoch_id,
+            self.config.prover.profile.as_str(),
+            BuildInfo::get(),
+        )
+    }
+
+    /// Re-proving an epoch needs the circuits it was first proven with, proofs
+    /// from any others wouldn't verify under the keys its proofs were checked
+    /// with. Epochs proven before snapshots were recorded can't be checked.
+    async fn ensure_same_circuits(&self, epoch_id: &CryptoHash) -> Result<()> {
+        let Some(snapshot) = self.store.circuit_snapshot(epoch_id).await else {
+            return Ok(());
+        };
+        let differences = snapshot.differences(&self.circuit_snapshot(*epoch_id));
+        if differences.is_empty() {
+            return Ok(());
+        }
+        Err(Failure::new(
+            FailureReason::CircuitMismatch,
+            format!(
+                "Epoch {} was proven with other circuits, re-prove it with the build from {}: {}",
+                epoch_id,
+                snapshot.git_commit,
+                differences.join(", ")
+            ),
+        )
+        .into())
+    }
+
+    async fn get_anchor(&self, root: &CryptoHash) -> Option<Anchor> {
         self.store
-            .insert(&[(head.inner_lite.block_merkle_root, Entity::UsedRoot)])
+            .get(&Collection::Anchors, root)
+            .await
+            .and_then(|e| e.anchor())
+            .ok()
+    }
+
+    async fn record_relay(&self, root: &CryptoHash, relay: Relay) -> Result<Anchor> {
+        if let Some(canary) = &self.canary {
+            if &relay.function_id == canary.function_id() && !canary.status().await.eligible {
+                anyhow::bail!(
+                    "Function id {} is still in its shadow period",
+                    relay.function_id
+                );
+            }
+        }
+        // The contract would reject a proof from a key it never registered,
+        // or has since revoked
+        if let Some(verifier) = &self.verifier {
+            if !compat::is_registered(verifier.as_ref(), &relay.function_id).await? {
+                let failure = Failure::new(
+                    FailureReason::CircuitMismatch,
+                    format!(
+                        "Function id {} isn't registered with the verifier contract",
+                        relay.function_id
+                    ),
+                );
+                self.failures
+                    .record_failure(Some(root.to_string()), &failure);
+                return Err(failure.into());
+            }
+        }
+        let mut anchor = self
+            .get_anchor(root)
+            .await
+            .ok_or_else(|| anyhow!("No anchor for root {}", root))?;
+        if let Some(existing) = &anchor.relay {
+            // Replays of the same relay are fine
+            if existing == &relay {
+                return Ok(anchor);
+            }
+            let failure = Failure::new(
+                FailureReason::AlreadyRelayed,
+                format!("{} was already relayed in {}", anchor.head, existing.tx),
+            );
+            self.failures
+                .record_failure(Some(root.to_string()), &failure);
+            return Err(failure.into());
+        }
+        // The destination only ever moves forward, so neither should we
+        if let Some(relayed) = self.store.progress().await.relayed {
+            if anchor.height <= relayed {
+                let failure = Failure::new(
+                    FailureReason::AlreadyRelayed,
+                    format!(
+                        "{} at {} is behind the relayed head at {}",
+                        anchor.head, anchor.height, relayed
+                    ),
+                );
+                self.failures
+                    .record_failure(Some(root.to_string()), &failure);
+                return Err(failure.into());
+            }
+        }
+        anchor.relay = Some(relay.clone());
+        self.store
+            .insert(&[
+                (*root, anchor.clone().into()),
+                Pipeline::Relayed.mark(anchor.height),
+            ])
             .await?;
-        Ok(oks
+        self.audit
+            .try_record(Action::ProofRelayed {
+                root: *root,
+                head: anchor.head,
+                relay,
+            })
+            .await;
+        heads::publish(&self.heads, HeadEvent::relayed(anchor.clone()));
+        Ok(anchor)
+    }
+
+    /// Fetch the proofs for a batch and persist them, so it can be proven
+    /// without going back to the RPC.
+    ///
+    /// The RPC can prove an outcome in a block the head doesn't cover, often a
+    /// receipt executed after its transaction. Those slots are requeued until
+    /// a newer head is synced, rather than failing the batch on its root.
+    async fn prepare_batch(&self, ids: Vec<TransactionOrReceiptId>) -> Result<Option<CryptoHash>> {
+        let key = PreparedBatch::key(&ids);
+        if self.store.contains(&Collection::Prepared, &key).await? {
+            return Ok(Some(key));
+        }
+
+        let head = self.latest_proving_head().await?;
+        let (hash, root) = (head.hash(), head.inner_lite.block_merkle_root);
+        let mut proofs = self.fetch_proofs(&hash, &root, ids.clone()).await;
+        let fetched = ids
             .into_iter()
-            .map(|x| (head.inner_lite.block_merkle_root, x))
-            .map(Into::into)
-            .collect())
+            .map(|id| match proofs.remove(&request_id(&id)) {
+                Some(Ok(proof)) => Ok((id, proof)),
+                Some(Err(e)) => Err(e.into()),
+                None => Err(anyhow!("No proof fetched for {:?}", id)),
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| {
+                Failure::new(
+                    FailureReason::RpcUnavailable,
+                    format!("Failed to fetch proofs: {:?}", e),
+                )
+            })?;
+
+        let (slots, uncovered): (Vec<_>, Vec<_>) = fetched.into_iter().partition(|(_, proof)| {
+            Protocol::ensure_block_is_before_head(head.inner_lite.height, proof).is_ok()
+        });
+        let prepared = if slots.is_empty() {
+            None
+        } else {
+            let batch = PreparedBatch::new(hash, root, &slots)?;
+            self.store.insert(&[(key, batch.into())]).await?;
+            Some(key)
+        };
+
+        // Only once the batch is persisted, a retry would prove them twice
+        let timestamp = Timestamp::from(&head.inner_lite).as_nanos();
+        for (id, proof) in uncovered {
+            log::debug!(
+                "Requeueing {:?}, block {} isn't before the head at {}",
+                id,
+                proof.block_header_lite.inner_lite.height,
+                head.inner_lite.height
+            );
+            self.queue.requeue(&id, timestamp).await;
+        }
+        Ok(prepared)
     }
 
-    pub async fn experimental_get_proofs(&self, req: BatchGetProof) -> Result<ExperimentalProof> {
+    pub async fn experimental_get_proofs(
+        &self,
+        req: BatchGetProof,
+        head: Option<CryptoHash>,
+    ) -> Result<ExperimentalProof> {
         let req = req.0.into_iter().map(|p| p.0).collect();
 
-        let head = self.store.head().await?;
-        let proofs = self.client.batch_fetch_proofs(&head.hash(), req).await;
+        let (head, root) = self.proving_head(head).await?;
+        let proofs = self.fetch_proofs(&head, &root, req).await;
 
         let (oks, errs): (Vec<_>, Vec<_>) = proofs.into_values().partition_result();
         if !errs.is_empty() {
-            Err(anyhow::format_err!("Failed to fetch proofs: {:?}", errs))
+            Err(Failure::new(
+                FailureReason::RpcUnavailable,
+                format!("Failed to fetch proofs: {:?}", errs),
+            )
+            .into())
         } else {
-            let p = protocol::experimental::Proof::new(head.inner_lite.block_merkle_root, oks);
-            self.store
-                .insert(&[(head.inner_lite.block_merkle_root, Entity::UsedRoot)])
+            let proof = self
+                .cpu
+                .run(move || protocol::experimental::Proof::new(root, oks))
                 .await?;
-
-            Ok(p)
+            if let Some(anchor) = self.get_anchor(&root).await {
+                self.store
+                    .insert(&[Pipeline::Proven.mark(anchor.height)])
+                    .await?;
+            }
+            Ok(proof)
         }
     }
 }
 
+/// The id proofs are keyed by.
+fn request_id(req: &TransactionOrReceiptId) -> CryptoHash {
+    match req {
+        TransactionOrReceiptId::Transaction {
+            transaction_hash, ..
+        } => *transaction_hash,
+        TransactionOrReceiptId::Receipt { receipt_id, .. } => *receipt_id,
+    }
+}
+
+/// Start from `starting_head`, trusting its next block producers.
+fn bootstrap_inserts(starting_head: LightClientBlockView) -> Result<Vec<(CryptoHash, Entity)>> {
+    let bps = starting_head
+        .next_bps
+        .ok_or_else(|| anyhow::anyhow!("next_bps should be Some for boostrapped head"))?
+        .into_iter()
+        .map(ValidatorStakeView::into_validator_stake)
+        .collect_vec();
+
+    let boostrapped_head = Header {
+        prev_block_hash: starting_head.prev_block_hash,
+        inner_rest_hash: starting_head.inner_rest_hash,
+        inner_lite: starting_head.inner_lite,
+    };
+
+    Ok(vec![
+        (boostrapped_head.inner_lite.epoch_id, bps.into()),
+        Pipeline::Fetched.mark(boostrapped_head.inner_lite.height),
+        (head_key(), boostrapped_head.into()),
+    ])
+}
+
+/// Register a head in the root registry so proofs can later be pinned to it.
+fn anchor_inserts(anchor: Anchor) -> [(CryptoHash, Entity); 2] {
+    let (root, head) = (anchor.block_merkle_root, anchor.head);
+    [(root, anchor.into()), (head, Entity::AnchorHead(root))]
+}
+
 #[cfg(test)]
 mod tests {
+    use async_trait::async_trait;
+    use protocol::{ED25519PublicKey, PublicKey, ValidatorStake};
+    use test_utils::{test_first, test_last, test_next, LightClientFixture};
+
+    use super::*;
+    use crate::config::{FinalityConfig, SelectionConfig};
 
     #[test]
     fn t() {}
+
+    /// Serves recorded light client blocks by the head they follow.
+    struct FixtureRpc(HashMap<CryptoHash, LightClientBlockView>);
+
+    impl FixtureRpc {
+        fn new(blocks: impl IntoIterator<Item = LightClientFixture<LightClientBlockView>>) -> Self {
+            Self(
+                blocks
+                    .into_iter()
+                    .map(|f| (f.last_block_hash, f.body))
+                    .collect(),
+            )
+        }
+    }
+
+    #[async_trait]
+    impl LightClientRpc for FixtureRpc {
+        async fn fetch_latest_header(
+            &self,
+            latest_verified: &CryptoHash,
+        ) -> nearx_error::Result<Option<LightClientBlockView>> {
+            Ok(self.0.get(latest_verified).cloned())
+        }
+
+        async fn fetch_light_client_proof(
+            &self,
+            _req: TransactionOrReceiptId,
+            _latest_verified: CryptoHash,
+        ) -> nearx_error::Result<BasicProof> {
+            nearx_error::bail!(Rpc, "No proofs are recorded")
+        }
+
+        async fn fetch_epoch_bps(
+            &self,
+            _epoch_id: &CryptoHash,
+        ) -> nearx_error::Result<Vec<ValidatorStakeView>> {
+            nearx_error::bail!(Rpc, "No validators are recorded")
+        }
+
+        async fn fetch_header(&self, _hash: &CryptoHash) -> nearx_error::Result<Header> {
+            nearx_error::bail!(Rpc, "No headers are recorded")
+        }
+    }
+
+    /// The sync pipeline against an in-memory store, proving natively.
+    struct Operator {
+        store: Arc<Store<store::sled::Store>>,
+        heads: HeadFeed,
+        cpu: CpuPool,
+        queue: Queue,
+        selector: Selector,
+        finality: Finality,
+    }
+
+    impl Operator {
+        async fn bootstrap(rpc: &FixtureRpc, from: &CryptoHash) -> Self {
+            let store: Arc<_> = Store(store::sled::temporary().unwrap().into()).into();
+            let starting_head = rpc.fetch_latest_header(from).await.unwrap().unwrap();
+            store
+                .insert(&bootstrap_inserts(starting_head).unwrap())
+                .await
+                .unwrap();
+            Self {
+                store,
+                heads: heads::feed(),
+                cpu: CpuPool::new(1),
+                queue: Default::default(),
+                selector: Selector::new(SelectionConfig::default()),
+                finality: Finality::new(FinalityConfig::default()),
+            }
+        }
+
+        async fn sync(&self, rpc: &FixtureRpc) -> Result<bool> {
+            LightClient::sync(
+                self.store.clone(),
+                rpc,
+                &self.heads,
+                &self.cpu,
+                &self.queue,
+                &self.selector,
+                &self.finality,
+            )
+            .await
+        }
+
+        async fn bps(&self, epoch_id: &CryptoHash) -> Vec<ValidatorStake> {
+            self.store
+                .get(&Collection::BlockProducers, epoch_id)
+                .await
+                .and_then(|e| e.bps())
+                .unwrap()
+        }
+    }
+
+    fn next_bps(block: &LightClientBlockView) -> Vec<ValidatorStake> {
+        block
+            .next_bps
+            .clone()
+            .unwrap()
+            .into_iter()
+            .map(ValidatorStakeView::into_validator_stake)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_operator_syncs_across_epochs() {
+        let (first, next, last) = (test_first(), test_next(), test_last());
+        let rpc = FixtureRpc::new([first.clone(), next.clone(), last.clone()]);
+        let op = Operator::bootstrap(&rpc, &first.last_block_hash).await;
+        let mut events = op.heads.subscribe();
+
+        let bootstrapped = op.store.head().await.unwrap();
+        assert_eq!(bootstrapped.inner_lite, first.body.inner_lite);
+        assert_eq!(
+            op.bps(&bootstrapped.inner_lite.epoch_id).await,
+            next_bps(&first.body)
+        );
+
+        for (block, prev) in [(&next, &first), (&last, &next)] {
+            let block = &block.body;
+            // Every recorded block starts a new epoch
+            assert_eq!(
+                block.inner_lite.epoch_id,
+                prev.body.inner_lite.next_epoch_id
+            );
+            assert_eq!(
+                CryptoHash::hash_borsh(block.next_bps.clone().unwrap()),
+                block.inner_lite.next_bp_hash
+            );
+
+            assert!(
+                op.sync(&rpc).await.unwrap(),
+                "epoch boundaries are selected"
+            );
+            let head = op.store.head().await.unwrap();
+            assert_eq!(head.inner_lite, block.inner_lite);
+
+            // The producers rotate in, we sync out of this epoch with them
+            assert_eq!(op.bps(&head.inner_lite.epoch_id).await, next_bps(block));
+
+            // The head is anchored and can be pinned
+            let root = head.inner_lite.block_merkle_root;
+            let anchor = op
+                .store
+                .get(&Collection::Anchors, &root)
+                .await
+                .and_then(|e| e.anchor())
+                .unwrap();
+            assert_eq!(anchor.head, head.hash());
+            assert_eq!(anchor.height, head.inner_lite.height);
+            assert_eq!(anchor.relay, None);
+            assert!(anchor.margin_bps.unwrap() > 0);
+            assert_eq!(
+                anchor.marginal,
+                anchor.margin_bps.unwrap() < FinalityConfig::default().marginal_bps
+            );
+            // Every recorded signature is valid
+            assert_eq!(anchor.rejected_keys, Some(0));
+            let approvals = anchor.approvals.clone().unwrap();
+            assert!(approvals.approved() > 0);
+            assert!(approvals
+                .iter()
+                .zip(&block.approvals_after_next)
+                .all(|(approved, signature)| approved == signature.is_some()));
+            assert_eq!(
+                op.store
+                    .get(&Collection::AnchorHeads, &head.hash())
+                    .await
+                    .and_then(|e| e.anchor_head())
+                    .unwrap(),
+                root
+            );
+            assert_eq!(
+                op.store.progress().await.fetched,
+                Some(head.inner_lite.height)
+            );
+            assert_eq!(
+                events.recv().await.unwrap(),
+                HeadEvent::proven(&head, &anchor)
+            );
+        }
+
+        // Nothing is recorded after the last block
+        assert!(op.sync(&rpc).await.is_err());
+        assert_eq!(
+            op.store.head().await.unwrap().inner_lite,
+            last.body.inner_lite
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operator_counts_rejected_keys() {
+        let (first, next) = (test_first(), test_next());
+        let rpc = FixtureRpc::new([first.clone(), next.clone()]);
+        let op = Operator::bootstrap(&rpc, &first.last_block_hash).await;
+        let mut events = op.heads.subscribe();
+
+        // Swap a seat that signed for a key that isn't a valid point
+        let epoch_id = first.body.inner_lite.epoch_id;
+        let mut bps = op.bps(&epoch_id).await;
+        let i = next
+            .body
+            .approvals_after_next
+            .iter()
+            .position(Option::is_some)
+            .unwrap();
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        bps[i] = ValidatorStake::new_v1(
+            bps[i].account_id().clone(),
+            PublicKey::ED25519(ED25519PublicKey(p)),
+            bps[i].stake(),
+        );
+        op.store.insert(&[(epoch_id, bps.into())]).await.unwrap();
+
+        // The default policy treats the seat as absent
+        assert!(op.sync(&rpc).await.unwrap());
+        let head = op.store.head().await.unwrap();
+        assert_eq!(head.inner_lite, next.body.inner_lite);
+        let anchor = op
+            .store
+            .get(&Collection::Anchors, &head.inner_lite.block_merkle_root)
+            .await
+            .and_then(|e| e.anchor())
+            .unwrap();
+        assert_eq!(anchor.rejected_keys, Some(1));
+        assert!(!anchor.approvals.clone().unwrap().is_approved(i));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HeadEvent::proven(&head, &anchor)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operator_rejects_bad_next_bps() {
+        let (first, mut next) = (test_first(), test_next());
+        next.body.next_bps.as_mut().unwrap().pop();
+        let rpc = FixtureRpc::new([first.clone(), next.clone()]);
+        let op = Operator::bootstrap(&rpc, &first.last_block_hash).await;
+
+        // The producers no longer hash to `next_bp_hash`
+        assert!(op.sync(&rpc).await.is_err());
+        assert_eq!(
+            op.store.head().await.unwrap().inner_lite,
+            first.body.inner_lite
+        );
+        assert!(op
+            .store
+            .get(
+                &Collection::BlockProducers,
+                &first.body.inner_lite.next_epoch_id
+            )
+            .await
+            .is_err());
+        assert!(!op
+            .store
+            .contains(
+                &Collection::Anchors,
+                &next.body.inner_lite.block_merkle_root
+            )
+            .await
+            .unwrap());
+        assert_eq!(
+            op.store.progress().await.fetched,
+            Some(first.body.inner_lite.height)
+        );
+    }
 }
@@ -0,0 +1,799 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use near_primitives::types::TransactionOrReceiptId;
+use protocol::experimental::Proof as ExperimentalProof;
+use rpc::Unprovable;
+use tokio::sync::{oneshot, RwLock};
+
+use super::{
+    failure::Failure,
+    hooks::Attestation,
+    rules::Priority,
+    store::{self, Anchor, Collection, QueuedJob, Store},
+    tenant::{Tenant, TenantError, DEFAULT_TENANT},
+};
+use crate::{build_info::BuildInfo, prelude::*};
+
+pub const DEFAULT_PRIORITY: Priority = 0;
+
+/// Who asked for a proof, costs are accounted against this. Names are only
+/// unique within a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, BorshSerialize, BorshDeserialize)]
+pub struct Requester {
+    pub tenant: Tenant,
+    pub name: String,
+}
+
+impl Requester {
+    pub fn new(tenant: &str, name: &str) -> Self {
+        Self {
+            tenant: tenant.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Requests made by the operator rather than through the API.
+    pub fn operator(name: &str) -> Self {
+        Self::new(DEFAULT_TENANT, name)
+    }
+}
+
+/// Why a request wasn't queued.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EnqueueError {
+    #[error(transparent)]
+    Tenant(#[from] TenantError),
+    /// Turned away up front rather than failing later in a batch.
+    #[error(transparent)]
+    Unprovable(#[from] Unprovable),
+}
+
+/// Why a request wasn't cancelled.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CancelError {
+    #[error("{0:?} isn't queued by this requester")]
+    NotQueued(TransactionOrReceiptId),
+    /// Its batch is being proven, the result is delivered as usual.
+    #[error("{0:?} is already being proven")]
+    InFlight(TransactionOrReceiptId),
+}
+
+pub type JobResult = std::result::Result<AnchoredProof, Failure>;
+
+/// A proof along with the sync it needs to be submitted after.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnchoredProof {
+    pub proof: ExperimentalProof,
+    pub anchor: Option<Anchor>,
+    /// What the proof was built with.
+    pub build: &'static BuildInfo,
+    /// Signatures added by the `attest` hook.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attestations: Vec<Attestation>,
+    /// The verify results of the batch, hex encoded with
+    /// `protocol::packing`. Only with `scheduler.output = "packed"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packed_results: Option<String>,
+}
+
+/// What each requester of a job receives once it is proven.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub result: JobResult,
+    /// Where the slot is in the batch it was proven in.
+    pub slot: usize,
+    /// This requester's share of the slot.
+    pub cost: u64,
+}
+
+/// A slot in the queue as a requester sees it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueuedRequest {
+    pub id: TransactionOrReceiptId,
+    pub priority: Priority,
+    /// Where it is in the queue, unset once it is in flight.
+    pub position: Option<usize>,
+    /// The tenant's requesters waiting on it.
+    pub requesters: Vec<String>,
+    /// When it was first requested, in unix nanoseconds.
+    pub queued_at: u64,
+}
+
+struct Job {
+    priority: Priority,
+    id: TransactionOrReceiptId,
+    subscribers: Vec<(Requester, Option<oneshot::Sender<Delivery>>)>,
+    queued_at: u64,
+    /// Only heads after this, in unix nanoseconds, can cover the slot. When
+    /// it was first requested, or the head it was found not to be covered by.
+    anchor_after: u64,
+}
+
+impl Job {
+    fn record(&self, in_flight: bool) -> Result<QueuedJob> {
+        let requesters = self.subscribers.iter().map(|(r, _)| r.clone()).collect();
+        QueuedJob::new(
+            &self.id,
+            self.priority,
+            self.queued_at,
+            self.anchor_after,
+            requesters,
+            in_flight,
+        )
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    pending: VecDeque<Job>,
+    in_flight: Vec<Job>,
+    /// Where jobs are written, unless the queue only lives in memory.
+    store: Option<Arc<Store<store::sled::Store>>>,
+}
+
+impl Inner {
+    async fn save(&self, job: &Job, in_flight: bool) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let saved = match job.record(in_flight) {
+            Ok(record) => {
+                store
+                    .insert(&[(QueuedJob::key(&job.id), record.into())])
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            log::error!("Failed to save queued {:?}: {:?}", job.id, e);
+        }
+    }
+
+    async fn forget(&self, id: &TransactionOrReceiptId) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        if let Err(e) = store.remove(&Collection::Queued, &QueuedJob::key(id)).await {
+            log::error!("Failed to remove queued {:?}: {:?}", id, e);
+        }
+    }
+
+    /// Behind anything of the same priority so we stay FIFO within it.
+    fn insert_pending(&mut self, job: Job) {
+        let at = self.pending.partition_point(|j| j.priority >= job.priority);
+        self.pending.insert(at, job);
+    }
+}
+
+/// Requests waiting to be proven by the verify circuit, ordered by priority
+/// and then by arrival.
+///
+/// The same id is only ever given one slot, whether it is pending or already
+/// in flight, any further requests subscribe to that slot and the result is
+/// fanned out to every requester.
+///
+/// A durable queue writes each slot to the store as it changes, so requests
+/// survive a restart, see `restore`.
+#[derive(Default)]
+pub struct Queue(RwLock<Inner>);
+
+impl Queue {
+    pub fn durable(store: Arc<Store<store::sled::Store>>) -> Self {
+        Self(RwLock::new(Inner {
+            store: Some(store),
+            ..Default::default()
+        }))
+    }
+
+    /// Reload the slots queued before a restart, returning how many there
+    /// were. Slots in a prepared batch stay in flight as the scheduler proves
+    /// it again, any others are pending. Their requesters are still charged,
+    /// but anyone that was waiting on a delivery has gone.
+    pub async fn restore(&self) -> Result<usize> {
+        let mut inner = self.0.write().await;
+        let Some(store) = inner.store.clone() else {
+            return Ok(0);
+        };
+        let mut prepared = vec![];
+        for key in store.prepared().await? {
+            let batch = store
+                .get(&Collection::Prepared, &key)
+                .await
+                .and_then(|e| e.prepared())?;
+            prepared.extend(batch.slots()?.into_iter().map(|(id, _)| id));
+        }
+
+        let mut records = store.queued().await?;
+        records.sort_by_key(|r| r.queued_at);
+        let restored = records.len();
+        for record in records {
+            let id = record.id()?;
+            if inner
+                .pending
+                .iter()
+                .chain(&inner.in_flight)
+                .any(|j| j.id == id)
+            {
+                continue;
+            }
+            let job = Job {
+                priority: record.priority,
+                id,
+                subscribers: record.requesters.into_iter().map(|r| (r, None)).collect(),
+                queued_at: record.queued_at,
+                anchor_after: record.anchor_after,
+            };
+            if prepared.contains(&job.id) {
+                inner.in_flight.push(job);
+            } else {
+                if record.in_flight {
+                    inner.save(&job, false).await;
+                }
+                inner.insert_pending(job);
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Request a proof for `id`, the receiver resolves once its slot has been
+    /// proven.
+    pub async fn enqueue(
+        &self,
+        priority: Priority,
+        id: TransactionOrReceiptId,
+        requester: Requester,
+    ) -> oneshot::Receiver<Delivery> {
+        let (tx, rx) = oneshot::channel();
+        self.subscribe(priority, id, requester, Some(tx)).await;
+        rx
+    }
+
+    /// Enqueue without waiting on the results.
+    pub async fn extend(
+        &self,
+        ids: impl IntoIterator<Item = (Priority, TransactionOrReceiptId)>,
+        requester: &Requester,
+    ) {
+        for (priority, id) in ids {
+            self.subscribe(priority, id, requester.clone(), None).await;
+        }
+    }
+
+    async fn subscribe(
+        &self,
+        priority: Priority,
+        id: TransactionOrReceiptId,
+        requester: Requester,
+        tx: Option<oneshot::Sender<Delivery>>,
+    ) {
+        let mut inner = self.0.write().await;
+
+        if let Some(i) = inner.in_flight.iter().position(|j| j.id == id) {
+            log::debug!("Coalescing {:?} into in flight slot", id);
+            inner.in_flight[i].subscribers.push((requester, tx));
+            inner.save(&inner.in_flight[i], true).await;
+            return;
+        }
+
+        let mut job = match inner.pending.iter().position(|j| j.id == id) {
+            Some(i) => {
+                log::debug!("Coalescing {:?} into pending slot", id);
+                inner.pending.remove(i).unwrap()
+            }
+            None => {
+                let now = now_ns();
+                Job {
+                    priority,
+                    id,
+                    subscribers: vec![],
+                    queued_at: now,
+                    anchor_after: now,
+                }
+            }
+        };
+        // A slot is as urgent as its most urgent requester
+        job.priority = job.priority.max(priority);
+        job.subscribers.push((requester, tx));
+        inner.save(&job, false).await;
+        inner.insert_pending(job);
+    }
+
+    /// Take the next `n` slots to prove against a head at
+    /// `head_timestamp_ns`, they stay subscribable until they are completed.
+    ///
+    /// Slots aren't bound to a head until they are taken. Those requested
+    /// after the head may not be covered by it, so they keep their place
+    /// until a newer head is synced, see `unanchored`.
+    pub async fn take(&self, n: usize, head_timestamp_ns: u64) -> Vec<TransactionOrReceiptId> {
+        let mut inner = self.0.write().await;
+        let mut jobs = vec![];
+        let mut waiting = VecDeque::new();
+        for job in std::mem::take(&mut inner.pending) {
+            if jobs.len() < n && job.anchor_after <= head_timestamp_ns {
+                jobs.push(job);
+            } else {
+                waiting.push_back(job);
+            }
+        }
+        inner.pending = waiting;
+        for job in &jobs {
+            inner.save(job, true).await;
+        }
+        let ids = jobs.iter().map(|j| j.id.clone()).collect();
+        inner.in_flight.extend(jobs);
+        ids
+    }
+
+    /// Put an in flight slot back in the queue, its outcome was in a block the
+    /// head at `head_timestamp_ns` doesn't cover so it waits for a newer one.
+    pub async fn requeue(&self, id: &TransactionOrReceiptId, head_timestamp_ns: u64) {
+        let mut inner = self.0.write().await;
+        let Some(i) = inner.in_flight.iter().position(|j| &j.id == id) else {
+            return;
+        };
+        let mut job = inner.in_flight.swap_remove(i);
+        job.anchor_after = job.anchor_after.max(head_timestamp_ns + 1);
+        inner.save(&job, false).await;
+        inner.insert_pending(job);
+    }
+
+    /// Deliver the result of a slot, the `slot`th of its batch, to all of its
+    /// requesters, splitting `slot_cost` between them. Returns what each
+    /// requester was charged.
+    pub async fn complete(
+        &self,
+        id: &TransactionOrReceiptId,
+        slot: usize,
+        result: &JobResult,
+        slot_cost: u64,
+    ) -> Vec<(Requester, u64)> {
+        let job = {
+            let mut inner = self.0.write().await;
+            let job = match inner.in_flight.iter().position(|j| &j.id == id) {
+                Some(i) => inner.in_flight.swap_remove(i),
+                None => return vec![],
+            };
+            inner.forget(id).await;
+            job
+        };
+
+        split_cost(slot_cost, job.subscribers.len())
+            .zip(job.subscribers)
+            .map(|(cost, (requester, tx))| {
+                if let Some(tx) = tx {
+                    let _ = tx.send(Delivery {
+                        result: result.clone(),
+                        slot,
+                        cost,
+                    });
+                }
+                (requester, cost)
+            })
+            .collect()
+    }
+
+    /// Withdraw a requester from a pending slot, the slot is dropped once
+    /// nobody is waiting on it. The requester isn't charged, anyone waiting
+    /// on its delivery sees the channel close.
+    pub async fn cancel(
+        &self,
+        id: &TransactionOrReceiptId,
+        requester: &Requester,
+    ) -> std::result::Result<(), CancelError> {
+        let mut inner = self.0.write().await;
+        let requested = |j: &Job| &j.id == id && j.subscribers.iter().any(|(r, _)| r == requester);
+        if inner.in_flight.iter().any(requested) {
+            return Err(CancelError::InFlight(id.clone()));
+        }
+        let Some(i) = inner.pending.iter().position(requested) else {
+            return Err(CancelError::NotQueued(id.clone()));
+        };
+
+        let job = &mut inner.pending[i];
+        job.subscribers.retain(|(r, _)| r != requester);
+        if job.subscribers.is_empty() {
+            inner.pending.remove(i);
+            inner.forget(id).await;
+        } else {
+            inner.save(&inner.pending[i], false).await;
+        }
+        Ok(())
+    }
+
+    /// The slots a tenant is waiting on, pending in queue order then in
+    /// flight.
+    pub async fn requests(&self, tenant: &str) -> Vec<QueuedRequest> {
+        let inner = self.0.read().await;
+        let pending = inner.pending.iter().enumerate().map(|(i, j)| (Some(i), j));
+        let in_flight = inner.in_flight.iter().map(|j| (None, j));
+        pending
+            .chain(in_flight)
+            .filter_map(|(position, job)| {
+                let requesters = job
+                    .subscribers
+                    .iter()
+                    .filter(|(r, _)| r.tenant == tenant)
+                    .map(|(r, _)| r.name.clone())
+                    .unique()
+                    .collect_vec();
+                (!requesters.is_empty()).then(|| QueuedRequest {
+                    id: job.id.clone(),
+                    priority: job.priority,
+                    position,
+                    requesters,
+                    queued_at: job.queued_at,
+                })
+            })
+            .collect()
+    }
+
+    /// The pending slots a tenant has requested, slots shared with other
+    /// tenants are included but not who else requested them.
+    pub async fn pending(&self, tenant: &str) -> Vec<TransactionOrReceiptId> {
+        self.0
+            .read()
+            .await
+            .pending
+            .iter()
+            .filter(|j| j.subscribers.iter().any(|(r, _)| r.tenant == tenant))
+            .map(|j| j.id.clone())
+            .collect()
+    }
+
+    /// How many pending slots each tenant is waiting on.
+    pub async fn pending_by_tenant(&self) -> HashMap<Tenant, usize> {
+        let inner = self.0.read().await;
+        let mut counts = HashMap::new();
+        for job in &inner.pending {
+            for tenant in job.subscribers.iter().map(|(r, _)| &r.tenant).unique() {
+                *counts.entry(tenant.clone()).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Whether any slot, pending or in flight, was requested after
+    /// `head_timestamp_ns`. Those may be for blocks the head doesn't cover yet,
+    /// so need a newer head to be anchored against.
+    pub async fn unanchored(&self, head_timestamp_ns: u64) -> bool {
+        let inner = self.0.read().await;
+        inner
+            .pending
+            .iter()
+            .chain(&inner.in_flight)
+            .any(|j| j.anchor_after > head_timestamp_ns)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.0.read().await.pending.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.0.read().await.pending.is_empty()
+    }
+
+    /// Slots taken into a batch that hasn't been delivered yet.
+    pub async fn in_flight(&self) -> usize {
+        self.0.read().await.in_flight.len()
+    }
+}
+
+pub(crate) fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Split evenly, the first requesters pick up any remainder.
+fn split_cost(cost: u64, n: usize) -> impl Iterator<Item = u64> {
+    let n = n.max(1) as u64;
+    let (share, rem) = (cost / n, cost % n);
+    (0..n).map(move |i| share + u64::from(i < rem))
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::hash::CryptoHash;
+
+    use super::*;
+    use crate::client::failure::FailureReason;
+
+    fn ids(n: u8) -> Vec<TransactionOrReceiptId> {
+        (0..n)
+            .map(|i| TransactionOrReceiptId::Receipt {
+                receipt_id: CryptoHash::hash_bytes(&[i]),
+                receiver_id: "test.near".parse().unwrap(),
+            })
+            .collect()
+    }
+
+    fn requester(s: &str) -> Requester {
+        Requester::operator(s)
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_fifo() {
+        let queue = Queue::default();
+        let ids = ids(3);
+
+        queue
+            .extend(
+                ids.iter().cloned().map(|id| (DEFAULT_PRIORITY, id)),
+                &requester("a"),
+            )
+            .await;
+
+        assert_eq!(queue.len().await, 3);
+        assert_eq!(queue.pending(DEFAULT_TENANT).await, ids);
+    }
+
+    #[tokio::test]
+    async fn test_queue_orders_by_priority() {
+        let queue = Queue::default();
+        let ids = ids(4);
+
+        for (priority, id) in [1, 0, 5, 1].into_iter().zip(ids.clone()) {
+            queue.enqueue(priority, id, requester("a")).await;
+        }
+
+        assert_eq!(
+            queue.pending(DEFAULT_TENANT).await,
+            vec![
+                ids[2].clone(),
+                ids[0].clone(),
+                ids[3].clone(),
+                ids[1].clone()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_share_a_slot() {
+        let queue = Queue::default();
+        let ids = ids(2);
+
+        queue.enqueue(0, ids[1].clone(), requester("a")).await;
+        let a = queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        // Bumps the shared slot ahead of ids[1]
+        let b = queue.enqueue(3, ids[0].clone(), requester("b")).await;
+        assert_eq!(queue.pending(DEFAULT_TENANT).await, ids);
+
+        assert_eq!(queue.take(1, u64::MAX).await, vec![ids[0].clone()]);
+        // Requested again after the batch was taken
+        let c = queue.enqueue(0, ids[0].clone(), requester("c")).await;
+        assert_eq!(queue.pending(DEFAULT_TENANT).await, vec![ids[1].clone()]);
+
+        let failure = Failure::new(FailureReason::Timeout, "boom");
+        let result: JobResult = Err(failure.clone());
+        let charges = queue.complete(&ids[0], 0, &result, 10).await;
+        assert_eq!(
+            charges,
+            vec![
+                (requester("a"), 4),
+                (requester("b"), 3),
+                (requester("c"), 3)
+            ]
+        );
+
+        for (rx, cost) in [(a, 4), (b, 3), (c, 3)] {
+            let delivery = rx.await.unwrap();
+            assert_eq!(delivery.cost, cost);
+            assert_eq!(delivery.result.unwrap_err(), failure);
+        }
+        // Completed slots are no longer coalesced into
+        assert!(queue.complete(&ids[0], 0, &result, 10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tenants_only_see_their_slots() {
+        let queue = Queue::default();
+        let ids = ids(3);
+
+        queue
+            .enqueue(0, ids[0].clone(), Requester::new("bridge", "a"))
+            .await;
+        queue
+            .enqueue(0, ids[1].clone(), Requester::new("wallet", "a"))
+            .await;
+        // Shared slots are still proven once
+        queue
+            .enqueue(0, ids[1].clone(), Requester::new("bridge", "b"))
+            .await;
+        queue
+            .enqueue(0, ids[2].clone(), Requester::new("wallet", "b"))
+            .await;
+
+        assert_eq!(queue.len().await, 3);
+        assert_eq!(queue.pending("bridge").await, ids[..2].to_vec());
+        assert_eq!(queue.pending("wallet").await, ids[1..].to_vec());
+        assert!(queue.pending("other").await.is_empty());
+        assert_eq!(
+            queue.pending_by_tenant().await,
+            HashMap::from([("bridge".to_string(), 2), ("wallet".to_string(), 2)])
+        );
+
+        queue.take(2, u64::MAX).await;
+        let result: JobResult = Err(Failure::new(FailureReason::Timeout, "boom"));
+        let charges = queue.complete(&ids[1], 0, &result, 10).await;
+        assert_eq!(
+            charges,
+            vec![
+                (Requester::new("wallet", "a"), 5),
+                (Requester::new("bridge", "b"), 5)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take_waits_for_a_covering_head() {
+        let queue = Queue::default();
+        let ids = ids(3);
+
+        queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        queue.enqueue(0, ids[1].clone(), requester("a")).await;
+        let head_timestamp_ns = now_ns();
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        // Requested after the head, ahead of the others
+        queue.enqueue(5, ids[2].clone(), requester("a")).await;
+
+        assert!(queue.take(5, 0).await.is_empty());
+        assert_eq!(queue.take(1, head_timestamp_ns).await, vec![ids[0].clone()]);
+        assert_eq!(
+            queue.pending(DEFAULT_TENANT).await,
+            vec![ids[2].clone(), ids[1].clone()]
+        );
+        assert_eq!(queue.take(5, head_timestamp_ns).await, vec![ids[1].clone()]);
+        // Bound once a newer head is synced
+        assert_eq!(queue.take(5, now_ns()).await, vec![ids[2].clone()]);
+        assert!(queue.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_requeue_waits_for_a_newer_head() {
+        let queue = Queue::default();
+        let ids = ids(2);
+        queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        queue.enqueue(1, ids[1].clone(), requester("b")).await;
+
+        let head_timestamp_ns = now_ns();
+        assert_eq!(queue.take(2, head_timestamp_ns).await.len(), 2);
+        queue.requeue(&ids[1], head_timestamp_ns).await;
+
+        // Keeps its priority and subscribers, but not against the same head
+        assert_eq!(queue.pending(DEFAULT_TENANT).await, vec![ids[1].clone()]);
+        assert!(queue.unanchored(head_timestamp_ns).await);
+        assert!(queue.take(2, head_timestamp_ns).await.is_empty());
+        assert_eq!(
+            queue.take(2, head_timestamp_ns + 1).await,
+            vec![ids[1].clone()]
+        );
+        let result: JobResult = Err(Failure::new(FailureReason::Timeout, "boom"));
+        assert_eq!(
+            queue.complete(&ids[1], 0, &result, 10).await,
+            vec![(requester("b"), 10)]
+        );
+
+        // Anything not in flight is left alone
+        queue.requeue(&ids[1], head_timestamp_ns).await;
+        assert!(queue.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_unanchored() {
+        let queue = Queue::default();
+        assert!(!queue.unanchored(0).await);
+
+        let ids = ids(1);
+        queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        assert!(queue.unanchored(0).await);
+        assert!(!queue.unanchored(u64::MAX).await);
+
+        // Still waiting on an anchor once it's in flight
+        queue.take(1, u64::MAX).await;
+        assert!(queue.unanchored(0).await);
+
+        let result: JobResult = Err(Failure::new(FailureReason::Timeout, "boom"));
+        queue.complete(&ids[0], 0, &result, 10).await;
+        assert!(!queue.unanchored(0).await);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let queue = Queue::default();
+        let ids = ids(2);
+        let a = queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        queue.enqueue(0, ids[0].clone(), requester("b")).await;
+        queue.enqueue(0, ids[1].clone(), requester("a")).await;
+
+        assert_eq!(
+            queue.cancel(&ids[0], &requester("c")).await,
+            Err(CancelError::NotQueued(ids[0].clone()))
+        );
+        // Still wanted by b
+        queue.cancel(&ids[0], &requester("a")).await.unwrap();
+        assert!(a.await.is_err());
+        assert_eq!(queue.len().await, 2);
+        queue.cancel(&ids[0], &requester("b")).await.unwrap();
+        assert_eq!(queue.pending(DEFAULT_TENANT).await, vec![ids[1].clone()]);
+
+        queue.take(1, u64::MAX).await;
+        assert_eq!(
+            queue.cancel(&ids[1], &requester("a")).await,
+            Err(CancelError::InFlight(ids[1].clone()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests() {
+        let queue = Queue::default();
+        let ids = ids(3);
+        queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        queue.enqueue(2, ids[1].clone(), requester("a")).await;
+        queue.enqueue(2, ids[1].clone(), requester("b")).await;
+        queue
+            .enqueue(0, ids[2].clone(), Requester::new("bridge", "a"))
+            .await;
+        queue.take(1, u64::MAX).await;
+
+        let requests = queue.requests(DEFAULT_TENANT).await;
+        assert_eq!(
+            requests
+                .iter()
+                .map(|r| (r.id.clone(), r.position, r.requesters.clone()))
+                .collect_vec(),
+            vec![
+                (ids[0].clone(), Some(0), vec!["a".to_string()]),
+                (ids[1].clone(), None, vec!["a".to_string(), "b".to_string()]),
+            ]
+        );
+        assert_eq!(queue.requests("bridge").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_durable_queue_survives_restarts() {
+        let store: Arc<_> = Store(store::sled::temporary().unwrap().into()).into();
+        let ids = ids(4);
+        let queue = Queue::durable(store.clone());
+        queue.enqueue(0, ids[0].clone(), requester("a")).await;
+        queue.enqueue(5, ids[1].clone(), requester("b")).await;
+        queue.enqueue(0, ids[2].clone(), requester("a")).await;
+        queue.enqueue(0, ids[3].clone(), requester("a")).await;
+        queue.cancel(&ids[3], &requester("a")).await.unwrap();
+        // Taken, but no batch was prepared before the restart
+        assert_eq!(queue.take(1, u64::MAX).await, vec![ids[1].clone()]);
+
+        let restarted = Queue::durable(store.clone());
+        assert_eq!(restarted.restore().await.unwrap(), 3);
+        assert_eq!(
+            restarted.pending(DEFAULT_TENANT).await,
+            vec![ids[1].clone(), ids[0].clone(), ids[2].clone()]
+        );
+        // Requesters are kept, though nobody is waiting on them any more
+        assert_eq!(restarted.take(1, u64::MAX).await, vec![ids[1].clone()]);
+        let result: JobResult = Err(Failure::new(FailureReason::Timeout, "boom"));
+        assert_eq!(
+            restarted.complete(&ids[1], 0, &result, 10).await,
+            vec![(requester("b"), 10)]
+        );
+
+        let restarted = Queue::durable(store);
+        assert_eq!(restarted.restore().await.unwrap(), 2);
+        assert_eq!(
+            restarted.pending(DEFAULT_TENANT).await,
+            vec![ids[0].clone(), ids[2].clone()]
+        );
+        assert_eq!(Queue::default().restore().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_split_cost() {
+        assert_eq!(split_cost(10, 3).collect_vec(), vec![4, 3, 3]);
+        assert_eq!(split_cost(9, 3).sum::<u64>(), 9);
+        assert_eq!(split_cost(5, 0).collect_vec(), vec![5]);
+    }
+}
@@ -0,0 +1,280 @@
+use near_primitives::{
+    types::Balance,
+    views::{ActionView, ReceiptEnumView, ReceiptView},
+};
+use protocol::balance;
+use serde::Deserializer;
+
+use crate::prelude::*;
+
+/// Higher priorities are proven first.
+pub type Priority = u8;
+
+/// Decides which receipts are automatically proven and at which priority.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Rules(pub Vec<Rule>);
+
+impl Rules {
+    /// The highest priority of any matching rule, `None` if nothing matched.
+    pub fn evaluate(&self, receipt: &ReceiptView) -> Option<Priority> {
+        self.0
+            .iter()
+            .filter(|r| r.matches(receipt))
+            .map(|r| r.priority)
+            .max()
+    }
+}
+
+/// A single auto-proving policy, every configured condition must hold.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rule {
+    /// Glob over the receiver, `*` matches any run of characters and `?` a
+    /// single character.
+    pub receiver: String,
+    /// Glob over the predecessor, any predecessor matches if not set.
+    #[serde(default)]
+    pub predecessor: Option<String>,
+    /// Function calls the receipt must contain at least one of, any receipt
+    /// matches if empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Minimum attached deposit in yoctoNEAR summed over every action.
+    #[serde(default, deserialize_with = "deserialize_balance")]
+    pub min_deposit: Balance,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+impl Rule {
+    pub fn matches(&self, receipt: &ReceiptView) -> bool {
+        if !glob_match(&self.receiver, receipt.receiver_id.as_str()) {
+            return false;
+        }
+        if let Some(predecessor) = &self.predecessor {
+            if !glob_match(predecessor, receipt.predecessor_id.as_str()) {
+                return false;
+            }
+        }
+
+        let actions = match &receipt.receipt {
+            ReceiptEnumView::Action { actions, .. } => &actions[..],
+            // Data receipts carry no methods or deposits
+            ReceiptEnumView::Data { .. } => &[],
+        };
+
+        if !self.methods.is_empty()
+            && !actions.iter().any(|a| match a {
+                ActionView::FunctionCall { method_name, .. } => {
+                    self.methods.iter().any(|m| m == method_name)
+                }
+                _ => false,
+            })
+        {
+            return false;
+        }
+
+        deposit(actions) >= self.min_deposit
+    }
+}
+
+fn deposit(actions: &[ActionView]) -> Balance {
+    actions
+        .iter()
+        .map(|a| match a {
+            ActionView::FunctionCall { deposit, .. } | ActionView::Transfer { deposit } => *deposit,
+            _ => 0,
+        })
+        .fold(0, Balance::saturating_add)
+}
+
+/// Balances overflow TOML integers, so we also accept them as strings, either
+/// in yoctoNEAR or in NEAR with a ` NEAR` suffix.
+fn deserialize_balance<'de, D: Deserializer<'de>>(d: D) -> Result<Balance, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Int(u64),
+        Str(String),
+    }
+    match Repr::deserialize(d)? {
+        Repr::Int(i) => Ok(i as Balance),
+        Repr::Str(s) => match s.strip_suffix("NEAR") {
+            Some(near) => balance::parse_near(near.trim_end()).map_err(serde::de::Error::custom),
+            None => s.parse().map_err(serde::de::Error::custom),
+        },
+    }
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let (p, v) = (pattern.as_bytes(), value.as_bytes());
+    let (mut pi, mut vi) = (0, 0);
+    // The last `*` seen and the value position it was tried from
+    let mut backtrack = None;
+
+    while vi < v.len() {
+        match p.get(pi) {
+            Some(b'*') => {
+                backtrack = Some((pi, vi));
+                pi += 1;
+            }
+            Some(c) if *c == b'?' || *c == v[vi] => {
+                pi += 1;
+                vi += 1;
+            }
+            _ => match backtrack {
+                Some((star, from)) => {
+                    pi = star + 1;
+                    vi = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::hash::CryptoHash;
+
+    use super::*;
+
+    fn receipt(receiver: &str, actions: Vec<ActionView>) -> ReceiptView {
+        serde_json::from_value(serde_json::json!({
+            "predecessor_id": "alice.near",
+            "receiver_id": receiver,
+            "receipt_id": CryptoHash::default(),
+            "receipt": {
+                "Action": {
+                    "signer_id": "alice.near",
+                    "signer_public_key": "ed25519:11111111111111111111111111111111",
+                    "gas_price": "0",
+                    "output_data_receivers": [],
+                    "input_data_ids": [],
+                    "actions": actions,
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn call(method: &str, deposit: Balance) -> ActionView {
+        ActionView::FunctionCall {
+            method_name: method.to_string(),
+            args: vec![].into(),
+            gas: 0,
+            deposit,
+        }
+    }
+
+    fn rule(receiver: &str) -> Rule {
+        Rule {
+            receiver: receiver.to_string(),
+            predecessor: None,
+            methods: vec![],
+            min_deposit: 0,
+            priority: 0,
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match("*.near", "bridge.near"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c.*.near", "abc.x.y.near"));
+        assert!(glob_match("*bridge*", "my.bridge.near"));
+        assert!(!glob_match("*.near", "bridge.testnet"));
+        assert!(!glob_match("bridge.near", "bridge.near.x"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn test_rule_conditions() {
+        let r = receipt("bridge.near", vec![call("deposit", 10)]);
+
+        assert!(rule("*.near").matches(&r));
+        assert!(!rule("*.testnet").matches(&r));
+
+        let methods = Rule {
+            methods: vec!["withdraw".into()],
+            ..rule("*")
+        };
+        assert!(!methods.matches(&r));
+        let methods = Rule {
+            methods: vec!["withdraw".into(), "deposit".into()],
+            ..methods
+        };
+        assert!(methods.matches(&r));
+
+        let min_deposit = Rule {
+            min_deposit: 11,
+            ..rule("*")
+        };
+        assert!(!min_deposit.matches(&r));
+
+        let predecessor = Rule {
+            predecessor: Some("bob.*".into()),
+            ..rule("*")
+        };
+        assert!(!predecessor.matches(&r));
+    }
+
+    #[test]
+    fn test_highest_priority_wins() {
+        let rules = Rules(vec![
+            Rule {
+                priority: 1,
+                ..rule("*")
+            },
+            Rule {
+                priority: 5,
+                min_deposit: 100,
+                ..rule("*")
+            },
+        ]);
+        let cheap = receipt("bridge.near", vec![call("deposit", 10)]);
+        let expensive = receipt(
+            "bridge.near",
+            vec![call("deposit", 10), call("deposit", 90)],
+        );
+
+        assert_eq!(rules.evaluate(&cheap), Some(1));
+        assert_eq!(rules.evaluate(&expensive), Some(5));
+        assert_eq!(Rules::default().evaluate(&cheap), None);
+    }
+
+    #[test]
+    fn test_deserialize_large_deposit() {
+        let rule: Rule = serde_json::from_value(serde_json::json!({
+            "receiver": "*.near",
+            "min_deposit": "1000000000000000000000000",
+        }))
+        .unwrap();
+        assert_eq!(rule.min_deposit, 10u128.pow(24));
+    }
+
+    #[test]
+    fn test_deserialize_deposit_in_near() {
+        let rule: Rule = serde_json::from_value(serde_json::json!({
+            "receiver": "*.near",
+            "min_deposit": "1.5 NEAR",
+        }))
+        .unwrap();
+        assert_eq!(rule.min_deposit, balance::YOCTO_PER_NEAR * 3 / 2);
+
+        let rule: Rule = serde_json::from_value(serde_json::json!({
+            "receiver": "*.near",
+            "min_deposit": 42,
+        }))
+        .unwrap();
+        assert_eq!(rule.min_deposit, 42);
+
+        assert!(serde_json::from_value::<Rule>(serde_json::json!({
+            "receiver": "*.near",
+            "min_deposit": "0.5 yocto",
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rpc::limits::Usage;
+use tokio::sync::Semaphore;
+
+use super::metrics::render_histogram;
+use crate::{config::RuntimeConfig, prelude::*};
+
+/// Runs CPU heavy work, like verifying signatures and building proofs, on the
+/// blocking pool, bounded so it can't take every blocking thread nor starve
+/// the async workers.
+#[derive(Debug, Clone)]
+pub struct CpuPool(Arc<Semaphore>);
+
+impl CpuPool {
+    pub fn new(threads: usize) -> Self {
+        Self(Arc::new(Semaphore::new(threads.max(1))))
+    }
+
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let _permit = self.0.acquire().await?;
+        Ok(tokio::task::spawn_blocking(f).await?)
+    }
+}
+
+/// Measures how late the runtime wakes a task that should sleep for a fixed
+/// interval. A starved runtime shows up as lag long before requests time out.
+#[derive(Debug)]
+pub struct RuntimeHealth {
+    config: RuntimeConfig,
+    last_lag_ms: AtomicU64,
+    max_lag_ms: AtomicU64,
+    lagged: AtomicU64,
+}
+
+impl RuntimeHealth {
+    pub fn new(config: RuntimeConfig) -> Self {
+        Self {
+            config,
+            last_lag_ms: Default::default(),
+            max_lag_ms: Default::default(),
+            lagged: Default::default(),
+        }
+    }
+
+    pub async fn start(self: Arc<Self>) {
+        let interval = Duration::from_millis(self.config.probe_interval_ms);
+        loop {
+            let start = Instant::now();
+            tokio::time::sleep(interval).await;
+            self.observe(start.elapsed().saturating_sub(interval));
+        }
+    }
+
+    fn observe(&self, lag: Duration) {
+        let lag_ms = lag.as_millis() as u64;
+        self.last_lag_ms.store(lag_ms, Ordering::Relaxed);
+        self.max_lag_ms.fetch_max(lag_ms, Ordering::Relaxed);
+        if lag_ms > self.config.lag_warn_ms {
+            self.lagged.fetch_add(1, Ordering::Relaxed);
+            log::warn!("Runtime lagged by {}ms, is it starved?", lag_ms);
+        }
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP light_client_runtime_lag_ms How late the runtime woke the probe\n");
+        out.push_str("# TYPE light_client_runtime_lag_ms gauge\n");
+        out.push_str(&format!(
+            "light_client_runtime_lag_ms {}\n",
+            self.last_lag_ms.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP light_client_runtime_lag_max_ms The worst lag since starting\n");
+        out.push_str("# TYPE light_client_runtime_lag_max_ms gauge\n");
+        out.push_str(&format!(
+            "light_client_runtime_lag_max_ms {}\n",
+            self.max_lag_ms.load(Ordering::Relaxed)
+        ));
+        out.push_str(
+            "# HELP light_client_runtime_lagged_total Probes that lagged more than the warning \
+             threshold\n",
+        );
+        out.push_str("# TYPE light_client_runtime_lagged_total counter\n");
+        out.push_str(&format!(
+            "light_client_runtime_lagged_total {}\n",
+            self.lagged.load(Ordering::Relaxed)
+        ));
+        out
+    }
+}
+
+/// Render RPC endpoint usage in the prometheus text format.
+pub fn render_rpc(usage: &[Usage]) -> String {
+    type Value = fn(&Usage) -> u64;
+    let metrics: [(&str, &str, &str, Value); 4] = [
+        (
+            "light_client_rpc_in_flight",
+            "Requests in flight to each endpoint",
+            "gauge",
+            |u| u.in_flight as u64,
+        ),
+        (
+            "light_client_rpc_max_concurrent",
+            "Requests each endpoint allows in flight",
+            "gauge",
+            |u| u.max_concurrent as u64,
+        ),
+        (
+            "light_client_rpc_requests_total",
+            "Requests made to each endpoint",
+            "counter",
+            |u| u.requests,
+        ),
+        (
+            "light_client_rpc_throttled_total",
+            "Requests that waited on an endpoint's limits",
+            "counter",
+            |u| u.throttled,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, kind, value) in metrics {
+        out.push_str(&format!("# HELP {} {}\n", name, help));
+        out.push_str(&format!("# TYPE {} {}\n", name, kind));
+        for u in usage {
+            out.push_str(&format!(
+                "{}{{endpoint=\"{}\"}} {}\n",
+                name,
+                u.endpoint,
+                value(u)
+            ));
+        }
+    }
+    out.push_str(&render_histogram(
+        "light_client_rpc_latency_seconds",
+        "How long requests to each endpoint took",
+        usage
+            .iter()
+            .map(|u| (format!("endpoint=\"{}\"", u.endpoint), u.latency.clone())),
+    ));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use rpc::histogram::{Histogram, LATENCY_BUCKETS_MS};
+
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let health = RuntimeHealth::new(RuntimeConfig {
+            cpu_threads: 1,
+            probe_interval_ms: 100,
+            lag_warn_ms: 50,
+        });
+        health.observe(Duration::from_millis(80));
+        health.observe(Duration::from_millis(10));
+
+        let metrics = health.render();
+        assert!(metrics.contains("light_client_runtime_lag_ms 10\n"));
+        assert!(metrics.contains("light_client_runtime_lag_max_ms 80\n"));
+        assert!(metrics.contains("light_client_runtime_lagged_total 1\n"));
+    }
+
+    #[test]
+    fn test_render_rpc() {
+        let latency = Histogram::new(LATENCY_BUCKETS_MS);
+        latency.observe(Duration::from_millis(200));
+        let metrics = render_rpc(&[Usage {
+            endpoint: "archive",
+            in_flight: 3,
+            max_concurrent: 4,
+            requests: 10,
+            throttled: 2,
+            latency: latency.snapshot(),
+        }]);
+        assert!(metrics.contains("light_client_rpc_in_flight{endpoint=\"archive\"} 3\n"));
+        assert!(metrics.contains("light_client_rpc_throttled_total{endpoint=\"archive\"} 2\n"));
+        assert!(metrics.contains(
+            "light_client_rpc_latency_seconds_bucket{endpoint=\"archive\",le=\"0.25\"} 1\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cpu_pool_bounds_concurrency() {
+        let pool = CpuPool::new(2);
+        let running = Arc::new(AtomicU64::new(0));
+        let peak = Arc::new(AtomicU64::new(0));
+
+        let tasks = (0..8)
+            .map(|_| {
+                let (pool, running, peak) = (pool.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    pool.run(move || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect_vec();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use coerce::actor::LocalActorRef;
+use near_primitives::types::TransactionOrReceiptId;
+use protocol::{
+    experimental::Proof as ExperimentalProof,
+    packing::{pack_verify_results, VerifyResult, CALLDATA_BUDGET},
+    timestamp::Timestamp,
+};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Mutex, RwLock,
+};
+
+use super::{
+    audit::{Action, AuditLog},
+    failure::{Failure, FailureCounters},
+    hooks::Hooks,
+    message::{GetAnchor, PrepareBatch},
+    metrics::ProvingMetrics,
+    queue::{AnchoredProof, JobResult, Queue, Requester},
+    runtime::CpuPool,
+    store::{self, Charge, Collection, Pipeline, PreparedBatch, Store},
+    tenant::Tenant,
+    LightClient,
+};
+use crate::{
+    build_info::BuildInfo,
+    config::{OutputMode, SchedulerConfig},
+    prelude::*,
+};
+
+/// Drains the queue in batches, proving each batch together and fanning the
+/// result out to everyone that requested a slot in it.
+///
+/// Preparing a batch, fetching its proofs from the RPC, is a separate stage
+/// from proving it. Prepared batches are persisted and handed to a pool of
+/// provers, so a slow RPC doesn't idle the provers, and a failed proving is
+/// retried without fetching anything again.
+pub struct Scheduler {
+    config: SchedulerConfig,
+    queue: Arc<Queue>,
+    ledger: Arc<Ledger>,
+    failures: Arc<FailureCounters>,
+    audit: Arc<AuditLog>,
+    store: Arc<Store<store::sled::Store>>,
+    cpu: CpuPool,
+    hooks: Arc<Hooks>,
+    metrics: Arc<ProvingMetrics>,
+    client: LocalActorRef<LightClient>,
+}
+
+impl Scheduler {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: SchedulerConfig,
+        queue: Arc<Queue>,
+        ledger: Arc<Ledger>,
+        failures: Arc<FailureCounters>,
+        audit: Arc<AuditLog>,
+        store: Arc<Store<store::sled::Store>>,
+        cpu: CpuPool,
+        hooks: Arc<Hooks>,
+        metrics: Arc<ProvingMetrics>,
+        client: LocalActorRef<LightClient>,
+    ) -> Self {
+        Self {
+            config,
+            queue,
+            ledger,
+            failures,
+            audit,
+            store,
+            cpu,
+            hooks,
+            metrics,
+            client,
+        }
+    }
+
+    pub async fn start(self) {
+        let scheduler = Arc::new(self);
+        let (ready, rx) = mpsc::unbounded_channel();
+
+        // Anything prepared before a restart is still ready to prove
+        match scheduler.store.prepared().await {
+            Ok(keys) => {
+                for key in keys {
+                    let _ = ready.send(key);
+                }
+            }
+            Err(e) => log::error!("Failed to load prepared batches: {:?}", e),
+        }
+
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..scheduler.config.provers.max(1) {
+            tokio::task::spawn(scheduler.clone().prover(rx.clone(), ready.clone()));
+        }
+
+        let interval = Duration::from_millis(scheduler.config.interval_ms);
+        loop {
+            tokio::time::sleep(interval).await;
+            scheduler.prepare_batch(&ready).await;
+        }
+    }
+
+    async fn prepare_batch(&self, ready: &UnboundedSender<CryptoHash>) {
+        // Slots are bound to the freshest head when their batch is formed,
+        // rather than when they were requested
+        let head = match self.store.head().await {
+            Ok(head) => head,
+            Err(e) => {
+                log::error!("Failed to load the head: {:?}", e);
+                return;
+            }
+        };
+        let ids = self
+            .queue
+            .take(
+                self.config.batch_size,
+                Timestamp::from(&head.inner_lite).as_nanos(),
+            )
+            .await;
+        if ids.is_empty() {
+            return;
+        }
+        log::debug!("Preparing batch of {}", ids.len());
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = tokio::time::timeout(timeout, self.client.send(PrepareBatch(ids.clone())))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|r| r.map_err(|e| anyhow!(e)))
+                .and_then(|r| r);
+            match result {
+                Ok(Some(key)) => {
+                    let _ = ready.send(key);
+                    return;
+                }
+                Ok(None) => {
+                    log::debug!("Every slot is waiting on a newer head");
+                    return;
+                }
+                Err(e) if attempts < self.config.attempts => {
+                    log::warn!("Failed to prepare batch, attempt {}: {:?}", attempts, e);
+                    tokio::time::sleep(Duration::from_millis(self.config.interval_ms)).await;
+                }
+                Err(e) => {
+                    log::error!("Error preparing batch: {}", e);
+                    self.complete(&ids, &Err(Failure::from(&e))).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn prover(
+        self: Arc<Self>,
+        ready: Arc<Mutex<UnboundedReceiver<CryptoHash>>>,
+        retry: UnboundedSender<CryptoHash>,
+    ) {
+        loop {
+            let Some(key) = ready.lock().await.recv().await else {
+                return;
+            };
+            self.prove_batch(key, &retry).await;
+        }
+    }
+
+    async fn prove_batch(&self, key: CryptoHash, retry: &UnboundedSender<CryptoHash>) {
+        let loaded = self
+            .store
+            .get(&Collection::Prepared, &key)
+            .await
+            .and_then(|e| e.prepared())
+            .and_then(|batch| Ok((batch.slots()?, batch)));
+        let (slots, mut batch) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                log::error!("Failed to load prepared batch {}: {:?}", key, e);
+                return;
+            }
+        };
+        let ids = slots.iter().map(|(id, _)| id.clone()).collect_vec();
+        log::debug!("Proving batch of {}", ids.len());
+
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let start = Instant::now();
+        let result = tokio::time::timeout(timeout, self.prove(&batch, slots))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r)
+            .map_err(|e| Failure::from(&e));
+        match &result {
+            Ok(proof) => {
+                self.metrics.record(ids.len(), start.elapsed());
+                self.audit
+                    .try_record(Action::ProofDelivered {
+                        root: proof.proof.head_block_root,
+                        slots: ids.len(),
+                    })
+                    .await
+            }
+            Err(e) if batch.attempts + 1 < self.config.attempts => {
+                batch.attempts += 1;
+                log::warn!("Failed to prove batch, attempt {}: {}", batch.attempts, e);
+                match self.store.insert(&[(key, batch.into())]).await {
+                    Ok(()) => {
+                        tokio::time::sleep(Duration::from_millis(self.config.interval_ms)).await;
+                        let _ = retry.send(key);
+                        return;
+                    }
+                    Err(e) => log::error!("Failed to record attempt for {}: {:?}", key, e),
+                }
+            }
+            Err(e) => log::error!("Error proving batch: {}", e),
+        }
+
+        // The head is proven with the batch that proved it, a crash in
+        // between proves it again
+        let marks = match &result {
+            Ok(AnchoredProof {
+                anchor: Some(anchor),
+                ..
+            }) => vec![Pipeline::Proven.mark(anchor.height)],
+            _ => vec![],
+        };
+        if let Err(e) = self
+            .store
+            .write(&marks, &[(Collection::Prepared, key)])
+            .await
+        {
+            log::error!("Failed to remove prepared batch {}: {:?}", key, e);
+        }
+        self.complete(&ids, &result).await;
+    }
+
+    async fn prove(
+        &self,
+        batch: &PreparedBatch,
+        slots: Vec<(TransactionOrReceiptId, BasicProof)>,
+    ) -> Result<AnchoredProof> {
+        let root = batch.root;
+        let proofs = slots.into_iter().map(|(_, proof)| proof).collect_vec();
+        let output = self.config.output;
+        let (proof, packed_results) = self
+            .cpu
+            .run(move || {
+                let packed = (output == OutputMode::Packed).then(|| pack_results(&root, &proofs));
+                (ExperimentalProof::new(root, proofs), packed)
+            })
+            .await?;
+        let anchor = self
+            .client
+            .send(GetAnchor { root })
+            .await
+            .map_err(|e| anyhow!(e))?;
+        let mut proof = AnchoredProof {
+            proof,
+            anchor,
+            build: BuildInfo::get(),
+            attestations: vec![],
+            packed_results,
+        };
+        self.hooks.batch(&mut proof).await?;
+        Ok(proof)
+    }
+
+    /// Deliver the result to each slot's requesters and charge them.
+    async fn complete(&self, ids: &[TransactionOrReceiptId], result: &JobResult) {
+        for (slot, id) in ids.iter().enumerate() {
+            if let Err(e) = result {
+                self.failures.record_failure(Some(job_id(id)), e);
+            }
+            let charges = self
+                .queue
+                .complete(id, slot, result, self.config.slot_cost)
+                .await;
+            self.ledger.charge(charges).await;
+        }
+    }
+}
+
+/// The verify results of a batch, verified natively and packed.
+fn pack_results(root: &CryptoHash, proofs: &[BasicProof]) -> String {
+    let results = proofs
+        .iter()
+        .map(|p| VerifyResult::native(root, p))
+        .collect_vec();
+    let packed = pack_verify_results(&results);
+    if packed.len() > CALLDATA_BUDGET {
+        log::warn!(
+            "Packed results of {} slots take {} bytes, over the calldata budget",
+            results.len(),
+            packed.len()
+        );
+    }
+    format!("0x{}", hex::encode(packed))
+}
+
+/// The id of a slot, as reported in failures.
+fn job_id(id: &TransactionOrReceiptId) -> String {
+    match id {
+        TransactionOrReceiptId::Transaction {
+            transaction_hash, ..
+        } => transaction_hash.to_string(),
+        TransactionOrReceiptId::Receipt { receipt_id, .. } => receipt_id.to_string(),
+    }
+}
+
+/// What each requester has been charged for their slots so far.
+///
+/// A durable ledger writes each requester's total to the store as it is
+/// charged, so spend, and the quotas checked against it, survive a restart,
+/// see `restore`.
+#[derive(Default)]
+pub struct Ledger {
+    charges: RwLock<HashMap<Requester, u64>>,
+    store: Option<Arc<Store<store::sled::Store>>>,
+}
+
+impl Ledger {
+    pub fn durable(store: Arc<Store<store::sled::Store>>) -> Self {
+        Self {
+            charges: Default::default(),
+            store: Some(store),
+        }
+    }
+
+    /// Reload the charges made before a restart, returning how many
+    /// requesters had been charged.
+    pub async fn restore(&self) -> Result<usize> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let charges = store.charges().await?;
+        let restored = charges.len();
+        let mut ledger = self.charges.write().await;
+        for charge in charges {
+            ledger.insert(charge.requester, charge.cost);
+        }
+        Ok(restored)
+    }
+
+    pub async fn charge(&self, charges: impl IntoIterator<Item = (Requester, u64)>) {
+        let mut ledger = self.charges.write().await;
+        let mut totals: Vec<(CryptoHash, store::Entity)> = vec![];
+        for (requester, cost) in charges {
+            let total = ledger.entry(requester.clone()).or_default();
+            *total += cost;
+            totals.push((
+                Charge::key(&requester),
+                Charge {
+                    requester,
+                    cost: *total,
+                }
+                .into(),
+            ));
+        }
+        // Written under the lock so a later total never lands first
+        if let Some(store) = &self.store {
+            if let Err(e) = store.insert(&totals).await {
+                log::error!("Failed to save {} charges: {:?}", totals.len(), e);
+            }
+        }
+    }
+
+    /// What each of a tenant's requesters has been charged, by name.
+    pub async fn costs(&self, tenant: &str) -> HashMap<String, u64> {
+        self.charges
+            .read()
+            .await
+            .iter()
+            .filter(|(requester, _)| requester.tenant == tenant)
+            .map(|(requester, cost)| (requester.name.clone(), *cost))
+            .collect()
+    }
+
+    /// What a tenant has been charged over all of its requesters.
+    pub async fn spent(&self, tenant: &str) -> u64 {
+        self.costs(tenant).await.values().sum()
+    }
+
+    pub async fn by_tenant(&self) -> HashMap<Tenant, u64> {
+        let mut totals = HashMap::new();
+        for (requester, cost) in self.charges.read().await.iter() {
+            *totals.entry(requester.tenant.clone()).or_default() += cost;
+        }
+        totals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::packing::unpack_verify_results;
+    use test_utils::fixture;
+
+    use super::*;
+    use crate::client::tenant::DEFAULT_TENANT;
+
+    #[test]
+    fn test_pack_results() {
+        let proof: BasicProof = fixture("old.json");
+        let root = CryptoHash::default();
+        let packed = pack_results(&root, &[proof.clone()]);
+
+        let results = unpack_verify_results(&hex::decode(&packed[2..]).unwrap()).unwrap();
+        assert_eq!(results, vec![VerifyResult::native(&root, &proof)]);
+        assert_eq!(results[0].id, proof.outcome_proof.id);
+        // Not against this root
+        assert!(!results[0].passed);
+    }
+
+    #[tokio::test]
+    async fn test_ledger_accumulates() {
+        let ledger = Ledger::default();
+        ledger
+            .charge([(Requester::operator("a"), 4), (Requester::operator("b"), 3)])
+            .await;
+        ledger.charge([(Requester::operator("a"), 5)]).await;
+
+        let costs = ledger.costs(DEFAULT_TENANT).await;
+        assert_eq!(costs["a"], 9);
+        assert_eq!(costs["b"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_ledger_is_per_tenant() {
+        let ledger = Ledger::default();
+        ledger
+            .charge([
+                (Requester::new("bridge", "a"), 4),
+                (Requester::new("wallet", "a"), 3),
+                (Requester::new("wallet", "b"), 2),
+            ])
+            .await;
+
+        assert_eq!(
+            ledger.costs("bridge").await,
+            HashMap::from([("a".to_string(), 4)])
+        );
+        assert_eq!(ledger.spent("wallet").await, 5);
+        assert_eq!(ledger.spent("other").await, 0);
+        assert_eq!(ledger.by_tenant().await["wallet"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_durable_ledger_survives_restarts() {
+        let store: Arc<_> = Store(store::sled::temporary().unwrap().into()).into();
+        let ledger = Ledger::durable(store.clone());
+        ledger
+            .charge([
+                (Requester::new("bridge", "a"), 4),
+                (Requester::new("wallet", "a"), 3),
+            ])
+            .await;
+        ledger.charge([(Requester::new("bridge", "a"), 5)]).await;
+
+        let restarted = Ledger::durable(store.clone());
+        assert_eq!(restarted.restore().await.unwrap(), 2);
+        assert_eq!(restarted.spent("bridge").await, 9);
+        assert_eq!(restarted.spent("wallet").await, 3);
+
+        restarted.charge([(Requester::new("wallet", "a"), 1)]).await;
+        let restarted = Ledger::durable(store);
+        restarted.restore().await.unwrap();
+        assert_eq!(restarted.spent("wallet").await, 4);
+        assert_eq!(Ledger::default().restore().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use near_primitives::views::LightClientBlockView;
+
+use crate::{config::SelectionConfig, prelude::*};
+
+/// Why a light client block was chosen to sync to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Crosses into the next epoch, we can't follow the block producers
+    /// without it.
+    EpochBoundary,
+    /// Requests are waiting on a newer head to be anchored against.
+    Requested,
+    /// Nothing else chose a block within the configured interval.
+    Heartbeat,
+}
+
+impl Target {
+    pub const ALL: [Target; 3] = [Self::EpochBoundary, Self::Requested, Self::Heartbeat];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EpochBoundary => "epoch_boundary",
+            Self::Requested => "requested",
+            Self::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+/// Chooses which light client blocks are worth syncing to, rather than
+/// following every final block. Each head we sync to may need proving and
+/// relaying, so we only take the ones we need.
+#[derive(Debug)]
+pub struct Selector {
+    config: SelectionConfig,
+    selected: [AtomicU64; Target::ALL.len()],
+    skipped: AtomicU64,
+}
+
+impl Selector {
+    pub fn new(config: SelectionConfig) -> Self {
+        Self {
+            config,
+            selected: Default::default(),
+            skipped: Default::default(),
+        }
+    }
+
+    /// Whether to sync from `head` to `next`, `unanchored` is whether any
+    /// requests need a head newer than ours.
+    pub fn select(
+        &self,
+        head: &Header,
+        next: &LightClientBlockView,
+        unanchored: bool,
+    ) -> Option<Target> {
+        let crosses_epoch =
+            next.inner_lite.epoch_id != head.inner_lite.epoch_id || next.next_bps.is_some();
+        let blocks = next
+            .inner_lite
+            .height
+            .saturating_sub(head.inner_lite.height);
+
+        let target = self.decide(crosses_epoch, blocks, unanchored);
+        match target {
+            Some(target) => {
+                log::debug!("Selected {} as {:?}", next.inner_lite.height, target);
+                self.selected[target as usize].fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                log::trace!("Skipping {}", next.inner_lite.height);
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        target
+    }
+
+    fn decide(&self, crosses_epoch: bool, blocks: u64, unanchored: bool) -> Option<Target> {
+        if crosses_epoch {
+            Some(Target::EpochBoundary)
+        } else if unanchored && blocks > 0 {
+            Some(Target::Requested)
+        } else if blocks >= self.config.heartbeat_blocks {
+            Some(Target::Heartbeat)
+        } else {
+            None
+        }
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP light_client_sync_targets_total Light client blocks synced to\n");
+        out.push_str("# TYPE light_client_sync_targets_total counter\n");
+        for target in Target::ALL {
+            out.push_str(&format!(
+                "light_client_sync_targets_total{{target=\"{}\"}} {}\n",
+                target.as_str(),
+                self.selected[target as usize].load(Ordering::Relaxed)
+            ));
+        }
+        out.push_str(
+            "# HELP light_client_sync_skipped_total Light client blocks not worth syncing to\n",
+        );
+        out.push_str("# TYPE light_client_sync_skipped_total counter\n");
+        out.push_str(&format!(
+            "light_client_sync_skipped_total {}\n",
+            self.skipped.load(Ordering::Relaxed)
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector() -> Selector {
+        Selector::new(SelectionConfig {
+            heartbeat_blocks: 100,
+        })
+    }
+
+    #[test]
+    fn test_epoch_boundaries_are_always_selected() {
+        let s = selector();
+        assert_eq!(s.decide(true, 1, false), Some(Target::EpochBoundary));
+        assert_eq!(s.decide(true, 0, true), Some(Target::EpochBoundary));
+    }
+
+    #[test]
+    fn test_requests_are_anchored() {
+        let s = selector();
+        assert_eq!(s.decide(false, 1, true), Some(Target::Requested));
+        // The same block as our head can't anchor anything new
+        assert_eq!(s.decide(false, 0, true), None);
+        assert_eq!(s.decide(false, 1, false), None);
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let s = selector();
+        assert_eq!(s.decide(false, 99, false), None);
+        assert_eq!(s.decide(false, 100, false), Some(Target::Heartbeat));
+    }
+}
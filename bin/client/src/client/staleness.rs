@@ -0,0 +1,168 @@
+use std::{
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use protocol::timestamp::Timestamp;
+
+use crate::{config::StalenessConfig, prelude::*};
+
+const NANOS_PER_MILLI: i64 = 1_000_000;
+
+/// How the head's timestamp compares to local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Freshness {
+    Fresh {
+        age_ms: u64,
+    },
+    Stale {
+        age_ms: u64,
+    },
+    /// The head is further in the future than the tolerance allows, either our
+    /// clock or the producers' is off. This is never treated as stale.
+    Skewed {
+        ahead_ms: u64,
+    },
+}
+
+/// Decides whether the head is stale, allowing for skew between our clock and
+/// the block producers'.
+#[derive(Debug)]
+pub struct Staleness {
+    config: StalenessConfig,
+    /// Head timestamp minus local time at the last check, positive when the
+    /// head is ahead of us.
+    last_skew_ms: AtomicI64,
+    skewed: AtomicU64,
+    stale: AtomicU64,
+}
+
+impl Staleness {
+    pub fn new(config: StalenessConfig) -> Self {
+        Self {
+            config,
+            last_skew_ms: Default::default(),
+            skewed: Default::default(),
+            stale: Default::default(),
+        }
+    }
+
+    pub fn check(&self, head: &Header) -> Freshness {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        self.check_at(Timestamp::from(&head.inner_lite).as_nanos(), now)
+    }
+
+    fn check_at(&self, head_timestamp_ns: u64, now_ns: u64) -> Freshness {
+        let skew_ms = (head_timestamp_ns as i64 - now_ns as i64) / NANOS_PER_MILLI;
+        self.last_skew_ms.store(skew_ms, Ordering::Relaxed);
+
+        let freshness = if skew_ms > self.config.skew_tolerance_ms as i64 {
+            self.skewed.fetch_add(1, Ordering::Relaxed);
+            Freshness::Skewed {
+                ahead_ms: skew_ms as u64,
+            }
+        } else {
+            // A head slightly in the future is just fresh
+            let age_ms = (-skew_ms).max(0) as u64;
+            if age_ms > self.config.max_head_age_ms + self.config.skew_tolerance_ms {
+                self.stale.fetch_add(1, Ordering::Relaxed);
+                Freshness::Stale { age_ms }
+            } else {
+                Freshness::Fresh { age_ms }
+            }
+        };
+        if !matches!(freshness, Freshness::Fresh { .. }) {
+            log::warn!(
+                "Head freshness {:?}, tolerance {}ms",
+                freshness,
+                self.config.skew_tolerance_ms
+            );
+        }
+        freshness
+    }
+
+    /// Render in the prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP light_client_head_skew_ms Head timestamp minus local time at the last check\n",
+        );
+        out.push_str("# TYPE light_client_head_skew_ms gauge\n");
+        out.push_str(&format!(
+            "light_client_head_skew_ms {}\n",
+            self.last_skew_ms.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP light_client_head_checks_total Head checks that were not fresh\n");
+        out.push_str("# TYPE light_client_head_checks_total counter\n");
+        for (status, count) in [("skewed", &self.skewed), ("stale", &self.stale)] {
+            out.push_str(&format!(
+                "light_client_head_checks_total{{status=\"{}\"}} {}\n",
+                status,
+                count.load(Ordering::Relaxed)
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+    const NOW: u64 = 1_700_000_000 * SECOND;
+
+    fn staleness() -> Staleness {
+        Staleness::new(StalenessConfig {
+            max_head_age_ms: 60_000,
+            skew_tolerance_ms: 5_000,
+        })
+    }
+
+    #[test]
+    fn test_fresh_within_tolerance() {
+        let s = staleness();
+        assert_eq!(
+            s.check_at(NOW - 30 * SECOND, NOW),
+            Freshness::Fresh { age_ms: 30_000 }
+        );
+        // Past the max age, but within the skew tolerance
+        assert_eq!(
+            s.check_at(NOW - 63 * SECOND, NOW),
+            Freshness::Fresh { age_ms: 63_000 }
+        );
+        // Slightly ahead of us
+        assert_eq!(
+            s.check_at(NOW + 2 * SECOND, NOW),
+            Freshness::Fresh { age_ms: 0 }
+        );
+    }
+
+    #[test]
+    fn test_stale() {
+        let s = staleness();
+        assert_eq!(
+            s.check_at(NOW - 66 * SECOND, NOW),
+            Freshness::Stale { age_ms: 66_000 }
+        );
+        assert!(s
+            .render()
+            .contains("light_client_head_checks_total{status=\"stale\"} 1\n"));
+    }
+
+    #[test]
+    fn test_skew_is_surfaced() {
+        let s = staleness();
+        assert_eq!(
+            s.check_at(NOW + 10 * SECOND, NOW),
+            Freshness::Skewed { ahead_ms: 10_000 }
+        );
+        let metrics = s.render();
+        assert!(metrics.contains("light_client_head_skew_ms 10000\n"));
+        assert!(metrics.contains("light_client_head_checks_total{status=\"skewed\"} 1\n"));
+    }
+}
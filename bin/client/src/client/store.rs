@@ -1,9 +1,18 @@
+use std::collections::BTreeMap;
+
 use ::sled::IVec;
-use near_primitives::types::validator_stake::ValidatorStake;
+use near_primitives::{
+    types::{validator_stake::ValidatorStake, BlockHeight, TransactionOrReceiptId},
+    views::validator_stake_view::ValidatorStakeView,
+};
+use protocol::{
+    approval::ApprovalBitmap,
+    block_merkle::{NodeKey, NodeStore, TreeNode},
+};
 use tokio::sync::RwLock;
 
-use super::Header;
-use crate::prelude::*;
+use super::{block_tree::TreeBounds, queue::Requester, rules::Priority, Header};
+use crate::{build_info::BuildInfo, prelude::*};
 
 pub struct Store<S: LightClientStore>(pub RwLock<S>);
 
@@ -18,10 +27,38 @@ impl<S: LightClientStore> Store<S> {
         self.0.write().await.insert(entries)
     }
 
+    /// Insert the entries and remove the keys in one transaction.
+    pub async fn write(
+        &self,
+        entries: &[(CryptoHash, Entity)],
+        removes: &[(Collection, CryptoHash)],
+    ) -> Result<()> {
+        self.0.write().await.write(entries, removes)
+    }
+
     pub async fn get(&self, collection: &Collection, k: &CryptoHash) -> Result<Entity> {
         self.0.read().await.get(collection, k)
     }
 
+    pub async fn remove(&self, collection: &Collection, k: &CryptoHash) -> Result<()> {
+        self.0.write().await.remove(collection, k)
+    }
+
+    /// The keys of batches prepared but not yet proven.
+    pub async fn prepared(&self) -> Result<Vec<CryptoHash>> {
+        self.0.read().await.prepared()
+    }
+
+    /// Every request in the queue, pending or in flight.
+    pub async fn queued(&self) -> Result<Vec<QueuedJob>> {
+        self.0.read().await.queued()
+    }
+
+    /// What every requester has been charged.
+    pub async fn charges(&self) -> Result<Vec<Charge>> {
+        self.0.read().await.charges()
+    }
+
     pub async fn shutdown(&self) {
         self.0.write().await.shutdown();
     }
@@ -29,13 +66,79 @@ impl<S: LightClientStore> Store<S> {
     pub async fn contains(&self, collection: &Collection, k: &CryptoHash) -> Result<bool> {
         self.0.read().await.contains(collection, k)
     }
+
+    pub async fn anchor_at(&self, height: BlockHeight) -> Result<Option<Anchor>> {
+        let root = self.0.read().await.anchor_root_at(height)?;
+        match root {
+            Some(root) => self
+                .get(&Collection::Anchors, &root)
+                .await
+                .and_then(|e| e.anchor())
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// How far each pipeline has got.
+    pub async fn progress(&self) -> Progress {
+        Progress {
+            fetched: self.high_water_mark(Pipeline::Fetched).await,
+            proven: self.high_water_mark(Pipeline::Proven).await,
+            relayed: self.high_water_mark(Pipeline::Relayed).await,
+        }
+    }
+
+    /// The circuits an epoch was first proven with.
+    pub async fn circuit_snapshot(&self, epoch_id: &CryptoHash) -> Option<CircuitSnapshot> {
+        self.get(&Collection::CircuitSnapshots, epoch_id)
+            .await
+            .and_then(|e| e.circuit_snapshot())
+            .ok()
+    }
+
+    /// The block producers of an epoch, if we have synced to it.
+    pub async fn epoch_bps(&self, epoch_id: &CryptoHash) -> Option<EpochBps> {
+        self.get(&Collection::BlockProducers, epoch_id)
+            .await
+            .and_then(|e| e.bps())
+            .ok()
+            .map(|bps| EpochBps::new(*epoch_id, bps))
+    }
+
+    async fn high_water_mark(&self, pipeline: Pipeline) -> Option<BlockHeight> {
+        self.get(&Collection::Progress, &pipeline.key())
+            .await
+            .and_then(|e| e.high_water_mark())
+            .ok()
+    }
+
+    pub async fn block_tree_bounds(&self) -> Result<Option<TreeBounds>> {
+        self.0.read().await.block_tree_bounds()
+    }
+
+    pub async fn block_tree_leaf(&self, block: &CryptoHash) -> Result<Option<u64>> {
+        self.0.read().await.block_tree_leaf(block)
+    }
+
+    pub async fn write_block_tree(&self, nodes: &[TreeNode], bounds: TreeBounds) -> Result<()> {
+        self.0.write().await.write_block_tree(nodes, bounds)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Collection {
     BlockProducers,
     Headers,
     UsedRoots,
+    Anchors,
+    AnchorHeads,
+    /// Relayed anchor roots keyed by their height, written with the relay.
+    AnchorHeights,
+    Progress,
+    Prepared,
+    Queued,
+    CircuitSnapshots,
+    Charges,
 }
 
 #[derive(Debug, BorshSerialize, BorshDeserialize)]
@@ -43,6 +146,286 @@ pub enum Entity {
     BlockProducers(Vec<ValidatorStake>),
     Header(Box<Header>),
     UsedRoot,
+    Anchor(Box<Anchor>),
+    /// The root of the anchor for a head, keyed by the head hash.
+    AnchorHead(CryptoHash),
+    /// The highest height a pipeline has reached, keyed by `Pipeline::key`.
+    HighWaterMark(BlockHeight),
+    /// A batch ready to prove, keyed by `PreparedBatch::key`.
+    Prepared(Box<PreparedBatch>),
+    /// A slot in the queue, keyed by `QueuedJob::key`.
+    Queued(Box<QueuedJob>),
+    /// Keyed by the epoch id.
+    CircuitSnapshot(Box<CircuitSnapshot>),
+    /// What a requester has been charged so far, keyed by `Charge::key`.
+    Charge(Box<Charge>),
+}
+
+/// A batch whose proofs have been fetched, so proving it, or retrying a
+/// failed proving, needs nothing more from the RPC.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct PreparedBatch {
+    pub head: CryptoHash,
+    pub root: CryptoHash,
+    /// Proving attempts that have failed so far.
+    pub attempts: u32,
+    /// The RPC types aren't borsh, so the slots are kept as json.
+    slots: Vec<u8>,
+}
+
+impl PreparedBatch {
+    pub fn new(
+        head: CryptoHash,
+        root: CryptoHash,
+        slots: &[(TransactionOrReceiptId, BasicProof)],
+    ) -> Result<Self> {
+        Ok(Self {
+            head,
+            root,
+            attempts: 0,
+            slots: serde_json::to_vec(slots)?,
+        })
+    }
+
+    /// The key for a batch of slots, the same slots always get the same key.
+    pub fn key(ids: &[TransactionOrReceiptId]) -> CryptoHash {
+        CryptoHash::hash_bytes(&serde_json::to_vec(ids).unwrap_or_default())
+    }
+
+    pub fn slots(&self) -> Result<Vec<(TransactionOrReceiptId, BasicProof)>> {
+        Ok(serde_json::from_slice(&self.slots)?)
+    }
+}
+
+/// A slot in the queue, written whenever it changes so the queue survives
+/// restarts.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct QueuedJob {
+    pub priority: Priority,
+    /// When it was first requested, in unix nanoseconds, to restore the
+    /// order of the queue.
+    pub queued_at: u64,
+    /// See `Job::anchor_after`.
+    pub anchor_after: u64,
+    pub requesters: Vec<Requester>,
+    /// Taken into a batch.
+    pub in_flight: bool,
+    /// The RPC types aren't borsh, so the id is kept as json.
+    id: Vec<u8>,
+}
+
+impl QueuedJob {
+    pub fn new(
+        id: &TransactionOrReceiptId,
+        priority: Priority,
+        queued_at: u64,
+        anchor_after: u64,
+        requesters: Vec<Requester>,
+        in_flight: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            priority,
+            queued_at,
+            anchor_after,
+            requesters,
+            in_flight,
+            id: serde_json::to_vec(id)?,
+        })
+    }
+
+    pub fn key(id: &TransactionOrReceiptId) -> CryptoHash {
+        CryptoHash::hash_bytes(&serde_json::to_vec(id).unwrap_or_default())
+    }
+
+    pub fn id(&self) -> Result<TransactionOrReceiptId> {
+        Ok(serde_json::from_slice(&self.id)?)
+    }
+}
+
+/// The running total a requester has been charged, written with each charge
+/// so tenant quotas survive restarts.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct Charge {
+    pub requester: Requester,
+    pub cost: u64,
+}
+
+impl Charge {
+    pub fn key(requester: &Requester) -> CryptoHash {
+        CryptoHash::hash_bytes(&borsh::to_vec(requester).unwrap_or_default())
+    }
+}
+
+/// The circuits an epoch was first proven with, so re-proving it later, for
+/// a backfill or an audit, uses the same ones and verifiers of old proofs know
+/// which verifier key to use.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct CircuitSnapshot {
+    pub epoch_id: CryptoHash,
+    /// The circuit variants, see `Profile`.
+    pub profile: String,
+    pub git_commit: String,
+    pub plonky2x: String,
+    /// Verifier key digests by circuit, as in `BuildInfo`.
+    pub circuits: BTreeMap<String, Option<String>>,
+}
+
+impl CircuitSnapshot {
+    pub fn new(epoch_id: CryptoHash, profile: &str, build: &BuildInfo) -> Self {
+        Self {
+            epoch_id,
+            profile: profile.to_string(),
+            git_commit: build.git_commit.clone(),
+            plonky2x: build.plonky2x.clone(),
+            circuits: build.circuits.clone(),
+        }
+    }
+
+    /// What differs from the circuits of another snapshot, empty if proofs
+    /// from either verify under the same keys. Builds from another commit
+    /// with the same circuits are fine.
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        let mut differences = vec![];
+        if self.profile != other.profile {
+            differences.push(format!("profile {} != {}", self.profile, other.profile));
+        }
+        if self.plonky2x != other.plonky2x {
+            differences.push(format!("plonky2x {} != {}", self.plonky2x, other.plonky2x));
+        }
+        let names = self.circuits.keys().chain(other.circuits.keys()).unique();
+        for name in names {
+            let (ours, theirs) = (self.digest(name), other.digest(name));
+            if ours != theirs {
+                differences.push(format!(
+                    "{} {} != {}",
+                    name,
+                    ours.unwrap_or("unrecorded"),
+                    theirs.unwrap_or("unrecorded")
+                ));
+            }
+        }
+        differences
+    }
+
+    pub fn digest(&self, circuit: &str) -> Option<&str> {
+        self.circuits.get(circuit)?.as_deref()
+    }
+}
+
+/// The stages a head goes through, each keeps a high-water mark of the
+/// highest head it has reached.
+///
+/// Marks are written in the same transaction as whatever advanced them, and
+/// never move backwards, so a crash can't leave one ahead of or behind the
+/// work it records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pipeline {
+    /// Light client blocks fetched and synced to.
+    Fetched,
+    /// Heads that proofs were built against.
+    Proven,
+    /// Heads whose sync was accepted on the destination chain.
+    Relayed,
+}
+
+impl Pipeline {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fetched => "fetched",
+            Self::Proven => "proven",
+            Self::Relayed => "relayed",
+        }
+    }
+
+    pub fn key(&self) -> CryptoHash {
+        CryptoHash::hash_bytes(self.as_str().as_bytes())
+    }
+
+    /// The insert advancing this pipeline to `height`, to be written with the
+    /// entities that advanced it.
+    pub fn mark(&self, height: BlockHeight) -> (CryptoHash, Entity) {
+        (self.key(), Entity::HighWaterMark(height))
+    }
+}
+
+/// The block producers of an epoch, as the headers before it commit to them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EpochBps {
+    pub epoch_id: CryptoHash,
+    /// The `next_bp_hash` committing to them, the hash of the borsh encoded
+    /// `bps`.
+    pub bps_hash: CryptoHash,
+    pub bps: Vec<ValidatorStakeView>,
+}
+
+impl EpochBps {
+    pub fn new(epoch_id: CryptoHash, stake: Vec<ValidatorStake>) -> Self {
+        let bps = stake.into_iter().map(Into::into).collect_vec();
+        Self {
+            epoch_id,
+            bps_hash: CryptoHash::hash_borsh(&bps),
+            bps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    pub fetched: Option<BlockHeight>,
+    pub proven: Option<BlockHeight>,
+    pub relayed: Option<BlockHeight>,
+}
+
+/// The synced head a proof was built against, keyed by its
+/// `block_merkle_root`. Proofs can only be verified once the sync to this head
+/// has landed, otherwise the root is unknown.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct Anchor {
+    pub head: CryptoHash,
+    pub height: BlockHeight,
+    pub epoch_id: CryptoHash,
+    pub block_merkle_root: CryptoHash,
+    /// Where the sync to this head was accepted on the destination chain, if
+    /// it was relayed.
+    pub relay: Option<Relay>,
+    /// Which seats approved the head, unless it was anchored without syncing
+    /// to it.
+    pub approvals: Option<ApprovalBitmap>,
+    /// How far the approved stake was past the threshold, in basis points of
+    /// the total stake, unless it was anchored without syncing to it.
+    pub margin_bps: Option<u32>,
+    /// The approved stake was close to the threshold, see `Finality`.
+    pub marginal: bool,
+    /// How many seats signed with a key that isn't a valid point, see
+    /// `KeyPolicy`, unless it was anchored without syncing to it.
+    pub rejected_keys: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct Relay {
+    pub tx: String,
+    /// The destination chain block the tx was included in.
+    pub block_number: u64,
+    /// The verifier contract's head after accepting the sync.
+    pub verifier_state: CryptoHash,
+    /// The function id, the circuit digest, the proof was generated under.
+    pub function_id: CryptoHash,
+}
+
+impl From<&Header> for Anchor {
+    fn from(head: &Header) -> Self {
+        Self {
+            head: head.hash(),
+            height: head.inner_lite.height,
+            epoch_id: head.inner_lite.epoch_id,
+            block_merkle_root: head.inner_lite.block_merkle_root,
+            relay: None,
+            approvals: None,
+            margin_bps: None,
+            marginal: false,
+            rejected_keys: None,
+        }
+    }
 }
 
 // Maybe tryinto
@@ -59,6 +442,48 @@ impl Entity {
             _ => Err(anyhow::format_err!("Not a header")),
         }
     }
+    pub fn anchor(self) -> Result<Anchor> {
+        match self {
+            Entity::Anchor(anchor) => Ok(*anchor),
+            _ => Err(anyhow::format_err!("Not an anchor")),
+        }
+    }
+    pub fn anchor_head(self) -> Result<CryptoHash> {
+        match self {
+            Entity::AnchorHead(root) => Ok(root),
+            _ => Err(anyhow::format_err!("Not an anchor head")),
+        }
+    }
+    pub fn high_water_mark(self) -> Result<BlockHeight> {
+        match self {
+            Entity::HighWaterMark(height) => Ok(height),
+            _ => Err(anyhow::format_err!("Not a high-water mark")),
+        }
+    }
+    pub fn prepared(self) -> Result<PreparedBatch> {
+        match self {
+            Entity::Prepared(batch) => Ok(*batch),
+            _ => Err(anyhow::format_err!("Not a prepared batch")),
+        }
+    }
+    pub fn queued(self) -> Result<QueuedJob> {
+        match self {
+            Entity::Queued(job) => Ok(*job),
+            _ => Err(anyhow::format_err!("Not a queued job")),
+        }
+    }
+    pub fn circuit_snapshot(self) -> Result<CircuitSnapshot> {
+        match self {
+            Entity::CircuitSnapshot(snapshot) => Ok(*snapshot),
+            _ => Err(anyhow::format_err!("Not a circuit snapshot")),
+        }
+    }
+    pub fn charge(self) -> Result<Charge> {
+        match self {
+            Entity::Charge(charge) => Ok(*charge),
+            _ => Err(anyhow::format_err!("Not a charge")),
+        }
+    }
 }
 
 impl From<Vec<ValidatorStake>> for Entity {
@@ -73,18 +498,68 @@ impl From<Header> for Entity {
     }
 }
 
+impl From<Anchor> for Entity {
+    fn from(anchor: Anchor) -> Self {
+        Self::Anchor(Box::new(anchor))
+    }
+}
+
+impl From<PreparedBatch> for Entity {
+    fn from(batch: PreparedBatch) -> Self {
+        Self::Prepared(Box::new(batch))
+    }
+}
+
+impl From<QueuedJob> for Entity {
+    fn from(job: QueuedJob) -> Self {
+        Self::Queued(Box::new(job))
+    }
+}
+
+impl From<CircuitSnapshot> for Entity {
+    fn from(snapshot: CircuitSnapshot) -> Self {
+        Self::CircuitSnapshot(Box::new(snapshot))
+    }
+}
+
+impl From<Charge> for Entity {
+    fn from(charge: Charge) -> Self {
+        Self::Charge(Box::new(charge))
+    }
+}
+
 pub trait LightClientStore {
     fn insert(&mut self, entries: &[(CryptoHash, Entity)]) -> Result<()>;
+    fn write(
+        &mut self,
+        entries: &[(CryptoHash, Entity)],
+        removes: &[(Collection, CryptoHash)],
+    ) -> Result<()>;
     fn get(&self, collection: &Collection, k: &CryptoHash) -> Result<Entity>;
     fn head(&self) -> Result<Header>;
     fn contains(&self, collection: &Collection, k: &CryptoHash) -> Result<bool>;
+    fn remove(&mut self, collection: &Collection, k: &CryptoHash) -> Result<()>;
+    fn prepared(&self) -> Result<Vec<CryptoHash>>;
+    fn queued(&self) -> Result<Vec<QueuedJob>>;
+    fn charges(&self) -> Result<Vec<Charge>>;
     fn shutdown(&mut self);
+    /// The root of the latest relayed anchor at or below `height`.
+    fn anchor_root_at(&self, height: BlockHeight) -> Result<Option<CryptoHash>>;
+    /// A node of the block merkle tree, leaves are level 0.
+    fn block_tree_node(&self, level: u8, index: u64) -> Result<Option<CryptoHash>>;
+    /// The leaf index of a block in the block merkle tree.
+    fn block_tree_leaf(&self, block: &CryptoHash) -> Result<Option<u64>>;
+    fn block_tree_bounds(&self) -> Result<Option<TreeBounds>>;
+    /// Write nodes of the block merkle tree along with its new bounds, leaves
+    /// are also indexed by their hash.
+    fn write_block_tree(&mut self, nodes: &[TreeNode], bounds: TreeBounds) -> Result<()>;
 }
 
 pub trait DatabaseOperations {
     fn raw_insert<K: Into<IVec>, V: Into<IVec>>(
         &mut self,
         inserts: Vec<(Collection, Vec<(K, V)>)>,
+        removes: Vec<(Collection, K)>,
     ) -> Result<()>;
     fn raw_get<K: AsRef<[u8]>, T: BorshDeserialize>(
         &self,
@@ -92,6 +567,7 @@ pub trait DatabaseOperations {
         key: K,
     ) -> Result<T>;
     fn raw_contains<K: AsRef<[u8]>>(&self, collection: &Collection, key: K) -> Result<bool>;
+    fn raw_remove<K: AsRef<[u8]>>(&mut self, collection: &Collection, key: K) -> Result<()>;
     fn shutdown(&mut self);
 }
 
@@ -110,12 +586,40 @@ pub mod sled {
         block_producers: Tree,
         headers: Tree,
         used_roots: Tree,
+        anchors: Tree,
+        anchor_heads: Tree,
+        anchor_heights: Tree,
+        progress: Tree,
+        prepared: Tree,
+        queued: Tree,
+        circuit_snapshots: Tree,
+        charges: Tree,
+        block_tree: Tree,
+        block_tree_leaves: Tree,
+    }
+
+    /// Node keys are 9 bytes, so this can't collide with them.
+    const BLOCK_TREE_BOUNDS_KEY: &[u8] = b"bounds";
+
+    fn block_tree_key(level: u8, index: u64) -> [u8; 9] {
+        let mut key = [0; 9];
+        key[0] = level;
+        key[1..].copy_from_slice(&index.to_be_bytes());
+        key
     }
 
     pub(crate) fn init(config: &crate::config::Config) -> Result<Store> {
-        log::info!("Opening store at {:?}", config.state_path);
-        let db = open(&config.state_path)?;
+        log::info!("Opening store at {:?}", config.store.path);
+        with_db(open(&config.store.path)?)
+    }
 
+    /// A store that only lives in memory, for tests.
+    #[cfg(test)]
+    pub(crate) fn temporary() -> Result<Store> {
+        with_db(::sled::Config::new().temporary(true).open()?)
+    }
+
+    fn with_db(db: Db) -> Result<Store> {
         log::debug!("Initializing block producers tree");
         let block_producers = db.open_tree("bps")?;
 
@@ -124,13 +628,50 @@ pub mod sled {
 
         log::debug!("Initializing used_roots tree");
         let used_roots = db.open_tree("used_roots")?;
-        used_roots.set_merge_operator(increment_ref);
+
+        log::debug!("Initializing anchors tree");
+        let anchors = db.open_tree("anchors")?;
+
+        log::debug!("Initializing anchor heads tree");
+        let anchor_heads = db.open_tree("anchor_heads")?;
+
+        log::debug!("Initializing anchor heights tree");
+        let anchor_heights = db.open_tree("anchor_heights")?;
+
+        log::debug!("Initializing progress tree");
+        let progress = db.open_tree("progress")?;
+
+        log::debug!("Initializing prepared tree");
+        let prepared = db.open_tree("prepared")?;
+
+        log::debug!("Initializing queue tree");
+        let queued = db.open_tree("queue")?;
+
+        log::debug!("Initializing circuit snapshots tree");
+        let circuit_snapshots = db.open_tree("circuit_snapshots")?;
+
+        log::debug!("Initializing charges tree");
+        let charges = db.open_tree("charges")?;
+
+        log::debug!("Initializing block tree");
+        let block_tree = db.open_tree("block_tree")?;
+        let block_tree_leaves = db.open_tree("block_tree_leaves")?;
 
         Ok(Store {
             db,
             block_producers,
             headers,
             used_roots,
+            anchors,
+            anchor_heads,
+            anchor_heights,
+            progress,
+            prepared,
+            queued,
+            circuit_snapshots,
+            charges,
+            block_tree,
+            block_tree_leaves,
         })
     }
 
@@ -146,6 +687,14 @@ pub mod sled {
                 Collection::BlockProducers => self.block_producers.get(key),
                 Collection::Headers => self.headers.get(key),
                 Collection::UsedRoots => self.used_roots.get(key),
+                Collection::Anchors => self.anchors.get(key),
+                Collection::AnchorHeads => self.anchor_heads.get(key),
+                Collection::AnchorHeights => self.anchor_heights.get(key),
+                Collection::Progress => self.progress.get(key),
+                Collection::Prepared => self.prepared.get(key),
+                Collection::Queued => self.queued.get(key),
+                Collection::CircuitSnapshots => self.circuit_snapshots.get(key),
+                Collection::Charges => self.charges.get(key),
             }?
             .ok_or_else(|| anyhow::anyhow!("Key not found"))
             .and_then(|value| T::try_from_slice(&value).map_err(|e| anyhow::anyhow!(e)))
@@ -155,17 +704,25 @@ pub mod sled {
             self.db.flush().unwrap();
         }
 
-        fn raw_insert<K, V>(&mut self, inserts: Vec<(Collection, Vec<(K, V)>)>) -> Result<()>
+        fn raw_insert<K, V>(
+            &mut self,
+            inserts: Vec<(Collection, Vec<(K, V)>)>,
+            removes: Vec<(Collection, K)>,
+        ) -> Result<()>
         where
             K: Into<IVec>,
             V: Into<IVec>,
         {
-            let mut used_roots_entries = vec![];
+            let mut used_roots_entries: Vec<IVec> = vec![];
+            let mut marks: Vec<(IVec, IVec)> = vec![];
             let batches = inserts
                 .into_iter()
                 .filter_map(|(collection, entries)| {
                     if let Collection::UsedRoots = collection {
-                        used_roots_entries = entries;
+                        used_roots_entries.extend(entries.into_iter().map(|(k, _)| k.into()));
+                        None
+                    } else if let Collection::Progress = collection {
+                        marks.extend(entries.into_iter().map(|(k, v)| (k.into(), v.into())));
                         None
                     } else {
                         let mut b = Batch::default();
@@ -175,25 +732,71 @@ pub mod sled {
                         Some((collection, b))
                     }
                 })
+                .chain(removes.into_iter().map(|(collection, k)| {
+                    let mut b = Batch::default();
+                    b.remove(k);
+                    (collection, b)
+                }))
                 .collect_vec();
-            (&self.block_producers, &self.headers)
-                .transaction(|(bps, headers)| {
-                    for (collection, b) in &batches {
-                        match collection {
-                            Collection::BlockProducers => bps.apply_batch(b)?,
-                            Collection::Headers => headers.apply_batch(b)?,
-                            Collection::UsedRoots => {}
-                        };
-                    }
-                    Ok(())
-                })
+            (
+                &self.block_producers,
+                &self.headers,
+                &self.used_roots,
+                &self.anchors,
+                &self.anchor_heads,
+                &self.anchor_heights,
+                &self.progress,
+                &self.prepared,
+                &self.queued,
+                &self.circuit_snapshots,
+                &self.charges,
+            )
+                .transaction(
+                    |(
+                        bps,
+                        headers,
+                        used_roots,
+                        anchors,
+                        anchor_heads,
+                        anchor_heights,
+                        progress,
+                        prepared,
+                        queued,
+                        circuit_snapshots,
+                        charges,
+                    )| {
+                        for (collection, b) in &batches {
+                            match collection {
+                                Collection::BlockProducers => bps.apply_batch(b)?,
+                                Collection::Headers => headers.apply_batch(b)?,
+                                Collection::Anchors => anchors.apply_batch(b)?,
+                                Collection::AnchorHeads => anchor_heads.apply_batch(b)?,
+                                Collection::AnchorHeights => anchor_heights.apply_batch(b)?,
+                                Collection::Prepared => prepared.apply_batch(b)?,
+                                Collection::Queued => queued.apply_batch(b)?,
+                                Collection::CircuitSnapshots => circuit_snapshots.apply_batch(b)?,
+                                Collection::Charges => charges.apply_batch(b)?,
+                                // Only removals are batched for these
+                                Collection::UsedRoots => used_roots.apply_batch(b)?,
+                                Collection::Progress => progress.apply_batch(b)?,
+                            };
+                        }
+                        // Roots are counted each time they are used
+                        for k in &used_roots_entries {
+                            let count = used_roots.get(k)?;
+                            used_roots.insert(k.clone(), increment_ref(k, count.as_deref()))?;
+                        }
+                        // Marks only move forward, a stale write is dropped
+                        for (k, v) in &marks {
+                            let current = progress.get(k)?;
+                            if current.as_deref().and_then(mark_height) < mark_height(v) {
+                                progress.insert(k.clone(), v.clone())?;
+                            }
+                        }
+                        Ok(())
+                    },
+                )
                 .map_err(|e: TransactionError| anyhow::anyhow!("{:?}", e))?;
-
-            if !used_roots_entries.is_empty() {
-                for (k, v) in used_roots_entries {
-                    self.used_roots.merge(k.into(), v.into())?;
-                }
-            }
             Ok(())
         }
 
@@ -202,14 +805,48 @@ pub mod sled {
                 Collection::BlockProducers => self.block_producers.contains_key(key),
                 Collection::Headers => self.headers.contains_key(key),
                 Collection::UsedRoots => self.used_roots.contains_key(key),
+                Collection::Anchors => self.anchors.contains_key(key),
+                Collection::AnchorHeads => self.anchor_heads.contains_key(key),
+                Collection::AnchorHeights => self.anchor_heights.contains_key(key),
+                Collection::Progress => self.progress.contains_key(key),
+                Collection::Prepared => self.prepared.contains_key(key),
+                Collection::Queued => self.queued.contains_key(key),
+                Collection::CircuitSnapshots => self.circuit_snapshots.contains_key(key),
+                Collection::Charges => self.charges.contains_key(key),
             }
             .map_err(|e| anyhow::anyhow!("Contains: {:?}", e))
         }
+
+        fn raw_remove<K: AsRef<[u8]>>(&mut self, collection: &Collection, key: K) -> Result<()> {
+            log::debug!("Remove {:?} {:?}", collection, key.as_ref());
+            match collection {
+                Collection::BlockProducers => self.block_producers.remove(key),
+                Collection::Headers => self.headers.remove(key),
+                Collection::UsedRoots => self.used_roots.remove(key),
+                Collection::Anchors => self.anchors.remove(key),
+                Collection::AnchorHeads => self.anchor_heads.remove(key),
+                Collection::AnchorHeights => self.anchor_heights.remove(key),
+                Collection::Progress => self.progress.remove(key),
+                Collection::Prepared => self.prepared.remove(key),
+                Collection::Queued => self.queued.remove(key),
+                Collection::CircuitSnapshots => self.circuit_snapshots.remove(key),
+                Collection::Charges => self.charges.remove(key),
+            }?;
+            Ok(())
+        }
     }
 
     impl LightClientStore for Store {
-        fn insert(&mut self, inserts: &[(CryptoHash, Entity)]) -> Result<()> {
-            let inserts = inserts
+        fn insert(&mut self, entities: &[(CryptoHash, Entity)]) -> Result<()> {
+            self.write(entities, &[])
+        }
+
+        fn write(
+            &mut self,
+            entities: &[(CryptoHash, Entity)],
+            removes: &[(Collection, CryptoHash)],
+        ) -> Result<()> {
+            let mut inserts = entities
                 .iter()
                 .map(|(k, v)| {
                     log::debug!("Insert {:?}", k);
@@ -220,6 +857,13 @@ pub mod sled {
                                 Entity::BlockProducers(_) => Collection::BlockProducers,
                                 Entity::Header(_) => Collection::Headers,
                                 Entity::UsedRoot => Collection::UsedRoots,
+                                Entity::Anchor(_) => Collection::Anchors,
+                                Entity::AnchorHead(_) => Collection::AnchorHeads,
+                                Entity::HighWaterMark(_) => Collection::Progress,
+                                Entity::Prepared(_) => Collection::Prepared,
+                                Entity::Queued(_) => Collection::Queued,
+                                Entity::CircuitSnapshot(_) => Collection::CircuitSnapshots,
+                                Entity::Charge(_) => Collection::Charges,
                             };
                             (collection, ek, ev)
                         })
@@ -229,7 +873,26 @@ pub mod sled {
                     acc.push((collection, vec![(k, v)]));
                     acc
                 })?;
-            self.raw_insert(inserts)
+            // Relayed anchors are indexed by their NEAR height in the same
+            // transaction, big endian so the keys sort by height
+            for (_, v) in entities {
+                if let Entity::Anchor(anchor) = v {
+                    if anchor.relay.is_some() {
+                        inserts.push((
+                            Collection::AnchorHeights,
+                            vec![(
+                                anchor.height.to_be_bytes().to_vec(),
+                                borsh::to_vec(&anchor.block_merkle_root)?,
+                            )],
+                        ));
+                    }
+                }
+            }
+            let removes = removes
+                .iter()
+                .map(|(collection, k)| Ok((collection.clone(), borsh::to_vec(k)?)))
+                .collect::<Result<Vec<_>>>()?;
+            self.raw_insert(inserts, removes)
         }
 
         fn get(&self, collection: &Collection, k: &CryptoHash) -> Result<Entity> {
@@ -252,24 +915,290 @@ pub mod sled {
         fn contains(&self, collection: &Collection, k: &CryptoHash) -> Result<bool> {
             self.raw_contains(collection, borsh::to_vec(k)?)
         }
+
+        fn remove(&mut self, collection: &Collection, k: &CryptoHash) -> Result<()> {
+            self.raw_remove(collection, borsh::to_vec(k)?)
+        }
+
+        fn prepared(&self) -> Result<Vec<CryptoHash>> {
+            self.prepared
+                .iter()
+                .keys()
+                .map(|k| Ok(CryptoHash::try_from_slice(&k?)?))
+                .collect()
+        }
+
+        fn queued(&self) -> Result<Vec<QueuedJob>> {
+            self.queued
+                .iter()
+                .values()
+                .map(|v| Entity::try_from_slice(&v?)?.queued())
+                .collect()
+        }
+
+        fn charges(&self) -> Result<Vec<Charge>> {
+            self.charges
+                .iter()
+                .values()
+                .map(|v| Entity::try_from_slice(&v?)?.charge())
+                .collect()
+        }
+
+        fn anchor_root_at(&self, height: BlockHeight) -> Result<Option<CryptoHash>> {
+            self.anchor_heights
+                .range(..=height.to_be_bytes())
+                .next_back()
+                .transpose()?
+                .map(|(_, root)| CryptoHash::try_from_slice(&root))
+                .transpose()
+                .map_err(Into::into)
+        }
+
+        fn block_tree_node(&self, level: u8, index: u64) -> Result<Option<CryptoHash>> {
+            self.block_tree
+                .get(block_tree_key(level, index))?
+                .map(|hash| CryptoHash::try_from_slice(&hash))
+                .transpose()
+                .map_err(Into::into)
+        }
+
+        fn block_tree_leaf(&self, block: &CryptoHash) -> Result<Option<u64>> {
+            self.block_tree_leaves
+                .get(block.0)?
+                .map(|index| u64::try_from_slice(&index))
+                .transpose()
+                .map_err(Into::into)
+        }
+
+        fn block_tree_bounds(&self) -> Result<Option<TreeBounds>> {
+            self.block_tree
+                .get(BLOCK_TREE_BOUNDS_KEY)?
+                .map(|bounds| TreeBounds::try_from_slice(&bounds))
+                .transpose()
+                .map_err(Into::into)
+        }
+
+        fn write_block_tree(&mut self, nodes: &[TreeNode], bounds: TreeBounds) -> Result<()> {
+            let mut tree = Batch::default();
+            let mut leaves = Batch::default();
+            for node in nodes {
+                tree.insert(
+                    &block_tree_key(node.level, node.index)[..],
+                    borsh::to_vec(&node.hash)?,
+                );
+                if node.level == 0 {
+                    leaves.insert(&node.hash.0[..], borsh::to_vec(&node.index)?);
+                }
+            }
+            tree.insert(BLOCK_TREE_BOUNDS_KEY, borsh::to_vec(&bounds)?);
+            (&self.block_tree, &self.block_tree_leaves)
+                .transaction(|(tree_tx, leaves_tx)| {
+                    tree_tx.apply_batch(&tree)?;
+                    leaves_tx.apply_batch(&leaves)?;
+                    Ok(())
+                })
+                .map_err(|e: TransactionError| anyhow::anyhow!("{:?}", e))
+        }
     }
 
-    fn increment_ref(
-        key: &[u8],             // the key being merged
-        old_ref: Option<&[u8]>, // the previous value, if one existed
-        _merged_bytes: &[u8],   // the new bytes being merged in
-    ) -> Option<Vec<u8>> {
+    impl NodeStore for Store {
+        fn node(&self, key: NodeKey) -> nearx_error::Result<Option<CryptoHash>> {
+            self.block_tree_node(key.0, key.1)
+                .map_err(|e| nearx_error::Error::from_boxed(nearx_error::Layer::Io, e.into()))
+        }
+    }
+
+    fn mark_height(mark: &[u8]) -> Option<BlockHeight> {
+        Entity::try_from_slice(mark)
+            .ok()
+            .and_then(|e| e.high_water_mark().ok())
+    }
+
+    fn increment_ref(key: &[u8], old_ref: Option<&[u8]>) -> Vec<u8> {
         let ref_count = old_ref
-            .map(|ov| ov.to_vec())
-            .and_then(|ov| u32::try_from_slice(&ov).ok())
+            .and_then(|ov| u32::try_from_slice(ov).ok())
             .unwrap_or(0);
         log::debug!("Incrementing ref count for {:?}, {}", key, ref_count);
-        borsh::to_vec(&(ref_count + 1)).ok()
+        // As borsh encodes it
+        (ref_count + 1).to_le_bytes().to_vec()
     }
     #[cfg(test)]
     mod tests {
+        use super::*;
+
+        fn store() -> crate::client::store::Store<Store> {
+            crate::client::store::Store(temporary().unwrap().into())
+        }
+
+        #[tokio::test]
+        async fn test_marks_only_move_forward() {
+            let store = store();
+            assert_eq!(store.progress().await, Progress::default());
+
+            store.insert(&[Pipeline::Fetched.mark(10)]).await.unwrap();
+            // A stale write, e.g from a retry after a crash
+            store.insert(&[Pipeline::Fetched.mark(5)]).await.unwrap();
+            store.insert(&[Pipeline::Proven.mark(7)]).await.unwrap();
+            assert_eq!(
+                store.progress().await,
+                Progress {
+                    fetched: Some(10),
+                    proven: Some(7),
+                    relayed: None,
+                }
+            );
+        }
+
+        #[tokio::test]
+        async fn test_relay_is_indexed_with_its_mark() {
+            let store = store();
+            let anchor = Anchor {
+                head: CryptoHash::hash_bytes(b"head"),
+                height: 100,
+                epoch_id: CryptoHash::default(),
+                block_merkle_root: CryptoHash::hash_bytes(b"root"),
+                relay: Some(Relay {
+                    tx: "0x01".to_string(),
+                    block_number: 1,
+                    verifier_state: CryptoHash::default(),
+                    function_id: CryptoHash::default(),
+                }),
+                approvals: Some(ApprovalBitmap::new(&[true, false, true])),
+                margin_bps: Some(1200),
+                marginal: false,
+                rejected_keys: Some(0),
+            };
+            store
+                .insert(&[
+                    (anchor.block_merkle_root, anchor.clone().into()),
+                    Pipeline::Relayed.mark(anchor.height),
+                ])
+                .await
+                .unwrap();
+
+            assert_eq!(store.anchor_at(150).await.unwrap(), Some(anchor));
+            assert_eq!(store.anchor_at(99).await.unwrap(), None);
+            assert_eq!(store.progress().await.relayed, Some(100));
+        }
+
+        #[tokio::test]
+        async fn test_epoch_bps_hash_to_their_commitment() {
+            let store = store();
+            let first = test_utils::test_first().body;
+            let epoch_id = first.inner_lite.next_epoch_id;
+            let stake = first
+                .next_bps
+                .unwrap()
+                .into_iter()
+                .map(ValidatorStakeView::into_validator_stake)
+                .collect_vec();
+            assert!(store.epoch_bps(&epoch_id).await.is_none());
+
+            store.insert(&[(epoch_id, stake.into())]).await.unwrap();
+            let bps = store.epoch_bps(&epoch_id).await.unwrap();
+            assert_eq!(bps.bps_hash, first.inner_lite.next_bp_hash);
+            assert_eq!(
+                bps.bps_hash,
+                CryptoHash::hash_bytes(&borsh::to_vec(&bps.bps).unwrap())
+            );
+        }
+
+        #[tokio::test]
+        async fn test_circuit_snapshots() {
+            let store = store();
+            let epoch_id = CryptoHash::hash_bytes(b"epoch");
+            let snapshot = CircuitSnapshot::new(epoch_id, "prod", BuildInfo::get());
+            assert!(store.circuit_snapshot(&epoch_id).await.is_none());
+            store
+                .insert(&[(epoch_id, snapshot.clone().into())])
+                .await
+                .unwrap();
+            assert_eq!(
+                store.circuit_snapshot(&epoch_id).await,
+                Some(snapshot.clone())
+            );
+
+            // Another commit with the same circuits proves the same
+            let rebuilt = CircuitSnapshot {
+                git_commit: "other".to_string(),
+                ..snapshot.clone()
+            };
+            assert!(snapshot.differences(&rebuilt).is_empty());
+
+            let mut changed = CircuitSnapshot::new(epoch_id, "dev", BuildInfo::get());
+            changed
+                .circuits
+                .insert("verify".to_string(), Some("0x01".to_string()));
+            assert_eq!(
+                snapshot.differences(&changed),
+                vec![
+                    "profile prod != dev".to_string(),
+                    format!(
+                        "verify {} != 0x01",
+                        snapshot.digest("verify").unwrap_or("unrecorded")
+                    )
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn test_used_roots_are_counted_with_their_marks() {
+            let store = store();
+            let root = CryptoHash::hash_bytes(b"root");
+            let count = || async {
+                store
+                    .0
+                    .read()
+                    .await
+                    .raw_get::<_, u32>(&Collection::UsedRoots, borsh::to_vec(&root).unwrap())
+                    .ok()
+            };
+
+            store
+                .insert(&[(root, Entity::UsedRoot), Pipeline::Proven.mark(7)])
+                .await
+                .unwrap();
+            assert_eq!(count().await, Some(1));
+            assert!(store.contains(&Collection::UsedRoots, &root).await.unwrap());
+            store
+                .insert(&[(root, Entity::UsedRoot), Pipeline::Proven.mark(8)])
+                .await
+                .unwrap();
+            assert_eq!(count().await, Some(2));
+            assert_eq!(store.progress().await.proven, Some(8));
+        }
 
-        #[test]
-        fn test_name() {}
+        #[tokio::test]
+        async fn test_prepared_batches() {
+            let store = store();
+            let ids = vec![TransactionOrReceiptId::Receipt {
+                receipt_id: CryptoHash::hash_bytes(b"receipt"),
+                receiver_id: "test.near".parse().unwrap(),
+            }];
+            let key = PreparedBatch::key(&ids);
+            assert_eq!(key, PreparedBatch::key(&ids.clone()));
+            assert_ne!(key, PreparedBatch::key(&[]));
+
+            let batch =
+                PreparedBatch::new(CryptoHash::default(), CryptoHash::default(), &[]).unwrap();
+            store.insert(&[(key, batch.clone().into())]).await.unwrap();
+            assert_eq!(store.prepared().await.unwrap(), vec![key]);
+            assert_eq!(
+                store
+                    .get(&Collection::Prepared, &key)
+                    .await
+                    .and_then(|e| e.prepared())
+                    .unwrap(),
+                batch
+            );
+
+            // Proving it removes it and marks its head proven together
+            store
+                .write(&[Pipeline::Proven.mark(3)], &[(Collection::Prepared, key)])
+                .await
+                .unwrap();
+            assert!(store.prepared().await.unwrap().is_empty());
+            assert_eq!(store.progress().await.proven, Some(3));
+        }
     }
 }
@@ -0,0 +1,140 @@
+//! Namespacing requests, so one deployment can serve several independent
+//! products. Each tenant has its own API keys and quota, and only sees its own
+//! slots and costs.
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// Everything belongs to this tenant when none are configured, including what
+/// we enqueue ourselves.
+pub const DEFAULT_TENANT: &str = "default";
+
+pub type Tenant = String;
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TenantConfig {
+    /// Accepted in the `x-api-key` header.
+    pub api_keys: Vec<String>,
+    /// The most this tenant can be charged, in the units of
+    /// `scheduler.slot_cost`. Unlimited if not set.
+    #[serde(default)]
+    pub quota: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Tenants(pub HashMap<Tenant, TenantConfig>);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TenantError {
+    #[error("Missing or unknown API key")]
+    Unauthorized,
+    #[error("Tenant {0} has used its quota of {1}")]
+    OverQuota(Tenant, u64),
+}
+
+impl Tenants {
+    /// The tenant an API key belongs to. Without any tenants configured the
+    /// deployment is open and no key is needed.
+    pub fn authenticate(&self, api_key: Option<&str>) -> Result<Tenant, TenantError> {
+        if self.0.is_empty() {
+            return Ok(DEFAULT_TENANT.to_string());
+        }
+        let api_key = api_key.ok_or(TenantError::Unauthorized)?;
+        self.0
+            .iter()
+            .find(|(_, config)| config.api_keys.iter().any(|k| k == api_key))
+            .map(|(tenant, _)| tenant.clone())
+            .ok_or(TenantError::Unauthorized)
+    }
+
+    /// Whether a tenant that has been charged `spent` can enqueue more. Slots
+    /// that are still queued aren't charged yet, so a tenant can go over by
+    /// at most what it has pending.
+    pub fn check_quota(&self, tenant: &str, spent: u64) -> Result<(), TenantError> {
+        match self.0.get(tenant).and_then(|config| config.quota) {
+            Some(quota) if spent >= quota => Err(TenantError::OverQuota(tenant.to_string(), quota)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Costs and pending slots labelled by tenant, in the prometheus text format.
+pub fn render(costs: &HashMap<Tenant, u64>, pending: &HashMap<Tenant, usize>) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP light_client_tenant_cost_total What each tenant has been charged\n");
+    out.push_str("# TYPE light_client_tenant_cost_total counter\n");
+    for (tenant, cost) in costs.iter().sorted() {
+        out.push_str(&format!(
+            "light_client_tenant_cost_total{{tenant=\"{}\"}} {}\n",
+            tenant, cost
+        ));
+    }
+    out.push_str("# HELP light_client_tenant_pending Slots each tenant is waiting on\n");
+    out.push_str("# TYPE light_client_tenant_pending gauge\n");
+    for (tenant, count) in pending.iter().sorted() {
+        out.push_str(&format!(
+            "light_client_tenant_pending{{tenant=\"{}\"}} {}\n",
+            tenant, count
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenants() -> Tenants {
+        serde_json::from_value(serde_json::json!({
+            "bridge": { "api_keys": ["a", "b"], "quota": 10 },
+            "wallet": { "api_keys": ["c"] },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let tenants = tenants();
+        assert_eq!(tenants.authenticate(Some("b")).unwrap(), "bridge");
+        assert_eq!(tenants.authenticate(Some("c")).unwrap(), "wallet");
+        assert_eq!(
+            tenants.authenticate(Some("d")),
+            Err(TenantError::Unauthorized)
+        );
+        assert_eq!(tenants.authenticate(None), Err(TenantError::Unauthorized));
+
+        // Open when nothing is configured
+        assert_eq!(
+            Tenants::default().authenticate(None).unwrap(),
+            DEFAULT_TENANT
+        );
+    }
+
+    #[test]
+    fn test_quota() {
+        let tenants = tenants();
+        assert!(tenants.check_quota("bridge", 9).is_ok());
+        assert_eq!(
+            tenants.check_quota("bridge", 10),
+            Err(TenantError::OverQuota("bridge".to_string(), 10))
+        );
+        assert!(tenants.check_quota("wallet", u64::MAX).is_ok());
+        assert!(tenants.check_quota(DEFAULT_TENANT, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_render() {
+        let costs = HashMap::from([("wallet".to_string(), 3), ("bridge".to_string(), 4)]);
+        let pending = HashMap::from([("bridge".to_string(), 1)]);
+        let out = render(&costs, &pending);
+        let bridge = out
+            .find("light_client_tenant_cost_total{tenant=\"bridge\"} 4")
+            .unwrap();
+        let wallet = out
+            .find("light_client_tenant_cost_total{tenant=\"wallet\"} 3")
+            .unwrap();
+        assert!(bridge < wallet);
+        assert!(out.contains("light_client_tenant_pending{tenant=\"bridge\"} 1"));
+    }
+}
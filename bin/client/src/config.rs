@@ -1,19 +1,783 @@
 use std::{env, path::PathBuf};
 
 use config::{Config as ConfigTrait, ConfigError, Environment, File};
-use rpc::Network;
+use near_primitives::types::BlockHeight;
+use protocol::signature::KeyPolicy;
+use rpc::{limits::RpcLimits, Network};
+use toml::Value;
 
-use crate::prelude::*;
+use crate::{
+    client::{rules::Rules, tenant::Tenants},
+    prelude::*,
+};
+
+/// Versioning the schema, and moving files between versions.
+pub mod migrate;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
-    #[serde(default = "default_db_path")]
-    pub state_path: PathBuf,
-    pub starting_head: String,
+    /// The schema the file was written for, see `migrate`.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    #[serde(default)]
+    pub store: StoreConfig,
+    /// The trusted header to sync from while the store is empty, unless
+    /// there is a checkpoint to resume from in the `head_store`.
+    #[serde(default)]
+    pub starting_head: Option<String>,
+    /// Where the trusted head is checkpointed, if anywhere.
+    #[serde(default)]
+    pub head_store: Option<HeadStoreConfig>,
+    pub catchup: bool,
+    #[serde(default)]
+    pub rpc: RpcConfig,
+    #[serde(default)]
+    pub prover: ProverConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub ingest: Option<IngestConfig>,
+    #[serde(default)]
+    pub block_tree: Option<BlockTreeConfig>,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    /// What happens to proofs between proving and delivery.
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Where proven heads and proofs are posted for availability, if
+    /// anywhere.
+    #[serde(default)]
+    pub da: Option<DaConfig>,
+    #[serde(default)]
+    pub selection: SelectionConfig,
+    #[serde(default)]
+    pub canary: Option<CanaryConfig>,
+    /// The deployed verifier contract, checked at startup to accept what we
+    /// prove, if any.
+    #[serde(default)]
+    pub verifier: Option<VerifierConfig>,
+    #[serde(default)]
+    pub staleness: StalenessConfig,
+    #[serde(default)]
+    pub finality: FinalityConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    /// Products served by this deployment, keyed by tenant. Anyone can make
+    /// requests if none are configured.
+    #[serde(default)]
+    pub tenants: Tenants,
+}
+
+/// A table of the config file with defaults for every key, documented in code
+/// so `Config::example` can't drift from the fields.
+pub trait Section: Serialize + Default {
+    /// The table it is read from.
+    const NAME: &'static str;
+    /// What each key is for, in the order they are written.
+    const DOCS: &'static [(&'static str, &'static str)];
+}
+
+/// The NEAR RPC we follow the chain with.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RpcConfig {
+    #[serde(default)]
     pub network: Network,
+    /// Concurrency and pacing for each RPC endpoint.
+    #[serde(flatten)]
+    pub limits: RpcLimits,
+}
+
+impl Section for RpcConfig {
+    const NAME: &'static str = "rpc";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        ("network", "Mainnet, Testnet, Localnet or Statelessnet"),
+        ("rpc", "Limits on the RPC used to follow the head"),
+        (
+            "history",
+            "Limits on the RPC used for proofs and lookups by hash",
+        ),
+        ("archive", "Limits on the archival RPC"),
+        ("timeouts", "How long head and history queries may take"),
+    ];
+}
+
+/// How proofs are made.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProverConfig {
+    /// The circuit variants the prover was built with.
+    #[serde(default)]
+    pub profile: Profile,
+}
+
+impl Section for ProverConfig {
+    const NAME: &'static str = "prover";
+    const DOCS: &'static [(&'static str, &'static str)] = &[(
+        "profile",
+        "The circuit variants the prover was built with, dev or prod",
+    )];
+}
+
+/// The HTTP API.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiConfig {
     #[serde(default = "default_host")]
     pub host: String,
-    pub catchup: bool,
+    /// How many failures are kept for `/status/errors`.
+    #[serde(default = "default_recent_errors")]
+    pub recent_errors: usize,
+    /// Where to serve the gRPC service, it isn't served unless set.
+    #[serde(default)]
+    pub grpc_host: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            recent_errors: default_recent_errors(),
+            grpc_host: None,
+        }
+    }
+}
+
+impl Section for ApiConfig {
+    const NAME: &'static str = "api";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        ("host", "The address to serve on"),
+        (
+            "recent_errors",
+            "How many failures are kept for /status/errors",
+        ),
+        (
+            "grpc_host",
+            "Where to serve the gRPC service, unset to not serve it",
+        ),
+    ];
+}
+
+/// Which circuit variants are being proven, this must match the profile the
+/// circuits were built with in nearx.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// Small circuits that can be built and proven on a laptop.
+    Dev,
+    #[default]
+    Prod,
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Prod => "prod",
+        }
+    }
+
+    /// The slots in the verify circuit, see `nearx::repro`.
+    pub fn verify_slots(&self) -> usize {
+        match self {
+            Profile::Dev => 4,
+            Profile::Prod => 128,
+        }
+    }
+}
+
+/// Keeping CPU heavy work from starving the async runtime.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RuntimeConfig {
+    /// How many CPU heavy tasks can run at once.
+    #[serde(default = "default_cpu_threads")]
+    pub cpu_threads: usize,
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval_ms: u64,
+    /// Lag beyond this is logged and counted.
+    #[serde(default = "default_lag_warn")]
+    pub lag_warn_ms: u64,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            cpu_threads: default_cpu_threads(),
+            probe_interval_ms: default_probe_interval(),
+            lag_warn_ms: default_lag_warn(),
+        }
+    }
+}
+
+impl Section for RuntimeConfig {
+    const NAME: &'static str = "runtime";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        (
+            "cpu_threads",
+            "How many CPU heavy tasks can run at once, one less than the cores by default",
+        ),
+        (
+            "probe_interval_ms",
+            "How often the async runtime's lag is probed",
+        ),
+        ("lag_warn_ms", "Lag beyond this is logged and counted"),
+    ];
+}
+
+/// Where the client's state is kept.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoreConfig {
+    #[serde(default = "default_db_path")]
+    pub path: PathBuf,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path: default_db_path(),
+        }
+    }
+}
+
+impl Section for StoreConfig {
+    const NAME: &'static str = "store";
+    const DOCS: &'static [(&'static str, &'static str)] =
+        &[("path", "Where the client's state is kept")];
+}
+
+/// Where operator actions are recorded, and how the log is checkpointed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditConfig {
+    #[serde(default = "default_audit_path")]
+    pub path: PathBuf,
+    /// Entries between signed checkpoints.
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    /// The key checkpoints are signed with, e.g `ed25519:...`. Without one no
+    /// checkpoints are written.
+    #[serde(default)]
+    pub signer_key: Option<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: default_audit_path(),
+            checkpoint_interval: default_checkpoint_interval(),
+            signer_key: None,
+        }
+    }
+}
+
+impl Section for AuditConfig {
+    const NAME: &'static str = "audit";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        ("path", "Where operator actions are recorded"),
+        ("checkpoint_interval", "Entries between signed checkpoints"),
+        (
+            "signer_key",
+            "The key checkpoints are signed with, e.g ed25519:..., none are written without one",
+        ),
+    ];
+}
+
+/// When the head is considered too old to prove against.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StalenessConfig {
+    #[serde(default = "default_max_head_age")]
+    pub max_head_age_ms: u64,
+    /// How far our clock may disagree with the block producers'.
+    #[serde(default = "default_skew_tolerance")]
+    pub skew_tolerance_ms: u64,
+}
+
+impl Default for StalenessConfig {
+    fn default() -> Self {
+        Self {
+            max_head_age_ms: default_max_head_age(),
+            skew_tolerance_ms: default_skew_tolerance(),
+        }
+    }
+}
+
+impl Section for StalenessConfig {
+    const NAME: &'static str = "staleness";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        (
+            "max_head_age_ms",
+            "The head is too old to prove against past this age",
+        ),
+        (
+            "skew_tolerance_ms",
+            "How far our clock may disagree with the block producers'",
+        ),
+    ];
+}
+
+/// When a synced head's approvals are considered marginal.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FinalityConfig {
+    /// Heads whose approved stake is within this many basis points of the
+    /// total stake past the 2/3 threshold are flagged.
+    #[serde(default = "default_marginal_bps")]
+    pub marginal_bps: u32,
+    /// What an approval by a seat with an invalid key does, the circuits
+    /// must be built with the same.
+    #[serde(default)]
+    pub key_policy: KeyPolicy,
+}
+
+impl Default for FinalityConfig {
+    fn default() -> Self {
+        Self {
+            marginal_bps: default_marginal_bps(),
+            key_policy: KeyPolicy::default(),
+        }
+    }
+}
+
+impl Section for FinalityConfig {
+    const NAME: &'static str = "finality";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        ("marginal_bps", "Heads approved by stake within this many basis points of the total past the 2/3 threshold are flagged"),
+        ("key_policy", "What an approval by a seat with an invalid key does, `inactive` drops it and `reject` fails the sync, as the circuits were built"),
+    ];
+}
+
+/// A circuit build being rolled out in shadow before it may be relayed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CanaryConfig {
+    /// The function id, the circuit digest, of the shadow build.
+    pub function_id: CryptoHash,
+    /// How many clean epochs are needed before it is eligible.
+    #[serde(default = "default_shadow_epochs")]
+    pub shadow_epochs: u64,
+}
+
+/// How queued requests are batched into the verify circuit.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SchedulerConfig {
+    /// Slots in each batch.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_batch_interval")]
+    pub interval_ms: u64,
+    /// The cost of proving a slot, split between everyone that requested it.
+    #[serde(default = "default_slot_cost")]
+    pub slot_cost: u64,
+    /// How long a batch can take before it is failed.
+    #[serde(default = "default_batch_timeout")]
+    pub timeout_ms: u64,
+    /// Check with the RPC that requests can be proven before queueing them.
+    #[serde(default = "default_prevalidate")]
+    pub prevalidate: bool,
+    /// Workers proving prepared batches, bounded by `runtime.cpu_threads`.
+    #[serde(default = "default_provers")]
+    pub provers: usize,
+    /// How many times preparing or proving a batch is tried before its slots
+    /// are failed.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+    /// What is attached to each proof besides the proof.
+    #[serde(default)]
+    pub output: OutputMode,
+}
+
+/// Extra outputs of a proven batch.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Only the proof.
+    #[default]
+    None,
+    /// The result of each slot as the verify circuit outputs it, packed to
+    /// fit the calldata of cheaper L2s, see `protocol::packing`.
+    Packed,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: default_batch_size(),
+            interval_ms: default_batch_interval(),
+            slot_cost: default_slot_cost(),
+            timeout_ms: default_batch_timeout(),
+            prevalidate: default_prevalidate(),
+            provers: default_provers(),
+            attempts: default_attempts(),
+            output: Default::default(),
+        }
+    }
+}
+
+impl Section for SchedulerConfig {
+    const NAME: &'static str = "scheduler";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        (
+            "batch_size",
+            "Slots in each batch, at most the verify circuit's",
+        ),
+        ("interval_ms", "How often a batch is prepared"),
+        (
+            "slot_cost",
+            "The cost of proving a slot, split between everyone that requested it",
+        ),
+        (
+            "timeout_ms",
+            "How long a batch can take before it is failed",
+        ),
+        (
+            "prevalidate",
+            "Check with the RPC that requests can be proven before queueing them",
+        ),
+        (
+            "provers",
+            "Workers proving prepared batches, bounded by runtime.cpu_threads",
+        ),
+        (
+            "attempts",
+            "How many times a batch is tried before its slots are failed",
+        ),
+        (
+            "output",
+            "`packed` attaches each batch's verify results, packed for calldata",
+        ),
+    ];
+}
+
+/// The post processors run on each kind of proof, in order. Only batches
+/// are proven here.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HooksConfig {
+    #[serde(default)]
+    pub batch: Vec<HookConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HookConfig {
+    /// Sign the proof, with a key like `ed25519:...`.
+    Attest { signer_key: String },
+    /// Post the proof as json to a relayer.
+    Relay(RelayConfig),
+}
+
+/// A relayer that submits proofs to a destination chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RelayConfig {
+    pub url: String,
+    /// How long posting a proof may take before it is failed.
+    #[serde(default = "default_relay_timeout")]
+    pub timeout_ms: u64,
+}
+
+/// Which light client blocks are synced to, epoch boundaries and blocks
+/// that pending requests need are always taken.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelectionConfig {
+    /// The most blocks the head can fall behind by before we sync anyway.
+    /// Keep this within `staleness.max_head_age_ms`, or the head is reported
+    /// stale between heartbeats.
+    #[serde(default = "default_heartbeat_blocks")]
+    pub heartbeat_blocks: u64,
+}
+
+impl Default for SelectionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_blocks: default_heartbeat_blocks(),
+        }
+    }
+}
+
+impl Section for SelectionConfig {
+    const NAME: &'static str = "selection";
+    const DOCS: &'static [(&'static str, &'static str)] = &[
+        ("heartbeat_blocks", "The most blocks the head can fall behind by before we sync anyway, keep this within staleness.max_head_age_ms"),
+    ];
+}
+
+/// Watches the chain for receipts matching the rules and enqueues them for
+/// proving.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IngestConfig {
+    pub rules: Rules,
+    /// Where to start ingesting from, if not set we start from the latest
+    /// final block.
+    pub start_height: Option<BlockHeight>,
+    #[serde(default = "default_ingest_interval")]
+    pub poll_interval_ms: u64,
+    /// Read blocks from NEAR Lake rather than the RPC, if set.
+    #[serde(default)]
+    pub lake: Option<LakeConfig>,
+}
+
+/// A NEAR Lake bucket, where the Lake indexer writes each block and its
+/// shards. The buckets are requester pays, so credentials are needed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LakeConfig {
+    /// `near-lake-data-mainnet` or `near-lake-data-testnet`.
+    pub bucket: String,
+    #[serde(default = "default_lake_region")]
+    pub region: String,
+    /// For S3 compatible stores, AWS in `region` if not set.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Read from `AWS_ACCESS_KEY_ID` if not set.
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    /// Read from `AWS_SECRET_ACCESS_KEY` if not set.
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    #[serde(default = "default_s3_timeout")]
+    pub timeout_ms: u64,
+}
+
+impl LakeConfig {
+    /// The bucket as an S3 store, objects are addressed by height.
+    pub fn s3(&self) -> S3Config {
+        S3Config {
+            bucket: self.bucket.clone(),
+            key: String::new(),
+            region: self.region.clone(),
+            endpoint: self.endpoint.clone(),
+            access_key_id: self.access_key_id.clone(),
+            secret_access_key: self.secret_access_key.clone(),
+            timeout_ms: self.timeout_ms,
+        }
+    }
+}
+
+/// Follows every block to build block proofs locally, rather than have the
+/// RPC build them against our head.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlockTreeConfig {
+    #[serde(default = "default_ingest_interval")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_relay_timeout() -> u64 {
+    30_000
+}
+
+/// A data availability layer to post proven heads and proofs to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DaConfig {
+    Celestia(CelestiaConfig),
+    EigenDa(EigenDaConfig),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CelestiaConfig {
+    /// The RPC of a light or bridge node.
+    pub url: String,
+    /// The node's auth token with write permissions.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// The hex of a namespace id, up to 10 bytes.
+    pub namespace: String,
+    /// Left to the node to estimate if not set.
+    #[serde(default)]
+    pub gas_price: Option<f64>,
+    #[serde(default = "default_da_timeout")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EigenDaConfig {
+    /// An eigenda-proxy.
+    pub url: String,
+    #[serde(default = "default_da_timeout")]
+    pub timeout_ms: u64,
+}
+
+/// The verifier contract on the destination chain, read with `eth_call`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VerifierConfig {
+    /// The destination chain's JSON-RPC.
+    pub rpc_url: String,
+    /// The contract, as 0x prefixed hex.
+    pub address: String,
+    #[serde(default)]
+    pub on_mismatch: OnMismatch,
+    #[serde(default = "default_verifier_timeout")]
+    pub timeout_ms: u64,
+}
+
+/// What to do when the contract would reject our proofs.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnMismatch {
+    /// Exit rather than prove what the contract rejects.
+    #[default]
+    Refuse,
+    /// Keep syncing and serving proofs to verify off chain, but don't relay
+    /// any.
+    VerifyOnly,
+}
+
+/// Where checkpoints of the trusted head are kept, and how often they are
+/// written.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HeadStoreConfig {
+    #[serde(flatten)]
+    pub backend: HeadStoreBackend,
+    /// Heads synced between checkpoints, one is also written on each new
+    /// epoch.
+    #[serde(default = "default_head_store_interval")]
+    pub interval: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HeadStoreBackend {
+    /// A file, replaced whole on each checkpoint.
+    Fs {
+        path: PathBuf,
+    },
+    /// A sled database of its own, the store's can't be opened twice.
+    Sled {
+        path: PathBuf,
+    },
+    S3(S3Config),
+}
+
+/// An object in S3 or an S3 compatible store.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default = "default_s3_key")]
+    pub key: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// For S3 compatible stores, AWS in `region` if not set.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Read from `AWS_ACCESS_KEY_ID` if not set.
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    /// Read from `AWS_SECRET_ACCESS_KEY` if not set.
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    #[serde(default = "default_s3_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_head_store_interval() -> u64 {
+    // ~10 minutes at block time
+    300
+}
+
+fn default_s3_key() -> String {
+    "near-light-client/checkpoint".into()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".into()
+}
+
+fn default_lake_region() -> String {
+    // Where the NEAR Lake buckets are
+    "eu-central-1".into()
+}
+
+fn default_s3_timeout() -> u64 {
+    30_000
+}
+
+fn default_verifier_timeout() -> u64 {
+    10_000
+}
+
+/// Blobs can take a few blocks to be included.
+fn default_da_timeout() -> u64 {
+    120_000
+}
+
+fn default_schema_version() -> u32 {
+    // Files from before versioning
+    1
+}
+
+fn default_ingest_interval() -> u64 {
+    // ~block time
+    1000
+}
+
+fn default_batch_size() -> usize {
+    16
+}
+
+fn default_batch_interval() -> u64 {
+    2000
+}
+
+fn default_slot_cost() -> u64 {
+    1000
+}
+
+fn default_batch_timeout() -> u64 {
+    60_000
+}
+
+fn default_heartbeat_blocks() -> u64 {
+    // ~100s, within the default max head age
+    100
+}
+
+fn default_prevalidate() -> bool {
+    true
+}
+
+fn default_provers() -> usize {
+    2
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+fn default_shadow_epochs() -> u64 {
+    3
+}
+
+fn default_max_head_age() -> u64 {
+    120_000
+}
+
+fn default_skew_tolerance() -> u64 {
+    5_000
+}
+
+fn default_marginal_bps() -> u32 {
+    500
+}
+
+fn default_recent_errors() -> usize {
+    crate::client::failure::DEFAULT_RECENT_ERRORS
+}
+
+fn default_cpu_threads() -> usize {
+    // Leave a core for the async workers
+    std::thread::available_parallelism()
+        .map(|n| n.get().saturating_sub(1))
+        .unwrap_or(1)
+}
+
+fn default_probe_interval() -> u64 {
+    100
+}
+
+fn default_lag_warn() -> u64 {
+    200
+}
+
+fn default_audit_path() -> PathBuf {
+    "audit.log".into()
+}
+
+fn default_checkpoint_interval() -> u64 {
+    100
 }
 
 fn default_db_path() -> PathBuf {
@@ -34,17 +798,219 @@ impl Config {
         let default_path =
             env::var("NEAR_LIGHT_CLIENT_CONFIG_FILE").unwrap_or_else(|_| "default".to_string());
 
+        let file = |name: &str| migrate::shim_file(name, File::with_name(name).required(false));
         let s = ConfigTrait::builder()
-            .add_source(File::with_name(&default_path).required(false))
-            .add_source(File::with_name(&run_mode).required(false))
+            .add_source(file(&default_path)?)
+            .add_source(file(&run_mode)?)
             // This file shouldn't be checked in to git
-            .add_source(File::with_name("local").required(false))
-            .add_source(Environment::with_prefix("NEAR_LIGHT_CLIENT"))
+            .add_source(file("local")?)
+            .add_source(migrate::shim_env(Environment::with_prefix(
+                "NEAR_LIGHT_CLIENT",
+            ))?)
             .build()?;
 
-        let r = s.try_deserialize();
+        let r = s.try_deserialize().map(Self::with_profile);
 
         log::debug!("Config: {:#?}", r);
         r
     }
+
+    /// A batch can't be bigger than the verify circuit it is proven in.
+    fn with_profile(mut self) -> Self {
+        let slots = self.prover.profile.verify_slots();
+        if self.scheduler.batch_size > slots {
+            log::warn!(
+                "Batch size {} is too big for the {:?} circuits, using {}",
+                self.scheduler.batch_size,
+                self.prover.profile,
+                slots
+            );
+            self.scheduler.batch_size = slots;
+        }
+        self
+    }
+
+    /// A config file with every key at its default and a comment on each,
+    /// see `near-light-client config example`. Tables without defaults are
+    /// shown commented out.
+    pub fn example() -> String {
+        let mut out = format!(
+            "# near-light-client config, schema {version}\n\
+             schema_version = {version}\n\
+             # The trusted header to sync from while the store is empty, unless\n\
+             # there is a checkpoint in the head_store\n\
+             starting_head = \"<header hash>\"\n\
+             # Sync to the latest head on start rather than waiting for the next block\n\
+             catchup = false\n",
+            version = migrate::SCHEMA_VERSION
+        );
+        write_section::<RpcConfig>(&mut out);
+        write_section::<ProverConfig>(&mut out);
+        write_section::<ApiConfig>(&mut out);
+        write_section::<StoreConfig>(&mut out);
+        write_section::<SchedulerConfig>(&mut out);
+        write_section::<SelectionConfig>(&mut out);
+        write_section::<StalenessConfig>(&mut out);
+        write_section::<FinalityConfig>(&mut out);
+        write_section::<AuditConfig>(&mut out);
+        write_section::<RuntimeConfig>(&mut out);
+        out.push_str(OPTIONAL_SECTIONS);
+        out
+    }
+}
+
+const OPTIONAL_SECTIONS: &str = r#"
+# Post processors run on each proven batch, in order
+# [[hooks.batch]]
+# kind = "attest"
+# signer_key = "ed25519:..."
+# [[hooks.batch]]
+# kind = "relay"
+# url = "https://relayer.example"
+# timeout_ms = 30000
+
+# Post proven heads and proofs to a DA layer, kind is "celestia" or "eigenda"
+# [da]
+# kind = "celestia"
+# url = "http://localhost:26658"
+# auth_token = "..."
+# namespace = "6e6561722d6c63"
+
+# Checkpoint the trusted head to resume from, kind is "fs", "sled" or "s3"
+# [head_store]
+# kind = "s3"
+# bucket = "light-client"
+# region = "us-east-1"
+# interval = 300
+
+# Watch the chain for receipts matching the rules and enqueue them
+# [ingest]
+# start_height = 1000
+# poll_interval_ms = 1000
+# Read blocks from NEAR Lake, rather than polling the RPC for each height
+# [ingest.lake]
+# bucket = "near-lake-data-mainnet"
+# [ingest.rules]
+# ...
+
+# Follow every block to build block proofs locally
+# [block_tree]
+# poll_interval_ms = 1000
+
+# Shadow a circuit build before it may be relayed
+# [canary]
+# function_id = "<circuit digest>"
+# shadow_epochs = 3
+
+# Check the verifier contract accepts our circuits and output version at
+# startup, on_mismatch is "refuse" or "verify_only". Relays under a function id
+# it hasn't registered are refused
+# [verifier]
+# rpc_url = "https://ethereum-rpc.example"
+# address = "0x..."
+# on_mismatch = "refuse"
+
+# API keys and quotas by tenant, anyone can make requests without any
+# [tenants.example]
+# api_keys = ["..."]
+# quota = 100000
+"#;
+
+fn write_section<T: Section>(out: &mut String) {
+    let Ok(Value::Table(defaults)) = Value::try_from(T::default()) else {
+        unreachable!("{} is a table", T::NAME);
+    };
+    out.push_str(&format!("\n[{}]\n", T::NAME));
+    for (key, doc) in T::DOCS {
+        out.push_str(&format!("# {}\n", doc));
+        match defaults.get(*key) {
+            Some(value) => out.push_str(&format!("{} = {}\n", key, inline(value))),
+            None => out.push_str(&format!("# {} =\n", key)),
+        }
+    }
+}
+
+/// The value as it is written on the right of a key.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Table(table) if table.is_empty() => "{}".to_string(),
+        Value::Table(table) => format!(
+            "{{ {} }}",
+            table
+                .iter()
+                .map(|(k, v)| format!("{} = {}", k, inline(v)))
+                .join(", ")
+        ),
+        Value::Array(values) => format!("[{}]", values.iter().map(inline).join(", ")),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FileFormat;
+
+    use super::*;
+
+    #[test]
+    fn test_profile_bounds_batch_size() {
+        let config = |profile: &str| {
+            let config: Config = serde_json::from_value(serde_json::json!({
+                "starting_head": "4zwZQzjQDpimeLK3tX39nzok6UjDU9edS57EFhkAa4Sk",
+                "catchup": false,
+                "prover": { "profile": profile },
+            }))
+            .unwrap();
+            config.with_profile()
+        };
+        assert_eq!(config("dev").scheduler.batch_size, 4);
+        assert_eq!(config("prod").scheduler.batch_size, default_batch_size());
+    }
+
+    fn undocumented<T: Section>() -> Vec<String> {
+        let Ok(Value::Table(defaults)) = Value::try_from(T::default()) else {
+            panic!("{} is not a table", T::NAME);
+        };
+        defaults
+            .keys()
+            .filter(|key| !T::DOCS.iter().any(|(documented, _)| documented == key))
+            .map(|key| format!("{}.{}", T::NAME, key))
+            .collect()
+    }
+
+    #[test]
+    fn test_sections_are_documented() {
+        let undocumented = [
+            undocumented::<RpcConfig>(),
+            undocumented::<ProverConfig>(),
+            undocumented::<ApiConfig>(),
+            undocumented::<StoreConfig>(),
+            undocumented::<SchedulerConfig>(),
+            undocumented::<SelectionConfig>(),
+            undocumented::<StalenessConfig>(),
+            undocumented::<FinalityConfig>(),
+            undocumented::<AuditConfig>(),
+            undocumented::<RuntimeConfig>(),
+        ]
+        .concat();
+        assert_eq!(undocumented, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_example_loads_as_defaults() {
+        let example = Config::example();
+        let config: Config =
+            migrate::shim_file("example", File::from_str(&example, FileFormat::Toml))
+                .and_then(ConfigTrait::try_deserialize)
+                .unwrap();
+        assert_eq!(config.schema_version, migrate::SCHEMA_VERSION);
+        assert_eq!(config.store.path, default_db_path());
+        assert_eq!(config.api.host, default_host());
+        assert_eq!(config.rpc.limits, RpcLimits::default());
+        assert_eq!(config.prover.profile, Profile::Prod);
+        assert_eq!(config.scheduler.batch_size, default_batch_size());
+        assert_eq!(config.audit.signer_key, None);
+        assert!(config.hooks.batch.is_empty());
+        assert!(example.contains("# Slots in each batch"));
+    }
 }
@@ -0,0 +1,320 @@
+//! Config schema versions.
+//!
+//! Files without a `schema_version` are version 1. When a key moves, the old
+//! key keeps working: its value is used when the new key isn't set in the
+//! same file, and a warning names the replacement. Each file is shimmed
+//! before they are merged, so an old key still overrides the new key from
+//! the files before it. `near-light-client config migrate <file>` rewrites a
+//! file in the current schema, so the shims can be dropped once deployments
+//! have migrated.
+use std::{cmp::Ordering, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use config::{Config as ConfigTrait, ConfigError, Source, Value as ConfigValue};
+use toml::{value::Table, Value};
+
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// A key that moved, by its dotted path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Moved {
+    pub from: &'static str,
+    pub to: &'static str,
+    /// The schema version it moved in.
+    pub since: u32,
+}
+
+pub const MOVED: &[Moved] = &[
+    Moved {
+        from: "state_path",
+        to: "store.path",
+        since: 2,
+    },
+    Moved {
+        from: "network",
+        to: "rpc.network",
+        since: 3,
+    },
+    Moved {
+        from: "profile",
+        to: "prover.profile",
+        since: 3,
+    },
+    Moved {
+        from: "host",
+        to: "api.host",
+        since: 3,
+    },
+    Moved {
+        from: "recent_errors",
+        to: "api.recent_errors",
+        since: 3,
+    },
+];
+
+/// Apply the shims for moved keys to one config file, before it is merged
+/// with the others.
+pub fn shim_file<S>(name: &str, file: S) -> Result<ConfigTrait, ConfigError>
+where
+    S: Source + Send + Sync + 'static,
+{
+    let config = ConfigTrait::builder().add_source(file).build()?;
+    if config.collect()?.is_empty() {
+        // Optional files that aren't there
+        return Ok(config);
+    }
+    let version = config.get::<u32>("schema_version").unwrap_or(1);
+    match version.cmp(&SCHEMA_VERSION) {
+        Ordering::Greater => {
+            return Err(ConfigError::Message(format!(
+                "config {} schema {} is newer than this build supports, {}",
+                name, version, SCHEMA_VERSION
+            )))
+        }
+        Ordering::Less => log::warn!(
+            "Config {} schema {} is outdated, upgrade it with `near-light-client config \
+             migrate <file>`",
+            name,
+            version
+        ),
+        Ordering::Equal => (),
+    }
+    shim(name, config)
+}
+
+/// Apply the shims for moved keys to the environment, which has no schema
+/// version.
+pub fn shim_env<S>(env: S) -> Result<ConfigTrait, ConfigError>
+where
+    S: Source + Send + Sync + 'static,
+{
+    shim(
+        "environment",
+        ConfigTrait::builder().add_source(env).build()?,
+    )
+}
+
+fn shim(name: &str, config: ConfigTrait) -> Result<ConfigTrait, ConfigError> {
+    let mut builder = ConfigTrait::builder().add_source(config.clone());
+    for moved in MOVED {
+        let Ok(value) = config.get::<ConfigValue>(moved.from) else {
+            continue;
+        };
+        log::warn!(
+            "`{}` in the {} config is deprecated since schema {}, use `{}`",
+            moved.from,
+            name,
+            moved.since,
+            moved.to
+        );
+        if config.get::<ConfigValue>(moved.to).is_ok() {
+            log::warn!("`{}` is set there too and takes precedence", moved.to);
+        } else {
+            builder = builder.set_override(moved.to, value)?;
+        }
+    }
+    builder.build()
+}
+
+/// Upgrade a config file to the current schema, returning the keys that
+/// moved. Where both the old and the new key are set the new one is kept.
+pub fn migrate(file: &mut Table) -> Result<Vec<Moved>> {
+    let version = match file.get("schema_version") {
+        None => 1,
+        Some(v) => v
+            .as_integer()
+            .ok_or_else(|| anyhow!("`schema_version` should be an integer"))?,
+    };
+    if version > SCHEMA_VERSION as i64 {
+        bail!(
+            "Config schema {} is newer than this build supports, {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+
+    let mut moved = vec![];
+    for m in MOVED.iter().filter(|m| m.since as i64 > version) {
+        if let Some(value) = take(file, m.from) {
+            if get(file, m.to).is_none() {
+                insert(file, m.to, value)?;
+            }
+            moved.push(*m);
+        }
+    }
+    file.insert(
+        "schema_version".to_string(),
+        Value::Integer(SCHEMA_VERSION as i64),
+    );
+    Ok(moved)
+}
+
+/// Migrate the file in place, keeping the original next to it as `.bak`.
+pub fn migrate_file(path: &Path) -> Result<Vec<Moved>> {
+    let original = fs::read_to_string(path)?;
+    let mut file: Table = toml::from_str(&original)?;
+    let moved = migrate(&mut file)?;
+
+    fs::write(path.with_extension("toml.bak"), &original)?;
+    fs::write(path, toml::to_string(&Value::Table(file))?)?;
+    Ok(moved)
+}
+
+fn get<'a>(table: &'a Table, path: &str) -> Option<&'a Value> {
+    match path.split_once('.') {
+        None => table.get(path),
+        Some((key, rest)) => get(table.get(key)?.as_table()?, rest),
+    }
+}
+
+fn take(table: &mut Table, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        None => table.remove(path),
+        Some((key, rest)) => take(table.get_mut(key)?.as_table_mut()?, rest),
+    }
+}
+
+fn insert(table: &mut Table, path: &str, value: Value) -> Result<()> {
+    match path.split_once('.') {
+        None => {
+            table.insert(path.to_string(), value);
+            Ok(())
+        }
+        Some((key, rest)) => {
+            let inner = table
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("`{}` should be a table", key))?;
+            insert(inner, rest, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Environment, File, FileFormat};
+
+    use super::*;
+    use crate::config::Config;
+
+    const V1: &str = r#"
+        catchup = false
+        network = "Testnet"
+        starting_head = "4zwZQzjQDpimeLK3tX39nzok6UjDU9edS57EFhkAa4Sk"
+        state_path = "old.db"
+    "#;
+
+    const DEFAULTS: &str = include_str!("../../../../default.toml");
+
+    fn file(toml: &str) -> File<config::FileSourceString, FileFormat> {
+        File::from_str(toml, FileFormat::Toml)
+    }
+
+    fn load(toml: &str) -> Result<Config, ConfigError> {
+        shim_file("test", file(toml))?.try_deserialize()
+    }
+
+    #[test]
+    fn test_shim_reads_moved_keys() {
+        let config = load(V1).unwrap();
+        assert_eq!(config.schema_version, 1);
+        assert_eq!(config.store.path, Path::new("old.db"));
+        assert!(matches!(config.rpc.network, rpc::Network::Testnet));
+
+        let both = format!("{}\n[store]\npath = \"new.db\"", V1);
+        assert_eq!(load(&both).unwrap().store.path, Path::new("new.db"));
+
+        let newer = format!("schema_version = {}\n{}", SCHEMA_VERSION + 1, V1);
+        assert!(load(&newer).is_err());
+    }
+
+    #[test]
+    fn test_shim_old_file_over_defaults() {
+        let v1 = r#"
+            network = "Mainnet"
+            state_path = "/data/state.db"
+        "#;
+        let config: Config = ConfigTrait::builder()
+            .add_source(shim_file("default", file(DEFAULTS)).unwrap())
+            .add_source(shim_file("local", file(v1)).unwrap())
+            .build()
+            .and_then(ConfigTrait::try_deserialize)
+            .unwrap();
+        assert_eq!(config.store.path, Path::new("/data/state.db"));
+        assert!(matches!(config.rpc.network, rpc::Network::Mainnet));
+
+        // The same from the environment
+        let env = Environment::with_prefix("NEAR_LIGHT_CLIENT").source(Some(
+            [
+                ("NEAR_LIGHT_CLIENT_NETWORK", "Mainnet"),
+                ("NEAR_LIGHT_CLIENT_STATE_PATH", "/data/state.db"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into(),
+        ));
+        let config: Config = ConfigTrait::builder()
+            .add_source(shim_file("default", file(DEFAULTS)).unwrap())
+            .add_source(shim_env(env).unwrap())
+            .build()
+            .and_then(ConfigTrait::try_deserialize)
+            .unwrap();
+        assert_eq!(config.store.path, Path::new("/data/state.db"));
+        assert!(matches!(config.rpc.network, rpc::Network::Mainnet));
+
+        // Once migrated it reads the same
+        let mut migrated: Table = toml::from_str(v1).unwrap();
+        migrate(&mut migrated).unwrap();
+        let config: Config = ConfigTrait::builder()
+            .add_source(shim_file("default", file(DEFAULTS)).unwrap())
+            .add_source(shim_file("local", file(&toml::to_string(&migrated).unwrap())).unwrap())
+            .build()
+            .and_then(ConfigTrait::try_deserialize)
+            .unwrap();
+        assert_eq!(config.store.path, Path::new("/data/state.db"));
+        assert!(matches!(config.rpc.network, rpc::Network::Mainnet));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut file: Table = toml::from_str(V1).unwrap();
+        let moved = migrate(&mut file).unwrap();
+        assert_eq!(
+            moved.iter().map(|m| m.from).collect::<Vec<_>>(),
+            vec!["state_path", "network"]
+        );
+        assert_eq!(get(&file, "state_path"), None);
+        assert_eq!(
+            get(&file, "store.path"),
+            Some(&Value::String("old.db".into()))
+        );
+        assert_eq!(
+            get(&file, "rpc.network"),
+            Some(&Value::String("Testnet".into()))
+        );
+        assert_eq!(
+            file["schema_version"],
+            Value::Integer(SCHEMA_VERSION as i64)
+        );
+
+        // Migrating again changes nothing, and the file still loads
+        let migrated = file.clone();
+        assert_eq!(migrate(&mut file).unwrap(), vec![]);
+        assert_eq!(file, migrated);
+        let written = toml::to_string(&Value::Table(file)).unwrap();
+        assert_eq!(load(&written).unwrap().store.path, Path::new("old.db"));
+
+        let mut both: Table =
+            toml::from_str(&format!("{}\n[store]\npath = \"new.db\"", V1)).unwrap();
+        migrate(&mut both).unwrap();
+        assert_eq!(
+            get(&both, "store.path"),
+            Some(&Value::String("new.db".into()))
+        );
+        assert_eq!(get(&both, "state_path"), None);
+
+        let mut newer: Table =
+            toml::from_str(&format!("schema_version = {}", SCHEMA_VERSION + 1)).unwrap();
+        assert!(migrate(&mut newer).is_err());
+    }
+}
@@ -11,24 +11,73 @@ use axum::{
 use coerce::actor::LocalActorRef;
 use tokio::task::JoinHandle;
 
-use crate::{client::LightClient, config::Config, prelude::*};
+use crate::{build_info::BuildInfo, client::LightClient, config::Config, prelude::*};
 
 // TODO: replace with jsonrpc
 pub(crate) fn init(config: &Config, ctx: LocalActorRef<LightClient>) -> JoinHandle<Result<()>> {
     let controller = Router::new()
         .route("/health", get(health_check))
+        .with_state(ctx.clone())
+        .route("/metrics", get(metrics))
+        .with_state(ctx.clone())
+        .route("/status", get(status))
+        .route("/status/errors", get(recent_errors))
+        .route("/status/progress", get(progress))
+        .with_state(ctx.clone())
+        .route("/audit/head", get(audit_head))
+        .with_state(ctx.clone())
         .route("/head", get(header::get_head))
         .with_state(ctx.clone())
+        .route("/heads/stream", get(header::stream_heads))
+        .with_state(ctx.clone())
         .route("/header/:epoch", get(header::get_by_epoch))
         .with_state(ctx.clone())
+        .route("/epochs/:epoch_id/bps", get(epochs::get_bps))
+        .with_state(ctx.clone())
+        .route("/epochs/:epoch_id/circuits", get(epochs::get_circuits))
+        .with_state(ctx.clone())
         .route("/proof", post(proof::post_get_proof))
         .with_state(ctx.clone())
         .route("/proof/verify", post(proof::post_verify_proof))
         .with_state(ctx.clone())
         .route("/proof/experimental", post(proof::post_get_batch_proof))
+        .with_state(ctx.clone())
+        .route("/proof/:id", get(proof::get_proof))
+        .with_state(ctx.clone())
+        .route("/prove/transaction", post(proof::post_prove_transaction))
+        .with_state(ctx.clone())
+        .route("/prove/receipt", post(proof::post_prove_receipt))
+        .with_state(ctx.clone())
+        .route(
+            "/queue",
+            get(queue::get_pending)
+                .post(queue::post_enqueue)
+                .delete(queue::delete_request),
+        )
+        .with_state(ctx.clone())
+        .route("/queue/requests", get(queue::get_requests))
+        .with_state(ctx.clone())
+        .route("/queue/costs", get(queue::get_costs))
+        .with_state(ctx.clone())
+        .route("/queue/batch", post(queue::post_enqueue_batch))
+        .with_state(ctx.clone())
+        .route("/queue/batch/:batch_id/stream", get(queue::stream_batch))
+        .with_state(ctx.clone())
+        .route("/anchor/:root", get(anchor::get_anchor))
+        .with_state(ctx.clone())
+        .route("/anchor/:root/relay", post(anchor::post_relay))
+        .with_state(ctx.clone())
+        .route("/anchor/:root/circuits", get(anchor::get_circuits))
+        .with_state(ctx.clone())
+        .route("/anchors", get(anchor::get_anchor_at))
+        .with_state(ctx.clone())
+        .route(
+            "/canary",
+            get(canary::get_status).post(canary::post_shadow_output),
+        )
         .with_state(ctx.clone());
 
-    let host = config.host.clone();
+    let host = config.api.host.clone();
     tokio::spawn(async move {
         let addr = SocketAddr::from_str(&host).map_err(|e| anyhow::anyhow!(e))?;
         axum::Server::bind(&addr)
@@ -41,13 +90,77 @@ pub(crate) fn init(config: &Config, ctx: LocalActorRef<LightClient>) -> JoinHand
     })
 }
 
-async fn health_check() -> StatusCode {
-    StatusCode::OK
+/// Unavailable while the head is stale, skew alone doesn't fail the check.
+async fn health_check(State(client): State<LocalActorRef<LightClient>>) -> impl IntoResponse {
+    use crate::client::{message::CheckHead, staleness::Freshness};
+
+    client
+        .send(CheckHead)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+        .and_then(|x| x)
+        .map(|freshness| {
+            let status = match freshness {
+                Freshness::Stale { .. } => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::OK,
+            };
+            (status, axum::Json(freshness))
+        })
+        .map_err(ErrorMapper)
+        .map_err(IntoResponse::into_response)
+}
+
+async fn metrics(State(client): State<LocalActorRef<LightClient>>) -> impl IntoResponse {
+    client
+        .send(crate::client::message::Metrics)
+        .await
+        .map_err(ErrorMapper)
+        .map_err(IntoResponse::into_response)
+}
+
+/// What this prover was built from.
+async fn status() -> impl IntoResponse {
+    axum::Json(BuildInfo::get())
+}
+
+/// The most recent failures with their job, for triage without log access.
+async fn recent_errors(State(client): State<LocalActorRef<LightClient>>) -> impl IntoResponse {
+    client
+        .send(crate::client::message::RecentErrors)
+        .await
+        .map(axum::Json)
+        .map_err(ErrorMapper)
+        .map_err(IntoResponse::into_response)
+}
+
+/// The high-water mark of each pipeline.
+async fn progress(State(client): State<LocalActorRef<LightClient>>) -> impl IntoResponse {
+    client
+        .send(crate::client::message::GetProgress)
+        .await
+        .map(axum::Json)
+        .map_err(ErrorMapper)
+        .map_err(IntoResponse::into_response)
+}
+
+/// The latest audit entry and signed checkpoint, for partners to compare
+/// against the log they were given.
+async fn audit_head(State(client): State<LocalActorRef<LightClient>>) -> impl IntoResponse {
+    client
+        .send(crate::client::message::GetAuditHead)
+        .await
+        .map(axum::Json)
+        .map_err(ErrorMapper)
+        .map_err(IntoResponse::into_response)
 }
 
 mod header {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use futures::Stream;
+    use tokio::sync::broadcast::error::RecvError;
+
     use super::*;
-    use crate::client::message::{Archive, Head};
+    use crate::client::message::{Archive, Head, SubscribeHeads};
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct Params {
@@ -77,22 +190,158 @@ mod header {
             .map_err(ErrorMapper)
             .map_err(IntoResponse::into_response)
     }
+
+    /// Server sent events for every proven and relayed head.
+    pub(super) async fn stream_heads(
+        State(client): State<LocalActorRef<LightClient>>,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Response> {
+        let rx = client
+            .send(SubscribeHeads)
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?;
+
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(head) => {
+                        let event = Event::default().event(head.kind()).json_data(&head);
+                        return Some((event, rx));
+                    }
+                    Err(RecvError::Lagged(n)) => log::warn!("Head stream lagged by {}", n),
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+    }
+}
+
+mod epochs {
+    use axum::{
+        extract::Query,
+        http::header::{HeaderName, CONTENT_TYPE},
+    };
+
+    use super::*;
+    use crate::client::message::{GetCircuitSnapshot, GetEpochBps};
+
+    const BPS_HASH_HEADER: HeaderName = HeaderName::from_static("x-bps-hash");
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Params {
+        epoch_id: CryptoHash,
+    }
+
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Encoding {
+        #[default]
+        Json,
+        /// The raw pre-image, hashing the body gives the BPS hash.
+        Borsh,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct EncodingQuery {
+        #[serde(default)]
+        encoding: Encoding,
+    }
+
+    /// The circuits an epoch was first proven with, to re-prove it with the
+    /// same ones or pick the verifier key for its proofs.
+    pub(super) async fn get_circuits(
+        State(client): State<LocalActorRef<LightClient>>,
+        Path(params): Path<Params>,
+    ) -> Result<impl IntoResponse, Response> {
+        circuits(&client, params.epoch_id).await.map(axum::Json)
+    }
+
+    pub(super) async fn circuits(
+        client: &LocalActorRef<LightClient>,
+        epoch_id: CryptoHash,
+    ) -> Result<crate::client::store::CircuitSnapshot, Response> {
+        client
+            .send(GetCircuitSnapshot { epoch_id })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| {
+                let msg = format!("No circuits recorded for epoch {}", epoch_id);
+                (StatusCode::NOT_FOUND, msg).into_response()
+            })
+    }
+
+    /// The validators behind an epoch's BPS hash, so consumers that only see
+    /// the hash on-chain can re-hash the set themselves.
+    pub(super) async fn get_bps(
+        State(client): State<LocalActorRef<LightClient>>,
+        Path(params): Path<Params>,
+        Query(query): Query<EncodingQuery>,
+    ) -> Result<Response, Response> {
+        let bps = client
+            .send(GetEpochBps {
+                epoch_id: params.epoch_id,
+            })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| {
+                let msg = format!("No block producers for epoch {}", params.epoch_id);
+                (StatusCode::NOT_FOUND, msg).into_response()
+            })?;
+
+        Ok(match query.encoding {
+            Encoding::Json => axum::Json(bps).into_response(),
+            Encoding::Borsh => {
+                let body = borsh::to_vec(&bps.bps)
+                    .map_err(ErrorMapper)
+                    .map_err(IntoResponse::into_response)?;
+                (
+                    [
+                        (CONTENT_TYPE, "application/octet-stream".to_string()),
+                        (BPS_HASH_HEADER, bps.bps_hash.to_string()),
+                    ],
+                    body,
+                )
+                    .into_response()
+            }
+        })
+    }
 }
 
 mod proof {
-    use axum::Json;
+    use axum::{extract::Query, http::HeaderMap, Json};
     use protocol::Proof;
+    use rpc::request::GetProofRequest;
 
     use super::*;
-    use crate::client::message::{BatchGetProof, GetProof, VerifyProof};
+    use crate::client::{
+        batches::BatchStatus,
+        message::{BatchGetProof, EnqueueBatch, GetBatch, GetProof, ProveAt, VerifyProof},
+        rules::Priority,
+    };
+
+    /// Pin the proof to a past head from the root registry, rather than the
+    /// latest.
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct HeadQuery {
+        head: Option<CryptoHash>,
+    }
 
     pub(super) async fn post_get_proof(
         State(client): State<LocalActorRef<LightClient>>,
+        Query(query): Query<HeadQuery>,
         Json(params): Json<GetProof>,
     ) -> impl IntoResponse {
         client
-            .send(params)
+            .send(ProveAt {
+                request: params,
+                head: query.head,
+            })
             .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .and_then(|x| x)
             .map(axum::Json)
             .map_err(ErrorMapper)
             .map_err(IntoResponse::into_response)
@@ -112,6 +361,128 @@ mod proof {
             .map_err(IntoResponse::into_response)
     }
 
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ProveTransaction {
+        transaction_hash: String,
+        sender_id: String,
+        /// Unique within the tenant.
+        requester: String,
+        #[serde(default)]
+        priority: Priority,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ProveReceipt {
+        receipt_id: String,
+        receiver_id: String,
+        /// Unique within the tenant.
+        requester: String,
+        #[serde(default)]
+        priority: Priority,
+    }
+
+    /// Poll `GET /proof/:id` for the proof.
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Submitted {
+        id: CryptoHash,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct IdParams {
+        id: CryptoHash,
+    }
+
+    /// Queues a transaction to be proven without waiting for it.
+    pub(super) async fn post_prove_transaction(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Json(params): Json<ProveTransaction>,
+    ) -> Result<(StatusCode, Json<Submitted>), Response> {
+        let request = GetProofRequest::transaction()
+            .id(params.transaction_hash)
+            .account(params.sender_id);
+        submit(
+            &client,
+            &headers,
+            request,
+            params.requester,
+            params.priority,
+        )
+        .await
+    }
+
+    /// Queues a receipt to be proven without waiting for it.
+    pub(super) async fn post_prove_receipt(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Json(params): Json<ProveReceipt>,
+    ) -> Result<(StatusCode, Json<Submitted>), Response> {
+        let request = GetProofRequest::receipt()
+            .id(params.receipt_id)
+            .account(params.receiver_id);
+        submit(
+            &client,
+            &headers,
+            request,
+            params.requester,
+            params.priority,
+        )
+        .await
+    }
+
+    /// A submission is a batch of one, so it is followed like any batch.
+    async fn submit(
+        client: &LocalActorRef<LightClient>,
+        headers: &HeaderMap,
+        request: GetProofRequest,
+        requester: String,
+        priority: Priority,
+    ) -> Result<(StatusCode, Json<Submitted>), Response> {
+        let id = request
+            .build()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+        let tenant = super::queue::tenant(client, headers).await?;
+        let batch = client
+            .send(EnqueueBatch {
+                ids: vec![id],
+                requester,
+                priority,
+                tenant,
+            })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .map_err(IntoResponse::into_response)?;
+        Ok((StatusCode::ACCEPTED, Json(Submitted { id: batch.batch_id })))
+    }
+
+    /// Accepted while the submission is queued, then the proof or why it
+    /// failed. Forgotten some minutes after it completes.
+    pub(super) async fn get_proof(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Path(params): Path<IdParams>,
+    ) -> Result<(StatusCode, Json<BatchStatus>), Response> {
+        let tenant = super::queue::tenant(&client, &headers).await?;
+        let status = client
+            .send(GetBatch {
+                batch_id: params.id,
+                tenant,
+            })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| {
+                let msg = format!("No proof request {}", params.id);
+                (StatusCode::NOT_FOUND, msg).into_response()
+            })?;
+        let code = match status {
+            BatchStatus::Pending { .. } => StatusCode::ACCEPTED,
+            BatchStatus::Completed { .. } => StatusCode::OK,
+        };
+        Ok((code, Json(status)))
+    }
+
     #[derive(Debug, Serialize)]
     pub struct BatchProofWithErrors {
         proofs: protocol::experimental::Proof,
@@ -120,19 +491,347 @@ mod proof {
 
     pub(super) async fn post_get_batch_proof(
         State(client): State<LocalActorRef<LightClient>>,
+        Query(query): Query<HeadQuery>,
         Json(body): Json<BatchGetProof>,
     ) -> impl IntoResponse {
         client
-            .send(body)
+            .send(ProveAt {
+                request: body,
+                head: query.head,
+            })
             .await
             .map_err(|e| anyhow::anyhow!(e))
-            .and_then(|x| x.ok_or_else(|| anyhow::anyhow!("Failed to get batch proof")))
+            .and_then(|x| x)
             .map_err(ErrorMapper)
             .map_err(IntoResponse::into_response)
             .map(axum::Json)
     }
 }
 
+mod queue {
+    use axum::{
+        http::HeaderMap,
+        response::sse::{Event, KeepAlive, Sse},
+        Json,
+    };
+    use futures::{Stream, StreamExt};
+    use rpc::Unprovable;
+
+    use super::*;
+    use crate::client::{
+        batches::EnqueuedBatch,
+        message::{
+            Authenticate, CancelRequest, Costs, Enqueue, EnqueueBatch, GetRequests, Pending,
+            SubscribeBatch,
+        },
+        queue::{CancelError, Delivery, EnqueueError},
+        tenant::{Tenant, TenantError},
+    };
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct BatchParams {
+        batch_id: CryptoHash,
+    }
+
+    const API_KEY_HEADER: &str = "x-api-key";
+
+    impl IntoResponse for TenantError {
+        fn into_response(self) -> Response {
+            let status = match self {
+                TenantError::Unauthorized => StatusCode::UNAUTHORIZED,
+                TenantError::OverQuota(..) => StatusCode::TOO_MANY_REQUESTS,
+            };
+            (status, self.to_string()).into_response()
+        }
+    }
+
+    impl IntoResponse for EnqueueError {
+        fn into_response(self) -> Response {
+            let status = match &self {
+                EnqueueError::Tenant(e) => return e.clone().into_response(),
+                EnqueueError::Unprovable(Unprovable::NotFound(..)) => StatusCode::NOT_FOUND,
+                EnqueueError::Unprovable(Unprovable::NotFinal(..)) => {
+                    StatusCode::UNPROCESSABLE_ENTITY
+                }
+                EnqueueError::Unprovable(Unprovable::Unavailable(..)) => {
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+            };
+            (status, self.to_string()).into_response()
+        }
+    }
+
+    impl IntoResponse for CancelError {
+        fn into_response(self) -> Response {
+            let status = match self {
+                CancelError::NotQueued(..) => StatusCode::NOT_FOUND,
+                CancelError::InFlight(..) => StatusCode::CONFLICT,
+            };
+            (status, self.to_string()).into_response()
+        }
+    }
+
+    pub(super) async fn tenant(
+        client: &LocalActorRef<LightClient>,
+        headers: &HeaderMap,
+    ) -> Result<Tenant, Response> {
+        let api_key = headers
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        client
+            .send(Authenticate { api_key })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// The tenant's pending slots.
+    pub(super) async fn get_pending(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        client
+            .send(Pending { tenant })
+            .await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// Waits until the requested slot is proven, requests for an id that is
+    /// already queued share its slot.
+    pub(super) async fn post_enqueue(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Json(params): Json<Enqueue>,
+    ) -> Result<Json<Delivery>, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        let rx = client
+            .send(Enqueue { tenant, ..params })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .map_err(IntoResponse::into_response)?;
+        rx.await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// The tenant's requests with their place in the queue, including those
+    /// in flight.
+    pub(super) async fn get_requests(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        client
+            .send(GetRequests { tenant })
+            .await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// Withdraws a pending request, anyone waiting on it gets no delivery.
+    pub(super) async fn delete_request(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Json(params): Json<CancelRequest>,
+    ) -> Result<StatusCode, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        client
+            .send(CancelRequest { tenant, ..params })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .map(|()| StatusCode::NO_CONTENT)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// Queues a batch without waiting, follow it with `stream_batch`.
+    pub(super) async fn post_enqueue_batch(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Json(params): Json<EnqueueBatch>,
+    ) -> Result<Json<EnqueuedBatch>, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        client
+            .send(EnqueueBatch { tenant, ..params })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .map(axum::Json)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// Server sent events as each request of a batch is delivered, then one
+    /// with the proofs and where each request is in them.
+    pub(super) async fn stream_batch(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+        Path(params): Path<BatchParams>,
+    ) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        let subscription = client
+            .send(SubscribeBatch {
+                batch_id: params.batch_id,
+                tenant,
+            })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| {
+                let msg = format!("No batch {}", params.batch_id);
+                (StatusCode::NOT_FOUND, msg).into_response()
+            })?;
+
+        let stream = subscription
+            .into_stream()
+            .map(|event| Event::default().event(event.kind()).json_data(&event));
+        Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+    }
+
+    /// What each of the tenant's requesters has been charged.
+    pub(super) async fn get_costs(
+        State(client): State<LocalActorRef<LightClient>>,
+        headers: HeaderMap,
+    ) -> Result<impl IntoResponse, Response> {
+        let tenant = tenant(&client, &headers).await?;
+        client
+            .send(Costs { tenant })
+            .await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+mod anchor {
+    use axum::{extract::Query, Json};
+    use near_primitives::types::BlockHeight;
+
+    use super::*;
+    use crate::client::{
+        message::{AnchorAt, GetAnchor, RecordRelay},
+        store::Relay,
+    };
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Params {
+        root: CryptoHash,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct HeightQuery {
+        near_height: BlockHeight,
+    }
+
+    /// Which sync a proof's root needs, for diagnosing unknown root reverts.
+    pub(super) async fn get_anchor(
+        State(client): State<LocalActorRef<LightClient>>,
+        Path(params): Path<Params>,
+    ) -> impl IntoResponse {
+        client
+            .send(GetAnchor { root: params.root })
+            .await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// The circuits for a proof against a root, by the epoch of its anchor,
+    /// so a verifier can pick the key without knowing when it was proven.
+    pub(super) async fn get_circuits(
+        State(client): State<LocalActorRef<LightClient>>,
+        Path(params): Path<Params>,
+    ) -> Result<impl IntoResponse, Response> {
+        let anchor = client
+            .send(GetAnchor { root: params.root })
+            .await
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)?
+            .ok_or_else(|| {
+                let msg = format!("No anchor for root {}", params.root);
+                (StatusCode::NOT_FOUND, msg).into_response()
+            })?;
+        super::epochs::circuits(&client, anchor.epoch_id)
+            .await
+            .map(axum::Json)
+    }
+
+    pub(super) async fn post_relay(
+        State(client): State<LocalActorRef<LightClient>>,
+        Path(params): Path<Params>,
+        Json(relay): Json<Relay>,
+    ) -> impl IntoResponse {
+        client
+            .send(RecordRelay {
+                root: params.root,
+                relay,
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .and_then(|x| x)
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    /// The on-chain anchor to reference for a NEAR height, the latest relayed
+    /// sync at or below it.
+    pub(super) async fn get_anchor_at(
+        State(client): State<LocalActorRef<LightClient>>,
+        Query(query): Query<HeightQuery>,
+    ) -> impl IntoResponse {
+        client
+            .send(AnchorAt {
+                height: query.near_height,
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .and_then(|x| x)
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+mod canary {
+    use axum::Json;
+
+    use super::*;
+    use crate::client::message::{GetCanaryStatus, ShadowOutput};
+
+    pub(super) async fn get_status(
+        State(client): State<LocalActorRef<LightClient>>,
+    ) -> impl IntoResponse {
+        client
+            .send(GetCanaryStatus)
+            .await
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+
+    pub(super) async fn post_shadow_output(
+        State(client): State<LocalActorRef<LightClient>>,
+        Json(output): Json<ShadowOutput>,
+    ) -> impl IntoResponse {
+        client
+            .send(output)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .and_then(|x| x)
+            .map(axum::Json)
+            .map_err(ErrorMapper)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
 struct ErrorMapper<T>(pub T);
 impl<T> IntoResponse for ErrorMapper<T>
 where
@@ -0,0 +1,386 @@
+//! The gRPC service, see `proto/prover.proto`. Long running jobs are followed
+//! on a stream rather than polled for, otherwise it asks the client what the
+//! HTTP controller asks it.
+use std::{fmt, net::SocketAddr, pin::Pin, str::FromStr};
+
+use coerce::actor::LocalActorRef;
+use futures::{Stream, StreamExt};
+use near_primitives::types::TransactionOrReceiptId;
+use rpc::{request::GetProofRequest, Unprovable};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+use tonic::{metadata::MetadataMap, transport::Server, Request, Response, Status};
+
+use self::proto::{
+    id::Kind,
+    job_status::Event,
+    prover_server::{Prover, ProverServer},
+    Completed, Delivered, Job, JobStatus, Placement, ProofRequest, SyncRequest, SyncStatus,
+};
+use crate::{
+    client::{
+        batches::BatchEvent,
+        failure::Failure,
+        heads::HeadEvent,
+        message::{Authenticate, EnqueueBatch, Head, SubscribeBatch, SubscribeHeads},
+        queue::EnqueueError,
+        tenant::{Tenant, TenantError},
+        LightClient,
+    },
+    config::Config,
+    prelude::*,
+};
+
+pub mod proto {
+    tonic::include_proto!("nearx.prover.v1");
+}
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Serve on `api.grpc_host`, if it is set.
+pub(crate) fn init(
+    config: &Config,
+    ctx: LocalActorRef<LightClient>,
+) -> Option<JoinHandle<Result<()>>> {
+    let host = config.api.grpc_host.clone()?;
+    Some(tokio::spawn(async move {
+        let addr = SocketAddr::from_str(&host).map_err(|e| anyhow!(e))?;
+        Server::builder()
+            .add_service(ProverServer::new(ProverService(ctx)))
+            .serve(addr)
+            .await
+            .map_err(|e| {
+                log::error!("Failed to start gRPC server: {:?}", e);
+                anyhow!(e)
+            })
+    }))
+}
+
+struct ProverService(LocalActorRef<LightClient>);
+
+impl ProverService {
+    async fn tenant(&self, metadata: &MetadataMap) -> Result<Tenant, Status> {
+        let api_key = metadata
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        Ok(self
+            .0
+            .send(Authenticate { api_key })
+            .await
+            .map_err(internal)??)
+    }
+}
+
+#[tonic::async_trait]
+impl Prover for ProverService {
+    type SyncStream = ResponseStream<SyncStatus>;
+    type WatchProofsStream = ResponseStream<JobStatus>;
+
+    async fn sync(
+        &self,
+        request: Request<SyncRequest>,
+    ) -> Result<Response<Self::SyncStream>, Status> {
+        let min_height = request.into_inner().min_height;
+        // Subscribed first, so no head is missed between reading it and
+        // following it
+        let heads = self.0.send(SubscribeHeads).await.map_err(internal)?;
+        let head = self
+            .0
+            .send(Head)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::unavailable("Not synced to a head yet"))?;
+        let first = sync_status(
+            head.hash(),
+            head.inner_lite.height,
+            head.inner_lite.epoch_id,
+            head.inner_lite.block_merkle_root,
+            min_height,
+        );
+
+        let state = (Some(first), heads, false);
+        let stream = futures::stream::unfold(state, move |(first, mut heads, done)| async move {
+            if done {
+                return None;
+            }
+            let status = match first {
+                Some(status) => status,
+                None => loop {
+                    match heads.recv().await {
+                        Ok(HeadEvent::Proven {
+                            id,
+                            height,
+                            epoch_id,
+                            block_merkle_root,
+                            ..
+                        }) => {
+                            break sync_status(id, height, epoch_id, block_merkle_root, min_height)
+                        }
+                        Ok(HeadEvent::Relayed { .. }) => {}
+                        Err(RecvError::Lagged(n)) => log::warn!("Sync stream lagged by {}", n),
+                        Err(RecvError::Closed) => return None,
+                    }
+                },
+            };
+            let done = status.reached;
+            Some((Ok(status), (None, heads, done)))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn submit_proofs(&self, request: Request<ProofRequest>) -> Result<Response<Job>, Status> {
+        let tenant = self.tenant(request.metadata()).await?;
+        let ProofRequest {
+            ids,
+            requester,
+            priority,
+        } = request.into_inner();
+        let ids = ids
+            .into_iter()
+            .map(get_proof)
+            .collect::<Result<Vec<_>, _>>()?;
+        let priority = u8::try_from(priority)
+            .map_err(|_| Status::invalid_argument(format!("Priority {} is over 255", priority)))?;
+
+        let batch = self
+            .0
+            .send(EnqueueBatch {
+                ids,
+                requester,
+                priority,
+                tenant,
+            })
+            .await
+            .map_err(internal)??;
+        Ok(Response::new(Job {
+            id: batch.batch_id.0.to_vec(),
+            len: batch.len as u32,
+        }))
+    }
+
+    async fn watch_proofs(
+        &self,
+        request: Request<Job>,
+    ) -> Result<Response<Self::WatchProofsStream>, Status> {
+        let tenant = self.tenant(request.metadata()).await?;
+        let job = request.into_inner();
+        let batch_id = CryptoHash::try_from(job.id.as_slice())
+            .map_err(|_| Status::invalid_argument("A job id is 32 bytes"))?;
+        let subscription = self
+            .0
+            .send(SubscribeBatch { batch_id, tenant })
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| Status::not_found(format!("No job {}", batch_id)))?;
+        Ok(Response::new(Box::pin(
+            subscription.into_stream().map(job_status),
+        )))
+    }
+}
+
+fn sync_status(
+    id: CryptoHash,
+    height: u64,
+    epoch_id: CryptoHash,
+    block_merkle_root: CryptoHash,
+    min_height: u64,
+) -> SyncStatus {
+    SyncStatus {
+        head: id.0.to_vec(),
+        height,
+        epoch_id: epoch_id.0.to_vec(),
+        block_merkle_root: block_merkle_root.0.to_vec(),
+        reached: height >= min_height,
+    }
+}
+
+fn get_proof(id: proto::Id) -> Result<TransactionOrReceiptId, Status> {
+    let request = match id.kind {
+        Some(Kind::Transaction(tx)) => GetProofRequest::transaction()
+            .id(tx.transaction_hash)
+            .account(tx.sender_id),
+        Some(Kind::Receipt(receipt)) => GetProofRequest::receipt()
+            .id(receipt.receipt_id)
+            .account(receipt.receiver_id),
+        None => {
+            return Err(Status::invalid_argument(
+                "An id is a transaction or a receipt",
+            ))
+        }
+    };
+    request
+        .build()
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+fn job_status(event: BatchEvent) -> Result<JobStatus, Status> {
+    let event = match event {
+        BatchEvent::Item {
+            index, delivery, ..
+        } => {
+            let (proof, failure) = match &delivery.result {
+                Ok(proof) => (json(proof)?, None),
+                Err(e) => (vec![], Some(to_proto(e))),
+            };
+            Event::Delivered(Delivered {
+                index: index as u32,
+                slot: delivery.slot as u32,
+                cost: delivery.cost,
+                proof,
+                failure,
+            })
+        }
+        BatchEvent::Completed { proofs, items, .. } => Event::Completed(Completed {
+            proofs: proofs.iter().map(json).collect::<Result<_, _>>()?,
+            items: items
+                .into_iter()
+                .map(|item| Placement {
+                    index: item.index as u32,
+                    proof: item.proof.unwrap_or_default() as u32,
+                    slot: item.slot as u32,
+                    cost: item.cost,
+                    failure: item.error.as_ref().map(to_proto),
+                })
+                .collect(),
+        }),
+    };
+    Ok(JobStatus { event: Some(event) })
+}
+
+fn to_proto(failure: &Failure) -> proto::Failure {
+    proto::Failure {
+        reason: failure.reason.to_string(),
+        message: failure.message.clone(),
+    }
+}
+
+fn json(value: &impl Serialize) -> Result<Vec<u8>, Status> {
+    serde_json::to_vec(value).map_err(internal)
+}
+
+fn internal(e: impl fmt::Display) -> Status {
+    Status::internal(e.to_string())
+}
+
+impl From<TenantError> for Status {
+    fn from(e: TenantError) -> Self {
+        match e {
+            TenantError::Unauthorized => Status::unauthenticated(e.to_string()),
+            TenantError::OverQuota(..) => Status::resource_exhausted(e.to_string()),
+        }
+    }
+}
+
+impl From<EnqueueError> for Status {
+    fn from(e: EnqueueError) -> Self {
+        match e {
+            EnqueueError::Tenant(e) => e.into(),
+            EnqueueError::Unprovable(Unprovable::NotFound(..)) => Status::not_found(e.to_string()),
+            EnqueueError::Unprovable(Unprovable::NotFinal(..)) => {
+                Status::failed_precondition(e.to_string())
+            }
+            EnqueueError::Unprovable(Unprovable::Unavailable(..)) => {
+                Status::unavailable(e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+    use crate::{
+        build_info::BuildInfo,
+        client::{
+            batches::Item,
+            failure::FailureReason,
+            queue::{AnchoredProof, Delivery},
+        },
+    };
+
+    #[test]
+    fn test_get_proof() {
+        let id = proto::Id {
+            kind: Some(Kind::Receipt(proto::Receipt {
+                receipt_id: "9cVuYLKYF26QevZ315RLb9ArU3gbcgPc4LDRJfZQyZHo".to_string(),
+                receiver_id: "priceoracle.testnet".to_string(),
+            })),
+        };
+        assert!(matches!(
+            get_proof(id),
+            Ok(TransactionOrReceiptId::Receipt { .. })
+        ));
+
+        let invalid = proto::Id {
+            kind: Some(Kind::Transaction(proto::Transaction {
+                transaction_hash: "nope".to_string(),
+                sender_id: "a.near".to_string(),
+            })),
+        };
+        assert_eq!(
+            get_proof(invalid).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            get_proof(proto::Id { kind: None }).unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn test_job_status() {
+        let id = TransactionOrReceiptId::Receipt {
+            receipt_id: CryptoHash::default(),
+            receiver_id: "a.near".parse().unwrap(),
+        };
+        let failure = Failure::new(FailureReason::Timeout, "boom");
+        let delivered = job_status(BatchEvent::Item {
+            batch_id: CryptoHash::default(),
+            index: 1,
+            id: id.clone(),
+            delivery: Delivery {
+                result: Err(failure.clone()),
+                slot: 0,
+                cost: 2,
+            },
+        })
+        .unwrap();
+        let Some(Event::Delivered(delivered)) = delivered.event else {
+            panic!("Expected a delivery");
+        };
+        assert_eq!((delivered.index, delivered.cost), (1, 2));
+        assert!(delivered.proof.is_empty());
+        assert_eq!(delivered.failure.unwrap().reason, "timeout");
+
+        let proof = AnchoredProof {
+            proof: ExperimentalProof::new(CryptoHash::default(), vec![]),
+            anchor: None,
+            build: BuildInfo::get(),
+            attestations: vec![],
+            packed_results: None,
+        };
+        let completed = job_status(BatchEvent::Completed {
+            batch_id: CryptoHash::default(),
+            proofs: vec![proof],
+            items: vec![Item {
+                index: 0,
+                id,
+                proof: Some(0),
+                slot: 3,
+                cost: 1,
+                error: None,
+            }],
+        })
+        .unwrap();
+        let Some(Event::Completed(completed)) = completed.event else {
+            panic!("Expected the completion");
+        };
+        assert_eq!(completed.proofs.len(), 1);
+        assert!(serde_json::from_slice::<serde_json::Value>(&completed.proofs[0]).is_ok());
+        assert_eq!(completed.items[0].slot, 3);
+    }
+}
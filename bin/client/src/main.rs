@@ -1,18 +1,40 @@
 use coerce::actor::{system::ActorSystem, IntoActor};
 
-use crate::client::{message::Shutdown, LightClient};
+use crate::client::{ingest::Ingester, message::Shutdown, LightClient};
 
+mod build_info;
 mod client;
 mod config;
 mod controller;
+mod grpc;
 
 pub struct ShutdownMsg;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
+    // Serves tokio-console on the default port, 6669
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("config") {
+        return config_command(&args[2..]);
+    }
+
+    let mut config = config::Config::new()?;
+    match args.get(1).map(String::as_str) {
+        Some("dry-run") => return dry_run(&config, &args[2..]).await,
+        Some("audit-verify") => return audit_verify(&config, &args[2..]),
+        _ => (),
+    }
+
+    client::host::check(&config);
+    if let Some(verifier) = &config.verifier {
+        let mode = client::compat::handshake(verifier, build_info::BuildInfo::get()).await?;
+        mode.apply(&mut config);
+    }
 
-    let config = config::Config::new()?;
     let system = ActorSystem::builder()
         .system_name("near-light-client")
         .build();
@@ -21,17 +43,87 @@ async fn main() -> anyhow::Result<()> {
         .into_actor(Some("light-client"), &system)
         .await?;
     let webapi = controller::init(&config, client_actor.clone());
+    let grpc = grpc::init(&config, client_actor.clone());
 
     if tokio::signal::ctrl_c().await.is_ok() {
         log::info!("Shutting down..");
         webapi.abort();
+        if let Some(grpc) = grpc {
+            grpc.abort();
+        }
         client_actor.notify(Shutdown)?;
     }
 
     Ok(())
 }
 
+/// Report which receipts in a historical range the ingest rules would have
+/// proven, e.g. `near-light-client dry-run <from> <to>`.
+async fn dry_run(config: &config::Config, args: &[String]) -> anyhow::Result<()> {
+    let [from, to] = args else {
+        anyhow::bail!("usage: dry-run <from height> <to height>");
+    };
+    let ingest = config
+        .ingest
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("no ingest rules configured"))?;
+
+    let ingester = Ingester::new(
+        ingest,
+        rpc::NearRpcClient::with_limits(config.rpc.network, config.rpc.limits),
+        Default::default(),
+    )?;
+    let matched = ingester.dry_run(from.parse()?, to.parse()?).await?;
+    for (height, priority, id) in &matched {
+        println!("{height}\t{priority}\t{id:?}");
+    }
+    log::info!("{} receipts matched", matched.len());
+    Ok(())
+}
+
+/// Verify an audit log, e.g. `near-light-client audit-verify [path]`,
+/// defaulting to the configured log.
+fn audit_verify(config: &config::Config, args: &[String]) -> anyhow::Result<()> {
+    let path = args
+        .first()
+        .map(Into::into)
+        .unwrap_or_else(|| config.audit.path.clone());
+    let head = client::audit::verify(&path)?;
+    println!("{}", serde_json::to_string_pretty(&head)?);
+    Ok(())
+}
+
+/// Upgrade a config file to the current schema in place, e.g.
+/// `near-light-client config migrate local.toml`, or print a commented example
+/// with `near-light-client config example`.
+fn config_command(args: &[String]) -> anyhow::Result<()> {
+    const USAGE: &str = "usage: config migrate <file> | config example";
+    let path = match args {
+        [command] if command == "example" => {
+            print!("{}", config::Config::example());
+            return Ok(());
+        }
+        [command, path] if command == "migrate" => path,
+        _ => anyhow::bail!(USAGE),
+    };
+
+    let moved = config::migrate::migrate_file(path.as_ref())?;
+    for m in &moved {
+        println!("{} -> {}", m.from, m.to);
+    }
+    println!(
+        "{} is at schema {}, the original was kept as {}.bak",
+        path,
+        config::migrate::SCHEMA_VERSION,
+        path
+    );
+    Ok(())
+}
+
 pub mod prelude {
+    // The library crates return `nearx_error::Result`, which converts into
+    // ours with `?`
+    pub use anyhow::{anyhow, Result};
     pub use async_trait::async_trait;
     pub use protocol::prelude::*;
 }
@@ -0,0 +1,312 @@
+//! The error type shared by the light client crates.
+//!
+//! An error starts in one layer, where it is raised, and picks up context on
+//! its way out through the layers above: fetching from the RPC, checking the
+//! protocol, preparing the witness, proving and relaying. Each layer adds what
+//! it was doing, so an error reaching the API reads as the whole story:
+//!
+//! ```text
+//! relaying proof 7Gv..: proving receipt 9xM..: merkle root mismatch at level 7
+//! ```
+//!
+//! `{}` shows the outermost context, `{:#}` the whole chain. The error raised
+//! first stays downcastable through any number of contexts, and the chain is
+//! exposed through `std::error::Error::source`, so it survives conversion into
+//! `anyhow` too.
+use std::{error::Error as StdError, fmt};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where an error was raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layer {
+    /// Fetching from the NEAR RPC.
+    Rpc,
+    /// Checking headers and proofs natively.
+    Protocol,
+    /// Turning what was fetched into circuit inputs.
+    Witness,
+    /// Building, proving or wrapping a circuit.
+    Prove,
+    /// Submitting proofs to a verifier.
+    Relay,
+    /// Reading and writing files and encodings.
+    Io,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Rpc => "rpc",
+            Self::Protocol => "protocol",
+            Self::Witness => "witness",
+            Self::Prove => "prove",
+            Self::Relay => "relay",
+            Self::Io => "io",
+        })
+    }
+}
+
+pub struct Error(Box<Inner>);
+
+enum Inner {
+    Root {
+        layer: Layer,
+        error: Box<dyn StdError + Send + Sync + 'static>,
+    },
+    Context {
+        message: String,
+        source: Error,
+    },
+}
+
+/// An error raised as a message, see `err!`.
+#[derive(Debug)]
+struct Message(String);
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for Message {}
+
+impl Error {
+    pub fn new(layer: Layer, error: impl StdError + Send + Sync + 'static) -> Self {
+        Self(Box::new(Inner::Root {
+            layer,
+            error: Box::new(error),
+        }))
+    }
+
+    /// For errors that are already boxed, such as `anyhow`'s.
+    pub fn from_boxed(layer: Layer, error: Box<dyn StdError + Send + Sync + 'static>) -> Self {
+        Self(Box::new(Inner::Root { layer, error }))
+    }
+
+    pub fn msg(layer: Layer, message: impl fmt::Display) -> Self {
+        Self::new(layer, Message(message.to_string()))
+    }
+
+    /// Say what was being done when this error was raised.
+    pub fn context(self, message: impl fmt::Display) -> Self {
+        Self(Box::new(Inner::Context {
+            message: message.to_string(),
+            source: self,
+        }))
+    }
+
+    /// The layer the error was raised in, not the layers it passed through.
+    pub fn layer(&self) -> Layer {
+        match &*self.0 {
+            Inner::Root { layer, .. } => *layer,
+            Inner::Context { source, .. } => source.layer(),
+        }
+    }
+
+    /// This error and each of its sources, outermost first. The error raised
+    /// first is in the chain as itself, so it can be downcast.
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+        std::iter::successors(Some(self.as_dyn()), |e| e.source())
+    }
+
+    fn as_dyn(&self) -> &(dyn StdError + 'static) {
+        match &*self.0 {
+            Inner::Root { error, .. } => &**error,
+            Inner::Context { .. } => self,
+        }
+    }
+
+    /// The innermost source.
+    pub fn root_cause(&self) -> &(dyn StdError + 'static) {
+        self.chain().last().expect("the chain starts with self")
+    }
+
+    /// The first error of type `E` in the chain.
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        self.chain().find_map(|e| e.downcast_ref::<E>())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            for (i, e) in self.chain().enumerate() {
+                if i > 0 {
+                    f.write_str(": ")?;
+                }
+                write!(f, "{}", e)?;
+            }
+            return Ok(());
+        }
+        match &*self.0 {
+            Inner::Root { error, .. } => write!(f, "{}", error),
+            Inner::Context { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {:#}", self.layer(), self)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &*self.0 {
+            Inner::Root { error, .. } => error.source(),
+            Inner::Context { source, .. } => Some(source.as_dyn()),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(Layer::Io, e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::new(Layer::Io, e)
+    }
+}
+
+/// Add context to an error on its way up.
+pub trait Context<T> {
+    fn context(self, message: impl fmt::Display) -> Result<T>;
+
+    fn with_context<C: fmt::Display>(self, f: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, message: impl fmt::Display) -> Result<T> {
+        self.map_err(|e| e.into().context(message))
+    }
+
+    fn with_context<C: fmt::Display>(self, f: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+/// Raise an error of another crate in a layer, for errors that have no
+/// conversion of their own.
+pub trait InLayer<T> {
+    fn in_layer(self, layer: Layer) -> Result<T>;
+}
+
+impl<T, E: StdError + Send + Sync + 'static> InLayer<T> for Result<T, E> {
+    fn in_layer(self, layer: Layer) -> Result<T> {
+        self.map_err(|e| Error::new(layer, e))
+    }
+}
+
+/// An error raised in a layer, `err!(Rpc, "no block at {}", height)`.
+#[macro_export]
+macro_rules! err {
+    ($layer:ident, $($arg:tt)+) => {
+        $crate::Error::msg($crate::Layer::$layer, format!($($arg)+))
+    };
+}
+
+/// Return early with `err!`.
+#[macro_export]
+macro_rules! bail {
+    ($layer:ident, $($arg:tt)+) => {
+        return Err($crate::err!($layer, $($arg)+).into())
+    };
+}
+
+/// Return early with `err!` unless the condition holds.
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $layer:ident, $($arg:tt)+) => {
+        if !$cond {
+            $crate::bail!($layer, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Mismatch(u8);
+
+    impl fmt::Display for Mismatch {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "merkle root mismatch at level {}", self.0)
+        }
+    }
+
+    impl StdError for Mismatch {}
+
+    fn verify(level: u8) -> Result<()> {
+        Err(Error::new(Layer::Protocol, Mismatch(level)))
+    }
+
+    fn prove() -> Result<()> {
+        verify(7).context("proving receipt X")
+    }
+
+    fn relay() -> Result<()> {
+        prove().with_context(|| format!("relaying {}", "proof Y"))
+    }
+
+    #[test]
+    fn test_context_chain() {
+        let e = relay().unwrap_err();
+        assert_eq!(e.to_string(), "relaying proof Y");
+        assert_eq!(
+            format!("{:#}", e),
+            "relaying proof Y: proving receipt X: merkle root mismatch at level 7"
+        );
+        assert_eq!(
+            format!("{:?}", e),
+            "[protocol] relaying proof Y: proving receipt X: merkle root mismatch at level 7"
+        );
+        assert_eq!(e.layer(), Layer::Protocol);
+        assert_eq!(e.downcast_ref::<Mismatch>(), Some(&Mismatch(7)));
+        assert_eq!(e.root_cause().to_string(), Mismatch(7).to_string());
+        assert_eq!(e.chain().count(), 3);
+    }
+
+    #[test]
+    fn test_macros() {
+        fn check(height: u64) -> Result<u64> {
+            ensure!(height > 0, Rpc, "no block at {}", height);
+            if height > 10 {
+                bail!(Witness, "{} is past the head", height);
+            }
+            Ok(height)
+        }
+        assert_eq!(check(1).unwrap(), 1);
+        let e = check(0).unwrap_err();
+        assert_eq!(
+            (e.layer(), e.to_string()),
+            (Layer::Rpc, "no block at 0".into())
+        );
+        assert_eq!(check(11).unwrap_err().layer(), Layer::Witness);
+    }
+
+    #[test]
+    fn test_conversions() {
+        fn read() -> Result<serde_json::Value> {
+            let value = serde_json::from_str("{")?;
+            Ok(value)
+        }
+        let e = read().unwrap_err();
+        assert_eq!(e.layer(), Layer::Io);
+        assert!(e.downcast_ref::<serde_json::Error>().is_some());
+
+        let e = Err::<(), _>(std::fmt::Error)
+            .in_layer(Layer::Relay)
+            .context("submitting")
+            .unwrap_err();
+        assert_eq!(e.layer(), Layer::Relay);
+        assert!(e.downcast_ref::<std::fmt::Error>().is_some());
+    }
+}
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use near_light_client_outputs::abi::{SyncOutput, VerifyOutput};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(output) = SyncOutput::decode(data) {
+        assert_eq!(output.next_bps().count(), output.next_bps_len());
+    }
+    if let Ok(output) = VerifyOutput::decode(data) {
+        assert_eq!(output.results().count(), output.len());
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use near_light_client_outputs::concat::{RollingSyncOutput, SyncOutput, VerifyOutput};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = SyncOutput::decode(data);
+    let _ = RollingSyncOutput::decode(data);
+    if let Ok(output) = VerifyOutput::decode(data) {
+        assert_eq!(output.results().count(), output.len());
+    }
+});
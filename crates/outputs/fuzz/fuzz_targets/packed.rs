@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use near_light_client_outputs::packed::{SyncCommitment, SyncOutput, VerifyResults};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(output) = VerifyResults::decode(data) {
+        assert_eq!(output.results().count(), output.len());
+    }
+    if let Ok(output) = SyncOutput::decode(data) {
+        assert_eq!(output.next_bps().count(), output.next_bps_len());
+    }
+    let _ = SyncCommitment::decode(data);
+});
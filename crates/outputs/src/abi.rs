@@ -0,0 +1,337 @@
+pub use crate::concat::VerifyResult;
+use crate::{Error, Hash, Reader, Result};
+
+const WORD: usize = 32;
+
+/// A `Validator` is four words, the account id takes two.
+const VALIDATOR_LEN: usize = 4 * WORD;
+
+/// A `Result` is five words.
+const RESULT_LEN: usize = 5 * WORD;
+
+/// A uint or bool of `size` bytes, right aligned in its word with the rest
+/// zeroed, `abi.decode` reverts otherwise.
+fn uint(reader: &mut Reader<'_>, size: usize) -> Result<u128> {
+    let word = reader.hash()?;
+    let (padding, value) = word.split_at(WORD - size);
+    if padding.iter().any(|b| *b != 0) {
+        return Err(Error::InvalidAbi);
+    }
+    Ok(value.iter().fold(0, |acc, b| acc << 8 | *b as u128))
+}
+
+/// The head of a dynamic array, its offset which must point right after the
+/// head, then its length. Returns the elements, which must fill the input.
+fn array<'a>(reader: &mut Reader<'a>, head_words: usize, size: usize) -> Result<(usize, &'a [u8])> {
+    if uint(reader, 8)? != (head_words * WORD) as u128 {
+        return Err(Error::InvalidAbi);
+    }
+    let len = uint(reader, 8)?;
+    let bytes = usize::try_from(len)
+        .ok()
+        .and_then(|l| l.checked_mul(size))
+        .ok_or(Error::UnexpectedEof)?;
+    let elements = reader.bytes(bytes)?;
+    reader.clone().finish()?;
+    Ok((len as usize, elements))
+}
+
+/// A seat of the next BPS, unfilled seats are zeroed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validator<'a> {
+    /// Without the padding of its two words.
+    pub account_id: &'a str,
+    pub public_key: &'a Hash,
+    pub stake: u128,
+}
+
+impl<'a> Validator<'a> {
+    fn read(reader: &mut Reader<'a>) -> Result<Self> {
+        let account_id =
+            core::str::from_utf8(reader.bytes(2 * WORD)?).map_err(|_| Error::InvalidUtf8)?;
+        Ok(Self {
+            account_id: account_id.trim_end_matches([',', '\0']),
+            public_key: reader.hash()?,
+            stake: uint(reader, 16)?,
+        })
+    }
+}
+
+/// The outputs of `SyncCircuit` built with the `abi` feature,
+/// `(bytes32, bytes32, bytes32, bytes32, bytes32, Validator[])`. The
+/// validators are checked when decoding, but only read out when iterated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOutput<'a> {
+    pub domain: &'a Hash,
+    pub new_head_hash: &'a Hash,
+    /// The epoch of the new head.
+    pub epoch_id: &'a Hash,
+    pub next_epoch_id: &'a Hash,
+    pub next_bps_epoch: &'a Hash,
+    len: usize,
+    next_bps: &'a [u8],
+}
+
+impl<'a> SyncOutput<'a> {
+    const HEAD_WORDS: usize = 6;
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let domain = reader.hash()?;
+        let new_head_hash = reader.hash()?;
+        let epoch_id = reader.hash()?;
+        let next_epoch_id = reader.hash()?;
+        let next_bps_epoch = reader.hash()?;
+        let (len, next_bps) = array(&mut reader, Self::HEAD_WORDS, VALIDATOR_LEN)?;
+
+        let mut validators = Reader::new(next_bps);
+        for _ in 0..len {
+            Validator::read(&mut validators)?;
+        }
+
+        Ok(Self {
+            domain,
+            new_head_hash,
+            epoch_id,
+            next_epoch_id,
+            next_bps_epoch,
+            len,
+            next_bps,
+        })
+    }
+
+    pub fn next_bps_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn next_bps(&self) -> impl Iterator<Item = Validator<'a>> + 'a {
+        let mut reader = Reader::new(self.next_bps);
+        (0..self.len).map_while(move |_| Validator::read(&mut reader).ok())
+    }
+}
+
+/// The outputs of `VerifyCircuit` built with the `abi` feature,
+/// `(bytes32, Result[])` of the contract's `ProofVerificationResult`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOutput<'a> {
+    pub domain: &'a Hash,
+    len: usize,
+    results: &'a [u8],
+}
+
+impl<'a> VerifyOutput<'a> {
+    const HEAD_WORDS: usize = 2;
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let domain = reader.hash()?;
+        let (len, results) = array(&mut reader, Self::HEAD_WORDS, RESULT_LEN)?;
+
+        let mut checked = Reader::new(results);
+        for _ in 0..len {
+            Self::read(&mut checked)?;
+        }
+
+        Ok(Self {
+            domain,
+            len,
+            results,
+        })
+    }
+
+    fn read(reader: &mut Reader<'a>) -> Result<VerifyResult<'a>> {
+        let id = reader.hash()?;
+        let passed = match uint(reader, 1)? {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::InvalidAbi),
+        };
+        Ok(VerifyResult {
+            id,
+            passed,
+            status: uint(reader, 1)? as u8,
+            gas_burnt: uint(reader, 8)? as u64,
+            result_hash: reader.hash()?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = VerifyResult<'a>> + 'a {
+        let mut reader = Reader::new(self.results);
+        (0..self.len).map_while(move |_| Self::read(&mut reader).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::fuzz;
+
+    fn word(value: u128) -> [u8; WORD] {
+        let mut word = [0u8; WORD];
+        word[16..].copy_from_slice(&value.to_be_bytes());
+        word
+    }
+
+    /// Writes words into a fixed buffer, there is no allocator.
+    struct Writer<const N: usize>([u8; N], usize);
+
+    impl<const N: usize> Writer<N> {
+        fn new() -> Self {
+            Self([0; N], 0)
+        }
+
+        fn write(&mut self, bytes: &[u8]) -> &mut Self {
+            self.0[self.1..self.1 + bytes.len()].copy_from_slice(bytes);
+            self.1 += bytes.len();
+            self
+        }
+
+        fn bytes(&self) -> &[u8] {
+            &self.0[..self.1]
+        }
+    }
+
+    fn sync_fixture() -> Writer<{ 7 * WORD + 2 * VALIDATOR_LEN }> {
+        let mut account_id = [b','; 2 * WORD];
+        account_id[..12].copy_from_slice(b"alice.near,,");
+        let mut w = Writer::new();
+        w.write(&[1; WORD])
+            .write(&[2; WORD])
+            .write(&[3; WORD])
+            .write(&[4; WORD])
+            .write(&[5; WORD])
+            .write(&word(6 * WORD as u128))
+            .write(&word(2))
+            .write(&account_id)
+            .write(&[7; WORD])
+            .write(&word(1_000))
+            // An unfilled seat
+            .write(&[0; VALIDATOR_LEN]);
+        w
+    }
+
+    fn verify_fixture() -> Writer<{ 3 * WORD + 2 * RESULT_LEN }> {
+        let mut w = Writer::new();
+        w.write(&[1; WORD])
+            .write(&word(2 * WORD as u128))
+            .write(&word(2))
+            .write(&[7; WORD])
+            .write(&word(1))
+            .write(&word(3))
+            .write(&word(2434069818500))
+            .write(&[8; WORD])
+            .write(&[0; RESULT_LEN]);
+        w
+    }
+
+    #[test]
+    fn test_sync() {
+        let fixture = sync_fixture();
+        let bytes = fixture.bytes();
+        let output = SyncOutput::decode(bytes).unwrap();
+        assert_eq!(output.new_head_hash, &[2; WORD]);
+        assert_eq!(output.next_epoch_id, &[4; WORD]);
+        assert_eq!(output.next_bps_epoch, &[5; WORD]);
+        assert_eq!(output.next_bps_len(), 2);
+
+        let mut next_bps = output.next_bps();
+        assert_eq!(
+            next_bps.next(),
+            Some(Validator {
+                account_id: "alice.near",
+                public_key: &[7; WORD],
+                stake: 1_000,
+            })
+        );
+        assert_eq!(
+            next_bps.next().map(|v| (v.account_id, v.stake)),
+            Some(("", 0))
+        );
+        assert_eq!(next_bps.next(), None);
+
+        assert_eq!(
+            SyncOutput::decode(&bytes[..bytes.len() - 1]),
+            Err(Error::UnexpectedEof)
+        );
+        // A concatenated output has no offset
+        assert_eq!(
+            SyncOutput::decode(&bytes[..4 * WORD]),
+            Err(Error::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_sync_rejects_what_solidity_would() {
+        let fixture = sync_fixture();
+        let valid = fixture.bytes();
+        let mut bytes = [0u8; 7 * WORD + 2 * VALIDATOR_LEN];
+
+        // An offset past the head
+        bytes.copy_from_slice(valid);
+        bytes[6 * WORD - 1] += 32;
+        assert_eq!(SyncOutput::decode(&bytes), Err(Error::InvalidAbi));
+
+        // A stake overflowing a uint128
+        bytes.copy_from_slice(valid);
+        bytes[7 * WORD + 3 * WORD] = 1;
+        assert_eq!(SyncOutput::decode(&bytes), Err(Error::InvalidAbi));
+    }
+
+    #[test]
+    fn test_verify() {
+        let fixture = verify_fixture();
+        let bytes = fixture.bytes();
+        let output = VerifyOutput::decode(bytes).unwrap();
+        assert_eq!(output.domain, &[1; WORD]);
+        assert_eq!(output.len(), 2);
+
+        let mut results = output.results();
+        assert_eq!(
+            results.next(),
+            Some(VerifyResult {
+                id: &[7; WORD],
+                passed: true,
+                status: 3,
+                gas_burnt: 2434069818500,
+                result_hash: &[8; WORD],
+            })
+        );
+        assert_eq!(results.next().map(|r| r.passed), Some(false));
+        assert_eq!(results.next(), None);
+
+        let mut extended = [0u8; 4 * WORD + 2 * RESULT_LEN];
+        extended[..bytes.len()].copy_from_slice(bytes);
+        assert_eq!(
+            VerifyOutput::decode(&extended),
+            Err(Error::TrailingBytes(WORD))
+        );
+
+        // Solidity only decodes 0 or 1 as a bool
+        let mut dirty = [0u8; 3 * WORD + 2 * RESULT_LEN];
+        dirty.copy_from_slice(bytes);
+        dirty[5 * WORD - 1] = 2;
+        assert_eq!(VerifyOutput::decode(&dirty), Err(Error::InvalidAbi));
+    }
+
+    #[test]
+    fn test_fuzz() {
+        fuzz(sync_fixture().bytes(), |bytes| {
+            if let Ok(output) = SyncOutput::decode(bytes) {
+                assert_eq!(output.next_bps().count(), output.next_bps_len());
+            }
+        });
+        fuzz(verify_fixture().bytes(), |bytes| {
+            if let Ok(output) = VerifyOutput::decode(bytes) {
+                assert_eq!(output.results().count(), output.len());
+            }
+        });
+    }
+}
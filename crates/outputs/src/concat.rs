@@ -0,0 +1,184 @@
+use crate::{Error, Hash, Reader, Result};
+
+/// The outputs of `SyncCircuit`, also those of `SkipSyncCircuit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOutput<'a> {
+    pub domain: &'a Hash,
+    pub new_head_hash: &'a Hash,
+    /// The epoch of the new head.
+    pub epoch_id: &'a Hash,
+    pub next_epoch_id: &'a Hash,
+}
+
+impl<'a> SyncOutput<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let output = Self {
+            domain: reader.hash()?,
+            new_head_hash: reader.hash()?,
+            epoch_id: reader.hash()?,
+            next_epoch_id: reader.hash()?,
+        };
+        reader.finish()?;
+        Ok(output)
+    }
+}
+
+/// The outputs of `RollingSyncCircuit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollingSyncOutput<'a> {
+    pub domain: &'a Hash,
+    pub new_head_hash: &'a Hash,
+}
+
+impl<'a> RollingSyncOutput<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let output = Self {
+            domain: reader.hash()?,
+            new_head_hash: reader.hash()?,
+        };
+        reader.finish()?;
+        Ok(output)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyResult<'a> {
+    pub id: &'a Hash,
+    pub passed: bool,
+    /// The borsh tag of the outcome's `PartialExecutionStatus`.
+    pub status: u8,
+    pub gas_burnt: u64,
+    /// The hash of the returned value, or the receipt id the result is
+    /// deferred to, zero for the other statuses.
+    pub result_hash: &'a Hash,
+}
+
+/// The outputs of `VerifyCircuit`, the domain followed by an id, a result
+/// byte, a status byte, the big endian gas burnt and a result hash for each
+/// slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOutput<'a> {
+    pub domain: &'a Hash,
+    results: &'a [u8],
+}
+
+impl<'a> VerifyOutput<'a> {
+    const RESULT_LEN: usize = 32 + 1 + 1 + 8 + 32;
+
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let domain = reader.hash()?;
+        let results = reader.remaining();
+        match results.len() % Self::RESULT_LEN {
+            0 => Ok(Self { domain, results }),
+            n => Err(Error::TrailingBytes(n)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len() / Self::RESULT_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Like the contract, any non zero result byte has passed.
+    pub fn results(&self) -> impl Iterator<Item = VerifyResult<'a>> + 'a {
+        self.results
+            .chunks_exact(Self::RESULT_LEN)
+            .map(|r| VerifyResult {
+                id: r[..32].try_into().expect("chunk is 74 bytes"),
+                passed: r[32] != 0,
+                status: r[33],
+                gas_burnt: u64::from_be_bytes(r[34..42].try_into().expect("chunk is 74 bytes")),
+                result_hash: r[42..].try_into().expect("chunk is 74 bytes"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::fuzz;
+
+    #[test]
+    fn test_sync() {
+        let mut bytes = [0u8; 160];
+        bytes[32..64].fill(1);
+        bytes[64..96].fill(2);
+        bytes[96..128].fill(3);
+
+        let output = SyncOutput::decode(&bytes[..128]).unwrap();
+        assert_eq!(output.new_head_hash, &[1; 32]);
+        assert_eq!(output.epoch_id, &[2; 32]);
+        assert_eq!(output.next_epoch_id, &[3; 32]);
+        assert_eq!(SyncOutput::decode(&bytes), Err(Error::TrailingBytes(32)));
+        assert_eq!(SyncOutput::decode(&bytes[..64]), Err(Error::UnexpectedEof));
+
+        let output = RollingSyncOutput::decode(&bytes[..64]).unwrap();
+        assert_eq!(output.new_head_hash, &[1; 32]);
+        assert_eq!(
+            RollingSyncOutput::decode(&bytes[..96]),
+            Err(Error::TrailingBytes(32))
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut bytes = [0u8; 32 + 74 * 2];
+        bytes[32..64].fill(7);
+        bytes[64] = 1;
+        bytes[65] = 3;
+        bytes[66..74].copy_from_slice(&2434069818500u64.to_be_bytes());
+        bytes[74..106].fill(8);
+
+        let output = VerifyOutput::decode(&bytes).unwrap();
+        assert_eq!(output.len(), 2);
+        let mut results = output.results();
+        assert_eq!(
+            results.next(),
+            Some(VerifyResult {
+                id: &[7; 32],
+                passed: true,
+                status: 3,
+                gas_burnt: 2434069818500,
+                result_hash: &[8; 32],
+            })
+        );
+        assert_eq!(
+            results.next(),
+            Some(VerifyResult {
+                id: &[0; 32],
+                passed: false,
+                status: 0,
+                gas_burnt: 0,
+                result_hash: &[0; 32],
+            })
+        );
+        assert_eq!(results.next(), None);
+
+        assert_eq!(
+            VerifyOutput::decode(&bytes[..bytes.len() - 1]),
+            Err(Error::TrailingBytes(73))
+        );
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let valid = [3u8; 32 + 74 * 4];
+        fuzz(&valid[..128], |bytes| {
+            let _ = SyncOutput::decode(bytes);
+        });
+        fuzz(&valid[..96], |bytes| {
+            let _ = RollingSyncOutput::decode(bytes);
+        });
+        fuzz(&valid, |bytes| {
+            if let Ok(output) = VerifyOutput::decode(bytes) {
+                assert_eq!(output.results().count(), output.len());
+            }
+        });
+    }
+}
@@ -0,0 +1,203 @@
+//! Decoders for the public outputs of the light client circuits.
+//!
+//! These are `no_std` and never allocate, everything borrows from the input,
+//! so they can run inside other zkVM guests that consume the outputs as part
+//! of a bigger statement. Malformed input is an error, never a panic.
+#![no_std]
+
+use core::fmt;
+
+/// The outputs of circuits built with the `abi` feature, as `abi.encode`d.
+pub mod abi;
+/// The concatenated outputs the circuits write by default.
+pub mod concat;
+/// The packed outputs, see `near_light_client_protocol::packing`.
+pub mod packed;
+
+pub type Hash = [u8; 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEof,
+    VarintOverflow,
+    InvalidUtf8,
+    /// An outcome status kind past `SuccessReceiptId`.
+    InvalidStatus(u8),
+    /// A word Solidity's `abi.decode` would reject, a value with dirty
+    /// padding or an offset that isn't where the data follows.
+    InvalidAbi,
+    /// The input has bytes left over after the output.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::VarintOverflow => write!(f, "varint overflows"),
+            Self::InvalidUtf8 => write!(f, "invalid utf8"),
+            Self::InvalidStatus(kind) => write!(f, "invalid outcome status {}", kind),
+            Self::InvalidAbi => write!(f, "invalid abi encoding"),
+            Self::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A cursor over the input.
+#[derive(Debug, Clone)]
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::UnexpectedEof);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn hash(&mut self) -> Result<&'a Hash> {
+        Ok(self.bytes(32)?.try_into().expect("length checked"))
+    }
+
+    /// An LEB128 varint.
+    pub fn varint(&mut self) -> Result<u128> {
+        let mut value = 0u128;
+        for shift in (0..u128::BITS).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            let bits = (byte & 0x7f) as u128;
+            if bits << shift >> shift != bits {
+                return Err(Error::VarintOverflow);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::VarintOverflow)
+    }
+
+    pub fn varint_u64(&mut self) -> Result<u64> {
+        self.varint()?.try_into().map_err(|_| Error::VarintOverflow)
+    }
+
+    /// A length that must fit in the remaining input, at `size` bytes each.
+    pub fn len_prefix(&mut self, size: usize) -> Result<usize> {
+        let len = self.varint_u64()?;
+        match usize::try_from(len).ok().and_then(|l| l.checked_mul(size)) {
+            Some(bytes) if bytes <= self.0.len() => Ok(len as usize),
+            _ => Err(Error::UnexpectedEof),
+        }
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.0.len() {
+            0 => Ok(()),
+            n => Err(Error::TrailingBytes(n)),
+        }
+    }
+}
+
+/// Deterministic fuzzing, so the decoders are exercised against malformed
+/// input on every test run. The `fuzz` directory has coverage guided targets
+/// for longer runs.
+#[cfg(test)]
+pub(crate) mod fuzz {
+    /// xorshift64, good enough to generate garbage.
+    pub struct Rng(pub u64);
+
+    impl Rng {
+        pub fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        pub fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    pub const ITERATIONS: usize = 10_000;
+
+    /// Run `decode` over random input and mutations of `valid`: truncations,
+    /// extensions and bit flips. It must never panic.
+    pub fn fuzz(valid: &[u8], mut decode: impl FnMut(&[u8])) {
+        let mut rng = Rng(0x5eed);
+        let mut buf = [0u8; 4096];
+        assert!(valid.len() < buf.len());
+
+        for _ in 0..ITERATIONS {
+            let len = match rng.below(3) {
+                // Random bytes
+                0 => {
+                    let len = rng.below(buf.len());
+                    buf[..len].iter_mut().for_each(|b| *b = rng.next() as u8);
+                    len
+                }
+                // Truncated or extended
+                1 => {
+                    let len = rng.below(valid.len() * 2 + 1).min(buf.len());
+                    let copied = len.min(valid.len());
+                    buf[..copied].copy_from_slice(&valid[..copied]);
+                    buf[copied..len]
+                        .iter_mut()
+                        .for_each(|b| *b = rng.next() as u8);
+                    len
+                }
+                // Bit flips
+                _ => {
+                    buf[..valid.len()].copy_from_slice(valid);
+                    for _ in 0..=rng.below(4) {
+                        if !valid.is_empty() {
+                            buf[rng.below(valid.len())] ^= 1 << rng.below(8);
+                        }
+                    }
+                    valid.len()
+                }
+            };
+            decode(&buf[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        assert_eq!(Reader::new(&[0xac, 0x02]).varint(), Ok(300));
+        assert_eq!(Reader::new(&[0x80]).varint(), Err(Error::UnexpectedEof));
+        assert_eq!(
+            Reader::new(&[0xff; 20]).varint(),
+            Err(Error::VarintOverflow)
+        );
+        assert_eq!(
+            Reader::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]).varint_u64(),
+            Err(Error::VarintOverflow)
+        );
+    }
+
+    #[test]
+    fn test_len_bounded_by_input() {
+        // A huge length must not be trusted
+        let mut reader = Reader::new(&[0xff, 0xff, 0xff, 0xff, 0x0f, 0, 0]);
+        assert_eq!(reader.len_prefix(32), Err(Error::UnexpectedEof));
+
+        let mut reader = Reader::new(&[2, 0, 0]);
+        assert_eq!(reader.len_prefix(1), Ok(2));
+    }
+}
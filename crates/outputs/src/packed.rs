@@ -0,0 +1,301 @@
+pub use crate::concat::VerifyResult;
+use crate::{Error, Hash, Reader, Result};
+
+const SUCCESS_VALUE: u8 = 2;
+const SUCCESS_RECEIPT_ID: u8 = 3;
+
+/// The result hash of the statuses that have none.
+const NO_RESULT: Hash = [0; 32];
+
+/// Packed verify results, the ids followed by a bitmap of whether each
+/// passed, then each status kind, varint gas burnt and result hash if the
+/// status has one. The statuses are checked when decoding, but only read
+/// out when iterated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyResults<'a> {
+    ids: &'a [u8],
+    passed: &'a [u8],
+    statuses: &'a [u8],
+}
+
+/// A status kind, its gas burnt and result hash.
+fn read_status<'a>(reader: &mut Reader<'a>) -> Result<(u8, u64, &'a Hash)> {
+    let kind = reader.bytes(1)?[0];
+    if kind > SUCCESS_RECEIPT_ID {
+        return Err(Error::InvalidStatus(kind));
+    }
+    let gas_burnt = reader.varint_u64()?;
+    let result_hash = match kind {
+        SUCCESS_VALUE | SUCCESS_RECEIPT_ID => reader.hash()?,
+        _ => &NO_RESULT,
+    };
+    Ok((kind, gas_burnt, result_hash))
+}
+
+impl<'a> VerifyResults<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let len = reader.len_prefix(32)?;
+        let ids = reader.bytes(len * 32)?;
+        let passed = reader.bytes(len.div_ceil(8))?;
+
+        let statuses = reader.remaining();
+        for _ in 0..len {
+            read_status(&mut reader)?;
+        }
+        reader.finish()?;
+        Ok(Self {
+            ids,
+            passed,
+            statuses,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len() / 32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = VerifyResult<'a>> + 'a {
+        let passed = self.passed;
+        let mut statuses = Reader::new(self.statuses);
+        self.ids
+            .chunks_exact(32)
+            .enumerate()
+            .map_while(move |(i, id)| {
+                let (status, gas_burnt, result_hash) = read_status(&mut statuses).ok()?;
+                Some(VerifyResult {
+                    id: id.try_into().expect("chunk is 32 bytes"),
+                    passed: passed[i / 8] & (1 << (7 - i % 8)) != 0,
+                    status,
+                    gas_burnt,
+                    result_hash,
+                })
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validator<'a> {
+    pub account_id: &'a str,
+    pub public_key: &'a Hash,
+    pub stake: u128,
+}
+
+impl<'a> Validator<'a> {
+    /// The smallest encoding, a single byte account id and stake.
+    const MIN_LEN: usize = 1 + 32 + 1;
+
+    fn read(reader: &mut Reader<'a>) -> Result<Self> {
+        let len = reader.len_prefix(1)?;
+        let account_id =
+            core::str::from_utf8(reader.bytes(len)?).map_err(|_| Error::InvalidUtf8)?;
+        Ok(Self {
+            account_id,
+            public_key: reader.hash()?,
+            stake: reader.varint()?,
+        })
+    }
+}
+
+/// Packed sync outputs. The validators are checked when decoding, but only
+/// read out when iterated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncOutput<'a> {
+    pub new_head_hash: &'a Hash,
+    pub height: u64,
+    pub next_bps_epoch: &'a Hash,
+    len: usize,
+    next_bps: &'a [u8],
+}
+
+impl<'a> SyncOutput<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let new_head_hash = reader.hash()?;
+        let height = reader.varint_u64()?;
+        let next_bps_epoch = reader.hash()?;
+        let len = reader.len_prefix(Validator::MIN_LEN)?;
+
+        let next_bps = reader.remaining();
+        for _ in 0..len {
+            Validator::read(&mut reader)?;
+        }
+        let next_bps = &next_bps[..next_bps.len() - reader.remaining().len()];
+        reader.finish()?;
+
+        Ok(Self {
+            new_head_hash,
+            height,
+            next_bps_epoch,
+            len,
+            next_bps,
+        })
+    }
+
+    pub fn next_bps_len(&self) -> usize {
+        self.len
+    }
+
+    pub fn next_bps(&self) -> impl Iterator<Item = Validator<'a>> + 'a {
+        let mut reader = Reader::new(self.next_bps);
+        (0..self.len).map_while(move |_| Validator::read(&mut reader).ok())
+    }
+}
+
+/// Packed sync outputs carrying only the hash of the next BPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncCommitment<'a> {
+    pub new_head_hash: &'a Hash,
+    pub height: u64,
+    pub next_bps_epoch: &'a Hash,
+    pub next_bps_hash: &'a Hash,
+}
+
+impl<'a> SyncCommitment<'a> {
+    pub fn decode(bytes: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let output = Self {
+            new_head_hash: reader.hash()?,
+            height: reader.varint_u64()?,
+            next_bps_epoch: reader.hash()?,
+            next_bps_hash: reader.hash()?,
+        };
+        reader.finish()?;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::fuzz;
+
+    const STATUSES: usize = 1 + 9 * 32 + 2;
+
+    // Nine ids, the first and last two passed, only the eighth returned a value
+    fn verify_fixture() -> [u8; STATUSES + 8 * 2 + 3 + 32] {
+        let mut bytes = [0u8; STATUSES + 8 * 2 + 3 + 32];
+        bytes[0] = 9;
+        for i in 0..9 {
+            bytes[1 + i * 32..1 + (i + 1) * 32].fill(i as u8);
+        }
+        bytes[1 + 9 * 32] = 0b1000_0001;
+        bytes[2 + 9 * 32] = 0b1000_0000;
+        // A value returned with 300 gas
+        let eighth = STATUSES + 7 * 2;
+        bytes[eighth..eighth + 3].copy_from_slice(&[SUCCESS_VALUE, 0xac, 0x02]);
+        bytes[eighth + 3..eighth + 35].fill(9);
+        bytes
+    }
+
+    // head hash, height 300, epoch, two validators
+    const SYNC_FIXTURE: &[u8] = &[
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 0xac, 0x02, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+        2, 2, 2, 2, 2, 2, 2, 2, 2, 6, b'a', b'.', b'n', b'e', b'a', b'r', 3, 3, 3, 3, 3, 3, 3, 3,
+        3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 42, 1, b'b', 4, 4,
+        4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+        0x80, 0x01,
+    ];
+
+    #[test]
+    fn test_verify_results() {
+        let bytes = verify_fixture();
+        let output = VerifyResults::decode(&bytes).unwrap();
+        assert_eq!(output.len(), 9);
+
+        let mut results = output.results();
+        assert_eq!(
+            results.next().map(|r| (r.id, r.passed, r.status)),
+            Some((&[0; 32], true, 0))
+        );
+        assert!(results.by_ref().take(6).all(|r| !r.passed));
+        assert_eq!(
+            results.next(),
+            Some(VerifyResult {
+                id: &[7; 32],
+                passed: true,
+                status: SUCCESS_VALUE,
+                gas_burnt: 300,
+                result_hash: &[9; 32],
+            })
+        );
+        assert_eq!(
+            results.next().map(|r| (r.id, r.passed, r.result_hash)),
+            Some((&[8; 32], true, &NO_RESULT))
+        );
+        assert_eq!(results.next(), None);
+
+        assert_eq!(
+            VerifyResults::decode(&bytes[..bytes.len() - 1]),
+            Err(Error::UnexpectedEof)
+        );
+        let mut invalid = bytes;
+        invalid[STATUSES] = 4;
+        assert_eq!(
+            VerifyResults::decode(&invalid),
+            Err(Error::InvalidStatus(4))
+        );
+    }
+
+    #[test]
+    fn test_sync() {
+        let output = SyncOutput::decode(SYNC_FIXTURE).unwrap();
+        assert_eq!(output.height, 300);
+        assert_eq!(output.next_bps_epoch, &[2; 32]);
+
+        let mut bps = output.next_bps();
+        assert_eq!(
+            bps.next(),
+            Some(Validator {
+                account_id: "a.near",
+                public_key: &[3; 32],
+                stake: 42
+            })
+        );
+        assert_eq!(
+            bps.next(),
+            Some(Validator {
+                account_id: "b",
+                public_key: &[4; 32],
+                stake: 128
+            })
+        );
+        assert_eq!(bps.next(), None);
+    }
+
+    #[test]
+    fn test_sync_commitment() {
+        // The fixture's head, height and epoch, then the commitment
+        let mut bytes = [5u8; 66 + 32];
+        bytes[..66].copy_from_slice(&SYNC_FIXTURE[..66]);
+        let output = SyncCommitment::decode(&bytes).unwrap();
+        assert_eq!(output.new_head_hash, &[1; 32]);
+        assert_eq!(output.height, 300);
+        assert_eq!(output.next_bps_epoch, &[2; 32]);
+        assert_eq!(output.next_bps_hash, &[5; 32]);
+
+        assert_eq!(
+            SyncCommitment::decode(&bytes[..bytes.len() - 1]),
+            Err(Error::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_fuzz() {
+        fuzz(&verify_fixture(), |bytes| {
+            if let Ok(output) = VerifyResults::decode(bytes) {
+                assert_eq!(output.results().count(), output.len());
+            }
+        });
+        fuzz(SYNC_FIXTURE, |bytes| {
+            if let Ok(output) = SyncOutput::decode(bytes) {
+                assert_eq!(output.next_bps().count(), output.next_bps_len());
+            }
+        });
+    }
+}
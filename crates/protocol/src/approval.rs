@@ -0,0 +1,278 @@
+//! The messages block producers sign when approving a block.
+//!
+//! An approval for `target_height` is `borsh(inner) ++ le(target_height)`.
+//! The inner is an endorsement of the parent's hash when the target directly
+//! follows the parent, otherwise a skip carrying the parent's height. So an
+//! endorsement is 41 bytes, `0u8 ++ hash ++ height` and a skip is 17 bytes,
+//! `1u8 ++ parent height ++ height`.
+//!
+//! The approvals a light client block carries are for the block after next,
+//! which doomslug finality guarantees directly follows next. They are always
+//! endorsements at `next.height + 2`, skips only appear between heights that
+//! are never final.
+use near_primitives::{block_header::ApprovalInner, types::BlockHeight};
+
+use crate::{packing::Packer, prelude::*};
+
+pub const ENDORSEMENT_LEN: usize = 41;
+pub const SKIP_LEN: usize = 17;
+
+/// The signed message approving `target_height`.
+pub fn message(inner: &ApprovalInner, target_height: BlockHeight) -> Vec<u8> {
+    let mut bytes = borsh::to_vec(inner).expect("approvals always serialize");
+    bytes.extend_from_slice(&target_height.to_le_bytes());
+    bytes
+}
+
+/// Approve `target_height` by endorsing the block hashed `parent_hash`.
+pub fn endorsement(parent_hash: CryptoHash, target_height: BlockHeight) -> Vec<u8> {
+    message(&ApprovalInner::Endorsement(parent_hash), target_height)
+}
+
+/// Approve `target_height` after skipping the heights since `parent_height`.
+pub fn skip(parent_height: BlockHeight, target_height: BlockHeight) -> Vec<u8> {
+    message(&ApprovalInner::Skip(parent_height), target_height)
+}
+
+/// Which seats approved a block with a valid signature, a bit per seat in
+/// seat order, the first in the most significant bit as `Packer::bools` packs
+/// them.
+///
+/// The json has the bitmap hex encoded, e.g `{"seats":10,"bitmap":"ff40"}`.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize,
+)]
+#[serde(try_from = "BitmapRepr", into = "BitmapRepr")]
+pub struct ApprovalBitmap {
+    seats: u16,
+    bitmap: Vec<u8>,
+}
+
+impl ApprovalBitmap {
+    pub fn new(approved: &[bool]) -> Self {
+        Self {
+            seats: approved.len() as u16,
+            bitmap: Packer::default().bools(approved).finish(),
+        }
+    }
+
+    pub fn seats(&self) -> usize {
+        self.seats as usize
+    }
+
+    pub fn is_approved(&self, seat: usize) -> bool {
+        seat < self.seats()
+            && self
+                .bitmap
+                .get(seat / 8)
+                .map_or(false, |byte| byte & (0x80 >> (seat % 8)) != 0)
+    }
+
+    /// How many seats approved.
+    pub fn approved(&self) -> usize {
+        self.iter().filter(|approved| *approved).count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.seats()).map(|seat| self.is_approved(seat))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bitmap
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BitmapRepr {
+    seats: u16,
+    bitmap: String,
+}
+
+impl From<ApprovalBitmap> for BitmapRepr {
+    fn from(bitmap: ApprovalBitmap) -> Self {
+        Self {
+            seats: bitmap.seats,
+            bitmap: bitmap.bitmap.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+impl TryFrom<BitmapRepr> for ApprovalBitmap {
+    type Error = String;
+
+    fn try_from(repr: BitmapRepr) -> Result<Self, Self::Error> {
+        let hex = repr.bitmap.as_bytes();
+        if hex.len() != (repr.seats as usize).div_ceil(8) * 2 {
+            return Err(format!(
+                "a bitmap of {} seats is {} bytes",
+                repr.seats,
+                (repr.seats as usize).div_ceil(8)
+            ));
+        }
+        let bitmap = hex
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| format!("invalid hex {:?}", repr.bitmap))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            seats: repr.seats,
+            bitmap,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::fixture;
+
+    use super::*;
+    use crate::{combine_hash, Protocol};
+
+    /// A published approval message, see `fixtures/approval_vectors.json`.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct Vector {
+        description: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        endorsement: Option<CryptoHash>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        skip: Option<BlockHeight>,
+        target_height: BlockHeight,
+        /// Hex encoded
+        message: String,
+    }
+
+    impl Vector {
+        fn new(description: &str, inner: ApprovalInner, target_height: BlockHeight) -> Self {
+            let (endorsement, skip) = match inner {
+                ApprovalInner::Endorsement(hash) => (Some(hash), None),
+                ApprovalInner::Skip(height) => (None, Some(height)),
+            };
+            Self {
+                description: description.to_string(),
+                endorsement,
+                skip,
+                target_height,
+                message: hex::encode(message(&inner, target_height)),
+            }
+        }
+
+        fn inner(&self) -> ApprovalInner {
+            match (self.endorsement, self.skip) {
+                (Some(hash), None) => ApprovalInner::Endorsement(hash),
+                (None, Some(height)) => ApprovalInner::Skip(height),
+                _ => panic!("{}: exactly one of endorsement or skip", self.description),
+            }
+        }
+    }
+
+    fn vectors() -> Vec<Vector> {
+        vec![
+            Vector::new(
+                "endorse the genesis successor",
+                ApprovalInner::Endorsement(CryptoHash::hash_bytes(b"block 1")),
+                2,
+            ),
+            Vector::new(
+                "endorse at a mainnet height",
+                ApprovalInner::Endorsement(CryptoHash::hash_bytes(b"block 117000001")),
+                117_000_002,
+            ),
+            Vector::new(
+                "endorse the default hash",
+                ApprovalInner::Endorsement(CryptoHash::default()),
+                u64::MAX,
+            ),
+            Vector::new("skip a single height", ApprovalInner::Skip(100), 102),
+            Vector::new(
+                "skip several heights",
+                ApprovalInner::Skip(117_000_001),
+                117_000_005,
+            ),
+            Vector::new("skip from genesis", ApprovalInner::Skip(0), u64::MAX),
+        ]
+    }
+
+    /// Regenerate the published vectors, only needed when adding cases.
+    #[test]
+    #[ignore]
+    fn generate_approval_vectors() {
+        let path = format!(
+            "{}/fixtures/approval_vectors.json",
+            test_utils::workspace_dir().display()
+        );
+        let json = serde_json::to_string_pretty(&vectors()).unwrap();
+        std::fs::write(path, json + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_approval_vectors() {
+        let published: Vec<Vector> = fixture("approval_vectors.json");
+        assert_eq!(published, vectors());
+
+        for vector in published {
+            let msg = message(&vector.inner(), vector.target_height);
+            assert_eq!(hex::encode(&msg), vector.message, "{}", vector.description);
+
+            let expected_len = match vector.inner() {
+                ApprovalInner::Endorsement(_) => ENDORSEMENT_LEN,
+                ApprovalInner::Skip(_) => SKIP_LEN,
+            };
+            assert_eq!(msg.len(), expected_len, "{}", vector.description);
+        }
+    }
+
+    #[test]
+    fn test_domain_encoding() {
+        let hash = CryptoHash::hash_bytes(b"parent");
+        let msg = endorsement(hash, 7);
+        assert_eq!(msg[0], 0);
+        assert_eq!(&msg[1..33], hash.as_bytes());
+        assert_eq!(&msg[33..], &7u64.to_le_bytes());
+
+        let msg = skip(5, 7);
+        assert_eq!(msg[0], 1);
+        assert_eq!(&msg[1..9], &5u64.to_le_bytes());
+        assert_eq!(&msg[9..], &7u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_approval_bitmap() {
+        let approved = [
+            true, false, true, true, false, false, false, false, true, true,
+        ];
+        let bitmap = ApprovalBitmap::new(&approved);
+        assert_eq!(bitmap.seats(), 10);
+        assert_eq!(bitmap.as_bytes(), &[0b1011_0000, 0b1100_0000]);
+        assert_eq!(bitmap.iter().collect_vec(), approved);
+        assert_eq!(bitmap.approved(), 5);
+        assert!(!bitmap.is_approved(10));
+
+        let json = serde_json::to_string(&bitmap).unwrap();
+        assert_eq!(json, r#"{"seats":10,"bitmap":"b0c0"}"#);
+        assert_eq!(
+            serde_json::from_str::<ApprovalBitmap>(&json).unwrap(),
+            bitmap
+        );
+        assert!(serde_json::from_str::<ApprovalBitmap>(r#"{"seats":10,"bitmap":"b0"}"#).is_err());
+        assert!(serde_json::from_str::<ApprovalBitmap>(r#"{"seats":1,"bitmap":"zz"}"#).is_err());
+    }
+
+    #[test]
+    fn test_light_client_blocks_endorse_two_ahead() {
+        let next = test_utils::test_next().body;
+        let header = Header {
+            prev_block_hash: next.prev_block_hash,
+            inner_rest_hash: next.inner_rest_hash,
+            inner_lite: next.inner_lite.clone(),
+        };
+        let parent = combine_hash(&next.next_block_inner_hash, &header.hash());
+        assert_eq!(
+            Protocol::reconstruct_approval_message(&next).unwrap(),
+            endorsement(parent, next.inner_lite.height + 2)
+        );
+    }
+}
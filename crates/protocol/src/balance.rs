@@ -0,0 +1,181 @@
+//! Converting balances between yoctoNEAR, NEAR and EVM words.
+//!
+//! Balances are yoctoNEAR u128s, which json numbers can't hold exactly and
+//! which are easily misread by 24 orders of magnitude. APIs write them as
+//! decimal yoctoNEAR strings, see `yocto`, and logs write them in NEAR, see
+//! `format_near`.
+use near_primitives::types::Balance;
+
+pub const NEAR_DECIMALS: usize = 24;
+pub const YOCTO_PER_NEAR: Balance = 10u128.pow(NEAR_DECIMALS as u32);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum BalanceError {
+    #[error("invalid NEAR amount {0:?}")]
+    InvalidNear(String),
+    #[error("balance overflows u128")]
+    Overflow,
+}
+
+/// Format yoctoNEAR as NEAR without losing precision, e.g `1.5`.
+pub fn format_near(yocto: Balance) -> String {
+    let whole = yocto / YOCTO_PER_NEAR;
+    let fraction = yocto % YOCTO_PER_NEAR;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = NEAR_DECIMALS);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+/// Parse NEAR into yoctoNEAR, e.g `1.5`. Amounts finer than a yoctoNEAR are
+/// rejected rather than rounded.
+pub fn parse_near(near: &str) -> Result<Balance, BalanceError> {
+    let invalid = || BalanceError::InvalidNear(near.to_string());
+    let (whole, fraction) = near.split_once('.').unwrap_or((near, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty())
+        || !digits(whole)
+        || !digits(fraction)
+        || fraction.len() > NEAR_DECIMALS
+    {
+        return Err(invalid());
+    }
+
+    let whole: Balance = match whole {
+        "" => 0,
+        whole => whole.parse().map_err(|_| BalanceError::Overflow)?,
+    };
+    let fraction: Balance = match fraction {
+        "" => 0,
+        fraction => {
+            let scale = 10u128.pow((NEAR_DECIMALS - fraction.len()) as u32);
+            fraction.parse::<Balance>().map_err(|_| invalid())? * scale
+        }
+    };
+    whole
+        .checked_mul(YOCTO_PER_NEAR)
+        .and_then(|w| w.checked_add(fraction))
+        .ok_or(BalanceError::Overflow)
+}
+
+/// The balance as an EVM word, a big endian uint256.
+pub fn to_evm_word(yocto: Balance) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&yocto.to_be_bytes());
+    word
+}
+
+pub fn from_evm_word(word: &[u8; 32]) -> Result<Balance, BalanceError> {
+    if word[..16].iter().any(|b| *b != 0) {
+        return Err(BalanceError::Overflow);
+    }
+    Ok(Balance::from_be_bytes(
+        word[16..].try_into().expect("16 bytes"),
+    ))
+}
+
+/// Decimal yoctoNEAR strings, for `#[serde(with = "balance::yocto")]`.
+/// Integers are accepted too, but never written.
+pub mod yocto {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use super::Balance;
+
+    pub fn serialize<S: Serializer>(yocto: &Balance, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&yocto.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Balance, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Int(u64),
+            Str(String),
+        }
+        match Repr::deserialize(d)? {
+            Repr::Int(i) => Ok(i as Balance),
+            Repr::Str(s) => s.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn test_near_roundtrip() {
+        for (yocto, near) in [
+            (0, "0"),
+            (YOCTO_PER_NEAR, "1"),
+            (YOCTO_PER_NEAR * 3 / 2, "1.5"),
+            (1, "0.000000000000000000000001"),
+            (Balance::MAX, "340282366920938.463463374607431768211455"),
+        ] {
+            assert_eq!(format_near(yocto), near);
+            assert_eq!(parse_near(near), Ok(yocto));
+        }
+    }
+
+    #[test]
+    fn test_parse_near() {
+        assert_eq!(parse_near(".5"), Ok(YOCTO_PER_NEAR / 2));
+        assert_eq!(parse_near("2."), Ok(2 * YOCTO_PER_NEAR));
+        assert_eq!(parse_near("0.100"), Ok(YOCTO_PER_NEAR / 10));
+
+        for invalid in [
+            "",
+            ".",
+            "-1",
+            "1e24",
+            "1.2.3",
+            " 1",
+            "0.0000000000000000000000001",
+        ] {
+            assert!(
+                matches!(parse_near(invalid), Err(BalanceError::InvalidNear(_))),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            parse_near("340282366920938.463463374607431768211456"),
+            Err(BalanceError::Overflow)
+        );
+        assert_eq!(parse_near("1000000000000000"), Err(BalanceError::Overflow));
+    }
+
+    #[test]
+    fn test_evm_word() {
+        let word = to_evm_word(YOCTO_PER_NEAR);
+        assert_eq!(&word[..16], &[0; 16]);
+        assert_eq!(from_evm_word(&word), Ok(YOCTO_PER_NEAR));
+        assert_eq!(from_evm_word(&to_evm_word(Balance::MAX)), Ok(Balance::MAX));
+
+        let mut word = [0u8; 32];
+        word[15] = 1;
+        assert_eq!(from_evm_word(&word), Err(BalanceError::Overflow));
+    }
+
+    #[test]
+    fn test_yocto_serde() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Stake {
+            #[serde(with = "yocto")]
+            stake: Balance,
+        }
+
+        let stake = Stake {
+            stake: Balance::MAX,
+        };
+        let json = serde_json::to_string(&stake).unwrap();
+        assert_eq!(json, format!(r#"{{"stake":"{}"}}"#, Balance::MAX));
+        assert_eq!(serde_json::from_str::<Stake>(&json).unwrap(), stake);
+        assert_eq!(
+            serde_json::from_str::<Stake>(r#"{"stake":42}"#).unwrap(),
+            Stake { stake: 42 }
+        );
+    }
+}
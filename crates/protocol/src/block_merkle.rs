@@ -0,0 +1,315 @@
+//! NEAR's block merkle tree.
+//!
+//! Every block hash is appended in order, leaf `i` being the block with
+//! ordinal `i + 1`, and each header's `block_merkle_root` is the root over
+//! every block before it. The shape matches nearcore's `PartialMerkleTree`:
+//! the leaves split into perfect subtrees, largest first, and the roots of
+//! those are folded from the right.
+//!
+//! Nodes of perfect subtrees never change once complete, so they can be
+//! persisted as they are appended and used to prove any leaf against the root
+//! of any later size. A tree doesn't need to start at genesis, it can be
+//! seeded with the roots of the perfect subtrees before some leaf, which are
+//! the left siblings in any path to it.
+use std::collections::HashMap;
+
+use nearx_error::bail;
+
+use crate::{
+    merkle_util::{combine_hash, verify_hash, Direction, MerklePath, MerklePathItem},
+    prelude::*,
+};
+
+/// The level and index of a node, leaves are level 0.
+pub type NodeKey = (u8, u64);
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct TreeNode {
+    pub level: u8,
+    pub index: u64,
+    pub hash: CryptoHash,
+}
+
+impl TreeNode {
+    pub fn key(&self) -> NodeKey {
+        (self.level, self.index)
+    }
+}
+
+/// Where the nodes are persisted.
+pub trait NodeStore {
+    fn node(&self, key: NodeKey) -> Result<Option<CryptoHash>>;
+}
+
+impl NodeStore for HashMap<NodeKey, CryptoHash> {
+    fn node(&self, key: NodeKey) -> Result<Option<CryptoHash>> {
+        Ok(self.get(&key).copied())
+    }
+}
+
+fn get<S: NodeStore + ?Sized>(store: &S, key: NodeKey) -> Result<CryptoHash> {
+    store
+        .node(key)?
+        .ok_or_else(|| err!(Protocol, "Missing block tree node {:?}", key))
+}
+
+/// The roots of the perfect subtrees covering the first `size` leaves,
+/// largest first.
+pub fn frontier(size: u64) -> Vec<NodeKey> {
+    let mut offset = 0;
+    (0..64u8)
+        .rev()
+        .filter(|level| size & (1 << level) != 0)
+        .map(|level| {
+            let key = (level, offset >> level);
+            offset += 1 << level;
+            key
+        })
+        .collect()
+}
+
+fn fold_roots<S: NodeStore + ?Sized>(store: &S, keys: &[NodeKey]) -> Result<CryptoHash> {
+    let mut keys = keys.iter().rev();
+    match keys.next() {
+        None => Ok(CryptoHash::default()),
+        Some(last) => keys.try_fold(get(store, *last)?, |acc, key| {
+            Ok(combine_hash(&get(store, *key)?, &acc))
+        }),
+    }
+}
+
+/// The root over the first `size` leaves.
+pub fn root<S: NodeStore + ?Sized>(store: &S, size: u64) -> Result<CryptoHash> {
+    fold_roots(store, &frontier(size))
+}
+
+/// Append a leaf at `index`, returning the nodes it completes, the leaf
+/// included, for the caller to persist.
+pub fn append<S: NodeStore + ?Sized>(
+    store: &S,
+    index: u64,
+    leaf: CryptoHash,
+) -> Result<Vec<TreeNode>> {
+    let (mut level, mut index, mut hash) = (0, index, leaf);
+    let mut nodes = vec![TreeNode { level, index, hash }];
+    while index % 2 == 1 {
+        hash = combine_hash(&get(store, (level, index - 1))?, &hash);
+        level += 1;
+        index /= 2;
+        nodes.push(TreeNode { level, index, hash });
+    }
+    Ok(nodes)
+}
+
+/// The path from a leaf to the root over the first `size` leaves.
+pub fn path<S: NodeStore + ?Sized>(store: &S, leaf: u64, size: u64) -> Result<MerklePath> {
+    let subtrees = frontier(size);
+    let i = subtrees
+        .iter()
+        .position(|(level, index)| leaf >> level == *index)
+        .ok_or_else(|| err!(Protocol, "Leaf {} is not in a tree of {}", leaf, size))?;
+
+    let mut path = (0..subtrees[i].0)
+        .map(|level| {
+            let index = leaf >> level;
+            let (sibling, direction) = if index % 2 == 1 {
+                (index - 1, Direction::Left)
+            } else {
+                (index + 1, Direction::Right)
+            };
+            Ok(MerklePathItem {
+                hash: get(store, (level, sibling))?,
+                direction,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if i + 1 < subtrees.len() {
+        path.push(MerklePathItem {
+            hash: fold_roots(store, &subtrees[i + 1..])?,
+            direction: Direction::Right,
+        });
+    }
+    for key in subtrees[..i].iter().rev() {
+        path.push(MerklePathItem {
+            hash: get(store, *key)?,
+            direction: Direction::Left,
+        });
+    }
+    Ok(path)
+}
+
+/// The roots of the perfect subtrees before a leaf, from any path to it.
+pub fn frontier_from_path(leaf: u64, path: &[MerklePathItem]) -> Result<Vec<TreeNode>> {
+    let lefts = path
+        .iter()
+        .filter(|item| matches!(item.direction, Direction::Left))
+        .map(|item| item.hash)
+        .collect_vec();
+    let keys = frontier(leaf);
+    if lefts.len() != keys.len() {
+        bail!(Protocol, "Path is not for leaf {}", leaf);
+    }
+    Ok(keys
+        .into_iter()
+        // Paths go from the leaf up, so the smallest come first
+        .zip(lefts.into_iter().rev())
+        .map(|((level, index), hash)| TreeNode { level, index, hash })
+        .collect())
+}
+
+/// A block merkle tree held in memory, for when there's nowhere to persist
+/// the nodes, or to check a range of blocks against their headers.
+#[derive(Debug, Clone, Default)]
+pub struct BlockMerkleTree {
+    nodes: HashMap<NodeKey, CryptoHash>,
+    start: u64,
+    size: u64,
+}
+
+impl BlockMerkleTree {
+    /// A tree from genesis.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A tree seeded at a block, given its leaf index and a path to it. Only
+    /// it and the blocks after it can be proven.
+    pub fn seeded(leaf: u64, block: CryptoHash, path: &[MerklePathItem]) -> Result<Self> {
+        let mut tree = Self {
+            nodes: frontier_from_path(leaf, path)?
+                .into_iter()
+                .map(|n| (n.key(), n.hash))
+                .collect(),
+            start: leaf,
+            size: leaf,
+        };
+        tree.append(block)?;
+        Ok(tree)
+    }
+
+    /// The first leaf that can be proven.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn append(&mut self, block: CryptoHash) -> Result<Vec<TreeNode>> {
+        let nodes = append(&self.nodes, self.size, block)?;
+        self.nodes.extend(nodes.iter().map(|n| (n.key(), n.hash)));
+        self.size += 1;
+        Ok(nodes)
+    }
+
+    pub fn root(&self) -> Result<CryptoHash> {
+        root(&self.nodes, self.size)
+    }
+
+    /// The root when the tree had `size` leaves, the `block_merkle_root` of
+    /// the block with ordinal `size + 1`.
+    pub fn root_at(&self, size: u64) -> Result<CryptoHash> {
+        if size < self.start || size > self.size {
+            bail!(
+                Protocol,
+                "Size {} is outside of {}..={}",
+                size,
+                self.start,
+                self.size
+            );
+        }
+        root(&self.nodes, size)
+    }
+
+    /// Prove a leaf against the root when the tree had `size` leaves.
+    pub fn prove(&self, leaf: u64, size: u64) -> Result<MerklePath> {
+        if leaf < self.start || size > self.size {
+            bail!(Protocol, "Leaf {} of {} is not in the tree", leaf, size);
+        }
+        path(&self.nodes, leaf, size)
+    }
+
+    pub fn verify(root: &CryptoHash, block: &CryptoHash, path: &MerklePath) -> bool {
+        verify_hash(*root, path.iter(), *block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::merkle::PartialMerkleTree;
+
+    use super::*;
+
+    fn block(i: u64) -> CryptoHash {
+        CryptoHash::hash_bytes(&i.to_le_bytes())
+    }
+
+    #[test]
+    fn test_matches_nearcore() {
+        let mut tree = BlockMerkleTree::new();
+        let mut expected = PartialMerkleTree::default();
+        for size in 0..70 {
+            let root = tree.root().unwrap();
+            assert_eq!(root, expected.root(), "size {}", size);
+            for i in 0..size {
+                let path = tree.prove(i, size).unwrap();
+                assert!(BlockMerkleTree::verify(&root, &block(i), &path));
+            }
+            tree.append(block(size)).unwrap();
+            expected.insert(block(size));
+        }
+    }
+
+    #[test]
+    fn test_proves_against_past_roots() {
+        let mut tree = BlockMerkleTree::new();
+        for i in 0..50 {
+            tree.append(block(i)).unwrap();
+        }
+        for size in 1..=50 {
+            let root = tree.root_at(size).unwrap();
+            for i in 0..size {
+                let path = tree.prove(i, size).unwrap();
+                assert!(BlockMerkleTree::verify(&root, &block(i), &path));
+                assert!(!BlockMerkleTree::verify(&root, &block(i + 1), &path));
+            }
+        }
+        assert!(tree.prove(50, 50).is_err());
+        assert!(tree.root_at(51).is_err());
+    }
+
+    #[test]
+    fn test_seeded_tree_proves_later_blocks() {
+        let mut full = BlockMerkleTree::new();
+        for i in 0..100 {
+            full.append(block(i)).unwrap();
+        }
+
+        let seed = 37;
+        let proof = full.prove(seed, 80).unwrap();
+        let mut seeded = BlockMerkleTree::seeded(seed, block(seed), &proof).unwrap();
+        for i in seed + 1..100 {
+            seeded.append(block(i)).unwrap();
+        }
+
+        for size in seed + 1..=100 {
+            assert_eq!(seeded.root_at(size).unwrap(), full.root_at(size).unwrap());
+            for i in seed..size {
+                assert_eq!(seeded.prove(i, size).unwrap(), full.prove(i, size).unwrap());
+            }
+        }
+        assert!(seeded.prove(seed - 1, 100).is_err());
+    }
+
+    #[test]
+    fn test_seeding_rejects_wrong_leaf() {
+        let mut full = BlockMerkleTree::new();
+        for i in 0..20 {
+            full.append(block(i)).unwrap();
+        }
+        // 7 has three subtrees before it, 8 has one
+        let proof = full.prove(7, 20).unwrap();
+        assert!(BlockMerkleTree::seeded(8, block(7), &proof).is_err());
+    }
+}
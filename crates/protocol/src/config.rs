@@ -1,7 +1,102 @@
-// https://github.com/near/nearcore/blob/master/nearcore/src/config.rs#L133C1-L134C1
-// TODO: expose this from NP, currently this is a risk that the light client
-// could be exploited if the max seats changes without knowing
-pub const NUM_BLOCK_PRODUCER_SEATS: usize = 50;
+/// Sizes that can differ between networks, kept in one place so the circuits
+/// are shaped by them rather than by constants scattered across the codebase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkParams {
+    /// The block producer seats whose approvals are counted.
+    // https://github.com/near/nearcore/blob/master/nearcore/src/config.rs#L133C1-L134C1
+    // TODO: expose this from NP, currently this is a risk that the light client
+    // could be exploited if the max seats changes without knowing
+    pub block_producer_seats: usize,
+    /// The chunk validators assigned to a chunk whose endorsements are
+    /// counted, nearcore's `num_chunk_validator_seats`.
+    pub chunk_validator_seats: usize,
+    /// The depth of an outcome proof, bounded by the outcomes in a chunk.
+    pub outcome_proof_depth: usize,
+    /// The depth of an outcome root proof, bounded by the shards.
+    pub outcome_root_proof_depth: usize,
+    /// The depth of a block proof, bounded by the blocks since genesis.
+    pub block_proof_depth: usize,
+}
+
+impl NetworkParams {
+    pub const MAINNET: Self = Self {
+        block_producer_seats: 50,
+        chunk_validator_seats: 300,
+        outcome_proof_depth: 16,
+        outcome_root_proof_depth: 8,
+        block_proof_depth: 64,
+    };
+
+    pub const TESTNET: Self = Self {
+        block_producer_seats: 50,
+        chunk_validator_seats: 300,
+        outcome_proof_depth: 16,
+        outcome_root_proof_depth: 8,
+        block_proof_depth: 64,
+    };
+
+    /// What the circuits are built with, every network has to fit in it.
+    pub const CIRCUIT: Self = Self::MAINNET.max(&Self::TESTNET);
+
+    const fn max(&self, other: &Self) -> Self {
+        const fn max(a: usize, b: usize) -> usize {
+            if a > b {
+                a
+            } else {
+                b
+            }
+        }
+        Self {
+            block_producer_seats: max(self.block_producer_seats, other.block_producer_seats),
+            chunk_validator_seats: max(self.chunk_validator_seats, other.chunk_validator_seats),
+            outcome_proof_depth: max(self.outcome_proof_depth, other.outcome_proof_depth),
+            outcome_root_proof_depth: max(
+                self.outcome_root_proof_depth,
+                other.outcome_root_proof_depth,
+            ),
+            block_proof_depth: max(self.block_proof_depth, other.block_proof_depth),
+        }
+    }
+
+    /// Whether everything these params allow fits in `capacity`.
+    pub fn fits(&self, capacity: &Self) -> bool {
+        self.block_producer_seats <= capacity.block_producer_seats
+            && self.chunk_validator_seats <= capacity.chunk_validator_seats
+            && self.outcome_proof_depth <= capacity.outcome_proof_depth
+            && self.outcome_root_proof_depth <= capacity.outcome_root_proof_depth
+            && self.block_proof_depth <= capacity.block_proof_depth
+    }
+}
+
+pub const NUM_BLOCK_PRODUCER_SEATS: usize = NetworkParams::CIRCUIT.block_producer_seats;
+pub const NUM_CHUNK_VALIDATOR_SEATS: usize = NetworkParams::CIRCUIT.chunk_validator_seats;
+
+/// The most total stake the threshold is checked for. The circuits compare
+/// `approved * 3` with `total * 2` in a u128, so anything more would wrap
+/// rather than fail, natively it is rejected too so the two agree.
+pub const MAX_TOTAL_STAKE: u128 = u128::MAX / 3;
+
+/// The most logs an outcome can have, nearcore's `max_number_logs`. An outcome
+/// hashes its id, the rest of the outcome and each of its logs.
+pub const MAX_OUTCOME_LOGS: usize = 100;
 
 // Used by nearcore to determine the end of the account in the state trie.
+// It is never valid in an account id, so the circuits pad accounts with it too,
+// though implicit accounts are already the max length and have no padding.
 pub const ACCOUNT_DATA_SEPARATOR: u8 = b',';
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_networks_fit_the_circuit() {
+        assert!(NetworkParams::MAINNET.fits(&NetworkParams::CIRCUIT));
+        assert!(NetworkParams::TESTNET.fits(&NetworkParams::CIRCUIT));
+        let bigger = NetworkParams {
+            block_producer_seats: NUM_BLOCK_PRODUCER_SEATS + 1,
+            ..NetworkParams::CIRCUIT
+        };
+        assert!(!bigger.fits(&NetworkParams::CIRCUIT));
+    }
+}
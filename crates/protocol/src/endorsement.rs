@@ -0,0 +1,93 @@
+//! The endorsements chunk validators sign under stateless validation.
+//!
+//! Block producers no longer hold the state to apply chunks themselves, a
+//! chunk is only included once validators with more than 2/3 of the stake
+//! assigned to it have validated its state witness and endorsed it. An
+//! endorsement signs `borsh(ChunkEndorsementInner)`, the chunk hash and then
+//! the differentiator as a borsh string, so it is `hash ++ le(16u32) ++
+//! "ChunkEndorsement"`, 52 bytes.
+use crate::{prelude::*, Signature, ValidatorStake};
+
+pub const SIGNATURE_DIFFERENTIATOR: &str = "ChunkEndorsement";
+pub const MESSAGE_LEN: usize = 32 + 4 + SIGNATURE_DIFFERENTIATOR.len();
+
+/// The signed message endorsing the chunk hashed `chunk_hash`.
+pub fn message(chunk_hash: &CryptoHash) -> Vec<u8> {
+    borsh::to_vec(&(chunk_hash, SIGNATURE_DIFFERENTIATOR)).expect("endorsements always serialize")
+}
+
+/// A chunk validator's endorsement, as nearcore's `ChunkEndorsementV1`.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct ChunkEndorsement {
+    pub chunk_hash: CryptoHash,
+    pub account_id: AccountId,
+    pub signature: Signature,
+}
+
+/// The signatures endorsing `chunk_hash` in the order of the validators
+/// assigned to it, `None` for those that didn't endorse it. Endorsements of
+/// other chunks, or from validators that aren't assigned, are left out.
+pub fn by_seat(
+    chunk_hash: &CryptoHash,
+    assignments: &[ValidatorStake],
+    endorsements: &[ChunkEndorsement],
+) -> Vec<Option<Box<Signature>>> {
+    assignments
+        .iter()
+        .map(|vs| {
+            endorsements
+                .iter()
+                .find(|e| &e.chunk_hash == chunk_hash && &e.account_id == vs.account_id())
+                .map(|e| Box::new(e.signature.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::{KeyType, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let chunk_hash = CryptoHash::hash_bytes(b"chunk");
+        let message = message(&chunk_hash);
+        assert_eq!(message.len(), MESSAGE_LEN);
+        assert_eq!(&message[..32], chunk_hash.as_bytes());
+        assert_eq!(&message[32..36], &16u32.to_le_bytes());
+        assert_eq!(&message[36..], b"ChunkEndorsement");
+    }
+
+    #[test]
+    fn test_by_seat() {
+        let chunk_hash = CryptoHash::hash_bytes(b"chunk");
+        let validator = |name: &str| {
+            let key = SecretKey::from_seed(KeyType::ED25519, name);
+            let vs = ValidatorStake::new_v1(name.parse().unwrap(), key.public_key(), 1);
+            (key, vs)
+        };
+        let (a, b, c) = (
+            validator("a.near"),
+            validator("b.near"),
+            validator("c.near"),
+        );
+        let endorse =
+            |(key, vs): &(SecretKey, ValidatorStake), chunk_hash: CryptoHash| ChunkEndorsement {
+                chunk_hash,
+                account_id: vs.account_id().clone(),
+                signature: key.sign(&message(&chunk_hash)),
+            };
+        let endorsements = [
+            endorse(&c, chunk_hash),
+            endorse(&a, CryptoHash::default()),
+            endorse(&validator("unassigned.near"), chunk_hash),
+        ];
+
+        let signatures = by_seat(&chunk_hash, &[a.1, b.1, c.1], &endorsements);
+        assert_eq!(
+            signatures.iter().map(Option::is_some).collect_vec(),
+            [false, false, true]
+        );
+    }
+}
@@ -1,5 +1,8 @@
+use nearx_error::Layer;
 use thiserror::Error;
 
+use crate::{prelude::AccountId, BlockHeight};
+
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum Error {
     #[error("Block already verified")]
@@ -14,4 +17,21 @@ pub enum Error {
     NextBpsInvalid,
     #[error("Validator not signed")]
     ValidatorNotSigned,
+    #[error("Stake overflows the stake that can be accounted for")]
+    StakeOverflow,
+    #[error("The proof is of block {block}, which the head at {head} doesn't cover")]
+    BlockAfterHead {
+        block: BlockHeight,
+        head: BlockHeight,
+    },
+    #[error("{0} signed with a key that is not a valid point")]
+    InvalidPublicKey(AccountId),
+    #[error("{assigned} validators are assigned to the chunk, only {seats} seats are counted")]
+    TooManyAssignments { assigned: usize, seats: usize },
+}
+
+impl From<Error> for nearx_error::Error {
+    fn from(e: Error) -> Self {
+        Self::new(Layer::Protocol, e)
+    }
 }
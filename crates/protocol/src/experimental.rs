@@ -2,26 +2,29 @@ use either::Either;
 use itertools::Itertools;
 use near_primitives::{
     block_header::BlockHeaderInnerLite,
-    merkle::{combine_hash, MerklePathItem},
+    merkle::{combine_hash, MerklePath, MerklePathItem},
     views::LightClientBlockLiteView,
 };
 use near_primitives_core::hash::CryptoHash;
 use serde::{Deserialize, Serialize};
 
-use crate::{prelude::*, Protocol};
+use crate::{
+    merkle_util::{verify_block_proof, verify_outcome_proof},
+    prelude::*,
+};
 
 /// Requires only needed parts of the LightClientBlockLiteView, and pre hashes
 /// the inner_lite.
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct LiteHeader {
-    inner_lite_hash: CryptoHash,
-    inner_rest_hash: CryptoHash,
-    prev_block_hash: CryptoHash,
-    outcome_root: CryptoHash,
+    pub inner_lite_hash: CryptoHash,
+    pub inner_rest_hash: CryptoHash,
+    pub prev_block_hash: CryptoHash,
+    pub outcome_root: CryptoHash,
 }
 
 impl LiteHeader {
-    fn hash(&self) -> CryptoHash {
+    pub fn hash(&self) -> CryptoHash {
         combine_hash(
             &combine_hash(&self.inner_lite_hash, &self.inner_rest_hash),
             &self.prev_block_hash,
@@ -219,7 +222,7 @@ impl BorshDeserialize for LookupMerklePathItem {
         } else {
             Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                anyhow::anyhow!("Invalid LookupMerklePathItem kind: {}", kind),
+                format!("Invalid LookupMerklePathItem kind: {}", kind),
             ))
         }
     }
@@ -290,6 +293,40 @@ impl Proof {
     }
 }
 
+/// A proof in the batch with the cache and common ancestry resolved, the
+/// shape the batch circuit verifies.
+#[derive(Debug, Clone)]
+pub struct ExpandedProof {
+    pub outcome_proof_block_hash: CryptoHash,
+    pub outcome_hash: CryptoHash,
+    pub outcome_proof: MerklePath,
+    pub outcome_root_proof: MerklePath,
+    pub block_proof: MerklePath,
+    pub header: LiteHeader,
+}
+
+impl Proof {
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    pub fn expand(&self) -> impl Iterator<Item = ExpandedProof> + '_ {
+        let collect = |path| self.cache.collect(path).cloned().collect_vec();
+        self.batch.iter().map(move |blinded| ExpandedProof {
+            outcome_proof_block_hash: blinded.outcome_proof_block_hash,
+            outcome_hash: blinded.outcome_hash,
+            outcome_proof: collect(&blinded.outcome_proof),
+            outcome_root_proof: collect(&blinded.outcome_root_proof),
+            block_proof: [collect(&blinded.block_proof), self.ancestry.clone()].concat(),
+            header: blinded.header.clone(),
+        })
+    }
+}
+
 pub fn verify_proof(proof: Proof) -> bool {
     // TODO: We should know about the known_sync_block_merkle_root
     //assert!(blinded_headers.contains_key(&body.created_from));
@@ -299,14 +336,14 @@ pub fn verify_proof(proof: Proof) -> bool {
         log::debug!("Verifying blinded proof: {:?}", blinded.header.outcome_root);
         let block_hash_matches = block_hash == blinded.outcome_proof_block_hash;
 
-        let outcome_verified = Protocol::verify_outcome(
+        let outcome_verified = verify_outcome_proof(
             &blinded.outcome_hash,
             proof.cache.collect(&blinded.outcome_proof),
             proof.cache.collect(&blinded.outcome_root_proof),
             &blinded.header.outcome_root,
         );
 
-        let block_verified = Protocol::verify_block(
+        let block_verified = verify_block_proof(
             &proof.head_block_root,
             proof
                 .cache
@@ -440,6 +477,28 @@ pub(crate) mod tests {
         assert!(verify_proof(proof));
     }
 
+    #[test]
+    fn test_expand() {
+        let root = CryptoHash::from_str(BLOCK_MERKLE_ROOT).unwrap();
+        let original = vec![proof_fixture(true), proof_fixture(false)];
+        let proof = Proof::new(root, original.clone());
+        assert_eq!(proof.len(), 2);
+
+        for (expanded, original) in proof.expand().zip(original) {
+            // The same paths the batch was made from
+            assert_eq!(expanded.outcome_proof, original.outcome_proof.proof);
+            assert_eq!(expanded.outcome_root_proof, original.outcome_root_proof);
+            assert_eq!(expanded.header.hash(), original.block_header_lite.hash());
+            // The block proof is split between the proof and the common
+            // ancestry, rejoined it proves the block again
+            assert!(verify_block_proof(
+                &root,
+                expanded.block_proof.iter(),
+                &expanded.outcome_proof_block_hash
+            ));
+        }
+    }
+
     // Util for rewriting the original bridge proofs
     fn _rewrite_bridge_proofs(rainbow_prover_fixture_path: &str) {
         let rewritten = [
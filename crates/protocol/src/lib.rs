@@ -1,4 +1,4 @@
-use config::NUM_BLOCK_PRODUCER_SEATS;
+use config::{MAX_TOTAL_STAKE, NUM_BLOCK_PRODUCER_SEATS, NUM_CHUNK_VALIDATOR_SEATS};
 use error::Error;
 pub use merkle_util::*;
 pub use near_crypto::{ED25519PublicKey, PublicKey, Signature};
@@ -12,19 +12,46 @@ pub use near_primitives::{
     },
 };
 
-use crate::prelude::*;
+use crate::{
+    approval::ApprovalBitmap,
+    endorsement::ChunkEndorsement,
+    prelude::*,
+    signature::KeyPolicy,
+    weights::{ByStake, StakeWeight},
+};
 
+pub mod approval;
+pub mod balance;
+pub mod block_merkle;
 pub mod config;
+pub mod endorsement;
 pub mod error;
 pub mod merkle_util;
+pub mod outcomes;
+pub mod packing;
 pub mod prelude;
+pub mod signature;
+pub mod solana;
+#[cfg(feature = "interop-starknet")]
+pub mod starknet;
+pub mod state;
+pub mod timestamp;
+pub mod weights;
 // Lightweight batch protocol with lookups for proofs
 pub mod experimental;
 
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Debug)]
 pub struct Synced {
     pub new_head: Header,
     pub next_bps: Option<(EpochId, Vec<ValidatorStake>)>,
+    /// Which seats of the epoch approved the new head.
+    pub approvals: ApprovalBitmap,
+    /// The total and approved stake of the epoch for the new head.
+    pub stake: StakeInfo,
+    /// The seats that signed with an invalid key, see `KeyPolicy`.
+    pub rejected_keys: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,10 +93,28 @@ impl Proof {
 pub struct Protocol;
 
 impl Protocol {
+    /// Sync from `head` to any later final block, the heights between them
+    /// may have been skipped or simply not synced. The approvals we check are
+    /// always endorsements, see `approval`. Approvals by seats with an invalid
+    /// key are handled by `policy`, which must be the one the circuits are
+    /// built with.
     pub fn sync(
         head: &Header,
         epoch_bps: &[ValidatorStake],
         next_block: LightClientBlockView,
+        policy: KeyPolicy,
+    ) -> Result<Synced> {
+        Self::sync_weighted(head, epoch_bps, next_block, &ByStake, policy)
+    }
+
+    /// Sync with the approvals weighted by `weight` rather than stake, see
+    /// `weights`.
+    pub fn sync_weighted(
+        head: &Header,
+        epoch_bps: &[ValidatorStake],
+        next_block: LightClientBlockView,
+        weight: &impl StakeWeight,
+        policy: KeyPolicy,
     ) -> Result<Synced> {
         Self::ensure_not_already_verified(head, &next_block.inner_lite.height)?;
         Self::ensure_epoch_is_current_or_next(head, &next_block.inner_lite.epoch_id)?;
@@ -87,13 +132,15 @@ impl Protocol {
 
         let approval_message = Self::reconstruct_approval_message(&next_block).unwrap();
 
-        let StakeInfo { total, approved } = Self::validate_signatures(
+        let (stake, approvals, rejected_keys) = Self::tally_approvals(
             &next_block.approvals_after_next,
             epoch_bps,
+            &weight.weigh(epoch_bps),
             &approval_message,
-        );
+            policy,
+        )?;
 
-        Self::ensure_stake_is_sufficient(&total, &approved)?;
+        Self::ensure_stake_is_sufficient(&stake.total, &stake.approved)?;
 
         log::trace!(
             "prev/current head: {}/{}",
@@ -103,6 +150,9 @@ impl Protocol {
 
         Ok(Synced {
             new_head,
+            approvals,
+            stake,
+            rejected_keys,
             next_bps: Self::ensure_next_bps_is_valid(
                 &next_block.inner_lite.next_bp_hash,
                 next_block.next_bps,
@@ -122,62 +172,50 @@ impl Protocol {
                 head_block_root,
                 proof,
             } => {
-                let block_hash = proof.block_header_lite.hash();
-                let block_hash_matches = block_hash == proof.outcome_proof.block_hash;
-
-                let outcome_hash = CryptoHash::hash_borsh(proof.outcome_proof.to_hashes());
-
-                let outcome_verified = Self::verify_outcome(
-                    &outcome_hash,
-                    proof.outcome_proof.proof.iter(),
-                    proof.outcome_root_proof.iter(),
-                    &proof.block_header_lite.inner_lite.outcome_root,
-                );
-
-                let block_verified =
-                    Self::verify_block(&head_block_root, proof.block_proof.iter(), &block_hash);
-
-                log::debug!(
-                    "shard outcome included: {:?}, block included: {:?}",
-                    outcome_verified,
-                    block_verified
-                );
-                Ok(block_hash_matches && outcome_verified && block_verified).map(|verified| {
-                    log::debug!("Verified proof {:?}", verified);
-                    verified
-                })
+                let checks = Self::inclusion_checks(&head_block_root, &proof);
+                log::debug!("Verified proof {:?}", checks);
+                Ok(checks.passed())
             }
         }
     }
 
-    pub(crate) fn verify_outcome<'a>(
-        outcome_hash: &CryptoHash,
-        outcome_proof: impl Iterator<Item = &'a MerklePathItem>,
-        outcome_root_proof: impl Iterator<Item = &'a MerklePathItem>,
-        expected_outcome_root: &CryptoHash,
-    ) -> bool {
-        let outcome_root = compute_root_from_path(outcome_proof, *outcome_hash);
-        #[cfg(test)]
-        println!("outcome_root: {:?}", hex::encode(outcome_root));
-
-        let leaf = CryptoHash::hash_borsh(outcome_root);
-        #[cfg(test)]
-        println!("leaf: {:?}", hex::encode(leaf));
-
-        let outcome_root = compute_root_from_path(outcome_root_proof, leaf);
-        #[cfg(test)]
-        println!("outcome_root: {:?}", hex::encode(outcome_root));
-        log::debug!("outcome_root: {:?}", outcome_root);
-
-        &outcome_root == expected_outcome_root
+    /// Each check a basic inclusion proof must pass, to tell which failed.
+    pub fn inclusion_checks(head_block_root: &CryptoHash, proof: &BasicProof) -> InclusionChecks {
+        let block_hash = proof.block_header_lite.hash();
+        InclusionChecks {
+            block_hash_matches: block_hash == proof.outcome_proof.block_hash,
+            outcome_included: verify_outcome_proof(
+                &outcome_hash(&proof.outcome_proof),
+                proof.outcome_proof.proof.iter(),
+                proof.outcome_root_proof.iter(),
+                &proof.block_header_lite.inner_lite.outcome_root,
+            ),
+            block_included: verify_block_proof(
+                head_block_root,
+                proof.block_proof.iter(),
+                &block_hash,
+            ),
+        }
     }
 
-    pub(crate) fn verify_block<'a>(
-        block_merkle_root: &CryptoHash,
-        block_proof: impl Iterator<Item = &'a MerklePathItem>,
-        block_hash: &CryptoHash,
-    ) -> bool {
-        verify_hash(*block_merkle_root, block_proof, *block_hash)
+    /// A head's block merkle root only covers the blocks before it. Receipts
+    /// execute in blocks after their transaction, so the RPC can prove an
+    /// outcome in the head's block or a later one, which no block proof can
+    /// reach from the head's root.
+    pub fn ensure_block_is_before_head(
+        head_height: BlockHeight,
+        proof: &BasicProof,
+    ) -> Result<(), Error> {
+        let block = proof.block_header_lite.inner_lite.height;
+        if block < head_height {
+            Ok(())
+        } else {
+            log::debug!("Proof of block {} is not before {}", block, head_height);
+            Err(Error::BlockAfterHead {
+                block,
+                head: head_height,
+            })
+        }
     }
 
     pub fn reconstruct_approval_message(block_view: &LightClientBlockView) -> Option<Vec<u8>> {
@@ -189,16 +227,11 @@ impl Protocol {
 
         let next_block_hash = combine_hash(&block_view.next_block_inner_hash, &new_head.hash());
 
-        let endorsement = ApprovalInner::Endorsement(next_block_hash);
-
-        let approval_message = {
-            let mut temp_vec = Vec::new();
-            BorshSerialize::serialize(&endorsement, &mut temp_vec).ok()?;
-            //temp_vec.extend_from_slice(&(endorsement.try_to_vec().ok()?[..]));
-            temp_vec.extend_from_slice(&((block_view.inner_lite.height + 2).to_le_bytes()[..]));
-            println!("temp_vec len: {:?}", temp_vec.len());
-            temp_vec
-        };
+        // The block after next directly follows next, so it's always endorsed
+        let approval_message = approval::endorsement(
+            next_block_hash,
+            block_view.inner_lite.height.checked_add(2)?,
+        );
 
         log::debug!("Next block hash: {}", next_block_hash);
         log::debug!("Approval message: {:?}", approval_message);
@@ -250,25 +283,130 @@ impl Protocol {
         signatures: &[Option<Box<Signature>>],
         epoch_bps: &[ValidatorStake],
         approval_message: &[u8],
-    ) -> StakeInfo {
-        signatures
-            .iter()
-            .zip(epoch_bps.iter())
-            .take(NUM_BLOCK_PRODUCER_SEATS)
-            .fold((0, 0), |(total_stake, approved_stake), (sig, vs)| {
-                let pk = vs.public_key();
-                let stake = vs.stake();
-                let total_stake = total_stake + stake;
+    ) -> Result<StakeInfo, Error> {
+        Self::validate_signatures_weighted(
+            signatures,
+            epoch_bps,
+            &ByStake.weigh(epoch_bps),
+            approval_message,
+        )
+    }
+
+    /// The approved and total weight, `weights` are in seat order. Seats with
+    /// an invalid key count as not approving, see `tally_approvals` to reject
+    /// them.
+    ///
+    /// Fails rather than wrapping if the total overflows, the approved weight
+    /// never exceeds it.
+    pub fn validate_signatures_weighted(
+        signatures: &[Option<Box<Signature>>],
+        epoch_bps: &[ValidatorStake],
+        weights: &[u128],
+        approval_message: &[u8],
+    ) -> Result<StakeInfo, Error> {
+        Self::tally_approvals(
+            signatures,
+            epoch_bps,
+            weights,
+            approval_message,
+            KeyPolicy::Inactive,
+        )
+        .map(|(stake, ..)| stake)
+    }
+
+    /// As `validate_signatures_weighted`, along with which seats approved and
+    /// how many signed with an invalid key, which `policy` decides the fate
+    /// of.
+    pub fn tally_approvals(
+        signatures: &[Option<Box<Signature>>],
+        epoch_bps: &[ValidatorStake],
+        weights: &[u128],
+        approval_message: &[u8],
+        policy: KeyPolicy,
+    ) -> Result<(StakeInfo, ApprovalBitmap, usize), Error> {
+        Self::tally_seats(
+            signatures,
+            epoch_bps,
+            weights,
+            approval_message,
+            policy,
+            NUM_BLOCK_PRODUCER_SEATS,
+        )
+    }
 
-                let approved_stake = match Self::validate_signature(approval_message, sig, pk) {
-                    Ok(_) => approved_stake + stake,
-                    Err(Error::SignatureInvalid) | Err(Error::ValidatorNotSigned) => approved_stake,
-                    Err(_) => approved_stake,
+    /// The assigned and endorsed stake of a chunk, with which validators
+    /// endorsed it. Fails unless more than 2/3 of the stake assigned to it
+    /// endorsed it, see `endorsement`. Endorsements by an invalid key are
+    /// handled by `policy`, as approvals are.
+    ///
+    /// Leaving out assignments past `NUM_CHUNK_VALIDATOR_SEATS` would leave
+    /// their stake out of the total, so more than that is an error.
+    pub fn verify_chunk_endorsements(
+        chunk_hash: &CryptoHash,
+        assignments: &[ValidatorStake],
+        endorsements: &[ChunkEndorsement],
+        policy: KeyPolicy,
+    ) -> Result<(StakeInfo, ApprovalBitmap), Error> {
+        if assignments.len() > NUM_CHUNK_VALIDATOR_SEATS {
+            return Err(Error::TooManyAssignments {
+                assigned: assignments.len(),
+                seats: NUM_CHUNK_VALIDATOR_SEATS,
+            });
+        }
+        let (stake, endorsed, _) = Self::tally_seats(
+            &endorsement::by_seat(chunk_hash, assignments, endorsements),
+            assignments,
+            &ByStake.weigh(assignments),
+            &endorsement::message(chunk_hash),
+            policy,
+            NUM_CHUNK_VALIDATOR_SEATS,
+        )?;
+        Self::ensure_stake_is_sufficient(&stake.total, &stake.approved)?;
+        Ok((stake, endorsed))
+    }
+
+    /// Tally the signatures of the first `seats` seats over `message`.
+    fn tally_seats(
+        signatures: &[Option<Box<Signature>>],
+        epoch_bps: &[ValidatorStake],
+        weights: &[u128],
+        approval_message: &[u8],
+        policy: KeyPolicy,
+        seats: usize,
+    ) -> Result<(StakeInfo, ApprovalBitmap, usize), Error> {
+        let mut approvals = vec![];
+        let mut rejected_keys = 0;
+        let stake = izip!(signatures, epoch_bps, weights).take(seats).try_fold(
+            (0u128, 0u128),
+            |(total_stake, approved_stake), (sig, vs, weight)| {
+                let pk = vs.public_key();
+                let stake = *weight;
+                let total_stake = total_stake.checked_add(stake).ok_or_else(|| {
+                    log::debug!("Stake overflowed at {}", vs.account_id());
+                    Error::StakeOverflow
+                })?;
+
+                let valid_key = sig.is_none() || crate::signature::is_valid_key(pk);
+                if !valid_key {
+                    log::debug!("{} signed with an invalid key {}", vs.account_id(), pk);
+                    rejected_keys += 1;
+                    if policy == KeyPolicy::Reject {
+                        return Err(Error::InvalidPublicKey(vs.account_id().clone()));
+                    }
+                }
+                let approves =
+                    valid_key && Self::validate_signature(approval_message, sig, pk).is_ok();
+                approvals.push(approves);
+                let approved_stake = if approves {
+                    approved_stake + stake
+                } else {
+                    approved_stake
                 };
 
-                (total_stake, approved_stake)
-            })
-            .into()
+                Ok((total_stake, approved_stake))
+            },
+        )?;
+        Ok((stake.into(), ApprovalBitmap::new(&approvals), rejected_keys))
     }
 
     pub fn validate_signature(
@@ -277,7 +415,7 @@ impl Protocol {
         pk: &PublicKey,
     ) -> Result<(), Error> {
         match sig {
-            Some(signature) if signature.verify(msg, pk) => {
+            Some(signature) if crate::signature::verify(signature, msg, pk) => {
                 log::trace!("{} {:?} pk {} approved", signature, msg, pk);
                 Ok(())
             }
@@ -296,7 +434,11 @@ impl Protocol {
         total_stake: &u128,
         approved_stake: &u128,
     ) -> Result<(), Error> {
-        let threshold = total_stake / 3 * 2;
+        if total_stake > &MAX_TOTAL_STAKE {
+            log::debug!("Total stake {} is out of range", total_stake);
+            return Err(Error::StakeOverflow);
+        }
+        let threshold = StakeInfo::from((*total_stake, *approved_stake)).threshold();
 
         if approved_stake <= &threshold {
             log::debug!("Not enough stake approved");
@@ -325,12 +467,59 @@ impl Protocol {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct InclusionChecks {
+    /// The proven block's header hashes to the block the outcome is in.
+    pub block_hash_matches: bool,
+    /// The outcome is in the block's outcome root.
+    pub outcome_included: bool,
+    /// The block is in the head's block merkle root.
+    pub block_included: bool,
+}
+
+impl InclusionChecks {
+    pub fn passed(&self) -> bool {
+        self.block_hash_matches && self.outcome_included && self.block_included
+    }
+
+    pub fn iter(&self) -> [(&'static str, bool); 3] {
+        [
+            ("block_hash_matches", self.block_hash_matches),
+            ("outcome_included", self.outcome_included),
+            ("block_included", self.block_included),
+        ]
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StakeInfo {
     pub total: u128,
     pub approved: u128,
 }
 
+impl StakeInfo {
+    /// The approved stake must be more than this, see
+    /// `Protocol::ensure_stake_is_sufficient`.
+    pub fn threshold(&self) -> u128 {
+        self.total / 3 * 2
+    }
+
+    /// How far the approved stake is past the threshold, in basis points of
+    /// the total stake. Zero if it isn't past it.
+    pub fn margin_bps(&self) -> u32 {
+        const BPS: u128 = 10_000;
+        let excess = self.approved.saturating_sub(self.threshold());
+        if self.total == 0 {
+            return 0;
+        }
+        let margin = excess
+            .checked_mul(BPS)
+            .map(|e| e / self.total)
+            .unwrap_or_else(|| excess / (self.total / BPS));
+        margin.min(BPS) as u32
+    }
+}
+
 impl From<(u128, u128)> for StakeInfo {
     fn from((total, approved): (u128, u128)) -> Self {
         Self { total, approved }
@@ -353,6 +542,7 @@ macro_rules! cvec {
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
+    use near_crypto::{KeyType, SecretKey};
     use near_jsonrpc_primitives::types::light_client::RpcLightClientExecutionProofResponse;
     use serde_json::{self};
     use test_utils::*;
@@ -366,7 +556,13 @@ mod tests {
         let mut next_epoch_id = EpochId(head.inner_lite.next_epoch_id);
 
         let mut sync_and_update = |next_block: LightClientBlockView| {
-            let sync_next = Protocol::sync(&head, &next_bps[..], next_block.clone()).unwrap();
+            let sync_next = Protocol::sync(
+                &head,
+                &next_bps[..],
+                next_block.clone(),
+                KeyPolicy::default(),
+            )
+            .unwrap();
             // Assert we matched the epoch id for the new BPS
             assert_eq!(
                 head.inner_lite.next_epoch_id,
@@ -402,6 +598,15 @@ mod tests {
         sync_and_update(next_block.body);
     }
 
+    #[test]
+    fn test_sync_across_skipped_heights() {
+        let (head, bps, next_block) = test_state();
+        assert!(next_block.inner_lite.height > head.inner_lite.height + 1);
+
+        let synced = Protocol::sync(&head, &bps, next_block.clone(), KeyPolicy::default()).unwrap();
+        assert_eq!(synced.new_head.inner_lite, next_block.inner_lite);
+    }
+
     #[test]
     fn test_validate_already_verified() {
         let (head, _, _) = test_state();
@@ -468,7 +673,8 @@ mod tests {
             &next_block.approvals_after_next,
             &next_bps.clone(),
             &approval_message.unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!((total, approved), (440511369730158962073902098744970, 0));
     }
@@ -483,7 +689,8 @@ mod tests {
             &next_block.approvals_after_next,
             &next_bps[..],
             &approval_message.unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(
             (total, approved),
@@ -503,6 +710,238 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stake_overflow() {
+        let (_, bps, next_block) = test_state();
+        let approval_message = Protocol::reconstruct_approval_message(&next_block).unwrap();
+
+        // More validators than seats, each with the most stake they could have
+        let bps = bps.iter().cycle().take(120).cloned().collect_vec();
+        let signatures = vec![None; bps.len()];
+        let weights = vec![u128::MAX; bps.len()];
+        assert_eq!(
+            Protocol::validate_signatures_weighted(&signatures, &bps, &weights, &approval_message),
+            Err(Error::StakeOverflow)
+        );
+
+        // Filling every seat without overflowing
+        let weights = vec![u128::MAX / NUM_BLOCK_PRODUCER_SEATS as u128; bps.len()];
+        let StakeInfo { total, approved } =
+            Protocol::validate_signatures_weighted(&signatures, &bps, &weights, &approval_message)
+                .unwrap();
+        assert_eq!(approved, 0);
+        assert_eq!(
+            Protocol::ensure_stake_is_sufficient(&total, &total),
+            Err(Error::StakeOverflow)
+        );
+
+        // The bound itself is checked as usual
+        assert!(Protocol::ensure_stake_is_sufficient(&MAX_TOTAL_STAKE, &MAX_TOTAL_STAKE).is_ok());
+        assert_eq!(
+            Protocol::ensure_stake_is_sufficient(&MAX_TOTAL_STAKE, &0),
+            Err(Error::NotEnoughApprovedStake)
+        );
+    }
+
+    #[test]
+    fn test_sync_approvals() {
+        let (head, bps, mut next_block) = test_state();
+        let signed = next_block
+            .approvals_after_next
+            .iter()
+            .take(bps.len().min(NUM_BLOCK_PRODUCER_SEATS))
+            .map(Option::is_some)
+            .collect_vec();
+
+        let synced = Protocol::sync(&head, &bps, next_block.clone(), KeyPolicy::default()).unwrap();
+        assert_eq!(synced.approvals.iter().collect_vec(), signed);
+
+        // A signature from another seat is there, but doesn't approve
+        let (i, j) = signed
+            .iter()
+            .positions(|signed| *signed)
+            .next_tuple()
+            .unwrap();
+        next_block.approvals_after_next[i] = next_block.approvals_after_next[j].clone();
+        let approval_message = Protocol::reconstruct_approval_message(&next_block).unwrap();
+        let (_, approvals, _) = Protocol::tally_approvals(
+            &next_block.approvals_after_next,
+            &bps,
+            &ByStake.weigh(&bps),
+            &approval_message,
+            KeyPolicy::default(),
+        )
+        .unwrap();
+        assert!(!approvals.is_approved(i));
+        assert_eq!(approvals.approved(), synced.approvals.approved() - 1);
+    }
+
+    #[test]
+    fn test_key_policy() {
+        let (head, mut bps, next_block) = test_state();
+        let synced = Protocol::sync(&head, &bps, next_block.clone(), KeyPolicy::Reject).unwrap();
+        assert_eq!(synced.rejected_keys, 0);
+
+        // A seat that signed, with its key swapped for p, which isn't canonical
+        let i = next_block
+            .approvals_after_next
+            .iter()
+            .position(Option::is_some)
+            .unwrap();
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        let account_id = bps[i].account_id().clone();
+        bps[i] = ValidatorStake::new_v1(
+            account_id.clone(),
+            PublicKey::ED25519(ED25519PublicKey(p)),
+            bps[i].stake(),
+        );
+
+        let approval_message = Protocol::reconstruct_approval_message(&next_block).unwrap();
+        let tally = |policy| {
+            Protocol::tally_approvals(
+                &next_block.approvals_after_next,
+                &bps,
+                &ByStake.weigh(&bps),
+                &approval_message,
+                policy,
+            )
+        };
+        let (stake, approvals, rejected_keys) = tally(KeyPolicy::Inactive).unwrap();
+        assert_eq!(rejected_keys, 1);
+        assert!(!approvals.is_approved(i));
+        assert_eq!(stake.approved, synced.stake.approved - bps[i].stake());
+        assert_eq!(
+            tally(KeyPolicy::Reject).unwrap_err(),
+            Error::InvalidPublicKey(account_id.clone())
+        );
+
+        // The same through a sync, under either policy
+        let sync = |policy| Protocol::sync(&head, &bps, next_block.clone(), policy);
+        let inactive = sync(KeyPolicy::Inactive).unwrap();
+        assert_eq!(inactive.rejected_keys, 1);
+        assert!(!inactive.approvals.is_approved(i));
+        assert_eq!(
+            sync(KeyPolicy::Reject).unwrap_err().downcast_ref::<Error>(),
+            Some(&Error::InvalidPublicKey(account_id))
+        );
+    }
+
+    #[test]
+    fn test_chunk_endorsements() {
+        let chunk_hash = CryptoHash::hash_bytes(b"chunk");
+        let validators = [("a.near", 40), ("b.near", 30), ("c.near", 30)].map(|(name, stake)| {
+            let key = SecretKey::from_seed(KeyType::ED25519, name);
+            let vs = ValidatorStake::new_v1(name.parse().unwrap(), key.public_key(), stake);
+            (key, vs)
+        });
+        let assignments = validators.iter().map(|(_, vs)| vs.clone()).collect_vec();
+        let endorse = |i: usize, endorsed: &CryptoHash| {
+            let (key, vs) = &validators[i];
+            ChunkEndorsement {
+                chunk_hash,
+                account_id: vs.account_id().clone(),
+                signature: key.sign(&endorsement::message(endorsed)),
+            }
+        };
+        let verify = |endorsements: &[ChunkEndorsement]| {
+            Protocol::verify_chunk_endorsements(
+                &chunk_hash,
+                &assignments,
+                endorsements,
+                KeyPolicy::default(),
+            )
+        };
+
+        let (stake, endorsed) =
+            verify(&[endorse(0, &chunk_hash), endorse(2, &chunk_hash)]).unwrap();
+        assert_eq!((stake.total, stake.approved), (100, 70));
+        assert_eq!(endorsed.iter().collect_vec(), [true, false, true]);
+
+        // 60 isn't more than 2/3 of 100
+        assert_eq!(
+            verify(&[endorse(1, &chunk_hash), endorse(2, &chunk_hash)]).unwrap_err(),
+            Error::NotEnoughApprovedStake
+        );
+        // A signature over another chunk doesn't endorse this one
+        assert_eq!(
+            verify(&[endorse(0, &chunk_hash), endorse(2, &CryptoHash::default())]).unwrap_err(),
+            Error::NotEnoughApprovedStake
+        );
+
+        // Seats past the circuit's aren't dropped from the total
+        let crowded = std::iter::repeat(assignments[0].clone())
+            .take(NUM_CHUNK_VALIDATOR_SEATS + 1)
+            .collect_vec();
+        assert_eq!(
+            Protocol::verify_chunk_endorsements(
+                &chunk_hash,
+                &crowded,
+                &[endorse(0, &chunk_hash)],
+                KeyPolicy::default(),
+            )
+            .unwrap_err(),
+            Error::TooManyAssignments {
+                assigned: NUM_CHUNK_VALIDATOR_SEATS + 1,
+                seats: NUM_CHUNK_VALIDATOR_SEATS,
+            }
+        );
+    }
+
+    #[test]
+    fn test_stake_margin() {
+        let margin = |total, approved| StakeInfo { total, approved }.margin_bps();
+        assert_eq!(margin(300, 200), 0);
+        assert_eq!(margin(300, 100), 0);
+        assert_eq!(margin(300, 201), 33);
+        assert_eq!(margin(300, 300), 3333);
+        assert_eq!(margin(0, 0), 0);
+        // Too big to scale without wrapping
+        assert_eq!(margin(MAX_TOTAL_STAKE, MAX_TOTAL_STAKE), 3333);
+
+        let (head, bps, next_block) = test_state();
+        let synced = Protocol::sync(&head, &bps, next_block, KeyPolicy::default()).unwrap();
+        assert!(synced.stake.approved > synced.stake.threshold());
+        assert!(synced.stake.margin_bps() > 0);
+    }
+
+    #[test]
+    fn test_sync_weighted() {
+        use crate::weights::Table;
+
+        let (head, bps, next_block) = test_state();
+        let table = |signed: bool| {
+            Table(
+                izip!(&next_block.approvals_after_next, &bps)
+                    .filter(|(approval, _)| approval.is_some() == signed)
+                    .map(|(_, vs)| (vs.account_id().clone(), 1))
+                    .collect(),
+            )
+        };
+
+        assert!(Protocol::sync_weighted(
+            &head,
+            &bps,
+            next_block.clone(),
+            &table(true),
+            KeyPolicy::default(),
+        )
+        .is_ok());
+        let err = Protocol::sync_weighted(
+            &head,
+            &bps,
+            next_block.clone(),
+            &table(false),
+            KeyPolicy::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Error>(),
+            Some(&Error::NotEnoughApprovedStake)
+        );
+    }
+
     #[test]
     fn test_next_bps_invalid_hash() {
         let (_, _, next_block) = test_state();
@@ -544,14 +983,98 @@ mod tests {
         let req = r#"{"outcome_proof":{"proof":[],"block_hash":"5CY72FinjVV2Hd5zRikYYMaKh67pftXJsw8vwRXAUAQF","id":"9UhBumQ3eEmPH5ALc3NwiDCQfDrFakteRD7rHE9CfZ32","outcome":{"logs":[],"receipt_ids":["2mrt6jXKwWzkGrhucAtSc8R3mjrhkwCjnqVckPdCMEDo"],"gas_burnt":2434069818500,"tokens_burnt":"243406981850000000000","executor_id":"datayalla.testnet","status":{"SuccessReceiptId":"2mrt6jXKwWzkGrhucAtSc8R3mjrhkwCjnqVckPdCMEDo"},"metadata":{"version":1,"gas_profile":null}}},"outcome_root_proof":[{"hash":"9f7YjLvzvSspJMMJ3DDTrFaEyPQ5qFqQDNoWzAbSTjTy","direction":"Right"},{"hash":"67ZxFmzWXbWJSyi7Wp9FTSbbJx2nMr7wSuW3EP1cJm4K","direction":"Left"}],"block_header_lite":{"prev_block_hash":"AEnTyGRrk2roQkYSWoqYhzkbp5SWWJtCd71ZYyj1P26i","inner_rest_hash":"G25j8jSWRyrXV317cPC3qYA4SyJWXsBfErjhBYQkxw5A","inner_lite":{"height":134481525,"epoch_id":"4tBzDozzGED3QiCRURfViVuyJy5ikaN9dVH7m2MYkTyw","next_epoch_id":"9gYJSiT3TQbKbwui5bdbzBA9PCMSSfiffWhBdMtcasm2","prev_state_root":"EwkRecSP8GRvaxL7ynCEoHhsL1ksU6FsHVLCevcccF5q","outcome_root":"8Eu5qpDUMpW5nbmTrTKmDH2VYqFEHTKPETSTpPoyGoGc","timestamp":1691615068679535000,"timestamp_nanosec":"1691615068679535094","next_bp_hash":"8LCFsP6LeueT4X3PEni9CMvH7maDYpBtfApWZdXmagss","block_merkle_root":"583vb6csYnczHyt5z6Msm4LzzGkceTZHdvXjC8vcWeGK"}},"block_proof":[{"hash":"AEnTyGRrk2roQkYSWoqYhzkbp5SWWJtCd71ZYyj1P26i","direction":"Left"},{"hash":"HgZaHXpb5zs4rxUQTeW69XBNLBJoo4sz2YEDh7aFnMpC","direction":"Left"},{"hash":"EYNXYsnESQkXo7B27a9xu6YgbDSyynNcByW5Q2SqAaKH","direction":"Right"},{"hash":"AbKbsD7snoSnmzAtwNqXLBT5sm7bZr48GCCLSdksFuzi","direction":"Left"},{"hash":"7KKmS7n3MtCfv7UqciidJ24Abqsk8m85jVQTh94KTjYS","direction":"Left"},{"hash":"5nKA1HCZMJbdCccZ16abZGEng4sMoZhKez74rcCFjnhL","direction":"Left"},{"hash":"BupagAycSLD7v42ksgMKJFiuCzCdZ6ksrGLwukw7Vfe3","direction":"Right"},{"hash":"D6v37P4kcVJh8N9bV417eqJoyMeQbuZ743oNsbKxsU7z","direction":"Right"},{"hash":"8sWxxbe1rdquP5VdYfQbw1UvtcXDRansJYJV5ySzyow4","direction":"Right"},{"hash":"CmKVKWRqEqi4UaeKKYXpPSesYqdQYwHQM3E4xLKEUAj8","direction":"Left"},{"hash":"3TvjFzVyPBvPpph5zL6VCASLCxdNeiKV6foPwUpAGqRv","direction":"Left"},{"hash":"AnzSG9f91ePS6L6ii3eAkocp4iKjp6wjzSwWsDYWLnMX","direction":"Right"},{"hash":"FYVJDL4T6c87An3pdeBvntB68NzpcPtpvLP6ifjxxNkr","direction":"Left"},{"hash":"2YMF6KE8XTz7Axj3uyAoFbZisWej9Xo8mxgVtauWCZaV","direction":"Left"},{"hash":"4BHtLcxqNfWSneBdW76qsd8om8Gjg58Qw5BX8PHz93hf","direction":"Left"},{"hash":"7G3QUT7NQSHyXNQyzm8dsaYrFk5LGhYaG7aVafKAekyG","direction":"Left"},{"hash":"3XaMNnvnX69gGqBJX43Na1bSTJ4VUe7z6h5ZYJsaSZZR","direction":"Left"},{"hash":"FKu7GtfviPioyAGXGZLBVTJeG7KY5BxGwuL447oAZxiL","direction":"Right"},{"hash":"BePd7DPKUQnGtnSds5fMJGBUwHGxSNBpaNLwceJGUcJX","direction":"Left"},{"hash":"2BVKWMd9pXZTEyE9D3KL52hAWAyMrXj1NqutamyurrY1","direction":"Left"},{"hash":"EWavHKhwQiT8ApnXvybvc9bFY6aJYJWqBhcrZpubKXtA","direction":"Left"},{"hash":"83Fsd3sdx5tsJkb6maBE1yViKiqbWCCNfJ4XZRsKnRZD","direction":"Left"},{"hash":"AaT9jQmUvVpgDHdFkLR2XctaUVdTti49enmtbT5hsoyL","direction":"Left"}]}"#;
         let p: RpcLightClientExecutionProofResponse = serde_json::from_str(req).unwrap();
 
-        let outcome_hash = CryptoHash::hash_borsh(p.outcome_proof.to_hashes());
-
-        let root_matches = Protocol::verify_outcome(
-            &outcome_hash,
+        let root_matches = verify_outcome_proof(
+            &outcome_hash(&p.outcome_proof),
             p.outcome_proof.proof.iter(),
             p.outcome_root_proof.iter(),
             &p.block_header_lite.inner_lite.outcome_root,
         );
         assert!(root_matches);
     }
+
+    /// Fields that aren't committed to by the block hash or outcome hash.
+    const UNCOMMITTED_FIELDS: &[&str] = &[
+        // Only `timestamp_nanosec` is hashed
+        "/inner_lite/timestamp",
+        "/block_header_lite/inner_lite/timestamp",
+        // Excluded from `to_hashes`
+        "/outcome_proof/outcome/metadata",
+    ];
+
+    #[test]
+    fn test_inclusion_checks() {
+        let proof: BasicProof = fixture("old.json");
+        let head_block_root =
+            compute_root_from_path(proof.block_proof.iter(), proof.block_header_lite.hash());
+        assert!(Protocol::inclusion_checks(&head_block_root, &proof).passed());
+
+        // A root we don't trust only fails the block inclusion
+        let checks = Protocol::inclusion_checks(&CryptoHash::default(), &proof);
+        assert_eq!(checks.iter().map(|(_, ok)| ok), [true, true, false]);
+
+        let mut bad_outcome = proof.clone();
+        bad_outcome.outcome_proof.outcome.gas_burnt += 1;
+        let checks = Protocol::inclusion_checks(&head_block_root, &bad_outcome);
+        assert_eq!(checks.iter().map(|(_, ok)| ok), [true, false, true]);
+    }
+
+    #[test]
+    fn test_block_must_be_before_head() {
+        let proof: BasicProof = fixture("old.json");
+        let block = proof.block_header_lite.inner_lite.height;
+        assert!(Protocol::ensure_block_is_before_head(block + 1, &proof).is_ok());
+        // An outcome in the head's own block, such as a receipt executed
+        // after its transaction, is only covered by the next head
+        for head in [block, block - 1] {
+            assert_eq!(
+                Protocol::ensure_block_is_before_head(head, &proof),
+                Err(Error::BlockAfterHead { block, head })
+            );
+        }
+    }
+
+    #[test]
+    fn test_fuzz_inclusion_proof() {
+        let proof: BasicProof = fixture("old.json");
+        let head_block_root =
+            compute_root_from_path(proof.block_proof.iter(), proof.block_header_lite.hash());
+        let verify = |proof: BasicProof| {
+            Protocol::inclusion_proof_verify(Proof::from((head_block_root, proof))).unwrap_or(false)
+        };
+        assert!(verify(proof.clone()));
+
+        let mut coverage = mutate::Coverage::default();
+        for mutant in mutate::mutants(&proof, usize::MAX) {
+            let verdict = if verify(mutant.value) {
+                mutate::Verdict::Unconstrained
+            } else {
+                mutate::Verdict::Rejected
+            };
+            coverage.record(&mutant.field, verdict);
+        }
+        coverage.emit("protocol_inclusion_proof");
+        assert_eq!(coverage.unexpected(UNCOMMITTED_FIELDS), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_fuzz_sync() {
+        let (head, bps, next_block) = test_state();
+        let sync = |next_block| {
+            Protocol::sync(&head, &bps, next_block, KeyPolicy::default())
+                .map(|synced| format!("{:?}", synced))
+        };
+        let expected = sync(next_block.clone()).unwrap();
+
+        let mut coverage = mutate::Coverage::default();
+        for mutant in mutate::mutants(&next_block, 8) {
+            let verdict = match sync(mutant.value) {
+                Err(_) => mutate::Verdict::Rejected,
+                Ok(synced) if synced == expected => mutate::Verdict::Ignored,
+                Ok(_) => mutate::Verdict::Unconstrained,
+            };
+            coverage.record(&mutant.field, verdict);
+        }
+        coverage.emit("protocol_sync");
+        assert_eq!(coverage.unexpected(UNCOMMITTED_FIELDS), Vec::<&str>::new());
+    }
 }
@@ -1,4 +1,11 @@
+//! Merkle paths as NEAR builds them, and the inclusion checks the `verify`
+//! circuit constrains.
+//!
+//! A path lists the siblings from the leaf up. Each step hashes with sha256,
+//! a `Left` sibling as `sha256(sibling || hash)` and a `Right` one as
+//! `sha256(hash || sibling)`, see `combine_hash`.
 pub use near_primitives::merkle::{combine_hash, Direction, MerklePath, MerklePathItem};
+use near_primitives::views::ExecutionOutcomeWithIdView;
 use near_primitives_core::types::MerkleHash;
 
 use crate::prelude::*;
@@ -35,3 +42,95 @@ pub fn compute_root_from_path_and_item<'a, T: BorshSerialize>(
 ) -> MerkleHash {
     compute_root_from_path(path, CryptoHash::hash_borsh(item))
 }
+
+/// The leaf of an outcome in its chunk's outcome tree, `sha256` of the borsh
+/// encoded id, partial outcome hash and log hashes, see `to_hashes`.
+pub fn outcome_hash(outcome: &ExecutionOutcomeWithIdView) -> CryptoHash {
+    CryptoHash::hash_borsh(outcome.to_hashes())
+}
+
+/// Whether an outcome is in a block's `outcome_root`, as the `verify` circuit
+/// checks it.
+///
+/// Outcomes are merklized per chunk, then the chunk roots are merklized in
+/// shard order. So `outcome_proof` goes from the outcome hash, see
+/// `outcome_hash`, to the chunk's outcome root, and `outcome_root_proof` from
+/// `sha256(chunk outcome root)` to the block's `outcome_root`.
+pub fn verify_outcome_proof<'a>(
+    outcome_hash: &CryptoHash,
+    outcome_proof: impl Iterator<Item = &'a MerklePathItem>,
+    outcome_root_proof: impl Iterator<Item = &'a MerklePathItem>,
+    outcome_root: &CryptoHash,
+) -> bool {
+    let chunk_outcome_root = compute_root_from_path(outcome_proof, *outcome_hash);
+    log::debug!("chunk outcome_root: {:?}", chunk_outcome_root);
+    verify_hash(
+        *outcome_root,
+        outcome_root_proof,
+        CryptoHash::hash_borsh(chunk_outcome_root),
+    )
+}
+
+/// Whether a block is in a head's `block_merkle_root`, as the `verify` circuit
+/// checks it.
+///
+/// `block_proof` goes from the block hash, the leaves being every block hash
+/// by ordinal. A head's root only covers the blocks before it, see
+/// `block_merkle`.
+pub fn verify_block_proof<'a>(
+    block_merkle_root: &CryptoHash,
+    block_proof: impl Iterator<Item = &'a MerklePathItem>,
+    block_hash: &CryptoHash,
+) -> bool {
+    verify_hash(*block_merkle_root, block_proof, *block_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::merkle::merklize;
+    use test_utils::fixture;
+
+    use super::*;
+
+    #[test]
+    fn test_hash_order() {
+        let leaves = [1u8, 2, 3].map(|i| CryptoHash::hash_bytes(&[i]));
+        let (root, paths) = merklize(&leaves);
+        let expected = combine_hash(&combine_hash(&leaves[0], &leaves[1]), &leaves[2]);
+        assert_eq!(root, expected);
+        for (leaf, path) in leaves.iter().zip(&paths) {
+            assert!(verify_block_proof(&root, path.iter(), leaf));
+        }
+
+        // The direction says which side the sibling is on
+        let mut flipped = paths[0].clone();
+        flipped[0].direction = Direction::Left;
+        assert!(!verify_block_proof(&root, flipped.iter(), &leaves[0]));
+    }
+
+    #[test]
+    fn test_verify_outcome_proof() {
+        let proof: BasicProof = fixture("old.json");
+        let outcome_root = proof.block_header_lite.inner_lite.outcome_root;
+        let verify = |proof: &BasicProof| {
+            verify_outcome_proof(
+                &outcome_hash(&proof.outcome_proof),
+                proof.outcome_proof.proof.iter(),
+                proof.outcome_root_proof.iter(),
+                &outcome_root,
+            )
+        };
+        assert!(verify(&proof));
+
+        let mut swapped = proof.clone();
+        std::mem::swap(
+            &mut swapped.outcome_proof.proof,
+            &mut swapped.outcome_root_proof,
+        );
+        assert!(!verify(&swapped));
+
+        let mut logged = proof;
+        logged.outcome_proof.outcome.logs.push("log".to_string());
+        assert!(!verify(&logged));
+    }
+}
@@ -0,0 +1,286 @@
+//! Recomputing a block's outcome root from the outcomes of each chunk.
+//!
+//! The outcomes of a chunk are merklized in execution order into the chunk's
+//! outcome root, and the block's `outcome_root` merklizes those. Given every
+//! outcome we can check the RPC's proofs against our own, and build proofs
+//! for outcomes the RPC won't serve.
+//!
+//! An outcome is hashed as its id, its partial outcome and its logs. The
+//! partial outcome holds the status and the gas burnt, so consumers of a
+//! proof can see what a transaction or receipt did, see `OutcomeStatus`.
+use near_primitives::{
+    merkle::{merklize, MerklePath},
+    transaction::PartialExecutionOutcome,
+    views::{
+        ExecutionOutcomeView, ExecutionOutcomeWithIdView, ExecutionStatusView,
+        LightClientBlockLiteView,
+    },
+};
+use nearx_error::bail;
+
+use crate::prelude::*;
+
+/// The outcomes of a block, by chunk, each in execution order.
+#[derive(Debug, Clone)]
+pub struct BlockOutcomes {
+    block_hash: CryptoHash,
+    chunks: Vec<Vec<ExecutionOutcomeWithIdView>>,
+    chunk_roots: Vec<CryptoHash>,
+    root: CryptoHash,
+}
+
+impl BlockOutcomes {
+    pub fn new(block_hash: CryptoHash, chunks: Vec<Vec<ExecutionOutcomeWithIdView>>) -> Self {
+        let chunk_roots = chunks
+            .iter()
+            .map(|outcomes| merklize(&outcome_hashes(outcomes)).0)
+            .collect_vec();
+        let root = merklize(&chunk_roots).0;
+        Self {
+            block_hash,
+            chunks,
+            chunk_roots,
+            root,
+        }
+    }
+
+    /// The recomputed `outcome_root`.
+    pub fn root(&self) -> &CryptoHash {
+        &self.root
+    }
+
+    pub fn chunk_roots(&self) -> &[CryptoHash] {
+        &self.chunk_roots
+    }
+
+    /// The outcome and its proofs up to the outcome root, the same as the
+    /// `outcome_proof` and `outcome_root_proof` the RPC would serve.
+    pub fn prove(&self, id: &CryptoHash) -> Option<(ExecutionOutcomeWithIdView, MerklePath)> {
+        let (chunk, index) = self
+            .chunks
+            .iter()
+            .enumerate()
+            .find_map(|(chunk, outcomes)| {
+                outcomes
+                    .iter()
+                    .position(|o| &o.id == id)
+                    .map(|index| (chunk, index))
+            })?;
+
+        let mut outcome = self.chunks[chunk][index].clone();
+        outcome.proof = merklize(&outcome_hashes(&self.chunks[chunk])).1[index].clone();
+        outcome.block_hash = self.block_hash;
+        let outcome_root_proof = merklize(&self.chunk_roots).1[chunk].clone();
+        Some((outcome, outcome_root_proof))
+    }
+
+    /// Build a full proof for an outcome, given the header of the block and
+    /// its proof against the head.
+    pub fn proof(
+        &self,
+        id: &CryptoHash,
+        header: LightClientBlockLiteView,
+        block_proof: MerklePath,
+    ) -> Result<BasicProof> {
+        if header.hash() != self.block_hash {
+            bail!(Protocol, "Header is not for block {}", self.block_hash);
+        }
+        if header.inner_lite.outcome_root != self.root {
+            bail!(
+                Protocol,
+                "Recomputed outcome root {} does not match the header's {}",
+                self.root,
+                header.inner_lite.outcome_root
+            );
+        }
+        let (outcome_proof, outcome_root_proof) = self
+            .prove(id)
+            .ok_or_else(|| err!(Protocol, "No outcome for {} in {}", id, self.block_hash))?;
+        Ok(BasicProof {
+            outcome_proof,
+            outcome_root_proof,
+            block_header_lite: header,
+            block_proof,
+        })
+    }
+
+    /// Check a proof served by the RPC against the recomputed outcomes.
+    pub fn cross_check(&self, proof: &BasicProof) -> Result<()> {
+        let id = proof.outcome_proof.id;
+        if proof.block_header_lite.inner_lite.outcome_root != self.root {
+            bail!(
+                Protocol,
+                "Recomputed outcome root {} does not match the header's {}",
+                self.root,
+                proof.block_header_lite.inner_lite.outcome_root
+            );
+        }
+        let (outcome, outcome_root_proof) = self
+            .prove(&id)
+            .ok_or_else(|| err!(Protocol, "No outcome for {} in {}", id, self.block_hash))?;
+        if outcome.to_hashes() != proof.outcome_proof.to_hashes() {
+            bail!(Protocol, "Outcome for {} differs", id);
+        }
+        if outcome.proof != proof.outcome_proof.proof
+            || outcome_root_proof != proof.outcome_root_proof
+        {
+            bail!(Protocol, "Proof for {} differs", id);
+        }
+        Ok(())
+    }
+}
+
+/// The leaves of a chunk's outcome tree, merklize hashes these again.
+fn outcome_hashes(outcomes: &[ExecutionOutcomeWithIdView]) -> Vec<Vec<CryptoHash>> {
+    outcomes.iter().map(|o| o.to_hashes()).collect()
+}
+
+/// The borsh encoded partial outcome, its hash is the second of
+/// `to_hashes`.
+pub fn partial_outcome(outcome: &ExecutionOutcomeView) -> Vec<u8> {
+    borsh::to_vec(&PartialExecutionOutcome {
+        receipt_ids: outcome.receipt_ids.clone(),
+        gas_burnt: outcome.gas_burnt,
+        tokens_burnt: outcome.tokens_burnt,
+        executor_id: outcome.executor_id.clone(),
+        status: outcome.status.clone().into(),
+    })
+    .expect("outcomes always serialize")
+}
+
+/// What an outcome did, as far as its partial outcome shows. A failure's
+/// error isn't part of it, only that it failed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutcomeStatus {
+    /// The borsh tag of `PartialExecutionStatus`.
+    pub kind: u8,
+    pub gas_burnt: u64,
+    /// The hash of the returned value, the receipt id the result is deferred
+    /// to, or the default hash for the other kinds.
+    pub result_hash: CryptoHash,
+}
+
+impl OutcomeStatus {
+    pub const UNKNOWN: u8 = 0;
+    pub const FAILURE: u8 = 1;
+    pub const SUCCESS_VALUE: u8 = 2;
+    pub const SUCCESS_RECEIPT_ID: u8 = 3;
+}
+
+impl From<&ExecutionOutcomeView> for OutcomeStatus {
+    fn from(outcome: &ExecutionOutcomeView) -> Self {
+        let (kind, result_hash) = match &outcome.status {
+            ExecutionStatusView::Unknown => (Self::UNKNOWN, CryptoHash::default()),
+            ExecutionStatusView::Failure(_) => (Self::FAILURE, CryptoHash::default()),
+            ExecutionStatusView::SuccessValue(value) => {
+                (Self::SUCCESS_VALUE, CryptoHash::hash_bytes(value))
+            }
+            ExecutionStatusView::SuccessReceiptId(id) => (Self::SUCCESS_RECEIPT_ID, *id),
+        };
+        Self {
+            kind,
+            gas_burnt: outcome.gas_burnt,
+            result_hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Proof, Protocol};
+
+    const FIXTURE: &str = r#"{"proof":[],"block_hash":"5CY72FinjVV2Hd5zRikYYMaKh67pftXJsw8vwRXAUAQF","id":"9UhBumQ3eEmPH5ALc3NwiDCQfDrFakteRD7rHE9CfZ32","outcome":{"logs":[],"receipt_ids":["2mrt6jXKwWzkGrhucAtSc8R3mjrhkwCjnqVckPdCMEDo"],"gas_burnt":2434069818500,"tokens_burnt":"243406981850000000000","executor_id":"datayalla.testnet","status":{"SuccessReceiptId":"2mrt6jXKwWzkGrhucAtSc8R3mjrhkwCjnqVckPdCMEDo"},"metadata":{"version":1,"gas_profile":null}}}"#;
+
+    fn outcome(i: u8) -> ExecutionOutcomeWithIdView {
+        let mut outcome: ExecutionOutcomeWithIdView = serde_json::from_str(FIXTURE).unwrap();
+        outcome.id = CryptoHash::hash_bytes(&[i]);
+        outcome.outcome.gas_burnt += i as u64;
+        outcome
+    }
+
+    fn fixture() -> (LightClientBlockLiteView, BlockOutcomes) {
+        let mut header = test_utils::test_state().0;
+        let chunks = vec![
+            vec![outcome(0), outcome(1), outcome(2)],
+            vec![],
+            vec![outcome(3)],
+            vec![outcome(4), outcome(5)],
+        ];
+        let recomputed = BlockOutcomes::new(CryptoHash::default(), chunks.clone());
+        header.inner_lite.outcome_root = *recomputed.root();
+        (header.clone(), BlockOutcomes::new(header.hash(), chunks))
+    }
+
+    #[test]
+    fn test_built_proofs_verify() {
+        let (header, outcomes) = fixture();
+        for i in 0..6 {
+            let id = CryptoHash::hash_bytes(&[i]);
+            // Prove against the block itself
+            let proof = outcomes.proof(&id, header.clone(), vec![]).unwrap();
+            outcomes.cross_check(&proof).unwrap();
+            assert!(Protocol::inclusion_proof_verify(Proof::from((header.hash(), proof))).unwrap());
+        }
+        assert!(outcomes
+            .proof(&CryptoHash::hash_bytes(&[6]), header, vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn test_cross_check_catches_differences() {
+        let (header, outcomes) = fixture();
+        let id = CryptoHash::hash_bytes(&[1]);
+        let proof = outcomes.proof(&id, header, vec![]).unwrap();
+
+        let mut changed = proof.clone();
+        changed.outcome_proof.outcome.gas_burnt += 1;
+        assert!(outcomes.cross_check(&changed).is_err());
+
+        let mut reordered = proof.clone();
+        reordered.outcome_proof.proof.reverse();
+        assert!(outcomes.cross_check(&reordered).is_err());
+
+        let mut wrong_root = proof;
+        wrong_root.block_header_lite.inner_lite.outcome_root = CryptoHash::default();
+        assert!(outcomes.cross_check(&wrong_root).is_err());
+    }
+
+    #[test]
+    fn test_single_outcome_chunk_matches_rpc() {
+        // The recorded proof is for the only outcome in its chunk, so the
+        // chunk's root is the leaf itself
+        let outcome: ExecutionOutcomeWithIdView = serde_json::from_str(FIXTURE).unwrap();
+        let outcomes = BlockOutcomes::new(outcome.block_hash, vec![vec![outcome.clone()]]);
+        assert_eq!(
+            outcomes.chunk_roots()[0],
+            CryptoHash::hash_borsh(outcome.to_hashes())
+        );
+    }
+
+    #[test]
+    fn test_partial_outcome() {
+        let mut outcome: ExecutionOutcomeWithIdView = serde_json::from_str(FIXTURE).unwrap();
+        let bytes = partial_outcome(&outcome.outcome);
+        assert_eq!(CryptoHash::hash_bytes(&bytes), outcome.to_hashes()[1]);
+        // One receipt, the gas, the tokens, the executor then the receipt id
+        assert_eq!(bytes.len(), 4 + 32 + 8 + 16 + 4 + 17 + 1 + 32);
+        assert_eq!(
+            OutcomeStatus::from(&outcome.outcome),
+            OutcomeStatus {
+                kind: OutcomeStatus::SUCCESS_RECEIPT_ID,
+                gas_burnt: 2434069818500,
+                result_hash: outcome.outcome.receipt_ids[0],
+            }
+        );
+
+        outcome.outcome.status = ExecutionStatusView::SuccessValue(b"42".to_vec());
+        let bytes = partial_outcome(&outcome.outcome);
+        assert_eq!(CryptoHash::hash_bytes(&bytes), outcome.to_hashes()[1]);
+        assert_eq!(bytes[bytes.len() - 7], OutcomeStatus::SUCCESS_VALUE);
+        assert_eq!(
+            OutcomeStatus::from(&outcome.outcome).result_hash,
+            CryptoHash::hash_bytes(b"42")
+        );
+    }
+}
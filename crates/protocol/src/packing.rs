@@ -0,0 +1,533 @@
+//! A compact, non-ABI encoding of the public outputs.
+//!
+//! The ABI encoding pads every field to a word, so a batch of verify results
+//! ends up mostly padding. Here booleans are bit-packed, heights and lengths
+//! are LEB128 varints and placeholder entries, which only exist to fill the
+//! fixed size of the circuit, are truncated.
+//!
+//! The operator attaches the packed verify results of each batch to its
+//! proof with `scheduler.output = "packed"`, the SDK's `unpack` reads them.
+use near_primitives::types::{validator_stake::ValidatorStake, Balance, BlockHeight};
+
+use crate::{
+    outcomes::OutcomeStatus, prelude::*, ED25519PublicKey, Protocol, PublicKey, ValidatorStakeView,
+};
+
+/// The calldata size we aim to stay under, cheaper L2s start penalising
+/// transactions beyond this.
+pub const CALLDATA_BUDGET: usize = 128 * 1024;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum UnpackError {
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+    #[error("varint overflows {0} bits")]
+    VarintOverflow(u32),
+    #[error("invalid account id")]
+    InvalidAccountId,
+    #[error("invalid outcome status {0}")]
+    InvalidStatus(u8),
+    #[error("{0} trailing bytes")]
+    TrailingBytes(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct Packer(Vec<u8>);
+
+impl Packer {
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn hash(&mut self, hash: &CryptoHash) -> &mut Self {
+        self.bytes(&hash.0)
+    }
+
+    pub fn varint(&mut self, mut value: u128) -> &mut Self {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.0.push(byte);
+                return self;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    /// Pack booleans eight to a byte, the first in the most significant bit.
+    /// The length is not written.
+    pub fn bools(&mut self, bools: &[bool]) -> &mut Self {
+        for chunk in bools.chunks(8) {
+            let byte = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (i, b)| acc | ((*b as u8) << (7 - i)));
+            self.0.push(byte);
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct Unpacker<'a>(&'a [u8]);
+
+impl<'a> Unpacker<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], UnpackError> {
+        if self.0.len() < len {
+            return Err(UnpackError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn hash(&mut self) -> Result<CryptoHash, UnpackError> {
+        let bytes = self.bytes(32)?;
+        Ok(CryptoHash(bytes.try_into().expect("length checked")))
+    }
+
+    pub fn varint(&mut self) -> Result<u128, UnpackError> {
+        let mut value = 0u128;
+        for shift in (0..u128::BITS).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            let bits = (byte & 0x7f) as u128;
+            if bits << shift >> shift != bits {
+                return Err(UnpackError::VarintOverflow(u128::BITS));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(UnpackError::VarintOverflow(u128::BITS))
+    }
+
+    pub fn varint_u64(&mut self) -> Result<u64, UnpackError> {
+        self.varint()?
+            .try_into()
+            .map_err(|_| UnpackError::VarintOverflow(u64::BITS))
+    }
+
+    pub fn bools(&mut self, len: usize) -> Result<Vec<bool>, UnpackError> {
+        let bytes = self.bytes(len.div_ceil(8))?;
+        Ok((0..len)
+            .map(|i| bytes[i / 8] & (1 << (7 - i % 8)) != 0)
+            .collect())
+    }
+
+    pub fn finish(self) -> Result<(), UnpackError> {
+        match self.0.len() {
+            0 => Ok(()),
+            n => Err(UnpackError::TrailingBytes(n)),
+        }
+    }
+}
+
+/// The result of verifying an outcome, what `VerifyCircuit` outputs for
+/// each id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerifyResult {
+    pub id: CryptoHash,
+    pub passed: bool,
+    pub status: OutcomeStatus,
+}
+
+impl VerifyResult {
+    /// Verify a proof against `head_block_root` natively, as the circuit
+    /// does.
+    pub fn native(head_block_root: &CryptoHash, proof: &BasicProof) -> Self {
+        Self {
+            id: proof.outcome_proof.id,
+            passed: Protocol::inclusion_checks(head_block_root, proof).passed(),
+            status: OutcomeStatus::from(&proof.outcome_proof.outcome),
+        }
+    }
+}
+
+/// Only these statuses have a result hash, it is zero for the others.
+fn has_result_hash(kind: u8) -> bool {
+    matches!(
+        kind,
+        OutcomeStatus::SUCCESS_VALUE | OutcomeStatus::SUCCESS_RECEIPT_ID
+    )
+}
+
+/// Pack the results of a verify batch, the ids followed by a bitmap of
+/// whether each passed, then each status kind, gas burnt and result hash if
+/// it has one. Placeholder entries, with a default id, are dropped.
+pub fn pack_verify_results(results: &[VerifyResult]) -> Vec<u8> {
+    let results = results
+        .iter()
+        .filter(|r| r.id != CryptoHash::default())
+        .collect_vec();
+
+    let mut packer = Packer::default();
+    packer.varint(results.len() as u128);
+    for r in &results {
+        packer.hash(&r.id);
+    }
+    packer.bools(&results.iter().map(|r| r.passed).collect_vec());
+    for r in &results {
+        packer
+            .bytes(&[r.status.kind])
+            .varint(r.status.gas_burnt as u128);
+        if has_result_hash(r.status.kind) {
+            packer.hash(&r.status.result_hash);
+        }
+    }
+    packer.finish()
+}
+
+pub fn unpack_verify_results(bytes: &[u8]) -> Result<Vec<VerifyResult>, UnpackError> {
+    let mut unpacker = Unpacker::new(bytes);
+    let len = unpacker.varint_u64()? as usize;
+    let ids = (0..len)
+        .map(|_| unpacker.hash())
+        .collect::<Result<Vec<_>, _>>()?;
+    let passed = unpacker.bools(len)?;
+    let results = ids
+        .into_iter()
+        .zip(passed)
+        .map(|(id, passed)| {
+            let kind = unpacker.bytes(1)?[0];
+            if kind > OutcomeStatus::SUCCESS_RECEIPT_ID {
+                return Err(UnpackError::InvalidStatus(kind));
+            }
+            let gas_burnt = unpacker.varint_u64()?;
+            let result_hash = if has_result_hash(kind) {
+                unpacker.hash()?
+            } else {
+                CryptoHash::default()
+            };
+            Ok(VerifyResult {
+                id,
+                passed,
+                status: OutcomeStatus {
+                    kind,
+                    gas_burnt,
+                    result_hash,
+                },
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    unpacker.finish()?;
+    Ok(results)
+}
+
+/// The outputs of a sync, with the next BPS if the epoch changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncOutput {
+    pub new_head_hash: CryptoHash,
+    pub height: BlockHeight,
+    pub next_bps_epoch: CryptoHash,
+    pub next_bps: Vec<ValidatorStake>,
+}
+
+impl SyncOutput {
+    /// The seats that aren't placeholders, which have no stake.
+    fn seats(&self) -> impl Iterator<Item = &ValidatorStake> {
+        self.next_bps.iter().filter(|vs| vs.stake() > 0)
+    }
+
+    /// The hash the headers commit to the next BPS with, `next_bp_hash`.
+    pub fn next_bps_hash(&self) -> CryptoHash {
+        CryptoHash::hash_borsh(
+            self.seats()
+                .cloned()
+                .map(ValidatorStakeView::from)
+                .collect_vec(),
+        )
+    }
+}
+
+/// How the next BPS are carried in a packed sync output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BpsMode {
+    /// Every validator, for consumers that track the set themselves.
+    #[default]
+    Full,
+    /// Only the hash of the set, for consumers that only check a commitment
+    /// or fetch the set elsewhere.
+    Commitment,
+}
+
+/// Pack the outputs of a sync with the next BPS in full.
+pub fn pack_sync(output: &SyncOutput) -> Vec<u8> {
+    pack_sync_with(output, BpsMode::Full)
+}
+
+/// Pack the outputs of a sync. Placeholder seats, which have no stake, are
+/// dropped and account ids are written without their padding.
+pub fn pack_sync_with(output: &SyncOutput, mode: BpsMode) -> Vec<u8> {
+    let mut packer = Packer::default();
+    packer
+        .hash(&output.new_head_hash)
+        .varint(output.height as u128)
+        .hash(&output.next_bps_epoch);
+    if mode == BpsMode::Commitment {
+        return packer.hash(&output.next_bps_hash()).finish();
+    }
+
+    let bps = output.seats().collect_vec();
+    packer.varint(bps.len() as u128);
+    for vs in bps {
+        let account_id = vs.account_id().as_str().as_bytes();
+        packer
+            .varint(account_id.len() as u128)
+            .bytes(account_id)
+            .bytes(vs.public_key().key_data())
+            .varint(vs.stake());
+    }
+    packer.finish()
+}
+
+pub fn unpack_sync(bytes: &[u8]) -> Result<SyncOutput, UnpackError> {
+    let mut unpacker = Unpacker::new(bytes);
+    let new_head_hash = unpacker.hash()?;
+    let height = unpacker.varint_u64()?;
+    let next_bps_epoch = unpacker.hash()?;
+    let len = unpacker.varint_u64()?;
+    let next_bps = (0..len)
+        .map(|_| {
+            let account_len = unpacker.varint_u64()? as usize;
+            let account_id = std::str::from_utf8(unpacker.bytes(account_len)?)
+                .ok()
+                .and_then(|s| s.parse::<AccountId>().ok())
+                .ok_or(UnpackError::InvalidAccountId)?;
+            let public_key = PublicKey::ED25519(ED25519PublicKey(unpacker.hash()?.0));
+            let stake: Balance = unpacker.varint()?;
+            Ok(ValidatorStake::new_v1(account_id, public_key, stake))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    unpacker.finish()?;
+    Ok(SyncOutput {
+        new_head_hash,
+        height,
+        next_bps_epoch,
+        next_bps,
+    })
+}
+
+/// The outputs of a sync packed with `BpsMode::Commitment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncCommitment {
+    pub new_head_hash: CryptoHash,
+    pub height: BlockHeight,
+    pub next_bps_epoch: CryptoHash,
+    pub next_bps_hash: CryptoHash,
+}
+
+pub fn unpack_sync_commitment(bytes: &[u8]) -> Result<SyncCommitment, UnpackError> {
+    let mut unpacker = Unpacker::new(bytes);
+    let output = SyncCommitment {
+        new_head_hash: unpacker.hash()?,
+        height: unpacker.varint_u64()?,
+        next_bps_epoch: unpacker.hash()?,
+        next_bps_hash: unpacker.hash()?,
+    };
+    unpacker.finish()?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for value in [0, 1, 127, 128, 300, u64::MAX as u128, u128::MAX] {
+            let bytes = Packer::default().varint(value).finish();
+            let mut unpacker = Unpacker::new(&bytes);
+            assert_eq!(unpacker.varint().unwrap(), value);
+            unpacker.finish().unwrap();
+        }
+        assert_eq!(Packer::default().varint(300).finish(), vec![0xac, 0x02]);
+
+        let too_long = [0xff; 20];
+        assert_eq!(
+            Unpacker::new(&too_long).varint(),
+            Err(UnpackError::VarintOverflow(u128::BITS))
+        );
+    }
+
+    #[test]
+    fn test_bools() {
+        let bools = [true, false, true, true, false, false, false, false, true];
+        let bytes = Packer::default().bools(&bools).finish();
+        assert_eq!(bytes, vec![0b1011_0000, 0b1000_0000]);
+        assert_eq!(Unpacker::new(&bytes).bools(9).unwrap(), bools);
+    }
+
+    fn verify_results(n: u8) -> Vec<VerifyResult> {
+        (0..n)
+            .map(|i| VerifyResult {
+                id: CryptoHash::hash_bytes(&[i]),
+                passed: i % 2 == 0,
+                status: OutcomeStatus {
+                    kind: i % 4,
+                    gas_burnt: 2434069818500 + i as u64,
+                    result_hash: if has_result_hash(i % 4) {
+                        CryptoHash::hash_bytes(&[i, i])
+                    } else {
+                        CryptoHash::default()
+                    },
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_results_truncate_placeholders() {
+        let mut results = verify_results(5);
+        let expected = results.clone();
+        results.resize(64, Default::default());
+
+        let bytes = pack_verify_results(&results);
+        // Two of the five have a result hash, the gas takes 6 bytes
+        assert_eq!(bytes.len(), 1 + 5 * 32 + 1 + 5 * (1 + 6) + 2 * 32);
+        assert_eq!(unpack_verify_results(&bytes).unwrap(), expected);
+
+        let mut invalid = bytes.clone();
+        invalid[1 + 5 * 32 + 1] = 9;
+        assert_eq!(
+            unpack_verify_results(&invalid),
+            Err(UnpackError::InvalidStatus(9))
+        );
+
+        assert_eq!(
+            unpack_verify_results(&bytes[..bytes.len() - 1]),
+            Err(UnpackError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_sync_roundtrip() {
+        let vs = |account: &str, stake| {
+            ValidatorStake::new_v1(
+                account.parse().unwrap(),
+                PublicKey::ED25519(ED25519PublicKey([stake as u8; 32])),
+                stake,
+            )
+        };
+        let output = SyncOutput {
+            new_head_hash: CryptoHash::hash_bytes(b"head"),
+            height: 137_000_000,
+            next_bps_epoch: CryptoHash::hash_bytes(b"epoch"),
+            next_bps: vec![vs("a.near", 10u128.pow(30)), vs("b.near", 42)],
+        };
+        let mut padded = output.clone();
+        padded.next_bps.push(vs("placeholder.near", 0));
+
+        let bytes = pack_sync(&padded);
+        assert_eq!(unpack_sync(&bytes).unwrap(), output);
+    }
+
+    #[test]
+    fn test_sync_commitment() {
+        let first = test_utils::test_first().body;
+        let output = SyncOutput {
+            new_head_hash: CryptoHash::hash_bytes(b"head"),
+            height: first.inner_lite.height,
+            next_bps_epoch: first.inner_lite.next_epoch_id,
+            next_bps: first
+                .next_bps
+                .unwrap()
+                .into_iter()
+                .map(ValidatorStakeView::into_validator_stake)
+                .collect(),
+        };
+        let mut padded = output.clone();
+        padded
+            .next_bps
+            .resize(crate::config::NUM_BLOCK_PRODUCER_SEATS, placeholder());
+
+        let bytes = pack_sync_with(&padded, BpsMode::Commitment);
+        assert_eq!(
+            unpack_sync_commitment(&bytes).unwrap(),
+            SyncCommitment {
+                new_head_hash: output.new_head_hash,
+                height: output.height,
+                next_bps_epoch: output.next_bps_epoch,
+                next_bps_hash: first.inner_lite.next_bp_hash,
+            }
+        );
+        assert!(bytes.len() * 2 < pack_sync(&padded).len());
+
+        // The modes aren't interchangeable
+        assert!(unpack_sync(&bytes).is_err());
+    }
+
+    fn placeholder() -> ValidatorStake {
+        ValidatorStake::new_v1(
+            "placeholder.near".parse().unwrap(),
+            PublicKey::ED25519(ED25519PublicKey([0; 32])),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_no_std_decoders_agree() {
+        use near_light_client_outputs::packed;
+
+        let results = verify_results(10);
+        let bytes = pack_verify_results(&results);
+        let decoded = packed::VerifyResults::decode(&bytes)
+            .unwrap()
+            .results()
+            .map(|r| VerifyResult {
+                id: CryptoHash(*r.id),
+                passed: r.passed,
+                status: OutcomeStatus {
+                    kind: r.status,
+                    gas_burnt: r.gas_burnt,
+                    result_hash: CryptoHash(*r.result_hash),
+                },
+            })
+            .collect_vec();
+        assert_eq!(decoded, results);
+
+        let output = SyncOutput {
+            new_head_hash: CryptoHash::hash_bytes(b"head"),
+            height: 137_000_000,
+            next_bps_epoch: CryptoHash::hash_bytes(b"epoch"),
+            next_bps: vec![ValidatorStake::new_v1(
+                "a.near".parse().unwrap(),
+                PublicKey::ED25519(ED25519PublicKey([1; 32])),
+                10u128.pow(30),
+            )],
+        };
+        let bytes = pack_sync(&output);
+        let decoded = packed::SyncOutput::decode(&bytes).unwrap();
+        assert_eq!(decoded.height, output.height);
+        assert_eq!(decoded.next_bps_epoch, &output.next_bps_epoch.0);
+        let bps = decoded.next_bps().collect_vec();
+        assert_eq!(bps.len(), 1);
+        assert_eq!(bps[0].account_id, "a.near");
+        assert_eq!(bps[0].stake, 10u128.pow(30));
+
+        let bytes = pack_sync_with(&output, BpsMode::Commitment);
+        let decoded = packed::SyncCommitment::decode(&bytes).unwrap();
+        assert_eq!(decoded.height, output.height);
+        assert_eq!(decoded.next_bps_hash, &output.next_bps_hash().0);
+    }
+
+    #[test]
+    fn test_full_batch_fits_budget() {
+        // Far more than a verify batch, even before truncation
+        let results = (0..2048u32)
+            .map(|i| (CryptoHash::hash_bytes(&i.to_be_bytes()), true))
+            .collect_vec();
+        assert!(pack_verify_results(&results).len() < CALLDATA_BUDGET);
+    }
+}
@@ -1,4 +1,3 @@
-pub use anyhow::{anyhow, Result};
 pub use itertools::{izip, Itertools};
 pub use log::{debug, error, info, trace, warn};
 pub use near_primitives::types::AccountId;
@@ -6,6 +5,7 @@ pub use near_primitives_core::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     hash::CryptoHash,
 };
+pub use nearx_error::{err, Result};
 pub use serde::{Deserialize, Serialize};
 
 pub type Header = near_primitives::views::LightClientBlockLiteView;
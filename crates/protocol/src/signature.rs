@@ -0,0 +1,205 @@
+//! The signature schemes block producer approvals are accepted in.
+//!
+//! NEAR has more key types than the circuits can verify, so only the schemes
+//! in [`SUPPORTED_SCHEMES`] count towards approved stake, natively too so that
+//! both agree. Supporting another means implementing [`VerifiableSignature`]
+//! for it and a gadget in the circuits, which commit to the registry so their
+//! verifier keys change with it.
+//!
+//! Keys come from the RPC as compressed points, which aren't all on the curve.
+//! What an approval by such a key does is decided by the [`KeyPolicy`], the
+//! same for both.
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use near_crypto::KeyType;
+
+use crate::{prelude::*, PublicKey, Signature};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Scheme {
+    Ed25519 = 0,
+    Secp256k1 = 1,
+}
+
+impl From<KeyType> for Scheme {
+    fn from(key_type: KeyType) -> Self {
+        match key_type {
+            KeyType::ED25519 => Scheme::Ed25519,
+            KeyType::SECP256K1 => Scheme::Secp256k1,
+        }
+    }
+}
+
+impl Scheme {
+    pub fn is_supported(&self) -> bool {
+        SUPPORTED_SCHEMES.contains(self)
+    }
+}
+
+/// A scheme's keys and signatures in the form its verifier takes them.
+pub trait VerifiableSignature {
+    const SCHEME: Scheme;
+    type PublicKey;
+    type Signature;
+
+    /// `None` if the key is of another scheme.
+    fn public_key(pk: &PublicKey) -> Option<Self::PublicKey>;
+
+    /// `None` if the signature is of another scheme.
+    fn signature(sig: &Signature) -> Option<Self::Signature>;
+}
+
+pub struct Ed25519;
+
+impl VerifiableSignature for Ed25519 {
+    const SCHEME: Scheme = Scheme::Ed25519;
+    /// The compressed point.
+    type PublicKey = [u8; 32];
+    /// `R` and `s`, as they are encoded in the signature.
+    type Signature = ([u8; 32], [u8; 32]);
+
+    fn public_key(pk: &PublicKey) -> Option<Self::PublicKey> {
+        match pk {
+            PublicKey::ED25519(pk) => Some(pk.0),
+            _ => None,
+        }
+    }
+
+    fn signature(sig: &Signature) -> Option<Self::Signature> {
+        match sig {
+            Signature::ED25519(sig) => Some((*sig.r_bytes(), *sig.s_bytes())),
+            _ => None,
+        }
+    }
+}
+
+impl Ed25519 {
+    /// Whether the key decompresses to a point, and is that point's canonical
+    /// encoding.
+    pub fn is_valid_key(pk: &<Self as VerifiableSignature>::PublicKey) -> bool {
+        CompressedEdwardsY(*pk)
+            .decompress()
+            .is_some_and(|point| point.compress().0 == *pk)
+    }
+}
+
+/// The schemes the circuits have a gadget for.
+pub const SUPPORTED_SCHEMES: &[Scheme] = &[Ed25519::SCHEME];
+
+/// Identifies the set of supported schemes, a bit for each.
+pub const SCHEME_REGISTRY: u64 = registry(SUPPORTED_SCHEMES);
+
+const fn registry(schemes: &[Scheme]) -> u64 {
+    let mut registry = 0;
+    let mut i = 0;
+    while i < schemes.len() {
+        registry |= 1 << schemes[i] as u8;
+        i += 1;
+    }
+    registry
+}
+
+/// What an approval by a seat with an invalid key does. A seat's key is only
+/// used when it signed, so only those are rejected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum KeyPolicy {
+    /// The block doesn't sync.
+    Reject = 0,
+    /// The seat counts as not having approved.
+    #[default]
+    Inactive = 1,
+}
+
+impl KeyPolicy {
+    /// The policy a circuit's `KEY_POLICY` parameter stands for, const
+    /// generics can't be enums.
+    pub const fn from_u8(policy: u8) -> Self {
+        match policy {
+            0 => Self::Reject,
+            1 => Self::Inactive,
+            _ => panic!("unknown key policy"),
+        }
+    }
+}
+
+/// Whether `pk` can be used to verify, keys of other schemes are left to
+/// `verify`.
+pub fn is_valid_key(pk: &PublicKey) -> bool {
+    Ed25519::public_key(pk).map_or(true, |pk| Ed25519::is_valid_key(&pk))
+}
+
+/// Whether `sig` is a valid signature of `msg` by `pk` in a supported scheme.
+pub fn verify(sig: &Signature, msg: &[u8], pk: &PublicKey) -> bool {
+    let scheme = Scheme::from(sig.key_type());
+    if !scheme.is_supported() {
+        log::debug!("{} signed with unsupported scheme {:?}", pk, scheme);
+        return false;
+    }
+    sig.verify(msg, pk)
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        assert_eq!(SCHEME_REGISTRY, 0b01);
+        assert_eq!(registry(&[Scheme::Ed25519, Scheme::Secp256k1]), 0b11);
+        assert_eq!(registry(&[]), 0);
+    }
+
+    #[test]
+    fn test_key_policy_from_u8() {
+        for policy in [KeyPolicy::Reject, KeyPolicy::Inactive] {
+            assert_eq!(KeyPolicy::from_u8(policy as u8), policy);
+        }
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let key = SecretKey::from_seed(KeyType::ED25519, "test");
+        let sig = key.sign(b"msg");
+        let pk = Ed25519::public_key(&key.public_key()).unwrap();
+        assert_eq!(pk, key.public_key().key_data());
+        let (r, s) = Ed25519::signature(&sig).unwrap();
+        match &sig {
+            Signature::ED25519(sig) => assert_eq!([r, s].concat(), sig.to_bytes()),
+            _ => unreachable!(),
+        }
+        assert!(verify(&sig, b"msg", &key.public_key()));
+        assert!(!verify(&sig, b"other", &key.public_key()));
+    }
+
+    #[test]
+    fn test_unsupported_schemes_never_verify() {
+        let key = SecretKey::from_seed(KeyType::SECP256K1, "test");
+        let sig = key.sign(b"msg");
+        // Valid, but the circuits can't check it
+        assert!(sig.verify(b"msg", &key.public_key()));
+        assert!(!verify(&sig, b"msg", &key.public_key()));
+        assert!(Ed25519::public_key(&key.public_key()).is_none());
+        assert!(Ed25519::signature(&sig).is_none());
+        assert!(is_valid_key(&key.public_key()));
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let key = SecretKey::from_seed(KeyType::ED25519, "test");
+        assert!(is_valid_key(&key.public_key()));
+
+        // p itself, which decompresses as y = 0
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert!(!Ed25519::is_valid_key(&p));
+        assert!(Ed25519::is_valid_key(&[0; 32]));
+
+        // About half of the y coordinates aren't on the curve
+        assert!((0..=u8::MAX).any(|y| !Ed25519::is_valid_key(&[y; 32])));
+    }
+}
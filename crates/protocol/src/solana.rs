@@ -0,0 +1,181 @@
+//! Encoding the proven head and verify results for an Anchor program on
+//! Solana.
+//!
+//! Everything is borsh, so little endian, and hashes are kept as 32 byte
+//! arrays, the size of a `Pubkey`, so the program can use them as seeds
+//! without copying. Instruction data is prefixed with Anchor's discriminator.
+use crate::prelude::*;
+
+/// Anchor's discriminators are the first 8 bytes of this hashed with the
+/// instruction name.
+const INSTRUCTION_NAMESPACE: &str = "global";
+
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+/// Borsh's length prefix of a `Vec`.
+const VEC_PREFIX_LEN: usize = 4;
+
+/// A synced head, as the program stores it.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct ProvenHead {
+    pub domain: [u8; 32],
+    pub hash: [u8; 32],
+    pub height: u64,
+    pub epoch_id: [u8; 32],
+    pub next_epoch_id: [u8; 32],
+    pub block_merkle_root: [u8; 32],
+}
+
+impl ProvenHead {
+    pub fn new(domain: &CryptoHash, head: &Header) -> Self {
+        Self {
+            domain: domain.0,
+            hash: head.hash().0,
+            height: head.inner_lite.height,
+            epoch_id: head.inner_lite.epoch_id.0,
+            next_epoch_id: head.inner_lite.next_epoch_id.0,
+            block_merkle_root: head.inner_lite.block_merkle_root.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct VerifyResult {
+    pub id: [u8; 32],
+    pub passed: bool,
+}
+
+impl VerifyResult {
+    const LEN: usize = 32 + 1;
+}
+
+/// Verify results against the head with `block_merkle_root`.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct VerifyResults {
+    pub domain: [u8; 32],
+    pub block_merkle_root: [u8; 32],
+    pub results: Vec<VerifyResult>,
+}
+
+impl VerifyResults {
+    const FIXED_LEN: usize = 32 + 32 + VEC_PREFIX_LEN;
+
+    /// Placeholder slots, with a default id, are dropped. Transactions on
+    /// Solana are small enough that they matter.
+    pub fn new(
+        domain: &CryptoHash,
+        block_merkle_root: &CryptoHash,
+        results: &[(CryptoHash, bool)],
+    ) -> Self {
+        Self {
+            domain: domain.0,
+            block_merkle_root: block_merkle_root.0,
+            results: results
+                .iter()
+                .filter(|(id, _)| *id != CryptoHash::default())
+                .map(|(id, passed)| VerifyResult {
+                    id: id.0,
+                    passed: *passed,
+                })
+                .collect(),
+        }
+    }
+
+    /// Split into batches whose instruction data fits in `max_len` bytes.
+    pub fn chunks(&self, max_len: usize) -> Vec<Self> {
+        let per_chunk = max_len
+            .saturating_sub(DISCRIMINATOR_LEN + Self::FIXED_LEN)
+            .checked_div(VerifyResult::LEN)
+            .filter(|n| *n > 0)
+            .expect("max_len fits at least one result");
+        self.results
+            .chunks(per_chunk)
+            .map(|results| Self {
+                results: results.to_vec(),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// The discriminator Anchor dispatches instruction `name` with.
+pub fn discriminator(name: &str) -> [u8; DISCRIMINATOR_LEN] {
+    let preimage = format!("{}:{}", INSTRUCTION_NAMESPACE, name);
+    CryptoHash::hash_bytes(preimage.as_bytes()).0[..DISCRIMINATOR_LEN]
+        .try_into()
+        .expect("hash is longer than the discriminator")
+}
+
+/// The data of an Anchor instruction `name` taking `args`.
+pub fn instruction_data(name: &str, args: &impl BorshSerialize) -> Vec<u8> {
+    let mut data = discriminator(name).to_vec();
+    args.serialize(&mut data)
+        .expect("writing to a vec can't fail");
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discriminator() {
+        // As generated by anchor for `initialize`
+        assert_eq!(
+            discriminator("initialize"),
+            [175, 175, 109, 31, 13, 152, 155, 237]
+        );
+    }
+
+    #[test]
+    fn test_head_layout() {
+        let head = test_utils::test_state().0;
+        let domain = CryptoHash::hash_bytes(b"domain");
+        let data = instruction_data("sync", &ProvenHead::new(&domain, &head));
+
+        assert_eq!(data.len(), DISCRIMINATOR_LEN + 32 * 5 + 8);
+        assert_eq!(&data[8..40], &domain.0);
+        assert_eq!(&data[40..72], &head.hash().0);
+        assert_eq!(
+            &data[72..80],
+            &head.inner_lite.height.to_le_bytes(),
+            "heights are little endian"
+        );
+        let decoded = ProvenHead::try_from_slice(&data[DISCRIMINATOR_LEN..]).unwrap();
+        assert_eq!(decoded, ProvenHead::new(&domain, &head));
+    }
+
+    #[test]
+    fn test_verify_results_drop_placeholders_and_chunk() {
+        let domain = CryptoHash::hash_bytes(b"domain");
+        let root = CryptoHash::hash_bytes(b"root");
+        let mut results = (0..10u8)
+            .map(|i| (CryptoHash::hash_bytes(&[i]), i % 2 == 0))
+            .collect_vec();
+        results.resize(64, Default::default());
+
+        let verify = VerifyResults::new(&domain, &root, &results);
+        assert_eq!(verify.results.len(), 10);
+        let data = instruction_data("verify", &verify);
+        assert_eq!(
+            data.len(),
+            DISCRIMINATOR_LEN + VerifyResults::FIXED_LEN + 10 * VerifyResult::LEN
+        );
+        assert_eq!(&data[72..76], &10u32.to_le_bytes());
+
+        let max_len = DISCRIMINATOR_LEN + VerifyResults::FIXED_LEN + 4 * VerifyResult::LEN;
+        let chunks = verify.chunks(max_len);
+        assert_eq!(
+            chunks.iter().map(|c| c.results.len()).collect_vec(),
+            [4, 4, 2]
+        );
+        for chunk in &chunks {
+            assert!(instruction_data("verify", chunk).len() <= max_len);
+            assert_eq!(chunk.block_merkle_root, root.0);
+        }
+        assert_eq!(
+            chunks.into_iter().flat_map(|c| c.results).collect_vec(),
+            verify.results
+        );
+    }
+}
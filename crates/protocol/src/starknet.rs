@@ -0,0 +1,192 @@
+//! Encoding the public outputs for Cairo verifiers on Starknet.
+//!
+//! Cairo reads calldata as an array of felts, field elements below the Stark
+//! prime, so a 32 byte hash doesn't fit in one. Hashes are encoded as `u256`,
+//! which Cairo's `Serde` expects as two felts, the low 128 bits first. Arrays
+//! are prefixed with their length and booleans are a `0` or `1` felt.
+//!
+//! Rather than pass every output, contracts can take the poseidon commitment
+//! to them, see [`commitment`].
+use std::fmt;
+
+use starknet_crypto::{poseidon_hash_many, FieldElement};
+
+use crate::prelude::*;
+
+/// A felt, big endian. Everything we encode is at most 128 bits, so it is
+/// always below the Stark prime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Felt([u8; 32]);
+
+impl Felt {
+    pub fn to_bytes_be(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl From<u128> for Felt {
+    fn from(value: u128) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[16..].copy_from_slice(&value.to_be_bytes());
+        Self(bytes)
+    }
+}
+
+impl From<u64> for Felt {
+    fn from(value: u64) -> Self {
+        Self::from(value as u128)
+    }
+}
+
+impl From<bool> for Felt {
+    fn from(value: bool) -> Self {
+        Self::from(value as u128)
+    }
+}
+
+/// The hex Starknet tooling takes calldata in, without leading zeros.
+impl fmt::Display for Felt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = hex::encode(self.0);
+        match hex.trim_start_matches('0') {
+            "" => write!(f, "0x0"),
+            digits => write!(f, "0x{}", digits),
+        }
+    }
+}
+
+impl From<Felt> for FieldElement {
+    fn from(felt: Felt) -> Self {
+        FieldElement::from_bytes_be(&felt.0).expect("felts are at most 128 bits")
+    }
+}
+
+/// A hash as a Cairo `u256`, reading it as a big endian integer like the EVM
+/// does, so both chains agree on the value.
+pub fn u256(hash: &CryptoHash) -> [Felt; 2] {
+    let (high, low) = hash.0.split_at(16);
+    let felt = |half: &[u8]| Felt::from(u128::from_be_bytes(half.try_into().expect("16 bytes")));
+    [felt(low), felt(high)]
+}
+
+/// Builds the felts of an output in `Serde` order.
+#[derive(Debug, Default)]
+pub struct Encoder(Vec<Felt>);
+
+impl Encoder {
+    pub fn felt(&mut self, felt: impl Into<Felt>) -> &mut Self {
+        self.0.push(felt.into());
+        self
+    }
+
+    pub fn hash(&mut self, hash: &CryptoHash) -> &mut Self {
+        self.0.extend(u256(hash));
+        self
+    }
+
+    /// The length prefix of an `Array`.
+    pub fn array_len(&mut self, len: usize) -> &mut Self {
+        self.felt(len as u64)
+    }
+
+    pub fn finish(&mut self) -> Vec<Felt> {
+        std::mem::take(&mut self.0)
+    }
+}
+
+/// The outputs of `SyncCircuit`.
+pub fn encode_sync(domain: &CryptoHash, new_head_hash: &CryptoHash) -> Vec<Felt> {
+    Encoder::default().hash(domain).hash(new_head_hash).finish()
+}
+
+/// The outputs of `RollingSyncCircuit`, the same as a sync's.
+pub fn encode_rolling_sync(domain: &CryptoHash, new_head_hash: &CryptoHash) -> Vec<Felt> {
+    encode_sync(domain, new_head_hash)
+}
+
+/// The outputs of `VerifyCircuit`, as an `Array<(u256, bool)>` after the
+/// domain. Placeholder slots are kept so the layout matches the ABI outputs.
+pub fn encode_verify(domain: &CryptoHash, results: &[(CryptoHash, bool)]) -> Vec<Felt> {
+    let mut encoder = Encoder::default();
+    encoder.hash(domain).array_len(results.len());
+    for (id, passed) in results {
+        encoder.hash(id).felt(*passed);
+    }
+    encoder.finish()
+}
+
+/// The poseidon hash of the felts, what `poseidon_hash_span` computes in
+/// Cairo.
+pub fn commitment(felts: &[Felt]) -> Felt {
+    let elements = felts.iter().copied().map(FieldElement::from).collect_vec();
+    Felt(poseidon_hash_many(&elements).to_bytes_be())
+}
+
+/// Calldata as the hex strings `starkli` and starknet.js take.
+pub fn calldata(felts: &[Felt]) -> Vec<String> {
+    felts.iter().map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u256_is_low_then_high() {
+        let mut bytes = [0u8; 32];
+        bytes[15] = 1;
+        bytes[31] = 2;
+        assert_eq!(
+            u256(&CryptoHash(bytes)),
+            [Felt::from(2u128), Felt::from(1u128)]
+        );
+        assert_eq!(
+            u256(&CryptoHash([0xff; 32])),
+            [Felt::from(u128::MAX), Felt::from(u128::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_layouts() {
+        let domain = CryptoHash::hash_bytes(b"domain");
+        let head = CryptoHash::hash_bytes(b"head");
+        assert_eq!(encode_sync(&domain, &head).len(), 4);
+        assert_eq!(encode_rolling_sync(&domain, &head).len(), 4);
+
+        let results = [(head, true), (CryptoHash::default(), false)];
+        let felts = encode_verify(&domain, &results);
+        assert_eq!(felts.len(), 2 + 1 + 3 * 2);
+        assert_eq!(felts[2], Felt::from(2u64));
+        assert_eq!(&felts[3..5], &u256(&head));
+        assert_eq!(felts[5], Felt::from(true));
+        assert_eq!(felts[8], Felt::from(false));
+    }
+
+    #[test]
+    fn test_calldata() {
+        let felts = [Felt::default(), Felt::from(true), Felt::from(0xabcu64)];
+        assert_eq!(calldata(&felts), ["0x0", "0x1", "0xabc"]);
+    }
+
+    #[test]
+    fn test_commitment_binds_every_felt() {
+        let domain = CryptoHash::hash_bytes(b"domain");
+        let results = (0..4u8)
+            .map(|i| (CryptoHash::hash_bytes(&[i]), i % 2 == 0))
+            .collect_vec();
+        let felts = encode_verify(&domain, &results);
+        let expected = commitment(&felts);
+        assert_eq!(commitment(&felts), expected);
+        for i in 0..felts.len() {
+            let mut changed = felts.clone();
+            changed[i] =
+                Felt::from(u128::from_be_bytes(changed[i].0[16..].try_into().unwrap()) ^ 1);
+            assert_ne!(
+                commitment(&changed),
+                expected,
+                "felt {} isn't committed to",
+                i
+            );
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Proofs of contract state against a header's `prev_state_root`.
+//!
+//! A header commits to the state root of each shard before its chunks are
+//! applied, `prev_state_root` is the merkle root over them in shard order. So
+//! the state as of the header's previous block is proven in two steps: a
+//! merkle path from the shard's state root to `prev_state_root`, then the trie
+//! nodes from that root down to the value, as `view_state` records them with
+//! `include_proof`.
+//!
+//! Only proofs that a key holds a value are supported, not that it is absent.
+use std::collections::HashMap;
+
+use near_primitives::merkle::merklize;
+use nearx_error::bail;
+
+use crate::{
+    config::ACCOUNT_DATA_SEPARATOR,
+    merkle_util::{compute_root_from_path_and_item, MerklePath},
+    prelude::*,
+};
+
+/// nearcore's `col::CONTRACT_DATA`, the first byte of a contract's keys.
+pub const CONTRACT_DATA: u8 = 9;
+
+/// The trie key of a contract's storage key, `TrieKey::ContractData`.
+pub fn contract_data_key(account_id: &AccountId, key: &[u8]) -> Vec<u8> {
+    let mut trie_key = vec![CONTRACT_DATA];
+    trie_key.extend_from_slice(account_id.as_str().as_bytes());
+    trie_key.push(ACCOUNT_DATA_SEPARATOR);
+    trie_key.extend_from_slice(key);
+    trie_key
+}
+
+/// The trie is keyed by nibbles, high first.
+pub fn nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+/// Where a value is stored, by the hash of its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueRef {
+    pub length: u32,
+    pub hash: CryptoHash,
+}
+
+/// A trie node as nearcore encodes it, `RawTrieNodeWithSize`, without the
+/// memory usage that follows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrieNode {
+    /// The rest of the key, in nibbles, and the value.
+    Leaf { key: Vec<u8>, value: ValueRef },
+    /// A child for each nibble, and the value of the key ending here.
+    Branch {
+        value: Option<ValueRef>,
+        children: [Option<CryptoHash>; 16],
+    },
+    /// A part of the key, in nibbles, shared by everything under the child.
+    Extension { key: Vec<u8>, child: CryptoHash },
+}
+
+impl TrieNode {
+    pub const LEAF: u8 = 0;
+    pub const BRANCH: u8 = 1;
+    pub const BRANCH_WITH_VALUE: u8 = 2;
+    pub const EXTENSION: u8 = 3;
+
+    /// The flags in the high nibble of an encoded key's first byte.
+    const ODD: u8 = 0x10;
+    const IS_LEAF: u8 = 0x20;
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = NodeReader(bytes);
+        let node = match reader.u8()? {
+            Self::LEAF => Self::Leaf {
+                key: reader.key(true)?,
+                value: reader.value_ref()?,
+            },
+            Self::BRANCH => Self::Branch {
+                value: None,
+                children: reader.children()?,
+            },
+            Self::BRANCH_WITH_VALUE => Self::Branch {
+                value: Some(reader.value_ref()?),
+                children: reader.children()?,
+            },
+            Self::EXTENSION => Self::Extension {
+                key: reader.key(false)?,
+                child: reader.hash()?,
+            },
+            tag => bail!(Protocol, "Unknown trie node {}", tag),
+        };
+        // The memory usage
+        reader.bytes(8)?;
+        if !reader.0.is_empty() {
+            bail!(Protocol, "Trie node has {} trailing bytes", reader.0.len());
+        }
+        Ok(node)
+    }
+
+    pub fn encode(&self, memory_usage: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            Self::Leaf { key, value } => {
+                bytes.push(Self::LEAF);
+                encode_key(&mut bytes, key, true);
+                encode_value_ref(&mut bytes, value);
+            }
+            Self::Branch { value, children } => {
+                match value {
+                    None => bytes.push(Self::BRANCH),
+                    Some(value) => {
+                        bytes.push(Self::BRANCH_WITH_VALUE);
+                        encode_value_ref(&mut bytes, value);
+                    }
+                }
+                let bitmap = children
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.is_some())
+                    .fold(0u16, |bitmap, (i, _)| bitmap | 1 << i);
+                bytes.extend_from_slice(&bitmap.to_le_bytes());
+                for child in children.iter().flatten() {
+                    bytes.extend_from_slice(&child.0);
+                }
+            }
+            Self::Extension { key, child } => {
+                bytes.push(Self::EXTENSION);
+                encode_key(&mut bytes, key, false);
+                bytes.extend_from_slice(&child.0);
+            }
+        }
+        bytes.extend_from_slice(&memory_usage.to_le_bytes());
+        bytes
+    }
+}
+
+fn encode_key(bytes: &mut Vec<u8>, nibbles: &[u8], is_leaf: bool) {
+    let flags = if is_leaf { TrieNode::IS_LEAF } else { 0 };
+    let mut encoded = match nibbles.len() % 2 {
+        1 => vec![flags | TrieNode::ODD | nibbles[0]],
+        _ => vec![flags],
+    };
+    encoded.extend(
+        nibbles[nibbles.len() % 2..]
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1]),
+    );
+    bytes.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    bytes.extend(encoded);
+}
+
+fn encode_value_ref(bytes: &mut Vec<u8>, value: &ValueRef) {
+    bytes.extend_from_slice(&value.length.to_le_bytes());
+    bytes.extend_from_slice(&value.hash.0);
+}
+
+struct NodeReader<'a>(&'a [u8]);
+
+impl<'a> NodeReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!(Protocol, "Trie node ends early");
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(
+            self.bytes(4)?.try_into().expect("length checked"),
+        ))
+    }
+
+    fn hash(&mut self) -> Result<CryptoHash> {
+        Ok(CryptoHash(
+            self.bytes(32)?.try_into().expect("length checked"),
+        ))
+    }
+
+    fn value_ref(&mut self) -> Result<ValueRef> {
+        Ok(ValueRef {
+            length: self.u32()?,
+            hash: self.hash()?,
+        })
+    }
+
+    fn key(&mut self, is_leaf: bool) -> Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        let encoded = self.bytes(len)?;
+        let Some((first, rest)) = encoded.split_first() else {
+            bail!(Protocol, "Trie node has an empty key");
+        };
+        if (first & TrieNode::IS_LEAF != 0) != is_leaf {
+            bail!(Protocol, "Trie node key is flagged for the wrong node");
+        }
+        let mut key = match first & TrieNode::ODD {
+            0 => vec![],
+            _ => vec![first & 0x0f],
+        };
+        key.extend(nibbles(rest));
+        Ok(key)
+    }
+
+    fn children(&mut self) -> Result<[Option<CryptoHash>; 16]> {
+        let bitmap = u16::from_le_bytes(self.bytes(2)?.try_into().expect("length checked"));
+        let mut children = [None; 16];
+        for (i, child) in children.iter_mut().enumerate() {
+            if bitmap & 1 << i != 0 {
+                *child = Some(self.hash()?);
+            }
+        }
+        Ok(children)
+    }
+}
+
+/// Walk from `root` down to the value of `trie_key`, `node` giving the
+/// encoding of each node by its position in the path and its hash. Returns
+/// the encoded nodes on the path and where the value is.
+fn walk<'a>(
+    root: &CryptoHash,
+    trie_key: &[u8],
+    mut node: impl FnMut(usize, &CryptoHash) -> Result<&'a [u8]>,
+) -> Result<(Vec<&'a [u8]>, ValueRef)> {
+    let key = nibbles(trie_key);
+    let mut cursor = 0;
+    let mut expected = *root;
+    let mut path = vec![];
+    loop {
+        let bytes = node(path.len(), &expected)?;
+        if CryptoHash::hash_bytes(bytes) != expected {
+            bail!(
+                Protocol,
+                "Trie node {} doesn't hash to {}",
+                path.len(),
+                expected
+            );
+        }
+        path.push(bytes);
+
+        let rest = &key[cursor..];
+        match TrieNode::decode(bytes)? {
+            TrieNode::Leaf { key, value } => {
+                if key != rest {
+                    bail!(Protocol, "The key is not in the trie");
+                }
+                return Ok((path, value));
+            }
+            TrieNode::Branch { value, children } => match rest.first() {
+                None => {
+                    let value = value.ok_or_else(|| err!(Protocol, "The key has no value"))?;
+                    return Ok((path, value));
+                }
+                Some(nibble) => {
+                    expected = children[*nibble as usize]
+                        .ok_or_else(|| err!(Protocol, "The key is not in the trie"))?;
+                    cursor += 1;
+                }
+            },
+            TrieNode::Extension { key, child } => {
+                if !rest.starts_with(&key) {
+                    bail!(Protocol, "The key is not in the trie");
+                }
+                cursor += key.len();
+                expected = child;
+            }
+        }
+    }
+}
+
+/// A proof of the value of a trie key, usually a contract's storage key, see
+/// `contract_data_key`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    /// The state root of the shard holding the key.
+    pub shard_root: CryptoHash,
+    /// From the shard root to `prev_state_root`.
+    pub shard_proof: MerklePath,
+    /// The encoded trie nodes from the shard root down to the value.
+    pub nodes: Vec<Vec<u8>>,
+    pub value: Vec<u8>,
+}
+
+impl StateProof {
+    /// Pick the path to `trie_key` out of the nodes `view_state` recorded,
+    /// which are in no particular order and cover the other keys it returned
+    /// too. `state_roots` are the header's chunks' `prev_state_root`s, in
+    /// shard order.
+    pub fn new(
+        state_roots: &[CryptoHash],
+        trie_key: &[u8],
+        value: Vec<u8>,
+        recorded: &[impl AsRef<[u8]>],
+    ) -> Result<Self> {
+        let by_hash: HashMap<CryptoHash, &[u8]> = recorded
+            .iter()
+            .map(|node| (CryptoHash::hash_bytes(node.as_ref()), node.as_ref()))
+            .collect();
+        let shard = state_roots
+            .iter()
+            .position(|root| by_hash.contains_key(root))
+            .ok_or_else(|| err!(Protocol, "None of the shard roots are recorded"))?;
+        let shard_root = state_roots[shard];
+
+        let (nodes, _) = walk(&shard_root, trie_key, |_, hash| {
+            by_hash
+                .get(hash)
+                .copied()
+                .ok_or_else(|| err!(Protocol, "Trie node {} was not recorded", hash))
+        })?;
+        Ok(Self {
+            shard_root,
+            shard_proof: merklize(state_roots).1.swap_remove(shard),
+            nodes: nodes.into_iter().map(<[u8]>::to_vec).collect(),
+            value,
+        })
+    }
+
+    /// Check the proof for `trie_key` against a header's `prev_state_root`.
+    pub fn verify(&self, prev_state_root: &CryptoHash, trie_key: &[u8]) -> Result<()> {
+        let root = compute_root_from_path_and_item(self.shard_proof.iter(), self.shard_root);
+        if &root != prev_state_root {
+            bail!(Protocol, "The shard root is not in {}", prev_state_root);
+        }
+
+        let (path, value) = walk(&self.shard_root, trie_key, |i, _| {
+            self.nodes
+                .get(i)
+                .map(Vec::as_slice)
+                .ok_or_else(|| err!(Protocol, "The proof ends before the value"))
+        })?;
+        if path.len() != self.nodes.len() {
+            bail!(
+                Protocol,
+                "The proof has {} nodes past the value",
+                self.nodes.len() - path.len()
+            );
+        }
+        if value.length as usize != self.value.len()
+            || value.hash != CryptoHash::hash_bytes(&self.value)
+        {
+            bail!(Protocol, "The value doesn't match the trie");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_ref(value: &[u8]) -> ValueRef {
+        ValueRef {
+            length: value.len() as u32,
+            hash: CryptoHash::hash_bytes(value),
+        }
+    }
+
+    /// A trie holding the keys `a` and `q` of a contract, which differ in
+    /// their last high nibble, and the key ending at the branch between them.
+    fn trie(a: &[u8], q: &[u8]) -> (CryptoHash, Vec<Vec<u8>>, Vec<u8>, Vec<u8>) {
+        let account: AccountId = "counter.testnet".parse().unwrap();
+        let key_a = contract_data_key(&account, b"a");
+        let key_q = contract_data_key(&account, b"q");
+        let prefix = nibbles(&key_a);
+        let shared = prefix.len() - 2;
+        assert_eq!(nibbles(&key_q)[..shared], prefix[..shared]);
+
+        // Both end in the nibble 1
+        let leaf = |value: &[u8]| TrieNode::Leaf {
+            key: vec![1],
+            value: value_ref(value),
+        };
+        let leaf_a = leaf(a).encode(100);
+        let leaf_q = leaf(q).encode(100);
+        let mut children = [None; 16];
+        children[nibbles(b"a")[0] as usize] = Some(CryptoHash::hash_bytes(&leaf_a));
+        children[nibbles(b"q")[0] as usize] = Some(CryptoHash::hash_bytes(&leaf_q));
+        let branch = TrieNode::Branch {
+            value: Some(value_ref(b"prefix")),
+            children,
+        }
+        .encode(300);
+        let extension = TrieNode::Extension {
+            key: prefix[..shared].to_vec(),
+            child: CryptoHash::hash_bytes(&branch),
+        }
+        .encode(400);
+
+        let root = CryptoHash::hash_bytes(&extension);
+        (root, vec![leaf_q, branch, extension, leaf_a], key_a, key_q)
+    }
+
+    #[test]
+    fn test_nodes_roundtrip() {
+        let (_, recorded, ..) = trie(b"1", b"2");
+        for node in &recorded {
+            assert_eq!(
+                &TrieNode::decode(node).unwrap().encode(0)[..node.len() - 8],
+                &node[..node.len() - 8]
+            );
+        }
+        let odd = TrieNode::Extension {
+            key: vec![1, 2, 3],
+            child: CryptoHash::default(),
+        };
+        assert_eq!(TrieNode::decode(&odd.encode(1)).unwrap(), odd);
+        assert!(TrieNode::decode(&recorded[0][..recorded[0].len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_state_proof() {
+        let (root, recorded, key_a, key_q) = trie(b"1", b"2");
+        let other_shard = CryptoHash::hash_bytes(b"other shard");
+        let roots = [other_shard, root];
+        let prev_state_root = merklize(&roots).0;
+
+        let proof = StateProof::new(&roots, &key_a, b"1".to_vec(), &recorded).unwrap();
+        assert_eq!(proof.shard_root, root);
+        assert_eq!(
+            proof.nodes,
+            vec![
+                recorded[2].clone(),
+                recorded[1].clone(),
+                recorded[3].clone()
+            ]
+        );
+        proof.verify(&prev_state_root, &key_a).unwrap();
+
+        // Another key, value or root
+        assert!(proof.verify(&prev_state_root, &key_q).is_err());
+        let wrong_value = StateProof {
+            value: b"2".to_vec(),
+            ..proof.clone()
+        };
+        assert!(wrong_value.verify(&prev_state_root, &key_a).is_err());
+        assert!(proof.verify(&other_shard, &key_a).is_err());
+
+        // The value of a key ending at a branch
+        let prefix = &key_a[..key_a.len() - 1];
+        let at_branch = StateProof::new(&roots, prefix, b"prefix".to_vec(), &recorded).unwrap();
+        assert_eq!(at_branch.nodes.len(), 2);
+        at_branch.verify(&prev_state_root, prefix).unwrap();
+
+        // Trailing nodes aren't ignored
+        let mut trailing = proof.clone();
+        trailing.nodes.push(recorded[0].clone());
+        assert!(trailing.verify(&prev_state_root, &key_a).is_err());
+    }
+}
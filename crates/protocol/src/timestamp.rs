@@ -0,0 +1,95 @@
+//! Block timestamps.
+//!
+//! Views carry the timestamp twice. `timestamp_nanosec` is what the header
+//! hash commits to, `timestamp` is a legacy field kept for older clients which
+//! can be zeroed or, from a dishonest RPC, differ without changing the hash.
+use near_primitives::views::BlockHeaderInnerLiteView;
+
+use crate::prelude::*;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Nanoseconds since the unix epoch.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self(nanos)
+    }
+
+    pub const fn as_nanos(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_millis(&self) -> u64 {
+        self.0 / NANOS_PER_MILLI
+    }
+
+    pub const fn as_secs(&self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
+}
+
+impl From<&BlockHeaderInnerLiteView> for Timestamp {
+    /// The timestamp the header hash commits to, the legacy field is never
+    /// used.
+    fn from(inner: &BlockHeaderInnerLiteView) -> Self {
+        Self(inner.timestamp_nanosec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let t = Timestamp::from_nanos(1_697_116_223_277_387_356);
+        assert_eq!(t.as_nanos(), 1_697_116_223_277_387_356);
+        assert_eq!(t.as_millis(), 1_697_116_223_277);
+        assert_eq!(t.as_secs(), 1_697_116_223);
+    }
+
+    #[test]
+    fn test_prefers_the_hashed_field() {
+        let header = test_utils::test_state().0;
+        let expected = Timestamp::from_nanos(header.inner_lite.timestamp_nanosec);
+        assert_eq!(Timestamp::from(&header.inner_lite), expected);
+
+        // The legacy field isn't hashed, so it can't be trusted
+        let mut legacy_differs = header.clone();
+        legacy_differs.inner_lite.timestamp += 1;
+        assert_eq!(legacy_differs.hash(), header.hash());
+        assert_eq!(Timestamp::from(&legacy_differs.inner_lite), expected);
+
+        let mut legacy_zeroed = header.clone();
+        legacy_zeroed.inner_lite.timestamp = 0;
+        assert_eq!(Timestamp::from(&legacy_zeroed.inner_lite), expected);
+    }
+
+    #[test]
+    fn test_never_falls_back_to_legacy() {
+        // Even when zero, as the hash commits to it
+        let header = test_utils::test_state().0;
+        let mut zeroed = header.clone();
+        zeroed.inner_lite.timestamp_nanosec = 0;
+        assert_ne!(zeroed.hash(), header.hash());
+        assert_eq!(Timestamp::from(&zeroed.inner_lite), Timestamp::default());
+    }
+}
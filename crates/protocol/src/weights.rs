@@ -0,0 +1,106 @@
+//! Weighting block producers in the stake threshold.
+//!
+//! Public networks weight approvals by stake. Private deployments may weight
+//! their validators differently, those weights are worked out off-circuit and
+//! the circuits only commit to them through [`params_hash`], so the verifier
+//! has to check it is the deployment's.
+use std::collections::BTreeMap;
+
+use near_primitives::types::Balance;
+
+use crate::{
+    config::{ACCOUNT_DATA_SEPARATOR, NUM_BLOCK_PRODUCER_SEATS},
+    prelude::*,
+    ValidatorStake,
+};
+
+/// A padded account id followed by its weight.
+const SEAT_LEN: usize = AccountId::MAX_LEN + 16;
+
+pub trait StakeWeight {
+    /// The weight of each block producer, in seat order.
+    fn weigh(&self, bps: &[ValidatorStake]) -> Vec<Balance>;
+}
+
+/// What public networks use.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByStake;
+
+impl StakeWeight for ByStake {
+    fn weigh(&self, bps: &[ValidatorStake]) -> Vec<Balance> {
+        bps.iter().map(|vs| vs.stake()).collect()
+    }
+}
+
+/// Fixed weights by account, anyone not in the table has no weight.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Table(pub BTreeMap<AccountId, Balance>);
+
+impl StakeWeight for Table {
+    fn weigh(&self, bps: &[ValidatorStake]) -> Vec<Balance> {
+        bps.iter()
+            .map(|vs| self.0.get(vs.account_id()).copied().unwrap_or_default())
+            .collect()
+    }
+}
+
+/// Commits to the weight of each seat the same way the circuits do. A seat is
+/// its padded account id followed by its weight, big endian, and empty seats
+/// are zeroed.
+pub fn params_hash(bps: &[ValidatorStake], weights: &[Balance]) -> CryptoHash {
+    let mut bytes = Vec::with_capacity(NUM_BLOCK_PRODUCER_SEATS * SEAT_LEN);
+    for i in 0..NUM_BLOCK_PRODUCER_SEATS {
+        match (bps.get(i), weights.get(i)) {
+            (Some(vs), Some(weight)) => {
+                let mut account_id = vs.account_id().as_str().as_bytes().to_vec();
+                account_id.resize(AccountId::MAX_LEN, ACCOUNT_DATA_SEPARATOR);
+                bytes.extend(account_id);
+                bytes.extend(weight.to_be_bytes());
+            }
+            _ => bytes.extend([0u8; SEAT_LEN]),
+        }
+    }
+    CryptoHash::hash_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weights() {
+        let bps = test_utils::test_state().1;
+        assert_eq!(
+            ByStake.weigh(&bps),
+            bps.iter().map(|vs| vs.stake()).collect_vec()
+        );
+
+        let table = Table(BTreeMap::from([(bps[1].account_id().clone(), 7)]));
+        let weights = table.weigh(&bps);
+        assert_eq!(weights.len(), bps.len());
+        assert_eq!(weights[1], 7);
+        assert_eq!(weights.iter().sum::<Balance>(), 7);
+    }
+
+    #[test]
+    fn test_params_hash_binds_seats() {
+        let bps = test_utils::test_state().1;
+        let weights = ByStake.weigh(&bps);
+        let hash = params_hash(&bps, &weights);
+
+        let mut reweighted = weights.clone();
+        reweighted[0] += 1;
+        assert_ne!(params_hash(&bps, &reweighted), hash);
+
+        let mut swapped = bps.clone();
+        swapped.swap(0, 1);
+        assert_ne!(params_hash(&swapped, &ByStake.weigh(&swapped)), hash);
+
+        // Only the seats the circuits have are committed to
+        assert!(bps.len() > NUM_BLOCK_PRODUCER_SEATS);
+        let mut beyond = weights;
+        *beyond.last_mut().unwrap() += 1;
+        assert_eq!(params_hash(&bps, &beyond), hash);
+    }
+}
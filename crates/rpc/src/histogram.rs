@@ -0,0 +1,84 @@
+//! A histogram of durations with fixed buckets, cheap enough to record every
+//! call into.
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::prelude::*;
+
+/// Buckets for RPC calls, in milliseconds.
+pub const LATENCY_BUCKETS_MS: &[u64] =
+    &[10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000];
+
+#[derive(Debug)]
+pub struct Histogram {
+    /// The upper bound of each bucket, the last bucket has none.
+    bounds_ms: &'static [u64],
+    counts: Vec<AtomicU64>,
+    sum_us: AtomicU64,
+}
+
+/// What a histogram has seen so far.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    pub bounds_ms: &'static [u64],
+    /// Observations in each bucket, not including those of the buckets
+    /// before it. One longer than `bounds_ms`.
+    pub counts: Vec<u64>,
+    pub sum_us: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds_ms: &'static [u64]) -> Self {
+        Self {
+            bounds_ms,
+            counts: (0..=bounds_ms.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let ms = duration.as_millis() as u64;
+        let bucket = self.bounds_ms.partition_point(|bound| *bound < ms);
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            bounds_ms: self.bounds_ms,
+            counts: self
+                .counts
+                .iter()
+                .map(|c| c.load(Ordering::Relaxed))
+                .collect(),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let histogram = Histogram::new(&[10, 100]);
+        for ms in [1, 10, 11, 100, 5_000] {
+            histogram.observe(Duration::from_millis(ms));
+        }
+        let snapshot = histogram.snapshot();
+        // Bounds are inclusive
+        assert_eq!(snapshot.counts, vec![2, 2, 1]);
+        assert_eq!(snapshot.count(), 5);
+        assert_eq!(snapshot.sum_us, 5_122_000);
+    }
+}
@@ -5,18 +5,39 @@ use std::{
 
 use async_trait::async_trait;
 use futures::TryFutureExt;
+use limits::{Endpoint, LatencyClass, RpcLimits, Usage};
 use near_jsonrpc_client::{
-    methods::{self, light_client_proof::RpcLightClientExecutionProofResponse},
-    JsonRpcClient,
+    methods::{self, light_client_proof::RpcLightClientExecutionProofResponse, RpcMethod},
+    MethodCallResult,
+};
+use near_jsonrpc_primitives::types::{
+    blocks::RpcBlockError, light_client::RpcLightClientProofError, query::QueryResponseKind,
+};
+use near_light_client_protocol::{
+    config::NetworkParams,
+    state::{contract_data_key, StateProof},
 };
 use near_primitives::{
     block_header::BlockHeader,
-    views::{validator_stake_view::ValidatorStakeView, LightClientBlockView},
+    types::{BlockHeight, BlockId, BlockReference, Finality},
+    views::{
+        validator_stake_view::ValidatorStakeView, BlockView, ChunkView, LightClientBlockView,
+        QueryRequest,
+    },
 };
+use nearx_error::{Context, Error, Layer};
+use retry::{is_retryable, RetryPolicy};
+use tokio::sync::Semaphore;
 
 use crate::prelude::*;
 
+pub mod histogram;
+pub mod limits;
 pub mod prelude;
+pub mod request;
+pub mod retry;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 pub enum Network {
@@ -48,6 +69,13 @@ impl Network {
             _ => "http://`localhost:3030",
         }
     }
+    pub fn params(&self) -> NetworkParams {
+        match self {
+            Self::Mainnet => NetworkParams::MAINNET,
+            // Localnets and statelessnet are configured like testnet
+            _ => NetworkParams::TESTNET,
+        }
+    }
 }
 
 impl From<usize> for Network {
@@ -75,8 +103,10 @@ impl Display for Network {
 
 #[derive(Clone)]
 pub struct NearRpcClient {
-    client: JsonRpcClient,
-    archive: JsonRpcClient,
+    client: Endpoint,
+    history: Endpoint,
+    archive: Endpoint,
+    retry: RetryPolicy,
 }
 
 impl std::fmt::Debug for NearRpcClient {
@@ -87,41 +117,279 @@ impl std::fmt::Debug for NearRpcClient {
 
 impl NearRpcClient {
     pub fn new(network: Network) -> Self {
-        let client = JsonRpcClient::connect(network.to_endpoint());
-        let archive = JsonRpcClient::connect(network.archive_endpoint());
+        Self::with_limits(network, RpcLimits::default())
+    }
+
+    pub fn with_limits(network: Network, limits: RpcLimits) -> Self {
+        let head_timeout = limits.timeouts.of(LatencyClass::Head);
+        let history_timeout = limits.timeouts.of(LatencyClass::History);
+
+        let url = network.to_endpoint();
+        let client = Endpoint::new("rpc", url, limits.rpc, head_timeout);
+        let history = Endpoint::new("history", url, limits.history, history_timeout);
+        let archive = Endpoint::new(
+            "archive",
+            network.archive_endpoint(),
+            limits.archive,
+            history_timeout,
+        );
 
-        NearRpcClient { client, archive }
+        NearRpcClient {
+            client,
+            history,
+            archive,
+            retry: limits.retry,
+        }
+    }
+
+    /// How each endpoint is being used right now.
+    pub fn usage(&self) -> [Usage; 3] {
+        [
+            self.client.usage(),
+            self.history.usage(),
+            self.archive.usage(),
+        ]
+    }
+
+    /// Call the rpc pool for `class`, falling back to the archive. Errors
+    /// that may pass are retried, see `retry`.
+    async fn call<M: RpcMethod>(
+        &self,
+        class: LatencyClass,
+        req: &M,
+    ) -> MethodCallResult<M::Response, M::Error> {
+        let endpoint = match class {
+            LatencyClass::Head => &self.client,
+            LatencyClass::History => &self.history,
+        };
+        let attempt = || {
+            endpoint.call(req).or_else(|e| {
+                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
+                self.archive.call(req)
+            })
+        };
+        self.retry
+            .retry(req.method_name(), attempt, is_retryable)
+            .await
     }
+
     pub async fn batch_fetch_proofs(
         &self,
         last_verified_hash: &CryptoHash,
         reqs: Vec<GetProof>,
-    ) -> HashMap<CryptoHash, Result<BasicProof, anyhow::Error>> {
-        let mut futs = vec![];
-        for req in reqs {
-            futs.push(Box::pin(async {
-                (
-                    match req {
-                        near_primitives::types::TransactionOrReceiptId::Transaction {
-                            transaction_hash,
-                            ..
-                        } => transaction_hash,
-                        near_primitives::types::TransactionOrReceiptId::Receipt {
-                            receipt_id,
-                            ..
-                        } => receipt_id,
-                    },
-                    self.fetch_light_client_proof(req, *last_verified_hash)
-                        .await,
-                )
-            }));
+    ) -> HashMap<CryptoHash, Result<BasicProof>> {
+        // Proofs are history queries, any more in flight would only queue at
+        // the endpoint
+        let max_concurrent = self.history.max_concurrent();
+        fetch_proofs_in_order(self, last_verified_hash, reqs, max_concurrent)
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Check that the RPC can prove `req` against the latest final block,
+    /// so requests that could never be proven are turned away up front.
+    pub async fn check_provable(&self, req: GetProof) -> std::result::Result<(), Unprovable> {
+        let id = proof_id(&req);
+        let head = self
+            .fetch_final_block()
+            .await
+            .map_err(|e| Unprovable::Unavailable(id, format!("{:#}", e)))?
+            .header
+            .hash;
+        let req = methods::light_client_proof::RpcLightClientExecutionProofRequest {
+            id: req,
+            light_client_head: head,
+        };
+        log::debug!("checking proof: {:?}", req);
+        self.call(LatencyClass::History, &req)
+            .await
+            .map(|_| ())
+            .map_err(|e| Unprovable::classify(id, e.handler_error(), &e))
+    }
+
+    pub async fn fetch_block(&self, block_reference: BlockReference) -> Result<BlockView> {
+        let class = match block_reference {
+            BlockReference::Finality(_) => LatencyClass::Head,
+            _ => LatencyClass::History,
+        };
+        let req = methods::block::RpcBlockRequest { block_reference };
+        log::debug!("requesting block: {:?}", req);
+        self.call(class, &req)
+            .await
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching block {:?}", req.block_reference))
+    }
+
+    /// The block at `height`, `None` if no block was produced at that height.
+    /// Any other error, such as a timeout or a rate limit, is returned.
+    pub async fn fetch_block_at(&self, height: BlockHeight) -> Result<Option<BlockView>> {
+        let req = methods::block::RpcBlockRequest {
+            block_reference: BlockReference::BlockId(BlockId::Height(height)),
+        };
+        log::debug!("requesting block: {:?}", req);
+        match self.call(LatencyClass::History, &req).await {
+            Ok(block) => Ok(Some(block)),
+            Err(e) if is_unknown_block(e.handler_error()) => Ok(None),
+            Err(e) => Err(rpc_error(e)).with_context(|| format!("fetching block at {}", height)),
         }
+    }
+
+    /// The header of the block at `height`, failing if no block was produced at
+    /// that height.
+    pub async fn fetch_header_at(&self, height: BlockHeight) -> Result<Header> {
+        self.fetch_block(BlockReference::BlockId(BlockId::Height(height)))
+            .await
+            .map(|x| BlockHeader::from(x.header).into())
+    }
+
+    pub async fn fetch_final_block(&self) -> Result<BlockView> {
+        self.fetch_block(BlockReference::Finality(Finality::Final))
+            .await
+    }
+
+    pub async fn fetch_chunk(&self, chunk_id: &CryptoHash) -> Result<ChunkView> {
+        let req = methods::chunk::RpcChunkRequest {
+            chunk_reference: near_jsonrpc_primitives::types::chunks::ChunkReference::ChunkHash {
+                chunk_id: *chunk_id,
+            },
+        };
+        log::debug!("requesting chunk: {:?}", req);
+        self.call(LatencyClass::History, &req)
+            .await
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching chunk {}", chunk_id))
+    }
 
-        let proofs: Vec<(CryptoHash, Result<BasicProof>)> = futures::future::join_all(futs).await;
-        proofs.into_iter().fold(HashMap::new(), |mut acc, (r, p)| {
-            acc.insert(r, p);
-            acc
-        })
+    /// A proof of a contract's storage key against the `prev_state_root` of
+    /// the block `block_hash`, so of the state as of the block before it.
+    pub async fn fetch_state_proof(
+        &self,
+        block_hash: &CryptoHash,
+        account_id: &AccountId,
+        key: &[u8],
+    ) -> Result<StateProof> {
+        let block = self
+            .fetch_block(BlockReference::BlockId(BlockId::Hash(*block_hash)))
+            .await?;
+        let state_roots = block
+            .chunks
+            .iter()
+            .map(|chunk| chunk.prev_state_root)
+            .collect_vec();
+
+        let req = methods::query::RpcQueryRequest {
+            block_reference: BlockReference::BlockId(BlockId::Hash(block.header.prev_hash)),
+            request: QueryRequest::ViewState {
+                account_id: account_id.clone(),
+                prefix: key.to_vec().into(),
+                include_proof: true,
+            },
+        };
+        log::debug!("requesting state: {:?}", req);
+        let response = self
+            .call(LatencyClass::History, &req)
+            .await
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching the state of {}", account_id))?;
+        let QueryResponseKind::ViewState(state) = response.kind else {
+            return Err(err!(Rpc, "Expected a view state response"));
+        };
+        // The prefix matches any longer keys too
+        let value = state
+            .values
+            .iter()
+            .find(|item| item.key.as_ref() == key)
+            .ok_or_else(|| err!(Rpc, "{} has no value for the key", account_id))?
+            .value
+            .as_ref()
+            .to_vec();
+        StateProof::new(
+            &state_roots,
+            &contract_data_key(account_id, key),
+            value,
+            &state.proof,
+        )
+        .with_context(|| format!("proving the state of {} at {}", account_id, block_hash))
+    }
+}
+
+/// Why the RPC can't prove a transaction or receipt.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Unprovable {
+    #[error("{0} was not found, check the hash and the sender or receiver")]
+    NotFound(CryptoHash),
+    #[error("{0} is not final yet, retry once it is")]
+    NotFinal(CryptoHash),
+    #[error("the RPC could not prove {0}: {1}")]
+    Unavailable(CryptoHash, String),
+}
+
+impl Unprovable {
+    fn classify(
+        id: CryptoHash,
+        error: Option<&RpcLightClientProofError>,
+        cause: &impl std::fmt::Debug,
+    ) -> Self {
+        match error {
+            Some(RpcLightClientProofError::UnknownTransactionOrReceipt { .. }) => {
+                Self::NotFound(id)
+            }
+            Some(RpcLightClientProofError::NotConfirmed { .. }) => Self::NotFinal(id),
+            _ => Self::Unavailable(id, format!("{:?}", cause)),
+        }
+    }
+}
+
+impl From<Unprovable> for Error {
+    fn from(e: Unprovable) -> Self {
+        Self::new(Layer::Rpc, e)
+    }
+}
+
+/// Whether a block wasn't produced, heights are skipped when the producer
+/// misses its slot.
+fn is_unknown_block(error: Option<&RpcBlockError>) -> bool {
+    matches!(error, Some(RpcBlockError::UnknownBlock { .. }))
+}
+
+/// Not every RPC error is an `Error`, but they are all `Debug`.
+fn rpc_error(e: impl std::fmt::Debug) -> Error {
+    Error::msg(Layer::Rpc, format!("{:?}", e))
+}
+
+/// Fetch the proofs of `reqs` with at most `max_concurrent` of them in flight,
+/// returning them in the order they were asked for.
+pub async fn fetch_proofs_in_order(
+    rpc: &(impl LightClientRpc + Sync),
+    latest_verified: &CryptoHash,
+    reqs: Vec<GetProof>,
+    max_concurrent: usize,
+) -> Vec<(CryptoHash, Result<BasicProof>)> {
+    let permits = Semaphore::new(max_concurrent.max(1));
+    let futs = reqs.into_iter().map(|req| {
+        let permits = &permits;
+        async move {
+            let id = proof_id(&req);
+            let _permit = permits
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            (
+                id,
+                rpc.fetch_light_client_proof(req, *latest_verified).await,
+            )
+        }
+    });
+    futures::future::join_all(futs).await
+}
+
+fn proof_id(req: &GetProof) -> CryptoHash {
+    match req {
+        near_primitives::types::TransactionOrReceiptId::Transaction {
+            transaction_hash, ..
+        } => *transaction_hash,
+        near_primitives::types::TransactionOrReceiptId::Receipt { receipt_id, .. } => *receipt_id,
     }
 }
 
@@ -148,14 +416,10 @@ impl LightClientRpc for NearRpcClient {
                 near_primitives::types::BlockId::Hash(*hash),
             ),
         };
-        self.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                self.archive.call(&req)
-            })
+        self.call(LatencyClass::History, &req)
             .await
-            .map_err(|e| anyhow!(e))
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching header {}", hash))
             .map(|x| x.header)
             .map(BlockHeader::from)
             .map(Into::into)
@@ -168,14 +432,10 @@ impl LightClientRpc for NearRpcClient {
             last_block_hash: *latest_verified,
         };
         log::debug!("requesting next block: {:?}", req);
-        self.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                self.archive.call(&req)
-            })
+        self.call(LatencyClass::Head, &req)
             .await
-            .map_err(|e| anyhow::format_err!("{:?}", e))
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching the block after {}", latest_verified))
     }
 
     async fn fetch_light_client_proof(
@@ -188,14 +448,10 @@ impl LightClientRpc for NearRpcClient {
             light_client_head: latest_verified,
         };
         log::debug!("requesting proof: {:?}", req);
-        self.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                self.archive.call(&req)
-            })
+        self.call(LatencyClass::History, &req)
             .await
-            .map_err(|e| anyhow::format_err!("{:?}:{}", req.id, e))
+            .map_err(rpc_error)
+            .with_context(|| format!("fetching the proof of {}", proof_id(&req.id)))
     }
 
     // It's cleaner to get epoch bps based on epoch id
@@ -204,72 +460,36 @@ impl LightClientRpc for NearRpcClient {
             last_block_hash: *epoch_id,
         };
         log::debug!("requesting validators: {:?}", req);
-        self.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                self.archive.call(&req)
-            })
+        self.call(LatencyClass::History, &req)
             .await
-            .map_err(|e| anyhow::format_err!("{:?}", e))
-            .and_then(|x| x.ok_or_else(|| anyhow::format_err!("no block found for {:?}", epoch_id)))
+            .map_err(rpc_error)
+            .and_then(|x| x.ok_or_else(|| err!(Rpc, "no block found for {:?}", epoch_id)))
             .and_then(|x| {
                 x.next_bps
-                    .ok_or_else(|| anyhow::format_err!("no BPS found for {:?}", epoch_id))
+                    .ok_or_else(|| err!(Rpc, "no BPS found for {:?}", epoch_id))
             })
-            .map_err(|e| anyhow::format_err!("{:?}:{}", epoch_id, e))
+            .with_context(|| format!("fetching the validators of {}", epoch_id))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
-
-    use near_primitives::{
-        types::{BlockId, BlockReference, TransactionOrReceiptId},
-        views::{BlockView, ChunkView},
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
     };
 
-    use super::*;
+    use near_primitives::types::{BlockId, TransactionOrReceiptId};
 
-    #[allow(dead_code)]
-    async fn fetch_chunk(c: &NearRpcClient, chunk_id: &CryptoHash) -> Result<ChunkView> {
-        println!("fetching chunk: {:?}", chunk_id);
-        let req = methods::chunk::RpcChunkRequest {
-            chunk_reference: near_jsonrpc_primitives::types::chunks::ChunkReference::ChunkHash {
-                chunk_id: *chunk_id,
-            },
-        };
-        c.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                c.archive.call(&req)
-            })
-            .await
-            .map_err(|e| anyhow::format_err!("{:?}", e))
-    }
-
-    #[allow(dead_code)]
-    async fn fetch_block(c: &NearRpcClient, block_reference: BlockReference) -> Result<BlockView> {
-        println!("fetching block: {:?}", block_reference);
-        let req = methods::block::RpcBlockRequest { block_reference };
-        c.client
-            .call(&req)
-            .or_else(|e| {
-                trace!("Error hitting main rpc, falling back to archive: {:?}", e);
-                c.archive.call(&req)
-            })
-            .await
-            .map_err(|e| anyhow::format_err!("{:?}", e))
-    }
+    use super::*;
 
     #[allow(dead_code)]
     async fn fetch_ids(client: &NearRpcClient, block: &BlockView) -> Vec<TransactionOrReceiptId> {
         let futs = block
             .chunks
             .iter()
-            .map(|c| fetch_chunk(client, &c.chunk_hash).map(|x| x.unwrap()))
+            .map(|c| client.fetch_chunk(&c.chunk_hash).map(|x| x.unwrap()))
             .map(Box::pin)
             .collect_vec();
         let chunks = futures::future::join_all(futs).await;
@@ -294,21 +514,123 @@ mod tests {
         [receipts, txs].concat()
     }
 
+    #[test]
+    fn test_classify_unprovable() {
+        let id = CryptoHash::hash_bytes(b"tx");
+        let classify = |e| Unprovable::classify(id, Some(&e), &"boom");
+        assert_eq!(
+            classify(RpcLightClientProofError::UnknownTransactionOrReceipt {
+                transaction_or_receipt_id: id,
+            }),
+            Unprovable::NotFound(id)
+        );
+        assert_eq!(
+            classify(RpcLightClientProofError::NotConfirmed {
+                transaction_or_receipt_id: id,
+            }),
+            Unprovable::NotFinal(id)
+        );
+        assert!(matches!(
+            Unprovable::classify(id, None, &"boom"),
+            Unprovable::Unavailable(..)
+        ));
+    }
+
+    /// Fails each proof with its id, after a delay that finishes them out of
+    /// order.
+    #[derive(Default)]
+    struct SlowRpc {
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LightClientRpc for SlowRpc {
+        async fn fetch_latest_header(
+            &self,
+            _: &CryptoHash,
+        ) -> Result<Option<LightClientBlockView>> {
+            Err(err!(
+                Rpc,
+                "the batch fetcher only fetches proofs, not latest header"
+            ))
+        }
+
+        async fn fetch_light_client_proof(
+            &self,
+            req: GetProof,
+            _: CryptoHash,
+        ) -> Result<RpcLightClientExecutionProofResponse> {
+            let id = proof_id(&req);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(u64::from(id.0[0] % 8))).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Err(err!(Rpc, "{}", id))
+        }
+
+        async fn fetch_epoch_bps(&self, _: &CryptoHash) -> Result<Vec<ValidatorStakeView>> {
+            Err(err!(
+                Rpc,
+                "the batch fetcher only fetches proofs, not epoch bps"
+            ))
+        }
+
+        async fn fetch_header(&self, _: &CryptoHash) -> Result<Header> {
+            Err(err!(
+                Rpc,
+                "the batch fetcher only fetches proofs, not header"
+            ))
+        }
+    }
+
+    #[test]
+    fn test_unknown_block() {
+        assert!(is_unknown_block(Some(&RpcBlockError::UnknownBlock {
+            error_message: "DB Not Found Error".to_string(),
+        })));
+        assert!(!is_unknown_block(Some(&RpcBlockError::NotSyncedYet)));
+        assert!(!is_unknown_block(Some(&RpcBlockError::InternalError {
+            error_message: "boom".to_string(),
+        })));
+        // A transport error or a 429
+        assert!(!is_unknown_block(None));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_proofs_in_order() {
+        let reqs = (0..16u8)
+            .map(|i| TransactionOrReceiptId::Receipt {
+                receipt_id: CryptoHash::hash_bytes(&[i]),
+                receiver_id: "alice.near".parse().unwrap(),
+            })
+            .collect_vec();
+        let rpc = SlowRpc::default();
+        let proofs = fetch_proofs_in_order(&rpc, &CryptoHash::default(), reqs.clone(), 4).await;
+
+        assert_eq!(
+            proofs.iter().map(|(id, _)| *id).collect_vec(),
+            reqs.iter().map(proof_id).collect_vec()
+        );
+        for (id, proof) in &proofs {
+            assert_eq!(proof.as_ref().unwrap_err().to_string(), id.to_string());
+        }
+        assert_eq!(rpc.most_in_flight.load(Ordering::SeqCst), 4);
+    }
+
     // #[tokio::test]
     // this is committed in the repo, only needed for gathering data
     #[allow(dead_code)]
     async fn test_get_ids() {
         let client = NearRpcClient::new(Network::Testnet);
 
-        let first_block = fetch_block(
-            &client,
-            BlockReference::BlockId(BlockId::Hash(
+        let first_block = client
+            .fetch_block(BlockReference::BlockId(BlockId::Hash(
                 CryptoHash::from_str("6taaeb6h2uJcuUvvmwXpYgagYvyHsFanWhm2ziGGHCff").unwrap(),
-            )),
-        )
-        .await
-        .unwrap()
-        .header;
+            )))
+            .await
+            .unwrap()
+            .header;
 
         let mut ids = vec![];
 
@@ -317,7 +639,8 @@ mod tests {
         let mut block = first_block.prev_hash;
 
         while ids.len() < ids_to_fetch {
-            let next_block = fetch_block(&client, BlockReference::BlockId(BlockId::Hash(block)))
+            let next_block = client
+                .fetch_block(BlockReference::BlockId(BlockId::Hash(block)))
                 .await
                 .unwrap();
             ids.extend(fetch_ids(&client, &next_block).await);
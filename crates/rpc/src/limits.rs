@@ -0,0 +1,271 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use near_jsonrpc_client::{methods::RpcMethod, JsonRpcClient, MethodCallResult};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    time::Instant,
+};
+
+use crate::{
+    histogram::{Histogram, Snapshot, LATENCY_BUCKETS_MS},
+    prelude::*,
+    retry::RetryPolicy,
+};
+
+/// How hard we may hit an RPC provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointLimits {
+    /// Requests in flight at once, any more wait for a slot.
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// The least time between starting requests, 0 to not pace.
+    #[serde(default)]
+    pub min_interval_ms: u64,
+}
+
+impl Default for EndpointLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: default_max_concurrent(),
+            min_interval_ms: 0,
+        }
+    }
+}
+
+fn default_max_concurrent() -> usize {
+    16
+}
+
+/// How long a query may take, which decides where it is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyClass {
+    /// Following the head of the chain, these must stay fast.
+    Head,
+    /// Proofs and lookups by hash, these can be slow and may need an archive.
+    History,
+}
+
+/// How long each class of query may take before we give up on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timeouts {
+    #[serde(default = "default_head_ms")]
+    pub head_ms: u64,
+    #[serde(default = "default_history_ms")]
+    pub history_ms: u64,
+}
+
+impl Timeouts {
+    pub fn of(&self, class: LatencyClass) -> Duration {
+        Duration::from_millis(match class {
+            LatencyClass::Head => self.head_ms,
+            LatencyClass::History => self.history_ms,
+        })
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            head_ms: default_head_ms(),
+            history_ms: default_history_ms(),
+        }
+    }
+}
+
+fn default_head_ms() -> u64 {
+    10_000
+}
+
+fn default_history_ms() -> u64 {
+    120_000
+}
+
+/// Limits for each of the endpoints we talk to. Head and history queries to
+/// the rpc have their own connections and limits, so slow history queries
+/// can't hold up following the head.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcLimits {
+    #[serde(default)]
+    pub rpc: EndpointLimits,
+    #[serde(default)]
+    pub history: EndpointLimits,
+    #[serde(default)]
+    pub archive: EndpointLimits,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// How calls that failed for reasons that may pass are retried.
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+/// How an endpoint is being used right now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub endpoint: &'static str,
+    pub in_flight: usize,
+    pub max_concurrent: usize,
+    pub requests: u64,
+    /// Requests that had to wait for a slot or for pacing.
+    pub throttled: u64,
+    /// How long requests took once they were sent.
+    pub latency: Snapshot,
+}
+
+/// An RPC provider, calls to it are bounded and paced by its limits.
+#[derive(Clone)]
+pub(crate) struct Endpoint {
+    name: &'static str,
+    client: JsonRpcClient,
+    limits: EndpointLimits,
+    permits: Arc<Semaphore>,
+    /// When the next request may start.
+    next: Arc<Mutex<Instant>>,
+    requests: Arc<AtomicU64>,
+    throttled: Arc<AtomicU64>,
+    latency: Arc<Histogram>,
+}
+
+impl Endpoint {
+    /// A connection pool to `url`, requests to it are abandoned after
+    /// `timeout`.
+    pub fn new(name: &'static str, url: &str, limits: EndpointLimits, timeout: Duration) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("the http client is always valid");
+        Self {
+            name,
+            client: JsonRpcClient::with(http).connect(url),
+            limits,
+            permits: Arc::new(Semaphore::new(limits.max_concurrent.max(1))),
+            next: Arc::new(Mutex::new(Instant::now())),
+            requests: Default::default(),
+            throttled: Default::default(),
+            latency: Histogram::new(LATENCY_BUCKETS_MS).into(),
+        }
+    }
+
+    pub async fn call<M: RpcMethod>(&self, method: M) -> MethodCallResult<M::Response, M::Error> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let mut throttled = false;
+
+        let _permit = match self.permits.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                throttled = true;
+                self.permits
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed")
+            }
+        };
+        throttled |= self.pace().await;
+        if throttled {
+            self.throttled.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let start = Instant::now();
+        let result = self.client.call(method).await;
+        self.latency.observe(start.elapsed());
+        result
+    }
+
+    /// Wait until the next request may start, returning whether we waited.
+    async fn pace(&self) -> bool {
+        if self.limits.min_interval_ms == 0 {
+            return false;
+        }
+        // Held while waiting, so requests start one interval apart
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        let waited = *next > now;
+        if waited {
+            tokio::time::sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + Duration::from_millis(self.limits.min_interval_ms);
+        waited
+    }
+
+    /// Requests it takes in flight at once.
+    pub fn max_concurrent(&self) -> usize {
+        self.limits.max_concurrent.max(1)
+    }
+
+    pub fn usage(&self) -> Usage {
+        let max_concurrent = self.max_concurrent();
+        Usage {
+            endpoint: self.name,
+            in_flight: max_concurrent - self.permits.available_permits(),
+            max_concurrent,
+            requests: self.requests.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(max_concurrent: usize, min_interval_ms: u64) -> Endpoint {
+        Endpoint::new(
+            "test",
+            "http://localhost:3030",
+            EndpointLimits {
+                max_concurrent,
+                min_interval_ms,
+            },
+            Duration::from_secs(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pacing() {
+        let endpoint = endpoint(1, 20);
+        let start = Instant::now();
+        assert!(!endpoint.pace().await);
+        assert!(endpoint.pace().await);
+        assert!(endpoint.pace().await);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let timeouts = Timeouts::default();
+        assert!(timeouts.of(LatencyClass::History) > timeouts.of(LatencyClass::Head));
+
+        let timeouts: Timeouts = serde_json::from_str(r#"{"head_ms": 500}"#).unwrap();
+        assert_eq!(timeouts.of(LatencyClass::Head), Duration::from_millis(500));
+        assert_eq!(
+            timeouts.of(LatencyClass::History),
+            Duration::from_millis(default_history_ms())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_usage() {
+        let endpoint = endpoint(4, 0);
+        let _held = endpoint.permits.acquire_many(3).await.unwrap();
+        assert_eq!(
+            endpoint.usage(),
+            Usage {
+                endpoint: "test",
+                in_flight: 3,
+                max_concurrent: 4,
+                requests: 0,
+                throttled: 0,
+                latency: Histogram::new(LATENCY_BUCKETS_MS).snapshot(),
+            }
+        );
+        // A limit of 0 still lets one through
+        assert_eq!(endpoint.max_concurrent(), 4);
+        assert_eq!(self::endpoint(0, 0).max_concurrent(), 1);
+    }
+}
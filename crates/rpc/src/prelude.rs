@@ -1,4 +1,3 @@
-pub use anyhow::{anyhow, Result};
 pub use futures::{FutureExt, TryFutureExt};
 pub use itertools::Itertools;
 pub use log::{debug, error, info, trace, warn};
@@ -8,6 +7,7 @@ pub use near_primitives_core::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     hash::CryptoHash,
 };
+pub use nearx_error::{err, Result};
 pub use serde::{Deserialize, Serialize};
 
 pub type Header = near_primitives::views::LightClientBlockLiteView;
@@ -0,0 +1,158 @@
+//! Building a `GetProof` from the strings integrators have, checking the hash
+//! and account up front rather than waiting for the RPC to turn it away.
+use std::str::FromStr;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InvalidRequest {
+    #[error("{0} is missing")]
+    Missing(&'static str),
+    #[error("{0} is not a valid hash: {1}")]
+    InvalidHash(String, String),
+    #[error("{0} is not a valid account id: {1}")]
+    InvalidAccount(String, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Transaction,
+    Receipt,
+}
+
+/// Builds a request for the proof of a transaction or a receipt:
+///
+/// ```
+/// # use near_light_client_rpc::request::GetProofRequest;
+/// let req = GetProofRequest::transaction()
+///     .id("3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz")
+///     .account("zavodil.testnet")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetProofRequest {
+    kind: Kind,
+    id: Option<String>,
+    account: Option<String>,
+}
+
+impl GetProofRequest {
+    pub fn transaction() -> Self {
+        Self::new(Kind::Transaction)
+    }
+
+    pub fn receipt() -> Self {
+        Self::new(Kind::Receipt)
+    }
+
+    fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            id: None,
+            account: None,
+        }
+    }
+
+    /// The transaction hash or the receipt id, in base58.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// The sender of the transaction or the receiver of the receipt.
+    pub fn account(mut self, account: impl Into<String>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    pub fn build(self) -> Result<GetProof, InvalidRequest> {
+        let (id_field, account_field) = match self.kind {
+            Kind::Transaction => ("transaction_hash", "sender_id"),
+            Kind::Receipt => ("receipt_id", "receiver_id"),
+        };
+        let id = self.id.ok_or(InvalidRequest::Missing(id_field))?;
+        let id = CryptoHash::from_str(id.trim())
+            .map_err(|e| InvalidRequest::InvalidHash(id.clone(), e.to_string()))?;
+        let account = self.account.ok_or(InvalidRequest::Missing(account_field))?;
+        let account = AccountId::from_str(account.trim())
+            .map_err(|e| InvalidRequest::InvalidAccount(account.clone(), e.to_string()))?;
+
+        Ok(match self.kind {
+            Kind::Transaction => GetProof::Transaction {
+                transaction_hash: id,
+                sender_id: account,
+            },
+            Kind::Receipt => GetProof::Receipt {
+                receipt_id: id,
+                receiver_id: account,
+            },
+        })
+    }
+}
+
+impl TryFrom<GetProofRequest> for GetProof {
+    type Error = InvalidRequest;
+
+    fn try_from(request: GetProofRequest) -> Result<Self, Self::Error> {
+        request.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz";
+
+    #[test]
+    fn test_build() {
+        let tx = GetProofRequest::transaction()
+            .id(HASH)
+            .account("zavodil.testnet")
+            .build()
+            .unwrap();
+        assert_eq!(
+            tx,
+            GetProof::Transaction {
+                transaction_hash: CryptoHash::from_str(HASH).unwrap(),
+                sender_id: "zavodil.testnet".parse().unwrap(),
+            }
+        );
+
+        // Pasted with whitespace
+        let rx: GetProof = GetProofRequest::receipt()
+            .id(format!(" {}\n", HASH))
+            .account("priceoracle.testnet ")
+            .try_into()
+            .unwrap();
+        assert!(matches!(rx, GetProof::Receipt { .. }));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            GetProofRequest::receipt().account("a.near").build(),
+            Err(InvalidRequest::Missing("receipt_id"))
+        );
+        assert_eq!(
+            GetProofRequest::transaction().id(HASH).build(),
+            Err(InvalidRequest::Missing("sender_id"))
+        );
+        // Too short to be a hash
+        assert!(matches!(
+            GetProofRequest::transaction()
+                .id("3z2z")
+                .account("a.near")
+                .build(),
+            Err(InvalidRequest::InvalidHash(..))
+        ));
+        assert!(matches!(
+            GetProofRequest::transaction()
+                .id(HASH)
+                .account("Not An Account")
+                .build(),
+            Err(InvalidRequest::InvalidAccount(..))
+        ));
+    }
+}
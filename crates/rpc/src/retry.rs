@@ -0,0 +1,209 @@
+//! Retrying RPC calls that failed for reasons that may pass.
+//!
+//! Providers rate limit and have the odd bad minute, a 429 or a 503 hours
+//! into proving a range shouldn't abort it. Calls are retried with an
+//! exponential backoff, jittered so the requests a rate limit held back don't
+//! all come back at once. An error about the request itself, such as an
+//! unknown block, is returned straight away.
+use std::{fmt::Debug, future::Future, time::Duration};
+
+use near_jsonrpc_client::errors::{
+    JsonRpcError, JsonRpcServerError, JsonRpcServerResponseStatusError, JsonRpcTransportRecvError,
+    JsonRpcTransportSendError, RpcTransportError,
+};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts in all, 1 to never retry.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubling for each one after it.
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// The most a delay grows to, before jitter.
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+impl RetryPolicy {
+    /// The delay after the attempt numbered `attempt` failed, counting from
+    /// 0. A `jitter` from 0 to 1 scales it from half of it to all of it.
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(factor)
+            .min(self.max_delay_ms);
+        let scale = 0.5 + jitter.clamp(0.0, 1.0) / 2.0;
+        Duration::from_millis((delay as f64 * scale) as u64)
+    }
+
+    /// Call `f` until it succeeds, fails with an error `retryable` turns
+    /// down or runs out of attempts, returning the last result.
+    pub async fn retry<T, E: Debug, Fut>(
+        &self,
+        what: &str,
+        mut f: impl FnMut() -> Fut,
+        retryable: impl Fn(&E) -> bool,
+    ) -> std::result::Result<T, E>
+    where
+        Fut: Future<Output = std::result::Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(e) if attempt + 1 < self.max_attempts && retryable(&e) => {
+                    let delay = self.delay(attempt, rand::random());
+                    warn!(
+                        "{} failed on attempt {}, retrying in {:?}: {:?}",
+                        what,
+                        attempt + 1,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an RPC error may pass: the request not getting through, rate
+/// limits, timeouts and server errors.
+pub fn is_retryable<E>(e: &JsonRpcError<E>) -> bool {
+    use JsonRpcServerResponseStatusError as Status;
+    match e {
+        JsonRpcError::TransportError(e) => matches!(
+            e,
+            RpcTransportError::SendError(JsonRpcTransportSendError::PayloadSendError(_))
+                | RpcTransportError::RecvError(JsonRpcTransportRecvError::PayloadRecvError(_))
+        ),
+        JsonRpcError::ServerError(JsonRpcServerError::InternalError { .. }) => true,
+        JsonRpcError::ServerError(JsonRpcServerError::ResponseStatusError(status)) => {
+            match status {
+                Status::TooManyRequests | Status::TimeoutError | Status::ServiceUnavailable => true,
+                Status::Unexpected { status } => status.is_server_error(),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0, 1.0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2, 1.0), Duration::from_millis(2_000));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(1_000));
+        // Capped, however many attempts
+        assert_eq!(policy.delay(10, 1.0), Duration::from_millis(30_000));
+        assert_eq!(policy.delay(u32::MAX, 1.0), Duration::from_millis(30_000));
+
+        let policy: RetryPolicy = serde_json::from_str(r#"{"max_attempts": 1}"#).unwrap();
+        assert_eq!(
+            policy,
+            RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 1,
+            max_delay_ms: 1,
+        };
+        let calls = AtomicU32::new(0);
+        let fail_until = |n: u32| {
+            let calls = &calls;
+            move || async move {
+                let call = calls.fetch_add(1, Ordering::Relaxed) + 1;
+                if call < n {
+                    Err(call)
+                } else {
+                    Ok(call)
+                }
+            }
+        };
+
+        assert_eq!(policy.retry("test", fail_until(3), |_| true).await, Ok(3));
+
+        // Out of attempts
+        calls.store(0, Ordering::Relaxed);
+        assert_eq!(policy.retry("test", fail_until(4), |_| true).await, Err(3));
+
+        // Not retryable
+        calls.store(0, Ordering::Relaxed);
+        assert_eq!(
+            policy.retry("test", fail_until(3), |e| *e != 1).await,
+            Err(1)
+        );
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |status| {
+            JsonRpcError::<()>::ServerError(JsonRpcServerError::ResponseStatusError(status))
+        };
+        assert!(is_retryable(&status(
+            JsonRpcServerResponseStatusError::TooManyRequests
+        )));
+        assert!(is_retryable(&status(
+            JsonRpcServerResponseStatusError::ServiceUnavailable
+        )));
+        assert!(is_retryable(&status(
+            JsonRpcServerResponseStatusError::Unexpected {
+                status: StatusCode::BAD_GATEWAY
+            }
+        )));
+        assert!(!is_retryable(&status(
+            JsonRpcServerResponseStatusError::Unexpected {
+                status: StatusCode::NOT_FOUND
+            }
+        )));
+        assert!(!is_retryable(&status(
+            JsonRpcServerResponseStatusError::Unauthorized
+        )));
+        assert!(!is_retryable(&JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(())
+        )));
+    }
+}
@@ -0,0 +1,32 @@
+//! A single dependency for the light client's libraries.
+//!
+//! Each feature only compiles what it needs, most consumers want the protocol
+//! alone and shouldn't pay for building the circuits:
+//!
+//! - `protocol`, the default: the protocol types and native verification, and
+//!   `unpack` for packed outputs
+//! - `outputs`: the `no_std` decoders of the outputs, for zkVM guests
+//! - `rpc`: fetching headers, proofs and block producers from NEAR
+//! - `circuits`: the circuits, which pull in plonky2x, starkyx and ethers
+//!
+//! The operator is a binary, see `bin/client`, it is not a library to depend
+//! on. `make check-features` checks each combination builds and that the
+//! protocol alone doesn't depend on the circuits.
+#[cfg(feature = "outputs")]
+pub use near_light_client_outputs as outputs;
+#[cfg(feature = "protocol")]
+pub use near_light_client_protocol as protocol;
+#[cfg(feature = "rpc")]
+pub use near_light_client_rpc as rpc;
+#[cfg(feature = "circuits")]
+pub use near_light_clientx as circuits;
+
+/// Unpacking the packed outputs the operator attaches to proofs with
+/// `scheduler.output = "packed"`.
+#[cfg(feature = "protocol")]
+pub mod unpack {
+    pub use near_light_client_protocol::packing::{
+        unpack_sync, unpack_sync_commitment, unpack_verify_results, SyncCommitment, SyncOutput,
+        UnpackError, VerifyResult,
+    };
+}
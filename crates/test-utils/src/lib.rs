@@ -1,3 +1,5 @@
+pub mod mutate;
+
 use std::path::{Path, PathBuf};
 
 use derive_more::Into;
@@ -32,6 +34,11 @@ pub fn fixture<T: DeserializeOwned>(file: &str) -> T {
     .unwrap()
 }
 
+/// A binary fixture, for layouts that must match byte for byte.
+pub fn fixture_bytes(file: &str) -> Vec<u8> {
+    std::fs::read(format!("{}/fixtures/{}", workspace_dir().display(), file)).unwrap()
+}
+
 pub fn lc<T: DeserializeOwned>(file: &str) -> LightClientFixture<T> {
     fixture(file)
 }
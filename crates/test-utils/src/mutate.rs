@@ -0,0 +1,244 @@
+//! Structured mutations of recorded fixtures, and a report of which fields
+//! the verifier actually constrains.
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A fixture with a single field mutated, `field` is the JSON pointer to it.
+#[derive(Debug, Clone)]
+pub struct Mutant<T> {
+    pub field: String,
+    pub value: T,
+}
+
+/// Flip a bit in every leaf of the fixture, taking at most `per_field`
+/// mutants for each field pattern, see [`pattern`]. Mutants that no longer
+/// deserialize are dropped, the type system already rejects them.
+pub fn mutants<T: Serialize + DeserializeOwned>(fixture: &T, per_field: usize) -> Vec<Mutant<T>> {
+    let root = serde_json::to_value(fixture).expect("fixtures serialize");
+    let mut taken: BTreeMap<String, usize> = BTreeMap::new();
+    let mut mutants = vec![];
+    for (field, leaf) in leaves(&root, String::new()) {
+        let count = taken.entry(pattern(&field)).or_default();
+        if *count >= per_field {
+            continue;
+        }
+        let Some(flipped) = flip(leaf) else {
+            continue;
+        };
+        let mut value = root.clone();
+        *value
+            .pointer_mut(&field)
+            .expect("pointer is from the value") = flipped;
+        if let Ok(value) = serde_json::from_value(value) {
+            *count += 1;
+            mutants.push(Mutant { field, value });
+        }
+    }
+    mutants
+}
+
+fn leaves(value: &Value, pointer: String) -> Vec<(String, &Value)> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .flat_map(|(k, v)| leaves(v, format!("{}/{}", pointer, k)))
+            .collect(),
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .flat_map(|(i, v)| leaves(v, format!("{}/{}", pointer, i)))
+            .collect(),
+        Value::Null => vec![],
+        leaf => vec![(pointer, leaf)],
+    }
+}
+
+/// Flip the lowest bit of a leaf, whatever its encoding.
+fn flip(leaf: &Value) -> Option<Value> {
+    match leaf {
+        Value::Bool(b) => Some(Value::Bool(!b)),
+        Value::Number(n) => n.as_u64().map(|n| Value::from(n ^ 1)),
+        Value::String(s) => flip_str(s).map(Value::String),
+        _ => None,
+    }
+}
+
+fn flip_str(s: &str) -> Option<String> {
+    match s {
+        "Left" => return Some("Right".to_string()),
+        "Right" => return Some("Left".to_string()),
+        _ => (),
+    }
+    // Big numbers are encoded as decimal strings
+    if let Ok(n) = s.parse::<u128>() {
+        return Some((n ^ 1).to_string());
+    }
+    // Hashes, keys and signatures are base58, optionally prefixed with the key
+    // type. 58 is even, so flipping the parity of the last digit flips the
+    // lowest bit of the value.
+    let data = s.rsplit(':').next().unwrap_or(s);
+    if data.len() >= 32 && data.chars().all(|c| BASE58.contains(c)) {
+        let last = data.chars().last()?;
+        let digit = BASE58.find(last)?;
+        let flipped = BASE58.chars().nth(digit ^ 1)?;
+        return Some(format!("{}{}", &s[..s.len() - 1], flipped));
+    }
+    // Anything else, like an account id, gets its last character changed
+    let last = s.chars().last()?;
+    let replacement = if last == 'a' { 'b' } else { 'a' };
+    Some(format!(
+        "{}{}",
+        &s[..s.len() - last.len_utf8()],
+        replacement
+    ))
+}
+
+/// The field a pointer is to, with array indices replaced by `*` so that
+/// every element of a list is reported together.
+pub fn pattern(pointer: &str) -> String {
+    pointer
+        .split('/')
+        .map(|segment| {
+            if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                "*"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Rejected,
+    /// Accepted, but the result is the same as for the original.
+    Ignored,
+    /// Accepted with a different result, the field isn't constrained.
+    Unconstrained,
+}
+
+/// Verdicts by field pattern.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    fields: BTreeMap<String, [usize; 3]>,
+}
+
+impl Coverage {
+    pub fn record(&mut self, field: &str, verdict: Verdict) {
+        let counts = self.fields.entry(pattern(field)).or_default();
+        counts[verdict as usize] += 1;
+    }
+
+    /// The fields with at least one unconstrained mutation.
+    pub fn unconstrained(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(_, counts)| counts[Verdict::Unconstrained as usize] > 0)
+            .map(|(field, _)| field.as_str())
+            .collect()
+    }
+
+    /// Fields that aren't known to be unconstrained, but are.
+    pub fn unexpected(&self, known: &[&str]) -> Vec<&str> {
+        self.unconstrained()
+            .into_iter()
+            .filter(|field| {
+                !known
+                    .iter()
+                    .any(|k| field == k || field.starts_with(&format!("{}/", k)))
+            })
+            .collect()
+    }
+
+    pub fn render(&self) -> String {
+        let width = self.fields.keys().map(String::len).max().unwrap_or(0);
+        let mut out = format!(
+            "{:width$}  rejected  ignored  unconstrained\n",
+            "field",
+            width = width
+        );
+        for (field, [rejected, ignored, unconstrained]) in &self.fields {
+            out.push_str(&format!(
+                "{:width$}  {:>8}  {:>7}  {:>13}\n",
+                field,
+                rejected,
+                ignored,
+                unconstrained,
+                width = width
+            ));
+        }
+        out
+    }
+
+    /// Print the report and write it to `target/fuzz-coverage/{name}.txt`.
+    pub fn emit(&self, name: &str) {
+        let report = self.render();
+        println!("{}", report);
+        let dir: PathBuf = crate::workspace_dir().join("target").join("fuzz-coverage");
+        if let Err(e) = std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::write(dir.join(format!("{}.txt", name)), &report))
+        {
+            log::warn!("Failed to write coverage report: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flips() {
+        assert_eq!(flip_str("Left").unwrap(), "Right");
+        assert_eq!(flip_str("1000").unwrap(), "1001");
+        assert_eq!(
+            flip_str("figment.poolv1.near").unwrap(),
+            "figment.poolv1.neaa"
+        );
+        let hash = "WWrLWbWHwSmjtTn5oBZPYgRCuCYn6fkYVa4yhPWNK4L";
+        assert_eq!(
+            flip_str(hash).unwrap(),
+            "WWrLWbWHwSmjtTn5oBZPYgRCuCYn6fkYVa4yhPWNK4K"
+        );
+        let key = "ed25519:7RjyY1bRKDqkshbKZtgpQdwsdxou8j9my8g1hPKZ9ngM";
+        assert_eq!(
+            flip_str(key).unwrap(),
+            "ed25519:7RjyY1bRKDqkshbKZtgpQdwsdxou8j9my8g1hPKZ9ngN"
+        );
+    }
+
+    #[test]
+    fn test_mutants_flip_one_field() {
+        let fixture = serde_json::json!({"a": [1, 2, 3], "b": {"c": true, "d": null}});
+        let mutants = mutants(&fixture, 2);
+        let fields = mutants.iter().map(|m| m.field.as_str()).collect::<Vec<_>>();
+        assert_eq!(fields, ["/a/0", "/a/1", "/b/c"]);
+        assert_eq!(
+            mutants[1].value,
+            serde_json::json!({"a": [1, 3, 3], "b": {"c": true, "d": null}})
+        );
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut coverage = Coverage::default();
+        coverage.record("/next_bps/0/stake", Verdict::Unconstrained);
+        coverage.record("/next_bps/1/stake", Verdict::Rejected);
+        coverage.record("/inner_lite/height", Verdict::Rejected);
+        coverage.record("/inner_lite/timestamp", Verdict::Unconstrained);
+        assert_eq!(
+            coverage.unconstrained(),
+            ["/inner_lite/timestamp", "/next_bps/*/stake"]
+        );
+        assert_eq!(
+            coverage.unexpected(&["/next_bps"]),
+            ["/inner_lite/timestamp"]
+        );
+        assert!(coverage.render().contains("/next_bps/*/stake"));
+    }
+}
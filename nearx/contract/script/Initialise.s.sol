@@ -3,6 +3,7 @@ pragma solidity ^0.8.19;
 
 import {ERC1967Proxy} from "@openzeppelin/contracts/proxy/ERC1967/ERC1967Proxy.sol";
 import {NearX} from "../src/NearX.sol";
+import {parseOutputVersion} from "../src/interfaces/INearX.sol";
 import {Script} from "forge-std/Script.sol";
 import {DevOpsTools} from "lib/foundry-devops/src/DevOpsTools.sol";
 
@@ -22,15 +23,33 @@ contract Initialise is Script {
         address initialGateway = 0x6e4f1e9eA315EBFd69d18C2DB974EEf6105FB803;
         lightClient.updateGateway(initialGateway);
 
+        // We register the initial circuits ourselves, then hand the key admin
+        // role over if another signer should hold it
+        lightClient.updateKeyAdmin(msg.sender);
+
         bytes32 syncFunctionId = vm.envBytes32("SYNC_FUNCTION_ID");
+        lightClient.registerFunctionId(syncFunctionId);
         lightClient.updateSyncId(syncFunctionId);
 
         bytes32 verifyFunctionId = vm.envBytes32("VERIFY_FUNCTION_ID");
+        lightClient.registerFunctionId(verifyFunctionId);
         lightClient.updateVerifyId(verifyFunctionId);
 
+        address keyAdmin = vm.envOr("KEY_ADMIN", msg.sender);
+        lightClient.updateKeyAdmin(keyAdmin);
+
         bytes32 header = vm.envBytes32("NEAR_CHECKPOINT_HEADER_HASH");
         lightClient.setCheckpointHeader(header);
 
+        // Defaults to the chain id if not set
+        bytes32 domain = vm.envOr("NEAR_DOMAIN", bytes32(0));
+        lightClient.updateDomain(domain);
+
+        // The outputs the operators' circuits must write, see `nearx genesis`
+        lightClient.updateOutputVersion(
+            parseOutputVersion(vm.envString("NEAR_OUTPUT_VERSION"))
+        );
+
         vm.stopBroadcast();
     }
 }
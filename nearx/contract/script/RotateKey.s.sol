@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.19;
+
+import {NearX} from "../src/NearX.sol";
+import {Script} from "forge-std/Script.sol";
+import {DevOpsTools} from "lib/foundry-devops/src/DevOpsTools.sol";
+
+/// @notice Rotate to a new circuit build. The key admin registers the new
+/// function ids, then the owner switches to them. The previous ids are only
+/// revoked when asked, since that also fails any proofs still in flight.
+contract RotateKey is Script {
+    function setUp() public {}
+
+    function run() external {
+        address proxyAddress = DevOpsTools.get_most_recent_deployment(
+            "ERC1967Proxy",
+            block.chainid
+        );
+        NearX lightClient = NearX(payable(proxyAddress));
+
+        bytes32 previousSyncId = lightClient.syncFunctionId();
+        bytes32 previousVerifyId = lightClient.verifyFunctionId();
+        bytes32 syncFunctionId = vm.envOr("SYNC_FUNCTION_ID", previousSyncId);
+        bytes32 verifyFunctionId = vm.envOr(
+            "VERIFY_FUNCTION_ID",
+            previousVerifyId
+        );
+        uint256 keyAdmin = vm.envUint("KEY_ADMIN_PRIVATE_KEY");
+
+        vm.startBroadcast(keyAdmin);
+        lightClient.registerFunctionId(syncFunctionId);
+        lightClient.registerFunctionId(verifyFunctionId);
+        vm.stopBroadcast();
+
+        vm.startBroadcast();
+        lightClient.updateSyncId(syncFunctionId);
+        lightClient.updateVerifyId(verifyFunctionId);
+        vm.stopBroadcast();
+
+        if (vm.envOr("REVOKE_PREVIOUS", false)) {
+            vm.startBroadcast(keyAdmin);
+            if (previousSyncId != syncFunctionId) {
+                lightClient.revokeFunctionId(previousSyncId);
+            }
+            if (previousVerifyId != verifyFunctionId) {
+                lightClient.revokeFunctionId(previousVerifyId);
+            }
+            vm.stopBroadcast();
+        }
+    }
+}
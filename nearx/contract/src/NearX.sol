@@ -5,7 +5,7 @@ import {OwnableUpgradeable} from "@openzeppelin/contracts-upgradeable/access/Own
 import {Initializable} from "@openzeppelin/contracts-upgradeable/proxy/utils/Initializable.sol";
 import {UUPSUpgradeable} from "@openzeppelin/contracts-upgradeable/proxy/utils/UUPSUpgradeable.sol";
 import {ISuccinctGateway} from "./interfaces/ISuccinctGateway.sol";
-import {INearX, TransactionOrReceiptId, ProofVerificationResult, encodePackedIds, decodePackedIds, decodePackedResults} from "./interfaces/INearX.sol";
+import {INearX, SyncOutput, OutputVersion, ABI_OUTPUT_MAJOR, TransactionOrReceiptId, ProofVerificationResult, decodeSyncOutput, encodePackedIds, decodePackedIds, decodePackedResults, decodeAbiResults} from "./interfaces/INearX.sol";
 
 /// @notice The NearX contract is a light client for Near.
 contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
@@ -38,15 +38,70 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
     /// @notice The latest header that has been committed.
     bytes32 public latestHeader;
 
+    /// @notice App-defined domain separator, when unset the chain id is used.
+    bytes32 public domain;
+
+    /// @notice Function ids, the circuit digests, that proofs can be accepted under.
+    mapping(bytes32 => bool) public registeredFunctionIds;
+
+    /// @notice The signer allowed to register and revoke function ids.
+    address public keyAdmin;
+
+    /// @notice Every header a sync proof has been accepted for.
+    mapping(bytes32 => bool) public acceptedHeaders;
+
+    /// @notice The epoch of the latest header.
+    bytes32 public latestEpochId;
+
+    /// @notice The next epoch of the latest header.
+    bytes32 public latestNextEpochId;
+
+    /// @notice The version of the circuit outputs this contract decodes,
+    /// operators check theirs against it before relaying.
+    OutputVersion public outputVersion;
+
+    modifier onlyKeyAdmin() {
+        if (msg.sender != keyAdmin) {
+            revert NotKeyAdmin(msg.sender);
+        }
+        _;
+    }
+
     function updateGateway(address _gateway) external onlyOwner {
         gateway = _gateway;
     }
 
+    function updateKeyAdmin(address _keyAdmin) external onlyOwner {
+        keyAdmin = _keyAdmin;
+    }
+
+    /// @notice Allow proofs from a new circuit build, this must happen before
+    /// switching the active function id to it.
+    function registerFunctionId(bytes32 _functionId) external onlyKeyAdmin {
+        registeredFunctionIds[_functionId] = true;
+        emit FunctionIdRegistered(_functionId);
+    }
+
+    /// @notice Refuse any further proofs from a circuit build, including ones
+    /// already requested.
+    function revokeFunctionId(bytes32 _functionId) external onlyKeyAdmin {
+        registeredFunctionIds[_functionId] = false;
+        emit FunctionIdRevoked(_functionId);
+    }
+
+    function ensureRegistered(bytes32 _functionId) internal view {
+        if (!registeredFunctionIds[_functionId]) {
+            revert FunctionIdNotRegistered(_functionId);
+        }
+    }
+
     function updateSyncId(bytes32 _functionId) external onlyOwner {
+        ensureRegistered(_functionId);
         syncFunctionId = _functionId;
     }
 
     function updateVerifyId(bytes32 _functionId) external onlyOwner {
+        ensureRegistered(_functionId);
         verifyFunctionId = _functionId;
     }
 
@@ -55,6 +110,37 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
         latestHeader = _header;
     }
 
+    function updateDomain(bytes32 _domain) external onlyOwner {
+        domain = _domain;
+    }
+
+    function updateOutputVersion(OutputVersion memory _version)
+        external
+        onlyOwner
+    {
+        outputVersion = _version;
+    }
+
+    /// @notice The domain every request commits to and every proof must echo.
+    function domainSeparator() public view returns (bytes32) {
+        if (domain == bytes32(0)) {
+            return bytes32(block.chainid);
+        }
+        return domain;
+    }
+
+    function ensureDomain(bytes32 _domain) internal view {
+        if (_domain != domainSeparator()) {
+            revert DomainMismatch(domainSeparator(), _domain);
+        }
+    }
+
+    /// @notice Relayers should check this before submitting a sync so the
+    /// same head is only relayed once.
+    function isHeaderAccepted(bytes32 _header) external view returns (bool) {
+        return acceptedHeaders[_header];
+    }
+
     function ensureInitialized() internal view {
         if (gateway == address(0)) {
             revert GatewayNotInitialised();
@@ -70,11 +156,11 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
     /// @notice Inputs of a sync request.
     function requestSync() external payable {
         ensureInitialized();
-        bytes memory context;
+        bytes memory context = abi.encode(syncFunctionId, latestHeader);
 
         ISuccinctGateway(gateway).requestCallback{value: msg.value}(
             syncFunctionId,
-            abi.encodePacked(latestHeader),
+            abi.encodePacked(domainSeparator(), latestHeader),
             context,
             NearX.handleSync.selector,
             DEFAULT_GAS_LIMIT
@@ -83,16 +169,65 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
         emit SyncRequested(latestHeader);
     }
 
+    /// @notice Callbacks requested before the function id registry carry no
+    /// context, they were requested under the current sync id from the
+    /// current head.
+    function decodeSyncContext(bytes memory _context)
+        internal
+        view
+        returns (bytes32 functionId, bytes32 trustedHeader)
+    {
+        if (_context.length == 0) {
+            return (syncFunctionId, latestHeader);
+        }
+        return abi.decode(_context, (bytes32, bytes32));
+    }
+
+    /// @notice See `decodeSyncContext`.
+    function decodeVerifyContext(bytes memory _context)
+        internal
+        view
+        returns (bytes32)
+    {
+        if (_context.length == 0) {
+            return verifyFunctionId;
+        }
+        return abi.decode(_context, (bytes32));
+    }
+
     function handleSync(bytes memory _output, bytes memory _context) external {
         if (msg.sender != gateway || !ISuccinctGateway(gateway).isCallback()) {
             revert NotFromSuccinctGateway(msg.sender);
         }
 
-        bytes32 targetHeader = abi.decode(_output, (bytes32));
+        (bytes32 functionId, bytes32 trustedHeader) = decodeSyncContext(
+            _context
+        );
+        ensureRegistered(functionId);
+
+        SyncOutput memory output = decodeSyncOutput(_output);
+        ensureDomain(output.domain);
+        bytes32 targetHeader = output.header;
 
+        // Another request already relayed this head
+        if (acceptedHeaders[targetHeader]) {
+            emit AlreadyAccepted(targetHeader);
+            return;
+        }
+        // The head moved on since this was requested, accepting the late
+        // callback would roll it back
+        if (trustedHeader != latestHeader) {
+            emit StaleSync(trustedHeader, targetHeader);
+            return;
+        }
+        acceptedHeaders[targetHeader] = true;
         latestHeader = targetHeader;
+        latestEpochId = output.epochId;
+        latestNextEpochId = output.nextEpochId;
 
         emit HeadUpdate(targetHeader);
+        emit EpochUpdate(output.epochId, output.nextEpochId);
+        emit ProofAccepted(functionId, targetHeader);
     }
 
     function requestVerify(TransactionOrReceiptId[] memory ids)
@@ -100,8 +235,9 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
         payable
     {
         ensureInitialized();
-        bytes memory context;
+        bytes memory context = abi.encode(verifyFunctionId);
         bytes memory input = abi.encodePacked(
+            domainSeparator(),
             latestHeader,
             encodePackedIds(ids)
         );
@@ -123,7 +259,19 @@ contract NearX is INearX, Initializable, OwnableUpgradeable, UUPSUpgradeable {
         if (msg.sender != gateway || !ISuccinctGateway(gateway).isCallback()) {
             revert NotFromSuccinctGateway(msg.sender);
         }
-        ProofVerificationResult[] memory results = decodePackedResults(_output);
+        bytes32 functionId = decodeVerifyContext(_context);
+        ensureRegistered(functionId);
+
+        bytes32 outputDomain;
+        ProofVerificationResult[] memory results;
+        if (outputVersion.major >= ABI_OUTPUT_MAJOR) {
+            (outputDomain, results) = decodeAbiResults(_output);
+        } else {
+            outputDomain = bytes32(_output[0:32]);
+            results = decodePackedResults(_output[32:]);
+        }
+        ensureDomain(outputDomain);
         emit VerifyResult(results);
+        emit ProofAccepted(functionId, latestHeader);
     }
 }
@@ -7,6 +7,9 @@ interface INearX {
     /// @notice Emits event with the new head update.
     event HeadUpdate(bytes32 headerHash);
 
+    /// @notice The epochs of the new head, for epoch based policies.
+    event EpochUpdate(bytes32 indexed epochId, bytes32 nextEpochId);
+
     /// @notice Inputs of a sync request.
     /// @param trustedHeader The header hash of the trusted block.
     event SyncRequested(bytes32 indexed trustedHeader);
@@ -32,12 +35,95 @@ interface INearX {
 
     error FunctionIdsNotInitialised();
 
+    /// @notice The proof was generated for a different domain.
+    error DomainMismatch(bytes32 expected, bytes32 actual);
+
+    /// @notice Only the key admin can register or revoke function ids.
+    error NotKeyAdmin(address);
+
+    /// @notice The proof was generated by a circuit that is not registered.
+    error FunctionIdNotRegistered(bytes32 functionId);
+
+    /// @notice A circuit build was registered.
+    event FunctionIdRegistered(bytes32 indexed functionId);
+
+    /// @notice A circuit build was revoked.
+    event FunctionIdRevoked(bytes32 indexed functionId);
+
+    /// @notice A sync for a header that was already accepted was ignored.
+    event AlreadyAccepted(bytes32 indexed headerHash);
+
+    /// @notice A sync requested from a head that has since been replaced was
+    /// ignored.
+    event StaleSync(bytes32 indexed trustedHeader, bytes32 headerHash);
+
+    /// @notice A proof generated under `functionId` was accepted.
+    /// @param trustedHeader The head after accepting the proof.
+    event ProofAccepted(bytes32 indexed functionId, bytes32 trustedHeader);
+
     /// @notice The result of the verification request
     event VerifyResult(ProofVerificationResult[] results);
 }
 
 uint256 constant MAX_LEN = 64;
 
+/// @notice The semantic version of the circuit outputs, see
+/// `nearx::repro::OUTPUT_VERSION`.
+struct OutputVersion {
+    uint64 major;
+    uint64 minor;
+    uint64 patch;
+}
+
+/// @notice From this major version the circuits `abi.encode` their outputs,
+/// see `nearx::abi`.
+uint64 constant ABI_OUTPUT_MAJOR = 2;
+
+/// @notice Parse a `major.minor.patch` version.
+function parseOutputVersion(string memory _version)
+    pure
+    returns (OutputVersion memory version)
+{
+    bytes memory b = bytes(_version);
+    uint64[3] memory parts;
+    uint256 part = 0;
+    bool digits = false;
+    for (uint256 i = 0; i < b.length; i++) {
+        if (b[i] == ".") {
+            require(digits && part < 2, "invalid output version");
+            part++;
+            digits = false;
+        } else {
+            require(b[i] >= "0" && b[i] <= "9", "invalid output version");
+            parts[part] = parts[part] * 10 + uint8(b[i]) - uint8(bytes1("0"));
+            digits = true;
+        }
+    }
+    require(digits && part == 2, "invalid output version");
+    version = OutputVersion(parts[0], parts[1], parts[2]);
+}
+
+/// @notice The outputs of a sync proof.
+struct SyncOutput {
+    bytes32 domain;
+    bytes32 header;
+    /// @dev The epoch of the synced header.
+    bytes32 epochId;
+    bytes32 nextEpochId;
+}
+
+function decodeSyncOutput(bytes memory _output)
+    pure
+    returns (SyncOutput memory output)
+{
+    (
+        output.domain,
+        output.header,
+        output.epochId,
+        output.nextEpochId
+    ) = abi.decode(_output, (bytes32, bytes32, bytes32, bytes32));
+}
+
 struct TransactionOrReceiptId {
     bool isTransaction;
     bytes32 id;
@@ -104,16 +190,23 @@ function decodeTransactionOrReceiptId(bytes memory _input)
     id.account = nextBytes;
 }
 
+/// @notice The result of verifying an outcome.
 struct ProofVerificationResult {
     bytes32 id;
     bool result;
+    /// @dev The borsh tag of `PartialExecutionStatus`, 1 is a failure, 2 a
+    /// returned value and 3 a result deferred to a receipt.
+    uint8 status;
+    uint64 gasBurnt;
+    /// @dev The hash of the returned value or the receipt id, zero otherwise.
+    bytes32 resultHash;
 }
 
 function decodePackedResults(bytes memory _input)
     pure
     returns (ProofVerificationResult[] memory)
 {
-    uint256 iterationLength = 1 + 32;
+    uint256 iterationLength = 32 + 1 + 1 + 8 + 32;
     uint256 idsLength = _input.length / iterationLength;
     ProofVerificationResult[] memory results = new ProofVerificationResult[](
         idsLength
@@ -128,6 +221,18 @@ function decodePackedResults(bytes memory _input)
     return results;
 }
 
+/// @notice The outputs of a verify proof with `ABI_OUTPUT_MAJOR`, each
+/// result is static so they decode straight into the struct.
+function decodeAbiResults(bytes memory _output)
+    pure
+    returns (bytes32 domain, ProofVerificationResult[] memory results)
+{
+    (domain, results) = abi.decode(
+        _output,
+        (bytes32, ProofVerificationResult[])
+    );
+}
+
 function decodeProofVerificationResult(bytes memory _input)
     pure
     returns (ProofVerificationResult memory result)
@@ -139,4 +244,13 @@ function decodeProofVerificationResult(bytes memory _input)
 
     (nextBytes, offset) = Bytes.readBytes(_input, offset, 1);
     result.result = uint8(bytes1(nextBytes)) != 0;
+
+    (nextBytes, offset) = Bytes.readBytes(_input, offset, 1);
+    result.status = uint8(bytes1(nextBytes));
+
+    (nextBytes, offset) = Bytes.readBytes(_input, offset, 8);
+    result.gasBurnt = uint64(bytes8(nextBytes));
+
+    (nextBytes, offset) = Bytes.readBytes(_input, offset, 32);
+    result.resultHash = abi.decode(nextBytes, (bytes32));
 }
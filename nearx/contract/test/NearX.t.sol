@@ -2,14 +2,186 @@
 pragma solidity ^0.8.13;
 
 import "forge-std/Test.sol";
+import {ERC1967Proxy} from "@openzeppelin/contracts/proxy/ERC1967/ERC1967Proxy.sol";
 import "../src/NearX.sol";
 import {Bytes} from "../src/interfaces/Bytes.sol";
+import {parseOutputVersion, decodeAbiResults} from "../src/interfaces/INearX.sol";
+import {ISuccinctGateway} from "../src/interfaces/ISuccinctGateway.sol";
 
 contract NearXTest is Test {
     NearX public lightClient;
 
+    address constant GATEWAY = address(0x6a7e);
+    bytes32 constant SYNC_ID = keccak256("sync");
+    bytes32 constant CHECKPOINT = keccak256("checkpoint");
+    bytes32 constant HEADER = keccak256("header");
+    bytes32 constant EPOCH = keccak256("epoch");
+    bytes32 constant NEXT_EPOCH = keccak256("next epoch");
+    bytes32 constant VERIFY_ID = keccak256("verify");
+    bytes32 constant RESULT_ID = keccak256("result");
+
+    event AlreadyAccepted(bytes32 indexed headerHash);
+    event StaleSync(bytes32 indexed trustedHeader, bytes32 headerHash);
+    event VerifyResult(ProofVerificationResult[] results);
+    event ProofAccepted(bytes32 indexed functionId, bytes32 trustedHeader);
+
     function setUp() public {
-        lightClient = new NearX();
+        NearX implementation = new NearX();
+        ERC1967Proxy proxy = new ERC1967Proxy(
+            address(implementation),
+            abi.encodeCall(NearX.initialize, ())
+        );
+        lightClient = NearX(address(proxy));
+
+        lightClient.updateGateway(GATEWAY);
+        lightClient.updateKeyAdmin(address(this));
+        vm.mockCall(
+            GATEWAY,
+            abi.encodeWithSelector(ISuccinctGateway.isCallback.selector),
+            abi.encode(true)
+        );
+        lightClient.setCheckpointHeader(CHECKPOINT);
+    }
+
+    function syncOutput() internal view returns (bytes memory) {
+        return
+            abi.encode(
+                lightClient.domainSeparator(),
+                HEADER,
+                EPOCH,
+                NEXT_EPOCH
+            );
+    }
+
+    function syncContext(bytes32 trustedHeader)
+        internal
+        pure
+        returns (bytes memory)
+    {
+        return abi.encode(SYNC_ID, trustedHeader);
+    }
+
+    function testOnlyKeyAdminRegisters() public {
+        vm.prank(address(0xbad));
+        vm.expectRevert(
+            abi.encodeWithSelector(INearX.NotKeyAdmin.selector, address(0xbad))
+        );
+        lightClient.registerFunctionId(SYNC_ID);
+    }
+
+    function testUpdateSyncIdRequiresRegistered() public {
+        vm.expectRevert(
+            abi.encodeWithSelector(
+                INearX.FunctionIdNotRegistered.selector,
+                SYNC_ID
+            )
+        );
+        lightClient.updateSyncId(SYNC_ID);
+
+        lightClient.registerFunctionId(SYNC_ID);
+        lightClient.updateSyncId(SYNC_ID);
+        assertEq(lightClient.syncFunctionId(), SYNC_ID);
+    }
+
+    function testHandleSyncAcceptsRegistered() public {
+        lightClient.registerFunctionId(SYNC_ID);
+        bytes memory output = syncOutput();
+
+        vm.prank(GATEWAY);
+        lightClient.handleSync(output, syncContext(CHECKPOINT));
+        assertEq(lightClient.latestHeader(), HEADER);
+        assertEq(lightClient.latestEpochId(), EPOCH);
+        assertEq(lightClient.latestNextEpochId(), NEXT_EPOCH);
+    }
+
+    function testHandleSyncIsIdempotent() public {
+        lightClient.registerFunctionId(SYNC_ID);
+        bytes memory output = syncOutput();
+        assertFalse(lightClient.isHeaderAccepted(HEADER));
+
+        vm.prank(GATEWAY);
+        lightClient.handleSync(output, syncContext(CHECKPOINT));
+        assertTrue(lightClient.isHeaderAccepted(HEADER));
+
+        // A newer head lands, then a racing relay of the old one
+        bytes32 newer = keccak256("newer");
+        bytes memory newerOutput = abi.encode(
+            lightClient.domainSeparator(),
+            newer,
+            NEXT_EPOCH,
+            keccak256("after next epoch")
+        );
+        vm.prank(GATEWAY);
+        lightClient.handleSync(newerOutput, syncContext(HEADER));
+
+        vm.prank(GATEWAY);
+        vm.expectEmit(true, false, false, true);
+        emit AlreadyAccepted(HEADER);
+        lightClient.handleSync(output, syncContext(CHECKPOINT));
+        assertEq(lightClient.latestHeader(), newer);
+        assertEq(lightClient.latestEpochId(), NEXT_EPOCH);
+    }
+
+    function testHandleSyncIgnoresOutOfOrder() public {
+        lightClient.registerFunctionId(SYNC_ID);
+
+        vm.prank(GATEWAY);
+        lightClient.handleSync(syncOutput(), syncContext(CHECKPOINT));
+
+        // Another operator proved a different head from the checkpoint, its
+        // callback lands after the head moved on
+        bytes32 older = keccak256("older");
+        bytes memory olderOutput = abi.encode(
+            lightClient.domainSeparator(),
+            older,
+            keccak256("older epoch"),
+            EPOCH
+        );
+        vm.prank(GATEWAY);
+        vm.expectEmit(true, false, false, true);
+        emit StaleSync(CHECKPOINT, older);
+        lightClient.handleSync(olderOutput, syncContext(CHECKPOINT));
+
+        assertEq(lightClient.latestHeader(), HEADER);
+        assertEq(lightClient.latestEpochId(), EPOCH);
+        assertEq(lightClient.latestNextEpochId(), NEXT_EPOCH);
+        assertFalse(lightClient.isHeaderAccepted(older));
+    }
+
+    function testHandleSyncRejectsRevoked() public {
+        lightClient.registerFunctionId(SYNC_ID);
+        lightClient.revokeFunctionId(SYNC_ID);
+        bytes memory output = syncOutput();
+
+        vm.prank(GATEWAY);
+        vm.expectRevert(
+            abi.encodeWithSelector(
+                INearX.FunctionIdNotRegistered.selector,
+                SYNC_ID
+            )
+        );
+        lightClient.handleSync(output, syncContext(CHECKPOINT));
+    }
+
+    function testHandleSyncAcceptsEmptyContext() public {
+        lightClient.registerFunctionId(SYNC_ID);
+        lightClient.updateSyncId(SYNC_ID);
+        bytes memory output = syncOutput();
+
+        // Requested before the upgrade
+        vm.prank(GATEWAY);
+        lightClient.handleSync(output, "");
+        assertEq(lightClient.latestHeader(), HEADER);
+
+        lightClient.revokeFunctionId(SYNC_ID);
+        vm.prank(GATEWAY);
+        vm.expectRevert(
+            abi.encodeWithSelector(
+                INearX.FunctionIdNotRegistered.selector,
+                SYNC_ID
+            )
+        );
+        lightClient.handleSync(output, "");
     }
 
     function testGetEncodePackedSync() public view {
@@ -18,6 +190,67 @@ contract NearXTest is Test {
         console.logBytes(encodedInput);
     }
 
+    function testOutputVersion() public {
+        (uint64 major, uint64 minor, uint64 patch) = lightClient
+            .outputVersion();
+        assertEq(major + minor + patch, 0);
+
+        lightClient.updateOutputVersion(parseOutputVersion("1.12.0"));
+        (major, minor, patch) = lightClient.outputVersion();
+        assertEq(major, 1);
+        assertEq(minor, 12);
+        assertEq(patch, 0);
+
+        vm.prank(address(0xbad));
+        vm.expectRevert();
+        lightClient.updateOutputVersion(OutputVersion(2, 0, 0));
+    }
+
+    function testParseOutputVersionRejectsInvalid() public {
+        vm.expectRevert("invalid output version");
+        this.parseVersion("1.0");
+        vm.expectRevert("invalid output version");
+        this.parseVersion("1..0");
+        vm.expectRevert("invalid output version");
+        this.parseVersion("1.0.0-rc1");
+    }
+
+    function parseVersion(string memory _version)
+        external
+        pure
+        returns (OutputVersion memory)
+    {
+        return parseOutputVersion(_version);
+    }
+
+    /// The getters the operator reads at startup, see
+    /// `bin/client/src/client/compat.rs`.
+    function testOperatorGetters() public {
+        assertEq(
+            lightClient.syncFunctionId.selector,
+            bytes4(keccak256("syncFunctionId()"))
+        );
+        assertEq(
+            lightClient.verifyFunctionId.selector,
+            bytes4(keccak256("verifyFunctionId()"))
+        );
+        assertEq(
+            lightClient.outputVersion.selector,
+            bytes4(keccak256("outputVersion()"))
+        );
+
+        lightClient.updateOutputVersion(OutputVersion(1, 2, 3));
+        (bool ok, bytes memory output) = address(lightClient).call(
+            abi.encodeWithSignature("outputVersion()")
+        );
+        assertTrue(ok);
+        assertEq(output, abi.encode(uint64(1), uint64(2), uint64(3)));
+    }
+
+    function testDomainDefaultsToChainId() public {
+        assertEq(lightClient.domainSeparator(), bytes32(block.chainid));
+    }
+
     function testGetEncodePackedVerify() public {
         bytes32 header = hex"63b87190ffbaa36d7dab50f918fe36f70ab26910a0e9d797161e2356561598e3";
         bytes memory txIsAccount = hex"01";
@@ -64,14 +297,94 @@ contract NearXTest is Test {
 
     function testDecodeResult() public {
         bytes
-            memory inputData = hex"7ff581f8517ec58459099a5af2465d5232fdcdd7c4da9c3d42a887bf6bd5457e012c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d7901";
+            memory inputData = hex"7ff581f8517ec58459099a5af2465d5232fdcdd7c4da9c3d42a887bf6bd5457e010300000236b9ddec842c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d792c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d79000000000000000000000000000000000000000000000000000000000000000000000000000000000000";
         ProofVerificationResult[] memory results = decodePackedResults(
             inputData
         );
-        bytes
-            memory outputTest = hex"2c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d79017ff581f8517ec58459099a5af2465d5232fdcdd7c4da9c3d42a887bf6bd5457e01";
-        ProofVerificationResult[] memory expected = decodePackedResults(
-            outputTest
+        assertEq(results.length, 2);
+        assertEq(
+            results[0].id,
+            0x7ff581f8517ec58459099a5af2465d5232fdcdd7c4da9c3d42a887bf6bd5457e
+        );
+        assertTrue(results[0].result);
+        assertEq(results[0].status, 3);
+        assertEq(results[0].gasBurnt, 2434069818500);
+        assertEq(
+            results[0].resultHash,
+            0x2c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d79
+        );
+        assertEq(
+            results[1].id,
+            0x2c53bcfe871da28decc45c3437f5864568d91af6d990dbc2662f11ce44c18d79
+        );
+        assertFalse(results[1].result);
+        assertEq(results[1].status, 0);
+        assertEq(results[1].resultHash, bytes32(0));
+    }
+
+    /// @dev Words as `nearx::abi` writes them: the domain, the offset of
+    /// the results, their length then each result.
+    function abiVerifyOutput() internal view returns (bytes memory) {
+        return
+            abi.encodePacked(
+                lightClient.domainSeparator(),
+                uint256(64),
+                uint256(2),
+                abi.encode(RESULT_ID, true, uint8(3), uint64(2434069818500), HEADER),
+                abi.encode(bytes32(0), false, uint8(0), uint64(0), bytes32(0))
+            );
+    }
+
+    function testDecodeAbiResults() public {
+        (
+            bytes32 outputDomain,
+            ProofVerificationResult[] memory results
+        ) = decodeAbiResults(abiVerifyOutput());
+        assertEq(outputDomain, lightClient.domainSeparator());
+        assertEq(results.length, 2);
+        assertEq(results[0].id, RESULT_ID);
+        assertTrue(results[0].result);
+        assertEq(results[0].status, 3);
+        assertEq(results[0].gasBurnt, 2434069818500);
+        assertEq(results[0].resultHash, HEADER);
+        assertFalse(results[1].result);
+    }
+
+    function testHandleVerifyDecodesAbiFromVersion() public {
+        lightClient.registerFunctionId(VERIFY_ID);
+        bytes memory output = abiVerifyOutput();
+        (, ProofVerificationResult[] memory expected) = decodeAbiResults(
+            output
+        );
+
+        // Read as the concatenated layout the words don't line up
+        vm.recordLogs();
+        vm.prank(GATEWAY);
+        lightClient.handleVerify(output, abi.encode(VERIFY_ID));
+        Vm.Log[] memory logs = vm.getRecordedLogs();
+        ProofVerificationResult[] memory packed = abi.decode(
+            logs[0].data,
+            (ProofVerificationResult[])
         );
+        assertEq(packed.length, (output.length - 32) / 74);
+        assertTrue(packed[0].id != RESULT_ID);
+
+        lightClient.updateOutputVersion(OutputVersion(2, 0, 0));
+        vm.expectEmit();
+        emit VerifyResult(expected);
+        vm.prank(GATEWAY);
+        lightClient.handleVerify(output, abi.encode(VERIFY_ID));
+    }
+
+    function testHandleVerifyAcceptsEmptyContext() public {
+        lightClient.registerFunctionId(VERIFY_ID);
+        lightClient.updateVerifyId(VERIFY_ID);
+        lightClient.updateOutputVersion(OutputVersion(2, 0, 0));
+        bytes memory output = abiVerifyOutput();
+
+        vm.expectEmit();
+        emit ProofAccepted(VERIFY_ID, CHECKPOINT);
+        vm.prank(GATEWAY);
+        lightClient.handleVerify(output, "");
     }
 }
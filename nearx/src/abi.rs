@@ -0,0 +1,183 @@
+//! Public outputs encoded as Solidity's `abi.encode`.
+//!
+//! By default the circuits concatenate their outputs, which a contract has to
+//! slice by offset. With the `abi` feature the sync and verify circuits write
+//! each value in its own 32 byte word and the BPS and results as dynamic
+//! arrays, so the contract can decode them directly:
+//!
+//! ```solidity
+//! struct Validator { bytes32[2] accountId; bytes32 publicKey; uint128 stake; }
+//! (bytes32 domain, bytes32 head, bytes32 epochId, bytes32 nextEpochId,
+//!     bytes32 nextBpsEpoch, Validator[] memory nextBps) = abi.decode(
+//!     output, (bytes32, bytes32, bytes32, bytes32, bytes32, Validator[]));
+//!
+//! struct Result { bytes32 id; bool verified; uint8 status; uint64 gasBurnt; bytes32 resultHash; }
+//! (bytes32 domain, Result[] memory results) = abi.decode(output, (bytes32, Result[]));
+//! ```
+//!
+//! The arrays always have an element for every seat or id, unfilled seats
+//! have no stake and unused ids are zeroed.
+use near_light_client_protocol::{
+    config::NUM_BLOCK_PRODUCER_SEATS,
+    prelude::{CryptoHash, Header},
+    ValidatorStake,
+};
+use plonky2x::{frontend::vars::EvmVariable, prelude::*};
+
+use crate::variables::{pad_account_id, AccountIdVariable, BpsArr, ValidatorStakeVariable};
+
+pub const WORD: usize = 32;
+
+/// The words of a `Validator`, the account id takes two.
+pub const VALIDATOR_WORDS: usize = 4;
+
+/// The words before the sync's `nextBps`, the last is its offset.
+pub const SYNC_HEAD_WORDS: usize = 6;
+
+/// The words before the verify's `results`, the last is its offset.
+pub const VERIFY_HEAD_WORDS: usize = 2;
+
+/// Writing ABI words to the EVM outputs, a `bytes32` is already a word so is
+/// written with `evm_write`.
+pub trait AbiWrite<L: PlonkParameters<D>, const D: usize> {
+    /// Right align a value type, a uint or a bool, in its word.
+    fn abi_write_value(&mut self, bytes: &[ByteVariable]);
+
+    /// A constant uint, an offset or the length of an array.
+    fn abi_write_uint(&mut self, value: u64);
+
+    /// A `Validator[]`, its length then every seat.
+    fn abi_write_validators<const A: usize>(&mut self, bps: &BpsArr<ValidatorStakeVariable, A>);
+}
+
+impl<L: PlonkParameters<D>, const D: usize> AbiWrite<L, D> for CircuitBuilder<L, D> {
+    fn abi_write_value(&mut self, bytes: &[ByteVariable]) {
+        assert!(
+            bytes.len() <= WORD,
+            "{} bytes don't fit a word",
+            bytes.len()
+        );
+        let zero = self.constant::<ByteVariable>(0);
+        for _ in bytes.len()..WORD {
+            self.evm_write::<ByteVariable>(zero);
+        }
+        for byte in bytes {
+            self.evm_write::<ByteVariable>(*byte);
+        }
+    }
+
+    fn abi_write_uint(&mut self, value: u64) {
+        let bytes = value
+            .to_be_bytes()
+            .into_iter()
+            .map(|byte| self.constant::<ByteVariable>(byte))
+            .collect::<Vec<_>>();
+        self.abi_write_value(&bytes);
+    }
+
+    fn abi_write_validators<const A: usize>(&mut self, bps: &BpsArr<ValidatorStakeVariable, A>) {
+        self.abi_write_uint(A as u64);
+        for vs in bps.data.iter() {
+            self.evm_write::<AccountIdVariable>(vs.account_id);
+            self.evm_write::<Bytes32Variable>(vs.public_key.0);
+            let stake = vs.stake.encode(self);
+            self.abi_write_value(&stake);
+        }
+    }
+}
+
+/// A uint in a word.
+pub fn word(value: u128) -> [u8; WORD] {
+    let mut word = [0u8; WORD];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// A `Validator[]` of every seat, as `AbiWrite::abi_write_validators` writes
+/// it.
+pub fn encode_validators(bps: &[ValidatorStake]) -> Vec<u8> {
+    let mut bytes = word(NUM_BLOCK_PRODUCER_SEATS as u128).to_vec();
+    for i in 0..NUM_BLOCK_PRODUCER_SEATS {
+        match bps.get(i) {
+            Some(vs) => {
+                bytes.extend(pad_account_id(vs.account_id()));
+                bytes.extend(vs.public_key().key_data());
+                bytes.extend(word(vs.stake()));
+            }
+            None => bytes.extend([0u8; VALIDATOR_WORDS * WORD]),
+        }
+    }
+    bytes
+}
+
+/// The outputs of the sync circuit built with the `abi` feature.
+pub fn encode_sync(
+    domain: &[u8; WORD],
+    new_head: &Header,
+    next_bps_epoch: &CryptoHash,
+    next_bps: &[ValidatorStake],
+) -> Vec<u8> {
+    let mut bytes = domain.to_vec();
+    bytes.extend(new_head.hash().0);
+    bytes.extend(new_head.inner_lite.epoch_id.0);
+    bytes.extend(new_head.inner_lite.next_epoch_id.0);
+    bytes.extend(next_bps_epoch.0);
+    bytes.extend(word((SYNC_HEAD_WORDS * WORD) as u128));
+    bytes.extend(encode_validators(next_bps));
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use near_light_client_protocol::prelude::Itertools;
+
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_encode_sync() {
+        let (head, bps, _) = testnet_state();
+        let encoded = encode_sync(&[1; WORD], &head, &CryptoHash::default(), &bps);
+
+        assert_eq!(
+            encoded.len(),
+            (SYNC_HEAD_WORDS + 1 + NUM_BLOCK_PRODUCER_SEATS * VALIDATOR_WORDS) * WORD
+        );
+        let words = encoded.chunks(WORD).collect_vec();
+        assert_eq!(words[1], head.hash().0);
+        // The offset points at the length
+        assert_eq!(words[5], word((SYNC_HEAD_WORDS * WORD) as u128));
+        assert_eq!(words[6], word(NUM_BLOCK_PRODUCER_SEATS as u128));
+        // The stake is right aligned
+        assert_eq!(words[10], word(bps[0].stake()));
+    }
+
+    #[test]
+    fn test_write_validators() {
+        const SEATS: usize = 2;
+        let (_, bps, _) = testnet_state();
+        let bps = bps.into_iter().take(SEATS).collect_vec();
+
+        let define = |b: &mut B| {
+            let domain = b.evm_read::<Bytes32Variable>();
+            let bps = b.constant::<BpsArr<ValidatorStakeVariable, SEATS>>(
+                bps.iter().cloned().map(Into::into).collect_vec(),
+            );
+            b.evm_write::<Bytes32Variable>(domain);
+            b.abi_write_validators(&bps);
+        };
+        let writer = |input: &mut PI| {
+            input.evm_write::<Bytes32Variable>([1; WORD].into());
+        };
+        let assertions = |mut output: PO| {
+            output.evm_read::<Bytes32Variable>();
+            let written =
+                output.evm_read::<BytesVariable<{ (1 + SEATS * VALIDATOR_WORDS) * WORD }>>();
+            let mut expected = word(SEATS as u128).to_vec();
+            // The first seats of the encoding of every seat
+            expected.extend(&encode_validators(&bps)[WORD..(1 + SEATS * VALIDATOR_WORDS) * WORD]);
+            assert_eq!(written.to_vec(), expected);
+        };
+        builder_suite(define, writer, assertions);
+    }
+}
@@ -0,0 +1,174 @@
+use near_light_client_protocol::signature::KeyPolicy;
+use plonky2x::prelude::plonky2::plonk::{
+    config::{AlgebraicHasher, GenericConfig},
+    proof::ProofWithPublicInputsTarget,
+};
+pub use plonky2x::{self, backend::circuit::Circuit, prelude::*};
+
+use crate::{
+    variables::{CryptoHashVariable, DomainVariable},
+    SyncCircuit,
+};
+
+/// What a sync proof shows, read back from its public inputs.
+#[derive(Debug, Clone)]
+struct SyncStep {
+    domain: DomainVariable,
+    trusted: CryptoHashVariable,
+    synced_domain: DomainVariable,
+    synced: CryptoHashVariable,
+    epoch_id: CryptoHashVariable,
+    next_epoch_id: CryptoHashVariable,
+}
+
+impl SyncStep {
+    /// The sync circuit's public inputs are its input bytes then its output
+    /// bytes, in the order they were read and written.
+    fn from_proof<const D: usize>(proof: &ProofWithPublicInputsTarget<D>) -> Self {
+        let mut targets = proof.public_inputs.iter().copied();
+        let mut next = |n: usize| targets.by_ref().take(n).collect::<Vec<_>>();
+        let hash_len = CryptoHashVariable::nb_elements();
+        let domain_len = DomainVariable::nb_elements();
+
+        let step = Self {
+            domain: DomainVariable::from_targets(&next(domain_len)),
+            trusted: CryptoHashVariable::from_targets(&next(hash_len)),
+            synced_domain: DomainVariable::from_targets(&next(domain_len)),
+            synced: CryptoHashVariable::from_targets(&next(hash_len)),
+            epoch_id: CryptoHashVariable::from_targets(&next(hash_len)),
+            next_epoch_id: CryptoHashVariable::from_targets(&next(hash_len)),
+        };
+        assert!(
+            next(1).is_empty(),
+            "the sync circuit has more public inputs"
+        );
+        step
+    }
+}
+
+/// Recursively verifies `K` consecutive sync proofs, so a run of heads can be
+/// accepted with a single proof rather than one each.
+///
+/// Each proof must sync from the head the one before it synced to, for the
+/// same domain. The outputs are those of a sync straight from the first
+/// trusted head to the last synced one: the domain, the trusted header hash,
+/// the synced header hash and its epoch id and next epoch id. The sync
+/// proofs are of the circuit built with `KEY_POLICY`.
+#[derive(Debug, Clone)]
+pub struct AggregateSyncCircuit<
+    const K: usize,
+    const NETWORK: usize,
+    const KEY_POLICY: u8 = { KeyPolicy::Inactive as u8 },
+>;
+
+impl<const K: usize, const NETWORK: usize, const KEY_POLICY: u8> Circuit
+    for AggregateSyncCircuit<K, NETWORK, KEY_POLICY>
+{
+    fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher:
+            AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
+    {
+        assert!(K > 0, "nothing to aggregate");
+        let mut child = CircuitBuilder::<L, D>::new();
+        SyncCircuit::<NETWORK, false, KEY_POLICY>::define(&mut child);
+        let child = child.build();
+        let verifier_data = b.constant_verifier_data::<L>(&child.data);
+
+        let steps = (0..K)
+            .map(|_| {
+                let proof = b.proof_read(&child);
+                b.verify_proof::<L>(&proof, &verifier_data, &child.data.common);
+                SyncStep::from_proof(&proof)
+            })
+            .collect::<Vec<_>>();
+
+        let first = &steps[0];
+        for step in &steps {
+            b.assert_is_equal(step.domain, first.domain);
+            b.assert_is_equal(step.synced_domain, first.domain);
+        }
+        for (prev, next) in steps.iter().zip(&steps[1..]) {
+            b.assert_is_equal(prev.synced, next.trusted);
+        }
+
+        let last = &steps[K - 1];
+        b.watch(&last.synced, "aggregated_synced");
+        b.proof_write(first.domain);
+        b.proof_write(first.trusted);
+        b.proof_write(last.synced);
+        b.proof_write(last.epoch_id);
+        b.proof_write(last.next_epoch_id);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(
+        _registry: &mut HintRegistry<L, D>,
+    ) where
+        <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher: AlgebraicHasher<L::Field>,
+    {
+        // The sync proofs are given, none of their hints run here
+    }
+}
+
+#[cfg(test)]
+mod beefy_tests {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::{
+        test_utils::{testnet_state, DOMAIN, NETWORK},
+        variables::domain_from_chain_id,
+    };
+
+    type L = DefaultParameters;
+    const D: usize = 2;
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_aggregate_sync_e2e() {
+        pretty_env_logger::try_init().unwrap_or_default();
+        let (header, _, _) = testnet_state();
+        let domain = domain_from_chain_id(DOMAIN);
+
+        let mut b = CircuitBuilder::<L, D>::new();
+        SyncCircuit::<NETWORK>::define(&mut b);
+        let sync = b.build();
+
+        // Two consecutive syncs from the trusted header
+        let mut trusted = header.hash().0;
+        let mut epochs = (Default::default(), Default::default());
+        let mut proofs = vec![];
+        for _ in 0..2 {
+            let mut input = sync.input();
+            input.evm_write::<DomainVariable>(domain.into());
+            input.evm_write::<CryptoHashVariable>(trusted.into());
+            let (proof, mut output) = sync.prove(&input);
+            let _ = output.evm_read::<DomainVariable>();
+            trusted = output.evm_read::<CryptoHashVariable>().0;
+            epochs = (
+                output.evm_read::<CryptoHashVariable>(),
+                output.evm_read::<CryptoHashVariable>(),
+            );
+            proofs.push(proof);
+        }
+
+        let mut b = CircuitBuilder::<L, D>::new();
+        AggregateSyncCircuit::<2, NETWORK>::define(&mut b);
+        let aggregate = b.build();
+        let mut input = aggregate.input();
+        for proof in proofs {
+            input.proof_write(proof);
+        }
+        let (_, mut output) = aggregate.prove(&input);
+
+        assert_eq!(output.proof_read::<DomainVariable>(), domain.into());
+        assert_eq!(
+            output.proof_read::<CryptoHashVariable>(),
+            header.hash().0.into()
+        );
+        assert_eq!(output.proof_read::<CryptoHashVariable>(), trusted.into());
+        assert_eq!(output.proof_read::<CryptoHashVariable>(), epochs.0);
+        assert_eq!(output.proof_read::<CryptoHashVariable>(), epochs.1);
+    }
+}
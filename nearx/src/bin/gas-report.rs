@@ -0,0 +1,45 @@
+use near_light_client_protocol::{prelude::Itertools, LightClientBlockView, ValidatorStake};
+use near_light_clientx::gas::{compare_layouts, report, GasSchedule};
+
+/// Compares the on-chain cost of each sync output layout.
+///
+/// Usage: gas-report <head.json> <next.json>
+///
+/// Both files should be `LightClientBlockView`s as returned by the
+/// `next_light_client_block` RPC method.
+fn main() {
+    let args = std::env::args().skip(1).collect_vec();
+    assert!(args.len() == 2, "usage: gas-report <head.json> <next.json>");
+
+    let read = |path: &str| -> LightClientBlockView {
+        serde_json::from_reader(std::fs::File::open(path).expect("failed to open block"))
+            .expect("failed to parse block")
+    };
+    let head = read(&args[0]);
+    let next = read(&args[1]);
+
+    let into_bps = |block: &LightClientBlockView| -> Vec<ValidatorStake> {
+        block
+            .next_bps
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    };
+
+    let new_head = near_light_client_protocol::prelude::Header {
+        prev_block_hash: next.prev_block_hash,
+        inner_rest_hash: next.inner_rest_hash,
+        inner_lite: next.inner_lite.clone(),
+    };
+
+    let estimates = compare_layouts(
+        &GasSchedule::default(),
+        &new_head,
+        &head.inner_lite.next_epoch_id,
+        &into_bps(&next),
+        &into_bps(&head),
+    );
+    print!("{}", report(&estimates));
+}
@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use near_light_client_protocol::prelude::Itertools;
+use near_light_client_rpc::Network;
+use near_light_clientx::genesis::Genesis;
+
+const USAGE: &str =
+    "usage: genesis --network <network> --height <height> --chain-id <chain id> [--out <dir>]";
+
+/// Produces the payload a new verifier contract is initialised with, writing
+/// `genesis.json` and the `genesis.env` that `Initialise.s.sol` reads.
+///
+/// Usage: genesis --network <network> --height <height> --chain-id <chain id>
+/// [--out <dir>]
+///
+/// The checkpoint is the block at the height. Run from the workspace root, the
+/// output defaults to `build/genesis`.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect_vec();
+    let flag = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let (Some(network), Some(height), Some(chain_id)) =
+        (flag("--network"), flag("--height"), flag("--chain-id"))
+    else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    };
+    let network = match network.as_str() {
+        "mainnet" => Network::Mainnet,
+        "testnet" => Network::Testnet,
+        "statelessnet" => Network::Statelessnet,
+        _ => {
+            eprintln!("unknown network {}\n{}", network, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let out = PathBuf::from(flag("--out").map_or("build/genesis", |o| o.as_str()));
+
+    let genesis = Genesis::build(network, height.parse()?, chain_id.parse()?).await?;
+    let unrecorded = genesis.unrecorded();
+    if !unrecorded.is_empty() {
+        eprintln!(
+            "warning: no digest is recorded for {}, run `repro --update` before registering \
+             them",
+            unrecorded.join(", ")
+        );
+    }
+
+    std::fs::create_dir_all(&out)?;
+    std::fs::write(
+        out.join("genesis.json"),
+        serde_json::to_string_pretty(&genesis)? + "\n",
+    )?;
+    std::fs::write(out.join("genesis.env"), genesis.env())?;
+    println!(
+        "checkpoint {} at {}, written to {}",
+        genesis.checkpoint.hash,
+        genesis.checkpoint.height,
+        out.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::{path::PathBuf, str::FromStr};
+
+use near_light_client_protocol::prelude::{CryptoHash, Itertools};
+use near_light_clientx::{
+    journal::Journal,
+    range::{prove_range, RangeConfig},
+    repro::NETWORK,
+};
+use tokio::sync::mpsc;
+
+/// Re-proves the sync chain from a trusted header up to a height, writing a
+/// proof for each step to `<out dir>/<height>.json` as it is made. Each step
+/// is journaled in `<out dir>/journal.jsonl`, see `replay-journal`.
+///
+/// Usage: prove-range <trusted header hash> <until height> <chain id> <out dir>
+/// [capacity]
+///
+/// The capacity is how many steps fetching may run ahead of proving.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect_vec();
+    let (from, until, chain_id, out, capacity) = match &args[..] {
+        [from, until, chain_id, out] => (from, until, chain_id, out, "4"),
+        [from, until, chain_id, out, capacity] => (from, until, chain_id, out, &capacity[..]),
+        _ => {
+            eprintln!(
+                "usage: prove-range <trusted header hash> <until height> <chain id> <out dir> \
+                 [capacity]"
+            );
+            std::process::exit(2);
+        }
+    };
+
+    let config = RangeConfig {
+        chain_id: chain_id.parse()?,
+        from: CryptoHash::from_str(from).map_err(|e| anyhow::anyhow!("{}", e))?,
+        until: until.parse()?,
+        capacity: capacity.parse()?,
+    };
+    let out = PathBuf::from(out);
+    std::fs::create_dir_all(&out)?;
+    let journal = Journal::open(out.join("journal.jsonl"))?;
+
+    let (tx, mut rx) = mpsc::channel(config.capacity);
+    let write = async move {
+        while let Some(proven) = rx.recv().await {
+            let path = out.join(format!("{}.json", proven.step.height));
+            std::fs::write(&path, serde_json::to_string(&proven)?)?;
+            println!(
+                "{} -> {} at {}: {}",
+                proven.step.trusted,
+                proven.step.synced,
+                proven.step.height,
+                path.display()
+            );
+        }
+        Ok::<_, nearx_error::Error>(())
+    };
+    tokio::try_join!(prove_range::<NETWORK>(config, tx, Some(journal)), write)?;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use near_light_client_protocol::prelude::Itertools;
+use near_light_clientx::journal::{read, replay_named};
+
+/// Replays a journal of proving jobs in mock mode, in the order they were
+/// proven, and checks each job's outputs still match their commitment.
+///
+/// Usage: replay-journal <journal.jsonl>
+///
+/// The journal is checked to be unbroken and unmodified first. Exits non zero
+/// if any outputs differ.
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect_vec();
+    let [path] = &args[..] else {
+        eprintln!("usage: replay-journal <journal.jsonl>");
+        std::process::exit(2);
+    };
+
+    let entries = read(path)?;
+    let mismatches = replay_named(&entries)?;
+    if mismatches.is_empty() {
+        println!("{} jobs replayed, every output matches", entries.len());
+        return Ok(());
+    }
+    for m in &mismatches {
+        println!(
+            "{} {}: expected {} but got {}",
+            m.seq, m.circuit, m.expected, m.actual
+        );
+    }
+    eprintln!("{} of {} jobs differ", mismatches.len(), entries.len());
+    std::process::exit(1);
+}
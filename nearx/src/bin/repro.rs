@@ -0,0 +1,63 @@
+use near_light_client_protocol::prelude::Itertools;
+use near_light_clientx::repro::{circuits, Manifest, Verdict, MANIFEST_PATH, OUTPUT_VERSION, PROFILE};
+
+/// Rebuilds every deployed circuit and checks its verifier key digest against
+/// the committed manifest, exiting non zero if any differ or are unrecorded.
+///
+/// Usage: repro [--update] [circuit..]
+///
+/// Run from the workspace root, `--update` records the digests instead. This
+/// needs as much memory as building the circuits for deployment.
+fn main() {
+    if PROFILE != "prod" {
+        eprintln!("The manifest only records prod circuits, not {}", PROFILE);
+        std::process::exit(1);
+    }
+    let args = std::env::args().skip(1).collect_vec();
+    let update = args.iter().any(|a| a == "--update");
+    let only = args.iter().filter(|a| *a != "--update").collect_vec();
+
+    let mut manifest = Manifest::load(MANIFEST_PATH).expect("failed to load manifest");
+    let mut failed = false;
+    if update {
+        manifest.output_version = OUTPUT_VERSION.to_string();
+    } else if manifest.output_version != OUTPUT_VERSION {
+        println!(
+            "The manifest was recorded at output version {}, not {}",
+            manifest.output_version, OUTPUT_VERSION
+        );
+        failed = true;
+    }
+    for (name, digest) in circuits() {
+        if !only.is_empty() && !only.iter().any(|o| *o == name) {
+            continue;
+        }
+        println!("Building {}", name);
+        let digest = digest();
+
+        if update {
+            println!("{}: {}", name, digest);
+            manifest.record(name, digest);
+            continue;
+        }
+        match manifest.check(name, &digest) {
+            Verdict::Match => println!("{}: {} matches", name, digest),
+            Verdict::Unrecorded => {
+                println!("{}: {} is not recorded", name, digest);
+                failed = true;
+            }
+            Verdict::Mismatch { expected } => {
+                println!("{}: {} does not match {}", name, digest, expected);
+                failed = true;
+            }
+        }
+    }
+
+    if update {
+        manifest
+            .save(MANIFEST_PATH)
+            .expect("failed to save manifest");
+    } else if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+
+use near_light_client_protocol::prelude::Itertools;
+use near_light_clientx::trace::{capture_watches, diff, record_named, Trace};
+
+const USAGE: &str = "usage: trace-witness record <circuit> <request.json> <trace.json>\n       \
+                     trace-witness diff <before.json> <after.json>";
+
+/// Records what a circuit computes for a request in mock mode, or diffs two
+/// of these recordings.
+///
+/// Usage: trace-witness record <circuit> <request.json> <trace.json>
+///        trace-witness diff <before.json> <after.json>
+///
+/// Record the same request with the build before a dependency bump and the
+/// build after it, then diff the traces. The request is a prover request, as
+/// the tests write to `build/input.json`. Diffing exits non zero if anything
+/// differs.
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect_vec();
+    match &args.iter().map(String::as_str).collect_vec()[..] {
+        ["record", circuit, request, out] => {
+            capture_watches()?;
+            let request = serde_json::from_str(&fs::read_to_string(request)?)?;
+            let trace = record_named(circuit, request)?;
+            fs::write(out, serde_json::to_string_pretty(&trace)? + "\n")?;
+            println!(
+                "{}: {} outputs and {} watched values, written to {}",
+                circuit,
+                trace.outputs.len(),
+                trace.watched.len(),
+                out
+            );
+        }
+        ["diff", before, after] => {
+            let load = |path: &str| -> anyhow::Result<Trace> {
+                Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+            };
+            let differences = diff(&load(before)?, &load(after)?);
+            if differences.is_empty() {
+                println!("The traces agree");
+                return Ok(());
+            }
+            for difference in &differences {
+                println!("{}", difference);
+            }
+            eprintln!("{} differences", differences.len());
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
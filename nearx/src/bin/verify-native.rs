@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+use near_light_client_protocol::{
+    experimental,
+    prelude::{BasicProof, BorshDeserialize, CryptoHash, Header, Itertools},
+    Protocol,
+};
+use serde_json::Value;
+
+/// Verifies an inclusion proof natively, without the circuits, printing each
+/// check. If a proof passes here but not in the verify circuit, the circuit
+/// is at fault rather than the data.
+///
+/// Usage: verify-native <proof> <trusted head>
+///
+/// The proof can be the json from the `light_client_proof` RPC method, with or
+/// without the json-rpc envelope, a proof served by the light client, or a
+/// borsh encoded batch proof, hex encoded if the file ends in `.hex`. The
+/// trusted head is a block merkle root, or a json header such as the
+/// `next_light_client_block` RPC result.
+fn main() {
+    let args = std::env::args().skip(1).collect_vec();
+    let [proof, head] = &args[..] else {
+        eprintln!("usage: verify-native <proof> <trusted head>");
+        std::process::exit(2);
+    };
+
+    let root = trusted_root(head);
+    println!("trusted root: {}", root);
+
+    let checks = match read_proof(proof) {
+        Proof::Basic {
+            head_block_root,
+            proof,
+        } => {
+            let mut checks = vec![];
+            if let Some(head_block_root) = head_block_root {
+                checks.push(("head_root_trusted", head_block_root == root));
+            }
+            checks.extend(Protocol::inclusion_checks(&root, &proof).iter());
+            checks
+        }
+        Proof::Experimental(proof) => vec![
+            ("head_root_trusted", proof.head_block_root == root),
+            ("batch_verified", experimental::verify_proof(proof)),
+        ],
+    };
+
+    for (check, ok) in &checks {
+        println!("{:<20} {}", check, if *ok { "ok" } else { "FAILED" });
+    }
+    if !checks.iter().all(|(_, ok)| *ok) {
+        std::process::exit(1);
+    }
+}
+
+/// The root proofs must be against, given directly or as a header.
+fn trusted_root(head: &str) -> CryptoHash {
+    if let Ok(root) = CryptoHash::from_str(head) {
+        return root;
+    }
+    let header: Header =
+        serde_json::from_value(read_json(head)).expect("failed to parse the trusted head");
+    println!("trusted head: {} at {}", header.hash(), header.inner_lite.height);
+    header.inner_lite.block_merkle_root
+}
+
+/// A proof, where the head it was proven against is only known for proofs
+/// served by the light client.
+enum Proof {
+    Basic {
+        head_block_root: Option<CryptoHash>,
+        proof: Box<BasicProof>,
+    },
+    Experimental(experimental::Proof),
+}
+
+fn read_proof(path: &str) -> Proof {
+    if path.ends_with(".json") {
+        let json = read_json(path);
+        if let Ok(proof) = serde_json::from_value::<BasicProof>(json.clone()) {
+            return Proof::Basic {
+                head_block_root: None,
+                proof: proof.into(),
+            };
+        }
+        return match serde_json::from_value(json).expect("failed to parse the proof") {
+            near_light_client_protocol::Proof::Basic {
+                head_block_root,
+                proof,
+            } => Proof::Basic {
+                head_block_root: Some(head_block_root),
+                proof,
+            },
+            near_light_client_protocol::Proof::Experimental(proof) => Proof::Experimental(proof),
+        };
+    }
+
+    let bytes = std::fs::read(path).expect("failed to read the proof");
+    let bytes = if path.ends_with(".hex") {
+        hex::decode(String::from_utf8_lossy(&bytes).trim()).expect("failed to decode hex")
+    } else {
+        bytes
+    };
+    Proof::Experimental(
+        experimental::Proof::try_from_slice(&bytes).expect("failed to decode the batch proof"),
+    )
+}
+
+/// Read json, taking the result out of a json-rpc response.
+fn read_json(path: &str) -> Value {
+    let file = std::fs::File::open(path).expect("failed to open file");
+    let mut json: Value = serde_json::from_reader(file).expect("failed to parse json");
+    match json.get_mut("result") {
+        Some(result) => result.take(),
+        None => json,
+    }
+}
@@ -0,0 +1,47 @@
+use std::fs;
+
+use near_light_client_protocol::prelude::Itertools;
+use near_light_clientx::wrap::{build_named, data_dir, System, WrapConfig, Wrapper};
+
+const USAGE: &str =
+    "usage: wrap-proof <circuit> <proof.json> <gnark verifier> <out dir> [groth16|plonk]";
+
+/// Wraps a plonky2 proof of a deployed circuit into a proof over BN254 that
+/// the verifier contract accepts, writing it to
+/// `<out dir>/<circuit>-<system>/evm-proof.json`.
+///
+/// Usage: wrap-proof <circuit> <proof.json> <gnark verifier> <out dir>
+/// [groth16|plonk]
+///
+/// The circuit is named as in the manifest, and the proof is a plonky2 proof
+/// of it, such as the `proof` of a `prove-range` step. Groth16 is the
+/// default.
+fn main() -> anyhow::Result<()> {
+    let args = std::env::args().skip(1).collect_vec();
+    let (circuit, proof, gnark, out, system) = match &args[..] {
+        [circuit, proof, gnark, out] => (circuit, proof, gnark, out, System::default()),
+        [circuit, proof, gnark, out, system] => (circuit, proof, gnark, out, system.parse()?),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let proof = serde_json::from_str(&fs::read_to_string(proof)?)?;
+    let config = WrapConfig {
+        gnark: gnark.into(),
+        dir: data_dir(out, circuit, system),
+        system,
+    };
+    let path = config.dir.join("evm-proof.json");
+    let wrapper = Wrapper::build(build_named(circuit)?, config)?;
+    let wrapped = wrapper.wrap(&proof)?;
+    fs::write(&path, serde_json::to_string_pretty(&wrapped)? + "\n")?;
+    println!(
+        "input hash 0x{}, output hash 0x{}: {}",
+        hex::encode(&wrapped.input_hash),
+        hex::encode(&wrapped.output_hash),
+        path.display()
+    );
+    Ok(())
+}
@@ -1,13 +1,24 @@
-use near_light_client_protocol::{config::NUM_BLOCK_PRODUCER_SEATS, prelude::Itertools};
-use plonky2x::prelude::*;
+use near_light_client_protocol::{
+    config::{MAX_TOTAL_STAKE, NUM_BLOCK_PRODUCER_SEATS},
+    endorsement,
+    prelude::{AccountId, Itertools},
+    signature::{Ed25519, KeyPolicy, VerifiableSignature, SCHEME_REGISTRY, SUPPORTED_SCHEMES},
+};
+use plonky2x::{
+    frontend::vars::EvmVariable,
+    prelude::{plonky2::field::types::Field, *},
+};
 use pretty_assertions::assert_eq;
 
 use crate::{
     merkle::{MerklePathVariable, NearMerkleTree},
     variables::{
-        ApprovalMessage, BlockHeightVariable, BlockVariable, BpsApprovals, BpsArr,
-        BuildEndorsement, CryptoHashVariable, HeaderVariable, ProofVariable, StakeInfoVariable,
-        SyncedVariable, ValidatorStakeVariable,
+        shift_right, variable_to_byte, ApprovalMessage, BalanceVariable, BatchProofVariable,
+        BlindedProofVariable, BlockHeightVariable, BlockVariable, BpsApprovals, BpsArr,
+        BuildEndorsement, ChunkEndorsementsVariable, CryptoHashVariable, EndorsementMessage,
+        HeaderVariable, OutcomeStatusVariable, ProofVariable, PublicKeyVariable, StakeInfoVariable,
+        SyncedVariable, ValidKeys, ValidatorStakeVariable, VALIDATOR_STAKE_ENCODED_LEN,
+        VALIDATOR_STAKE_FIXED_LEN,
     },
 };
 
@@ -31,13 +42,38 @@ pub trait Ensure<L: PlonkParameters<D>, const D: usize> {
         next_bps: &BpsArr<ValidatorStakeVariable>,
     ) -> BoolVariable;
 
-    fn validate_signatures<const LEN: usize>(
+    /// Validate the signatures of each seat over `message`, an approval or a
+    /// chunk endorsement, with signatures by an invalid key handled by
+    /// `policy`.
+    fn validate_signatures<const LEN: usize, const M: usize>(
         &mut self,
         approvals: &BpsApprovals<LEN>,
         bps: &BpsArr<ValidatorStakeVariable, LEN>,
-        approval_message: ApprovalMessage,
+        message: BytesVariable<M>,
+        policy: KeyPolicy,
     ) -> StakeInfoVariable;
 
+    /// Validate signatures with approvals weighted by `weights` rather than
+    /// stake, see `near_light_client_protocol::weights`.
+    fn validate_signatures_weighted<const LEN: usize, const M: usize>(
+        &mut self,
+        approvals: &BpsApprovals<LEN>,
+        bps: &BpsArr<ValidatorStakeVariable, LEN>,
+        weights: &BpsArr<BalanceVariable, LEN>,
+        message: BytesVariable<M>,
+        policy: KeyPolicy,
+    ) -> StakeInfoVariable;
+
+    /// The total and approved weight of the seats, asserting the total
+    /// doesn't wrap.
+    fn sum_stake<const LEN: usize>(
+        &mut self,
+        is_active: &BpsArr<BoolVariable, LEN>,
+        weights: &BpsArr<BalanceVariable, LEN>,
+    ) -> StakeInfoVariable;
+
+    /// False if the total is past `MAX_TOTAL_STAKE`, where the threshold
+    /// would wrap.
     fn ensure_stake_is_sufficient(&mut self, stake: &StakeInfoVariable) -> BoolVariable;
 
     fn ensure_next_bps_is_valid(
@@ -58,6 +94,7 @@ pub trait Ensure<L: PlonkParameters<D>, const D: usize> {
         outcome_proof_block_hash: &CryptoHashVariable,
     ) -> BoolVariable;
 
+    /// Constrains `near_light_client_protocol::verify_outcome_proof`.
     fn verify_outcome<const OD: usize, const ORD: usize>(
         &mut self,
         expected: &CryptoHashVariable,
@@ -66,6 +103,7 @@ pub trait Ensure<L: PlonkParameters<D>, const D: usize> {
         outcome_root_proof: &MerklePathVariable<ORD>,
     ) -> BoolVariable;
 
+    /// Constrains `near_light_client_protocol::verify_block_proof`.
     fn verify_block<const BD: usize>(
         &mut self,
         expected: &CryptoHashVariable,
@@ -108,42 +146,110 @@ impl<L: PlonkParameters<D>, const D: usize> Ensure<L, D> for CircuitBuilder<L, D
         self.select(is_next_epoch, is_not_empty, ok_anyway)
     }
 
-    fn validate_signatures<const LEN: usize>(
+    fn validate_signatures<const LEN: usize, const M: usize>(
+        &mut self,
+        approvals_after_next: &BpsApprovals<LEN>,
+        epoch_bps: &BpsArr<ValidatorStakeVariable, LEN>,
+        message: BytesVariable<M>,
+        policy: KeyPolicy,
+    ) -> StakeInfoVariable {
+        let stakes = epoch_bps.data.iter().map(|vs| vs.stake).collect_vec();
+        self.validate_signatures_weighted(
+            approvals_after_next,
+            epoch_bps,
+            &ArrayVariable::new(stakes),
+            message,
+            policy,
+        )
+    }
+
+    fn validate_signatures_weighted<const LEN: usize, const M: usize>(
         &mut self,
         approvals_after_next: &BpsApprovals<LEN>,
         epoch_bps: &BpsArr<ValidatorStakeVariable, LEN>,
-        approval_message: ApprovalMessage,
+        weights: &BpsArr<BalanceVariable, LEN>,
+        message: BytesVariable<M>,
+        policy: KeyPolicy,
     ) -> StakeInfoVariable {
         assert_eq!(approvals_after_next.is_active.len(), LEN);
         assert_eq!(approvals_after_next.signatures.len(), LEN);
         assert_eq!(epoch_bps.data.len(), LEN);
+        assert_eq!(weights.data.len(), LEN);
+        assert_eq!(
+            SUPPORTED_SCHEMES,
+            [Ed25519::SCHEME],
+            "only ed25519 has a gadget"
+        );
+        // Commit to the schemes and key policy so the verifier key changes
+        // with them
+        self.constant::<Variable>(L::Field::from_canonical_u64(SCHEME_REGISTRY));
+        self.constant::<Variable>(L::Field::from_canonical_u8(policy as u8));
 
-        let messages = [approval_message; LEN];
-
-        let mut pubkeys = vec![];
-        let mut total_stake = self.zero();
-        let mut approved_stake = self.zero();
+        let messages = [message; LEN];
 
-        for i in 0..LEN {
-            let vs = &epoch_bps.data[i];
+        let pubkeys = epoch_bps
+            .data
+            .iter()
+            .map(|vs| vs.public_key.clone())
+            .collect_vec();
 
-            pubkeys.push(vs.public_key.clone());
+        // The validity of the keys is hinted, a key off the curve can't be
+        // claimed valid as the gadget fails to decompress it. Claiming a
+        // valid key invalid only drops its approval, as leaving out its
+        // signature would, and under `Reject` fails the proof.
+        let mut input_stream = VariableStream::new();
+        input_stream.write(&BpsArr::<PublicKeyVariable, LEN>::new(pubkeys.clone()));
+        let output_stream = self.hint(input_stream, ValidKeys::<LEN>);
+        let valid_keys = output_stream.read::<BpsArr<BoolVariable, LEN>>(self);
 
-            let maybe_add = self.add(approved_stake, vs.stake);
-            approved_stake =
-                self.select(approvals_after_next.is_active[i], maybe_add, approved_stake);
-            total_stake = self.add(total_stake, vs.stake);
+        let mut is_active = vec![];
+        for i in 0..LEN {
+            let active = approvals_after_next.is_active[i];
+            if policy == KeyPolicy::Reject {
+                let invalid = self.not(valid_keys[i]);
+                let rejected = self.and(active, invalid);
+                let f = self._false();
+                self.assert_is_equal(rejected, f);
+            }
+            is_active.push(self.and(active, valid_keys[i]));
         }
+        let is_active = BpsArr::<BoolVariable, LEN>::new(is_active);
+        let stake = self.sum_stake(&is_active, weights);
 
         // TODO: what happens if a conditionally active signature fails?
         self.curta_eddsa_verify_sigs_conditional(
-            approvals_after_next.is_active.clone(),
+            is_active,
             None,
             ArrayVariable::new(messages.to_vec()),
             approvals_after_next.signatures.clone(),
             ArrayVariable::new(pubkeys),
         );
 
+        stake
+    }
+
+    fn sum_stake<const LEN: usize>(
+        &mut self,
+        is_active: &BpsArr<BoolVariable, LEN>,
+        weights: &BpsArr<BalanceVariable, LEN>,
+    ) -> StakeInfoVariable {
+        let mut total_stake = self.zero();
+        let mut approved_stake = self.zero();
+        let mut in_range = self._true();
+
+        for i in 0..LEN {
+            let maybe_add = self.add(approved_stake, weights.data[i]);
+            approved_stake = self.select(is_active[i], maybe_add, approved_stake);
+
+            // A wrapped sum is less than the one before it, the approved stake
+            // is never more than the total so it can't wrap without it
+            let next_total = self.add(total_stake, weights.data[i]);
+            let no_wrap = self.gte(next_total, total_stake);
+            in_range = self.and(in_range, no_wrap);
+            total_stake = next_total;
+        }
+        self.assertx(in_range);
+
         StakeInfoVariable {
             total: total_stake,
             approved: approved_stake,
@@ -151,13 +257,17 @@ impl<L: PlonkParameters<D>, const D: usize> Ensure<L, D> for CircuitBuilder<L, D
     }
 
     fn ensure_stake_is_sufficient(&mut self, stake: &StakeInfoVariable) -> BoolVariable {
+        let max_total = self.constant::<BalanceVariable>(MAX_TOTAL_STAKE);
+        let in_range = self.lte(stake.total, max_total);
+
         // 2/3 stake
         let numerator = self.constant(2.into());
         let denominator = self.constant(3.into());
 
         let threshold = self.mul(stake.total, numerator);
         let approved = self.mul(stake.approved, denominator);
-        self.gte(approved, threshold)
+        let sufficient = self.gte(approved, threshold);
+        self.and(in_range, sufficient)
     }
 
     fn ensure_next_bps_is_valid(
@@ -196,19 +306,11 @@ impl<L: PlonkParameters<D>, const D: usize> Ensure<L, D> for CircuitBuilder<L, D
         outcome_hash: &CryptoHashVariable,
         outcome_root_proof: &MerklePathVariable<ORD>,
     ) -> BoolVariable {
-        let outcome_root = self.get_root_from_merkle_proof_hashed_leaf_unindex(
-            &outcome_proof.path,
-            &outcome_proof.indices,
-            *outcome_hash,
-        );
+        let outcome_root = self.get_root_from_merkle_path(outcome_proof, *outcome_hash);
 
         let leaf = self.curta_sha256(&outcome_root.0 .0);
 
-        let outcome_root = self.get_root_from_merkle_proof_hashed_leaf_unindex(
-            &outcome_root_proof.path,
-            &outcome_root_proof.indices,
-            leaf,
-        );
+        let outcome_root = self.get_root_from_merkle_path(outcome_root_proof, leaf);
         self.is_equal(outcome_root, *expected)
     }
 
@@ -218,11 +320,7 @@ impl<L: PlonkParameters<D>, const D: usize> Ensure<L, D> for CircuitBuilder<L, D
         block_proof: &MerklePathVariable<BD>,
         block_hash: &CryptoHashVariable,
     ) -> BoolVariable {
-        let block_root = self.get_root_from_merkle_proof_hashed_leaf_unindex(
-            &block_proof.path,
-            &block_proof.indices,
-            *block_hash,
-        );
+        let block_root = self.get_root_from_merkle_path(block_proof, *block_hash);
         self.is_equal(block_root, *expected)
     }
 
@@ -233,13 +331,38 @@ impl<L: PlonkParameters<D>, const D: usize> Ensure<L, D> for CircuitBuilder<L, D
 }
 
 pub trait Sync<L: PlonkParameters<D>, const D: usize> {
+    /// Constrains `near_light_client_protocol::Protocol::sync` under `policy`.
     fn sync(
         &mut self,
         head: &HeaderVariable,
         epoch_bps: &BpsArr<ValidatorStakeVariable>,
         next_block: &BlockVariable,
+        policy: KeyPolicy,
+    ) -> SyncedVariable;
+
+    /// Sync with approvals weighted by `weights`, a custom circuit should
+    /// expose `hash_weights` of them as a public input.
+    fn sync_weighted(
+        &mut self,
+        head: &HeaderVariable,
+        epoch_bps: &BpsArr<ValidatorStakeVariable>,
+        weights: &BpsArr<BalanceVariable>,
+        next_block: &BlockVariable,
+        policy: KeyPolicy,
     ) -> SyncedVariable;
 
+    /// The params hash of the weights, the same as
+    /// `near_light_client_protocol::weights::params_hash`.
+    fn hash_weights(
+        &mut self,
+        epoch_bps: &BpsArr<ValidatorStakeVariable>,
+        weights: &BpsArr<BalanceVariable>,
+    ) -> CryptoHashVariable;
+
+    /// The hash of the borsh encoded BPS, as `next_bp_hash` commits to, leaving
+    /// out padding. Asserts the padding is only at the end and has no stake.
+    fn hash_bps(&mut self, bps: &BpsArr<ValidatorStakeVariable>) -> CryptoHashVariable;
+
     fn reconstruct_approval_message(&mut self, next_block: &BlockVariable) -> ApprovalMessage;
 }
 
@@ -249,6 +372,25 @@ impl<L: PlonkParameters<D>, const D: usize> Sync<L, D> for CircuitBuilder<L, D>
         head: &HeaderVariable,
         epoch_bps: &BpsArr<ValidatorStakeVariable>,
         next_block: &BlockVariable,
+        policy: KeyPolicy,
+    ) -> SyncedVariable {
+        let stakes = epoch_bps.data.iter().map(|vs| vs.stake).collect_vec();
+        self.sync_weighted(
+            head,
+            epoch_bps,
+            &ArrayVariable::new(stakes),
+            next_block,
+            policy,
+        )
+    }
+
+    fn sync_weighted(
+        &mut self,
+        head: &HeaderVariable,
+        epoch_bps: &BpsArr<ValidatorStakeVariable>,
+        weights: &BpsArr<BalanceVariable>,
+        next_block: &BlockVariable,
+        policy: KeyPolicy,
     ) -> SyncedVariable {
         let a = self.ensure_not_already_verified(head, &next_block.header.inner_lite.height);
         self.assertx(a);
@@ -264,7 +406,13 @@ impl<L: PlonkParameters<D>, const D: usize> Sync<L, D> for CircuitBuilder<L, D>
         self.assertx(c);
 
         let approval = self.reconstruct_approval_message(next_block);
-        let stake = self.validate_signatures(&next_block.approvals_after_next, epoch_bps, approval);
+        let stake = self.validate_signatures_weighted(
+            &next_block.approvals_after_next,
+            epoch_bps,
+            weights,
+            approval,
+            policy,
+        );
         let d = self.ensure_stake_is_sufficient(&stake);
         self.assertx(d);
 
@@ -285,6 +433,86 @@ impl<L: PlonkParameters<D>, const D: usize> Sync<L, D> for CircuitBuilder<L, D>
         }
     }
 
+    fn hash_weights(
+        &mut self,
+        epoch_bps: &BpsArr<ValidatorStakeVariable>,
+        weights: &BpsArr<BalanceVariable>,
+    ) -> CryptoHashVariable {
+        let mut bytes = vec![];
+        for (vs, weight) in epoch_bps.data.iter().zip(weights.data.iter()) {
+            bytes.extend_from_slice(&vs.account_id.0);
+            bytes.extend(weight.encode(self));
+        }
+        self.curta_sha256(&bytes)
+    }
+
+    fn hash_bps(&mut self, bps: &BpsArr<ValidatorStakeVariable>) -> CryptoHashVariable {
+        // The u32 count of validators, then each of them
+        const MAX_LEN: usize = 4 + NUM_BLOCK_PRODUCER_SEATS * VALIDATOR_STAKE_ENCODED_LEN;
+        let zero = self.zero::<Variable>();
+        let no_stake = self.constant::<BalanceVariable>(0);
+        let fixed_len =
+            self.constant::<Variable>(L::Field::from_canonical_usize(VALIDATOR_STAKE_FIXED_LEN));
+
+        let mut message = vec![zero; MAX_LEN];
+        let mut count = zero;
+        let mut len = self.constant::<Variable>(L::Field::from_canonical_usize(4));
+        // Where a validator starts only varies by the account ids before it
+        let mut account_ids_len = zero;
+        let mut was_active = self._true();
+        for (seat, vs) in bps.data.iter().enumerate() {
+            let is_active = vs.is_active(self);
+            let is_padding = self.not(is_active);
+            let ordered = self.or(is_padding, was_active);
+            self.assertx(ordered);
+            let unstaked = self.is_equal(vs.stake, no_stake);
+            let weightless = self.or(is_active, unstaked);
+            self.assertx(weightless);
+
+            let (encoded, encoded_len) = vs.encode_borsh(self);
+            let encoded = encoded
+                .into_iter()
+                .map(|byte| self.select(is_active, byte, zero))
+                .collect_vec();
+
+            // Each account id before this one is at most 64 bytes
+            let max_shift = seat * AccountId::MAX_LEN;
+            let shift_bits = (usize::BITS - max_shift.leading_zeros()) as usize;
+            let start = 4 + seat * VALIDATOR_STAKE_FIXED_LEN;
+            let placed = shift_right(
+                self,
+                &encoded,
+                account_ids_len,
+                shift_bits,
+                VALIDATOR_STAKE_ENCODED_LEN + max_shift,
+            );
+            // The encodings don't overlap, so they can be summed into place
+            for (i, byte) in placed.into_iter().enumerate() {
+                message[start + i] = self.add(message[start + i], byte);
+            }
+
+            let encoded_len = self.select(is_active, encoded_len, zero);
+            len = self.add(len, encoded_len);
+            let account_len = self.sub(encoded_len, fixed_len);
+            let account_len = self.select(is_active, account_len, zero);
+            account_ids_len = self.add(account_ids_len, account_len);
+            count = self.add(count, is_active.variable);
+            was_active = is_active;
+        }
+        // The count fits in the first byte
+        message[0] = count;
+
+        let mut bytes = message
+            .into_iter()
+            .map(|byte| variable_to_byte(self, byte))
+            .collect_vec();
+        // Room for the SHA-256 padding of the longest message
+        let zero_byte = self.constant::<ByteVariable>(0);
+        bytes.resize((MAX_LEN + 9).div_ceil(64) * 64, zero_byte);
+        let len = U32Variable::from_variables_unsafe(&[len]);
+        self.curta_sha256_variable(&bytes, len)
+    }
+
     fn reconstruct_approval_message(&mut self, next_block: &BlockVariable) -> ApprovalMessage {
         let next_header_hash = next_block.header.hash(self);
         let next_block_hash =
@@ -311,8 +539,69 @@ impl<L: PlonkParameters<D>, const D: usize> Sync<L, D> for CircuitBuilder<L, D>
     }
 }
 
+/// Chunk endorsements, see `near_light_client_protocol::endorsement`.
+pub trait Endorse<L: PlonkParameters<D>, const D: usize> {
+    /// The message chunk validators sign endorsing `chunk_hash`.
+    fn endorsement_message(&mut self, chunk_hash: &CryptoHashVariable) -> EndorsementMessage;
+
+    /// Whether more than 2/3 of the stake assigned to the chunk endorsed it,
+    /// along with the assigned and endorsed stake.
+    fn verify_chunk_endorsements<const LEN: usize>(
+        &mut self,
+        endorsements: &ChunkEndorsementsVariable<LEN>,
+        policy: KeyPolicy,
+    ) -> (BoolVariable, StakeInfoVariable);
+}
+
+impl<L: PlonkParameters<D>, const D: usize> Endorse<L, D> for CircuitBuilder<L, D> {
+    fn endorsement_message(&mut self, chunk_hash: &CryptoHashVariable) -> EndorsementMessage {
+        let mut bytes = chunk_hash.as_bytes().to_vec();
+        let differentiator =
+            borsh::to_vec(endorsement::SIGNATURE_DIFFERENTIATOR).expect("strings always serialize");
+        for byte in differentiator {
+            bytes.push(self.constant::<ByteVariable>(byte));
+        }
+        let bytes: [ByteVariable; endorsement::MESSAGE_LEN] = bytes.try_into().unwrap();
+        BytesVariable(bytes)
+    }
+
+    fn verify_chunk_endorsements<const LEN: usize>(
+        &mut self,
+        endorsements: &ChunkEndorsementsVariable<LEN>,
+        policy: KeyPolicy,
+    ) -> (BoolVariable, StakeInfoVariable) {
+        let message = self.endorsement_message(&endorsements.chunk_hash);
+        let stake = self.validate_signatures(
+            &endorsements.endorsements,
+            &endorsements.assignments,
+            message,
+            policy,
+        );
+        (self.ensure_stake_is_sufficient(&stake), stake)
+    }
+}
+
 pub trait Verify<L: PlonkParameters<D>, const D: usize> {
     fn verify(&mut self, proof: ProofVariable) -> BoolVariable;
+
+    /// The status and gas burnt of a proof's outcome, `verify` checks the
+    /// partial outcome they're read from.
+    fn outcome_status(&mut self, proof: &ProofVariable) -> OutcomeStatusVariable;
+
+    /// Verify a proof of the batch against its head block root, without
+    /// asserting it.
+    fn verify_blinded(
+        &mut self,
+        head_block_root: &CryptoHashVariable,
+        proof: &BlindedProofVariable,
+    ) -> BoolVariable;
+
+    /// Asserts every active proof in the batch verifies, returning which were
+    /// verified.
+    fn verify_batch<const N: usize>(
+        &mut self,
+        batch: &BatchProofVariable<N>,
+    ) -> ArrayVariable<BoolVariable, N>;
 }
 
 impl<L: PlonkParameters<D>, const D: usize> Verify<L, D> for CircuitBuilder<L, D> {
@@ -321,10 +610,21 @@ impl<L: PlonkParameters<D>, const D: usize> Verify<L, D> for CircuitBuilder<L, D
 
         let block_hash_matches = self.is_equal(block_hash, proof.outcome_proof_block_hash);
 
+        // The partial outcome is the second hash, after the id
+        let partial_hash = proof.outcome.hash(self);
+        let mut partial_matches = self.is_equal(partial_hash, proof.outcome_hashes.hashes[1]);
+        for short in 0..2 {
+            let short = self.constant::<Variable>(L::Field::from_canonical_usize(short));
+            let is_short = self.is_equal(proof.outcome_hashes.len, short);
+            let not_short = self.not(is_short);
+            partial_matches = self.and(partial_matches, not_short);
+        }
+
+        let outcome_hash = proof.outcome_hashes.hash(self);
         let outcome_matches = self.verify_outcome(
             &proof.block_header.inner_lite.outcome_root,
             &proof.outcome_proof,
-            &proof.outcome_hash,
+            &outcome_hash,
             &proof.outcome_root_proof,
         );
 
@@ -332,10 +632,57 @@ impl<L: PlonkParameters<D>, const D: usize> Verify<L, D> for CircuitBuilder<L, D
             self.verify_block(&proof.head_block_root, &proof.block_proof, &block_hash);
 
         let comp = self.and(block_matches, outcome_matches);
+        let comp = self.and(comp, partial_matches);
         let verified = self.and(comp, block_hash_matches);
         self.assertx(verified);
         verified
     }
+
+    fn outcome_status(&mut self, proof: &ProofVariable) -> OutcomeStatusVariable {
+        proof.outcome.decode(self)
+    }
+
+    fn verify_blinded(
+        &mut self,
+        head_block_root: &CryptoHashVariable,
+        proof: &BlindedProofVariable,
+    ) -> BoolVariable {
+        let block_hash = proof.header.hash(self);
+
+        let block_hash_matches = self.is_equal(block_hash, proof.outcome_proof_block_hash);
+
+        let outcome_matches = self.verify_outcome(
+            &proof.header.outcome_root,
+            &proof.outcome_proof,
+            &proof.outcome_hash,
+            &proof.outcome_root_proof,
+        );
+
+        let block_matches = self.verify_block(head_block_root, &proof.block_proof, &block_hash);
+
+        let comp = self.and(block_matches, outcome_matches);
+        self.and(comp, block_hash_matches)
+    }
+
+    fn verify_batch<const N: usize>(
+        &mut self,
+        batch: &BatchProofVariable<N>,
+    ) -> ArrayVariable<BoolVariable, N> {
+        let verified = batch
+            .proofs
+            .data
+            .iter()
+            .map(|proof| {
+                let verified = self.verify_blinded(&batch.head_block_root, proof);
+                // Padding is never verified, but doesn't fail the batch
+                let is_padding = self.not(proof.is_active);
+                let ok = self.or(is_padding, verified);
+                self.assertx(ok);
+                self.and(proof.is_active, verified)
+            })
+            .collect_vec();
+        ArrayVariable::new(verified)
+    }
 }
 
 // TODO: test this and reuse for block header inner
@@ -362,7 +709,10 @@ fn to_le_bytes<L: PlonkParameters<D>, V: CircuitVariable, const D: usize, const
 
 #[cfg(test)]
 mod tests {
-    use near_light_client_protocol::{Protocol, StakeInfo};
+    use near_crypto::{ED25519PublicKey, KeyType, PublicKey, SecretKey};
+    use near_light_client_protocol::{
+        endorsement::ChunkEndorsement, Protocol, StakeInfo, ValidatorStake,
+    };
 
     use self::assert_eq;
     use super::*;
@@ -429,6 +779,81 @@ mod tests {
         builder_suite(define, writer, assertions);
     }
 
+    #[test]
+    fn test_ensure_stake_out_of_range() {
+        let totals = [MAX_TOTAL_STAKE, MAX_TOTAL_STAKE + 1, u128::MAX];
+
+        let define = |builder: &mut B| {
+            for _ in 0..totals.len() {
+                let total = builder.read::<BalanceVariable>();
+                // Everything is approved, only the range matters
+                let stake_info = StakeInfoVariable {
+                    total,
+                    approved: total,
+                };
+                let is_sufficient = builder.ensure_stake_is_sufficient(&stake_info);
+                builder.write::<BoolVariable>(is_sufficient);
+            }
+        };
+        let writer = |input: &mut PI| {
+            for total in totals {
+                input.write::<BalanceVariable>(total.into());
+            }
+        };
+        let assertions = |mut output: PO| {
+            assert!(output.read::<BoolVariable>(), "total is the max");
+            assert!(!output.read::<BoolVariable>(), "total is past the max");
+            assert!(!output.read::<BoolVariable>(), "total wraps the threshold");
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    const SEATS: usize = 4;
+
+    fn sum_stake_suite(weights: [u128; SEATS]) -> StakeInfo {
+        let is_active = [true, false, true, true];
+
+        let define = |builder: &mut B| {
+            let is_active = builder.read::<BpsArr<BoolVariable, SEATS>>();
+            let weights = builder.read::<BpsArr<BalanceVariable, SEATS>>();
+            let stake = builder.sum_stake(&is_active, &weights);
+            builder.write::<BalanceVariable>(stake.total);
+            builder.write::<BalanceVariable>(stake.approved);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<BpsArr<BoolVariable, SEATS>>(is_active.to_vec());
+            input.write::<BpsArr<BalanceVariable, SEATS>>(weights.to_vec());
+        };
+        let mut stake = None;
+        let assertions = |mut output: PO| {
+            stake = Some(StakeInfo {
+                total: output.read::<BalanceVariable>(),
+                approved: output.read::<BalanceVariable>(),
+            });
+        };
+        builder_suite(define, writer, assertions);
+        stake.unwrap()
+    }
+
+    #[test]
+    fn test_sum_stake() {
+        // The most each seat can have without the total wrapping
+        let max = u128::MAX / SEATS as u128;
+        assert_eq!(
+            sum_stake_suite([max; SEATS]),
+            StakeInfo {
+                total: max * 4,
+                approved: max * 3,
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sum_stake_overflow() {
+        sum_stake_suite([u128::MAX, 0, 0, 1]);
+    }
+
     #[test]
     fn test_ensure_height() {
         let test_header = to_header(test_next().body);
@@ -515,6 +940,24 @@ mod tests {
         builder_suite(define, writer, assertions);
     }
 
+    #[test]
+    fn test_endorsement_msg() {
+        let chunk_hash = CryptoHash::hash_bytes(b"chunk");
+        let define = |builder: &mut B| {
+            let chunk_hash = builder.read::<CryptoHashVariable>();
+            let os = builder.endorsement_message(&chunk_hash);
+            builder.write::<EndorsementMessage>(os);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<CryptoHashVariable>(chunk_hash.0.into());
+        };
+        let assertions = |mut output: PO| {
+            let created = output.read::<EndorsementMessage>();
+            assert_eq!(endorsement::message(&chunk_hash), created);
+        };
+        builder_suite(define, writer, assertions);
+    }
+
     #[test]
     fn test_raw_le_bytes() {
         let (_, _, next_block) = test_state();
@@ -556,6 +999,11 @@ mod tests {
 /// TODO: CI for only beefy tests
 #[cfg(test)]
 mod beefy_tests {
+    use near_light_client_protocol::{
+        outcomes::{partial_outcome, OutcomeStatus},
+        prelude::BasicProof,
+    };
+    use near_primitives::views::ExecutionStatusView;
     use serial_test::serial;
 
     use crate::{
@@ -607,6 +1055,91 @@ mod beefy_tests {
         builder_suite(define, writer, assertions);
     }
 
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_hash_weights() {
+        use near_light_client_protocol::{
+            config::NUM_BLOCK_PRODUCER_SEATS,
+            prelude::Itertools,
+            weights::{params_hash, ByStake, StakeWeight},
+        };
+
+        let (_, bps, _) = test_state();
+        let mut weights = ByStake.weigh(&bps);
+        weights[0] = 1;
+        let expected = params_hash(&bps, &weights);
+
+        let define = |builder: &mut B| {
+            let bps = builder.read::<BpsArr<ValidatorStakeVariable>>();
+            let weights = builder.read::<BpsArr<BalanceVariable>>();
+            let hash = builder.hash_weights(&bps, &weights);
+            builder.write::<CryptoHashVariable>(hash);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<BpsArr<ValidatorStakeVariable>>(bps_to_variable(Some(bps)));
+            let mut weights = weights.into_iter().map(Into::into).collect_vec();
+            weights.truncate(NUM_BLOCK_PRODUCER_SEATS);
+            weights.resize(NUM_BLOCK_PRODUCER_SEATS, 0u128.into());
+            input.write::<BpsArr<BalanceVariable>>(weights);
+        };
+        let assertions = |mut output: PO| {
+            assert_eq!(output.read::<CryptoHashVariable>().0, expected.0);
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_outcome_hash() {
+        let outcomes =
+            ["old.json", "new.json"].map(|f| fixture::<BasicProof>(f).outcome_proof.to_hashes());
+
+        let define = |builder: &mut B| {
+            for _ in 0..outcomes.len() {
+                let hashes = builder.read::<OutcomeHashesVariable>();
+                let hash = hashes.hash(builder);
+                builder.write::<CryptoHashVariable>(hash);
+            }
+        };
+        let writer = |input: &mut PI| {
+            for hashes in outcomes.clone() {
+                input.write::<OutcomeHashesVariable>(hashes.into());
+            }
+        };
+        let assertions = |mut output: PO| {
+            for hashes in &outcomes {
+                assert_eq!(
+                    output.read::<CryptoHashVariable>().0,
+                    CryptoHash::hash_borsh(hashes).0
+                );
+            }
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_hash_bps() {
+        let (header, bps, _) = testnet_state();
+        let expected = header.inner_lite.next_bp_hash;
+
+        let define = |builder: &mut B| {
+            let bps = builder.read::<BpsArr<ValidatorStakeVariable>>();
+            let hash = builder.hash_bps(&bps);
+            builder.write::<CryptoHashVariable>(hash);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<BpsArr<ValidatorStakeVariable>>(bps_to_variable(Some(bps)));
+        };
+        let assertions = |mut output: PO| {
+            assert_eq!(output.read::<CryptoHashVariable>().0, expected.0);
+        };
+        builder_suite(define, writer, assertions);
+    }
+
     #[test]
     #[serial]
     #[ignore]
@@ -634,6 +1167,40 @@ mod beefy_tests {
         builder_suite(define, writer, assertions);
     }
 
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_outcome_status() {
+        let proof: BasicProof = fixture("old.json");
+        let mut returned = proof.outcome_proof.outcome.clone();
+        returned.status = ExecutionStatusView::SuccessValue(b"42".to_vec());
+        let outcomes = [proof.outcome_proof.outcome, returned];
+
+        let define = |builder: &mut B| {
+            for _ in 0..outcomes.len() {
+                let outcome = builder.read::<PartialOutcomeVariable>();
+                let status = outcome.decode(builder);
+                builder.write::<OutcomeStatusVariable>(status);
+            }
+        };
+        let writer = |input: &mut PI| {
+            for outcome in &outcomes {
+                input.write::<PartialOutcomeVariable>(partial_outcome(outcome).into());
+            }
+        };
+        let assertions = |mut output: PO| {
+            for outcome in &outcomes {
+                assert_eq!(
+                    output.read::<OutcomeStatusVariable>(),
+                    OutcomeStatusVariableValue::<GoldilocksField>::from(OutcomeStatus::from(
+                        outcome
+                    ))
+                );
+            }
+        };
+        builder_suite(define, writer, assertions);
+    }
+
     #[test]
     #[serial]
     #[ignore]
@@ -644,7 +1211,7 @@ mod beefy_tests {
             let head = builder.read::<HeaderVariable>();
             let bps = builder.read::<BpsArr<ValidatorStakeVariable>>();
             let next_block = builder.read::<BlockVariable>();
-            let synced = builder.sync(&head, &bps, &next_block);
+            let synced = builder.sync(&head, &bps, &next_block, KeyPolicy::default());
             builder.write::<SyncedVariable>(synced);
         };
         let writer = |input: &mut PI| {
@@ -681,7 +1248,7 @@ mod beefy_tests {
 
             let msg = builder.reconstruct_approval_message(&next_block);
 
-            builder.validate_signatures(&next_block_approvals, &bps, msg);
+            builder.validate_signatures(&next_block_approvals, &bps, msg, KeyPolicy::default());
         };
         let writer = |input: &mut PI| {
             input.write::<BpsArr<ValidatorStakeVariable, BPS_AMT>>(
@@ -692,4 +1259,98 @@ mod beefy_tests {
         let assertions = |mut _output: PO| {};
         builder_suite(define, writer, assertions);
     }
+
+    /// Endorse a chunk by the first and last of three validators under
+    /// `policy`, proving only if the native check passes. With `invalid_key`
+    /// the middle one endorses too, with its key swapped for one that isn't
+    /// canonical.
+    fn chunk_endorsements_suite(policy: KeyPolicy, invalid_key: bool) {
+        const VALIDATORS: usize = 4;
+        let chunk_hash = CryptoHash::hash_bytes(b"chunk");
+        let keys = (0..3)
+            .map(|i| SecretKey::from_seed(KeyType::ED25519, &format!("v{}.near", i)))
+            .collect_vec();
+        let mut assignments = keys
+            .iter()
+            .zip([100, 50, 100])
+            .enumerate()
+            .map(|(i, (key, stake))| {
+                let account_id = format!("v{}.near", i).parse().unwrap();
+                ValidatorStake::new_v1(account_id, key.public_key(), stake)
+            })
+            .collect_vec();
+        let mut endorsers = vec![0, 2];
+        if invalid_key {
+            let mut p = [0xff; 32];
+            p[0] = 0xed;
+            p[31] = 0x7f;
+            assignments[1] = ValidatorStake::new_v1(
+                assignments[1].account_id().clone(),
+                PublicKey::ED25519(ED25519PublicKey(p)),
+                assignments[1].stake(),
+            );
+            endorsers.push(1);
+        }
+        let endorsements = endorsers
+            .into_iter()
+            .map(|i| ChunkEndorsement {
+                chunk_hash,
+                account_id: assignments[i].account_id().clone(),
+                signature: keys[i].sign(&endorsement::message(&chunk_hash)),
+            })
+            .collect_vec();
+        let expected =
+            Protocol::verify_chunk_endorsements(&chunk_hash, &assignments, &endorsements, policy)
+                .map(|(stake, _)| stake);
+
+        let define = |builder: &mut B| {
+            let endorsements = builder.read::<ChunkEndorsementsVariable<VALIDATORS>>();
+            let (endorsed, stake) = builder.verify_chunk_endorsements(&endorsements, policy);
+            builder.write::<BoolVariable>(endorsed);
+            builder.write::<StakeInfoVariable>(stake);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<ChunkEndorsementsVariable<VALIDATORS>>(
+                ChunkEndorsementsVariableValue::new(chunk_hash, assignments.clone(), &endorsements),
+            );
+        };
+        let assertions = |mut output: PO| {
+            assert!(output.read::<BoolVariable>(), "chunk is endorsed");
+            let stake = output.read::<StakeInfoVariable>();
+            let expected = expected.as_ref().unwrap();
+            assert_eq!(stake.total, expected.total);
+            assert_eq!(stake.approved, expected.approved);
+        };
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            builder_suite(define, writer, assertions)
+        }));
+        assert_eq!(
+            proved.is_ok(),
+            expected.is_ok(),
+            "the circuit agrees with the native check, {:?}",
+            expected
+        );
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_chunk_endorsements() {
+        chunk_endorsements_suite(KeyPolicy::default(), false);
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_key_policy_inactive() {
+        // The invalid key's endorsement is dropped, the rest still endorse
+        chunk_endorsements_suite(KeyPolicy::Inactive, true);
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_builder_test_key_policy_reject() {
+        chunk_endorsements_suite(KeyPolicy::Reject, true);
+    }
 }
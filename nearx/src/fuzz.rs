@@ -0,0 +1,270 @@
+//! Flipping bits in the witness of the gadgets, to find fields the circuits
+//! accept without constraining. This mirrors the native fuzzing in the protocol
+//! crate, see `test_utils::mutate`.
+//!
+//! Only a mock proof is generated, which catches broken copy constraints and
+//! failing generators but not every gate, so the real prover rejects at least
+//! as much as is reported here.
+use std::{
+    collections::HashMap,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+};
+
+use near_light_client_protocol::{
+    config::NUM_BLOCK_PRODUCER_SEATS, outcomes::partial_outcome, prelude::BasicProof,
+    signature::KeyPolicy,
+};
+use plonky2x::{
+    backend::circuit::MockCircuitBuild,
+    frontend::ecc::curve25519::ed25519::eddsa::EDDSASignatureVariable,
+    prelude::plonky2::field::types::{Field, PrimeField64},
+};
+use serial_test::serial;
+use test_utils::mutate::{pattern, Coverage, Verdict};
+
+use crate::{
+    builder::{Sync, Verify},
+    test_utils::*,
+    variables::*,
+};
+
+type F = GoldilocksField;
+
+/// Names the element ranges of a variable, in the order the derive lays them
+/// out.
+#[derive(Debug, Default)]
+struct Layout {
+    fields: Vec<(String, Range<usize>)>,
+    len: usize,
+}
+
+impl Layout {
+    fn field<V: CircuitVariable>(mut self, name: &str) -> Self {
+        let end = self.len + V::nb_elements();
+        self.fields.push((name.to_string(), self.len..end));
+        self.len = end;
+        self
+    }
+
+    fn skip<V: CircuitVariable>(mut self, amt: usize) -> Self {
+        self.len += V::nb_elements() * amt;
+        self
+    }
+
+    fn header(self, name: &str) -> Self {
+        let inner = format!("{}/inner_lite", name);
+        self.field::<CryptoHashVariable>(&format!("{}/prev_block_hash", name))
+            .field::<CryptoHashVariable>(&format!("{}/inner_rest_hash", name))
+            .field::<U64Variable>(&format!("{}/height", inner))
+            .field::<CryptoHashVariable>(&format!("{}/epoch_id", inner))
+            .field::<CryptoHashVariable>(&format!("{}/next_epoch_id", inner))
+            .field::<CryptoHashVariable>(&format!("{}/prev_state_root", inner))
+            .field::<CryptoHashVariable>(&format!("{}/outcome_root", inner))
+            .field::<U64Variable>(&format!("{}/timestamp", inner))
+            .field::<CryptoHashVariable>(&format!("{}/next_bp_hash", inner))
+            .field::<CryptoHashVariable>(&format!("{}/block_merkle_root", inner))
+    }
+
+    /// Nodes past the length aren't hashed, so they're skipped like padding.
+    fn merkle_path<const MAX_LEN: usize>(self, name: &str, len: usize) -> Self {
+        let layout = self.field::<Variable>(&format!("{}/len", name));
+        let layout = (0..len).fold(layout, |layout, i| {
+            layout.field::<Bytes32Variable>(&format!("{}/path/{}", name, i))
+        });
+        let layout = layout.skip::<Bytes32Variable>(MAX_LEN - len);
+        let layout = (0..len).fold(layout, |layout, i| {
+            layout.field::<BoolVariable>(&format!("{}/indices/{}", name, i))
+        });
+        layout.skip::<BoolVariable>(MAX_LEN - len)
+    }
+
+    /// Hashes past the length aren't hashed, so they're skipped like padding.
+    fn outcome_hashes(self, name: &str, len: usize) -> Self {
+        let layout = self.field::<Variable>(&format!("{}/len", name));
+        let layout = (0..len).fold(layout, |layout, i| {
+            layout.field::<CryptoHashVariable>(&format!("{}/hashes/{}", name, i))
+        });
+        layout.skip::<CryptoHashVariable>(MAX_OUTCOME_HASHES - len)
+    }
+
+    /// Bytes past the length aren't hashed, so they're skipped like padding.
+    fn partial_outcome(self, name: &str, len: usize) -> Self {
+        let layout = self.field::<Variable>(&format!("{}/len", name));
+        let layout = (0..len).fold(layout, |layout, i| {
+            layout.field::<ByteVariable>(&format!("{}/bytes/{}", name, i))
+        });
+        layout.skip::<ByteVariable>(MAX_PARTIAL_OUTCOME_LEN - len)
+    }
+
+    fn seats(self, name: &str, seat: impl Fn(Self, &str) -> Self) -> Self {
+        (0..NUM_BLOCK_PRODUCER_SEATS)
+            .fold(self, |layout, i| seat(layout, &format!("{}/{}", name, i)))
+    }
+
+    /// Flip the first and last element of each field, taking at most
+    /// `per_field` fields for each pattern.
+    fn mutants<V: CircuitVariable>(
+        &self,
+        value: V::ValueType<F>,
+        per_field: usize,
+    ) -> Vec<(String, V::ValueType<F>)> {
+        assert_eq!(
+            self.len,
+            V::nb_elements(),
+            "layout doesn't cover {}",
+            V::nb_elements()
+        );
+        let elements = V::elements::<F>(value);
+        let mut taken = HashMap::<String, usize>::new();
+        let mut mutants = vec![];
+        for (name, range) in &self.fields {
+            let count = taken.entry(pattern(name)).or_default();
+            if *count >= per_field {
+                continue;
+            }
+            *count += 1;
+            let mut indices = vec![range.start, range.end - 1];
+            indices.dedup();
+            for i in indices {
+                let mut elements = elements.clone();
+                elements[i] = F::from_canonical_u64(elements[i].to_canonical_u64() ^ 1);
+                mutants.push((name.clone(), V::from_elements::<F>(&elements)));
+            }
+        }
+        mutants
+    }
+}
+
+struct Fuzzer(MockCircuitBuild<DefaultParameters, 2>);
+
+impl Fuzzer {
+    fn new(define: impl FnOnce(&mut B)) -> Self {
+        pretty_env_logger::try_init().unwrap_or_default();
+        let mut builder = B::new();
+        define(&mut builder);
+        Self(builder.mock_build())
+    }
+
+    /// The outputs, or none if the witness is rejected.
+    fn prove(&self, writer: impl FnOnce(&mut PI)) -> Option<PO> {
+        let mut inputs = self.0.input();
+        writer(&mut inputs);
+        // Rejections panic, don't flood the output with them
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.0.mock_prove(&inputs)));
+        panic::set_hook(hook);
+        result.ok().map(|(_, output)| output)
+    }
+}
+
+#[test]
+#[serial]
+#[ignore]
+fn beefy_fuzz_verify() {
+    let proof: BasicProof = fixture("old.json");
+    let layout = Layout::default()
+        .field::<CryptoHashVariable>("/head_block_root")
+        .outcome_hashes("/outcome_hashes", proof.outcome_proof.to_hashes().len())
+        .partial_outcome(
+            "/outcome",
+            partial_outcome(&proof.outcome_proof.outcome).len(),
+        )
+        .field::<CryptoHashVariable>("/outcome_proof_block_hash")
+        .merkle_path::<OUTCOME_PROOF_DEPTH>("/outcome_proof", proof.outcome_proof.proof.len())
+        .merkle_path::<OUTCOME_ROOT_PROOF_DEPTH>(
+            "/outcome_root_proof",
+            proof.outcome_root_proof.len(),
+        )
+        .header("/block_header")
+        .merkle_path::<BLOCK_PROOF_DEPTH>("/block_proof", proof.block_proof.len());
+    let head_block_root =
+        CryptoHash::from_str("WWrLWbWHwSmjtTn5oBZPYgRCuCYn6fkYVa4yhPWNK4L").unwrap();
+    let value: ProofVariableValue<F> = near_light_client_protocol::Proof::Basic {
+        head_block_root,
+        proof: Box::new(proof),
+    }
+    .into();
+
+    let fuzzer = Fuzzer::new(|b| {
+        let proof = b.read::<ProofVariable>();
+        let verified = b.verify(proof);
+        b.write::<BoolVariable>(verified);
+    });
+    let verify = |value| {
+        fuzzer
+            .prove(|input| input.write::<ProofVariable>(value))
+            .map(|mut output| output.read::<BoolVariable>())
+            .unwrap_or(false)
+    };
+    assert!(verify(value.clone()), "fixture should verify");
+
+    let mut coverage = Coverage::default();
+    for (field, mutant) in layout.mutants::<ProofVariable>(value, usize::MAX) {
+        let verdict = if verify(mutant) {
+            Verdict::Unconstrained
+        } else {
+            Verdict::Rejected
+        };
+        coverage.record(&field, verdict);
+    }
+    coverage.emit("circuit_verify");
+    assert_eq!(coverage.unexpected(&[]), Vec::<&str>::new());
+}
+
+#[test]
+#[serial]
+#[ignore]
+fn beefy_fuzz_sync() {
+    let (head, bps, next_block) = test_state();
+    let layout = Layout::default()
+        .header("/header")
+        .field::<CryptoHashVariable>("/next_block_inner_hash")
+        .seats("/next_bps", |layout, seat| {
+            layout
+                .field::<AccountIdVariable>(&format!("{}/account_id", seat))
+                .field::<PublicKeyVariable>(&format!("{}/public_key", seat))
+                .field::<BalanceVariable>(&format!("{}/stake", seat))
+        })
+        .seats("/approvals_after_next/is_active", |layout, seat| {
+            layout.field::<BoolVariable>(seat)
+        })
+        .seats("/approvals_after_next/signatures", |layout, seat| {
+            layout.field::<EDDSASignatureVariable>(seat)
+        })
+        .field::<CryptoHashVariable>("/next_bps_hash");
+    let value: BlockVariableValue<F> = next_block.into();
+
+    let fuzzer = Fuzzer::new(|b| {
+        let head = b.read::<HeaderVariable>();
+        let bps = b.read::<BpsArr<ValidatorStakeVariable>>();
+        let next_block = b.read::<BlockVariable>();
+        let synced = b.sync(&head, &bps, &next_block, KeyPolicy::default());
+        b.write::<SyncedVariable>(synced);
+    });
+    let sync = |value| {
+        fuzzer
+            .prove(|input| {
+                input.write::<HeaderVariable>(head.clone().into());
+                input.write::<BpsArr<ValidatorStakeVariable>>(bps_to_variable(Some(bps.clone())));
+                input.write::<BlockVariable>(value);
+            })
+            .map(|mut output| SyncedVariable::elements::<F>(output.read::<SyncedVariable>()))
+    };
+    let expected = sync(value.clone()).expect("fixture should sync");
+
+    let mut coverage = Coverage::default();
+    for (field, mutant) in layout.mutants::<BlockVariable>(value, 2) {
+        let verdict = match sync(mutant) {
+            None => Verdict::Rejected,
+            Some(synced) if synced == expected => Verdict::Ignored,
+            Some(_) => Verdict::Unconstrained,
+        };
+        coverage.record(&field, verdict);
+    }
+    coverage.emit("circuit_sync");
+    // FIXME: the next bps are only checked against `next_bps_hash`, which is
+    // hashed outside of the circuit, see `BlockVariableValue::from`
+    assert_eq!(coverage.unexpected(&["/next_bps"]), Vec::<&str>::new());
+}
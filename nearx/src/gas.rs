@@ -0,0 +1,246 @@
+use near_light_client_protocol::{
+    config::NUM_BLOCK_PRODUCER_SEATS,
+    prelude::{CryptoHash, Header, Itertools},
+    ValidatorStake,
+};
+
+use crate::{abi, variables::pad_account_id};
+
+/// The public output layouts we could write for a sync proof.
+///
+/// Every layout is prefixed with the domain, the new head hash and its epoch
+/// ids, they only differ in how they represent the BPS for the next epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLayout {
+    /// The epoch id and every seat, padded to `NUM_BLOCK_PRODUCER_SEATS`.
+    FullBps,
+    /// The epoch id and only the seats that changed since the last epoch,
+    /// each prefixed with the seat index.
+    BpsDiff,
+    /// Only the hash of the next BPS.
+    CommitmentOnly,
+    /// The epoch id and every seat, `abi.encode`d, see `abi`.
+    Abi,
+}
+
+impl OutputLayout {
+    pub const ALL: [OutputLayout; 4] = [
+        Self::FullBps,
+        Self::BpsDiff,
+        Self::CommitmentOnly,
+        Self::Abi,
+    ];
+
+    /// Encode the outputs of a sync with this layout, `prev_bps` is only used
+    /// to calculate the diff.
+    pub fn encode(
+        &self,
+        new_head: &Header,
+        next_bps_epoch: &CryptoHash,
+        next_bps: &[ValidatorStake],
+        prev_bps: &[ValidatorStake],
+    ) -> Vec<u8> {
+        let mut bytes = vec![0u8; 32];
+        bytes.extend_from_slice(&new_head.hash().0);
+        bytes.extend_from_slice(&new_head.inner_lite.epoch_id.0);
+        bytes.extend_from_slice(&new_head.inner_lite.next_epoch_id.0);
+
+        match self {
+            Self::FullBps => {
+                bytes.extend_from_slice(&next_bps_epoch.0);
+                let mut bps = next_bps
+                    .iter()
+                    .take(NUM_BLOCK_PRODUCER_SEATS)
+                    .map(encode_validator)
+                    .collect_vec();
+                bps.resize(NUM_BLOCK_PRODUCER_SEATS, vec![0u8; ENCODED_VALIDATOR_LEN]);
+                bytes.extend(bps.concat());
+            }
+            Self::BpsDiff => {
+                bytes.extend_from_slice(&next_bps_epoch.0);
+                let changed = next_bps
+                    .iter()
+                    .take(NUM_BLOCK_PRODUCER_SEATS)
+                    .enumerate()
+                    .filter(|(i, vs)| prev_bps.get(*i) != Some(vs))
+                    .collect_vec();
+                bytes.push(changed.len() as u8);
+                for (i, vs) in changed {
+                    bytes.push(i as u8);
+                    bytes.extend(encode_validator(vs));
+                }
+            }
+            Self::CommitmentOnly => {
+                bytes.extend_from_slice(&CryptoHash::hash_borsh(next_bps).0);
+            }
+            // The same prefix, each value already a word
+            Self::Abi => bytes = abi::encode_sync(&[0u8; 32], new_head, next_bps_epoch, next_bps),
+        }
+        bytes
+    }
+}
+
+impl std::fmt::Display for OutputLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::FullBps => "full-bps",
+            Self::BpsDiff => "bps-diff",
+            Self::CommitmentOnly => "commitment-only",
+            Self::Abi => "abi",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Padded account id, public key and stake, the same as a
+/// `ValidatorStakeVariable`.
+const ENCODED_VALIDATOR_LEN: usize = 64 + 32 + 16;
+
+fn encode_validator(vs: &ValidatorStake) -> Vec<u8> {
+    let mut bytes = pad_account_id(vs.account_id()).to_vec();
+    bytes.extend_from_slice(vs.public_key().key_data());
+    bytes.extend_from_slice(&vs.stake().to_be_bytes());
+    bytes
+}
+
+/// Gas costs of the target EVM chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasSchedule {
+    pub calldata_zero_byte: u64,
+    pub calldata_nonzero_byte: u64,
+    /// Fixed cost of verifying the wrapped proof, this is an approximation of
+    /// the gateway's verification.
+    pub proof_verification: u64,
+    pub base_transaction: u64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self::ethereum()
+    }
+}
+
+impl GasSchedule {
+    /// EIP-2028 calldata costs.
+    pub fn ethereum() -> Self {
+        Self {
+            calldata_zero_byte: 4,
+            calldata_nonzero_byte: 16,
+            proof_verification: 300_000,
+            base_transaction: 21_000,
+        }
+    }
+
+    pub fn calldata_gas(&self, bytes: &[u8]) -> u64 {
+        bytes
+            .iter()
+            .map(|b| {
+                if *b == 0 {
+                    self.calldata_zero_byte
+                } else {
+                    self.calldata_nonzero_byte
+                }
+            })
+            .sum()
+    }
+
+    pub fn estimate(&self, layout: OutputLayout, bytes: &[u8]) -> GasEstimate {
+        let calldata_gas = self.calldata_gas(bytes);
+        GasEstimate {
+            layout,
+            calldata_len: bytes.len(),
+            calldata_gas,
+            total_gas: calldata_gas + self.proof_verification + self.base_transaction,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasEstimate {
+    pub layout: OutputLayout,
+    pub calldata_len: usize,
+    pub calldata_gas: u64,
+    pub total_gas: u64,
+}
+
+/// Estimate every layout for the same sync outputs.
+pub fn compare_layouts(
+    schedule: &GasSchedule,
+    new_head: &Header,
+    next_bps_epoch: &CryptoHash,
+    next_bps: &[ValidatorStake],
+    prev_bps: &[ValidatorStake],
+) -> Vec<GasEstimate> {
+    OutputLayout::ALL
+        .iter()
+        .map(|layout| {
+            let bytes = layout.encode(new_head, next_bps_epoch, next_bps, prev_bps);
+            schedule.estimate(*layout, &bytes)
+        })
+        .collect()
+}
+
+/// Render estimates as a table for the CLI.
+pub fn report(estimates: &[GasEstimate]) -> String {
+    let mut out = format!(
+        "{:<16} {:>14} {:>14} {:>14}\n",
+        "layout", "calldata bytes", "calldata gas", "total gas"
+    );
+    for e in estimates {
+        out.push_str(&format!(
+            "{:<16} {:>14} {:>14} {:>14}\n",
+            e.layout.to_string(),
+            e.calldata_len,
+            e.calldata_gas,
+            e.total_gas
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_calldata_gas() {
+        let schedule = GasSchedule::ethereum();
+        assert_eq!(schedule.calldata_gas(&[0, 0, 1, 255]), 4 + 4 + 16 + 16);
+    }
+
+    #[test]
+    fn test_compare_layouts() {
+        let (head, prev_bps, next_block) = testnet_state();
+        let next_bps = next_block
+            .next_bps
+            .unwrap()
+            .into_iter()
+            .map(Into::into)
+            .collect_vec();
+
+        let estimates = compare_layouts(
+            &GasSchedule::default(),
+            &head,
+            &head.inner_lite.next_epoch_id,
+            &next_bps,
+            &prev_bps,
+        );
+        println!("{}", report(&estimates));
+
+        let [full, diff, commitment, abi_encoded]: [GasEstimate; 4] = estimates.try_into().unwrap();
+        assert_eq!(
+            full.calldata_len,
+            32 * 5 + NUM_BLOCK_PRODUCER_SEATS * ENCODED_VALIDATOR_LEN
+        );
+        assert_eq!(commitment.calldata_len, 32 * 5);
+        assert!(commitment.total_gas < diff.total_gas);
+        assert!(diff.total_gas <= full.total_gas);
+        // Every value is padded to a word, but with zeros which are cheap
+        assert_eq!(
+            abi_encoded.calldata_len,
+            32 * 7 + NUM_BLOCK_PRODUCER_SEATS * abi::VALIDATOR_WORDS * abi::WORD
+        );
+        assert!(full.total_gas <= abi_encoded.total_gas);
+    }
+}
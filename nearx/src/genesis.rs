@@ -0,0 +1,177 @@
+//! The payload a new verifier contract is initialised with.
+//!
+//! A deployment trusts a checkpoint header, the BPS of the epoch after it
+//! and the circuits registered as its function ids.
+//! Assembling these by hand makes it easy to pair a header with the wrong
+//! epoch or a circuit with the wrong build, so they are fetched, checked
+//! against each other and written out together, along with the first sync
+//! proof from the checkpoint to show the deployment can make progress.
+use std::collections::BTreeMap;
+
+use near_light_client_protocol::{
+    prelude::{CryptoHash, Header},
+    BlockHeight, ValidatorStakeView,
+};
+use near_light_client_rpc::{LightClientRpc, NearRpcClient, Network};
+use nearx_error::{ensure, err, Context, Result};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    range::{prove_range, Proven, RangeConfig},
+    repro::{Manifest, MANIFEST_PATH, NETWORK, PROFILE},
+    variables::domain_from_chain_id,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Checkpoint {
+    pub height: BlockHeight,
+    pub hash: CryptoHash,
+    pub header: Header,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Genesis {
+    pub network: String,
+    pub profile: String,
+    pub checkpoint: Checkpoint,
+    /// The hash of the BPS for the epoch after the checkpoint, as its
+    /// `next_bp_hash` commits to.
+    pub bps_commitment: CryptoHash,
+    pub chain_id: u64,
+    /// Hex encoded, see `DomainVariable`.
+    pub domain: String,
+    /// The version of the outputs the contract decodes, see
+    /// `repro::OUTPUT_VERSION`.
+    pub output_version: String,
+    /// The verifier key digests from the manifest, by circuit.
+    pub digests: BTreeMap<String, Option<String>>,
+    /// Syncs from the checkpoint to the following light client block.
+    pub sync_proof: Proven,
+}
+
+impl Genesis {
+    /// Fetch the checkpoint at `height` and prove the first sync from it.
+    ///
+    /// The circuits are built for a single network, see `repro::NETWORK`, so
+    /// `network` must be the one they were built for.
+    pub async fn build(network: Network, height: BlockHeight, chain_id: u64) -> Result<Self> {
+        let built_for = Network::from(NETWORK);
+        ensure!(
+            network.to_string() == built_for.to_string(),
+            Prove,
+            "The circuits are built for {}, not {}",
+            built_for,
+            network
+        );
+        let manifest =
+            Manifest::load(MANIFEST_PATH).with_context(|| format!("loading {}", MANIFEST_PATH))?;
+
+        let client = NearRpcClient::new(network);
+        let header = client.fetch_header_at(height).await?;
+        let hash = header.hash();
+        let bps = client
+            .fetch_epoch_bps(&header.inner_lite.next_epoch_id)
+            .await?;
+        let bps_commitment = bps_commitment(&header, &bps)?;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let config = RangeConfig {
+            chain_id,
+            from: hash,
+            until: height + 1,
+            capacity: 1,
+        };
+        prove_range::<NETWORK>(config, tx, None)
+            .await
+            .with_context(|| format!("proving the first sync from {}", hash))?;
+        let sync_proof = rx
+            .recv()
+            .await
+            .ok_or_else(|| err!(Prove, "No sync was proven from {}", hash))?;
+
+        Ok(Self {
+            network: network.to_string(),
+            profile: PROFILE.to_string(),
+            checkpoint: Checkpoint {
+                height,
+                hash,
+                header,
+            },
+            bps_commitment,
+            chain_id,
+            domain: hex_bytes32(&domain_from_chain_id(chain_id)),
+            output_version: manifest.output_version,
+            digests: manifest.digests,
+            sync_proof,
+        })
+    }
+
+    /// The circuits without a recorded digest, these can't be registered.
+    pub fn unrecorded(&self) -> Vec<&str> {
+        self.digests
+            .iter()
+            .filter(|(_, digest)| digest.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// The environment `Initialise.s.sol` reads.
+    pub fn env(&self) -> String {
+        format!(
+            "NEAR_CHECKPOINT_HEADER_HASH={}\nNEAR_DOMAIN={}\nNEAR_OUTPUT_VERSION={}\n",
+            hex_bytes32(&self.checkpoint.hash.0),
+            self.domain,
+            self.output_version
+        )
+    }
+}
+
+/// Commit to the BPS of the epoch after `header`, which it must already
+/// commit to.
+fn bps_commitment(header: &Header, bps: &[ValidatorStakeView]) -> Result<CryptoHash> {
+    let commitment = CryptoHash::hash_borsh(bps);
+    ensure!(
+        commitment == header.inner_lite.next_bp_hash,
+        Witness,
+        "The BPS of {} don't match the checkpoint at {}",
+        header.inner_lite.next_epoch_id,
+        header.inner_lite.height
+    );
+    Ok(commitment)
+}
+
+fn hex_bytes32(bytes: &[u8; 32]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::{test_first, test_next, to_header};
+
+    use super::*;
+
+    #[test]
+    fn test_bps_commitment() {
+        let first = test_first().body;
+        let bps = first.next_bps.clone().unwrap();
+        let header = to_header(first);
+        assert_eq!(
+            bps_commitment(&header, &bps).unwrap(),
+            header.inner_lite.next_bp_hash
+        );
+
+        // The BPS of another epoch
+        let other = test_next().body.next_bps.unwrap();
+        assert!(bps_commitment(&header, &other).is_err());
+    }
+
+    #[test]
+    fn test_domain_matches_the_contract() {
+        // `bytes32(block.chainid)`, the contract's default domain
+        assert_eq!(
+            hex_bytes32(&domain_from_chain_id(5)),
+            "0x0000000000000000000000000000000000000000000000000000000000000005"
+        );
+    }
+}
@@ -1,14 +1,17 @@
-use std::collections::HashMap;
-
 use async_trait::async_trait;
 use near_light_client_protocol::{prelude::CryptoHash, Proof};
-use near_light_client_rpc::{prelude::GetProof, LightClientRpc, NearRpcClient, Network};
+use near_light_client_rpc::{
+    fetch_proofs_in_order, prelude::GetProof, LightClientRpc, NearRpcClient, Network,
+};
 use plonky2x::{frontend::hint::asynchronous::hint::AsyncHint, prelude::*};
 use serde::{Deserialize, Serialize};
 
-use crate::variables::{
-    normalise_account_id, BlockVariable, CryptoHashVariable, HeaderVariable, ProofVariable,
-    TransactionOrReceiptIdVariable,
+use crate::{
+    state::{StateProofVariable, MAX_STATE_KEY_LEN},
+    variables::{
+        normalise_account_id, AccountIdVariable, BlockVariable, CryptoHashVariable, HeaderVariable,
+        ProofVariable, TransactionOrReceiptIdVariable,
+    },
 };
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -89,7 +92,11 @@ impl FetchHeaderInputs {
         untrusted
     }
 }
+/// The proof requests a batch has in flight at once.
+const MAX_CONCURRENT_PROOFS: usize = 8;
+
 // TODO: refactor into some client-like carrier for all hints that is serdeable
+/// Fetches the proofs of a batch, written in the order of the requests.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FetchProofInputs<const B: usize>(pub Network);
 
@@ -122,21 +129,25 @@ impl<L: PlonkParameters<D>, const D: usize, const B: usize> AsyncHint<L, D>
             });
         }
 
-        let proofs = client
-            .batch_fetch_proofs(&CryptoHash(last_verified), reqs)
-            .await
-            .into_iter()
-            .map(|(k, p)| (k, p.expect("Failed to fetch proof")))
-            .map(|(k, p)| {
-                (
-                    k,
-                    Proof::Basic {
-                        proof: Box::new(p),
-                        head_block_root: CryptoHash(block_merkle_root),
-                    },
-                )
-            })
-            .collect::<HashMap<CryptoHash, Proof>>();
+        let proofs = fetch_proofs_in_order(
+            &client,
+            &CryptoHash(last_verified),
+            reqs,
+            MAX_CONCURRENT_PROOFS,
+        )
+        .await
+        .into_iter()
+        .map(|(k, p)| (k, p.expect("Failed to fetch proof")))
+        .map(|(k, p)| {
+            (
+                k,
+                Proof::Basic {
+                    proof: Box::new(p),
+                    head_block_root: CryptoHash(block_merkle_root),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
 
         assert_eq!(proofs.len(), B, "Invalid number of proofs");
 
@@ -185,6 +196,58 @@ pub struct ProofInputVariable {
     pub proof: ProofVariable,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FetchStateProofInputs(pub Network);
+
+#[async_trait]
+impl<L: PlonkParameters<D>, const D: usize> AsyncHint<L, D> for FetchStateProofInputs {
+    async fn hint(
+        &self,
+        input_stream: &mut ValueStream<L, D>,
+        output_stream: &mut ValueStream<L, D>,
+    ) {
+        let client = NearRpcClient::new(self.0);
+        let h = input_stream.read_value::<CryptoHashVariable>().0;
+        let account_id = input_stream.read_value::<AccountIdVariable>();
+        let key_len = input_stream.read_value::<ByteVariable>() as usize;
+        let key = input_stream.read_value::<BytesVariable<MAX_STATE_KEY_LEN>>();
+
+        let proof = client
+            .fetch_state_proof(
+                &CryptoHash(h),
+                &normalise_account_id::<L::Field>(&account_id),
+                &key[..key_len],
+            )
+            .await
+            .expect("Failed to fetch state proof");
+        log::debug!("Fetched a state proof of {} nodes", proof.nodes.len());
+
+        output_stream.write_value::<StateProofVariable>(proof.into());
+    }
+}
+
+impl FetchStateProofInputs {
+    /// Fetches the proof of a contract's storage key as of the block before
+    /// the header, the caller verifies it against the header.
+    pub fn fetch<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+        header_hash: &CryptoHashVariable,
+        account_id: &AccountIdVariable,
+        key_len: ByteVariable,
+        key: &BytesVariable<MAX_STATE_KEY_LEN>,
+    ) -> StateProofVariable {
+        let mut input_stream = VariableStream::new();
+        input_stream.write::<CryptoHashVariable>(header_hash);
+        input_stream.write::<AccountIdVariable>(account_id);
+        input_stream.write::<ByteVariable>(&key_len);
+        input_stream.write::<BytesVariable<MAX_STATE_KEY_LEN>>(key);
+
+        let output_stream = b.async_hint(input_stream, self.clone());
+        output_stream.read::<StateProofVariable>(b)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
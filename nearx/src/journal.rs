@@ -0,0 +1,328 @@
+//! A replayable journal of proving jobs, for audits of what was proven.
+//!
+//! Each job is appended in the order it was proven, with the circuit, its
+//! exact input and a commitment to its outputs. Entries are hash chained like
+//! the operator's audit log, so a journal can't be reordered or edited
+//! without it showing.
+//!
+//! Replaying mock proves each job again in order, see the `replay-journal`
+//! bin, and checks the outputs still match their commitments. Mock proving
+//! runs every constraint and hint, so it shows what the circuit computes
+//! without the cost of a proof.
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+use near_light_client_protocol::prelude::CryptoHash;
+use nearx_error::{bail, Context, Result};
+use plonky2x::{
+    backend::circuit::{PublicInput, PublicOutput},
+    prelude::{
+        plonky2::field::types::{Field, PrimeField64},
+        DefaultParameters, PlonkParameters,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::trace;
+
+type L = DefaultParameters;
+const D: usize = 2;
+
+/// The input of a job, byte inputs are hex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobInput {
+    Bytes(#[serde(with = "hex::serde")] Vec<u8>),
+    Elements(Vec<u64>),
+}
+
+impl JobInput {
+    pub fn from_public(input: &PublicInput<L, D>) -> Result<Self> {
+        Ok(match input {
+            PublicInput::Bytes(bytes) => Self::Bytes(bytes.clone()),
+            PublicInput::Elements(elements) => {
+                Self::Elements(elements.iter().map(|e| e.to_canonical_u64()).collect())
+            }
+            _ => bail!(Io, "Only byte and element inputs can be journaled"),
+        })
+    }
+
+    pub fn to_public(&self) -> PublicInput<L, D> {
+        match self {
+            Self::Bytes(bytes) => PublicInput::Bytes(bytes.clone()),
+            Self::Elements(elements) => PublicInput::Elements(
+                elements
+                    .iter()
+                    .map(|e| <L as PlonkParameters<D>>::Field::from_canonical_u64(*e))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Commit to outputs as `trace` writes them.
+pub fn commitment(outputs: &[String]) -> CryptoHash {
+    CryptoHash::hash_bytes(&serde_json::to_vec(outputs).expect("strings always serialize"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    /// The hash of the previous entry, the default hash for the first.
+    pub prev: CryptoHash,
+    /// By its name in the manifest.
+    pub circuit: String,
+    pub input: JobInput,
+    /// The commitment to the outputs, see `commitment`.
+    pub output: CryptoHash,
+    pub hash: CryptoHash,
+}
+
+impl Entry {
+    fn digest(
+        seq: u64,
+        prev: &CryptoHash,
+        circuit: &str,
+        input: &JobInput,
+        output: &CryptoHash,
+    ) -> CryptoHash {
+        let bytes = serde_json::to_vec(&(seq, prev, circuit, input, output))
+            .expect("entries always serialize");
+        CryptoHash::hash_bytes(&bytes)
+    }
+}
+
+/// Appends the jobs as they are proven.
+pub struct Journal {
+    file: File,
+    next_seq: u64,
+    prev: CryptoHash,
+}
+
+impl Journal {
+    /// Open the journal at `path`, continuing it if it exists.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (next_seq, prev) = if path.exists() {
+            read(path)?
+                .last()
+                .map_or((0, CryptoHash::default()), |e| (e.seq + 1, e.hash))
+        } else {
+            (0, CryptoHash::default())
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file,
+            next_seq,
+            prev,
+        })
+    }
+
+    /// Append a job, it is flushed before this returns.
+    pub fn record(
+        &mut self,
+        circuit: &str,
+        input: &PublicInput<L, D>,
+        output: &PublicOutput<L, D>,
+    ) -> Result<Entry> {
+        let input = JobInput::from_public(input)?;
+        let output = commitment(&trace::outputs(output));
+        let hash = Entry::digest(self.next_seq, &self.prev, circuit, &input, &output);
+        let entry = Entry {
+            seq: self.next_seq,
+            prev: self.prev,
+            circuit: circuit.to_string(),
+            input,
+            output,
+            hash,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.sync_data()?;
+
+        self.next_seq += 1;
+        self.prev = hash;
+        Ok(entry)
+    }
+}
+
+/// Read a journal, checking it is unbroken and unmodified.
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = vec![];
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let entry: Entry = serde_json::from_str(&line?)
+            .with_context(|| format!("Line {} is not an entry", i + 1))?;
+        let (expected_seq, prev) = entries
+            .last()
+            .map_or((0, CryptoHash::default()), |e| (e.seq + 1, e.hash));
+        if entry.seq != expected_seq || entry.prev != prev {
+            bail!(
+                Io,
+                "Entry {} does not follow entry {}",
+                entry.seq,
+                expected_seq
+            );
+        }
+        let hash = Entry::digest(
+            entry.seq,
+            &entry.prev,
+            &entry.circuit,
+            &entry.input,
+            &entry.output,
+        );
+        if entry.hash != hash {
+            bail!(Io, "Entry {} has been modified", entry.seq);
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// A job whose outputs no longer match the journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub seq: u64,
+    pub circuit: String,
+    pub expected: CryptoHash,
+    pub actual: CryptoHash,
+}
+
+/// Mock prove each job in order with `prove`, returning those whose outputs
+/// don't match their commitments. `prove` gives the outputs as `trace`
+/// writes them.
+pub fn replay(
+    entries: &[Entry],
+    mut prove: impl FnMut(&str, &PublicInput<L, D>) -> Result<Vec<String>>,
+) -> Result<Vec<Mismatch>> {
+    let mut mismatches = vec![];
+    for entry in entries {
+        log::info!("Replaying {}: {}", entry.seq, entry.circuit);
+        let outputs = prove(&entry.circuit, &entry.input.to_public())
+            .with_context(|| format!("replaying entry {}", entry.seq))?;
+        let actual = commitment(&outputs);
+        if actual != entry.output {
+            mismatches.push(Mismatch {
+                seq: entry.seq,
+                circuit: entry.circuit.clone(),
+                expected: entry.output,
+                actual,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Replay the journal against the deployed circuits.
+pub fn replay_named(entries: &[Entry]) -> Result<Vec<Mismatch>> {
+    replay(entries, |circuit, input| {
+        Ok(trace::record_input(circuit, input)?.outputs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("journal-{}-{}.jsonl", std::process::id(), nanos))
+    }
+
+    fn output(bytes: &[u8]) -> PublicOutput<L, D> {
+        PublicOutput::Bytes(bytes.to_vec())
+    }
+
+    #[test]
+    fn test_journal_is_chained_and_reopened() {
+        let path = temp_path();
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .record("sync", &PublicInput::Bytes(vec![1; 40]), &output(&[2; 40]))
+            .unwrap();
+        drop(journal);
+
+        let mut journal = Journal::open(&path).unwrap();
+        let second = journal
+            .record("sync", &PublicInput::Bytes(vec![3; 40]), &output(&[4; 40]))
+            .unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1], second);
+        assert_eq!(second.prev, entries[0].hash);
+        assert_eq!(entries[0].input, JobInput::Bytes(vec![1; 40]));
+
+        // Reordering breaks the chain
+        let lines = fs::read_to_string(&path).unwrap();
+        let mut lines = lines.lines().collect::<Vec<_>>();
+        lines.swap(0, 1);
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert!(read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_modified_entry_is_rejected() {
+        let path = temp_path();
+        let mut journal = Journal::open(&path).unwrap();
+        journal
+            .record("sync", &PublicInput::Bytes(vec![1; 40]), &output(&[2; 40]))
+            .unwrap();
+
+        let lines = fs::read_to_string(&path).unwrap();
+        fs::write(&path, lines.replace("\"sync\"", "\"rolling-sync\"")).unwrap();
+        assert!(read(&path)
+            .unwrap_err()
+            .to_string()
+            .contains("has been modified"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_finds_changed_outputs() {
+        let path = temp_path();
+        let mut journal = Journal::open(&path).unwrap();
+        for i in 0..3u8 {
+            journal
+                .record("sync", &PublicInput::Bytes(vec![i; 40]), &output(&[i; 32]))
+                .unwrap();
+        }
+        let entries = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Echo the first word of the input, except for the second job
+        let mut order = vec![];
+        let mismatches = replay(&entries, |_, input| {
+            let PublicInput::Bytes(bytes) = input else {
+                bail!(Prove, "expected bytes")
+            };
+            order.push(bytes[0]);
+            let word = if bytes[0] == 1 {
+                [9; 32]
+            } else {
+                [bytes[0]; 32]
+            };
+            Ok(trace::outputs(&output(&word)))
+        })
+        .unwrap();
+        assert_eq!(order, vec![0, 1, 2]);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].seq, 1);
+        assert_eq!(mismatches[0].expected, entries[1].output);
+    }
+
+    #[test]
+    fn test_element_inputs_roundtrip() {
+        let input = JobInput::Elements(vec![0, 1, u32::MAX as u64]);
+        assert_eq!(JobInput::from_public(&input.to_public()).unwrap(), input);
+        let json = serde_json::to_string(&JobInput::Bytes(vec![0xab])).unwrap();
+        assert_eq!(json, r#"{"bytes":"ab"}"#);
+    }
+}
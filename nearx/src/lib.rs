@@ -1,17 +1,40 @@
+pub use aggregate::AggregateSyncCircuit;
 pub use plonky2x::{self, backend::circuit::Circuit, prelude::*};
-pub use sync::SyncCircuit;
-pub use verify::VerifyCircuit;
+pub use state::StateProofCircuit;
+pub use sync::{RollingSyncCircuit, SkipSyncCircuit, SyncCircuit};
+pub use verify::{BatchVerifyCircuit, VerifyCircuit};
 
+/// Public outputs a contract can `abi.decode`
+pub mod abi;
 /// Building blocks injected into the CircuitBuilder
 mod builder;
+/// Estimating on-chain costs of public output layouts
+pub mod gas;
+/// Initialising a verifier contract
+pub mod genesis;
 mod hint;
+/// Journaling proving jobs so they can be replayed
+pub mod journal;
 /// Unprefixed merkle tree without collision resistance
 mod merkle;
+/// Re-proving a range of the sync chain
+pub mod range;
+/// Rebuilding the circuits to check their verifier keys
+pub mod repro;
+/// Diffing what the circuits compute across builds
+pub mod trace;
 mod variables;
+/// Wrapping proofs for verification on Ethereum
+pub mod wrap;
 
 /// Circuits for use by the operator
+pub mod aggregate;
+/// Proving contract state against a synced header
+pub mod state;
 pub mod sync;
 pub mod verify;
 
+#[cfg(test)]
+mod fuzz;
 #[cfg(test)]
 mod test_utils;
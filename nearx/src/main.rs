@@ -1,26 +1,57 @@
-#[cfg(any(feature = "sync", feature = "verify"))]
+#[cfg(any(
+    feature = "sync",
+    feature = "rolling-sync",
+    feature = "aggregate-sync",
+    feature = "skip-sync",
+    feature = "state-proof",
+    feature = "verify"
+))]
 use near_light_clientx::plonky2x::backend::function::Plonky2xFunction;
-
-// Testnet, FIXME: this is error prone, use something else
-#[allow(dead_code)]
-const NETWORK: usize = 1;
+#[cfg(any(
+    feature = "sync",
+    feature = "rolling-sync",
+    feature = "aggregate-sync",
+    feature = "skip-sync",
+    feature = "state-proof",
+    feature = "verify"
+))]
+use near_light_clientx::repro::{ABI, NETWORK};
 
 // TODO: make this use a nicer API for use by the prover.
 // TODO: perpetually sync, use queue etc
 fn main() {
     cfg_if::cfg_if! {
         if #[cfg(feature = "sync")] {
-            use near_light_clientx::SyncCircuit;
-            SyncCircuit::<NETWORK>::entrypoint();
+            use near_light_clientx::{repro::KEY_POLICY, SyncCircuit};
+            SyncCircuit::<NETWORK, ABI, KEY_POLICY>::entrypoint();
+        } else if #[cfg(feature = "rolling-sync")] {
+            use near_light_clientx::{repro::KEY_POLICY, RollingSyncCircuit};
+            RollingSyncCircuit::<NETWORK, KEY_POLICY>::entrypoint();
+        } else if #[cfg(feature = "aggregate-sync")] {
+            use near_light_clientx::{
+                repro::{AGGREGATE_SYNC_AMT, KEY_POLICY},
+                AggregateSyncCircuit,
+            };
+            AggregateSyncCircuit::<AGGREGATE_SYNC_AMT, NETWORK, KEY_POLICY>::entrypoint();
+        } else if #[cfg(feature = "skip-sync")] {
+            use near_light_clientx::{
+                repro::{KEY_POLICY, SKIP_SYNC_EPOCHS},
+                SkipSyncCircuit,
+            };
+            SkipSyncCircuit::<SKIP_SYNC_EPOCHS, NETWORK, KEY_POLICY>::entrypoint();
+        } else if #[cfg(feature = "state-proof")] {
+            use near_light_clientx::StateProofCircuit;
+            StateProofCircuit::<NETWORK>::entrypoint();
         } else if #[cfg(feature = "verify")] {
-            const PROOF_AMT: usize = 128;
-            const PROOF_BATCH_SIZE: usize = 4;
+            use near_light_clientx::repro::{
+                VERIFY_PROOF_AMT as PROOF_AMT, VERIFY_PROOF_BATCH_SIZE as PROOF_BATCH_SIZE,
+            };
 
             assert!(PROOF_AMT % PROOF_BATCH_SIZE == 0);
             assert!((PROOF_AMT / PROOF_BATCH_SIZE).is_power_of_two());
 
             use near_light_clientx::VerifyCircuit;
-            VerifyCircuit::<PROOF_AMT, PROOF_BATCH_SIZE, NETWORK>::entrypoint();
+            VerifyCircuit::<PROOF_AMT, PROOF_BATCH_SIZE, NETWORK, ABI>::entrypoint();
         } else {
             panic!("No circuit feature enabled");
         }
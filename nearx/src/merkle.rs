@@ -1,43 +1,53 @@
 use near_light_client_protocol::{merkle_util::MerklePath, prelude::Itertools};
-use plonky2x::prelude::*;
+use plonky2x::prelude::{plonky2::field::types::Field, *};
 
 /// This is an unprefixed merkle tree without collision resistance, this should
 /// probably adapt the tendermint tree or introduce this functionality to
 /// succinct's simple tree
 pub trait NearMerkleTree {
-    fn get_root_from_merkle_proof_hashed_leaf_unindex<const PROOF_DEPTH: usize>(
+    /// Fold the leaf up the first `path.len` nodes, the padding past them is
+    /// ignored. The length must fit the path.
+    fn get_root_from_merkle_path<const MAX_LEN: usize>(
         &mut self,
-        proof: &ArrayVariable<Bytes32Variable, PROOF_DEPTH>,
-        path_indices: &ArrayVariable<BoolVariable, PROOF_DEPTH>,
+        path: &MerklePathVariable<MAX_LEN>,
         leaf: Bytes32Variable,
     ) -> Bytes32Variable;
     fn inner_hash(&mut self, left: &Bytes32Variable, right: &Bytes32Variable) -> Bytes32Variable;
 }
 
 impl<L: PlonkParameters<D>, const D: usize> NearMerkleTree for CircuitBuilder<L, D> {
-    fn get_root_from_merkle_proof_hashed_leaf_unindex<const PROOF_DEPTH: usize>(
+    fn get_root_from_merkle_path<const MAX_LEN: usize>(
         &mut self,
-        proof: &ArrayVariable<Bytes32Variable, PROOF_DEPTH>,
-        path_indices: &ArrayVariable<BoolVariable, PROOF_DEPTH>,
+        path: &MerklePathVariable<MAX_LEN>,
         leaf: Bytes32Variable,
     ) -> Bytes32Variable {
         let mut hash_so_far = leaf;
 
-        let default = self.constant::<Bytes32Variable>(INACTIVE_NODE.into());
-
-        for i in 0..PROOF_DEPTH {
-            let aunt = proof[i];
-            let path_index = path_indices[i];
-
+        // A node is active until its index reaches the length
+        let mut active = self._true();
+        let mut fits = self._false();
+        for i in 0..MAX_LEN {
+            let index = self.constant::<Variable>(L::Field::from_canonical_usize(i));
+            let is_len = self.is_equal(path.len, index);
+            fits = self.or(fits, is_len);
+            let not_len = self.not(is_len);
+            active = self.and(active, not_len);
+
+            let aunt = path.path[i];
             let left_hash_pair = self.inner_hash(&hash_so_far, &aunt);
             let right_hash_pair = self.inner_hash(&aunt, &hash_so_far);
 
-            let aunt_is_default = self.is_equal(aunt, default);
+            let hash = self.select(path.indices[i], right_hash_pair, left_hash_pair);
 
-            let hash = self.select(path_index, right_hash_pair, left_hash_pair);
-
-            hash_so_far = self.select(aunt_is_default, hash_so_far, hash)
+            hash_so_far = self.select(active, hash, hash_so_far)
         }
+
+        let max = self.constant::<Variable>(L::Field::from_canonical_usize(MAX_LEN));
+        let is_max = self.is_equal(path.len, max);
+        fits = self.or(fits, is_max);
+        let t = self._true();
+        self.assert_is_equal(fits, t);
+
         hash_so_far
     }
     fn inner_hash(&mut self, left: &Bytes32Variable, right: &Bytes32Variable) -> Bytes32Variable {
@@ -57,40 +67,76 @@ pub fn determine_direction(dir: &near_light_client_protocol::Direction) -> bool
     }
 }
 
-const INACTIVE_NODE: [u8; 32] = [255; 32];
-
+/// A merkle path of up to `MAX_LEN` nodes, only the first `len` are hashed.
 #[derive(CircuitVariable, Clone, Debug)]
 pub struct MerklePathVariable<const MAX_LEN: usize> {
+    pub len: Variable,
     pub path: ArrayVariable<Bytes32Variable, MAX_LEN>,
     pub indices: ArrayVariable<BoolVariable, MAX_LEN>,
 }
+
 impl<F: RichField, const MAX_LEN: usize> From<MerklePath> for MerklePathVariableValue<MAX_LEN, F> {
     fn from(path: MerklePath) -> Self {
-        assert!(path.len() <= MAX_LEN, "merkle path too long");
+        assert!(
+            path.len() <= MAX_LEN,
+            "the merkle path has {} nodes, the circuit fits {}",
+            path.len(),
+            MAX_LEN
+        );
+        let len = F::from_canonical_usize(path.len());
 
         let mut indices = path
             .iter()
             .map(|x| &x.direction)
             .map(determine_direction)
             .collect_vec();
-
         indices.resize(MAX_LEN, Default::default());
 
         let mut path = path.iter().map(|x| x.hash.0.into()).collect_vec();
-        path.resize(MAX_LEN, INACTIVE_NODE.into());
+        path.resize(MAX_LEN, Default::default());
 
-        Self { path, indices }
+        Self { len, path, indices }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use near_light_client_protocol::prelude::BasicProof;
+    use plonky2x::prelude::plonky2::field::types::PrimeField64;
+
     use super::*;
-    use crate::{test_utils::*, variables::ProofVariable};
+    use crate::{
+        test_utils::*,
+        variables::{ProofVariable, ProofVariableValue},
+    };
+
+    #[test]
+    fn test_path_is_padded_to_the_circuit() {
+        let path: MerklePath = fixture::<BasicProof>("old.json").block_proof;
+        let len = path.len();
+        let value = MerklePathVariableValue::<64, GoldilocksField>::from(path.clone());
+        assert_eq!(value.len, GoldilocksField::from_canonical_usize(len));
+        assert_eq!(value.path.len(), 64);
+        assert_eq!(value.path[len..], vec![Default::default(); 64 - len]);
+        assert_eq!(
+            value.indices[..len],
+            path.iter()
+                .map(|x| determine_direction(&x.direction))
+                .collect_vec()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the circuit fits 2")]
+    fn test_path_too_long_for_the_circuit() {
+        let path: MerklePath = fixture::<BasicProof>("old.json").block_proof;
+        assert!(path.len() > 2);
+        let _ = MerklePathVariableValue::<2, GoldilocksField>::from(path);
+    }
 
     #[test]
     #[ignore]
-    fn beefy_test_path_is_ignored_if_default() {
+    fn beefy_test_padding_is_ignored() {
         let block_root =
             CryptoHash::from_str("WWrLWbWHwSmjtTn5oBZPYgRCuCYn6fkYVa4yhPWNK4L").unwrap();
 
@@ -98,23 +144,24 @@ mod tests {
             let p = b.read::<ProofVariable>();
             let hash = p.block_header.hash(b);
 
-            let root = b.get_root_from_merkle_proof_hashed_leaf_unindex(
-                &p.block_proof.path,
-                &p.block_proof.indices,
-                hash,
-            );
+            let root = b.get_root_from_merkle_path(&p.block_proof, hash);
             let v = b.is_equal(root, p.head_block_root);
 
             b.write::<BoolVariable>(v);
         };
         let writer = |input: &mut PI| {
-            input.write::<ProofVariable>(
+            let mut value: ProofVariableValue<GoldilocksField> =
                 near_light_client_protocol::Proof::Basic {
                     head_block_root: block_root,
                     proof: Box::new(fixture("old.json")),
                 }
-                .into(),
-            );
+                .into();
+            // Whatever is in the padding, it isn't hashed
+            let len = value.block_proof.len.to_canonical_u64() as usize;
+            for node in value.block_proof.path[len..].iter_mut() {
+                *node = [7u8; 32].into();
+            }
+            input.write::<ProofVariable>(value);
         };
         let assertions = |mut output: PO| {
             assert!(output.read::<BoolVariable>());
@@ -0,0 +1,366 @@
+//! Re-proving a contiguous range of the sync chain, e.g for a destination
+//! chain that wants its own genesis rather than trusting our latest head.
+//!
+//! Fetching, witnessing and proving run as stages joined by bounded channels.
+//! The prover is kept busy while the following steps are fetched, and a slow
+//! prover holds up fetching rather than buffering the rest of the range.
+//!
+//! The circuit fetches its own witness through async hints, so the witness
+//! stage doesn't hand it any data. It checks each step natively the way the
+//! circuit will, so a step that can't sync fails the job before it reaches
+//! the prover rather than after minutes of proving.
+use near_light_client_protocol::{
+    prelude::{CryptoHash, Header},
+    signature::KeyPolicy,
+    BlockHeight, LightClientBlockView, Protocol, ValidatorStake, ValidatorStakeView,
+};
+use near_light_client_rpc::{LightClientRpc, NearRpcClient};
+use nearx_error::{ensure, err, Context, InLayer, Layer, Result};
+use plonky2x::prelude::{
+    plonky2::plonk::proof::ProofWithPublicInputs, CircuitBuilder, DefaultParameters,
+    PlonkParameters,
+};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{
+    journal::Journal,
+    repro::{ABI, KEY_POLICY},
+    variables::{domain_from_chain_id, CryptoHashVariable, DomainVariable},
+    Circuit, SyncCircuit,
+};
+
+type L = DefaultParameters;
+const D: usize = 2;
+
+pub type Proof =
+    ProofWithPublicInputs<<L as PlonkParameters<D>>::Field, <L as PlonkParameters<D>>::Config, D>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeConfig {
+    /// The chain the proofs are for, see `DomainVariable`.
+    pub chain_id: u64,
+    /// The hash of the trusted header the range starts from.
+    pub from: CryptoHash,
+    /// Stop once synced to this height or past it.
+    pub until: BlockHeight,
+    /// How many steps each stage may run ahead of the next.
+    pub capacity: usize,
+}
+
+/// A step from a trusted head to the light client block after it.
+#[derive(Debug, Clone)]
+struct Step {
+    head: Header,
+    next: LightClientBlockView,
+}
+
+/// A step that syncs natively, so it will prove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Witnessed {
+    pub trusted: CryptoHash,
+    pub synced: CryptoHash,
+    pub height: BlockHeight,
+    /// The seats that signed with an invalid key, the circuit treats them as
+    /// natively, see `KeyPolicy`.
+    pub rejected_keys: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Proven {
+    #[serde(flatten)]
+    pub step: Witnessed,
+    pub proof: Proof,
+}
+
+/// Prove each step of the range in order, sending the proofs to `proven` as
+/// they are made. Each proof is recorded in the journal, if given, before it
+/// is sent.
+///
+/// The prover runs on its own thread, when fetching or witnessing fails it
+/// finishes the proof it is making and stops.
+pub async fn prove_range<const NETWORK: usize>(
+    config: RangeConfig,
+    proven: Sender<Proven>,
+    journal: Option<Journal>,
+) -> Result<()> {
+    let client = NearRpcClient::new(NETWORK.into());
+    let (witnessed_tx, witnessed_rx) = mpsc::channel(config.capacity);
+
+    let prover = std::thread::spawn(move || {
+        prove::<NETWORK>(config.chain_id, witnessed_rx, proven, journal)
+    });
+    witness_range(&client, &config, witnessed_tx).await?;
+
+    tokio::task::spawn_blocking(move || prover.join())
+        .await
+        .in_layer(Layer::Prove)?
+        .map_err(|_| err!(Prove, "The prover panicked"))?
+}
+
+/// Fetch the steps of the range and check each syncs, sending those that do
+/// to `witnessed`.
+pub async fn witness_range(
+    client: &(impl LightClientRpc + Sync),
+    config: &RangeConfig,
+    witnessed: Sender<Witnessed>,
+) -> Result<()> {
+    let (steps_tx, steps_rx) = mpsc::channel(config.capacity);
+    tokio::try_join!(
+        fetch(client, config, steps_tx),
+        witness(client, steps_rx, witnessed)
+    )?;
+    Ok(())
+}
+
+async fn fetch(
+    client: &(impl LightClientRpc + Sync),
+    config: &RangeConfig,
+    steps: Sender<Step>,
+) -> Result<()> {
+    let mut head = client.fetch_header(&config.from).await?;
+    while head.inner_lite.height < config.until {
+        let hash = head.hash();
+        let next = client
+            .fetch_latest_header(&hash)
+            .await?
+            .ok_or_else(|| err!(Rpc, "No light client block after {}", hash))?;
+        let new_head = Header {
+            prev_block_hash: next.prev_block_hash,
+            inner_rest_hash: next.inner_rest_hash,
+            inner_lite: next.inner_lite.clone(),
+        };
+        log::debug!("Fetched {}", new_head.inner_lite.height);
+
+        // The witness stage failed, it has the error
+        if steps.send(Step { head, next }).await.is_err() {
+            break;
+        }
+        head = new_head;
+    }
+    Ok(())
+}
+
+async fn witness(
+    client: &(impl LightClientRpc + Sync),
+    mut steps: Receiver<Step>,
+    witnessed: Sender<Witnessed>,
+) -> Result<()> {
+    let mut epoch: Option<(CryptoHash, Vec<ValidatorStake>)> = None;
+    while let Some(Step { head, next }) = steps.recv().await {
+        // The same BPS the circuit witnesses, see `sync_from_trusted`
+        let epoch_id = head.inner_lite.next_epoch_id;
+        if epoch.as_ref().map(|(id, _)| *id) != Some(epoch_id) {
+            let bps = client.fetch_epoch_bps(&epoch_id).await?;
+            ensure!(
+                CryptoHash::hash_borsh(&bps) == head.inner_lite.next_bp_hash,
+                Witness,
+                "The BPS of {} don't match the head at {}",
+                epoch_id,
+                head.inner_lite.height
+            );
+            let bps = bps
+                .into_iter()
+                .map(ValidatorStakeView::into_validator_stake)
+                .collect();
+            epoch = Some((epoch_id, bps));
+        }
+        let (_, bps) = epoch.as_ref().expect("the epoch was just set");
+
+        let height = next.inner_lite.height;
+        let synced = Protocol::sync(&head, bps, next, KeyPolicy::from_u8(KEY_POLICY))
+            .with_context(|| format!("syncing to {}", height))?;
+        log::debug!("Witnessed {}", height);
+
+        let step = Witnessed {
+            trusted: head.hash(),
+            synced: synced.new_head.hash(),
+            height,
+            rejected_keys: synced.rejected_keys,
+        };
+        // The prover stopped, nothing will prove this
+        if witnessed.send(step).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Build the sync circuit once and prove each step with it.
+fn prove<const NETWORK: usize>(
+    chain_id: u64,
+    mut witnessed: Receiver<Witnessed>,
+    proven: Sender<Proven>,
+    mut journal: Option<Journal>,
+) -> Result<()> {
+    let mut b = CircuitBuilder::<L, D>::new();
+    SyncCircuit::<NETWORK, ABI, KEY_POLICY>::define(&mut b);
+    let circuit = b.build();
+    let domain = domain_from_chain_id(chain_id);
+
+    while let Some(step) = witnessed.blocking_recv() {
+        log::info!("Proving {}", step.height);
+        let mut input = circuit.input();
+        input.evm_write::<DomainVariable>(domain.into());
+        input.evm_write::<CryptoHashVariable>(step.trusted.0.into());
+
+        let (proof, mut output) = circuit.prove(&input);
+        if let Some(journal) = journal.as_mut() {
+            journal
+                .record("sync", &input, &output)
+                .with_context(|| format!("journaling the proof of {}", step.height))?;
+        }
+        let _domain = output.evm_read::<DomainVariable>();
+        let synced = CryptoHash(output.evm_read::<CryptoHashVariable>().0);
+        ensure!(
+            synced == step.synced,
+            Prove,
+            "Proved {} to {} but witnessed {}",
+            step.height,
+            synced,
+            step.synced
+        );
+
+        if proven.blocking_send(Proven { step, proof }).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use near_light_client_protocol::prelude::BasicProof;
+    use near_light_client_rpc::prelude::GetProof;
+    use test_utils::{test_first, test_last, test_next, to_header, LightClientFixture};
+
+    use super::*;
+
+    /// Serves recorded light client blocks by the head they follow.
+    struct FixtureRpc(Vec<LightClientFixture<LightClientBlockView>>);
+
+    #[async_trait]
+    impl LightClientRpc for FixtureRpc {
+        async fn fetch_latest_header(
+            &self,
+            latest_verified: &CryptoHash,
+        ) -> Result<Option<LightClientBlockView>> {
+            Ok(self
+                .0
+                .iter()
+                .find(|f| f.last_block_hash == *latest_verified)
+                .map(|f| f.body.clone()))
+        }
+
+        async fn fetch_light_client_proof(
+            &self,
+            _req: GetProof,
+            _latest_verified: CryptoHash,
+        ) -> Result<BasicProof> {
+            nearx_error::bail!(Rpc, "No proofs are recorded")
+        }
+
+        async fn fetch_epoch_bps(&self, epoch_id: &CryptoHash) -> Result<Vec<ValidatorStakeView>> {
+            self.0
+                .iter()
+                .find(|f| f.body.inner_lite.next_epoch_id == *epoch_id)
+                .and_then(|f| f.body.next_bps.clone())
+                .ok_or_else(|| err!(Rpc, "No validators are recorded for {}", epoch_id))
+        }
+
+        async fn fetch_header(&self, hash: &CryptoHash) -> Result<Header> {
+            self.0
+                .iter()
+                .map(|f| to_header(f.body.clone()))
+                .find(|h| h.hash() == *hash)
+                .ok_or_else(|| err!(Rpc, "No header is recorded for {}", hash))
+        }
+    }
+
+    fn config(until: BlockHeight) -> RangeConfig {
+        RangeConfig {
+            chain_id: 5,
+            // The hash of the first block
+            from: test_next().last_block_hash,
+            until,
+            capacity: 1,
+        }
+    }
+
+    async fn witness_all(rpc: &FixtureRpc, config: &RangeConfig) -> Result<Vec<Witnessed>> {
+        let (tx, mut rx) = mpsc::channel(config.capacity);
+        let collect = async {
+            let mut all = vec![];
+            while let Some(step) = rx.recv().await {
+                all.push(step);
+            }
+            all
+        };
+        let (result, all) = tokio::join!(witness_range(rpc, config, tx), collect);
+        result.map(|_| all)
+    }
+
+    #[tokio::test]
+    async fn test_witness_range() {
+        let (first, next, last) = (test_first(), test_next(), test_last());
+        let rpc = FixtureRpc(vec![first.clone(), next.clone(), last.clone()]);
+
+        let config = config(last.body.inner_lite.height);
+        let steps = witness_all(&rpc, &config).await.unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                Witnessed {
+                    trusted: next.last_block_hash,
+                    synced: last.last_block_hash,
+                    height: next.body.inner_lite.height,
+                    rejected_keys: 0,
+                },
+                Witnessed {
+                    trusted: last.last_block_hash,
+                    synced: to_header(last.body.clone()).hash(),
+                    height: last.body.inner_lite.height,
+                    rejected_keys: 0,
+                },
+            ]
+        );
+
+        // Stops at the first block reaching the height
+        let config = RangeConfig {
+            until: next.body.inner_lite.height - 1,
+            ..config
+        };
+        assert_eq!(witness_all(&rpc, &config).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_witness_range_stops_at_bad_block() {
+        let (first, next, mut last) = (test_first(), test_next(), test_last());
+        last.body.approvals_after_next.clear();
+        let rpc = FixtureRpc(vec![first, next.clone(), last.clone()]);
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let e = witness_range(&rpc, &config(last.body.inner_lite.height), tx)
+            .await
+            .unwrap_err();
+        assert!(e
+            .to_string()
+            .contains(&last.body.inner_lite.height.to_string()));
+        assert_eq!(e.layer(), Layer::Protocol);
+        assert!(e
+            .downcast_ref::<near_light_client_protocol::error::Error>()
+            .is_some());
+
+        // The good step got through before it
+        assert_eq!(rx.recv().await.unwrap().height, next.body.inner_lite.height);
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_witness_range_runs_out_of_blocks() {
+        let (first, next) = (test_first(), test_next());
+        let rpc = FixtureRpc(vec![first, next]);
+        assert!(witness_all(&rpc, &config(BlockHeight::MAX)).await.is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Reproducible verifier keys.
+//!
+//! Building a circuit is deterministic, preprocessing samples no randomness,
+//! only proving does. So anyone can rebuild each circuit from this source and
+//! check its verifier key digest against the manifest, which is what the
+//! deployed function ids were registered with.
+//!
+//! The digest doesn't depend on the compiler, but the manifest records the
+//! environment it was produced in so that a mismatch can be narrowed down.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use near_light_client_protocol::signature::KeyPolicy;
+use plonky2x::prelude::{plonky2::plonk::config::GenericHashOut, *};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AggregateSyncCircuit, Circuit, RollingSyncCircuit, SkipSyncCircuit, StateProofCircuit,
+    SyncCircuit, VerifyCircuit,
+};
+
+/// The parameters of the deployed circuits, the entrypoints use these too.
+// Testnet, FIXME: this is error prone, use something else
+pub const NETWORK: usize = 1;
+
+/// Whether the sync and verify circuits `abi.encode` their outputs, their
+/// digests and output version differ with it so each encoding has its own
+/// manifest.
+pub const ABI: bool = cfg!(feature = "abi");
+
+/// The `KeyPolicy` the sync circuits are built with, `reject-invalid-keys`
+/// fails a sync approved by a seat with an invalid key rather than dropping
+/// its approval. Their digests differ with it, and the native sync of
+/// whoever picks the heads to prove must apply the same.
+pub const KEY_POLICY: u8 = if cfg!(feature = "reject-invalid-keys") {
+    KeyPolicy::Reject as u8
+} else {
+    KeyPolicy::Inactive as u8
+};
+
+// The `dev` profile shrinks the verify circuit so it builds and proves on a
+// laptop. The sync circuits keep every seat, since the approvals of a subset
+// can't be shown to reach the stake threshold. Dev circuits have their own
+// verifier keys so their proofs are never accepted by a deployment.
+cfg_if::cfg_if! {
+    if #[cfg(feature = "dev")] {
+        pub const PROFILE: &str = "dev";
+        pub const VERIFY_PROOF_AMT: usize = 4;
+        pub const VERIFY_PROOF_BATCH_SIZE: usize = 2;
+        pub const AGGREGATE_SYNC_AMT: usize = 2;
+        pub const SKIP_SYNC_EPOCHS: usize = 2;
+    } else {
+        pub const PROFILE: &str = "prod";
+        pub const VERIFY_PROOF_AMT: usize = 128;
+        pub const VERIFY_PROOF_BATCH_SIZE: usize = 4;
+        pub const AGGREGATE_SYNC_AMT: usize = 8;
+        pub const SKIP_SYNC_EPOCHS: usize = 4;
+    }
+}
+
+pub const MANIFEST_PATH: &str = if ABI {
+    "nearx/verifier-keys.abi.json"
+} else {
+    "nearx/verifier-keys.json"
+};
+
+/// The semantic version of the public outputs the circuits write, which a
+/// verifier contract decodes. A new major version changes the layout of
+/// existing outputs, a new minor version only appends to them.
+pub const OUTPUT_VERSION: &str = if ABI { "2.0.0" } else { "1.0.0" };
+
+/// Every deployed circuit, by its name in the manifest.
+pub fn circuits() -> Vec<(&'static str, fn() -> String)> {
+    vec![
+        ("sync", digest::<SyncCircuit<NETWORK, ABI, KEY_POLICY>>),
+        (
+            "rolling-sync",
+            digest::<RollingSyncCircuit<NETWORK, KEY_POLICY>>,
+        ),
+        (
+            "aggregate-sync",
+            digest::<AggregateSyncCircuit<AGGREGATE_SYNC_AMT, NETWORK, KEY_POLICY>>,
+        ),
+        (
+            "skip-sync",
+            digest::<SkipSyncCircuit<SKIP_SYNC_EPOCHS, NETWORK, KEY_POLICY>>,
+        ),
+        ("state-proof", digest::<StateProofCircuit<NETWORK>>),
+        (
+            "verify",
+            digest::<VerifyCircuit<VERIFY_PROOF_AMT, VERIFY_PROOF_BATCH_SIZE, NETWORK, ABI>>,
+        ),
+    ]
+}
+
+/// Build the circuit and return the digest of its verifier key.
+pub fn digest<C: Circuit>() -> String {
+    let mut b = CircuitBuilder::<DefaultParameters, 2>::new();
+    C::define(&mut b);
+    let circuit = b.build();
+    format!(
+        "0x{}",
+        hex::encode(circuit.data.verifier_only.circuit_digest.to_bytes())
+    )
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Environment {
+    pub toolchain: String,
+    pub command: String,
+    pub rustflags: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub environment: Environment,
+    /// The `OUTPUT_VERSION` the digests were recorded at.
+    #[serde(default)]
+    pub output_version: String,
+    /// Digest of the verifier key by circuit, unset until the circuit has
+    /// been built in the pinned environment.
+    pub digests: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Match,
+    Unrecorded,
+    Mismatch { expected: String },
+}
+
+impl Manifest {
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+    }
+
+    pub fn check(&self, name: &str, digest: &str) -> Verdict {
+        match self.digests.get(name).cloned().flatten() {
+            None => Verdict::Unrecorded,
+            Some(expected) if expected.eq_ignore_ascii_case(digest) => Verdict::Match,
+            Some(expected) => Verdict::Mismatch { expected },
+        }
+    }
+
+    pub fn record(&mut self, name: &str, digest: String) {
+        self.digests.insert(name.to_string(), Some(digest));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.check("sync", "0xab"), Verdict::Unrecorded);
+
+        manifest.record("sync", "0xAB".to_string());
+        assert_eq!(manifest.check("sync", "0xab"), Verdict::Match);
+        assert_eq!(
+            manifest.check("sync", "0xcd"),
+            Verdict::Mismatch {
+                expected: "0xAB".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_verify_params_are_valid() {
+        assert!(VERIFY_PROOF_AMT % VERIFY_PROOF_BATCH_SIZE == 0);
+        assert!((VERIFY_PROOF_AMT / VERIFY_PROOF_BATCH_SIZE).is_power_of_two());
+    }
+
+    #[test]
+    fn test_committed_manifest_covers_circuits() {
+        let path = crate::test_utils::workspace_dir().join(MANIFEST_PATH);
+        let manifest = Manifest::load(path).unwrap();
+        for (name, _) in circuits() {
+            assert!(manifest.digests.contains_key(name), "{} missing", name);
+        }
+        assert_eq!(manifest.digests.len(), circuits().len());
+        assert_eq!(manifest.output_version, OUTPUT_VERSION);
+    }
+
+    #[test]
+    fn test_committed_manifests_per_encoding() {
+        let dir = crate::test_utils::workspace_dir();
+        let packed = Manifest::load(dir.join("nearx/verifier-keys.json")).unwrap();
+        let abi = Manifest::load(dir.join("nearx/verifier-keys.abi.json")).unwrap();
+        assert_eq!(packed.output_version, "1.0.0");
+        assert_eq!(abi.output_version, "2.0.0");
+        assert!(abi.digests.keys().eq(packed.digests.keys()));
+    }
+}
+
+#[cfg(test)]
+mod beefy_tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_sync_digest_is_deterministic() {
+        assert_eq!(
+            digest::<SyncCircuit<NETWORK>>(),
+            digest::<SyncCircuit<NETWORK>>()
+        );
+    }
+}
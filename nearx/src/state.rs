@@ -0,0 +1,663 @@
+//! Proving contract state against a header, see `protocol::state`.
+//!
+//! The trie nodes are witnessed as their encodings, hashed as they are, then
+//! read where nearcore's encoding puts things: the child for the key's next
+//! nibble in a branch, or the part of the key and what follows it in a leaf
+//! or an extension. Keys, nodes and values are bounded by the constants
+//! below, anything bigger can't be proven.
+use near_light_client_protocol::{
+    config::ACCOUNT_DATA_SEPARATOR,
+    prelude::{AccountId, Itertools},
+    state::{StateProof, TrieNode, CONTRACT_DATA},
+};
+use plonky2x::prelude::plonky2::{
+    field::types::Field,
+    plonk::config::{AlgebraicHasher, GenericConfig},
+};
+pub use plonky2x::{self, backend::circuit::Circuit, prelude::*};
+
+use crate::{
+    hint::{FetchHeaderInputs, FetchStateProofInputs},
+    merkle::{MerklePathVariable, NearMerkleTree},
+    variables::{
+        account_id_len, assert_network_fits, byte_to_variable, le_sum, shift_left, shift_right,
+        variable_to_byte, AccountIdVariable, CryptoHashVariable, DomainVariable, EncodeInner,
+    },
+};
+
+/// The longest storage key that can be proven.
+pub const MAX_STATE_KEY_LEN: usize = 64;
+/// The longest value that can be proven.
+pub const MAX_STATE_VALUE_LEN: usize = 256;
+/// The most trie nodes from a shard root down to a value.
+pub const MAX_STATE_PROOF_NODES: usize = 20;
+/// Enough for 32 shards.
+pub const SHARD_PROOF_DEPTH: usize = 5;
+/// A branch with a value and every child is the longest node: the tag, the
+/// value, the children bitmap, the children and the memory usage.
+pub const MAX_TRIE_NODE_LEN: usize = 1 + 36 + 2 + 16 * 32 + 8;
+
+/// The column, the account id, the separator and the storage key.
+const MAX_TRIE_KEY_LEN: usize = 2 + AccountId::MAX_LEN + MAX_STATE_KEY_LEN;
+const MAX_TRIE_KEY_NIBBLES: usize = 2 * MAX_TRIE_KEY_LEN;
+/// Where the key of a leaf or an extension starts, after the tag and its u32
+/// length. Its first byte holds the flags.
+const NODE_KEY_START: usize = 5;
+
+/// A trie node's encoding, zeroed after its length.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct TrieNodeVariable {
+    pub len: Variable,
+    pub bytes: ArrayVariable<ByteVariable, MAX_TRIE_NODE_LEN>,
+}
+
+impl<F: RichField> From<Vec<u8>> for TrieNodeVariableValue<F> {
+    fn from(mut node: Vec<u8>) -> Self {
+        assert!(
+            node.len() <= MAX_TRIE_NODE_LEN,
+            "the trie node has {} bytes, the circuit fits {}",
+            node.len(),
+            MAX_TRIE_NODE_LEN
+        );
+        let len = F::from_canonical_usize(node.len());
+        node.resize(MAX_TRIE_NODE_LEN, 0);
+        Self { len, bytes: node }
+    }
+}
+
+/// A value, zeroed after its length.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct StateValueVariable {
+    pub len: Variable,
+    pub bytes: ArrayVariable<ByteVariable, MAX_STATE_VALUE_LEN>,
+}
+
+impl<F: RichField> From<Vec<u8>> for StateValueVariableValue<F> {
+    fn from(mut value: Vec<u8>) -> Self {
+        assert!(
+            value.len() <= MAX_STATE_VALUE_LEN,
+            "the value has {} bytes, the circuit fits {}",
+            value.len(),
+            MAX_STATE_VALUE_LEN
+        );
+        let len = F::from_canonical_usize(value.len());
+        value.resize(MAX_STATE_VALUE_LEN, 0);
+        Self { len, bytes: value }
+    }
+}
+
+/// See `protocol::state::StateProof`. The nodes after the value are padding.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct StateProofVariable {
+    pub shard_root: CryptoHashVariable,
+    pub shard_proof: MerklePathVariable<SHARD_PROOF_DEPTH>,
+    pub nodes: ArrayVariable<TrieNodeVariable, MAX_STATE_PROOF_NODES>,
+    pub value: StateValueVariable,
+}
+
+impl<F: RichField> From<StateProof> for StateProofVariableValue<F> {
+    fn from(proof: StateProof) -> Self {
+        assert!(
+            proof.nodes.len() <= MAX_STATE_PROOF_NODES,
+            "the state proof has {} nodes, the circuit fits {}",
+            proof.nodes.len(),
+            MAX_STATE_PROOF_NODES
+        );
+        let mut nodes = proof.nodes.into_iter().map(Into::into).collect_vec();
+        nodes.resize(MAX_STATE_PROOF_NODES, vec![].into());
+        Self {
+            shard_root: proof.shard_root.0.into(),
+            shard_proof: proof.shard_proof.into(),
+            nodes,
+            value: proof.value.into(),
+        }
+    }
+}
+
+/// A trie key as nibbles, zeroed after its length.
+#[derive(Clone, Debug)]
+pub struct TrieKeyVariable {
+    pub nibbles: Vec<Variable>,
+    pub len: Variable,
+}
+
+impl TrieKeyVariable {
+    /// The key of a contract's storage key, see
+    /// `protocol::state::contract_data_key`. The storage key must be zeroed
+    /// after its length.
+    pub fn contract_data<L: PlonkParameters<D>, const D: usize>(
+        b: &mut CircuitBuilder<L, D>,
+        account_id: &AccountIdVariable,
+        key_len: Variable,
+        key: &[ByteVariable],
+    ) -> Self {
+        assert_eq!(key.len(), MAX_STATE_KEY_LEN);
+        let zero = b.zero::<Variable>();
+        let t = b._true();
+        let (account_len, in_account) = account_id_len(b, account_id);
+
+        let mut bytes = vec![b.constant::<Variable>(L::Field::from_canonical_u8(CONTRACT_DATA))];
+        for (byte, in_account) in account_id.0.into_iter().zip(in_account) {
+            let byte = byte_to_variable(b, byte);
+            bytes.push(b.select(in_account, byte, zero));
+        }
+
+        // The separator and the storage key follow the account id, wherever
+        // it ends
+        let mut rest =
+            vec![b.constant::<Variable>(L::Field::from_canonical_u8(ACCOUNT_DATA_SEPARATOR))];
+        let mut in_key = t;
+        for (i, byte) in key.iter().enumerate() {
+            let i = b.constant::<Variable>(L::Field::from_canonical_usize(i));
+            let is_len = b.is_equal(key_len, i);
+            let not_len = b.not(is_len);
+            in_key = b.and(in_key, not_len);
+
+            let byte = byte_to_variable(b, *byte);
+            let is_zero = b.is_zero(byte);
+            let canonical = b.or(in_key, is_zero);
+            b.assert_is_equal(canonical, t);
+            rest.push(byte);
+        }
+        // The key fits if it ended by the last byte or is the whole array
+        let max = b.constant::<Variable>(L::Field::from_canonical_usize(MAX_STATE_KEY_LEN));
+        let room = b.sub(max, key_len);
+        b.api.range_check(room.0, 7);
+
+        let rest = shift_right(
+            b,
+            &rest,
+            account_len,
+            // The account id is at most 64 bytes
+            7,
+            MAX_TRIE_KEY_LEN - 1,
+        );
+        bytes.resize(MAX_TRIE_KEY_LEN, zero);
+        for (i, byte) in rest.into_iter().enumerate() {
+            bytes[1 + i] = b.add(bytes[1 + i], byte);
+        }
+
+        let nibbles = bytes
+            .into_iter()
+            .flat_map(|byte| {
+                let (hi, lo) = nibbles(b, byte);
+                [hi, lo]
+            })
+            .collect_vec();
+        let two = b.constant::<Variable>(L::Field::from_canonical_usize(2));
+        let fixed = b.add(account_len, key_len);
+        let len = b.add(fixed, two);
+        let len = b.mul(len, two);
+        Self { nibbles, len }
+    }
+}
+
+/// The high and low nibbles of a byte.
+fn nibbles<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    byte: Variable,
+) -> (Variable, Variable) {
+    let bits = b.api.split_le(byte.0, 8);
+    let lo = b.api.le_sum(bits[..4].iter().copied());
+    let hi = b.api.le_sum(bits[4..].iter().copied());
+    (Variable(hi), Variable(lo))
+}
+
+fn hash_at(bytes: &[ByteVariable], at: usize) -> CryptoHashVariable {
+    Bytes32Variable(BytesVariable(
+        bytes[at..at + 32].try_into().expect("32 bytes"),
+    ))
+}
+
+/// Whether `v` fits in `bits` bits, only asserted when `when` is true.
+fn range_check_when<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    when: BoolVariable,
+    v: Variable,
+    bits: usize,
+) {
+    let zero = b.zero::<Variable>();
+    let v = b.select(when, v, zero);
+    b.api.range_check(v.0, bits);
+}
+
+fn assert_when<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    when: BoolVariable,
+    condition: BoolVariable,
+) {
+    let skip = b.not(when);
+    let holds = b.or(skip, condition);
+    let t = b._true();
+    b.assert_is_equal(holds, t);
+}
+
+pub trait VerifyState<L: PlonkParameters<D>, const D: usize> {
+    /// Assert the proof shows the value of `key` under a header's
+    /// `prev_state_root`.
+    fn verify_state(
+        &mut self,
+        prev_state_root: &CryptoHashVariable,
+        key: &TrieKeyVariable,
+        proof: &StateProofVariable,
+    );
+}
+
+impl<L: PlonkParameters<D>, const D: usize> VerifyState<L, D> for CircuitBuilder<L, D> {
+    fn verify_state(
+        &mut self,
+        prev_state_root: &CryptoHashVariable,
+        key: &TrieKeyVariable,
+        proof: &StateProofVariable,
+    ) {
+        let b = self;
+        let t = b._true();
+        let zero = b.zero::<Variable>();
+        let one = b.one::<Variable>();
+        let constant = |b: &mut CircuitBuilder<L, D>, v: usize| {
+            b.constant::<Variable>(L::Field::from_canonical_usize(v))
+        };
+
+        // The shard root is a leaf of `prev_state_root`
+        let leaf = b.curta_sha256(&proof.shard_root.0 .0);
+        let root = b.get_root_from_merkle_path(&proof.shard_proof, leaf);
+        b.assert_is_equal(root, *prev_state_root);
+
+        let mut expected = proof.shard_root;
+        let mut cursor = zero;
+        let mut done = b._false();
+        let mut value_len = zero;
+        let mut value_hash = b.constant::<CryptoHashVariable>([0u8; 32].into());
+        for node in proof.nodes.data.iter() {
+            let active = b.not(done);
+            let bytes = &node.bytes.data;
+            let values = bytes
+                .iter()
+                .map(|byte| byte_to_variable(b, *byte))
+                .collect_vec();
+
+            // The node fits and hashes to what its parent points to
+            b.api.range_check(node.len.0, 10);
+            let max = constant(b, MAX_TRIE_NODE_LEN);
+            let room = b.sub(max, node.len);
+            b.api.range_check(room.0, 10);
+            let mut padded = bytes.clone();
+            // Room for the SHA-256 padding of the longest node
+            let zero_byte = b.constant::<ByteVariable>(0);
+            padded.resize((MAX_TRIE_NODE_LEN + 9).div_ceil(64) * 64, zero_byte);
+            let len = U32Variable::from_variables_unsafe(&[node.len]);
+            let hash = b.curta_sha256_variable(&padded, len);
+            let hashes = b.is_equal(hash, expected);
+            assert_when(b, active, hashes);
+
+            let tag = |b: &mut CircuitBuilder<L, D>, tag: u8| {
+                let tag = b.constant::<Variable>(L::Field::from_canonical_u8(tag));
+                b.is_equal(values[0], tag)
+            };
+            let is_leaf = tag(b, TrieNode::LEAF);
+            let is_branch = tag(b, TrieNode::BRANCH);
+            let has_value = tag(b, TrieNode::BRANCH_WITH_VALUE);
+            let is_extension = tag(b, TrieNode::EXTENSION);
+            let any_branch = b.or(is_branch, has_value);
+            let is_keyed = b.or(is_leaf, is_extension);
+            let known = b.or(any_branch, is_keyed);
+            assert_when(b, active, known);
+            let keyed = b.and(active, is_keyed);
+            let branching = b.and(active, any_branch);
+
+            // The rest of the key, from the cursor
+            let window = shift_left(b, &key.nibbles, cursor, 9, MAX_TRIE_KEY_NIBBLES);
+            let remaining = b.sub(key.len, cursor);
+            let at_end = b.is_zero(remaining);
+
+            // A branch: the child for the next nibble, unless the key ends
+            // here and the value is the branch's
+            let bitmap_at = |b: &mut CircuitBuilder<L, D>, i: usize| {
+                b.select(has_value, values[37 + i], values[1 + i])
+            };
+            let bitmap = [bitmap_at(b, 0), bitmap_at(b, 1)];
+            let bits = bitmap
+                .into_iter()
+                .flat_map(|byte| b.api.split_le(byte.0, 8))
+                .map(|bit| BoolVariable::from_targets(&[bit.target]))
+                .collect_vec();
+            let nibble = window[0];
+            let mut before = t;
+            let mut present = b._false();
+            let mut rank = zero;
+            for (i, bit) in bits.iter().enumerate() {
+                let i = constant(b, i);
+                let is_nibble = b.is_equal(nibble, i);
+                let not_nibble = b.not(is_nibble);
+                before = b.and(before, not_nibble);
+                let counted = b.and(before, *bit);
+                rank = b.add(rank, counted.variable);
+                let chosen = b.and(is_nibble, *bit);
+                present = b.or(present, chosen);
+            }
+            let mut child = hash_at(bytes, 3);
+            for k in 0..16 {
+                let candidate = hash_at(bytes, 3 + 32 * k);
+                let candidate_with_value = hash_at(bytes, 39 + 32 * k);
+                let candidate = b.select(has_value, candidate_with_value, candidate);
+                let k = constant(b, k);
+                let is_rank = b.is_equal(rank, k);
+                child = b.select(is_rank, candidate, child);
+            }
+            let descends = b.not(at_end);
+            let descending = b.and(branching, descends);
+            assert_when(b, descending, present);
+            let ends_at_branch = b.and(branching, at_end);
+            assert_when(b, ends_at_branch, has_value);
+            let branch_value_len = le_sum(b, &values[1..5]);
+            let branch_value_hash = hash_at(bytes, 5);
+
+            // A leaf or an extension: its part of the key must be next
+            // Only read as a length in leaves and extensions, the shift below
+            // needs it to fit regardless
+            let key_len = le_sum(b, &values[1..NODE_KEY_START]);
+            let key_len = b.select(keyed, key_len, zero);
+            b.api.range_check(key_len.0, 8);
+            let flags = b.api.split_le(values[NODE_KEY_START].0, 8);
+            let odd = BoolVariable::from_targets(&[flags[4].target]);
+            let leaf_flag = BoolVariable::from_targets(&[flags[5].target]);
+            let flagged = b.is_equal(leaf_flag, is_leaf);
+            assert_when(b, keyed, flagged);
+
+            // The nibbles of the encoded key, the first is only used if odd
+            let key_bytes = &values[NODE_KEY_START..NODE_KEY_START + 1 + MAX_TRIE_KEY_LEN];
+            let (_, first) = nibbles(b, key_bytes[0]);
+            let mut encoded = vec![first];
+            for byte in &key_bytes[1..] {
+                let (hi, lo) = nibbles(b, *byte);
+                encoded.extend([hi, lo]);
+            }
+            // 2 * (key_len - 1) + odd
+            let two = constant(b, 2);
+            let part_len = b.sub(key_len, one);
+            let part_len = b.mul(part_len, two);
+            let part_len = b.add(part_len, odd.variable);
+            range_check_when(b, keyed, part_len, 9);
+
+            let mut in_part = t;
+            for j in 0..MAX_TRIE_KEY_NIBBLES {
+                let index = constant(b, j);
+                let is_len = b.is_equal(part_len, index);
+                let not_len = b.not(is_len);
+                in_part = b.and(in_part, not_len);
+
+                let part = b.select(odd, encoded[j], encoded[j + 1]);
+                let matches = b.is_equal(part, window[j]);
+                let checked = b.and(keyed, in_part);
+                assert_when(b, checked, matches);
+            }
+            // A leaf ends the key, an extension leaves at least a nibble for
+            // the branch after it
+            let leaf_ends = b.is_equal(part_len, remaining);
+            let at_leaf = b.and(active, is_leaf);
+            assert_when(b, at_leaf, leaf_ends);
+            let past = b.sub(remaining, part_len);
+            let past = b.sub(past, one);
+            let at_extension = b.and(active, is_extension);
+            range_check_when(b, at_extension, past, 9);
+
+            // What follows the key, wherever it ends
+            let after = shift_left(b, &values[NODE_KEY_START..], key_len, 8, 36);
+            let after_bytes = after.iter().map(|v| variable_to_byte(b, *v)).collect_vec();
+            let leaf_value_len = le_sum(b, &after[..4]);
+            let leaf_value_hash = hash_at(&after_bytes, 4);
+            let extension_child = hash_at(&after_bytes, 0);
+
+            // Step down, or stop at the value
+            let found = b.or(is_leaf, ends_at_branch);
+            let found = b.and(active, found);
+            let found_len = b.select(is_leaf, leaf_value_len, branch_value_len);
+            let found_hash = b.select(is_leaf, leaf_value_hash, branch_value_hash);
+            value_len = b.select(found, found_len, value_len);
+            value_hash = b.select(found, found_hash, value_hash);
+            done = b.or(done, found);
+
+            let next = b.select(is_extension, extension_child, child);
+            expected = b.select(active, next, expected);
+            let step = b.select(is_extension, part_len, one);
+            let stepped = b.add(cursor, step);
+            cursor = b.select(active, stepped, cursor);
+        }
+        b.assert_is_equal(done, t);
+
+        // The value is the one the trie points to
+        let value = &proof.value;
+        let max = constant(b, MAX_STATE_VALUE_LEN);
+        let room = b.sub(max, value.len);
+        b.api.range_check(room.0, 9);
+        b.assert_is_equal(value.len, value_len);
+        let mut in_value = t;
+        for (i, byte) in value.bytes.data.iter().enumerate() {
+            let index = constant(b, i);
+            let is_len = b.is_equal(value.len, index);
+            let not_len = b.not(is_len);
+            in_value = b.and(in_value, not_len);
+            let byte = byte_to_variable(b, *byte);
+            let is_zero = b.is_zero(byte);
+            let canonical = b.or(in_value, is_zero);
+            b.assert_is_equal(canonical, t);
+        }
+        let mut padded = value.bytes.data.clone();
+        let zero_byte = b.constant::<ByteVariable>(0);
+        padded.resize((MAX_STATE_VALUE_LEN + 9).div_ceil(64) * 64, zero_byte);
+        let len = U32Variable::from_variables_unsafe(&[value.len]);
+        let hash = b.curta_sha256_variable(&padded, len);
+        b.assert_is_equal(hash, value_hash);
+    }
+}
+
+/// Proves the value of a contract's storage key as of the block before the
+/// trusted header, so consumers on other chains can read contract state.
+///
+/// The inputs are the domain, the trusted header hash, the padded account id,
+/// the storage key's length as a byte and the storage key zeroed to
+/// `MAX_STATE_KEY_LEN`. The outputs are the domain, the trusted header hash,
+/// the value's length as a u32 and the value zeroed to `MAX_STATE_VALUE_LEN`.
+#[derive(Debug, Clone)]
+pub struct StateProofCircuit<const NETWORK: usize>;
+
+impl<const NETWORK: usize> Circuit for StateProofCircuit<NETWORK> {
+    fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher:
+            AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
+    {
+        assert_network_fits(NETWORK.into());
+        let domain = b.evm_read::<DomainVariable>();
+        let trusted_header_hash = b.evm_read::<CryptoHashVariable>();
+        let account_id = b.evm_read::<AccountIdVariable>();
+        let key_len = b.evm_read::<ByteVariable>();
+        let key = b.evm_read::<BytesVariable<MAX_STATE_KEY_LEN>>();
+
+        let head = FetchHeaderInputs(NETWORK.into()).fetch(b, &trusted_header_hash);
+        let proof = FetchStateProofInputs(NETWORK.into()).fetch(
+            b,
+            &trusted_header_hash,
+            &account_id,
+            key_len,
+            &key,
+        );
+
+        let key_len = byte_to_variable(b, key_len);
+        let trie_key = TrieKeyVariable::contract_data(b, &account_id, key_len, &key.0);
+        b.verify_state(&head.inner_lite.prev_state_root, &trie_key, &proof);
+        b.watch(&proof.value.len, "state_value_len");
+
+        b.evm_write::<DomainVariable>(domain);
+        b.evm_write::<CryptoHashVariable>(trusted_header_hash);
+        b.evm_write::<U32Variable>(U32Variable::from_variables_unsafe(&[proof.value.len]));
+        let value: [ByteVariable; MAX_STATE_VALUE_LEN] = proof
+            .value
+            .bytes
+            .data
+            .try_into()
+            .expect("the value is padded");
+        b.evm_write::<BytesVariable<MAX_STATE_VALUE_LEN>>(BytesVariable(value));
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(registry: &mut HintRegistry<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher: AlgebraicHasher<L::Field>,
+    {
+        registry.register_async_hint::<FetchHeaderInputs>();
+        registry.register_async_hint::<FetchStateProofInputs>();
+        registry.register_hint::<EncodeInner>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_light_client_protocol::{
+        merkle_util::MerklePath,
+        state::{contract_data_key, nibbles as native_nibbles, ValueRef},
+    };
+    use near_primitives::merkle::merklize;
+
+    use super::*;
+    use crate::{
+        test_utils::{builder_suite, CryptoHash, B, PI, PO},
+        variables::pad_account_id,
+    };
+
+    #[test]
+    fn test_proof_is_padded_to_the_circuit() {
+        let proof = StateProof {
+            shard_root: CryptoHash::default(),
+            shard_proof: MerklePath::default(),
+            nodes: vec![vec![1; 10], vec![2; 20]],
+            value: vec![3; 4],
+        };
+        let value = StateProofVariableValue::<GoldilocksField>::from(proof);
+        assert_eq!(value.nodes.len(), MAX_STATE_PROOF_NODES);
+        assert_eq!(
+            value.nodes[1].len,
+            GoldilocksField::from_canonical_usize(20)
+        );
+        assert_eq!(value.nodes[1].bytes.len(), MAX_TRIE_NODE_LEN);
+        assert_eq!(value.nodes[2].len, GoldilocksField::ZERO);
+        assert_eq!(value.value.bytes[..5], [3, 3, 3, 3, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the value has 257 bytes, the circuit fits 256")]
+    fn test_value_too_long() {
+        let _ = StateValueVariableValue::<GoldilocksField>::from(vec![0; MAX_STATE_VALUE_LEN + 1]);
+    }
+
+    #[test]
+    fn test_contract_data_key() {
+        let account: AccountId = "counter.testnet".parse().unwrap();
+        let key = b"STATE".to_vec();
+        let expected = native_nibbles(&contract_data_key(&account, &key));
+
+        let define = |b: &mut B| {
+            let account = b.read::<AccountIdVariable>();
+            let key_len = b.read::<Variable>();
+            let key = b.read::<BytesVariable<MAX_STATE_KEY_LEN>>();
+            let trie_key = TrieKeyVariable::contract_data(b, &account, key_len, &key.0);
+            b.write::<Variable>(trie_key.len);
+            for nibble in trie_key.nibbles {
+                b.write::<Variable>(nibble);
+            }
+        };
+        let writer = |input: &mut PI| {
+            input.write::<AccountIdVariable>(pad_account_id(&account));
+            input.write::<Variable>(GoldilocksField::from_canonical_usize(key.len()));
+            let mut padded = key.clone();
+            padded.resize(MAX_STATE_KEY_LEN, 0);
+            input.write::<BytesVariable<MAX_STATE_KEY_LEN>>(padded.try_into().unwrap());
+        };
+        let assertions = |mut output: PO| {
+            let len = output.read::<Variable>().to_canonical_u64() as usize;
+            assert_eq!(len, expected.len());
+            let nibbles = (0..MAX_TRIE_KEY_NIBBLES)
+                .map(|_| output.read::<Variable>().to_canonical_u64() as u8)
+                .collect_vec();
+            assert_eq!(nibbles[..len], expected);
+            assert!(nibbles[len..].iter().all(|n| *n == 0));
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    /// A trie with the value of a contract's key under an extension and a
+    /// branch, in the second of two shards.
+    fn state_proof(value: &[u8]) -> (CryptoHash, Vec<u8>, StateProof) {
+        let account: AccountId = "counter.testnet".parse().unwrap();
+        let trie_key = contract_data_key(&account, b"a");
+        let key = native_nibbles(&trie_key);
+        let split = key.len() - 3;
+
+        let leaf = TrieNode::Leaf {
+            key: key[split + 1..].to_vec(),
+            value: ValueRef {
+                length: value.len() as u32,
+                hash: CryptoHash::hash_bytes(value),
+            },
+        }
+        .encode(100);
+        let mut children = [None; 16];
+        children[key[split] as usize] = Some(CryptoHash::hash_bytes(&leaf));
+        children[(key[split] as usize + 1) % 16] = Some(CryptoHash::hash_bytes(b"sibling"));
+        let branch = TrieNode::Branch {
+            value: None,
+            children,
+        }
+        .encode(200);
+        let extension = TrieNode::Extension {
+            key: key[..split].to_vec(),
+            child: CryptoHash::hash_bytes(&branch),
+        }
+        .encode(300);
+
+        let shard_root = CryptoHash::hash_bytes(&extension);
+        let roots = [CryptoHash::hash_bytes(b"other shard"), shard_root];
+        let (prev_state_root, paths) = merklize(&roots);
+        let proof = StateProof {
+            shard_root,
+            shard_proof: paths[1].clone(),
+            nodes: vec![extension, branch, leaf],
+            value: value.to_vec(),
+        };
+        proof.verify(&prev_state_root, &trie_key).unwrap();
+        (prev_state_root, b"a".to_vec(), proof)
+    }
+
+    #[test]
+    #[ignore]
+    fn beefy_test_verify_state() {
+        let account: AccountId = "counter.testnet".parse().unwrap();
+        let (prev_state_root, key, proof) = state_proof(b"42");
+
+        let define = |b: &mut B| {
+            let prev_state_root = b.read::<CryptoHashVariable>();
+            let account = b.read::<AccountIdVariable>();
+            let key_len = b.read::<Variable>();
+            let key = b.read::<BytesVariable<MAX_STATE_KEY_LEN>>();
+            let proof = b.read::<StateProofVariable>();
+            let trie_key = TrieKeyVariable::contract_data(b, &account, key_len, &key.0);
+            b.verify_state(&prev_state_root, &trie_key, &proof);
+            b.write::<Variable>(proof.value.len);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<CryptoHashVariable>(prev_state_root.0.into());
+            input.write::<AccountIdVariable>(pad_account_id(&account));
+            input.write::<Variable>(GoldilocksField::from_canonical_usize(key.len()));
+            let mut padded = key.clone();
+            padded.resize(MAX_STATE_KEY_LEN, 0);
+            input.write::<BytesVariable<MAX_STATE_KEY_LEN>>(padded.try_into().unwrap());
+            input.write::<StateProofVariable>(proof.clone().into());
+        };
+        let assertions = |mut output: PO| {
+            assert_eq!(
+                output.read::<Variable>(),
+                GoldilocksField::from_canonical_usize(2)
+            );
+        };
+        builder_suite(define, writer, assertions);
+    }
+}
@@ -1,9 +1,14 @@
+use near_light_client_protocol::{config::NUM_BLOCK_PRODUCER_SEATS, signature::KeyPolicy};
 pub use plonky2x::{self, backend::circuit::Circuit, prelude::*};
 
 use crate::{
+    abi::{AbiWrite, SYNC_HEAD_WORDS, WORD},
     builder::Sync,
     hint::{FetchHeaderInputs, FetchNextHeaderInputs},
-    variables::{BuildEndorsement, CryptoHashVariable, EncodeInner, HashBpsInputs},
+    variables::{
+        assert_network_fits, BpsArr, BuildEndorsement, CryptoHashVariable, DomainVariable,
+        EncodeInner, HeaderVariable, SyncedVariable, ValidKeys, ValidatorStakeVariable,
+    },
 };
 
 // TODO: lazy sync
@@ -11,41 +16,46 @@ use crate::{
 // TODO[Style]: macro to share all the same implementation with semantic type
 // differences between protocol crate
 // TODO: determine fees, allows integrators to charge
+/// Syncs the block after a trusted header.
+///
+/// The outputs are the domain, the synced header hash and the synced block's
+/// epoch id and next epoch id, so a contract can apply epoch policies
+/// without the header. With `ABI` they are `abi.encode`d and followed by the
+/// next BPS, see `abi`.
+///
+/// Approvals by a seat with an invalid key are handled by the `KeyPolicy`
+/// numbered `KEY_POLICY`, the sync circuits all take it.
 #[derive(Debug, Clone)]
-pub struct SyncCircuit<const NETWORK: usize>;
+pub struct SyncCircuit<
+    const NETWORK: usize,
+    const ABI: bool = false,
+    const KEY_POLICY: u8 = { KeyPolicy::Inactive as u8 },
+>;
 
-impl<const NETWORK: usize> Circuit for SyncCircuit<NETWORK> {
+impl<const NETWORK: usize, const ABI: bool, const KEY_POLICY: u8> Circuit
+    for SyncCircuit<NETWORK, ABI, KEY_POLICY>
+{
     fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
     where
         <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
             plonky2::plonk::config::AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
     {
-        let network = NETWORK.into();
-        let fetch_header = FetchHeaderInputs(network);
-        let fetch_next_header = FetchNextHeaderInputs(network);
-
+        let domain = b.evm_read::<DomainVariable>();
         let trusted_header_hash = b.evm_read::<CryptoHashVariable>();
 
-        // This is a very interesting trick to be able to get the BPS for the next epoch
-        // without the need to store the BPS, we verify the hash of the BPS in the
-        // circuit
-        let header = fetch_header.fetch(b, &trusted_header_hash);
-        let bps = fetch_next_header
-            .fetch(b, &header.inner_lite.next_epoch_id)
-            .unwrap()
-            .next_bps;
-
-        let bps_hash = HashBpsInputs.hash(b, &bps);
-        b.assert_is_equal(header.inner_lite.next_bp_hash, bps_hash);
-        b.watch(&bps_hash, "calculate_bps_hash");
-
-        let next_block = fetch_next_header
-            .fetch(b, &trusted_header_hash)
-            .expect("Failed to fetch next block");
-
-        let synced = b.sync(&header, &bps, &next_block);
+        let synced = sync_from_trusted::<L, D, NETWORK>(
+            b,
+            &trusted_header_hash,
+            KeyPolicy::from_u8(KEY_POLICY),
+        );
         let synced_hash = synced.new_head.hash(b);
-        b.evm_write::<CryptoHashVariable>(synced_hash);
+        b.evm_write::<DomainVariable>(domain);
+        write_synced(b, &synced_hash, &synced.new_head);
+        if ABI {
+            b.evm_write::<CryptoHashVariable>(synced.next_bps_epoch);
+            b.abi_write_uint((SYNC_HEAD_WORDS * WORD) as u64);
+            b.abi_write_validators(&synced.next_bps);
+        }
     }
 
     fn register_generators<L: PlonkParameters<D>, const D: usize>(registry: &mut HintRegistry<L, D>)
@@ -57,7 +67,165 @@ impl<const NETWORK: usize> Circuit for SyncCircuit<NETWORK> {
         registry.register_async_hint::<FetchNextHeaderInputs>();
         registry.register_hint::<EncodeInner>();
         registry.register_hint::<BuildEndorsement>();
-        registry.register_hint::<HashBpsInputs>();
+        registry.register_hint::<ValidKeys<NUM_BLOCK_PRODUCER_SEATS>>();
+    }
+}
+
+/// Syncs the next block from a trusted header hash.
+fn sync_from_trusted<L: PlonkParameters<D>, const D: usize, const NETWORK: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    trusted_header_hash: &CryptoHashVariable,
+    policy: KeyPolicy,
+) -> SyncedVariable {
+    let (header, bps) = fetch_trusted::<L, D, NETWORK>(b, trusted_header_hash);
+
+    let next_block = FetchNextHeaderInputs(NETWORK.into())
+        .fetch(b, trusted_header_hash)
+        .expect("Failed to fetch next block");
+
+    b.sync(&header, &bps, &next_block, policy)
+}
+
+/// Write the synced header hash and its epochs, the outputs of a sync after
+/// the domain.
+fn write_synced<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    hash: &CryptoHashVariable,
+    header: &HeaderVariable,
+) {
+    b.evm_write::<CryptoHashVariable>(*hash);
+    b.evm_write::<CryptoHashVariable>(header.inner_lite.epoch_id);
+    b.evm_write::<CryptoHashVariable>(header.inner_lite.next_epoch_id);
+}
+
+/// Witnesses the trusted header and the BPS of its next epoch.
+fn fetch_trusted<L: PlonkParameters<D>, const D: usize, const NETWORK: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    trusted_header_hash: &CryptoHashVariable,
+) -> (HeaderVariable, BpsArr<ValidatorStakeVariable>) {
+    let network = NETWORK.into();
+    assert_network_fits(network);
+    let fetch_header = FetchHeaderInputs(network);
+    let fetch_next_header = FetchNextHeaderInputs(network);
+
+    // This is a very interesting trick to be able to get the BPS for the next epoch
+    // without the need to store the BPS, we verify the hash of the BPS in the
+    // circuit
+    let header = fetch_header.fetch(b, trusted_header_hash);
+    let bps = fetch_next_header
+        .fetch(b, &header.inner_lite.next_epoch_id)
+        .unwrap()
+        .next_bps;
+
+    let bps_hash = b.hash_bps(&bps);
+    b.assert_is_equal(header.inner_lite.next_bp_hash, bps_hash);
+    b.watch(&bps_hash, "calculate_bps_hash");
+    (header, bps)
+}
+
+/// A sync circuit whose outputs are directly usable as the inputs of the
+/// following proof, so a chain of proofs can be verified on-chain by storing
+/// only the latest header hash.
+///
+/// The header commits to the BPS of its next epoch with `next_bp_hash`, so
+/// there is no separate BPS commitment to roll forward.
+#[derive(Debug, Clone)]
+pub struct RollingSyncCircuit<
+    const NETWORK: usize,
+    const KEY_POLICY: u8 = { KeyPolicy::Inactive as u8 },
+>;
+
+impl<const NETWORK: usize, const KEY_POLICY: u8> Circuit
+    for RollingSyncCircuit<NETWORK, KEY_POLICY>
+{
+    fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
+    {
+        let domain = b.evm_read::<DomainVariable>();
+        let trusted_header_hash = b.evm_read::<CryptoHashVariable>();
+
+        let synced = sync_from_trusted::<L, D, NETWORK>(
+            b,
+            &trusted_header_hash,
+            KeyPolicy::from_u8(KEY_POLICY),
+        );
+        let synced_hash = synced.new_head.hash(b);
+
+        b.evm_write::<DomainVariable>(domain);
+        b.evm_write::<CryptoHashVariable>(synced_hash);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(registry: &mut HintRegistry<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<L::Field>,
+    {
+        SyncCircuit::<NETWORK>::register_generators(registry);
+    }
+}
+
+/// Syncs `EPOCHS` epochs past the trusted header in one proof, so a head that
+/// has fallen epochs behind can catch up with a single update.
+///
+/// Each step syncs to a block in the epoch after its head's, signed by the
+/// BPS the head committed to, and the BPS of the following epoch are taken
+/// from the synced block once they are checked against its `next_bp_hash`.
+/// The inputs and outputs are the same as `SyncCircuit`'s.
+#[derive(Debug, Clone)]
+pub struct SkipSyncCircuit<
+    const EPOCHS: usize,
+    const NETWORK: usize,
+    const KEY_POLICY: u8 = { KeyPolicy::Inactive as u8 },
+>;
+
+impl<const EPOCHS: usize, const NETWORK: usize, const KEY_POLICY: u8> Circuit
+    for SkipSyncCircuit<EPOCHS, NETWORK, KEY_POLICY>
+{
+    fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
+    {
+        assert!(EPOCHS > 0, "nothing to skip");
+        let domain = b.evm_read::<DomainVariable>();
+        let trusted_header_hash = b.evm_read::<CryptoHashVariable>();
+
+        let fetch_next_header = FetchNextHeaderInputs(NETWORK.into());
+        let (mut head, mut bps) = fetch_trusted::<L, D, NETWORK>(b, &trusted_header_hash);
+        let mut head_hash = trusted_header_hash;
+        for epoch in 0..EPOCHS {
+            let next_block = fetch_next_header
+                .fetch(b, &head_hash)
+                .expect("Failed to fetch next block");
+            // Only the next epoch is signed by the BPS we hold
+            b.assert_is_equal(
+                next_block.header.inner_lite.epoch_id,
+                head.inner_lite.next_epoch_id,
+            );
+
+            let synced = b.sync(&head, &bps, &next_block, KeyPolicy::from_u8(KEY_POLICY));
+            head_hash = synced.new_head.hash(b);
+            head = synced.new_head;
+            if epoch + 1 < EPOCHS {
+                let bps_hash = b.hash_bps(&synced.next_bps);
+                b.assert_is_equal(head.inner_lite.next_bp_hash, bps_hash);
+                bps = synced.next_bps;
+            }
+        }
+        b.watch(&head_hash, "skip_synced");
+
+        b.evm_write::<DomainVariable>(domain);
+        write_synced(b, &head_hash, &head);
+    }
+
+    fn register_generators<L: PlonkParameters<D>, const D: usize>(registry: &mut HintRegistry<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as plonky2::plonk::config::GenericConfig<D>>::Hasher:
+            plonky2::plonk::config::AlgebraicHasher<L::Field>,
+    {
+        SyncCircuit::<NETWORK>::register_generators(registry);
     }
 }
 
@@ -66,7 +234,10 @@ mod beefy_tests {
     use serial_test::serial;
 
     use super::*;
-    use crate::test_utils::{builder_suite, testnet_state, B, NETWORK, PI, PO};
+    use crate::{
+        test_utils::{builder_suite, testnet_state, B, DOMAIN, NETWORK, PI, PO},
+        variables::domain_from_chain_id,
+    };
 
     #[test]
     #[serial]
@@ -79,12 +250,67 @@ mod beefy_tests {
             SyncCircuit::<NETWORK>::define(b);
         };
         let writer = |input: &mut PI| {
+            input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
+            input.evm_write::<CryptoHashVariable>(header.into());
+        };
+        let assertions = |mut output: PO| {
+            let domain = output.evm_read::<DomainVariable>();
+            assert_eq!(domain, domain_from_chain_id(DOMAIN).into());
+            let hash = output.evm_read::<CryptoHashVariable>();
+            let epoch_id = output.evm_read::<CryptoHashVariable>();
+            let next_epoch_id = output.evm_read::<CryptoHashVariable>();
+            println!(
+                "hash: {:?}, epoch: {:?}, next epoch: {:?}",
+                hash, epoch_id, next_epoch_id
+            );
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_rolling_sync_e2e() {
+        let (header, _, _) = testnet_state();
+        let header = header.hash().0;
+
+        let define = |b: &mut B| {
+            RollingSyncCircuit::<NETWORK>::define(b);
+        };
+        let writer = |input: &mut PI| {
+            input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
             input.evm_write::<CryptoHashVariable>(header.into());
         };
         let assertions = |mut output: PO| {
+            let domain = output.evm_read::<DomainVariable>();
+            assert_eq!(domain, domain_from_chain_id(DOMAIN).into());
             let hash = output.evm_read::<CryptoHashVariable>();
             println!("hash: {:?}", hash);
         };
         builder_suite(define, writer, assertions);
     }
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_skip_sync_e2e() {
+        let (header, _, _) = testnet_state();
+        let header = header.hash().0;
+
+        let define = |b: &mut B| {
+            SkipSyncCircuit::<2, NETWORK>::define(b);
+        };
+        let writer = |input: &mut PI| {
+            input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
+            input.evm_write::<CryptoHashVariable>(header.into());
+        };
+        let assertions = |mut output: PO| {
+            let domain = output.evm_read::<DomainVariable>();
+            assert_eq!(domain, domain_from_chain_id(DOMAIN).into());
+            let hash = output.evm_read::<CryptoHashVariable>();
+            let epoch_id = output.evm_read::<CryptoHashVariable>();
+            println!("skipped to: {:?} in {:?}", hash, epoch_id);
+        };
+        builder_suite(define, writer, assertions);
+    }
 }
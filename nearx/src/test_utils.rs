@@ -11,6 +11,8 @@ pub use test_utils::*;
 
 // Testnet Repr
 pub const NETWORK: usize = 1;
+// Goerli
+pub const DOMAIN: u64 = 5;
 
 pub type B<const D: usize = 2> = CircuitBuilder<DefaultParameters, D>;
 pub type PI<const D: usize = 2> = PublicInput<DefaultParameters, D>;
@@ -0,0 +1,316 @@
+//! Tracing a circuit's witness, to catch a dependency bump that changes what a
+//! circuit computes before it is rolled out.
+//!
+//! Two builds can't share a process, so each records a trace of the same
+//! request in mock mode and the traces are diffed afterwards, see the
+//! `trace-witness` bin. A trace holds the public outputs and every watched
+//! value, by the name it was watched with. The layout of the witness itself
+//! changes with the gates, so only named values are compared.
+//!
+//! Watched values are taken from the watch generator's debug log, so
+//! `capture_watches` has to be installed as the logger first.
+use std::{collections::BTreeMap, fmt, sync::Mutex};
+
+use near_light_client_protocol::prelude::Itertools;
+use nearx_error::{bail, err, Result};
+use plonky2x::{
+    backend::{
+        circuit::{PublicInput, PublicOutput},
+        function::ProofRequest,
+    },
+    prelude::{plonky2::field::types::PrimeField64, CircuitBuilder, DefaultParameters},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    repro::{
+        ABI, KEY_POLICY, NETWORK, SKIP_SYNC_EPOCHS, VERIFY_PROOF_AMT, VERIFY_PROOF_BATCH_SIZE,
+    },
+    Circuit, RollingSyncCircuit, SkipSyncCircuit, StateProofCircuit, SyncCircuit, VerifyCircuit,
+};
+
+type L = DefaultParameters;
+const D: usize = 2;
+
+const WATCH_PREFIX: &str = "[Watch] ";
+
+static WATCHED: Mutex<Vec<(String, String)>> = Mutex::new(vec![]);
+
+struct Capture;
+
+impl log::Log for Capture {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let message = record.args().to_string();
+        if let Some(watch) = parse_watch(&message) {
+            WATCHED.lock().unwrap().push(watch);
+        } else if record.level() <= log::Level::Info {
+            eprintln!("{} {}", record.level(), message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger that collects watched values, anything else at info or
+/// above goes to stderr.
+pub fn capture_watches() -> Result<()> {
+    log::set_logger(&Capture).map_err(|e| err!(Prove, "Failed to capture watches: {}", e))?;
+    log::set_max_level(log::LevelFilter::Debug);
+    Ok(())
+}
+
+fn parse_watch(message: &str) -> Option<(String, String)> {
+    let (name, value) = message.strip_prefix(WATCH_PREFIX)?.split_once(": ")?;
+    Some((name.to_string(), value.to_string()))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    pub circuit: String,
+    /// Byte outputs are split into words, element outputs are one each.
+    pub outputs: Vec<String>,
+    /// In the order they were generated.
+    pub watched: Vec<(String, String)>,
+}
+
+/// Record a trace of a deployed circuit, by its name in the manifest, from a
+/// request as the prover is sent.
+pub fn record_named(circuit: &str, request: ProofRequest<L, D>) -> Result<Trace> {
+    let input = match request {
+        ProofRequest::Bytes(request) => PublicInput::Bytes(request.data.input),
+        ProofRequest::Elements(request) => PublicInput::Elements(request.data.input),
+        _ => bail!(Prove, "Only byte and element requests can be traced"),
+    };
+    record_input(circuit, &input)
+}
+
+/// Record a trace of a deployed circuit, by its name in the manifest.
+pub fn record_input(circuit: &str, input: &PublicInput<L, D>) -> Result<Trace> {
+    Ok(match circuit {
+        "sync" => record::<SyncCircuit<NETWORK, ABI, KEY_POLICY>>(circuit, input),
+        "rolling-sync" => record::<RollingSyncCircuit<NETWORK, KEY_POLICY>>(circuit, input),
+        "skip-sync" => {
+            record::<SkipSyncCircuit<SKIP_SYNC_EPOCHS, NETWORK, KEY_POLICY>>(circuit, input)
+        }
+        "state-proof" => record::<StateProofCircuit<NETWORK>>(circuit, input),
+        "verify" => {
+            record::<VerifyCircuit<VERIFY_PROOF_AMT, VERIFY_PROOF_BATCH_SIZE, NETWORK, ABI>>(
+                circuit, input,
+            )
+        }
+        _ => bail!(Prove, "Unknown circuit {}", circuit),
+    })
+}
+
+/// Mock prove the circuit, recording the outputs and what was watched.
+pub fn record<C: Circuit>(circuit: &str, input: &PublicInput<L, D>) -> Trace {
+    let mut b = CircuitBuilder::<L, D>::new();
+    C::define(&mut b);
+    let mock = b.mock_build();
+
+    WATCHED.lock().unwrap().clear();
+    let (_, output) = mock.mock_prove(input);
+    let watched = std::mem::take(&mut *WATCHED.lock().unwrap());
+    Trace {
+        circuit: circuit.to_string(),
+        outputs: outputs(&output),
+        watched,
+    }
+}
+
+/// Byte outputs split into words, element outputs one each.
+pub fn outputs(output: &PublicOutput<L, D>) -> Vec<String> {
+    match output {
+        PublicOutput::Bytes(bytes) => bytes.chunks(32).map(hex::encode).collect(),
+        PublicOutput::Elements(elements) => elements
+            .iter()
+            .map(|e| e.to_canonical_u64().to_string())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Circuit {
+        before: String,
+        after: String,
+    },
+    Output {
+        index: usize,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// The `occurrence`th time `name` was watched.
+    Watched {
+        name: String,
+        occurrence: usize,
+        before: Option<String>,
+        after: Option<String>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_missing = |v: &Option<String>| v.clone().unwrap_or_else(|| "<missing>".into());
+        match self {
+            Self::Circuit { before, after } => write!(f, "circuit: {} -> {}", before, after),
+            Self::Output {
+                index,
+                before,
+                after,
+            } => write!(
+                f,
+                "output {}: {} -> {}",
+                index,
+                or_missing(before),
+                or_missing(after)
+            ),
+            Self::Watched {
+                name,
+                occurrence,
+                before,
+                after,
+            } => write!(
+                f,
+                "{}#{}: {} -> {}",
+                name,
+                occurrence,
+                or_missing(before),
+                or_missing(after)
+            ),
+        }
+    }
+}
+
+/// The values that changed at each index, those only on one side included.
+fn changed<'a>(
+    before: &'a [String],
+    after: &'a [String],
+) -> impl Iterator<Item = (usize, Option<String>, Option<String>)> + 'a {
+    (0..before.len().max(after.len()))
+        .map(|i| (i, before.get(i), after.get(i)))
+        .filter(|(_, before, after)| before != after)
+        .map(|(i, before, after)| (i, before.cloned(), after.cloned()))
+}
+
+/// Everything that differs between the traces, empty if they agree.
+pub fn diff(before: &Trace, after: &Trace) -> Vec<Difference> {
+    let mut differences = vec![];
+    if before.circuit != after.circuit {
+        differences.push(Difference::Circuit {
+            before: before.circuit.clone(),
+            after: after.circuit.clone(),
+        });
+    }
+
+    for (index, before, after) in changed(&before.outputs, &after.outputs) {
+        differences.push(Difference::Output {
+            index,
+            before,
+            after,
+        });
+    }
+
+    let by_name = |trace: &Trace| {
+        let mut by_name = BTreeMap::<String, Vec<String>>::new();
+        for (name, value) in &trace.watched {
+            by_name.entry(name.clone()).or_default().push(value.clone());
+        }
+        by_name
+    };
+    let (before, after) = (by_name(before), by_name(after));
+    let none = vec![];
+    for name in before.keys().chain(after.keys()).sorted().dedup() {
+        let values = before.get(name).unwrap_or(&none);
+        let other = after.get(name).unwrap_or(&none);
+        for (occurrence, before, after) in changed(values, other) {
+            differences.push(Difference::Watched {
+                name: name.clone(),
+                occurrence,
+                before,
+                after,
+            });
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(outputs: &[&str], watched: &[(&str, &str)]) -> Trace {
+        Trace {
+            circuit: "sync".to_string(),
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+            watched: watched
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        assert_eq!(
+            parse_watch("[Watch] calculate_bps_hash: 0x12: 34"),
+            Some(("calculate_bps_hash".into(), "0x12: 34".into()))
+        );
+        assert_eq!(parse_watch("Proving 1"), None);
+        assert_eq!(parse_watch("[Watch] no value"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = trace(
+            &["aa", "bb"],
+            &[("hash", "1"), ("output", "x"), ("hash", "2")],
+        );
+        assert_eq!(diff(&before, &before), vec![]);
+
+        let after = trace(
+            &["aa", "cc", "dd"],
+            &[("hash", "1"), ("hash", "3"), ("new", "y")],
+        );
+        let differences = diff(&before, &after);
+        assert_eq!(
+            differences,
+            vec![
+                Difference::Output {
+                    index: 1,
+                    before: Some("bb".into()),
+                    after: Some("cc".into())
+                },
+                Difference::Output {
+                    index: 2,
+                    before: None,
+                    after: Some("dd".into())
+                },
+                Difference::Watched {
+                    name: "hash".into(),
+                    occurrence: 1,
+                    before: Some("2".into()),
+                    after: Some("3".into())
+                },
+                Difference::Watched {
+                    name: "new".into(),
+                    occurrence: 0,
+                    before: None,
+                    after: Some("y".into())
+                },
+                Difference::Watched {
+                    name: "output".into(),
+                    occurrence: 0,
+                    before: Some("x".into()),
+                    after: None
+                },
+            ]
+        );
+        assert_eq!(differences[4].to_string(), "output#0: x -> <missing>");
+    }
+}
@@ -1,11 +1,21 @@
 use ethers::types::U256;
 use near_light_client_protocol::{
-    config::{ACCOUNT_DATA_SEPARATOR, NUM_BLOCK_PRODUCER_SEATS},
-    prelude::{AccountId, CryptoHash, Header, Itertools},
+    approval, balance,
+    config::{NetworkParams, ACCOUNT_DATA_SEPARATOR, MAX_OUTCOME_LOGS, NUM_BLOCK_PRODUCER_SEATS},
+    endorsement::{self, ChunkEndorsement},
+    experimental::{ExpandedProof, LiteHeader},
+    outcomes::{partial_outcome, OutcomeStatus},
+    prelude::{AccountId, CryptoHash, ExperimentalProof, Header, Itertools},
+    signature::{Ed25519, Scheme, VerifiableSignature},
+    timestamp::Timestamp,
     BlockHeaderInnerLiteView, ED25519PublicKey, LightClientBlockView, Proof, PublicKey, Signature,
     StakeInfo, Synced, ValidatorStake, ValidatorStakeView, ValidatorStakeViewV1,
 };
-use near_light_client_rpc::prelude::GetProof;
+use near_light_client_rpc::{
+    prelude::GetProof,
+    request::{GetProofRequest, InvalidRequest},
+    Network,
+};
 use plonky2x::{
     frontend::{
         curta::ec::point::{CompressedEdwardsY, CompressedEdwardsYVariable},
@@ -13,7 +23,10 @@ use plonky2x::{
         hint::simple::hint::Hint,
         vars::EvmVariable,
     },
-    prelude::*,
+    prelude::{
+        plonky2::{field::types::Field, iop::target::BoolTarget},
+        *,
+    },
 };
 use pretty_assertions::assert_eq;
 use serde::{Deserialize, Serialize};
@@ -33,6 +46,45 @@ pub type BalanceVariable = U128Variable;
 pub type AccountIdVariable = BytesVariable<{ AccountId::MAX_LEN }>;
 pub type AccountIdVariableValue<F> = <AccountIdVariable as CircuitVariable>::ValueType<F>;
 
+pub const OUTCOME_PROOF_DEPTH: usize = NetworkParams::CIRCUIT.outcome_proof_depth;
+pub const OUTCOME_ROOT_PROOF_DEPTH: usize = NetworkParams::CIRCUIT.outcome_root_proof_depth;
+pub const BLOCK_PROOF_DEPTH: usize = NetworkParams::CIRCUIT.block_proof_depth;
+
+/// Panics when building a circuit for a network that doesn't fit in it.
+pub(crate) fn assert_network_fits(network: Network) {
+    let params = network.params();
+    assert!(
+        params.fits(&NetworkParams::CIRCUIT),
+        "{} needs {:?}, but the circuits are built with {:?}",
+        network,
+        params,
+        NetworkParams::CIRCUIT
+    );
+}
+
+/// Separates proofs by their destination so a proof generated for one verifier
+/// cannot be replayed on another. Usually the chain id of the destination,
+/// padded the same as `abi.encode(uint256)`, but any app-defined tag works.
+pub type DomainVariable = Bytes32Variable;
+
+/// A `BalanceVariable` value as the uint256 the EVM reads it as.
+pub fn balance_to_u256(yocto: u128) -> U256 {
+    U256::from_big_endian(&balance::to_evm_word(yocto))
+}
+
+/// The yoctoNEAR in a uint256, `None` if it can't be a balance.
+pub fn balance_from_u256(value: U256) -> Option<u128> {
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    balance::from_evm_word(&word).ok()
+}
+
+pub fn domain_from_chain_id(chain_id: u64) -> [u8; 32] {
+    let mut domain = [0u8; 32];
+    domain[24..].copy_from_slice(&chain_id.to_be_bytes());
+    domain
+}
+
 #[derive(CircuitVariable, Clone, Debug)]
 pub struct HeaderVariable {
     pub prev_block_hash: CryptoHashVariable,
@@ -142,18 +194,21 @@ impl<F: RichField> From<BlockHeaderInnerLiteView> for HeaderInnerVariableValue<F
             next_epoch_id: header.next_epoch_id.0.into(),
             prev_state_root: header.prev_state_root.0.into(),
             outcome_root: header.outcome_root.0.into(),
-            // Maybe don't need this anymore
-            timestamp: if header.timestamp > 0 {
-                header.timestamp
-            } else {
-                header.timestamp_nanosec
-            },
+            timestamp: Timestamp::from(&header).as_nanos(),
             next_bp_hash: header.next_bp_hash.0.into(),
             block_merkle_root: header.block_merkle_root.0.into(),
         }
     }
 }
+/// The borsh encoding of `BlockHeaderInnerLite`, the same on every network.
 pub const INNER_ENCODED_LEN: usize = 208;
+/// The `BlockHeaderInnerLite` layout we encode, locked by the golden
+/// `fixtures/inner_lite_v{N}.bin` and `fixtures/header_v{N}.bin`.
+///
+/// If NEAR changes the structure, add the new layout as the next version
+/// with its own fixtures and keep the old ones, headers from before the
+/// change must still hash the same.
+pub const INNER_LAYOUT_VERSION: u8 = 1;
 impl HeaderInnerVariable {
     pub(crate) fn encode_borsh<L: PlonkParameters<D>, const D: usize>(
         &self,
@@ -361,6 +416,9 @@ impl<F: RichField, const AMT: usize> From<Vec<Option<Box<Signature>>>>
             .into_iter()
             .take(AMT)
             .map(|s| {
+                // An approval in a scheme without a gadget is absent, as it is
+                // natively, rather than an active seat the circuit can't verify
+                let s = s.filter(|s| Scheme::from(s.key_type()).is_supported());
                 let is_active = s.is_some();
                 let s: SignatureVariableValue<F> = s.into();
 
@@ -375,6 +433,38 @@ impl<F: RichField, const AMT: usize> From<Vec<Option<Box<Signature>>>>
     }
 }
 
+/// The validators assigned to a chunk and their endorsements of it, in the
+/// order they were assigned, see `near_light_client_protocol::endorsement`.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct ChunkEndorsementsVariable<const AMT: usize> {
+    pub chunk_hash: CryptoHashVariable,
+    pub assignments: BpsArr<ValidatorStakeVariable, AMT>,
+    pub endorsements: BpsApprovals<AMT>,
+}
+
+impl<F: RichField, const AMT: usize> ChunkEndorsementsVariableValue<AMT, F> {
+    /// Assignments past `AMT` are left out, natively more than
+    /// `NUM_CHUNK_VALIDATOR_SEATS` of them is an error.
+    pub fn new(
+        chunk_hash: CryptoHash,
+        assignments: Vec<ValidatorStake>,
+        endorsements: &[ChunkEndorsement],
+    ) -> Self {
+        let signatures = endorsement::by_seat(&chunk_hash, &assignments, endorsements);
+        let mut assignments = assignments
+            .into_iter()
+            .take(AMT)
+            .map(Into::<ValidatorStakeVariableValue<F>>::into)
+            .collect_vec();
+        assignments.resize(AMT, Default::default());
+        Self {
+            chunk_hash: chunk_hash.0.into(),
+            assignments,
+            endorsements: signatures.into(),
+        }
+    }
+}
+
 pub(crate) fn bps_to_variable<F: RichField, T: Into<ValidatorStake>>(
     next_bps: Option<Vec<T>>,
 ) -> Vec<ValidatorStakeVariableValue<F>> {
@@ -399,10 +489,14 @@ pub struct ValidatorStakeVariable {
     pub stake: BalanceVariable,
 }
 
+/// Never valid in an account id, so the first padding byte ends it. Implicit
+/// accounts are exactly `AccountId::MAX_LEN` long and have no padding at all.
 const ACCOUNT_ID_PADDING_BYTE: u8 = ACCOUNT_DATA_SEPARATOR;
 impl<F: RichField> From<ValidatorStake> for ValidatorStakeVariableValue<F> {
     fn from(vs: ValidatorStake) -> Self {
-        let public_key = CompressedEdwardsY(vs.public_key().unwrap_as_ed25519().0);
+        let public_key = Ed25519::public_key(vs.public_key())
+            .map(CompressedEdwardsY)
+            .expect("block producers have ed25519 keys");
         let stake = vs.stake().into();
         let account_id = pad_account_id(&vs.take_account_id());
         Self {
@@ -419,19 +513,38 @@ pub(crate) fn pad_account_id(account_id: &AccountId) -> [u8; AccountId::MAX_LEN]
 }
 
 pub(crate) fn pad_account_bytes(mut account_id: Vec<u8>) -> [u8; AccountId::MAX_LEN] {
+    assert!(
+        account_id.len() <= AccountId::MAX_LEN,
+        "account id is longer than {} bytes",
+        AccountId::MAX_LEN
+    );
     account_id.resize(AccountId::MAX_LEN, ACCOUNT_ID_PADDING_BYTE);
     account_id.try_into().expect("invalid account bytes")
 }
 
+/// The account id of padded bytes. Only canonical padding is accepted, so the
+/// padded bytes of two accounts are equal in-circuit if and only if the
+/// accounts are.
 pub(crate) fn normalise_account_id<F: RichField>(
     account_id: &AccountIdVariableValue<F>,
 ) -> AccountId {
-    let unpadded_bytes = account_id
-        .split(|x| *x == ACCOUNT_ID_PADDING_BYTE)
-        .collect_vec()[0];
-    let account_str = String::from_utf8(unpadded_bytes.to_vec()).expect("invalid account bytes");
+    let len = account_id
+        .iter()
+        .position(|x| *x == ACCOUNT_ID_PADDING_BYTE)
+        .unwrap_or(AccountId::MAX_LEN);
+    assert!(
+        account_id[len..]
+            .iter()
+            .all(|x| *x == ACCOUNT_ID_PADDING_BYTE),
+        "account id has bytes after its padding"
+    );
+    let account_str = std::str::from_utf8(&account_id[..len]).expect("invalid account bytes");
     log::trace!("account id: {}", account_str);
-    account_str.parse().expect("invalid account id")
+    // Eth-implicit accounts are lowercase, a checksummed address is rejected
+    // rather than lowercased here, or it would differ from the padded bytes
+    account_str
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid account id {}: {}", account_str, e))
 }
 
 impl<F: RichField> From<ValidatorStakeVariableValue<F>> for ValidatorStakeView {
@@ -459,6 +572,194 @@ impl<F: RichField> Default for ValidatorStakeVariableValue<F> {
     }
 }
 
+/// The most bytes the borsh encoding of a `ValidatorStakeView` takes: its
+/// version, the length prefixed account id, then the key type, key and stake.
+pub const VALIDATOR_STAKE_ENCODED_LEN: usize = 1 + 4 + AccountId::MAX_LEN + 1 + 32 + 16;
+/// The bytes of the encoding other than the account id, every validator has
+/// them.
+pub const VALIDATOR_STAKE_FIXED_LEN: usize = VALIDATOR_STAKE_ENCODED_LEN - AccountId::MAX_LEN;
+
+impl ValidatorStakeVariable {
+    /// Whether the seat is filled, padding has a zeroed account id which no
+    /// account starts with, see `bps_to_variable`.
+    pub(crate) fn is_active<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> BoolVariable {
+        let zero = b.constant::<ByteVariable>(0);
+        let is_padding = b.is_equal(self.account_id.0[0], zero);
+        b.not(is_padding)
+    }
+
+    /// The length of the account id and whether each byte is part of it,
+    /// see `account_id_len`.
+    pub(crate) fn account_id_len<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> (Variable, Vec<BoolVariable>) {
+        account_id_len(b, &self.account_id)
+    }
+
+    /// The borsh encoding of the `ValidatorStakeView`, a byte per element and
+    /// zeroed after the encoding, with its length.
+    pub(crate) fn encode_borsh<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> (Vec<Variable>, Variable) {
+        let (account_len, in_account) = self.account_id_len(b);
+        let zero = b.zero::<Variable>();
+
+        // The V1 tag, then the account id prefixed with its u32 length, which
+        // fits in the first byte
+        let mut encoded = vec![zero, account_len, zero, zero, zero];
+        let account_start = encoded.len();
+        for (byte, in_account) in self.account_id.0.into_iter().zip(in_account) {
+            let byte = byte_to_variable(b, byte);
+            let byte = b.select(in_account, byte, zero);
+            encoded.push(byte);
+        }
+        encoded.resize(VALIDATOR_STAKE_ENCODED_LEN, zero);
+
+        // The ED25519 key type, the key and the little endian stake follow the
+        // account id, wherever it ends
+        let mut stake = self.stake.encode(b);
+        stake.reverse();
+        let mut rest = vec![zero];
+        for byte in self.public_key.0.as_bytes().into_iter().chain(stake) {
+            rest.push(byte_to_variable(b, byte));
+        }
+        let rest = shift_right(
+            b,
+            &rest,
+            account_len,
+            // The account id is at most 64 bytes
+            7,
+            VALIDATOR_STAKE_ENCODED_LEN - account_start,
+        );
+        for (i, byte) in rest.into_iter().enumerate() {
+            encoded[account_start + i] = b.add(encoded[account_start + i], byte);
+        }
+
+        let fixed_len =
+            b.constant::<Variable>(L::Field::from_canonical_usize(VALIDATOR_STAKE_FIXED_LEN));
+        let len = b.add(account_len, fixed_len);
+        (encoded, len)
+    }
+}
+
+/// The length of a padded account id and whether each byte is part of it,
+/// asserting the padding is canonical, see `pad_account_id`.
+pub(crate) fn account_id_len<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    account_id: &AccountIdVariable,
+) -> (Variable, Vec<BoolVariable>) {
+    let padding = b.constant::<ByteVariable>(ACCOUNT_ID_PADDING_BYTE);
+    let t = b._true();
+    let mut len = b.zero::<Variable>();
+    let mut in_account = t;
+    let mut in_accounts = vec![];
+    for byte in account_id.0 {
+        let is_padding = b.is_equal(byte, padding);
+        let not_padding = b.not(is_padding);
+        in_account = b.and(in_account, not_padding);
+        // Once the account id ends, only padding may follow
+        let canonical = b.or(in_account, is_padding);
+        b.assert_is_equal(canonical, t);
+
+        len = b.add(len, in_account.variable);
+        in_accounts.push(in_account);
+    }
+    (len, in_accounts)
+}
+
+/// A byte as a field element.
+pub(crate) fn byte_to_variable<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    byte: ByteVariable,
+) -> Variable {
+    let bits = byte
+        .targets()
+        .into_iter()
+        .rev()
+        .map(BoolTarget::new_unsafe)
+        .collect_vec();
+    Variable(b.api.le_sum(bits.into_iter()))
+}
+
+/// A field element as a byte, asserting it is one.
+pub(crate) fn variable_to_byte<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    variable: Variable,
+) -> ByteVariable {
+    let mut bits = b.api.split_le(variable.0, 8);
+    bits.reverse();
+    let targets = bits.iter().map(|b| b.target).collect_vec();
+    ByteVariable::from_targets(&targets)
+}
+
+/// Shift `values` right by `shift` places, filling with zeros and truncating
+/// to `len`. The shift is asserted to fit in `bits` bits, a barrel shifter
+/// costs a select per place for each of them.
+pub(crate) fn shift_right<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    values: &[Variable],
+    shift: Variable,
+    bits: usize,
+    len: usize,
+) -> Vec<Variable> {
+    let zero = b.zero::<Variable>();
+    let mut shifted = values.to_vec();
+    shifted.resize(len, zero);
+    for (i, bit) in b.api.split_le(shift.0, bits).into_iter().enumerate() {
+        let bit = BoolVariable::from_targets(&[bit.target]);
+        let by = 1 << i;
+        let mut next = Vec::with_capacity(len);
+        for p in 0..len {
+            let from = if p < by { zero } else { shifted[p - by] };
+            next.push(b.select(bit, from, shifted[p]));
+        }
+        shifted = next;
+    }
+    shifted
+}
+
+/// Shift `values` left by `shift` places, the inverse of `shift_right`, to
+/// read `len` values from a variable offset.
+pub(crate) fn shift_left<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    values: &[Variable],
+    shift: Variable,
+    bits: usize,
+    len: usize,
+) -> Vec<Variable> {
+    let zero = b.zero::<Variable>();
+    let mut shifted = values.to_vec();
+    for (i, bit) in b.api.split_le(shift.0, bits).into_iter().enumerate() {
+        let bit = BoolVariable::from_targets(&[bit.target]);
+        let by = 1 << i;
+        let mut next = Vec::with_capacity(shifted.len());
+        for p in 0..shifted.len() {
+            let from = shifted.get(p + by).copied().unwrap_or(zero);
+            next.push(b.select(bit, from, shifted[p]));
+        }
+        shifted = next;
+    }
+    shifted.resize(len, zero);
+    shifted
+}
+
+/// A little endian integer from its bytes.
+pub(crate) fn le_sum<L: PlonkParameters<D>, const D: usize>(
+    b: &mut CircuitBuilder<L, D>,
+    bytes: &[Variable],
+) -> Variable {
+    let radix = b.constant::<Variable>(L::Field::from_canonical_usize(256));
+    bytes.iter().rev().fold(b.zero::<Variable>(), |acc, byte| {
+        let acc = b.mul(acc, radix);
+        b.add(acc, *byte)
+    })
+}
+
 pub type PublicKeyVariable = CompressedEdwardsYVariable;
 
 #[derive(CircuitVariable, Clone, Debug)]
@@ -468,20 +769,17 @@ pub struct SignatureVariable {
 
 impl<F: RichField> From<Option<Box<Signature>>> for SignatureVariableValue<F> {
     fn from(sig: Option<Box<Signature>>) -> Self {
-        sig.and_then(|s| match *s {
-            Signature::ED25519(s) => Some(Self {
+        // Signatures in unsupported schemes are dropped, they aren't counted
+        // natively either, see `signature::SUPPORTED_SCHEMES`. Staking keys
+        // must be ed25519, so none of them could verify against a seat anyway
+        sig.and_then(|s| Ed25519::signature(&s))
+            .map(|(r, s)| Self {
                 signature: EDDSASignatureVariableValue {
-                    r: CompressedEdwardsY(*s.r_bytes()),
-                    s: U256::from_little_endian(s.s_bytes()),
+                    r: CompressedEdwardsY(r),
+                    s: U256::from_little_endian(&s),
                 },
-            }),
-            // Silently ignores invalid signatures (ECDSA)
-            // The reasoning being that ECDSA is being phased out and almost all validators
-            // use EDDSA.
-            // If we still need this, we should implement ECDSA.
-            _ => None,
-        })
-        .unwrap_or_default()
+            })
+            .unwrap_or_default()
     }
 }
 
@@ -496,16 +794,250 @@ impl<F: RichField> Default for SignatureVariableValue<F> {
     }
 }
 
+/// The hashes of an outcome: its id, the rest of the outcome, then each log,
+/// see `ExecutionOutcomeWithIdView::to_hashes`.
+pub const MAX_OUTCOME_HASHES: usize = 2 + MAX_OUTCOME_LOGS;
+
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct OutcomeHashesVariable {
+    pub len: Variable,
+    pub hashes: ArrayVariable<CryptoHashVariable, MAX_OUTCOME_HASHES>,
+}
+
+impl OutcomeHashesVariable {
+    /// The outcome hash, the hash of the borsh encoded hashes.
+    pub(crate) fn hash<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> CryptoHashVariable {
+        let mut fits = b._false();
+        for len in 0..=MAX_OUTCOME_HASHES {
+            let len = b.constant::<Variable>(L::Field::from_canonical_usize(len));
+            let is_len = b.is_equal(self.len, len);
+            fits = b.or(fits, is_len);
+        }
+        let t = b._true();
+        b.assert_is_equal(fits, t);
+
+        // The u32 count, which fits in the first byte, then the hashes
+        let zero = b.constant::<ByteVariable>(0);
+        let mut bytes = vec![variable_to_byte(b, self.len), zero, zero, zero];
+        for hash in self.hashes.data.iter() {
+            bytes.extend(hash.as_bytes());
+        }
+        // Room for the SHA-256 padding when every hash is used
+        bytes.resize((bytes.len() + 9).div_ceil(64) * 64, zero);
+
+        let hash_len = b.constant::<Variable>(L::Field::from_canonical_usize(32));
+        let header_len = b.constant::<Variable>(L::Field::from_canonical_usize(4));
+        let len = b.mul(self.len, hash_len);
+        let len = b.add(len, header_len);
+        let len = U32Variable::from_variables_unsafe(&[len]);
+        b.curta_sha256_variable(&bytes, len)
+    }
+}
+
+impl<F: RichField> From<Vec<CryptoHash>> for OutcomeHashesVariableValue<F> {
+    fn from(mut hashes: Vec<CryptoHash>) -> Self {
+        assert!(
+            hashes.len() <= MAX_OUTCOME_HASHES,
+            "the outcome has {} hashes, the circuit fits {}",
+            hashes.len(),
+            MAX_OUTCOME_HASHES
+        );
+        let len = F::from_canonical_usize(hashes.len());
+        hashes.resize(MAX_OUTCOME_HASHES, CryptoHash::default());
+        Self {
+            len,
+            hashes: hashes.into_iter().map(|h| h.0.into()).collect(),
+        }
+    }
+}
+
+/// The most receipts an outcome can spawn to have its status read.
+pub const MAX_OUTCOME_RECEIPTS: usize = 16;
+/// The longest value an outcome can return to have its status read.
+pub const MAX_OUTCOME_VALUE_LEN: usize = 256;
+/// The receipt ids, the gas and tokens burnt, the executor, then the status
+/// with the longest value.
+pub const MAX_PARTIAL_OUTCOME_LEN: usize =
+    4 + 32 * MAX_OUTCOME_RECEIPTS + 8 + 16 + 4 + AccountId::MAX_LEN + 1 + 4 + MAX_OUTCOME_VALUE_LEN;
+
+/// The borsh encoded partial outcome, zeroed after its length, see
+/// `outcomes::partial_outcome`.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct PartialOutcomeVariable {
+    pub len: Variable,
+    pub bytes: ArrayVariable<ByteVariable, MAX_PARTIAL_OUTCOME_LEN>,
+}
+
+/// See `outcomes::OutcomeStatus`.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct OutcomeStatusVariable {
+    pub kind: ByteVariable,
+    pub gas_burnt: U64Variable,
+    pub result_hash: CryptoHashVariable,
+}
+
+impl<F: RichField> From<OutcomeStatus> for OutcomeStatusVariableValue<F> {
+    fn from(status: OutcomeStatus) -> Self {
+        Self {
+            kind: status.kind,
+            gas_burnt: status.gas_burnt,
+            result_hash: status.result_hash.0.into(),
+        }
+    }
+}
+
+impl PartialOutcomeVariable {
+    /// The second of the outcome hashes.
+    pub(crate) fn hash<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> CryptoHashVariable {
+        let max = b.constant::<Variable>(L::Field::from_canonical_usize(MAX_PARTIAL_OUTCOME_LEN));
+        let room = b.sub(max, self.len);
+        b.api.range_check(room.0, 10);
+
+        let mut bytes = self.bytes.data.clone();
+        // Room for the SHA-256 padding of the longest outcome
+        let zero = b.constant::<ByteVariable>(0);
+        bytes.resize((MAX_PARTIAL_OUTCOME_LEN + 9).div_ceil(64) * 64, zero);
+        let len = U32Variable::from_variables_unsafe(&[self.len]);
+        b.curta_sha256_variable(&bytes, len)
+    }
+
+    /// Read the status and the gas burnt, asserting the encoding ends where
+    /// the length says. Only meaningful once `hash` has been checked.
+    pub(crate) fn decode<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> OutcomeStatusVariable {
+        let constant = |b: &mut CircuitBuilder<L, D>, v: usize| {
+            b.constant::<Variable>(L::Field::from_canonical_usize(v))
+        };
+        let values = self
+            .bytes
+            .data
+            .iter()
+            .map(|byte| byte_to_variable(b, *byte))
+            .collect_vec();
+
+        // Skip the receipt ids
+        let receipts = le_sum(b, &values[..4]);
+        let max = constant(b, MAX_OUTCOME_RECEIPTS);
+        let room = b.sub(max, receipts);
+        b.api.range_check(room.0, 5);
+        let hash_len = constant(b, 32);
+        let receipts_len = b.mul(receipts, hash_len);
+        let fixed = shift_left(
+            b,
+            &values[4..],
+            receipts_len,
+            // 32 * MAX_OUTCOME_RECEIPTS fits in 10 bits
+            10,
+            MAX_PARTIAL_OUTCOME_LEN - 4 - 32 * MAX_OUTCOME_RECEIPTS,
+        );
+
+        // The gas is a u64, borsh is little endian and evm bytes big endian
+        let gas_bytes = fixed[..8]
+            .iter()
+            .rev()
+            .map(|v| variable_to_byte(b, *v))
+            .collect_vec();
+        let gas_burnt = U64Variable::decode(b, &gas_bytes);
+
+        // Skip the tokens burnt and the executor
+        let executor_len = le_sum(b, &fixed[24..28]);
+        let max = constant(b, AccountId::MAX_LEN);
+        let room = b.sub(max, executor_len);
+        b.api.range_check(room.0, 7);
+        let status = shift_left(
+            b,
+            &fixed[28..],
+            executor_len,
+            7,
+            1 + 4 + MAX_OUTCOME_VALUE_LEN,
+        );
+
+        let kind = status[0];
+        b.api.range_check(kind.0, 2);
+        let is_kind = |b: &mut CircuitBuilder<L, D>, tag: u8| {
+            let tag = b.constant::<Variable>(L::Field::from_canonical_u8(tag));
+            b.is_equal(kind, tag)
+        };
+        let is_value = is_kind(b, OutcomeStatus::SUCCESS_VALUE);
+        let is_receipt = is_kind(b, OutcomeStatus::SUCCESS_RECEIPT_ID);
+
+        // The value is only read as one when it is
+        let zero = b.zero::<Variable>();
+        let value_len = le_sum(b, &status[1..5]);
+        let value_len = b.select(is_value, value_len, zero);
+        let max = constant(b, MAX_OUTCOME_VALUE_LEN);
+        let room = b.sub(max, value_len);
+        b.api.range_check(room.0, 9);
+        let mut value = status[5..]
+            .iter()
+            .map(|v| variable_to_byte(b, *v))
+            .collect_vec();
+        let zero_byte = b.constant::<ByteVariable>(0);
+        value.resize((MAX_OUTCOME_VALUE_LEN + 9).div_ceil(64) * 64, zero_byte);
+        let value_hash =
+            b.curta_sha256_variable(&value, U32Variable::from_variables_unsafe(&[value_len]));
+        let receipt_id = status[1..33]
+            .iter()
+            .map(|v| variable_to_byte(b, *v))
+            .collect_vec();
+        let receipt_id = CryptoHashVariable::decode(b, &receipt_id);
+        let default = b.constant::<CryptoHashVariable>([0u8; 32].into());
+        let result_hash = b.select(is_receipt, receipt_id, default);
+        let result_hash = b.select(is_value, value_hash, result_hash);
+
+        // The encoding ends after the status
+        let value_encoded_len = constant(b, 4);
+        let value_encoded_len = b.add(value_encoded_len, value_len);
+        let status_len = b.select(is_value, value_encoded_len, zero);
+        let receipt_len = b.select(is_receipt, hash_len, zero);
+        let status_len = b.add(status_len, receipt_len);
+        let len = constant(b, 4 + 8 + 16 + 4 + 1);
+        let len = b.add(len, receipts_len);
+        let len = b.add(len, executor_len);
+        let len = b.add(len, status_len);
+        b.assert_is_equal(len, self.len);
+
+        OutcomeStatusVariable {
+            kind: variable_to_byte(b, kind),
+            gas_burnt,
+            result_hash,
+        }
+    }
+}
+
+impl<F: RichField> From<Vec<u8>> for PartialOutcomeVariableValue<F> {
+    fn from(mut bytes: Vec<u8>) -> Self {
+        assert!(
+            bytes.len() <= MAX_PARTIAL_OUTCOME_LEN,
+            "the partial outcome has {} bytes, the circuit fits {}",
+            bytes.len(),
+            MAX_PARTIAL_OUTCOME_LEN
+        );
+        let len = F::from_canonical_usize(bytes.len());
+        bytes.resize(MAX_PARTIAL_OUTCOME_LEN, 0);
+        Self { len, bytes }
+    }
+}
+
 #[derive(CircuitVariable, Clone, Debug)]
 pub struct ProofVariable {
     pub head_block_root: CryptoHashVariable,
-    // TODO: constrain the outcome hash by borsh encoding in the circuit, not here
-    pub outcome_hash: CryptoHashVariable,
+    pub outcome_hashes: OutcomeHashesVariable,
+    /// Hashed to the second of the outcome hashes, for its status.
+    pub outcome: PartialOutcomeVariable,
     pub outcome_proof_block_hash: CryptoHashVariable,
-    pub outcome_proof: MerklePathVariable<16>, // TODO: get real number here
-    pub outcome_root_proof: MerklePathVariable<8>, // TODO: get real number here
+    pub outcome_proof: MerklePathVariable<OUTCOME_PROOF_DEPTH>,
+    pub outcome_root_proof: MerklePathVariable<OUTCOME_ROOT_PROOF_DEPTH>,
     pub block_header: HeaderVariable,
-    pub block_proof: MerklePathVariable<64>, // TODO: get real number here
+    pub block_proof: MerklePathVariable<BLOCK_PROOF_DEPTH>,
 }
 
 impl<F> From<Proof> for ProofVariableValue<F>
@@ -519,16 +1051,112 @@ where
                 proof,
             } => Self {
                 head_block_root: head_block_root.0.into(),
-                outcome_hash: CryptoHash::hash_borsh(proof.outcome_proof.to_hashes())
-                    .0
-                    .into(),
+                outcome_hashes: proof.outcome_proof.to_hashes().into(),
+                outcome: partial_outcome(&proof.outcome_proof.outcome).into(),
                 outcome_proof_block_hash: proof.outcome_proof.block_hash.0.into(),
                 outcome_proof: proof.outcome_proof.proof.into(),
                 outcome_root_proof: proof.outcome_root_proof.into(),
                 block_header: proof.block_header_lite.into(),
                 block_proof: proof.block_proof.into(),
             },
-            Proof::Experimental(_) => todo!("Batch proving"),
+            Proof::Experimental(_) => panic!("batches are verified as a BatchProofVariable"),
+        }
+    }
+}
+
+/// A header pre-hashed as in `experimental::LiteHeader`, three hashes rather
+/// than hashing the whole inner lite in the circuit.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct LiteHeaderVariable {
+    pub inner_lite_hash: CryptoHashVariable,
+    pub inner_rest_hash: CryptoHashVariable,
+    pub prev_block_hash: CryptoHashVariable,
+    pub outcome_root: CryptoHashVariable,
+}
+
+impl LiteHeaderVariable {
+    pub(crate) fn hash<L: PlonkParameters<D>, const D: usize>(
+        &self,
+        b: &mut CircuitBuilder<L, D>,
+    ) -> CryptoHashVariable {
+        let lite_rest = b.curta_sha256_pair(self.inner_lite_hash, self.inner_rest_hash);
+        b.curta_sha256_pair(lite_rest, self.prev_block_hash)
+    }
+}
+
+impl<F: RichField> From<LiteHeader> for LiteHeaderVariableValue<F> {
+    fn from(header: LiteHeader) -> Self {
+        Self {
+            inner_lite_hash: header.inner_lite_hash.0.into(),
+            inner_rest_hash: header.inner_rest_hash.0.into(),
+            prev_block_hash: header.prev_block_hash.0.into(),
+            outcome_root: header.outcome_root.0.into(),
+        }
+    }
+}
+
+/// A proof in a batch, verified against the batch's head block root.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct BlindedProofVariable {
+    /// False for the padding after the last proof in the batch.
+    pub is_active: BoolVariable,
+    pub outcome_hash: CryptoHashVariable,
+    pub outcome_proof_block_hash: CryptoHashVariable,
+    pub outcome_proof: MerklePathVariable<OUTCOME_PROOF_DEPTH>,
+    pub outcome_root_proof: MerklePathVariable<OUTCOME_ROOT_PROOF_DEPTH>,
+    pub header: LiteHeaderVariable,
+    pub block_proof: MerklePathVariable<BLOCK_PROOF_DEPTH>,
+}
+
+impl<F: RichField> From<Option<ExpandedProof>> for BlindedProofVariableValue<F> {
+    fn from(proof: Option<ExpandedProof>) -> Self {
+        let is_active = proof.is_some();
+        let proof = proof.unwrap_or_else(|| ExpandedProof {
+            outcome_proof_block_hash: CryptoHash::default(),
+            outcome_hash: CryptoHash::default(),
+            outcome_proof: vec![],
+            outcome_root_proof: vec![],
+            block_proof: vec![],
+            header: LiteHeader {
+                inner_lite_hash: CryptoHash::default(),
+                inner_rest_hash: CryptoHash::default(),
+                prev_block_hash: CryptoHash::default(),
+                outcome_root: CryptoHash::default(),
+            },
+        });
+        Self {
+            is_active,
+            outcome_hash: proof.outcome_hash.0.into(),
+            outcome_proof_block_hash: proof.outcome_proof_block_hash.0.into(),
+            outcome_proof: proof.outcome_proof.into(),
+            outcome_root_proof: proof.outcome_root_proof.into(),
+            header: proof.header.into(),
+            block_proof: proof.block_proof.into(),
+        }
+    }
+}
+
+/// Up to `N` outcome proofs against a single head block root, see
+/// `experimental::Proof`.
+#[derive(CircuitVariable, Clone, Debug)]
+pub struct BatchProofVariable<const N: usize> {
+    pub head_block_root: CryptoHashVariable,
+    pub proofs: ArrayVariable<BlindedProofVariable, N>,
+}
+
+impl<F: RichField, const N: usize> From<ExperimentalProof> for BatchProofVariableValue<N, F> {
+    fn from(batch: ExperimentalProof) -> Self {
+        assert!(
+            batch.len() <= N,
+            "the batch has {} proofs, the circuit fits {}",
+            batch.len(),
+            N
+        );
+        let mut proofs = batch.expand().map(Some).collect_vec();
+        proofs.resize(N, None);
+        Self {
+            head_block_root: batch.head_block_root.0.into(),
+            proofs: proofs.into_iter().map(Into::into).collect(),
         }
     }
 }
@@ -548,7 +1176,8 @@ impl<F: RichField> From<StakeInfo> for StakeInfoVariableValue<F> {
     }
 }
 
-pub type ApprovalMessage = BytesVariable<41>;
+pub type ApprovalMessage = BytesVariable<{ approval::ENDORSEMENT_LEN }>;
+pub type EndorsementMessage = BytesVariable<{ endorsement::MESSAGE_LEN }>;
 
 // TODO: not sure these even need to be hints
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -568,6 +1197,21 @@ impl<L: PlonkParameters<D>, const D: usize> Hint<L, D> for BuildEndorsement {
     }
 }
 
+/// Whether each key is valid, as `Ed25519::is_valid_key` checks natively.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ValidKeys<const LEN: usize>;
+
+impl<L: PlonkParameters<D>, const D: usize, const LEN: usize> Hint<L, D> for ValidKeys<LEN> {
+    fn hint(&self, input_stream: &mut ValueStream<L, D>, output_stream: &mut ValueStream<L, D>) {
+        let keys = input_stream.read_value::<BpsArr<PublicKeyVariable, LEN>>();
+        let valid = keys
+            .iter()
+            .map(|pk| Ed25519::is_valid_key(&pk.0))
+            .collect_vec();
+        output_stream.write_value::<BpsArr<BoolVariable, LEN>>(valid);
+    }
+}
+
 #[derive(CircuitVariable, Clone, Debug)]
 pub struct SyncedVariable {
     pub new_head: HeaderVariable,
@@ -596,44 +1240,6 @@ where
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct HashBpsInputs;
-
-impl<L: PlonkParameters<D>, const D: usize> Hint<L, D> for HashBpsInputs {
-    fn hint(&self, input_stream: &mut ValueStream<L, D>, output_stream: &mut ValueStream<L, D>) {
-        let bps = input_stream.read_value::<BpsArr<ValidatorStakeVariable>>();
-        let default_validator =
-            ValidatorStakeVariableValue::<<L as PlonkParameters<D>>::Field>::default();
-
-        let bps = bps
-            .into_iter()
-            .filter(|x| x.account_id != default_validator.account_id)
-            .map(Into::<ValidatorStakeView>::into)
-            .collect_vec();
-        log::debug!("Bps to hash: {:#?}", bps);
-        let hash = CryptoHash::hash_borsh(bps);
-        log::debug!("Hash: {:#?}", hash);
-
-        // TODO: figure out how to hash this in circuit
-        // It's non trivial because the account id is padded to the max len
-        output_stream.write_value::<CryptoHashVariable>(hash.0.into());
-    }
-}
-
-impl HashBpsInputs {
-    pub fn hash<L: PlonkParameters<D>, const D: usize>(
-        self,
-        b: &mut CircuitBuilder<L, D>,
-        bps: &BpsArr<ValidatorStakeVariable>,
-    ) -> CryptoHashVariable {
-        let mut input_stream = VariableStream::new();
-        input_stream.write::<BpsArr<ValidatorStakeVariable>>(bps);
-
-        let output_stream = b.hint(input_stream, self);
-        output_stream.read::<CryptoHashVariable>(b)
-    }
-}
-
 // TODO: EVM these, maybe macro?
 #[derive(CircuitVariable, Clone, Debug)]
 pub struct TransactionOrReceiptIdVariable {
@@ -665,6 +1271,14 @@ impl<F: RichField> From<GetProof> for TransactionOrReceiptIdVariableValue<F> {
     }
 }
 
+impl<F: RichField> TryFrom<GetProofRequest> for TransactionOrReceiptIdVariableValue<F> {
+    type Error = InvalidRequest;
+
+    fn try_from(value: GetProofRequest) -> Result<Self, Self::Error> {
+        value.build().map(Into::into)
+    }
+}
+
 pub fn byte_from_bool<L: PlonkParameters<D>, const D: usize>(
     b: &mut CircuitBuilder<L, D>,
     bool: BoolVariable,
@@ -724,42 +1338,26 @@ mod tests {
     use std::str::FromStr;
 
     use ::test_utils::CryptoHash;
-    use near_light_client_protocol::prelude::Itertools;
-    use near_primitives::types::TransactionOrReceiptId;
+    use near_light_client_protocol::prelude::{BasicProof, Itertools};
 
     use super::*;
     use crate::{
-        test_utils::{builder_suite, B, PI, PO},
+        test_utils::{builder_suite, mock_builder_suite, B, PI, PO},
         variables::TransactionOrReceiptIdVariableValue,
     };
 
     #[test]
     fn test_serialise_tx() {
-        fn tx(hash: &str, sender: &str) -> TransactionOrReceiptId {
-            TransactionOrReceiptId::Transaction {
-                transaction_hash: CryptoHash::from_str(hash).unwrap(),
-                sender_id: sender.parse().unwrap(),
-            }
-        }
-        fn rx(hash: &str, receiver: &str) -> TransactionOrReceiptId {
-            TransactionOrReceiptId::Receipt {
-                receipt_id: CryptoHash::from_str(hash).unwrap(),
-                receiver_id: receiver.parse().unwrap(),
-            }
-        }
-
         let txs: Vec<TransactionOrReceiptIdVariableValue<GoldilocksField>> = vec![
-            tx(
-                "3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz",
-                "zavodil.testnet",
-            ),
-            rx(
-                "9cVuYLKYF26QevZ315RLb9ArU3gbcgPc4LDRJfZQyZHo",
-                "priceoracle.testnet",
-            ),
+            GetProofRequest::transaction()
+                .id("3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz")
+                .account("zavodil.testnet"),
+            GetProofRequest::receipt()
+                .id("9cVuYLKYF26QevZ315RLb9ArU3gbcgPc4LDRJfZQyZHo")
+                .account("priceoracle.testnet"),
         ]
         .into_iter()
-        .map(Into::into)
+        .map(|req| req.try_into().unwrap())
         .collect_vec();
 
         let define = |b: &mut B| {
@@ -778,4 +1376,390 @@ mod tests {
         };
         builder_suite(define, writer, assertions);
     }
+
+    /// A named account, an account of the max length, a NEAR-implicit and an
+    /// eth-implicit account.
+    const ACCOUNTS: [&str; 4] = [
+        "a.near",
+        "a-very-long-account-name-that-fills-every-one-of-the-64b.testnet",
+        "98793cd91a3f870fb126f66285808c7e094afcfc4eda8a970f6648cdf0dbd6de",
+        "0x5a4e1e6e2d8dab6a1b0e48e1fa1b8b6f0b2f62f3",
+    ];
+
+    #[test]
+    fn test_implicit_account_padding() {
+        for account in ACCOUNTS {
+            let account_id: AccountId = account.parse().unwrap();
+            let padded = pad_account_id(&account_id);
+            assert_eq!(&padded[..account.len()], account.as_bytes());
+            assert!(padded[account.len()..]
+                .iter()
+                .all(|x| *x == ACCOUNT_ID_PADDING_BYTE));
+            assert_eq!(normalise_account_id::<GoldilocksField>(&padded), account_id);
+        }
+        // Implicit accounts fill the whole variable
+        assert_eq!(ACCOUNTS[1].len(), AccountId::MAX_LEN);
+        assert_eq!(ACCOUNTS[2].len(), AccountId::MAX_LEN);
+    }
+
+    #[test]
+    #[should_panic(expected = "account id has bytes after its padding")]
+    fn test_normalise_rejects_non_canonical_padding() {
+        let mut padded = pad_account_id(&ACCOUNTS[3].parse().unwrap());
+        padded[AccountId::MAX_LEN - 1] = b'a';
+        normalise_account_id::<GoldilocksField>(&padded);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid account id")]
+    fn test_normalise_rejects_checksummed_eth_accounts() {
+        let padded = pad_account_bytes(b"0x5A4e1e6e2d8dAb6a1b0E48e1fa1B8b6f0b2f62F3".to_vec());
+        normalise_account_id::<GoldilocksField>(&padded);
+    }
+
+    #[test]
+    #[should_panic(expected = "account id is longer than 64 bytes")]
+    fn test_pad_rejects_long_accounts() {
+        pad_account_bytes(vec![b'a'; AccountId::MAX_LEN + 1]);
+    }
+
+    #[test]
+    fn test_implicit_accounts_compare_in_circuit() {
+        let define = |b: &mut B| {
+            let accounts = (0..ACCOUNTS.len())
+                .map(|_| b.read::<AccountIdVariable>())
+                .collect_vec();
+            let changed = b.read::<AccountIdVariable>();
+            for (i, x) in accounts.iter().enumerate() {
+                for y in &accounts[i..] {
+                    let eq = b.is_equal(*x, *y);
+                    b.write::<BoolVariable>(eq);
+                }
+                let eq = b.is_equal(*x, changed);
+                b.write::<BoolVariable>(eq);
+            }
+        };
+        let writer = |input: &mut PI| {
+            for account in ACCOUNTS {
+                input.write::<AccountIdVariable>(pad_account_id(&account.parse().unwrap()));
+            }
+            // The NEAR-implicit account with its last character changed
+            let mut changed = ACCOUNTS[2].to_string();
+            changed.pop();
+            changed.push('f');
+            input.write::<AccountIdVariable>(pad_account_id(&changed.parse().unwrap()));
+        };
+        let assertions = |mut output: PO| {
+            for i in 0..ACCOUNTS.len() {
+                for j in i..ACCOUNTS.len() {
+                    assert_eq!(output.read::<BoolVariable>(), i == j, "{} == {}", i, j);
+                }
+                assert!(!output.read::<BoolVariable>(), "{} == changed", i);
+            }
+        };
+        builder_suite(define, writer, assertions);
+    }
+
+    #[test]
+    fn test_encode_validator_borsh() {
+        let validators = ACCOUNTS
+            .iter()
+            .enumerate()
+            .map(|(i, account)| {
+                ValidatorStakeView::V1(ValidatorStakeViewV1 {
+                    account_id: account.parse().unwrap(),
+                    public_key: PublicKey::ED25519(ED25519PublicKey([i as u8 + 1; 32])),
+                    stake: 10u128.pow(24) * 3 / 2 + i as u128,
+                })
+            })
+            .collect_vec();
+
+        let define = |b: &mut B| {
+            for _ in 0..ACCOUNTS.len() {
+                let vs = b.read::<ValidatorStakeVariable>();
+                let (encoded, len) = vs.encode_borsh(b);
+                for byte in encoded {
+                    let byte = variable_to_byte(b, byte);
+                    b.write::<ByteVariable>(byte);
+                }
+                let len = variable_to_byte(b, len);
+                b.write::<ByteVariable>(len);
+            }
+        };
+        let writer = |input: &mut PI| {
+            for vs in validators.clone() {
+                input.write::<ValidatorStakeVariable>(vs.into_validator_stake().into());
+            }
+        };
+        let assertions = |mut output: PO| {
+            for vs in &validators {
+                let encoded = (0..VALIDATOR_STAKE_ENCODED_LEN)
+                    .map(|_| output.read::<ByteVariable>())
+                    .collect_vec();
+                let len = output.read::<ByteVariable>() as usize;
+
+                let expected = borsh::to_vec(vs).unwrap();
+                assert_eq!(len, expected.len());
+                assert_eq!(&encoded[..len], &expected[..]);
+                assert!(encoded[len..].iter().all(|b| *b == 0));
+            }
+        };
+        mock_builder_suite(define, writer, assertions);
+    }
+
+    #[test]
+    fn test_unsupported_approvals_are_absent() {
+        // Zero bytes, which base58 writes as ones
+        let sig = |scheme: &str, len: usize| {
+            Some(Box::new(
+                Signature::from_str(&format!("{}:{}", scheme, "1".repeat(len))).unwrap(),
+            ))
+        };
+        let approvals = vec![sig("ed25519", 64), sig("secp256k1", 65), None];
+        let value = BpsApprovalsValue::<3, GoldilocksField>::from(approvals);
+        assert_eq!(value.is_active, vec![true, false, false]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the outcome has 103 hashes, the circuit fits 102")]
+    fn test_outcome_hashes_too_many() {
+        let hashes = vec![CryptoHash::default(); MAX_OUTCOME_HASHES + 1];
+        let _ = OutcomeHashesVariableValue::<GoldilocksField>::from(hashes);
+    }
+
+    #[test]
+    #[should_panic(expected = "the partial outcome has 870 bytes, the circuit fits 869")]
+    fn test_partial_outcome_too_long() {
+        let bytes = vec![0; MAX_PARTIAL_OUTCOME_LEN + 1];
+        let _ = PartialOutcomeVariableValue::<GoldilocksField>::from(bytes);
+    }
+
+    #[test]
+    fn test_domain_from_chain_id() {
+        let domain = domain_from_chain_id(5);
+        assert!(domain[..31].iter().all(|b| *b == 0));
+        assert_eq!(domain[31], 5);
+        assert_eq!(U256::from_big_endian(&domain), U256::from(5));
+    }
+
+    #[test]
+    fn test_batch_padding() {
+        let batch = ExperimentalProof::new(
+            CryptoHash::default(),
+            vec![::test_utils::fixture("new.json")],
+        );
+        let value = BatchProofVariableValue::<3, GoldilocksField>::from(batch);
+        assert_eq!(
+            value.proofs.iter().map(|p| p.is_active).collect_vec(),
+            [true, false, false]
+        );
+        assert_eq!(value.proofs[1].outcome_hash, [0u8; 32].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "the batch has 2 proofs, the circuit fits 1")]
+    fn test_batch_too_large() {
+        let proof = ::test_utils::fixture::<BasicProof>("new.json");
+        let batch = ExperimentalProof::new(CryptoHash::default(), vec![proof.clone(), proof]);
+        let _ = BatchProofVariableValue::<1, GoldilocksField>::from(batch);
+    }
+
+    #[test]
+    fn test_balance_u256() {
+        let stake = 10u128.pow(24) * 3 / 2;
+        assert_eq!(balance_to_u256(stake), U256::from(stake));
+        assert_eq!(
+            balance_from_u256(balance_to_u256(u128::MAX)),
+            Some(u128::MAX)
+        );
+        assert_eq!(balance_from_u256(U256::from(u128::MAX) + 1), None);
+    }
+
+    /// Golden layouts of the header encodings, any drift from what NEAR hashes
+    /// fails here rather than as a hash mismatch on chain.
+    mod golden {
+        use near_light_client_protocol::BlockHeaderInnerLite;
+
+        use super::*;
+
+        fn golden(name: &str) -> Vec<u8> {
+            ::test_utils::fixture_bytes(&format!("{}_v{}.bin", name, INNER_LAYOUT_VERSION))
+        }
+
+        #[test]
+        fn test_inner_lite_layout() {
+            let expected = golden("inner_lite");
+            assert_eq!(expected.len(), INNER_ENCODED_LEN);
+
+            let view = ::test_utils::test_next().body.inner_lite;
+            let borsh = borsh::to_vec(&BlockHeaderInnerLite::from(view.clone())).unwrap();
+            assert_eq!(borsh, expected, "NEAR's borsh layout changed");
+
+            let value = HeaderInnerVariableValue::<GoldilocksField>::from(view);
+            let ours = HeaderInnerVariable::encode_value::<GoldilocksField>(value);
+            assert_eq!(ours, expected, "our inner lite layout changed");
+
+            let decoded = HeaderInnerVariable::decode_value::<GoldilocksField>(&expected);
+            assert_eq!(
+                HeaderInnerVariable::encode_value::<GoldilocksField>(decoded),
+                expected
+            );
+        }
+
+        #[test]
+        fn test_header_layout() {
+            let expected = golden("header");
+            assert_eq!(expected.len(), 64 + INNER_ENCODED_LEN);
+            assert_eq!(expected[64..], golden("inner_lite"));
+
+            let fixture = ::test_utils::test_next().body;
+            let header = ::test_utils::view_to_lite_view(fixture);
+            let value = HeaderVariableValue::<GoldilocksField>::from(header.clone());
+            assert_eq!(
+                HeaderVariable::encode_value::<GoldilocksField>(value),
+                expected
+            );
+
+            // The layout is what the block hash commits to
+            let inner_lite = CryptoHash::hash_bytes(&expected[64..]);
+            let lite_rest =
+                CryptoHash::hash_bytes(&[inner_lite.0, header.inner_rest_hash.0].concat());
+            let hash = CryptoHash::hash_bytes(&[lite_rest.0, header.prev_block_hash.0].concat());
+            assert_eq!(hash, ::test_utils::test_last().last_block_hash);
+            assert_eq!(hash, header.hash());
+        }
+    }
+
+    /// Differential tests of the EVM encoding against ethers, using mirrors of
+    /// the structs in `INearX.sol`.
+    ///
+    /// The contract reads and writes these with `abi.encodePacked`, so that is
+    /// what we compare against, `abi.encode` intentionally diverges.
+    mod solidity {
+        use ethers::{
+            abi::{encode, encode_packed, Token},
+            types::H256,
+        };
+        use near_light_client_protocol::prelude::AccountId;
+        use near_light_client_rpc::prelude::GetProof;
+
+        use super::*;
+
+        /// `TransactionOrReceiptId`, the account is stored padded in `bytes`.
+        fn transaction_or_receipt_id(is_transaction: bool, id: [u8; 32], account: &str) -> Token {
+            let account = pad_account_id(&account.parse::<AccountId>().unwrap());
+            Token::Tuple(vec![
+                Token::Bool(is_transaction),
+                Token::FixedBytes(id.to_vec()),
+                Token::Bytes(account.to_vec()),
+            ])
+        }
+
+        /// `ProofVerificationResult`
+        fn proof_verification_result(id: [u8; 32], result: bool) -> Token {
+            Token::Tuple(vec![Token::FixedBytes(id.to_vec()), Token::Bool(result)])
+        }
+
+        /// The same as the contract's loop of `abi.encodePacked` over each
+        /// field, `encode_packed` refuses tuples.
+        fn encode_packed_structs(structs: &[Token]) -> Vec<u8> {
+            let fields = structs
+                .iter()
+                .flat_map(|t| match t {
+                    Token::Tuple(fields) => fields.clone(),
+                    t => vec![t.clone()],
+                })
+                .collect_vec();
+            encode_packed(&fields).unwrap()
+        }
+
+        #[test]
+        fn test_transaction_or_receipt_id() {
+            let ids = [
+                GetProof::Transaction {
+                    transaction_hash: CryptoHash([1; 32]),
+                    sender_id: "zavodil.testnet".parse().unwrap(),
+                },
+                GetProof::Receipt {
+                    receipt_id: CryptoHash([2; 32]),
+                    receiver_id: "priceoracle.testnet".parse().unwrap(),
+                },
+            ];
+
+            let ours = ids
+                .iter()
+                .cloned()
+                .flat_map(|id| {
+                    TransactionOrReceiptIdVariable::encode_value::<GoldilocksField>(id.into())
+                })
+                .collect_vec();
+            let theirs = encode_packed_structs(&[
+                transaction_or_receipt_id(true, [1; 32], "zavodil.testnet"),
+                transaction_or_receipt_id(false, [2; 32], "priceoracle.testnet"),
+            ]);
+            assert_eq!(ours, theirs);
+        }
+
+        #[test]
+        fn test_implicit_accounts() {
+            let ids = ACCOUNTS
+                .iter()
+                .map(|account| GetProof::Receipt {
+                    receipt_id: CryptoHash([5; 32]),
+                    receiver_id: account.parse().unwrap(),
+                })
+                .collect_vec();
+
+            for (id, account) in ids.into_iter().zip(ACCOUNTS) {
+                let ours =
+                    TransactionOrReceiptIdVariable::encode_value::<GoldilocksField>(id.into());
+                let theirs =
+                    encode_packed_structs(&[transaction_or_receipt_id(false, [5; 32], account)]);
+                assert_eq!(ours, theirs);
+
+                let decoded =
+                    TransactionOrReceiptIdVariable::decode_value::<GoldilocksField>(&ours);
+                assert_eq!(
+                    normalise_account_id::<GoldilocksField>(&decoded.account).as_str(),
+                    account
+                );
+            }
+        }
+
+        #[test]
+        fn test_proof_verification_result() {
+            let results = [([3u8; 32], true), ([4u8; 32], false)];
+
+            // As written by the verify circuit
+            let ours = results
+                .iter()
+                .flat_map(|(id, result)| {
+                    let mut bytes = CryptoHashVariable::encode_value::<GoldilocksField>(H256(*id));
+                    bytes.extend(ByteVariable::encode_value::<GoldilocksField>(*result as u8));
+                    bytes
+                })
+                .collect_vec();
+            let theirs = encode_packed_structs(
+                &results
+                    .iter()
+                    .map(|(id, result)| proof_verification_result(*id, *result))
+                    .collect_vec(),
+            );
+            assert_eq!(ours, theirs);
+
+            // Documents the divergence, each field is padded to a word
+            let abi = encode(&[proof_verification_result([3; 32], true)]);
+            assert_eq!(abi.len(), 64);
+            assert_ne!(abi[..33], ours[..33]);
+        }
+
+        #[test]
+        fn test_domain() {
+            // The domain is the one field that is ABI encoded, as `uint256`
+            let domain = DomainVariable::encode_value::<GoldilocksField>(H256(
+                domain_from_chain_id(11155111),
+            ));
+            assert_eq!(domain, encode(&[Token::Uint(U256::from(11155111))]));
+        }
+    }
 }
@@ -1,16 +1,23 @@
-use near_light_client_protocol::prelude::Itertools;
+use near_light_client_protocol::{outcomes::OutcomeStatus, prelude::Itertools};
 pub use plonky2x::{self, backend::circuit::Circuit, prelude::*};
 use plonky2x::{
-    frontend::{hint::simple::hint::Hint, mapreduce::generator::MapReduceDynamicGenerator},
+    frontend::{
+        hint::simple::hint::Hint, mapreduce::generator::MapReduceDynamicGenerator,
+        vars::EvmVariable,
+    },
     prelude::plonky2::plonk::config::{AlgebraicHasher, GenericConfig},
     register_watch_generator,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    abi::{AbiWrite, VERIFY_HEAD_WORDS, WORD},
     builder::Verify,
     hint::{FetchHeaderInputs, FetchProofInputs, ProofInputVariable},
-    variables::{byte_from_bool, CryptoHashVariable, EncodeInner, TransactionOrReceiptIdVariable},
+    variables::{
+        assert_network_fits, byte_from_bool, BatchProofVariable, CryptoHashVariable,
+        DomainVariable, EncodeInner, OutcomeStatusVariable, TransactionOrReceiptIdVariable,
+    },
 };
 
 pub type ProofMapReduceVariable<const B: usize> = ArrayVariable<ProofVerificationResultVariable, B>;
@@ -19,19 +26,35 @@ pub type ProofMapReduceVariable<const B: usize> = ArrayVariable<ProofVerificatio
 pub struct ProofVerificationResultVariable {
     pub id: CryptoHashVariable,
     pub result: BoolVariable,
+    pub status: OutcomeStatusVariable,
 }
 
+/// Verifies up to `N` transaction or receipt outcomes against a trusted
+/// header, in batches of `B`.
+///
+/// The outputs are the domain then, for each id, the id, whether it passed,
+/// the outcome's status kind, its gas burnt and its result hash, see
+/// `outcomes::OutcomeStatus`. So a contract can act on what the
+/// transaction or receipt did, not only that it happened. With `ABI` the
+/// results are an `abi.encode`d array, see `abi`.
 #[derive(Debug, Clone)]
-pub struct VerifyCircuit<const N: usize, const B: usize, const NETWORK: usize = 1>;
-
-impl<const N: usize, const B: usize, const NETWORK: usize> Circuit
-    for VerifyCircuit<N, B, NETWORK>
+pub struct VerifyCircuit<
+    const N: usize,
+    const B: usize,
+    const NETWORK: usize = 1,
+    const ABI: bool = false,
+>;
+
+impl<const N: usize, const B: usize, const NETWORK: usize, const ABI: bool> Circuit
+    for VerifyCircuit<N, B, NETWORK, ABI>
 {
     fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
     where
         <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher:
             AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
     {
+        assert_network_fits(NETWORK.into());
+        let domain = b.evm_read::<DomainVariable>();
         let trusted_header_hash = b.evm_read::<CryptoHashVariable>();
         let head = FetchHeaderInputs(NETWORK.into()).fetch(b, &trusted_header_hash);
 
@@ -45,9 +68,11 @@ impl<const N: usize, const B: usize, const NETWORK: usize> Circuit
         // Init a default result for N
         let zero = b.constant::<CryptoHashVariable>([0u8; 32].into());
         let _false = b._false();
+        let status = b.constant::<OutcomeStatusVariable>(OutcomeStatus::default().into());
         let default = ProofVerificationResultVariable {
             id: zero,
             result: _false,
+            status,
         };
 
         // TODO: write some outputs here for each ID
@@ -59,8 +84,9 @@ impl<const N: usize, const B: usize, const NETWORK: usize> Circuit
 
                 // TODO[Optimisation]: could parallelise these
                 for ProofInputVariable { id, proof } in proofs.data {
+                    let status = b.outcome_status(&proof);
                     let result = b.verify(proof);
-                    results.push(ProofVerificationResultVariable { id, result });
+                    results.push(ProofVerificationResultVariable { id, result, status });
                 }
 
                 results.resize(
@@ -73,10 +99,25 @@ impl<const N: usize, const B: usize, const NETWORK: usize> Circuit
             |_, l, r, b| MergeProofHint::<N>.merge(b, &l, &r),
         );
         b.watch_slice(&output.data, "output");
+        b.evm_write::<DomainVariable>(domain);
+        if ABI {
+            b.abi_write_uint((VERIFY_HEAD_WORDS * WORD) as u64);
+            b.abi_write_uint(N as u64);
+        }
         for r in output.data {
             b.evm_write::<CryptoHashVariable>(r.id);
             let passed = byte_from_bool(b, r.result);
-            b.evm_write::<ByteVariable>(passed);
+            if ABI {
+                b.abi_write_value(&[passed]);
+                b.abi_write_value(&[r.status.kind]);
+                let gas_burnt = r.status.gas_burnt.encode(b);
+                b.abi_write_value(&gas_burnt);
+            } else {
+                b.evm_write::<ByteVariable>(passed);
+                b.evm_write::<ByteVariable>(r.status.kind);
+                b.evm_write::<U64Variable>(r.status.gas_burnt);
+            }
+            b.evm_write::<CryptoHashVariable>(r.status.result_hash);
         }
     }
 
@@ -107,6 +148,35 @@ impl<const N: usize, const B: usize, const NETWORK: usize> Circuit
     }
 }
 
+/// Verifies a batch of up to `N` outcome proofs against a single head block
+/// root, so the cost of a proof is shared across the batch.
+///
+/// Unlike `VerifyCircuit` the proofs aren't fetched, the batch is the input,
+/// see `BatchProofVariable`. The head block root is written out so the
+/// verifier can check it was synced.
+#[derive(Debug, Clone)]
+pub struct BatchVerifyCircuit<const N: usize>;
+
+impl<const N: usize> Circuit for BatchVerifyCircuit<N> {
+    fn define<L: PlonkParameters<D>, const D: usize>(b: &mut CircuitBuilder<L, D>)
+    where
+        <<L as PlonkParameters<D>>::Config as GenericConfig<D>>::Hasher:
+            AlgebraicHasher<<L as PlonkParameters<D>>::Field>,
+    {
+        let domain = b.read::<DomainVariable>();
+        let batch = b.read::<BatchProofVariable<N>>();
+
+        let verified = b.verify_batch(&batch);
+
+        b.write::<DomainVariable>(domain);
+        b.write::<CryptoHashVariable>(batch.head_block_root);
+        for (proof, verified) in batch.proofs.data.iter().zip(verified.data) {
+            b.write::<CryptoHashVariable>(proof.outcome_hash);
+            b.write::<BoolVariable>(verified);
+        }
+    }
+}
+
 // Hinting for this as it's taking too much effort to do it in a constrained way
 // It's probably a security risk that we'd need to fix later since technically
 // these can just be changed post-verification
@@ -157,6 +227,7 @@ impl<L: PlonkParameters<D>, const D: usize, const N: usize> Hint<L, D> for Merge
             ProofVerificationResultVariableValue::<L::Field> {
                 id: [0u8; 32].into(),
                 result: false,
+                status: OutcomeStatus::default().into(),
             },
         );
 
@@ -184,15 +255,18 @@ impl<const N: usize> MergeProofHint<N> {
 mod beefy_tests {
     use std::str::FromStr;
 
-    use near_light_client_protocol::prelude::Itertools;
+    use near_light_client_protocol::prelude::{ExperimentalProof, Itertools};
+    use near_light_client_rpc::request::GetProofRequest;
     use near_primitives::types::TransactionOrReceiptId;
     use serial_test::serial;
     use test_utils::{fixture, CryptoHash};
 
     use super::*;
     use crate::{
-        test_utils::{builder_suite, testnet_state, B, NETWORK, PI, PO},
-        variables::TransactionOrReceiptIdVariableValue,
+        test_utils::{builder_suite, testnet_state, B, DOMAIN, NETWORK, PI, PO},
+        variables::{
+            domain_from_chain_id, OutcomeStatusVariableValue, TransactionOrReceiptIdVariableValue,
+        },
     };
 
     #[test]
@@ -205,32 +279,17 @@ mod beefy_tests {
         const AMT: usize = 2;
         const BATCH: usize = 1;
 
-        fn tx(hash: &str, sender: &str) -> TransactionOrReceiptId {
-            TransactionOrReceiptId::Transaction {
-                transaction_hash: CryptoHash::from_str(hash).unwrap(),
-                sender_id: sender.parse().unwrap(),
-            }
-        }
-        fn rx(hash: &str, receiver: &str) -> TransactionOrReceiptId {
-            TransactionOrReceiptId::Receipt {
-                receipt_id: CryptoHash::from_str(hash).unwrap(),
-                receiver_id: receiver.parse().unwrap(),
-            }
-        }
-
         // TODO: test way more of these, pull the last 64 transactions and prove them
         let txs: Vec<TransactionOrReceiptIdVariableValue<GoldilocksField>> = vec![
-            tx(
-                "3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz",
-                "zavodil.testnet",
-            ),
-            rx(
-                "9cVuYLKYF26QevZ315RLb9ArU3gbcgPc4LDRJfZQyZHo",
-                "priceoracle.testnet",
-            ),
+            GetProofRequest::transaction()
+                .id("3z2zqitrXNYQs19z5tK5a4bZSxdx7baqzGFUyGAkW9Mz")
+                .account("zavodil.testnet"),
+            GetProofRequest::receipt()
+                .id("9cVuYLKYF26QevZ315RLb9ArU3gbcgPc4LDRJfZQyZHo")
+                .account("priceoracle.testnet"),
         ]
         .into_iter()
-        .map(Into::into)
+        .map(|req| req.try_into().unwrap())
         .collect_vec();
 
         assert_eq!(txs.len(), AMT);
@@ -239,19 +298,28 @@ mod beefy_tests {
             VerifyCircuit::<AMT, BATCH, NETWORK>::define(b);
         };
         let writer = |input: &mut PI| {
+            input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
             input.evm_write::<CryptoHashVariable>(header.hash().0.into());
             for tx in txs {
                 input.evm_write::<TransactionOrReceiptIdVariable>(tx.into());
             }
         };
         let assertions = |mut output: PO| {
+            let domain = output.evm_read::<DomainVariable>();
+            assert_eq!(domain, domain_from_chain_id(DOMAIN).into());
             let mut results = vec![];
             for _ in 0..AMT {
                 let id = output.evm_read::<CryptoHashVariable>();
                 let result = output.evm_read::<ByteVariable>();
+                let status = OutcomeStatusVariableValue::<GoldilocksField> {
+                    kind: output.evm_read::<ByteVariable>(),
+                    gas_burnt: output.evm_read::<U64Variable>(),
+                    result_hash: output.evm_read::<CryptoHashVariable>(),
+                };
                 results.push(ProofVerificationResultVariableValue::<GoldilocksField> {
                     id,
                     result: result != 0,
+                    status,
                 });
             }
             println!("{:#?}", results);
@@ -259,6 +327,40 @@ mod beefy_tests {
         builder_suite(define, writer, assertions);
     }
 
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_verify_batch_e2e() {
+        let root = CryptoHash::from_str("WWrLWbWHwSmjtTn5oBZPYgRCuCYn6fkYVa4yhPWNK4L").unwrap();
+        let batch = ExperimentalProof::new(root, vec![fixture("new.json"), fixture("old.json")]);
+        let outcomes = batch.expand().map(|p| p.outcome_hash).collect_vec();
+
+        // Room for one more than the batch, so padding is proven too
+        const AMT: usize = 3;
+
+        let define = |b: &mut B| {
+            BatchVerifyCircuit::<AMT>::define(b);
+        };
+        let writer = |input: &mut PI| {
+            input.write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
+            input.write::<BatchProofVariable<AMT>>(batch.into());
+        };
+        let assertions = |mut output: PO| {
+            assert_eq!(
+                output.read::<DomainVariable>(),
+                domain_from_chain_id(DOMAIN).into()
+            );
+            assert_eq!(output.read::<CryptoHashVariable>(), root.0.into());
+            for outcome in outcomes {
+                assert_eq!(output.read::<CryptoHashVariable>(), outcome.0.into());
+                assert!(output.read::<BoolVariable>(), "proof verified");
+            }
+            assert_eq!(output.read::<CryptoHashVariable>(), [0u8; 32].into());
+            assert!(!output.read::<BoolVariable>(), "padding isn't verified");
+        };
+        builder_suite(define, writer, assertions);
+    }
+
     // TODO: ignore flag as this test will likely be overkill
     // #[test]
     // #[serial]
@@ -282,19 +384,28 @@ mod beefy_tests {
             VerifyCircuit::<AMT, BATCH, NETWORK>::define(b);
         };
         let writer = |input: &mut PI| {
+            input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
             input.evm_write::<CryptoHashVariable>(header.hash().0.into());
             for tx in txs {
                 input.evm_write::<TransactionOrReceiptIdVariable>(tx.into());
             }
         };
         let assertions = |mut output: PO| {
+            let domain = output.evm_read::<DomainVariable>();
+            assert_eq!(domain, domain_from_chain_id(DOMAIN).into());
             let mut results = vec![];
             for _ in 0..AMT {
                 let id = output.evm_read::<CryptoHashVariable>();
                 let result = output.evm_read::<ByteVariable>();
+                let status = OutcomeStatusVariableValue::<GoldilocksField> {
+                    kind: output.evm_read::<ByteVariable>(),
+                    gas_burnt: output.evm_read::<U64Variable>(),
+                    result_hash: output.evm_read::<CryptoHashVariable>(),
+                };
                 results.push(ProofVerificationResultVariableValue::<GoldilocksField> {
                     id,
                     result: result != 0,
+                    status,
                 });
             }
             println!("{:#?}", results);
@@ -0,0 +1,310 @@
+//! Wrapping proofs for verification on Ethereum.
+//!
+//! Verifying a plonky2 proof in the EVM costs far too much gas, the field
+//! isn't native and neither is the hash. So a proof is wrapped in two steps:
+//!
+//! 1. It is verified in a plonky2 circuit hashed with Poseidon over BN254,
+//!    plonky2x's `WrappedCircuit`, which reduces the public inputs to an input
+//!    hash and an output hash.
+//! 2. That proof is verified in a gnark circuit over BN254, proven with Groth16
+//!    or PLONK. This is the gnark verifier from plonky2x, built from
+//!    `plonky2x/verifier` in succinctx.
+//!
+//! The result is checked by the `verify(inputHash, outputHash, proof)` of the
+//! verifier contract gnark generates, the same one the function gateway
+//! uses. The gnark keys are set up for the wrapped circuit the first time a
+//! data dir is used, so a dir must only ever hold one circuit.
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use nearx_error::{bail, ensure, err, Context, Error, Result};
+use plonky2x::{
+    backend::{
+        circuit::{CircuitBuild, Groth16WrapperParameters},
+        wrapper::wrap::WrappedCircuit,
+    },
+    prelude::{CircuitBuilder, DefaultParameters},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    range::Proof,
+    repro::{
+        ABI, AGGREGATE_SYNC_AMT, KEY_POLICY, NETWORK, SKIP_SYNC_EPOCHS, VERIFY_PROOF_AMT,
+        VERIFY_PROOF_BATCH_SIZE,
+    },
+    AggregateSyncCircuit, Circuit, RollingSyncCircuit, SkipSyncCircuit, StateProofCircuit,
+    SyncCircuit, VerifyCircuit,
+};
+
+type L = DefaultParameters;
+const D: usize = 2;
+
+/// Written by gnark once the keys are set up.
+const PROVING_KEY: &str = "proving.key";
+/// Written by gnark for each proof.
+const PROOF: &str = "proof.json";
+
+/// The proof system of the final step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum System {
+    /// Cheapest to verify, with a setup per circuit.
+    #[default]
+    Groth16,
+    /// A universal setup, costs more gas to verify.
+    Plonk,
+}
+
+impl fmt::Display for System {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Groth16 => "groth16",
+            Self::Plonk => "plonk",
+        })
+    }
+}
+
+impl FromStr for System {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "groth16" => Ok(Self::Groth16),
+            "plonk" => Ok(Self::Plonk),
+            _ => bail!(
+                Prove,
+                "Unknown proof system {}, expected groth16 or plonk",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrapConfig {
+    /// The gnark verifier binary.
+    pub gnark: PathBuf,
+    /// Where the wrapped circuit, the gnark keys and the proofs are written.
+    pub dir: PathBuf,
+    pub system: System,
+}
+
+/// A proof for the verifier contract, the hashes are its public inputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvmProof {
+    #[serde(with = "hex_bytes")]
+    pub input_hash: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub output_hash: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl EvmProof {
+    fn parse(json: &str) -> Result<Self> {
+        let proof: Self = serde_json::from_str(json)?;
+        ensure!(
+            proof.input_hash.len() == 32 && proof.output_hash.len() == 32,
+            Prove,
+            "The input and output hashes should be 32 bytes"
+        );
+        ensure!(!proof.proof.is_empty(), Prove, "The proof is empty");
+        Ok(proof)
+    }
+}
+
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        hex::decode(s.strip_prefix("0x").unwrap_or(&s)).map_err(D::Error::custom)
+    }
+}
+
+/// Wraps the proofs of one circuit.
+pub struct Wrapper {
+    circuit: WrappedCircuit<L, Groth16WrapperParameters, D>,
+    config: WrapConfig,
+}
+
+impl Wrapper {
+    /// Build the wrapped circuit, this takes about as long as building the
+    /// circuit itself.
+    pub fn build(circuit: CircuitBuild<L, D>, config: WrapConfig) -> Result<Self> {
+        std::fs::create_dir_all(&config.dir)
+            .with_context(|| format!("Failed to create {}", config.dir.display()))?;
+        Ok(Self {
+            circuit: WrappedCircuit::build(circuit),
+            config,
+        })
+    }
+
+    /// Wrap a proof of the circuit, setting up the gnark keys first if this
+    /// is the first proof in the data dir.
+    pub fn wrap(&self, proof: &Proof) -> Result<EvmProof> {
+        let dir = &self.config.dir;
+        log::info!("Wrapping proof");
+        let wrapped = self
+            .circuit
+            .prove(proof)
+            .map_err(|e| err!(Prove, "Failed to wrap proof: {}", e))?;
+        wrapped
+            .save(dir)
+            .map_err(|e| err!(Io, "Failed to save wrapped proof: {}", e))?;
+
+        if !dir.join(PROVING_KEY).exists() {
+            log::info!(
+                "Setting up {} keys in {}",
+                self.config.system,
+                dir.display()
+            );
+            self.gnark("-compile")?;
+        }
+        log::info!("Proving with {}", self.config.system);
+        self.gnark("-prove")?;
+
+        let path = dir.join(PROOF);
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("gnark didn't write {}", path.display()))?;
+        EvmProof::parse(&json)
+    }
+
+    fn gnark(&self, step: &str) -> Result<()> {
+        let output = Command::new(&self.config.gnark)
+            .arg(step)
+            .arg("-system")
+            .arg(self.config.system.to_string())
+            .arg("-data")
+            .arg(&self.config.dir)
+            .output()
+            .with_context(|| format!("Failed to run {}", self.config.gnark.display()))?;
+        ensure!(
+            output.status.success(),
+            Prove,
+            "gnark {} failed with {}: {}",
+            step,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(())
+    }
+}
+
+/// Build a deployed circuit by its name in the manifest.
+pub fn build_named(circuit: &str) -> Result<CircuitBuild<L, D>> {
+    fn build<C: Circuit>() -> CircuitBuild<L, D> {
+        let mut b = CircuitBuilder::<L, D>::new();
+        C::define(&mut b);
+        b.build()
+    }
+    Ok(match circuit {
+        "sync" => build::<SyncCircuit<NETWORK, ABI, KEY_POLICY>>(),
+        "rolling-sync" => build::<RollingSyncCircuit<NETWORK, KEY_POLICY>>(),
+        "aggregate-sync" => {
+            build::<AggregateSyncCircuit<AGGREGATE_SYNC_AMT, NETWORK, KEY_POLICY>>()
+        }
+        "skip-sync" => build::<SkipSyncCircuit<SKIP_SYNC_EPOCHS, NETWORK, KEY_POLICY>>(),
+        "state-proof" => build::<StateProofCircuit<NETWORK>>(),
+        "verify" => {
+            build::<VerifyCircuit<VERIFY_PROOF_AMT, VERIFY_PROOF_BATCH_SIZE, NETWORK, ABI>>()
+        }
+        _ => bail!(Prove, "Unknown circuit {}", circuit),
+    })
+}
+
+/// The data dir for a circuit's wrapped proofs, by its name.
+pub fn data_dir(root: impl AsRef<Path>, circuit: &str, system: System) -> PathBuf {
+    root.as_ref().join(format!("{}-{}", circuit, system))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system() {
+        for system in [System::Groth16, System::Plonk] {
+            assert_eq!(system.to_string().parse::<System>().unwrap(), system);
+        }
+        assert!("fflonk".parse::<System>().is_err());
+        assert_eq!(
+            data_dir("build/wrapped", "sync", System::Groth16),
+            PathBuf::from("build/wrapped/sync-groth16")
+        );
+    }
+
+    #[test]
+    fn test_parse_evm_proof() {
+        let hash = format!("0x{}", "11".repeat(32));
+        let json = format!(
+            r#"{{"input_hash":"{}","output_hash":"{}","proof":"0xabcd"}}"#,
+            hash, hash
+        );
+        let proof = EvmProof::parse(&json).unwrap();
+        assert_eq!(proof.input_hash, vec![0x11; 32]);
+        assert_eq!(proof.proof, vec![0xab, 0xcd]);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::to_value(&proof).unwrap()
+        );
+
+        let short = format!(
+            r#"{{"input_hash":"0x11","output_hash":"{}","proof":"0xabcd"}}"#,
+            hash
+        );
+        assert!(EvmProof::parse(&short).is_err());
+        let empty = format!(
+            r#"{{"input_hash":"{}","output_hash":"{}","proof":"0x"}}"#,
+            hash, hash
+        );
+        assert!(EvmProof::parse(&empty).is_err());
+    }
+}
+
+#[cfg(test)]
+mod beefy_tests {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::{
+        test_utils::{testnet_state, DOMAIN},
+        variables::{domain_from_chain_id, CryptoHashVariable, DomainVariable},
+    };
+
+    #[test]
+    #[serial]
+    #[ignore]
+    fn beefy_test_wrap_sync_e2e() {
+        pretty_env_logger::try_init().unwrap_or_default();
+        let gnark = std::env::var("GNARK_VERIFIER").expect("GNARK_VERIFIER is the gnark verifier");
+        let (header, _, _) = testnet_state();
+
+        let sync = build_named("sync").unwrap();
+        let mut input = sync.input();
+        input.evm_write::<DomainVariable>(domain_from_chain_id(DOMAIN).into());
+        input.evm_write::<CryptoHashVariable>(header.hash().0.into());
+        let (proof, _) = sync.prove(&input);
+
+        let wrapper = Wrapper::build(
+            sync,
+            WrapConfig {
+                gnark: gnark.into(),
+                dir: data_dir("../build/wrapped", "sync", System::Groth16),
+                system: System::Groth16,
+            },
+        )
+        .unwrap();
+        let wrapped = wrapper.wrap(&proof).unwrap();
+        println!("{}", serde_json::to_string(&wrapped).unwrap());
+    }
+}